@@ -0,0 +1,56 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#include "DictionaryTrainer.h"
+#include "dwebble_rws.h"
+
+namespace DwebbleWS = Dwebble::WebSocket;
+
+class FDwebbleDictionaryTrainerImpl : public DwebbleWS::IDictionaryTrainer
+{
+public:
+	explicit FDwebbleDictionaryTrainerImpl(const DwebbleWSDictTrainerHandle InHandle)
+		: Handle(InHandle)
+	{
+	}
+
+	virtual ~FDwebbleDictionaryTrainerImpl() override
+	{
+		dwebble_rws_dict_trainer_destroy(Handle);
+	}
+
+	virtual void AddSample(const TArray<uint8>& Sample) override
+	{
+		dwebble_rws_dict_trainer_add_sample(Handle, Sample.GetData(), Sample.Num());
+	}
+
+	virtual bool Train(const int32 MaxSize, TArray<uint8>& OutDictionary) const override
+	{
+		OutDictionary.Empty();
+
+		DwebbleWSBuffer Buffer;
+		const DwebbleWSResult Result = dwebble_rws_dict_trainer_train(
+			Handle,
+			static_cast<size_t>(FMath::Max(MaxSize, 0)),
+			&Buffer
+		);
+
+		if (Result == DwebbleWSResult::Ok && Buffer.data)
+		{
+			OutDictionary.Append(Buffer.data, static_cast<int32>(Buffer.len));
+		}
+		dwebble_rws_free_buffer(Buffer);
+
+		return Result == DwebbleWSResult::Ok;
+	}
+
+private:
+	DwebbleWSDictTrainerHandle Handle;
+};
+
+TSharedPtr<DwebbleWS::IDictionaryTrainer> DwebbleWS::IDictionaryTrainer::Create()
+{
+	const DwebbleWSDictTrainerHandle Handle = dwebble_rws_dict_trainer_create();
+	if (!Handle) return nullptr;
+
+	return MakeShared<FDwebbleDictionaryTrainerImpl>(Handle);
+}
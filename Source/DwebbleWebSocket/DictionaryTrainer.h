@@ -0,0 +1,33 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#pragma once
+
+#include "CoreMinimal.h"
+
+namespace Dwebble::WebSocket
+{
+	/**
+	 * Trainer of zstd compression dictionaries from sample payloads
+	 *
+	 * Feed it messages of recorded traffic, then set the trained dictionary on a server
+	 * (IServer::SetZstdDictionary) and ship the same blob to clients. The samples are
+	 * freed when the last reference is released.
+	 */
+	class DWEBBLEWEBSOCKET_API IDictionaryTrainer
+	{
+	public:
+		virtual ~IDictionaryTrainer() = default;
+
+		/** Create a trainer. Returns null in builds without the zstd feature. */
+		static TSharedPtr<IDictionaryTrainer> Create();
+
+		/** Add a sample payload, typical of the messages to compress */
+		virtual void AddSample(const TArray<uint8>& Sample) = 0;
+
+		/**
+		 * Train a dictionary of up to MaxSize bytes from the samples so far.
+		 * Returns false if there are too few samples to train on.
+		 */
+		virtual bool Train(int32 MaxSize, TArray<uint8>& OutDictionary) const = 0;
+	};
+}
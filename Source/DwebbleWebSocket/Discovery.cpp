@@ -0,0 +1,78 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#include "Discovery.h"
+#include "dwebble_rws.h"
+
+namespace DwebbleWS = Dwebble::WebSocket;
+
+class FDwebbleDiscoveryAnnouncementImpl : public DwebbleWS::IDiscoveryAnnouncement
+{
+public:
+	explicit FDwebbleDiscoveryAnnouncementImpl(const DwebbleWSAnnouncementHandle InHandle)
+		: Handle(InHandle)
+	{
+	}
+
+	virtual ~FDwebbleDiscoveryAnnouncementImpl() override
+	{
+		dwebble_rws_discovery_withdraw(Handle);
+	}
+
+private:
+	DwebbleWSAnnouncementHandle Handle;
+};
+
+class FDwebbleDiscoveryBrowserImpl : public DwebbleWS::IDiscoveryBrowser
+{
+public:
+	explicit FDwebbleDiscoveryBrowserImpl(const DwebbleWSBrowserHandle InHandle)
+		: Handle(InHandle)
+	{
+	}
+
+	virtual ~FDwebbleDiscoveryBrowserImpl() override
+	{
+		dwebble_rws_discovery_browse_stop(Handle);
+	}
+
+	virtual FString GetServices() const override
+	{
+		char* ServicesStr = dwebble_rws_discovery_services(Handle);
+		if (!ServicesStr) return TEXT("[]");
+
+		FString Result = UTF8_TO_TCHAR(ServicesStr);
+		dwebble_rws_free_string(ServicesStr);
+		return Result;
+	}
+
+private:
+	DwebbleWSBrowserHandle Handle;
+};
+
+TSharedPtr<DwebbleWS::IDiscoveryAnnouncement> DwebbleWS::IDiscoveryAnnouncement::Announce(
+	const FString& ServiceName,
+	const int32 Port,
+	const FString& MetadataJson)
+{
+	const auto ServiceNameAnsi = StringCast<ANSICHAR>(*ServiceName);
+	const auto MetadataAnsi = StringCast<ANSICHAR>(*MetadataJson);
+	const DwebbleWSAnnouncementHandle Handle = dwebble_rws_discovery_announce(
+		ServiceNameAnsi.Get(),
+		static_cast<uint16_t>(FMath::Clamp(Port, 0, 65535)),
+		MetadataJson.IsEmpty() ? nullptr : MetadataAnsi.Get()
+	);
+
+	if (!Handle) return nullptr;
+
+	return MakeShared<FDwebbleDiscoveryAnnouncementImpl>(Handle);
+}
+
+TSharedPtr<DwebbleWS::IDiscoveryBrowser> DwebbleWS::IDiscoveryBrowser::Browse(const FString& ServiceName)
+{
+	const auto ServiceNameAnsi = StringCast<ANSICHAR>(*ServiceName);
+	const DwebbleWSBrowserHandle Handle = dwebble_rws_discovery_browse(ServiceNameAnsi.Get());
+
+	if (!Handle) return nullptr;
+
+	return MakeShared<FDwebbleDiscoveryBrowserImpl>(Handle);
+}
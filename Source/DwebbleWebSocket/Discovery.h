@@ -0,0 +1,44 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#pragma once
+
+#include "CoreMinimal.h"
+
+namespace Dwebble::WebSocket
+{
+	/**
+	 * Service announced to LAN browsers over mDNS
+	 *
+	 * The announcement is withdrawn when the last reference is released.
+	 */
+	class DWEBBLEWEBSOCKET_API IDiscoveryAnnouncement
+	{
+	public:
+		virtual ~IDiscoveryAnnouncement() = default;
+
+		/**
+		 * Announce Port under ServiceName (1-15 letters, digits and hyphens, e.g. "mygame").
+		 * Pass the server's actual port (IWebSocketServer::GetPort). MetadataJson is a JSON
+		 * object of string values carried with the announcement (empty for none).
+		 * Returns null on failure.
+		 */
+		static TSharedPtr<IDiscoveryAnnouncement> Announce(const FString& ServiceName, int32 Port, const FString& MetadataJson = TEXT(""));
+	};
+
+	/**
+	 * Browser of a service's announcements on the LAN
+	 *
+	 * Browsing stops when the last reference is released.
+	 */
+	class DWEBBLEWEBSOCKET_API IDiscoveryBrowser
+	{
+	public:
+		virtual ~IDiscoveryBrowser() = default;
+
+		/** Start browsing for announcements of ServiceName. Returns null on failure. */
+		static TSharedPtr<IDiscoveryBrowser> Browse(const FString& ServiceName);
+
+		/** Get the services currently found as a JSON array of {name, host, addresses, port, metadata} objects */
+		virtual FString GetServices() const = 0;
+	};
+}
@@ -21,6 +21,37 @@ enum class EDwebbleWSEventType : uint8
 	ClientDisconnected = 2,
 	MessageReceived = 3,
 	Error = 4,
+	SessionSuspended = 5,
+	SessionResumed = 6,
+	PresenceJoined = 7,
+	PresenceLeft = 8,
+	TopicMessage = 9,
+	ResponseReceived = 10,
+	RequestTimedOut = 11,
+	RpcCall = 12,
+	TypedMessage = 13,
+	SocketIoEvent = 14,
+	MqttPublish = 15,
+	DatagramReceived = 16,
+	PortMapped = 17,
+	PublicEndpoint = 18,
+	ChannelMessage = 19,
+	StateUpdated = 20,
+	MessageRejected = 21,
+	ValidationFailed = 22,
+	SignatureInvalid = 23,
+	ReplayRejected = 24,
+	ServerStopped = 25,
+	TlsHandshakeFailed = 26,
+	HandshakeFailed = 27,
+	RateLimited = 28,
+	EventsDropped = 29,
+	KeyRotated = 30,
+	ShutdownDue = 31,
+	HealthWarning = 32,
+	ResourceReport = 33,
+	MessageDropped = 34,
+	ChecksumMismatch = 35,
 };
 
 /**
@@ -39,6 +70,88 @@ enum class EDwebbleWSResult : uint8
 	RuntimeError = 7,
 	SendFailed = 8,
 	ConnectionClosed = 9,
+	QueueFull = 10,
+	InvalidUtf8 = 11,
+	/** Another thread is starting, rebinding or stopping the server */
+	Busy = 12,
+};
+
+/**
+ * What a server middleware does with a message
+ */
+UENUM(BlueprintType)
+enum class EDwebbleWSMiddlewareAction : uint8
+{
+	/** Hand the message on unchanged */
+	Pass = 0,
+	/** Hand the modified message on */
+	Modify = 1,
+	/** Discard the message, skipping the rest of the chain */
+	Drop = 2,
+};
+
+/**
+ * A stall the server's watchdog warns of
+ */
+UENUM(BlueprintType)
+enum class EDwebbleWSHealthIssue : uint8
+{
+	/** The runtime has not run a task spawned on it */
+	Runtime = 0,
+	/** A connection's writer has not written its queued messages */
+	Writer = 1,
+	/** Events on the server's queue have not been polled */
+	EventQueue = 2,
+};
+
+/**
+ * Compression of a connection's messages
+ */
+UENUM(BlueprintType)
+enum class EDwebbleWSCompression : uint8
+{
+	/** Messages go out as they are */
+	None = 0,
+	/** Messages are compressed with zstd, asked for with the zstd setting's subprotocol suffix */
+	Zstd = 1,
+};
+
+/**
+ * Where a server is in its lifecycle
+ */
+UENUM(BlueprintType)
+enum class EDwebbleWSServerState : uint8
+{
+	/** Created and never started */
+	Created = 0,
+	/** Binding its listeners and starting its runtime */
+	Starting = 1,
+	/** Accepting connections */
+	Running = 2,
+	/** Closing its connections and shutting its runtime down */
+	Draining = 3,
+	/** Stopped after running, and may be started again */
+	Stopped = 4,
+};
+
+/**
+ * What a client trusts its server's certificate by
+ */
+UENUM(BlueprintType)
+enum class EDwebbleWSCertificateVerification : uint8
+{
+	/** The connection doesn't use TLS */
+	Plaintext = 0,
+	/** A chain to the Mozilla root certificates built into the library */
+	MozillaRoots = 1,
+	/** A chain to the root certificates of the operating system's store */
+	SystemRoots = 2,
+	/** A chain to the CAs of the ca_pem bundle */
+	CaBundle = 3,
+	/** A public key of spki_pins */
+	Pinned = 4,
+	/** Nothing: verification is disabled, for development only */
+	Disabled = 5,
 };
 
 /**
@@ -57,7 +170,7 @@ struct DWEBBLEWEBSOCKET_API FDwebbleWSServerConfig
 	UPROPERTY(EditAnywhere, BlueprintReadWrite)
 	FString BindAddress = TEXT("127.0.0.1");
 
-	/** Subprotocols to support */
+	/** Subprotocols to support, in order of preference over the client's */
 	UPROPERTY(EditAnywhere, BlueprintReadWrite)
 	TArray<FString> Subprotocols;
 
@@ -69,6 +182,10 @@ struct DWEBBLEWEBSOCKET_API FDwebbleWSServerConfig
 	UPROPERTY(EditAnywhere, BlueprintReadWrite)
 	FString TlsKeyPath;
 
+	/** Extended settings as a JSON object (limits, timeouts, allowed origins, log level, network simulation, mock server mode, upstream bridge, clustering). Empty for defaults. */
+	UPROPERTY(EditAnywhere, BlueprintReadWrite)
+	FString SettingsJson;
+
 	/** Default configuration */
 	static FDwebbleWSServerConfig Default() { return FDwebbleWSServerConfig(); }
 };
@@ -86,13 +203,380 @@ struct DWEBBLEWEBSOCKET_API FDwebbleWSEvent
 
 	uint64 ConnectionId = 0;
 
+	/** Request the event belongs to (ResponseReceived/RequestTimedOut), or the call to answer (RpcCall, 0 for notifications; SocketIoEvent, 0 if no ack), or the signaling connection of a WebRTC data channel (ClientConnected), or the sequence of a sequenced send (MessageReceived on a loopback client, 0 otherwise) */
+	uint64 RequestId = 0;
+
+	/** Message type ID of a typed envelope (TypedMessage); Data holds the payload */
+	uint32 MessageType = 0;
+
+	/** Virtual channel the message arrived on (ChannelMessage); Data holds the payload */
+	uint8 Channel = 0;
+
+	/** Key of the state a loopback client rebuilt (StateUpdated); Data holds the whole state */
+	uint32 StateKey = 0;
+
+	/** Code a WASM filter rejected the message with (MessageRejected, -1 if the filter failed); Data holds the message, ErrorMessage the filter path */
+	int32 RejectCode = 0;
+
+	/** Whether a message that failed schema validation was delivered anyway (ValidationFailed); Data holds the message, ErrorMessage what failed */
+	bool bDeliveredAnyway = false;
+
+	/** Checksum of Data with the server's checksum setting, to compare against the sender's (0 otherwise) */
+	uint32 Checksum = 0;
+
 	UPROPERTY(BlueprintReadOnly)
 	TArray<uint8> Data;
 
+	/** Negotiated subprotocol (ClientConnected/SessionResumed, empty if none was selected; webrtc/<label> for WebRTC data channels) */
+	UPROPERTY(BlueprintReadOnly)
+	FString Subprotocol;
+
+	/** Logical user ID (PresenceJoined/PresenceLeft) */
+	UPROPERTY(BlueprintReadOnly)
+	FString UserId;
+
+	/** Topic the data was published to (TopicMessage, MqttPublish) */
+	UPROPERTY(BlueprintReadOnly)
+	FString Topic;
+
+	/** JSON-RPC method called (RpcCall) or Socket.IO event name (SocketIoEvent); Data holds the params/arguments JSON */
+	UPROPERTY(BlueprintReadOnly)
+	FString Method;
+
 	UPROPERTY(BlueprintReadOnly)
 	FString ErrorMessage;
 };
 
+/**
+ * Aggregate statistics of a load test
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSLoadTestStats
+{
+	GENERATED_BODY()
+
+	/** Time since the load test started */
+	UPROPERTY(BlueprintReadOnly)
+	int64 ElapsedMs = 0;
+
+	/** Clients currently connected */
+	UPROPERTY(BlueprintReadOnly)
+	int64 ActiveClients = 0;
+
+	/** Clients that failed to connect */
+	UPROPERTY(BlueprintReadOnly)
+	int64 ConnectFailures = 0;
+
+	/** Clients whose connection ended */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Disconnects = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesSent = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesReceived = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 BytesSent = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 BytesReceived = 0;
+
+	/** Average messages sent per second since the start */
+	UPROPERTY(BlueprintReadOnly)
+	double SendRate = 0.0;
+
+	/** Average messages received per second since the start */
+	UPROPERTY(BlueprintReadOnly)
+	double ReceiveRate = 0.0;
+
+	/** Number of echoed messages the latency figures are based on */
+	UPROPERTY(BlueprintReadOnly)
+	int64 LatencySamples = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 LatencyMinUs = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 LatencyAvgUs = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 LatencyMaxUs = 0;
+};
+
+/**
+ * Statistics of one virtual channel of a connection
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSChannelStats
+{
+	GENERATED_BODY()
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesSent = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesReceived = 0;
+
+	/** Payload bytes, excluding the channel envelope */
+	UPROPERTY(BlueprintReadOnly)
+	int64 BytesSent = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 BytesReceived = 0;
+
+	/** Sent messages the peer acknowledged */
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesAcked = 0;
+
+	/** Reliable messages awaiting acknowledgement */
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesUnacked = 0;
+
+	/** Reliable messages sent again after a session resumed */
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesRetransmitted = 0;
+
+	/** Reliable messages given up on because too many were unacknowledged */
+	UPROPERTY(BlueprintReadOnly)
+	int64 MessagesAbandoned = 0;
+
+	/** Sequenced messages dropped for arriving after a newer one */
+	UPROPERTY(BlueprintReadOnly)
+	int64 StaleDropped = 0;
+
+	/** Reliable messages dropped for arriving again */
+	UPROPERTY(BlueprintReadOnly)
+	int64 DuplicatesDropped = 0;
+
+	/** Smoothed acknowledgement round-trip time (0 until the first acknowledgement) */
+	UPROPERTY(BlueprintReadOnly)
+	int64 RttUs = 0;
+};
+
+/**
+ * Information about a connection
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSConnectionInfo
+{
+	GENERATED_BODY()
+
+	/** Whether messages are protected by application-layer encryption (encryption setting) */
+	UPROPERTY(BlueprintReadOnly)
+	bool bEncrypted = false;
+
+	/** Times the connection's encryption keys were rotated */
+	UPROPERTY(BlueprintReadOnly)
+	int64 KeyRotations = 0;
+
+	/** JA3 hash of the client's TLS ClientHello as lowercase hex, empty without TLS */
+	UPROPERTY(BlueprintReadOnly)
+	FString TlsFingerprint;
+
+	/** Hash of the order of the handshake's request header names as lowercase hex */
+	UPROPERTY(BlueprintReadOnly)
+	FString HeaderFingerprint;
+
+	/** Bytes queued but not yet written to the socket */
+	UPROPERTY(BlueprintReadOnly)
+	int64 QueuedBytes = 0;
+
+	/** Messages sent with a TTL and dropped from the send queue once it ran out */
+	UPROPERTY(BlueprintReadOnly)
+	int64 ExpiredMessages = 0;
+
+	/** Compression negotiated for the connection's WebSocket messages */
+	UPROPERTY(BlueprintReadOnly)
+	EDwebbleWSCompression Compression = EDwebbleWSCompression::None;
+
+	/** Compression level (0 without compression) */
+	UPROPERTY(BlueprintReadOnly)
+	int32 CompressionLevel = 0;
+
+	/** ID of the compression dictionary (0 without one, or for a raw content dictionary) */
+	UPROPERTY(BlueprintReadOnly)
+	int64 DictionaryId = 0;
+
+	/** Payload bytes of WebSocket data messages sent, before compression */
+	UPROPERTY(BlueprintReadOnly)
+	int64 RawBytesSent = 0;
+
+	/** Payload bytes of WebSocket data messages sent, after compression */
+	UPROPERTY(BlueprintReadOnly)
+	int64 CompressedBytesSent = 0;
+
+	/** Payload bytes of WebSocket data messages received, before decompression */
+	UPROPERTY(BlueprintReadOnly)
+	int64 CompressedBytesReceived = 0;
+
+	/** Payload bytes of WebSocket data messages received, after decompression */
+	UPROPERTY(BlueprintReadOnly)
+	int64 RawBytesReceived = 0;
+
+	/** Whether a protocol version was negotiated under the protocol_version setting */
+	UPROPERTY(BlueprintReadOnly)
+	bool bVersionNegotiated = false;
+
+	/** Major version of the negotiated protocol (0 without one) */
+	UPROPERTY(BlueprintReadOnly)
+	int32 VersionMajor = 0;
+
+	/** Minor version of the negotiated protocol (0 without one) */
+	UPROPERTY(BlueprintReadOnly)
+	int32 VersionMinor = 0;
+};
+
+/**
+ * Information about a client connection
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSClientInfo
+{
+	GENERATED_BODY()
+
+	/** What the server's certificate was trusted by (tls setting) */
+	UPROPERTY(BlueprintReadOnly)
+	EDwebbleWSCertificateVerification CertificateVerification = EDwebbleWSCertificateVerification::Plaintext;
+};
+
+/**
+ * Statistics of a service pool
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSServicePoolStats
+{
+	GENERATED_BODY()
+
+	/** Connections of the pool, open or not */
+	UPROPERTY(BlueprintReadOnly)
+	int32 Connections = 0;
+
+	/** Connections open and answering health checks, which requests go out on */
+	UPROPERTY(BlueprintReadOnly)
+	int32 HealthyConnections = 0;
+
+	/** Requests awaiting a response */
+	UPROPERTY(BlueprintReadOnly)
+	int64 PendingRequests = 0;
+};
+
+/**
+ * Statistics of the connections with a tag
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSTagStats
+{
+	GENERATED_BODY()
+
+	/** Live connections with the tag */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Connections = 0;
+
+	/** Suspended sessions with the tag, awaiting a reconnect */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Suspended = 0;
+
+	/** Bytes queued but not yet written to the tagged connections' sockets */
+	UPROPERTY(BlueprintReadOnly)
+	int64 QueuedBytes = 0;
+};
+
+/**
+ * Statistics of the pool of received message payloads, shared by every server and client
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSPoolStats
+{
+	GENERATED_BODY()
+
+	/** Payloads copied into a reused buffer */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Hits = 0;
+
+	/** Payloads copied into a newly allocated buffer */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Misses = 0;
+
+	/** Payloads too large to pool */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Unpooled = 0;
+
+	/** Buffers waiting in the pool */
+	UPROPERTY(BlueprintReadOnly)
+	int64 FreeBuffers = 0;
+
+	/** Capacity of the buffers waiting in the pool, in bytes */
+	UPROPERTY(BlueprintReadOnly)
+	int64 FreeBytes = 0;
+};
+
+/**
+ * Resource usage of a server; memory is approximated by the bytes held in its queues and buffers
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSResourceUsage
+{
+	GENERATED_BODY()
+
+	/** Worker threads of the runtime (0 while stopped or in single-thread builds) */
+	UPROPERTY(BlueprintReadOnly)
+	int64 WorkerThreads = 0;
+
+	/** Other threads of the server, such as the watchdog's */
+	UPROPERTY(BlueprintReadOnly)
+	int64 HelperThreads = 0;
+
+	/** Tasks alive on the runtime */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Tasks = 0;
+
+	/** Tasks waiting in the runtime's global queue */
+	UPROPERTY(BlueprintReadOnly)
+	int64 QueuedTasks = 0;
+
+	/** Time the workers have spent busy since the server started, in milliseconds */
+	UPROPERTY(BlueprintReadOnly)
+	int64 BusyMs = 0;
+
+	UPROPERTY(BlueprintReadOnly)
+	int64 Connections = 0;
+
+	/** Bytes queued to send, over all connections */
+	UPROPERTY(BlueprintReadOnly)
+	int64 SendQueueBytes = 0;
+
+	/** Bytes buffered for suspended sessions */
+	UPROPERTY(BlueprintReadOnly)
+	int64 SessionBufferBytes = 0;
+
+	/** Events on the server's queue not yet polled */
+	UPROPERTY(BlueprintReadOnly)
+	int64 QueuedEvents = 0;
+
+	/** Data and error message bytes of those events */
+	UPROPERTY(BlueprintReadOnly)
+	int64 EventQueueBytes = 0;
+};
+
+/**
+ * Connections closed by stopping a server
+ */
+USTRUCT(BlueprintType)
+struct DWEBBLEWEBSOCKET_API FDwebbleWSStopStats
+{
+	GENERATED_BODY()
+
+	/** Connections open when the server was asked to stop */
+	UPROPERTY(BlueprintReadOnly)
+	int64 Connections = 0;
+
+	/** Connections still open after the close grace period, dropped without a closing handshake */
+	UPROPERTY(BlueprintReadOnly)
+	int64 ForceClosed = 0;
+};
+
 // Delegate types (global scope for UE macro compatibility)
 DECLARE_DELEGATE_OneParam(FDwebbleWSOnClientConnected, uint64 /* ConnectionId */);
 DECLARE_DELEGATE_OneParam(FDwebbleWSOnClientDisconnected, uint64 /* ConnectionId */);
@@ -108,8 +592,22 @@ namespace Dwebble::WebSocket
 	// Type aliases for cleaner usage
 	using EEventType = EDwebbleWSEventType;
 	using EResult = EDwebbleWSResult;
+	using EMiddlewareAction = EDwebbleWSMiddlewareAction;
+	using ECompression = EDwebbleWSCompression;
+	using EHealthIssue = EDwebbleWSHealthIssue;
+	using EServerState = EDwebbleWSServerState;
+	using ECertificateVerification = EDwebbleWSCertificateVerification;
 	using FServerConfig = FDwebbleWSServerConfig;
 	using FEvent = FDwebbleWSEvent;
+	using FLoadTestStats = FDwebbleWSLoadTestStats;
+	using FChannelStats = FDwebbleWSChannelStats;
+	using FConnectionInfo = FDwebbleWSConnectionInfo;
+	using FClientInfo = FDwebbleWSClientInfo;
+	using FServicePoolStats = FDwebbleWSServicePoolStats;
+	using FTagStats = FDwebbleWSTagStats;
+	using FPoolStats = FDwebbleWSPoolStats;
+	using FStopStats = FDwebbleWSStopStats;
+	using FResourceUsage = FDwebbleWSResourceUsage;
 
 	// Delegate aliases
 	using FOnClientConnected = FDwebbleWSOnClientConnected;
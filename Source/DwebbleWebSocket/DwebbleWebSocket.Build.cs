@@ -30,6 +30,20 @@ public class DwebbleWebSocket : ModuleRules
 			}
 		);
 
+		// Platforms that forbid loading DLLs link a static library built with
+		// `cargo make release-static -e PLATFORM=<platform>` instead
+		var StaticDir = Path.Combine(PluginDirectory, "Binaries", Target.Platform.ToString());
+		foreach (var StaticName in new string[] { "dwebble_rws.lib", "libdwebble_rws.a" })
+		{
+			var StaticPath = Path.Combine(StaticDir, StaticName);
+			if (!File.Exists(StaticPath)) continue;
+
+			PublicAdditionalLibraries.Add(StaticPath);
+			PublicDefinitions.Add("DWEBBLE_RWS_STATIC=1");
+			return;
+		}
+		PublicDefinitions.Add("DWEBBLE_RWS_STATIC=0");
+
 		// Find Rust DLL and import a library
 		var BinariesDir = Path.Combine(PluginDirectory, "Binaries", "Win64");
 		const string DllName = "dwebble_rws.dll";
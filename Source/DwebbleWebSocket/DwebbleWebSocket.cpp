@@ -4,13 +4,37 @@
 #include "Interfaces/IPluginManager.h"
 #include "Misc/Paths.h"
 #include "HAL/PlatformProcess.h"
+#include "HAL/LowLevelMemTracker.h"
+#include "dwebble_rws.h"
 
 #define LOCTEXT_NAMESPACE "FDwebbleModule"
 
 void* GDwebbleRwsDllHandle = nullptr;
 
+namespace
+{
+	/** Allocate the strings and buffers the library hands over from the engine's allocator */
+	void* AllocForDwebble(void* UserData, const uintptr_t Size)
+	{
+		LLM_SCOPE_BYNAME(TEXT("Dwebble"));
+		return FMemory::Malloc(Size);
+	}
+
+	void FreeForDwebble(void* UserData, void* Ptr)
+	{
+		FMemory::Free(Ptr);
+	}
+}
+
 void FDwebbleWebSocketModule::StartupModule()
 {
+#if DWEBBLE_RWS_STATIC
+	// Linked in statically, so there is nothing to load
+	if (dwebble_rws_set_allocator(&AllocForDwebble, &FreeForDwebble, nullptr) != DwebbleWSResult::Ok)
+	{
+		UE_LOG(LogTemp, Warning, TEXT("Dwebble: Could not set the allocator of dwebble_rws"));
+	}
+#else
 	// Load the Rust DLL
 	const FString PluginDir = IPluginManager::Get().FindPlugin(TEXT("Dwebble"))->GetBaseDir();
 	const FString DllPath = FPaths::Combine(PluginDir, TEXT("Binaries/Win64/dwebble_rws.dll"));
@@ -21,6 +45,12 @@ void FDwebbleWebSocketModule::StartupModule()
 		if (GDwebbleRwsDllHandle)
 		{
 			UE_LOG(LogTemp, Log, TEXT("Dwebble: Loaded dwebble_rws.dll from %s"), *DllPath);
+
+			// Before anything else, so every string and buffer the library hands over is tracked
+			if (dwebble_rws_set_allocator(&AllocForDwebble, &FreeForDwebble, nullptr) != DwebbleWSResult::Ok)
+			{
+				UE_LOG(LogTemp, Warning, TEXT("Dwebble: Could not set the allocator of dwebble_rws.dll"));
+			}
 		}
 		else
 		{
@@ -31,6 +61,7 @@ void FDwebbleWebSocketModule::StartupModule()
 	{
 		UE_LOG(LogTemp, Warning, TEXT("Dwebble: dwebble_rws.dll not found at %s"), *DllPath);
 	}
+#endif
 }
 
 void FDwebbleWebSocketModule::ShutdownModule()
@@ -0,0 +1,140 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#include "LoadTest.h"
+#include "dwebble_rws.h"
+#include "HAL/IConsoleManager.h"
+
+namespace DwebbleWS = Dwebble::WebSocket;
+
+class FDwebbleLoadTestImpl : public DwebbleWS::ILoadTest
+{
+public:
+	explicit FDwebbleLoadTestImpl(const DwebbleWSLoadTestHandle InHandle)
+		: Handle(InHandle)
+	{
+	}
+
+	virtual ~FDwebbleLoadTestImpl() override
+	{
+		dwebble_rws_loadtest_stop(Handle);
+	}
+
+	virtual DwebbleWS::FLoadTestStats GetStats() const override
+	{
+		DwebbleWSLoadTestStats Stats;
+		DwebbleWS::FLoadTestStats Result;
+		if (dwebble_rws_loadtest_poll(Handle, &Stats) != DwebbleWSResult::Ok)
+		{
+			return Result;
+		}
+
+		Result.ElapsedMs = static_cast<int64>(Stats.elapsed_ms);
+		Result.ActiveClients = static_cast<int64>(Stats.active_clients);
+		Result.ConnectFailures = static_cast<int64>(Stats.connect_failures);
+		Result.Disconnects = static_cast<int64>(Stats.disconnects);
+		Result.MessagesSent = static_cast<int64>(Stats.messages_sent);
+		Result.MessagesReceived = static_cast<int64>(Stats.messages_received);
+		Result.BytesSent = static_cast<int64>(Stats.bytes_sent);
+		Result.BytesReceived = static_cast<int64>(Stats.bytes_received);
+		Result.SendRate = Stats.send_rate;
+		Result.ReceiveRate = Stats.receive_rate;
+		Result.LatencySamples = static_cast<int64>(Stats.latency_samples);
+		Result.LatencyMinUs = static_cast<int64>(Stats.latency_min_us);
+		Result.LatencyAvgUs = static_cast<int64>(Stats.latency_avg_us);
+		Result.LatencyMaxUs = static_cast<int64>(Stats.latency_max_us);
+		return Result;
+	}
+
+	virtual void Tick(const int32 BudgetMs) override
+	{
+		dwebble_rws_loadtest_tick(Handle, static_cast<uint32_t>(FMath::Max(BudgetMs, 0)));
+	}
+
+private:
+	DwebbleWSLoadTestHandle Handle;
+};
+
+TSharedPtr<DwebbleWS::ILoadTest> DwebbleWS::ILoadTest::Start(
+	const FString& Url,
+	const int32 NumClients,
+	const int32 MsgsPerSec,
+	const int32 PayloadLen)
+{
+	const auto UrlAnsi = StringCast<ANSICHAR>(*Url);
+	const DwebbleWSLoadTestHandle Handle = dwebble_rws_loadtest_start(
+		UrlAnsi.Get(),
+		static_cast<uint32_t>(FMath::Max(NumClients, 0)),
+		static_cast<uint32_t>(FMath::Max(MsgsPerSec, 0)),
+		static_cast<size_t>(FMath::Max(PayloadLen, 0))
+	);
+
+	if (!Handle) return nullptr;
+
+	return MakeShared<FDwebbleLoadTestImpl>(Handle);
+}
+
+// ============================================================================
+// Console commands
+// ============================================================================
+
+namespace
+{
+	TSharedPtr<DwebbleWS::ILoadTest> GConsoleLoadTest;
+
+	void LogLoadTestStats()
+	{
+		if (!GConsoleLoadTest)
+		{
+			UE_LOG(LogTemp, Log, TEXT("Dwebble: No load test running"));
+			return;
+		}
+
+		const DwebbleWS::FLoadTestStats Stats = GConsoleLoadTest->GetStats();
+		UE_LOG(LogTemp, Log,
+		       TEXT("Dwebble: Load test %.1fs: %lld active, %lld failed, %lld disconnected, "
+			       "sent %lld (%.1f/s), received %lld (%.1f/s), latency min/avg/max %lld/%lld/%lld us (%lld samples)"),
+		       Stats.ElapsedMs / 1000.0, Stats.ActiveClients, Stats.ConnectFailures, Stats.Disconnects,
+		       Stats.MessagesSent, Stats.SendRate, Stats.MessagesReceived, Stats.ReceiveRate,
+		       Stats.LatencyMinUs, Stats.LatencyAvgUs, Stats.LatencyMaxUs, Stats.LatencySamples);
+	}
+
+	FAutoConsoleCommand GLoadTestStartCommand(
+		TEXT("dwebble.LoadTest.Start"),
+		TEXT("Start a WebSocket load test: dwebble.LoadTest.Start <Url> [NumClients=10] [MsgsPerSec=10] [PayloadLen=64]"),
+		FConsoleCommandWithArgsDelegate::CreateLambda([](const TArray<FString>& Args)
+		{
+			if (Args.Num() < 1)
+			{
+				UE_LOG(LogTemp, Warning, TEXT("Dwebble: Usage: dwebble.LoadTest.Start <Url> [NumClients] [MsgsPerSec] [PayloadLen]"));
+				return;
+			}
+
+			const int32 NumClients = Args.IsValidIndex(1) ? FCString::Atoi(*Args[1]) : 10;
+			const int32 MsgsPerSec = Args.IsValidIndex(2) ? FCString::Atoi(*Args[2]) : 10;
+			const int32 PayloadLen = Args.IsValidIndex(3) ? FCString::Atoi(*Args[3]) : 64;
+
+			GConsoleLoadTest.Reset();
+			GConsoleLoadTest = DwebbleWS::ILoadTest::Start(Args[0], NumClients, MsgsPerSec, PayloadLen);
+			if (!GConsoleLoadTest)
+			{
+				UE_LOG(LogTemp, Error, TEXT("Dwebble: Failed to start load test against %s"), *Args[0]);
+			}
+		})
+	);
+
+	FAutoConsoleCommand GLoadTestStatsCommand(
+		TEXT("dwebble.LoadTest.Stats"),
+		TEXT("Log the statistics of the running WebSocket load test"),
+		FConsoleCommandDelegate::CreateStatic(&LogLoadTestStats)
+	);
+
+	FAutoConsoleCommand GLoadTestStopCommand(
+		TEXT("dwebble.LoadTest.Stop"),
+		TEXT("Stop the running WebSocket load test and log its final statistics"),
+		FConsoleCommandDelegate::CreateLambda([]()
+		{
+			LogLoadTestStats();
+			GConsoleLoadTest.Reset();
+		})
+	);
+}
@@ -0,0 +1,34 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#pragma once
+
+#include "CoreMinimal.h"
+#include "DwebbleTypes.h"
+
+namespace Dwebble::WebSocket
+{
+	/**
+	 * Load test of WebSocket clients run inside the library
+	 *
+	 * Clients send binary messages starting with a send timestamp; latency is measured
+	 * when the server echoes them back. The test stops when the last reference is released.
+	 */
+	class DWEBBLEWEBSOCKET_API ILoadTest
+	{
+	public:
+		virtual ~ILoadTest() = default;
+
+		/**
+		 * Start NumClients clients connecting to Url (ws:// or wss://), each sending MsgsPerSec
+		 * messages per second (0 to only hold connections open) of PayloadLen bytes (at least 8).
+		 * Returns null on failure.
+		 */
+		static TSharedPtr<ILoadTest> Start(const FString& Url, int32 NumClients, int32 MsgsPerSec, int32 PayloadLen);
+
+		/** Get the aggregate statistics so far */
+		virtual FLoadTestStats GetStats() const = 0;
+
+		/** Run the clients for up to BudgetMs on this thread; only needed in single-thread builds */
+		virtual void Tick(int32 BudgetMs) = 0;
+	};
+}
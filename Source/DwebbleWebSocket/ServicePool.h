@@ -0,0 +1,46 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#pragma once
+
+#include "CoreMinimal.h"
+#include "DwebbleTypes.h"
+
+namespace Dwebble::WebSocket
+{
+	/**
+	 * Pool of connections to backend services, for game servers fanning out requests to platform services
+	 *
+	 * Events reuse EEventType with the connection's index in the pool as ConnectionId (ConnectionsPerUrl per
+	 * URL, in the order of the urls setting): ClientConnected and ClientDisconnected as connections open and
+	 * close, Error for each failed attempt, and ResponseReceived or RequestTimedOut for each request.
+	 * The connections close when the last reference is released.
+	 */
+	class DWEBBLEWEBSOCKET_API IServicePool
+	{
+	public:
+		virtual ~IServicePool() = default;
+
+		/**
+		 * Open connections to the services of SettingsJson, e.g.
+		 * {"urls": ["wss://auth.example.com", "wss://inventory.example.com"], "connections_per_url": 2};
+		 * see dwebble_rws_service_pool_create. Returns null if the settings are invalid.
+		 */
+		static TSharedPtr<IServicePool> Create(const FString& SettingsJson);
+
+		/**
+		 * Send a request on the next healthy connection, answered like the server's requests (DWRS envelope).
+		 * OutRequestId identifies the ResponseReceived or RequestTimedOut event; ConnectionClosed if no
+		 * connection is healthy.
+		 */
+		virtual EResult Request(const TArray<uint8>& Data, int32 TimeoutMs, uint64& OutRequestId) = 0;
+
+		/** Get the number of healthy connections and requests in flight */
+		virtual FServicePoolStats GetStats() const = 0;
+
+		/** Poll for events (call from Tick) */
+		virtual bool PollEvent(FEvent& OutEvent) = 0;
+
+		/** Run the connections for up to BudgetMs on this thread; only needed in single-thread builds */
+		virtual void Tick(int32 BudgetMs) = 0;
+	};
+}
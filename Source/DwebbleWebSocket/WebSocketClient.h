@@ -0,0 +1,60 @@
+// Copyright 2024 tarnishablec. All Rights Reserved.
+
+#pragma once
+
+#include "CoreMinimal.h"
+#include "DwebbleTypes.h"
+
+namespace Dwebble::WebSocket
+{
+	/**
+	 * WebSocket client interface
+	 *
+	 * Client events reuse EEventType with a ConnectionId of 0: ClientConnected once the
+	 * handshake completes, MessageReceived, ChannelMessage, StateUpdated, Error and
+	 * ClientDisconnected. With an offline_queue setting, a client reconnects after its
+	 * connection drops, raising ClientDisconnected with a RequestId of 1, and queues the
+	 * messages sent meanwhile, raising MessageDropped for those it has no room for.
+	 */
+	class DWEBBLEWEBSOCKET_API IClient
+	{
+	public:
+		virtual ~IClient() = default;
+
+		/**
+		 * Connect to the server at Url (ws:// or wss://). SettingsJson may set the headers, cookies and
+		 * query parameters of the upgrade request, e.g. {"headers": {"Authorization": "Bearer ..."}}, and
+		 * how a wss:// server's certificate is verified, e.g. {"tls": {"verification": "system_roots"}}; see
+		 * dwebble_rws_client_connect. Returns null if the URL or settings are invalid; connection failures
+		 * are reported by Error and ClientDisconnected events.
+		 */
+		static TSharedPtr<IClient> Connect(const FString& Url, const FString& SettingsJson = TEXT(""));
+
+		/** Send binary data to the server */
+		virtual EResult Send(const TArray<uint8>& Data) = 0;
+
+		/** Send text data to the server */
+		virtual EResult SendText(const FString& Text) = 0;
+
+		/** Send binary data on a virtual channel, configured like the server's channels setting */
+		virtual EResult SendChannel(uint8 Channel, const TArray<uint8>& Data) = 0;
+
+		/** Get the statistics of a virtual channel */
+		virtual FChannelStats GetChannelStats(uint8 Channel) const = 0;
+
+		/** Get information about the connection, e.g. how the server's certificate was verified */
+		virtual FClientInfo GetInfo() const = 0;
+
+		/** Sign messages with an HMAC-SHA256 key and require the server's to be signed, matching its SetSigningKey (empty key to stop) */
+		virtual EResult SetSigningKey(const TArray<uint8>& Key) = 0;
+
+		/** Close the connection gracefully */
+		virtual EResult Close() = 0;
+
+		/** Poll for events (call from Tick) */
+		virtual bool PollEvent(FEvent& OutEvent) = 0;
+
+		/** Run a connected client for up to BudgetMs on this thread; only needed in single-thread builds */
+		virtual void Tick(int32 BudgetMs) = 0;
+	};
+}
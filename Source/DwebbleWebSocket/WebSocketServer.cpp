@@ -1,11 +1,512 @@
 // Copyright 2024 tarnishablec. All Rights Reserved.
 
 #include "WebSocketServer.h"
+#include "ServicePool.h"
 #include "dwebble_rws.h"
+#include "Algo/AnyOf.h"
 #include "HAL/PlatformProcess.h"
 
 namespace DwebbleWS = Dwebble::WebSocket;
 
+// The UE enums mirror the generated header's and are cast to and from it, so each variant
+// must keep the header's value. Add a line here with every variant added on both sides.
+#define DWEBBLE_WS_CHECK_MIRROR(Enum, Variant) \
+	static_assert(static_cast<int32>(EDwebbleWS##Enum::Variant) == static_cast<int32>(DwebbleWS##Enum::Variant), \
+		"EDwebbleWS" #Enum "::" #Variant " does not match dwebble_rws.h")
+
+DWEBBLE_WS_CHECK_MIRROR(Result, Ok);
+DWEBBLE_WS_CHECK_MIRROR(Result, InvalidHandle);
+DWEBBLE_WS_CHECK_MIRROR(Result, InvalidParam);
+DWEBBLE_WS_CHECK_MIRROR(Result, AlreadyRunning);
+DWEBBLE_WS_CHECK_MIRROR(Result, NotRunning);
+DWEBBLE_WS_CHECK_MIRROR(Result, BindFailed);
+DWEBBLE_WS_CHECK_MIRROR(Result, TlsError);
+DWEBBLE_WS_CHECK_MIRROR(Result, RuntimeError);
+DWEBBLE_WS_CHECK_MIRROR(Result, SendFailed);
+DWEBBLE_WS_CHECK_MIRROR(Result, ConnectionClosed);
+DWEBBLE_WS_CHECK_MIRROR(Result, QueueFull);
+DWEBBLE_WS_CHECK_MIRROR(Result, InvalidUtf8);
+DWEBBLE_WS_CHECK_MIRROR(Result, Busy);
+
+DWEBBLE_WS_CHECK_MIRROR(EventType, None);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ClientConnected);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ClientDisconnected);
+DWEBBLE_WS_CHECK_MIRROR(EventType, MessageReceived);
+DWEBBLE_WS_CHECK_MIRROR(EventType, Error);
+DWEBBLE_WS_CHECK_MIRROR(EventType, SessionSuspended);
+DWEBBLE_WS_CHECK_MIRROR(EventType, SessionResumed);
+DWEBBLE_WS_CHECK_MIRROR(EventType, PresenceJoined);
+DWEBBLE_WS_CHECK_MIRROR(EventType, PresenceLeft);
+DWEBBLE_WS_CHECK_MIRROR(EventType, TopicMessage);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ResponseReceived);
+DWEBBLE_WS_CHECK_MIRROR(EventType, RequestTimedOut);
+DWEBBLE_WS_CHECK_MIRROR(EventType, RpcCall);
+DWEBBLE_WS_CHECK_MIRROR(EventType, TypedMessage);
+DWEBBLE_WS_CHECK_MIRROR(EventType, SocketIoEvent);
+DWEBBLE_WS_CHECK_MIRROR(EventType, MqttPublish);
+DWEBBLE_WS_CHECK_MIRROR(EventType, DatagramReceived);
+DWEBBLE_WS_CHECK_MIRROR(EventType, PortMapped);
+DWEBBLE_WS_CHECK_MIRROR(EventType, PublicEndpoint);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ChannelMessage);
+DWEBBLE_WS_CHECK_MIRROR(EventType, StateUpdated);
+DWEBBLE_WS_CHECK_MIRROR(EventType, MessageRejected);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ValidationFailed);
+DWEBBLE_WS_CHECK_MIRROR(EventType, SignatureInvalid);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ReplayRejected);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ServerStopped);
+DWEBBLE_WS_CHECK_MIRROR(EventType, TlsHandshakeFailed);
+DWEBBLE_WS_CHECK_MIRROR(EventType, HandshakeFailed);
+DWEBBLE_WS_CHECK_MIRROR(EventType, RateLimited);
+DWEBBLE_WS_CHECK_MIRROR(EventType, EventsDropped);
+DWEBBLE_WS_CHECK_MIRROR(EventType, KeyRotated);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ShutdownDue);
+DWEBBLE_WS_CHECK_MIRROR(EventType, HealthWarning);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ResourceReport);
+DWEBBLE_WS_CHECK_MIRROR(EventType, MessageDropped);
+DWEBBLE_WS_CHECK_MIRROR(EventType, ChecksumMismatch);
+
+DWEBBLE_WS_CHECK_MIRROR(MiddlewareAction, Pass);
+DWEBBLE_WS_CHECK_MIRROR(MiddlewareAction, Modify);
+DWEBBLE_WS_CHECK_MIRROR(MiddlewareAction, Drop);
+
+DWEBBLE_WS_CHECK_MIRROR(HealthIssue, Runtime);
+DWEBBLE_WS_CHECK_MIRROR(HealthIssue, Writer);
+DWEBBLE_WS_CHECK_MIRROR(HealthIssue, EventQueue);
+
+DWEBBLE_WS_CHECK_MIRROR(Compression, None);
+DWEBBLE_WS_CHECK_MIRROR(Compression, Zstd);
+
+DWEBBLE_WS_CHECK_MIRROR(ServerState, Created);
+DWEBBLE_WS_CHECK_MIRROR(ServerState, Starting);
+DWEBBLE_WS_CHECK_MIRROR(ServerState, Running);
+DWEBBLE_WS_CHECK_MIRROR(ServerState, Draining);
+DWEBBLE_WS_CHECK_MIRROR(ServerState, Stopped);
+
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, Plaintext);
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, MozillaRoots);
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, SystemRoots);
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, CaBundle);
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, Pinned);
+DWEBBLE_WS_CHECK_MIRROR(CertificateVerification, Disabled);
+
+#undef DWEBBLE_WS_CHECK_MIRROR
+
+namespace
+{
+	DwebbleWS::EResult ConvertResult(const DwebbleWSResult Result)
+	{
+		switch (Result)
+		{
+		case DwebbleWSResult::Ok: return DwebbleWS::EResult::Ok;
+		case DwebbleWSResult::InvalidHandle: return DwebbleWS::EResult::InvalidHandle;
+		case DwebbleWSResult::InvalidParam: return DwebbleWS::EResult::InvalidParam;
+		case DwebbleWSResult::AlreadyRunning: return DwebbleWS::EResult::AlreadyRunning;
+		case DwebbleWSResult::NotRunning: return DwebbleWS::EResult::NotRunning;
+		case DwebbleWSResult::BindFailed: return DwebbleWS::EResult::BindFailed;
+		case DwebbleWSResult::TlsError: return DwebbleWS::EResult::TlsError;
+		case DwebbleWSResult::RuntimeError: return DwebbleWS::EResult::RuntimeError;
+		case DwebbleWSResult::SendFailed: return DwebbleWS::EResult::SendFailed;
+		case DwebbleWSResult::ConnectionClosed: return DwebbleWS::EResult::ConnectionClosed;
+		case DwebbleWSResult::QueueFull: return DwebbleWS::EResult::QueueFull;
+		case DwebbleWSResult::InvalidUtf8: return DwebbleWS::EResult::InvalidUtf8;
+		case DwebbleWSResult::Busy: return DwebbleWS::EResult::Busy;
+		default: return DwebbleWS::EResult::RuntimeError;
+		}
+	}
+
+	DwebbleWS::EEventType ConvertEventType(const DwebbleWSEventType Type)
+	{
+		switch (Type)
+		{
+		case DwebbleWSEventType::None: return DwebbleWS::EEventType::None;
+		case DwebbleWSEventType::ClientConnected: return DwebbleWS::EEventType::ClientConnected;
+		case DwebbleWSEventType::ClientDisconnected: return
+				DwebbleWS::EEventType::ClientDisconnected;
+		case DwebbleWSEventType::MessageReceived: return DwebbleWS::EEventType::MessageReceived;
+		case DwebbleWSEventType::Error: return DwebbleWS::EEventType::Error;
+		case DwebbleWSEventType::SessionSuspended: return DwebbleWS::EEventType::SessionSuspended;
+		case DwebbleWSEventType::SessionResumed: return DwebbleWS::EEventType::SessionResumed;
+		case DwebbleWSEventType::PresenceJoined: return DwebbleWS::EEventType::PresenceJoined;
+		case DwebbleWSEventType::PresenceLeft: return DwebbleWS::EEventType::PresenceLeft;
+		case DwebbleWSEventType::TopicMessage: return DwebbleWS::EEventType::TopicMessage;
+		case DwebbleWSEventType::ResponseReceived: return DwebbleWS::EEventType::ResponseReceived;
+		case DwebbleWSEventType::RequestTimedOut: return DwebbleWS::EEventType::RequestTimedOut;
+		case DwebbleWSEventType::RpcCall: return DwebbleWS::EEventType::RpcCall;
+		case DwebbleWSEventType::TypedMessage: return DwebbleWS::EEventType::TypedMessage;
+		case DwebbleWSEventType::SocketIoEvent: return DwebbleWS::EEventType::SocketIoEvent;
+		case DwebbleWSEventType::MqttPublish: return DwebbleWS::EEventType::MqttPublish;
+		case DwebbleWSEventType::DatagramReceived: return DwebbleWS::EEventType::DatagramReceived;
+		case DwebbleWSEventType::PortMapped: return DwebbleWS::EEventType::PortMapped;
+		case DwebbleWSEventType::PublicEndpoint: return DwebbleWS::EEventType::PublicEndpoint;
+		case DwebbleWSEventType::ChannelMessage: return DwebbleWS::EEventType::ChannelMessage;
+		case DwebbleWSEventType::StateUpdated: return DwebbleWS::EEventType::StateUpdated;
+		case DwebbleWSEventType::MessageRejected: return DwebbleWS::EEventType::MessageRejected;
+		case DwebbleWSEventType::ValidationFailed: return DwebbleWS::EEventType::ValidationFailed;
+		case DwebbleWSEventType::SignatureInvalid: return DwebbleWS::EEventType::SignatureInvalid;
+		case DwebbleWSEventType::ReplayRejected: return DwebbleWS::EEventType::ReplayRejected;
+		case DwebbleWSEventType::ServerStopped: return DwebbleWS::EEventType::ServerStopped;
+		case DwebbleWSEventType::TlsHandshakeFailed: return DwebbleWS::EEventType::TlsHandshakeFailed;
+		case DwebbleWSEventType::HandshakeFailed: return DwebbleWS::EEventType::HandshakeFailed;
+		case DwebbleWSEventType::RateLimited: return DwebbleWS::EEventType::RateLimited;
+		case DwebbleWSEventType::EventsDropped: return DwebbleWS::EEventType::EventsDropped;
+		case DwebbleWSEventType::KeyRotated: return DwebbleWS::EEventType::KeyRotated;
+		case DwebbleWSEventType::ShutdownDue: return DwebbleWS::EEventType::ShutdownDue;
+		case DwebbleWSEventType::HealthWarning: return DwebbleWS::EEventType::HealthWarning;
+		case DwebbleWSEventType::ResourceReport: return DwebbleWS::EEventType::ResourceReport;
+		case DwebbleWSEventType::MessageDropped: return DwebbleWS::EEventType::MessageDropped;
+		case DwebbleWSEventType::ChecksumMismatch: return DwebbleWS::EEventType::ChecksumMismatch;
+		default: return DwebbleWS::EEventType::None;
+		}
+	}
+
+	/** Run a C++ middleware passed as the user data of an FFI middleware */
+	DwebbleWSMiddlewareAction CallMiddleware(void* UserData, DwebbleWSMiddlewareMessage* Message)
+	{
+		const DwebbleWS::FMiddleware& Middleware = *static_cast<const DwebbleWS::FMiddleware*>(UserData);
+
+		DwebbleWS::FMiddlewareMessage Converted;
+		Converted.bInbound = Message->inbound;
+		Converted.ConnectionId = Message->connection_id;
+		Converted.bIsText = Message->is_text;
+		Converted.Data.Append(Message->data, static_cast<int32>(Message->data_len));
+
+		const DwebbleWS::EMiddlewareAction Action = Middleware(Converted);
+		if (Action == DwebbleWS::EMiddlewareAction::Modify)
+		{
+			Message->is_text = Converted.bIsText;
+			Message->replacement = dwebble_rws_alloc_buffer(Converted.Data.Num());
+			FMemory::Memcpy(Message->replacement.data, Converted.Data.GetData(), Converted.Data.Num());
+		}
+		return static_cast<DwebbleWSMiddlewareAction>(Action);
+	}
+
+	/** Run a C++ stopped function passed as the user data of the FFI callback */
+	void CallOnStopped(void* UserData, const DwebbleWSStopStats* Stats)
+	{
+		const DwebbleWS::FOnStopped& OnStopped = *static_cast<const DwebbleWS::FOnStopped*>(UserData);
+
+		DwebbleWS::FStopStats Converted;
+		Converted.Connections = static_cast<int64>(Stats->connections);
+		Converted.ForceClosed = static_cast<int64>(Stats->force_closed);
+		OnStopped(Converted);
+	}
+
+	/** Run a C++ health warning function passed as the user data of the FFI callback */
+	void CallOnHealthWarning(void* UserData, DwebbleWSHealthIssue Issue, uint64_t ConnectionId, uint64_t StalledMs)
+	{
+		const DwebbleWS::FOnHealthWarning& OnHealthWarning = *static_cast<const DwebbleWS::FOnHealthWarning*>(UserData);
+		OnHealthWarning(static_cast<DwebbleWS::EHealthIssue>(Issue), ConnectionId, static_cast<int64>(StalledMs));
+	}
+
+	/** Run a C++ thread start function passed as the user data of the FFI callback */
+	void CallOnThreadStart(void* UserData, uint32_t ThreadIndex)
+	{
+		const DwebbleWS::FOnThreadStart& OnThreadStart = *static_cast<const DwebbleWS::FOnThreadStart*>(UserData);
+		OnThreadStart(static_cast<int32>(ThreadIndex));
+	}
+
+	DwebbleWS::FChannelStats ConvertChannelStats(const DwebbleWSChannelStats& Stats)
+	{
+		DwebbleWS::FChannelStats Result;
+		Result.MessagesSent = static_cast<int64>(Stats.messages_sent);
+		Result.MessagesReceived = static_cast<int64>(Stats.messages_received);
+		Result.BytesSent = static_cast<int64>(Stats.bytes_sent);
+		Result.BytesReceived = static_cast<int64>(Stats.bytes_received);
+		Result.MessagesAcked = static_cast<int64>(Stats.messages_acked);
+		Result.MessagesUnacked = static_cast<int64>(Stats.messages_unacked);
+		Result.MessagesRetransmitted = static_cast<int64>(Stats.messages_retransmitted);
+		Result.MessagesAbandoned = static_cast<int64>(Stats.messages_abandoned);
+		Result.StaleDropped = static_cast<int64>(Stats.stale_dropped);
+		Result.DuplicatesDropped = static_cast<int64>(Stats.duplicates_dropped);
+		Result.RttUs = static_cast<int64>(Stats.rtt_us);
+		return Result;
+	}
+
+	/** Copy a polled FFI event (only valid until the next poll) into an owned event */
+	void ConvertEvent(const DwebbleWSEvent& Event, DwebbleWS::FEvent& OutEvent)
+	{
+		OutEvent.EventType = ConvertEventType(Event.event_type);
+		OutEvent.ConnectionId = Event.connection_id;
+		OutEvent.RequestId = Event.request_id;
+		OutEvent.Checksum = Event.checksum;
+
+		// Typed messages carry their type in the request ID field
+		OutEvent.MessageType = OutEvent.EventType == DwebbleWS::EEventType::TypedMessage
+			? static_cast<uint32>(Event.request_id)
+			: 0;
+
+		// Channel messages carry their channel in the request ID field
+		OutEvent.Channel = OutEvent.EventType == DwebbleWS::EEventType::ChannelMessage
+			? static_cast<uint8>(Event.request_id)
+			: 0;
+
+		// State updates carry their key in the request ID field
+		OutEvent.StateKey = OutEvent.EventType == DwebbleWS::EEventType::StateUpdated
+			? static_cast<uint32>(Event.request_id)
+			: 0;
+
+		// Rejected messages carry the filter's code in the request ID field
+		OutEvent.RejectCode = OutEvent.EventType == DwebbleWS::EEventType::MessageRejected
+			? static_cast<int32>(Event.request_id)
+			: 0;
+
+		// Validation failures carry whether the message was delivered in the request ID field
+		OutEvent.bDeliveredAnyway = OutEvent.EventType == DwebbleWS::EEventType::ValidationFailed
+			&& Event.request_id != 0;
+
+		if (Event.data && Event.data_len > 0)
+		{
+			OutEvent.Data.SetNumUninitialized(Event.data_len);
+			FMemory::Memcpy(OutEvent.Data.GetData(), Event.data, Event.data_len);
+		}
+		else
+		{
+			OutEvent.Data.Empty();
+		}
+
+		const bool bHasSubprotocol = OutEvent.EventType == DwebbleWS::EEventType::ClientConnected
+			|| OutEvent.EventType == DwebbleWS::EEventType::SessionResumed;
+		if (bHasSubprotocol && OutEvent.Data.Num() > 0)
+		{
+			const FUTF8ToTCHAR Converted(reinterpret_cast<const ANSICHAR*>(OutEvent.Data.GetData()), OutEvent.Data.Num());
+			OutEvent.Subprotocol = FString(Converted.Length(), Converted.Get());
+		}
+		else
+		{
+			OutEvent.Subprotocol.Empty();
+		}
+
+		const bool bHasUserId = OutEvent.EventType == DwebbleWS::EEventType::PresenceJoined
+			|| OutEvent.EventType == DwebbleWS::EEventType::PresenceLeft;
+		if (bHasUserId && OutEvent.Data.Num() > 0)
+		{
+			const FUTF8ToTCHAR Converted(reinterpret_cast<const ANSICHAR*>(OutEvent.Data.GetData()), OutEvent.Data.Num());
+			OutEvent.UserId = FString(Converted.Length(), Converted.Get());
+		}
+		else
+		{
+			OutEvent.UserId.Empty();
+		}
+
+		// Topic messages carry the topic, RPC calls and Socket.IO events their name in the error message field
+		OutEvent.ErrorMessage.Empty();
+		OutEvent.Topic.Empty();
+		OutEvent.Method.Empty();
+		if (Event.error_message)
+		{
+			FString& Target = OutEvent.EventType == DwebbleWS::EEventType::TopicMessage
+					|| OutEvent.EventType == DwebbleWS::EEventType::MqttPublish
+				? OutEvent.Topic
+				: OutEvent.EventType == DwebbleWS::EEventType::RpcCall
+					|| OutEvent.EventType == DwebbleWS::EEventType::SocketIoEvent
+				? OutEvent.Method
+				: OutEvent.ErrorMessage;
+			Target = UTF8_TO_TCHAR(Event.error_message);
+		}
+	}
+}
+
+class FDwebbleWebSocketClientImpl : public DwebbleWS::IClient
+{
+public:
+	explicit FDwebbleWebSocketClientImpl(const DwebbleWSClientHandle InClientHandle)
+		: ClientHandle(InClientHandle)
+	{
+	}
+
+	virtual ~FDwebbleWebSocketClientImpl() override
+	{
+		dwebble_rws_client_destroy(ClientHandle);
+	}
+
+	virtual DwebbleWS::EResult Send(const TArray<uint8>& Data) override
+	{
+		const DwebbleWSResult Result = dwebble_rws_client_send(ClientHandle, Data.GetData(), Data.Num());
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendText(const FString& Text) override
+	{
+		const FTCHARToUTF8 TextUtf8(*Text, Text.Len());
+		const DwebbleWSResult Result = dwebble_rws_client_send_text_len(
+			ClientHandle,
+			reinterpret_cast<const uint8*>(TextUtf8.Get()),
+			TextUtf8.Length()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendChannel(const uint8 Channel, const TArray<uint8>& Data) override
+	{
+		const DwebbleWSResult Result = dwebble_rws_client_send_channel(ClientHandle, Channel, Data.GetData(), Data.Num());
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::FChannelStats GetChannelStats(const uint8 Channel) const override
+	{
+		DwebbleWSChannelStats Stats;
+		if (dwebble_rws_client_channel_stats(ClientHandle, Channel, &Stats) != DwebbleWSResult::Ok)
+		{
+			return DwebbleWS::FChannelStats();
+		}
+		return ConvertChannelStats(Stats);
+	}
+
+	virtual DwebbleWS::FClientInfo GetInfo() const override
+	{
+		DwebbleWS::FClientInfo OutInfo;
+		DwebbleWSClientInfo Info;
+		if (dwebble_rws_client_get_info(ClientHandle, &Info) == DwebbleWSResult::Ok)
+		{
+			OutInfo.CertificateVerification = static_cast<DwebbleWS::ECertificateVerification>(Info.certificate_verification);
+		}
+		return OutInfo;
+	}
+
+	virtual DwebbleWS::EResult SetSigningKey(const TArray<uint8>& Key) override
+	{
+		const DwebbleWSResult Result = dwebble_rws_client_set_signing_key(ClientHandle, Key.GetData(), Key.Num());
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Close() override
+	{
+		return ConvertResult(dwebble_rws_client_close(ClientHandle));
+	}
+
+	virtual bool PollEvent(DwebbleWS::FEvent& OutEvent) override
+	{
+		DwebbleWSEvent Event;
+		if (!dwebble_rws_client_poll(ClientHandle, &Event))
+		{
+			return false;
+		}
+
+		ConvertEvent(Event, OutEvent);
+		return true;
+	}
+
+	virtual void Tick(const int32 BudgetMs) override
+	{
+		dwebble_rws_client_tick(ClientHandle, static_cast<uint32_t>(FMath::Max(BudgetMs, 0)));
+	}
+
+private:
+	DwebbleWSClientHandle ClientHandle;
+};
+
+TSharedPtr<DwebbleWS::IClient> DwebbleWS::IClient::Connect(const FString& Url, const FString& SettingsJson)
+{
+	const FTCHARToUTF8 UrlUtf8(*Url);
+	const FTCHARToUTF8 SettingsUtf8(*SettingsJson);
+	const DwebbleWSClientHandle ClientHandle = dwebble_rws_client_connect(
+		UrlUtf8.Get(),
+		SettingsJson.IsEmpty() ? nullptr : SettingsUtf8.Get()
+	);
+
+	if (!ClientHandle) return nullptr;
+
+	return MakeShared<FDwebbleWebSocketClientImpl>(ClientHandle);
+}
+
+class FDwebbleWebSocketServicePoolImpl : public DwebbleWS::IServicePool
+{
+public:
+	explicit FDwebbleWebSocketServicePoolImpl(const DwebbleWSServicePoolHandle InPoolHandle)
+		: PoolHandle(InPoolHandle)
+	{
+	}
+
+	virtual ~FDwebbleWebSocketServicePoolImpl() override
+	{
+		dwebble_rws_service_pool_destroy(PoolHandle);
+	}
+
+	virtual DwebbleWS::EResult Request(const TArray<uint8>& Data, const int32 TimeoutMs, uint64& OutRequestId) override
+	{
+		const DwebbleWSResult Result = dwebble_rws_service_pool_request(
+			PoolHandle,
+			Data.GetData(),
+			Data.Num(),
+			static_cast<uint32_t>(FMath::Max(TimeoutMs, 0)),
+			&OutRequestId
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::FServicePoolStats GetStats() const override
+	{
+		DwebbleWS::FServicePoolStats OutStats;
+		DwebbleWSServicePoolStats Stats;
+		if (dwebble_rws_service_pool_stats(PoolHandle, &Stats) == DwebbleWSResult::Ok)
+		{
+			OutStats.Connections = static_cast<int32>(Stats.connections);
+			OutStats.HealthyConnections = static_cast<int32>(Stats.healthy_connections);
+			OutStats.PendingRequests = static_cast<int64>(Stats.pending_requests);
+		}
+		return OutStats;
+	}
+
+	virtual bool PollEvent(DwebbleWS::FEvent& OutEvent) override
+	{
+		DwebbleWSEvent Event;
+		if (!dwebble_rws_service_pool_poll(PoolHandle, &Event))
+		{
+			return false;
+		}
+
+		ConvertEvent(Event, OutEvent);
+		return true;
+	}
+
+	virtual void Tick(const int32 BudgetMs) override
+	{
+		dwebble_rws_service_pool_tick(PoolHandle, static_cast<uint32_t>(FMath::Max(BudgetMs, 0)));
+	}
+
+private:
+	DwebbleWSServicePoolHandle PoolHandle;
+};
+
+TSharedPtr<DwebbleWS::IServicePool> DwebbleWS::IServicePool::Create(const FString& SettingsJson)
+{
+	const FTCHARToUTF8 SettingsUtf8(*SettingsJson);
+	const DwebbleWSServicePoolHandle PoolHandle = dwebble_rws_service_pool_create(SettingsUtf8.Get());
+
+	if (!PoolHandle) return nullptr;
+
+	return MakeShared<FDwebbleWebSocketServicePoolImpl>(PoolHandle);
+}
+
+class FDwebbleWebSocketEventQueueImpl : public DwebbleWS::IEventQueue
+{
+public:
+	explicit FDwebbleWebSocketEventQueueImpl(const DwebbleWSEventQueueHandle InQueueHandle)
+		: QueueHandle(InQueueHandle)
+	{
+	}
+
+	virtual ~FDwebbleWebSocketEventQueueImpl() override
+	{
+		dwebble_rws_event_queue_close(QueueHandle);
+	}
+
+	virtual bool PollEvent(DwebbleWS::FEvent& OutEvent) override
+	{
+		DwebbleWSEvent Event;
+		if (!dwebble_rws_event_queue_poll(QueueHandle, &Event))
+		{
+			return false;
+		}
+
+		ConvertEvent(Event, OutEvent);
+		return true;
+	}
+
+private:
+	DwebbleWSEventQueueHandle QueueHandle;
+};
+
 class FDwebbleWebSocketServerImpl : public DwebbleWS::IServer
 {
 public:
@@ -16,121 +517,1232 @@ public:
 	{
 	}
 
-	virtual ~FDwebbleWebSocketServerImpl() override
+	virtual ~FDwebbleWebSocketServerImpl() override
+	{
+		if (ServerHandle)
+		{
+			this->FDwebbleWebSocketServerImpl::Stop();
+			dwebble_rws_server_destroy(ServerHandle);
+			ServerHandle = nullptr;
+		}
+	}
+
+	virtual DwebbleWS::EResult Start() override
+	{
+		if (bIsRunning)
+		{
+			return DwebbleWS::EResult::AlreadyRunning;
+		}
+
+		// Convert config to FFI struct, passing UTF-8 with explicit lengths
+		const FTCHARToUTF8 BindAddressUtf8(*Config.BindAddress, Config.BindAddress.Len());
+
+		// Join subprotocols into a comma-separated string
+		const FString SubprotocolsJoined = FString::Join(Config.Subprotocols, TEXT(","));
+		const FTCHARToUTF8 SubprotocolsUtf8(*SubprotocolsJoined, SubprotocolsJoined.Len());
+
+		const FTCHARToUTF8 CertPathUtf8(*Config.TlsCertPath, Config.TlsCertPath.Len());
+		const FTCHARToUTF8 KeyPathUtf8(*Config.TlsKeyPath, Config.TlsKeyPath.Len());
+		const FTCHARToUTF8 SettingsJsonUtf8(*Config.SettingsJson, Config.SettingsJson.Len());
+
+		DwebbleWSServerConfig FfiConfig;
+		FfiConfig.port = static_cast<uint16_t>(Config.Port);
+		FfiConfig.bind_address = BindAddressUtf8.Get();
+		FfiConfig.bind_address_len = BindAddressUtf8.Length();
+		FfiConfig.subprotocols = Config.Subprotocols.IsEmpty() ? nullptr : SubprotocolsUtf8.Get();
+		FfiConfig.subprotocols_len = SubprotocolsUtf8.Length();
+		FfiConfig.tls_cert_path = Config.TlsCertPath.IsEmpty() ? nullptr : CertPathUtf8.Get();
+		FfiConfig.tls_cert_path_len = CertPathUtf8.Length();
+		FfiConfig.tls_key_path = Config.TlsKeyPath.IsEmpty() ? nullptr : KeyPathUtf8.Get();
+		FfiConfig.tls_key_path_len = KeyPathUtf8.Length();
+		FfiConfig.settings_json = Config.SettingsJson.IsEmpty() ? nullptr : SettingsJsonUtf8.Get();
+		FfiConfig.settings_json_len = SettingsJsonUtf8.Length();
+
+		// Waits for a StopAsync of the previous run to finish
+		if (ServerHandle)
+		{
+			dwebble_rws_server_destroy(ServerHandle);
+		}
+		ServerHandle = dwebble_rws_server_create(&FfiConfig);
+		if (!ServerHandle)
+		{
+			return DwebbleWS::EResult::RuntimeError;
+		}
+		if (OnStopped)
+		{
+			dwebble_rws_server_set_stopped_callback(ServerHandle, &CallOnStopped, OnStopped.Get());
+		}
+		if (OnHealthWarning)
+		{
+			dwebble_rws_server_set_health_callback(ServerHandle, &CallOnHealthWarning, OnHealthWarning.Get());
+		}
+		if (OnThreadStart)
+		{
+			dwebble_rws_server_set_thread_callback(ServerHandle, &CallOnThreadStart, OnThreadStart.Get());
+		}
+
+		const DwebbleWSResult Result = dwebble_rws_server_start(ServerHandle);
+		if (Result == DwebbleWSResult::Ok)
+		{
+			bIsRunning = true;
+		}
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Stop() override
+	{
+		if (!bIsRunning || !ServerHandle)
+		{
+			return DwebbleWS::EResult::NotRunning;
+		}
+
+		const DwebbleWSResult Result = dwebble_rws_server_stop(ServerHandle);
+		// Busy leaves the state to the start or stop in progress on another thread
+		if (Result != DwebbleWSResult::Busy)
+		{
+			bIsRunning = false;
+		}
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult StopAsync() override
+	{
+		if (!bIsRunning || !ServerHandle)
+		{
+			return DwebbleWS::EResult::NotRunning;
+		}
+
+		const DwebbleWSResult Result = dwebble_rws_server_stop_async(ServerHandle);
+		if (Result != DwebbleWSResult::Busy)
+		{
+			bIsRunning = false;
+		}
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult ForceStop() override
+	{
+		if (!bIsRunning || !ServerHandle)
+		{
+			return DwebbleWS::EResult::NotRunning;
+		}
+
+		const DwebbleWSResult Result = dwebble_rws_server_force_stop(ServerHandle);
+		if (Result != DwebbleWSResult::Busy)
+		{
+			bIsRunning = false;
+		}
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Tick(const int32 BudgetMs) override
+	{
+		if (!bIsRunning || !ServerHandle)
+		{
+			return DwebbleWS::EResult::NotRunning;
+		}
+
+		return ConvertResult(dwebble_rws_server_tick(ServerHandle, static_cast<uint32_t>(FMath::Max(BudgetMs, 0))));
+	}
+
+	virtual void SetOnStopped(DwebbleWS::FOnStopped InOnStopped) override
+	{
+		TUniquePtr<DwebbleWS::FOnStopped> NewOnStopped;
+		if (InOnStopped)
+		{
+			NewOnStopped = MakeUnique<DwebbleWS::FOnStopped>(MoveTemp(InOnStopped));
+		}
+
+		// Start registers it with each server handle it creates
+		if (ServerHandle)
+		{
+			dwebble_rws_server_set_stopped_callback(
+				ServerHandle,
+				NewOnStopped ? &CallOnStopped : nullptr,
+				NewOnStopped.Get()
+			);
+		}
+		OnStopped = MoveTemp(NewOnStopped);
+	}
+
+	virtual void SetOnHealthWarning(DwebbleWS::FOnHealthWarning InOnHealthWarning) override
+	{
+		TUniquePtr<DwebbleWS::FOnHealthWarning> NewOnHealthWarning;
+		if (InOnHealthWarning)
+		{
+			NewOnHealthWarning = MakeUnique<DwebbleWS::FOnHealthWarning>(MoveTemp(InOnHealthWarning));
+		}
+
+		// Start registers it with each server handle it creates; once this returns, no call to the old one is running
+		if (ServerHandle)
+		{
+			dwebble_rws_server_set_health_callback(
+				ServerHandle,
+				NewOnHealthWarning ? &CallOnHealthWarning : nullptr,
+				NewOnHealthWarning.Get()
+			);
+		}
+		OnHealthWarning = MoveTemp(NewOnHealthWarning);
+	}
+
+	virtual DwebbleWS::EResult SetOnThreadStart(DwebbleWS::FOnThreadStart InOnThreadStart) override
+	{
+		TUniquePtr<DwebbleWS::FOnThreadStart> NewOnThreadStart;
+		if (InOnThreadStart)
+		{
+			NewOnThreadStart = MakeUnique<DwebbleWS::FOnThreadStart>(MoveTemp(InOnThreadStart));
+		}
+
+		// Start registers it with each server handle it creates; the old one may be freed once no runtime is left
+		if (ServerHandle)
+		{
+			const DwebbleWSResult Result = dwebble_rws_server_set_thread_callback(
+				ServerHandle,
+				NewOnThreadStart ? &CallOnThreadStart : nullptr,
+				NewOnThreadStart.Get()
+			);
+			if (Result != DwebbleWSResult::Ok)
+			{
+				return ConvertResult(Result);
+			}
+		}
+		OnThreadStart = MoveTemp(NewOnThreadStart);
+		return DwebbleWS::EResult::Ok;
+	}
+
+	virtual bool IsRunning() const override
+	{
+		return bIsRunning;
+	}
+
+	virtual DwebbleWS::EServerState GetState() const override
+	{
+		DwebbleWSServerState State = DwebbleWSServerState::Created;
+		if (ServerHandle)
+		{
+			dwebble_rws_server_get_state(ServerHandle, &State);
+		}
+		return static_cast<DwebbleWS::EServerState>(State);
+	}
+
+	virtual int32 GetPort() const override
+	{
+		if (!ServerHandle) return 0;
+		return dwebble_rws_server_get_port(ServerHandle);
+	}
+
+	virtual int32 GetConnectionCount() const override
+	{
+		if (!ServerHandle) return 0;
+		return static_cast<int32>(dwebble_rws_server_get_connection_count(ServerHandle));
+	}
+
+	virtual uint64 GetDroppedEventCount() const override
+	{
+		if (!ServerHandle) return 0;
+		return dwebble_rws_server_get_dropped_event_count(ServerHandle);
+	}
+
+	virtual DwebbleWS::EResult GetConnectionInfo(const uint64 ConnectionId, DwebbleWS::FConnectionInfo& OutInfo) const override
+	{
+		OutInfo = DwebbleWS::FConnectionInfo();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		DwebbleWSConnectionInfo Info;
+		const DwebbleWSResult Result = dwebble_rws_server_get_connection_info(ServerHandle, ConnectionId, &Info);
+		if (Result == DwebbleWSResult::Ok)
+		{
+			OutInfo.bEncrypted = Info.encrypted;
+			OutInfo.KeyRotations = static_cast<int64>(Info.key_rotations);
+			const bool bHasTlsFingerprint = Algo::AnyOf(Info.tls_fingerprint, [](const uint8 Byte) { return Byte != 0; });
+			OutInfo.TlsFingerprint = bHasTlsFingerprint
+				? BytesToHexLower(Info.tls_fingerprint, UE_ARRAY_COUNT(Info.tls_fingerprint))
+				: FString();
+			OutInfo.HeaderFingerprint = FString::Printf(TEXT("%016llx"), Info.header_fingerprint);
+			OutInfo.QueuedBytes = static_cast<int64>(Info.queued_bytes);
+			OutInfo.ExpiredMessages = static_cast<int64>(Info.expired_messages);
+			OutInfo.Compression = static_cast<DwebbleWS::ECompression>(Info.compression);
+			OutInfo.CompressionLevel = Info.compression_level;
+			OutInfo.DictionaryId = static_cast<int64>(Info.dictionary_id);
+			OutInfo.RawBytesSent = static_cast<int64>(Info.raw_bytes_sent);
+			OutInfo.CompressedBytesSent = static_cast<int64>(Info.compressed_bytes_sent);
+			OutInfo.CompressedBytesReceived = static_cast<int64>(Info.compressed_bytes_received);
+			OutInfo.RawBytesReceived = static_cast<int64>(Info.raw_bytes_received);
+			OutInfo.bVersionNegotiated = Info.version_negotiated;
+			OutInfo.VersionMajor = Info.version_major;
+			OutInfo.VersionMinor = Info.version_minor;
+		}
+		return ConvertResult(Result);
+	}
+
+	virtual FString Info() const override
+	{
+		if (!ServerHandle) return TEXT("");
+
+		char* InfoStr = dwebble_rws_server_info(ServerHandle);
+		if (!InfoStr) return TEXT("");
+
+		FString Result = UTF8_TO_TCHAR(InfoStr);
+		dwebble_rws_free_string(InfoStr);
+		return Result;
+	}
+
+	virtual FString GetListenAddrs() const override
+	{
+		if (!ServerHandle) return TEXT("");
+
+		char* AddrsStr = dwebble_rws_server_get_listen_addrs(ServerHandle);
+		if (!AddrsStr) return TEXT("");
+
+		FString Result = UTF8_TO_TCHAR(AddrsStr);
+		dwebble_rws_free_string(AddrsStr);
+		return Result;
+	}
+
+	virtual FString GetHistograms() const override
+	{
+		if (!ServerHandle) return TEXT("");
+
+		char* HistogramsStr = dwebble_rws_server_get_histograms(ServerHandle);
+		if (!HistogramsStr) return TEXT("");
+
+		FString Result = UTF8_TO_TCHAR(HistogramsStr);
+		dwebble_rws_free_string(HistogramsStr);
+		return Result;
+	}
+
+	virtual DwebbleWS::EResult GetResourceUsage(DwebbleWS::FResourceUsage& OutUsage) const override
+	{
+		OutUsage = DwebbleWS::FResourceUsage();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		DwebbleWSResourceUsage Usage;
+		const DwebbleWSResult Result = dwebble_rws_server_get_resource_usage(ServerHandle, &Usage);
+		if (Result == DwebbleWSResult::Ok)
+		{
+			OutUsage.WorkerThreads = static_cast<int64>(Usage.worker_threads);
+			OutUsage.HelperThreads = static_cast<int64>(Usage.helper_threads);
+			OutUsage.Tasks = static_cast<int64>(Usage.tasks);
+			OutUsage.QueuedTasks = static_cast<int64>(Usage.queued_tasks);
+			OutUsage.BusyMs = static_cast<int64>(Usage.busy_ms);
+			OutUsage.Connections = static_cast<int64>(Usage.connections);
+			OutUsage.SendQueueBytes = static_cast<int64>(Usage.send_queue_bytes);
+			OutUsage.SessionBufferBytes = static_cast<int64>(Usage.session_buffer_bytes);
+			OutUsage.QueuedEvents = static_cast<int64>(Usage.queued_events);
+			OutUsage.EventQueueBytes = static_cast<int64>(Usage.event_queue_bytes);
+		}
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Send(const uint64 ConnectionId, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send(
+			ServerHandle,
+			ConnectionId,
+			Data.GetData(),
+			Data.Num()
+		);
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendWithKey(const uint64 ConnectionId, const FString& Key, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const FTCHARToUTF8 KeyUtf8(*Key);
+		const DwebbleWSResult Result = dwebble_rws_server_send_with_key(
+			ServerHandle,
+			ConnectionId,
+			KeyUtf8.Get(),
+			Data.GetData(),
+			Data.Num()
+		);
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendWithTtl(const uint64 ConnectionId, const TArray<uint8>& Data, const uint32 TtlMs) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_with_ttl(
+			ServerHandle,
+			ConnectionId,
+			Data.GetData(),
+			Data.Num(),
+			TtlMs
+		);
+
+		return ConvertResult(Result);
+	}
+
+	virtual FString GetLastSendError(const uint64 ConnectionId) const override
+	{
+		if (!ServerHandle) return FString();
+
+		char* ErrorStr = dwebble_rws_server_get_last_send_error(ServerHandle, ConnectionId);
+		if (!ErrorStr) return FString();
+
+		FString Result = UTF8_TO_TCHAR(ErrorStr);
+		dwebble_rws_free_string(ErrorStr);
+		return Result;
+	}
+
+	virtual DwebbleWS::EResult SendDatagram(const uint64 ConnectionId, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_datagram(
+			ServerHandle,
+			ConnectionId,
+			Data.GetData(),
+			Data.Num()
+		);
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendText(uint64 ConnectionId, const FString& Text) override;
+
+	virtual DwebbleWS::EResult SendTyped(
+		const uint64 ConnectionId,
+		const uint32 MessageType,
+		const TArray<uint8>& Data
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_typed(
+			ServerHandle,
+			ConnectionId,
+			MessageType,
+			Data.GetData(),
+			Data.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendChannel(
+		const uint64 ConnectionId,
+		const uint8 Channel,
+		const TArray<uint8>& Data
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_channel(
+			ServerHandle,
+			ConnectionId,
+			Channel,
+			Data.GetData(),
+			Data.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendState(
+		const uint64 ConnectionId,
+		const uint32 Key,
+		const TArray<uint8>& State
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_state(
+			ServerHandle,
+			ConnectionId,
+			Key,
+			State.GetData(),
+			State.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SendSequenced(
+		const uint64 ConnectionId,
+		const TArray<uint8>& Data,
+		uint64& OutSequence
+	) override
+	{
+		OutSequence = 0;
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_send_sequenced(
+			ServerHandle,
+			ConnectionId,
+			Data.GetData(),
+			Data.Num(),
+			&OutSequence
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual uint64 GetAckedSequence(const uint64 ConnectionId) const override
+	{
+		if (!ServerHandle) return 0;
+		return dwebble_rws_server_get_acked_sequence(ServerHandle, ConnectionId);
+	}
+
+	virtual DwebbleWS::EResult SetSigningKey(const uint64 ConnectionId, const TArray<uint8>& Key) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_set_signing_key(
+			ServerHandle,
+			ConnectionId,
+			Key.GetData(),
+			Key.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::FChannelStats GetChannelStats(const uint64 ConnectionId, const uint8 Channel) const override
+	{
+		DwebbleWSChannelStats Stats;
+		if (!ServerHandle || dwebble_rws_server_channel_stats(ServerHandle, ConnectionId, Channel, &Stats) != DwebbleWSResult::Ok)
+		{
+			return DwebbleWS::FChannelStats();
+		}
+		return ConvertChannelStats(Stats);
+	}
+
+	virtual DwebbleWS::EResult Disconnect(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_disconnect(ServerHandle, ConnectionId);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult MigrateConnection(const uint64 ConnectionId, DwebbleWS::IServer& Target) override
+	{
+		const DwebbleWSServerHandle TargetHandle = static_cast<FDwebbleWebSocketServerImpl&>(Target).ServerHandle;
+		if (!ServerHandle || !TargetHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_migrate_connection(ServerHandle, ConnectionId, TargetHandle);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult PauseRead(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_pause_read(ServerHandle, ConnectionId));
+	}
+
+	virtual DwebbleWS::EResult ResumeRead(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_resume_read(ServerHandle, ConnectionId));
+	}
+
+	virtual DwebbleWS::EResult AcknowledgeConnection(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_acknowledge_connection(ServerHandle, ConnectionId));
+	}
+
+	virtual DwebbleWS::EResult MarkAuthenticated(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_mark_authenticated(ServerHandle, ConnectionId));
+	}
+
+	virtual DwebbleWS::EResult Rekey(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_rekey(ServerHandle, ConnectionId));
+	}
+
+	virtual DwebbleWS::EResult KickAll(const uint16 Code, const FString& Reason) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto ReasonAnsi = StringCast<ANSICHAR>(*Reason);
+		return ConvertResult(dwebble_rws_server_kick_all(ServerHandle, Code, ReasonAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult KickIp(const FString& Ip, const uint16 Code, const FString& Reason) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto IpAnsi = StringCast<ANSICHAR>(*Ip);
+		const auto ReasonAnsi = StringCast<ANSICHAR>(*Reason);
+		return ConvertResult(dwebble_rws_server_kick_ip(ServerHandle, IpAnsi.Get(), Code, ReasonAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult BanIp(const FString& Ip, const uint64 DurationMs, const FString& Reason) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto IpAnsi = StringCast<ANSICHAR>(*Ip);
+		const auto ReasonAnsi = StringCast<ANSICHAR>(*Reason);
+		return ConvertResult(dwebble_rws_server_ban_ip(ServerHandle, IpAnsi.Get(), DurationMs, ReasonAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult UnbanIp(const FString& Ip) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto IpAnsi = StringCast<ANSICHAR>(*Ip);
+		return ConvertResult(dwebble_rws_server_unban_ip(ServerHandle, IpAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult KickRoom(const FString& Room, const uint16 Code, const FString& Reason) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		const auto ReasonAnsi = StringCast<ANSICHAR>(*Reason);
+		return ConvertResult(dwebble_rws_server_kick_room(ServerHandle, RoomAnsi.Get(), Code, ReasonAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult JoinRoom(const uint64 ConnectionId, const FString& Room) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		return ConvertResult(dwebble_rws_server_room_join(ServerHandle, ConnectionId, RoomAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult LeaveRoom(const uint64 ConnectionId, const FString& Room) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		return ConvertResult(dwebble_rws_server_room_leave(ServerHandle, ConnectionId, RoomAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult GetHistory(const FString& Room, const int32 MaxMessages, TArray<uint8>& OutMessages) override
+	{
+		OutMessages.Empty();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		DwebbleWSBuffer Buffer;
+		const DwebbleWSResult Result = dwebble_rws_server_get_history(
+			ServerHandle,
+			RoomAnsi.Get(),
+			static_cast<size_t>(FMath::Max(MaxMessages, 0)),
+			&Buffer
+		);
+
+		if (Result == DwebbleWSResult::Ok && Buffer.data)
+		{
+			OutMessages.Append(Buffer.data, static_cast<int32>(Buffer.len));
+		}
+		dwebble_rws_free_buffer(Buffer);
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult ClearHistory(const FString& Room) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		return ConvertResult(dwebble_rws_server_clear_history(ServerHandle, RoomAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult BroadcastToRoom(const FString& Room, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		const DwebbleWSResult Result = dwebble_rws_server_room_broadcast(
+			ServerHandle,
+			RoomAnsi.Get(),
+			Data.GetData(),
+			Data.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult BroadcastTextToRoom(const FString& Room, const FString& Text) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		const FTCHARToUTF8 TextUtf8(*Text, Text.Len());
+		const DwebbleWSResult Result = dwebble_rws_server_room_broadcast_text_len(
+			ServerHandle,
+			RoomAnsi.Get(),
+			reinterpret_cast<const uint8*>(TextUtf8.Get()),
+			TextUtf8.Length()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SetTag(const uint64 ConnectionId, const FString& Tag) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		return ConvertResult(dwebble_rws_server_set_tag(ServerHandle, ConnectionId, TagAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult ClearTag(const uint64 ConnectionId, const FString& Tag) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		return ConvertResult(dwebble_rws_server_clear_tag(ServerHandle, ConnectionId, TagAnsi.Get()));
+	}
+
+	virtual FString GetTags(const uint64 ConnectionId) const override
+	{
+		if (!ServerHandle) return TEXT("[]");
+
+		char* TagsStr = dwebble_rws_server_get_tags(ServerHandle, ConnectionId);
+		if (!TagsStr) return TEXT("[]");
+
+		FString Result = UTF8_TO_TCHAR(TagsStr);
+		dwebble_rws_free_string(TagsStr);
+		return Result;
+	}
+
+	virtual DwebbleWS::EResult BroadcastToTag(const FString& Tag, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		const DwebbleWSResult Result = dwebble_rws_server_tag_broadcast(
+			ServerHandle,
+			TagAnsi.Get(),
+			Data.GetData(),
+			Data.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult BroadcastTextToTag(const FString& Tag, const FString& Text) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		const FTCHARToUTF8 TextUtf8(*Text, Text.Len());
+		const DwebbleWSResult Result = dwebble_rws_server_tag_broadcast_text_len(
+			ServerHandle,
+			TagAnsi.Get(),
+			reinterpret_cast<const uint8*>(TextUtf8.Get()),
+			TextUtf8.Length()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult KickTag(const FString& Tag, const uint16 Code, const FString& Reason) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		const auto ReasonAnsi = StringCast<ANSICHAR>(*Reason);
+		return ConvertResult(dwebble_rws_server_kick_tag(ServerHandle, TagAnsi.Get(), Code, ReasonAnsi.Get()));
+	}
+
+	virtual DwebbleWS::FTagStats GetTagStats(const FString& Tag) const override
+	{
+		DwebbleWS::FTagStats Result;
+		if (!ServerHandle) return Result;
+
+		const auto TagAnsi = StringCast<ANSICHAR>(*Tag);
+		DwebbleWSTagStats Stats;
+		if (dwebble_rws_server_tag_stats(ServerHandle, TagAnsi.Get(), &Stats) == DwebbleWSResult::Ok)
+		{
+			Result.Connections = static_cast<int64>(Stats.connections);
+			Result.Suspended = static_cast<int64>(Stats.suspended);
+			Result.QueuedBytes = static_cast<int64>(Stats.queued_bytes);
+		}
+		return Result;
+	}
+
+	virtual DwebbleWS::EResult Pipe(const uint64 ConnectionA, const uint64 ConnectionB) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_pipe(ServerHandle, ConnectionA, ConnectionB);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Unpipe(const uint64 ConnectionId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_unpipe(ServerHandle, ConnectionId);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Request(
+		const uint64 ConnectionId,
+		const TArray<uint8>& Data,
+		const uint32 TimeoutMs,
+		uint64& OutRequestId
+	) override
+	{
+		OutRequestId = 0;
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_request(
+			ServerHandle,
+			ConnectionId,
+			Data.GetData(),
+			Data.Num(),
+			TimeoutMs,
+			&OutRequestId
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult Subscribe(const uint64 ConnectionId, const FString& Pattern) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto PatternAnsi = StringCast<ANSICHAR>(*Pattern);
+		return ConvertResult(dwebble_rws_server_subscribe(ServerHandle, ConnectionId, PatternAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult Unsubscribe(const uint64 ConnectionId, const FString& Pattern) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto PatternAnsi = StringCast<ANSICHAR>(*Pattern);
+		return ConvertResult(dwebble_rws_server_unsubscribe(ServerHandle, ConnectionId, PatternAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult Publish(const FString& Topic, const TArray<uint8>& Data) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TopicAnsi = StringCast<ANSICHAR>(*Topic);
+		const DwebbleWSResult Result = dwebble_rws_server_publish(
+			ServerHandle,
+			TopicAnsi.Get(),
+			Data.GetData(),
+			Data.Num()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult PublishText(const FString& Topic, const FString& Text) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TopicAnsi = StringCast<ANSICHAR>(*Topic);
+		const FTCHARToUTF8 TextUtf8(*Text, Text.Len());
+		const DwebbleWSResult Result = dwebble_rws_server_publish_text_len(
+			ServerHandle,
+			TopicAnsi.Get(),
+			reinterpret_cast<const uint8*>(TextUtf8.Get()),
+			TextUtf8.Length()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult MqttPublish(
+		const FString& Topic,
+		const TArray<uint8>& Data,
+		const uint8 Qos,
+		const bool bRetain
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto TopicAnsi = StringCast<ANSICHAR>(*Topic);
+		const DwebbleWSResult Result = dwebble_rws_server_mqtt_publish(
+			ServerHandle,
+			TopicAnsi.Get(),
+			Data.GetData(),
+			Data.Num(),
+			Qos,
+			bRetain
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult RpcReply(const uint64 CallId, const FString& ResultJson) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto ResultAnsi = StringCast<ANSICHAR>(*ResultJson);
+		return ConvertResult(dwebble_rws_server_rpc_reply(ServerHandle, CallId, ResultAnsi.Get()));
+	}
+
+	virtual DwebbleWS::EResult RpcError(
+		const uint64 CallId,
+		const int32 Code,
+		const FString& Message,
+		const FString& DataJson
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto MessageAnsi = StringCast<ANSICHAR>(*Message);
+		const auto DataAnsi = StringCast<ANSICHAR>(*DataJson);
+		const DwebbleWSResult Result = dwebble_rws_server_rpc_error(
+			ServerHandle,
+			CallId,
+			Code,
+			MessageAnsi.Get(),
+			DataJson.IsEmpty() ? nullptr : DataAnsi.Get()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SocketIoEmit(
+		const uint64 ConnectionId,
+		const FString& EventName,
+		const FString& ArgsJson
+	) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto EventAnsi = StringCast<ANSICHAR>(*EventName);
+		const auto ArgsAnsi = StringCast<ANSICHAR>(*ArgsJson);
+		const DwebbleWSResult Result = dwebble_rws_server_socketio_emit(
+			ServerHandle,
+			ConnectionId,
+			EventAnsi.Get(),
+			ArgsJson.IsEmpty() ? nullptr : ArgsAnsi.Get()
+		);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SocketIoEmitWithAck(
+		const uint64 ConnectionId,
+		const FString& EventName,
+		const FString& ArgsJson,
+		const uint32 TimeoutMs,
+		uint64& OutRequestId
+	) override
 	{
-		if (ServerHandle)
-		{
-			this->FDwebbleWebSocketServerImpl::Stop();
-			dwebble_rws_server_destroy(ServerHandle);
-			ServerHandle = nullptr;
-		}
+		OutRequestId = 0;
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto EventAnsi = StringCast<ANSICHAR>(*EventName);
+		const auto ArgsAnsi = StringCast<ANSICHAR>(*ArgsJson);
+		const DwebbleWSResult Result = dwebble_rws_server_socketio_emit_with_ack(
+			ServerHandle,
+			ConnectionId,
+			EventAnsi.Get(),
+			ArgsJson.IsEmpty() ? nullptr : ArgsAnsi.Get(),
+			TimeoutMs,
+			&OutRequestId
+		);
+		return ConvertResult(Result);
 	}
 
-	virtual DwebbleWS::EResult Start() override
+	virtual DwebbleWS::EResult SocketIoAck(const uint64 Ack, const FString& ArgsJson) override
 	{
-		if (bIsRunning)
-		{
-			return DwebbleWS::EResult::AlreadyRunning;
-		}
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto ArgsAnsi = StringCast<ANSICHAR>(*ArgsJson);
+		return ConvertResult(dwebble_rws_server_socketio_ack(
+			ServerHandle,
+			Ack,
+			ArgsJson.IsEmpty() ? nullptr : ArgsAnsi.Get()
+		));
+	}
 
-		// Convert config to FFI struct
-		const auto BindAddressAnsi = StringCast<ANSICHAR>(*Config.BindAddress);
+	virtual DwebbleWS::EResult SetUserId(const uint64 ConnectionId, const FString& UserId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
 
-		// Join subprotocols into a comma-separated string
-		const FString SubprotocolsJoined = FString::Join(Config.Subprotocols, TEXT(","));
-		const auto SubprotocolsAnsi = StringCast<ANSICHAR>(*SubprotocolsJoined);
+		const auto UserIdAnsi = StringCast<ANSICHAR>(*UserId);
+		const DwebbleWSResult Result = dwebble_rws_server_set_user_id(
+			ServerHandle,
+			ConnectionId,
+			UserId.IsEmpty() ? nullptr : UserIdAnsi.Get()
+		);
+		return ConvertResult(Result);
+	}
 
-		const auto CertPathAnsi = StringCast<ANSICHAR>(*Config.TlsCertPath);
-		const auto KeyPathAnsi = StringCast<ANSICHAR>(*Config.TlsKeyPath);
+	virtual FString GetUserId(const uint64 ConnectionId) const override
+	{
+		if (!ServerHandle) return TEXT("");
 
-		DwebbleWSServerConfig FfiConfig;
-		FfiConfig.port = static_cast<uint16_t>(Config.Port);
-		FfiConfig.bind_address = BindAddressAnsi.Get();
-		FfiConfig.subprotocols = Config.Subprotocols.IsEmpty() ? nullptr : SubprotocolsAnsi.Get();
-		FfiConfig.tls_cert_path = Config.TlsCertPath.IsEmpty() ? nullptr : CertPathAnsi.Get();
-		FfiConfig.tls_key_path = Config.TlsKeyPath.IsEmpty() ? nullptr : KeyPathAnsi.Get();
+		char* UserIdStr = dwebble_rws_server_get_user_id(ServerHandle, ConnectionId);
+		if (!UserIdStr) return TEXT("");
 
-		ServerHandle = dwebble_rws_server_create(&FfiConfig);
-		if (!ServerHandle)
-		{
-			return DwebbleWS::EResult::RuntimeError;
-		}
+		FString Result = UTF8_TO_TCHAR(UserIdStr);
+		dwebble_rws_free_string(UserIdStr);
+		return Result;
+	}
 
-		const DwebbleWSResult Result = dwebble_rws_server_start(ServerHandle);
-		if (Result == DwebbleWSResult::Ok)
+	virtual bool IsUserOnline(const FString& UserId) const override
+	{
+		if (!ServerHandle) return false;
+
+		const auto UserIdAnsi = StringCast<ANSICHAR>(*UserId);
+		return dwebble_rws_server_is_user_online(ServerHandle, UserIdAnsi.Get());
+	}
+
+	virtual FString GetOnlineUsers(const FString& Room) const override
+	{
+		if (!ServerHandle) return TEXT("[]");
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		char* UsersStr = dwebble_rws_server_get_online_users(ServerHandle, Room.IsEmpty() ? nullptr : RoomAnsi.Get());
+		if (!UsersStr) return TEXT("[]");
+
+		FString Result = UTF8_TO_TCHAR(UsersStr);
+		dwebble_rws_free_string(UsersStr);
+		return Result;
+	}
+
+	virtual FString GetSessionToken(const uint64 ConnectionId) const override
+	{
+		if (!ServerHandle) return TEXT("");
+
+		char* TokenStr = dwebble_rws_server_get_session_token(ServerHandle, ConnectionId);
+		if (!TokenStr) return TEXT("");
+
+		FString Result = UTF8_TO_TCHAR(TokenStr);
+		dwebble_rws_free_string(TokenStr);
+		return Result;
+	}
+
+	virtual DwebbleWS::EResult JournalSelect(const uint64 ConnectionId, const bool bEnabled) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const DwebbleWSResult Result = dwebble_rws_server_journal_select(ServerHandle, ConnectionId, bEnabled);
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult JournalSelectRoom(const FString& Room, const bool bEnabled) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		return ConvertResult(dwebble_rws_server_journal_select_room(ServerHandle, RoomAnsi.Get(), bEnabled));
+	}
+
+	virtual DwebbleWS::EResult JournalRead(const uint64 FromSequence, const int32 MaxRecords, TArray<uint8>& OutRecords) override
+	{
+		OutRecords.Empty();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		DwebbleWSBuffer Buffer;
+		const DwebbleWSResult Result = dwebble_rws_server_journal_read(
+			ServerHandle,
+			FromSequence,
+			static_cast<size_t>(FMath::Max(MaxRecords, 0)),
+			&Buffer
+		);
+
+		if (Result == DwebbleWSResult::Ok && Buffer.data)
 		{
-			bIsRunning = true;
+			OutRecords.Append(Buffer.data, static_cast<int32>(Buffer.len));
 		}
+		dwebble_rws_free_buffer(Buffer);
 
 		return ConvertResult(Result);
 	}
 
-	virtual DwebbleWS::EResult Stop() override
+	virtual DwebbleWS::EResult ArchiveQuery(const FString& QueryJson, TArray<uint8>& OutRows) override
 	{
-		if (!bIsRunning || !ServerHandle)
+		OutRows.Empty();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const FTCHARToUTF8 QueryUtf8(*QueryJson);
+		DwebbleWSBuffer Buffer;
+		const DwebbleWSResult Result = dwebble_rws_server_archive_query(
+			ServerHandle,
+			QueryJson.IsEmpty() ? nullptr : QueryUtf8.Get(),
+			&Buffer
+		);
+
+		if (Result == DwebbleWSResult::Ok && Buffer.data)
 		{
-			return DwebbleWS::EResult::NotRunning;
+			OutRows.Append(Buffer.data, static_cast<int32>(Buffer.len));
 		}
+		dwebble_rws_free_buffer(Buffer);
 
-		const DwebbleWSResult Result = dwebble_rws_server_stop(ServerHandle);
-		bIsRunning = false;
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SetNetworkSim(const uint64 ConnectionId, const FString& SimJson) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
 
+		const auto SimJsonAnsi = StringCast<ANSICHAR>(*SimJson);
+		const DwebbleWSResult Result = dwebble_rws_server_set_network_sim(
+			ServerHandle,
+			ConnectionId,
+			SimJson.IsEmpty() ? nullptr : SimJsonAnsi.Get()
+		);
 		return ConvertResult(Result);
 	}
 
-	virtual bool IsRunning() const override
+	virtual DwebbleWS::EResult ScheduleChaos(const FString& ScenarioJson) override
 	{
-		return bIsRunning;
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto ScenarioJsonAnsi = StringCast<ANSICHAR>(*ScenarioJson);
+		const DwebbleWSResult Result = dwebble_rws_server_schedule_chaos(ServerHandle, ScenarioJsonAnsi.Get());
+		return ConvertResult(Result);
 	}
 
-	virtual int32 GetPort() const override
+	virtual DwebbleWS::EResult CancelChaos() override
 	{
-		if (!ServerHandle) return 0;
-		return dwebble_rws_server_get_port(ServerHandle);
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_cancel_chaos(ServerHandle));
 	}
 
-	virtual int32 GetConnectionCount() const override
+	virtual DwebbleWS::EResult ScheduleShutdown(const FDateTime& At, const FString& WarningMessage, const TArray<uint32>& WarningIntervals) override
 	{
-		if (!ServerHandle) return 0;
-		return static_cast<int32>(dwebble_rws_server_get_connection_count(ServerHandle));
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto WarningMessageAnsi = StringCast<ANSICHAR>(*WarningMessage);
+		const DwebbleWSResult Result = dwebble_rws_server_schedule_shutdown(
+			ServerHandle,
+			static_cast<uint64>(FMath::Max<int64>(At.ToUnixTimestamp(), 0)),
+			WarningMessage.IsEmpty() ? nullptr : WarningMessageAnsi.Get(),
+			WarningIntervals.Num() > 0 ? WarningIntervals.GetData() : nullptr,
+			WarningIntervals.Num()
+		);
+		return ConvertResult(Result);
 	}
 
-	virtual FString Info() const override
+	virtual DwebbleWS::EResult CancelShutdown() override
 	{
-		if (!ServerHandle) return TEXT("");
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
 
-		char* InfoStr = dwebble_rws_server_info(ServerHandle);
-		if (!InfoStr) return TEXT("");
+		return ConvertResult(dwebble_rws_server_cancel_shutdown(ServerHandle));
+	}
 
-		FString Result = UTF8_TO_TCHAR(InfoStr);
-		dwebble_rws_free_string(InfoStr);
-		return Result;
+	virtual DwebbleWS::EResult DiscoverPublicEndpoint(const FString& StunServer) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const auto StunServerAnsi = StringCast<ANSICHAR>(*StunServer);
+		const DwebbleWSResult Result = dwebble_rws_server_discover_public_endpoint(ServerHandle, StunServerAnsi.Get());
+		return ConvertResult(Result);
 	}
 
-	virtual DwebbleWS::EResult Send(const uint64 ConnectionId, const TArray<uint8>& Data) override
+	virtual DwebbleWS::EResult UpdateConfig(const FString& SettingsJson) override
 	{
 		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
 
-		const DwebbleWSResult Result = dwebble_rws_server_send(
+		const auto SettingsJsonAnsi = StringCast<ANSICHAR>(*SettingsJson);
+		const DwebbleWSResult Result = dwebble_rws_server_update_config(ServerHandle, SettingsJsonAnsi.Get());
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult SetZstdDictionary(const TArray<uint8>& Dictionary) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_set_zstd_dictionary(
 			ServerHandle,
-			ConnectionId,
-			Data.GetData(),
-			Data.Num()
-		);
+			Dictionary.Num() > 0 ? Dictionary.GetData() : nullptr,
+			Dictionary.Num()
+		));
+	}
+
+	virtual DwebbleWS::EResult GetZstdDictionary(TArray<uint8>& OutDictionary) const override
+	{
+		OutDictionary.Empty();
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		DwebbleWSBuffer Buffer;
+		const DwebbleWSResult Result = dwebble_rws_server_get_zstd_dictionary(ServerHandle, &Buffer);
+
+		if (Result == DwebbleWSResult::Ok && Buffer.data)
+		{
+			OutDictionary.Append(Buffer.data, static_cast<int32>(Buffer.len));
+		}
+		dwebble_rws_free_buffer(Buffer);
 
 		return ConvertResult(Result);
 	}
 
-	virtual DwebbleWS::EResult SendText(uint64 ConnectionId, const FString& Text) override;
+	virtual DwebbleWS::EResult Rebind(
+		const int32 Port,
+		const FString& BindAddress,
+		const FString& TlsCertPath,
+		const FString& TlsKeyPath
+	) override
+	{
+		if (!bIsRunning || !ServerHandle)
+		{
+			return DwebbleWS::EResult::NotRunning;
+		}
 
-	virtual DwebbleWS::EResult Disconnect(const uint64 ConnectionId) override
+		const FTCHARToUTF8 BindAddressUtf8(*BindAddress, BindAddress.Len());
+		const FTCHARToUTF8 CertPathUtf8(*TlsCertPath, TlsCertPath.Len());
+		const FTCHARToUTF8 KeyPathUtf8(*TlsKeyPath, TlsKeyPath.Len());
+
+		DwebbleWSBindConfig FfiConfig;
+		FfiConfig.port = static_cast<uint16_t>(Port);
+		FfiConfig.bind_address = BindAddressUtf8.Get();
+		FfiConfig.bind_address_len = BindAddressUtf8.Length();
+		FfiConfig.tls_cert_path = TlsCertPath.IsEmpty() ? nullptr : CertPathUtf8.Get();
+		FfiConfig.tls_cert_path_len = CertPathUtf8.Length();
+		FfiConfig.tls_key_path = TlsKeyPath.IsEmpty() ? nullptr : KeyPathUtf8.Get();
+		FfiConfig.tls_key_path_len = KeyPathUtf8.Length();
+
+		const DwebbleWSResult Result = dwebble_rws_server_rebind(ServerHandle, &FfiConfig);
+		if (Result == DwebbleWSResult::Ok)
+		{
+			Config.Port = Port;
+			Config.BindAddress = BindAddress;
+			Config.TlsCertPath = TlsCertPath;
+			Config.TlsKeyPath = TlsKeyPath;
+		}
+
+		return ConvertResult(Result);
+	}
+
+	virtual DwebbleWS::EResult AddMiddleware(
+		const int32 Priority,
+		DwebbleWS::FMiddleware Middleware,
+		uint64& OutMiddlewareId
+	) override
 	{
+		OutMiddlewareId = 0;
 		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+		if (!Middleware) return DwebbleWS::EResult::InvalidParam;
 
-		const DwebbleWSResult Result = dwebble_rws_server_disconnect(ServerHandle, ConnectionId);
+		TUniquePtr<DwebbleWS::FMiddleware>& Stored = Middlewares.Add_GetRef(
+			MakeUnique<DwebbleWS::FMiddleware>(MoveTemp(Middleware))
+		);
+		const DwebbleWSResult Result = dwebble_rws_server_add_middleware(
+			ServerHandle,
+			&CallMiddleware,
+			Stored.Get(),
+			Priority,
+			&OutMiddlewareId
+		);
 		return ConvertResult(Result);
 	}
 
+	virtual DwebbleWS::EResult RemoveMiddleware(const uint64 MiddlewareId) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		// The function stays alive with the server, as messages in flight may still call it
+		return ConvertResult(dwebble_rws_server_remove_middleware(ServerHandle, MiddlewareId));
+	}
+
+	virtual DwebbleWS::EResult SetEventMask(const uint64 Mask) override
+	{
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		return ConvertResult(dwebble_rws_server_set_event_mask(ServerHandle, Mask));
+	}
+
 	virtual bool PollEvent(DwebbleWS::FEvent& OutEvent) override
 	{
 		if (!ServerHandle) return false;
@@ -141,77 +1753,91 @@ public:
 			return false;
 		}
 
-		OutEvent.EventType = ConvertEventType(Event.event_type);
-		OutEvent.ConnectionId = Event.connection_id;
+		ConvertEvent(Event, OutEvent);
+		return true;
+	}
 
-		if (Event.data && Event.data_len > 0)
-		{
-			OutEvent.Data.SetNumUninitialized(Event.data_len);
-			FMemory::Memcpy(OutEvent.Data.GetData(), Event.data, Event.data_len);
-		}
-		else
-		{
-			OutEvent.Data.Empty();
-		}
+	virtual int32 PollEvents(TArray<DwebbleWS::FEvent>& OutEvents, const int32 MaxEvents) override
+	{
+		OutEvents.Reset();
+		if (!ServerHandle || MaxEvents <= 0) return 0;
 
-		if (Event.error_message)
-		{
-			OutEvent.ErrorMessage = UTF8_TO_TCHAR(Event.error_message);
-		}
-		else
+		TArray<DwebbleWSEvent> Events;
+		Events.SetNumUninitialized(MaxEvents);
+		const int32 Count = static_cast<int32>(dwebble_rws_server_poll_batch(ServerHandle, Events.GetData(), MaxEvents));
+
+		OutEvents.SetNum(Count);
+		for (int32 Index = 0; Index < Count; ++Index)
 		{
-			OutEvent.ErrorMessage.Empty();
+			ConvertEvent(Events[Index], OutEvents[Index]);
 		}
+		return Count;
+	}
 
-		return true;
+	virtual TSharedPtr<DwebbleWS::IClient> ConnectLoopback() override
+	{
+		if (!ServerHandle) return nullptr;
+
+		const DwebbleWSClientHandle ClientHandle = dwebble_rws_server_connect_loopback(ServerHandle);
+		if (!ClientHandle) return nullptr;
+
+		return MakeShared<FDwebbleWebSocketClientImpl>(ClientHandle);
 	}
 
-private:
-	static DwebbleWS::EResult ConvertResult(const DwebbleWSResult Result)
+	virtual DwebbleWS::EResult Dial(const FString& Url, const FString& SettingsJson, uint64& OutConnectionId) override
 	{
-		switch (Result)
-		{
-		case DwebbleWSResult::Ok: return DwebbleWS::EResult::Ok;
-		case DwebbleWSResult::InvalidHandle: return DwebbleWS::EResult::InvalidHandle;
-		case DwebbleWSResult::InvalidParam: return DwebbleWS::EResult::InvalidParam;
-		case DwebbleWSResult::AlreadyRunning: return DwebbleWS::EResult::AlreadyRunning;
-		case DwebbleWSResult::NotRunning: return DwebbleWS::EResult::NotRunning;
-		case DwebbleWSResult::BindFailed: return DwebbleWS::EResult::BindFailed;
-		case DwebbleWSResult::TlsError: return DwebbleWS::EResult::TlsError;
-		case DwebbleWSResult::RuntimeError: return DwebbleWS::EResult::RuntimeError;
-		case DwebbleWSResult::SendFailed: return DwebbleWS::EResult::SendFailed;
-		case DwebbleWSResult::ConnectionClosed: return DwebbleWS::EResult::ConnectionClosed;
-		default: return DwebbleWS::EResult::RuntimeError;
-		}
+		OutConnectionId = 0;
+		if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
+
+		const FTCHARToUTF8 UrlUtf8(*Url);
+		const FTCHARToUTF8 SettingsUtf8(*SettingsJson);
+		const DwebbleWSResult Result = dwebble_rws_server_dial(
+			ServerHandle,
+			UrlUtf8.Get(),
+			SettingsJson.IsEmpty() ? nullptr : SettingsUtf8.Get(),
+			&OutConnectionId
+		);
+		return ConvertResult(Result);
 	}
 
-	static DwebbleWS::EEventType ConvertEventType(const DwebbleWSEventType Type)
+	virtual TSharedPtr<DwebbleWS::IEventQueue> OpenEventQueue(const FString& Room) override
 	{
-		switch (Type)
-		{
-		case DwebbleWSEventType::None: return DwebbleWS::EEventType::None;
-		case DwebbleWSEventType::ClientConnected: return DwebbleWS::EEventType::ClientConnected;
-		case DwebbleWSEventType::ClientDisconnected: return
-				DwebbleWS::EEventType::ClientDisconnected;
-		case DwebbleWSEventType::MessageReceived: return DwebbleWS::EEventType::MessageReceived;
-		case DwebbleWSEventType::Error: return DwebbleWS::EEventType::Error;
-		default: return DwebbleWS::EEventType::None;
-		}
+		if (!ServerHandle) return nullptr;
+
+		const auto RoomAnsi = StringCast<ANSICHAR>(*Room);
+		const DwebbleWSEventQueueHandle QueueHandle = dwebble_rws_server_open_event_queue(ServerHandle, RoomAnsi.Get());
+		if (!QueueHandle) return nullptr;
+
+		return MakeShared<FDwebbleWebSocketEventQueueImpl>(QueueHandle);
 	}
 
+private:
 	DwebbleWS::FServerConfig Config;
 	DwebbleWSServerHandle ServerHandle;
 	bool bIsRunning;
+
+	/** Functions of every middleware ever added, outliving the server handle */
+	TArray<TUniquePtr<DwebbleWS::FMiddleware>> Middlewares;
+
+	/** Function of the stopped callback, if one is set */
+	TUniquePtr<DwebbleWS::FOnStopped> OnStopped;
+
+	/** Function of the health callback, if one is set */
+	TUniquePtr<DwebbleWS::FOnHealthWarning> OnHealthWarning;
+
+	/** Function of the thread start callback, if one is set */
+	TUniquePtr<DwebbleWS::FOnThreadStart> OnThreadStart;
 };
 
 DwebbleWS::EResult FDwebbleWebSocketServerImpl::SendText(const uint64 ConnectionId, const FString& Text) {
 	if (!ServerHandle) return DwebbleWS::EResult::InvalidHandle;
 
-	const auto TextAnsi = StringCast<ANSICHAR>(*Text);
-	const DwebbleWSResult Result = dwebble_rws_server_send_text(
+	const FTCHARToUTF8 TextUtf8(*Text, Text.Len());
+	const DwebbleWSResult Result = dwebble_rws_server_send_text_len(
 		ServerHandle,
 		ConnectionId,
-		TextAnsi.Get()
+		reinterpret_cast<const uint8*>(TextUtf8.Get()),
+		TextUtf8.Length()
 	);
 
 	return ConvertResult(Result);
@@ -221,3 +1847,18 @@ TSharedPtr<DwebbleWS::IServer> DwebbleWS::IServer::Create(const FServerConfig& C
 {
 	return MakeShared<FDwebbleWebSocketServerImpl>(Config);
 }
+
+DwebbleWS::FPoolStats DwebbleWS::IServer::GetPoolStats()
+{
+	FPoolStats Result;
+	DwebbleWSPoolStats Stats;
+	if (dwebble_rws_pool_stats(&Stats) == DwebbleWSResult::Ok)
+	{
+		Result.Hits = static_cast<int64>(Stats.hits);
+		Result.Misses = static_cast<int64>(Stats.misses);
+		Result.Unpooled = static_cast<int64>(Stats.unpooled);
+		Result.FreeBuffers = static_cast<int64>(Stats.free_buffers);
+		Result.FreeBytes = static_cast<int64>(Stats.free_bytes);
+	}
+	return Result;
+}
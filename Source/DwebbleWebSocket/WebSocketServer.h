@@ -4,9 +4,53 @@
 
 #include "CoreMinimal.h"
 #include "DwebbleTypes.h"
+#include "WebSocketClient.h"
 
 namespace Dwebble::WebSocket
 {
+	/**
+	 * A message passing through server middleware
+	 */
+	struct FMiddlewareMessage
+	{
+		/** True for messages from a client, false for messages to one */
+		bool bInbound = false;
+
+		uint64 ConnectionId = 0;
+
+		/** Whether this is a text message; replacements that are not valid UTF-8 go on as binary */
+		bool bIsText = false;
+
+		/** Message content; change it and return Modify to replace the message */
+		TArray<uint8> Data;
+	};
+
+	/** Server middleware, called from any thread, possibly several at once */
+	using FMiddleware = TFunction<EMiddlewareAction(FMiddlewareMessage& Message)>;
+
+	/** Called on the stopping thread once a server has closed its connections and shut its runtime down */
+	using FOnStopped = TFunction<void(const FStopStats& Stats)>;
+
+	/** Called on the watchdog thread with each health warning; ConnectionId is that of a stalled writer, 0 otherwise */
+	using FOnHealthWarning = TFunction<void(EHealthIssue Issue, uint64 ConnectionId, int64 StalledMs)>;
+
+	/** Called on each thread of a server's runtime as it starts, with the order it started in */
+	using FOnThreadStart = TFunction<void(int32 ThreadIndex)>;
+
+	/**
+	 * Queue taking the events of a room's members, closed when released
+	 *
+	 * Different queues may be polled from different threads at the same time.
+	 */
+	class DWEBBLEWEBSOCKET_API IEventQueue
+	{
+	public:
+		virtual ~IEventQueue() = default;
+
+		/** Poll for events of the room's members */
+		virtual bool PollEvent(FEvent& OutEvent) = 0;
+	};
+
 	/**
 	 * WebSocket Server interface
 	 */
@@ -18,36 +62,334 @@ namespace Dwebble::WebSocket
 		/** Create a new WebSocket server instance */
 		static TSharedPtr<IServer> Create(const FServerConfig& Config);
 
+		/** Statistics of the payload buffer pool shared by every server and client */
+		static FPoolStats GetPoolStats();
+
 		/** Start the server */
 		virtual EResult Start() = 0;
 
 		/** Stop the server */
 		virtual EResult Stop() = 0;
 
+		/**
+		 * Stop the server on a background thread and return at once; the ServerStopped event reports when it is
+		 * done. Starting again or destroying the server waits for the stop to finish.
+		 */
+		virtual EResult StopAsync() = 0;
+
+		/** Stop the server at once without closing handshakes, aborting its outstanding work */
+		virtual EResult ForceStop() = 0;
+
+		/**
+		 * Run the server's pending work for up to BudgetMs on this thread. Builds with the
+		 * `single-thread` feature create no threads, so call this every frame while running;
+		 * other builds return at once.
+		 */
+		virtual EResult Tick(int32 BudgetMs) = 0;
+
+		/**
+		 * Call OnStopped at the end of every Stop of a running server, after the ServerStopped event is queued,
+		 * when it is safe to unload the module. Replaces any earlier function; an empty one removes it.
+		 */
+		virtual void SetOnStopped(FOnStopped OnStopped) = 0;
+
+		/**
+		 * Call OnHealthWarning with each warning of the watchdog setting, as the HealthWarning event is raised; it
+		 * still arrives when the game thread stops polling. Replaces any earlier function; an empty one removes it.
+		 */
+		virtual void SetOnHealthWarning(FOnHealthWarning OnHealthWarning) = 0;
+
+		/**
+		 * Call OnThreadStart on each thread of the server's runtime as it starts, e.g. to set its affinity with
+		 * FPlatformProcess::SetThreadAffinityMask where the threads setting can't. Takes effect from the next Start;
+		 * fails with AlreadyRunning until the server has stopped. An empty function removes it.
+		 */
+		virtual EResult SetOnThreadStart(FOnThreadStart OnThreadStart) = 0;
+
 		/** Check if the server is running */
 		virtual bool IsRunning() const = 0;
 
+		/**
+		 * Get where the server is in its lifecycle. Sending to and disconnecting its connections fail with
+		 * NotRunning unless it is Running.
+		 */
+		virtual EServerState GetState() const = 0;
+
 		/** Get the actual port the server is listening to */
 		virtual int32 GetPort() const = 0;
 
 		/** Get the number of active connections */
 		virtual int32 GetConnectionCount() const = 0;
 
+		/** Get the number of message events dropped over the event budget since the server was created */
+		virtual uint64 GetDroppedEventCount() const = 0;
+
+		/** Get information about a connection, such as whether it is encrypted */
+		virtual EResult GetConnectionInfo(uint64 ConnectionId, FConnectionInfo& OutInfo) const = 0;
+
 		/** Get server info string (address:port) */
 		virtual FString Info() const = 0;
 
-		/** Send binary data to a connection */
+		/** Get the bound address of each listener and the external addresses found through port mapping and STUN, as JSON */
+		virtual FString GetListenAddrs() const = 0;
+
+		/** Get the count, mean and percentiles of message sizes received and sent and of the time events wait to be polled, as JSON */
+		virtual FString GetHistograms() const = 0;
+
+		/** Get the server's threads, tasks and the bytes held in its queues and buffers */
+		virtual EResult GetResourceUsage(FResourceUsage& OutUsage) const = 0;
+
+		/** Send binary data to a connection (ConnectionClosed once it has closed, QueueFull past send_queue_limit) */
 		virtual EResult Send(uint64 ConnectionId, const TArray<uint8>& Data) = 0;
 
+		/** Send binary data to a connection, replacing the message sent under the same key if that is still queued */
+		virtual EResult SendWithKey(uint64 ConnectionId, const FString& Key, const TArray<uint8>& Data) = 0;
+
+		/** Send binary data to a connection, dropping it if it is still queued after TtlMs milliseconds */
+		virtual EResult SendWithTtl(uint64 ConnectionId, const TArray<uint8>& Data, uint32 TtlMs) = 0;
+
+		/** Why the latest failed send to a connection failed, empty if none */
+		virtual FString GetLastSendError(uint64 ConnectionId) const = 0;
+
+		/** Send an unreliable datagram to a WebTransport connection */
+		virtual EResult SendDatagram(uint64 ConnectionId, const TArray<uint8>& Data) = 0;
+
 		/** Send text data to a connection */
 		virtual EResult SendText(uint64 ConnectionId, const FString& Text) = 0;
 
+		/** Send binary data in a typed envelope encoded with the typed_codec setting (MessagePack or CBOR) */
+		virtual EResult SendTyped(uint64 ConnectionId, uint32 MessageType, const TArray<uint8>& Data) = 0;
+
+		/** Send binary data on a virtual channel configured in the channels setting (reliable or sequenced) */
+		virtual EResult SendChannel(uint64 ConnectionId, uint8 Channel, const TArray<uint8>& Data) = 0;
+
+		/** Get the statistics of a virtual channel of a connection */
+		virtual FChannelStats GetChannelStats(uint64 ConnectionId, uint8 Channel) const = 0;
+
+		/** Send the latest version of a keyed state, delta-compressed against the version the client last acknowledged (delta setting) */
+		virtual EResult SendState(uint64 ConnectionId, uint32 Key, const TArray<uint8>& State) = 0;
+
+		/** Send binary data numbered with the connection's next sequence, which the client acknowledges (receipts setting) */
+		virtual EResult SendSequenced(uint64 ConnectionId, const TArray<uint8>& Data, uint64& OutSequence) = 0;
+
+		/** Get the highest sequence of sequenced sends a connection acknowledged (0 if none) */
+		virtual uint64 GetAckedSequence(uint64 ConnectionId) const = 0;
+
+		/** Sign a connection's messages with an HMAC-SHA256 key and require its messages to be signed, dropping invalid ones with SignatureInvalid (empty key to stop) */
+		virtual EResult SetSigningKey(uint64 ConnectionId, const TArray<uint8>& Key) = 0;
+
 		/** Disconnect a connection */
 		virtual EResult Disconnect(uint64 ConnectionId) = 0;
 
+		/** Hand a live connection over to another running server, e.g. from the lobby to a match, without the client reconnecting. The connection keeps its ID; rooms, topics and sessions stay behind. */
+		virtual EResult MigrateConnection(uint64 ConnectionId, IServer& Target) = 0;
+
+		/** Stop reading a connection's messages, holding the client back with TCP flow control, e.g. while working through a backlog */
+		virtual EResult PauseRead(uint64 ConnectionId) = 0;
+
+		/** Resume reading a connection paused with PauseRead */
+		virtual EResult ResumeRead(uint64 ConnectionId) = 0;
+
+		/** Acknowledge a connection's ClientConnected event, delivering the messages held back under the hold_messages setting once per-player state is set up */
+		virtual EResult AcknowledgeConnection(uint64 ConnectionId) = 0;
+
+		/** Mark a connection authenticated, resuming delivery of its messages after the login messages under the authentication setting */
+		virtual EResult MarkAuthenticated(uint64 ConnectionId) = 0;
+
+		/** Rotate a connection's encryption keys with a fresh in-band key exchange, raising KeyRotated once done; InvalidParam if it isn't encrypted */
+		virtual EResult Rekey(uint64 ConnectionId) = 0;
+
+		/** Close every connection with a Close frame and end suspended sessions, e.g. at the end of a match */
+		virtual EResult KickAll(uint16 Code, const FString& Reason) = 0;
+
+		/** Close every connection from an IP address with a Close frame */
+		virtual EResult KickIp(const FString& Ip, uint16 Code, const FString& Reason) = 0;
+
+		/** Refuse connections from an IP address for DurationMs (0 until unbanned) and close those open with Reason */
+		virtual EResult BanIp(const FString& Ip, uint64 DurationMs, const FString& Reason) = 0;
+
+		/** Lift the ban of an IP address; InvalidParam if it isn't banned */
+		virtual EResult UnbanIp(const FString& Ip) = 0;
+
+		/** Close every member of a room with a Close frame */
+		virtual EResult KickRoom(const FString& Room, uint16 Code, const FString& Reason) = 0;
+
+		/** Add a connection to a room; with the history setting's replay_on_join, it is sent the room's history first */
+		virtual EResult JoinRoom(uint64 ConnectionId, const FString& Room) = 0;
+
+		/** Remove a connection from a room */
+		virtual EResult LeaveRoom(uint64 ConnectionId, const FString& Room) = 0;
+
+		/** Read a room's latest broadcasts, oldest first, back to back as u8 kind (0 binary, 1 text), u32 length and payload */
+		virtual EResult GetHistory(const FString& Room, int32 MaxMessages, TArray<uint8>& OutMessages) = 0;
+
+		/** Forget a room's history; InvalidParam if it has none */
+		virtual EResult ClearHistory(const FString& Room) = 0;
+
+		/** Send binary data to every member of a room (on every clustered instance) */
+		virtual EResult BroadcastToRoom(const FString& Room, const TArray<uint8>& Data) = 0;
+
+		/** Send text to every member of a room (on every clustered instance) */
+		virtual EResult BroadcastTextToRoom(const FString& Room, const FString& Text) = 0;
+
+		/** Tag a connection, e.g. with "team:red"; a lighter grouping than rooms, local to this instance */
+		virtual EResult SetTag(uint64 ConnectionId, const FString& Tag) = 0;
+
+		/** Remove a tag from a connection */
+		virtual EResult ClearTag(uint64 ConnectionId, const FString& Tag) = 0;
+
+		/** Get the tags of a connection as a JSON array */
+		virtual FString GetTags(uint64 ConnectionId) const = 0;
+
+		/** Send binary data to every connection with a tag */
+		virtual EResult BroadcastToTag(const FString& Tag, const TArray<uint8>& Data) = 0;
+
+		/** Send text to every connection with a tag */
+		virtual EResult BroadcastTextToTag(const FString& Tag, const FString& Text) = 0;
+
+		/** Close every connection with a tag with a Close frame */
+		virtual EResult KickTag(const FString& Tag, uint16 Code, const FString& Reason) = 0;
+
+		/** Get the statistics of the connections with a tag */
+		virtual FTagStats GetTagStats(const FString& Tag) const = 0;
+
+		/** Relay the messages of two connections to each other without raising events for them, until Unpipe or either ends */
+		virtual EResult Pipe(uint64 ConnectionA, uint64 ConnectionB) = 0;
+
+		/** End the pipe of a connection; both connections' messages raise events again */
+		virtual EResult Unpipe(uint64 ConnectionId) = 0;
+
+		/** Send a request and await the client's response; ResponseReceived or RequestTimedOut events carry OutRequestId */
+		virtual EResult Request(uint64 ConnectionId, const TArray<uint8>& Data, uint32 TimeoutMs, uint64& OutRequestId) = 0;
+
+		/** Subscribe a connection (or the host, with connection ID 0) to a topic pattern; `*` matches one segment, a trailing `>` the rest */
+		virtual EResult Subscribe(uint64 ConnectionId, const FString& Pattern) = 0;
+
+		/** Remove a topic subscription */
+		virtual EResult Unsubscribe(uint64 ConnectionId, const FString& Pattern) = 0;
+
+		/** Publish binary data to every matching subscriber (on every clustered instance) */
+		virtual EResult Publish(const FString& Topic, const TArray<uint8>& Data) = 0;
+
+		/** Publish text to every matching subscriber (on every clustered instance) */
+		virtual EResult PublishText(const FString& Topic, const FString& Text) = 0;
+
+		/** Publish to MQTT clients subscribed to Topic (QoS 0 or 1); retained messages are sent to later subscribers */
+		virtual EResult MqttPublish(const FString& Topic, const TArray<uint8>& Data, uint8 Qos = 0, bool bRetain = false) = 0;
+
+		/** Answer a JSON-RPC call (RpcCall event's RequestId) with a result given as JSON */
+		virtual EResult RpcReply(uint64 CallId, const FString& ResultJson) = 0;
+
+		/** Answer a JSON-RPC call with an error; DataJson is optional JSON (empty to omit) */
+		virtual EResult RpcError(uint64 CallId, int32 Code, const FString& Message, const FString& DataJson = FString()) = 0;
+
+		/** Emit a Socket.IO event; ArgsJson is a JSON array of arguments (empty for none) */
+		virtual EResult SocketIoEmit(uint64 ConnectionId, const FString& EventName, const FString& ArgsJson = FString()) = 0;
+
+		/** Emit a Socket.IO event and await its acknowledgement; ResponseReceived or RequestTimedOut events carry OutRequestId */
+		virtual EResult SocketIoEmitWithAck(uint64 ConnectionId, const FString& EventName, const FString& ArgsJson, uint32 TimeoutMs, uint64& OutRequestId) = 0;
+
+		/** Acknowledge a client's Socket.IO event (SocketIoEvent's RequestId); ArgsJson is a JSON array (empty for none) */
+		virtual EResult SocketIoAck(uint64 Ack, const FString& ArgsJson = FString()) = 0;
+
+		/** Identify a connection as a logical user for presence tracking (empty to clear) */
+		virtual EResult SetUserId(uint64 ConnectionId, const FString& UserId) = 0;
+
+		/** Get the user ID of a connection (empty if not identified) */
+		virtual FString GetUserId(uint64 ConnectionId) const = 0;
+
+		/** Check whether a user is online on this or any clustered instance */
+		virtual bool IsUserOnline(const FString& UserId) const = 0;
+
+		/** Get the online users as a JSON array (all instances, or this instance's members of a room) */
+		virtual FString GetOnlineUsers(const FString& Room = FString()) const = 0;
+
+		/** Get the session token of a connection (empty if sessions are disabled) */
+		virtual FString GetSessionToken(uint64 ConnectionId) const = 0;
+
+		/** Include or exclude a connection from the message journal (when not journaling all connections) */
+		virtual EResult JournalSelect(uint64 ConnectionId, bool bEnabled) = 0;
+
+		/** Include or exclude the members of a room from the message journal (when not journaling all connections) */
+		virtual EResult JournalSelectRoom(const FString& Room, bool bEnabled) = 0;
+
+		/** Read raw journal records starting at a sequence number */
+		virtual EResult JournalRead(uint64 FromSequence, int32 MaxRecords, TArray<uint8>& OutRecords) = 0;
+
+		/**
+		 * Query the SQLite message archive (archive feature and setting) by room, connection_id, from_ms/to_ms, after_id and limit.
+		 * Rows come back to back as in the archive module: id, timestamp_ms, connection_id, direction, kind, room and payload.
+		 */
+		virtual EResult ArchiveQuery(const FString& QueryJson, TArray<uint8>& OutRows) = 0;
+
+		/** Override the simulated network conditions of a connection (JSON object, empty to use the server-wide setting) */
+		virtual EResult SetNetworkSim(uint64 ConnectionId, const FString& SimJson) = 0;
+
+		/** Schedule a scripted chaos scenario (JSON object with timed disconnect/stall_writers/refuse_handshakes steps) */
+		virtual EResult ScheduleChaos(const FString& ScenarioJson) = 0;
+
+		/** Cancel scheduled chaos scenarios, resuming stalled writers and handshakes */
+		virtual EResult CancelChaos() = 0;
+
+		/**
+		 * Schedule a maintenance shutdown at a UTC time. WarningMessage is broadcast to every connection each of
+		 * WarningIntervals seconds before it, {seconds} replaced with the seconds left; at the time, connections
+		 * are closed and ShutdownDue is raised for the host to Stop the server.
+		 */
+		virtual EResult ScheduleShutdown(const FDateTime& At, const FString& WarningMessage, const TArray<uint32>& WarningIntervals) = 0;
+
+		/** Cancel a scheduled shutdown, taking new connections again if it already came due */
+		virtual EResult CancelShutdown() = 0;
+
+		/** Ask a STUN server (host[:port]) for the public IP:port, reported as a PublicEndpoint event */
+		virtual EResult DiscoverPublicEndpoint(const FString& StunServer) = 0;
+
+		/** Apply hot-changeable settings (JSON object) without dropping connections */
+		virtual EResult UpdateConfig(const FString& SettingsJson) = 0;
+
+		/** Set the dictionary of zstd compression negotiated from now on (empty for none); InvalidParam without the zstd setting */
+		virtual EResult SetZstdDictionary(const TArray<uint8>& Dictionary) = 0;
+
+		/** Get the dictionary of zstd compression negotiated from now on (empty for none); InvalidParam without the zstd setting */
+		virtual EResult GetZstdDictionary(TArray<uint8>& OutDictionary) const = 0;
+
+		/**
+		 * Move the listener to a new port and address, with a new TLS certificate (empty paths for no TLS), without
+		 * dropping connections. The old listener stays if the new one fails; later Starts use the new values.
+		 */
+		virtual EResult Rebind(int32 Port, const FString& BindAddress, const FString& TlsCertPath, const FString& TlsKeyPath) = 0;
+
+		/**
+		 * Register middleware run on every text and binary message of the running server. Inbound messages pass
+		 * through it in ascending priority order and outbound ones in descending order, so layers nest.
+		 */
+		virtual EResult AddMiddleware(int32 Priority, FMiddleware Middleware, uint64& OutMiddlewareId) = 0;
+
+		/** Unregister middleware; messages already passing through the chain may still reach it */
+		virtual EResult RemoveMiddleware(uint64 MiddlewareId) = 0;
+
+		/** Raise only the event types whose bit is set (1ull << EEventType value); others are dropped before queueing */
+		virtual EResult SetEventMask(uint64 Mask) = 0;
+
 		/** Poll for events (call from Tick) */
 		virtual bool PollEvent(FEvent& OutEvent) = 0;
 
+		/** Poll for up to MaxEvents events at once, e.g. all of a frame's, with less overhead per event than PollEvent. Returns how many were polled. */
+		virtual int32 PollEvents(TArray<FEvent>& OutEvents, int32 MaxEvents) = 0;
+
+		/** Connect an in-memory client that exercises the full event path without TCP or TLS (for tests) */
+		virtual TSharedPtr<IClient> ConnectLoopback() = 0;
+
+		/**
+		 * Dial another server as a peer: the outbound connection shares this server's connection IDs and events.
+		 * OutConnectionId is set at once; ClientConnected (RequestId 1) follows the handshake, or Error and ClientDisconnected.
+		 * SettingsJson takes the headers, cookies, query, subprotocols, handshake timeout and TLS settings of IClient::Connect.
+		 */
+		virtual EResult Dial(const FString& Url, const FString& SettingsJson, uint64& OutConnectionId) = 0;
+
+		/** Open a queue taking the events of a room's members (up to their ClientDisconnected) away from PollEvent, e.g. one per match thread */
+		virtual TSharedPtr<IEventQueue> OpenEventQueue(const FString& Room) = 0;
+
 		// Event delegates
 		FOnClientConnected OnClientConnected;
 		FOnClientDisconnected OnClientDisconnected;
@@ -5,7 +5,8 @@
 //! Build script for dwebble-rws
 //!
 //! Generates a C++ header using cbindgen.
-//! Cargo-make handles DLL copying (see Makefile.toml).
+//! Cargo-make stages the built library in the plugin Binaries for each target
+//! platform (see Makefile.toml); the library does not exist yet when this runs.
 
 use std::env;
 use std::fs;
@@ -13,6 +14,7 @@ use std::path::Path;
 
 fn main() {
     println!("cargo:rerun-if-changed=src/");
+    println!("cargo:rerun-if-changed=core/src/types.rs");
     println!("cargo:rerun-if-changed=cbindgen.toml");
 
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
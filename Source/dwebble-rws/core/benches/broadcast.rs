@@ -0,0 +1,107 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Room broadcasts to 5000 loopback connections, by shard count of the connection map
+//!
+//! `cargo bench -p dwebble-rws-core --bench broadcast`
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dwebble_rws_core::server::{Server, ServerConfig};
+use dwebble_rws_core::settings::Settings;
+use dwebble_rws_core::types::{DwebbleWSEventType, DwebbleWSResult};
+use futures_util::StreamExt;
+use tokio::runtime::Runtime;
+use tokio_tungstenite::tungstenite::Message;
+
+const CONNECTIONS: usize = 5_000;
+const ROOM: &str = "everyone";
+/// Threads sending to single connections while the broadcast runs
+const SENDERS: usize = 3;
+
+/// A server with `shards` connection shards, `CONNECTIONS` clients on `runtime` that
+/// read and drop what they receive, and the server's IDs of their connections
+fn serve(runtime: &Runtime, shards: usize) -> (Server, Vec<u64>) {
+    let settings = Settings {
+        connection_shards: shards,
+        ..Default::default()
+    };
+    let server = Server::new(ServerConfig {
+        settings,
+        ..Default::default()
+    });
+    assert_eq!(server.start(), DwebbleWSResult::Ok);
+
+    let url = format!("ws://127.0.0.1:{}", server.get_actual_port());
+    for _ in 0..CONNECTIONS {
+        let (ws_stream, _) = runtime
+            .block_on(tokio_tungstenite::connect_async(&url))
+            .unwrap();
+        runtime.spawn(ws_stream.for_each(|_| async {}));
+    }
+
+    let mut ids = Vec::with_capacity(CONNECTIONS);
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while ids.len() < CONNECTIONS && Instant::now() < deadline {
+        match server.poll_event() {
+            Some(event) if event.event_type == DwebbleWSEventType::ClientConnected => {
+                assert_eq!(server.join_room(event.connection_id, ROOM), DwebbleWSResult::Ok);
+                ids.push(event.connection_id);
+            }
+            Some(_) => {}
+            None => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    assert_eq!(ids.len(), CONNECTIONS);
+    (server, ids)
+}
+
+fn broadcast(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let payload = vec![0x2a; 64];
+    let mut group = c.benchmark_group("broadcast");
+    group.throughput(Throughput::Elements(CONNECTIONS as u64));
+    group.sample_size(20);
+
+    // One shard, four per core (the default) and many
+    for shards in [1, 0, 256] {
+        let (server, ids) = serve(&runtime, shards);
+        let name = match shards {
+            0 => "default shards".to_string(),
+            shards => format!("{shards} shards"),
+        };
+
+        group.bench_function(format!("{name}, alone"), |b| {
+            b.iter(|| server.broadcast_room(ROOM, Message::binary(payload.clone())))
+        });
+
+        let stop = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for sender in 0..SENDERS {
+                let (server, ids, stop, payload) = (&server, &ids, &stop, &payload);
+                scope.spawn(move || {
+                    for id in ids.iter().skip(sender).step_by(SENDERS).cycle() {
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        server.send(*id, payload);
+                    }
+                });
+            }
+            group.bench_function(format!("{name}, {SENDERS} senders"), |b| {
+                b.iter(|| server.broadcast_room(ROOM, Message::binary(payload.clone())))
+            });
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        server.stop();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcast);
+criterion_main!(benches);
@@ -0,0 +1,195 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Receive path: unmasking and UTF-8 checks of client frames
+//!
+//! `cargo bench -p dwebble-rws-core --bench receive`
+
+use std::hint::black_box;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use dwebble_rws_core::frames::{self, ClientFrames};
+use futures_util::StreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::WebSocketStream;
+
+const MASK: [u8; 4] = [0x6d, 0xb6, 0xb2, 0x80];
+const HEAD: &[u8] = b"GET / HTTP/1.1\r\nHost: bench\r\n\r\n";
+const FRAMES: usize = 1_000;
+
+/// Game-like JSON of about `len` bytes, with some non-ASCII names
+fn payload(len: usize) -> String {
+    let mut json = String::from("[");
+    let mut i = 0;
+    while json.len() < len {
+        let name = if i % 8 == 0 { "Jörmungandr" } else { "player" };
+        json.push_str(&format!(
+            r#"{{"id":{i},"name":"{name}","x":{}.5,"y":-{}.25}},"#,
+            i * 7,
+            i * 3
+        ));
+        i += 1;
+    }
+    json.push(']');
+    json
+}
+
+/// Unmasking as tungstenite does it, four bytes a step
+fn unmask_words32(buf: &mut [u8], mask: [u8; 4]) {
+    let word = u32::from_ne_bytes(mask);
+    let mut words = buf.chunks_exact_mut(4);
+    for chunk in &mut words {
+        let value = u32::from_ne_bytes(chunk.try_into().unwrap()) ^ word;
+        chunk.copy_from_slice(&value.to_ne_bytes());
+    }
+    for (i, byte) in words.into_remainder().iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// A client's masked text frames, as read off the socket
+fn masked_frames(text: &str, count: usize) -> Vec<u8> {
+    let mut payload = text.as_bytes().to_vec();
+    unmask_words32(&mut payload, MASK);
+    let mut frame = vec![0x81, 0x80 | 126];
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&MASK);
+    frame.extend_from_slice(&payload);
+    frame.repeat(count)
+}
+
+/// Bytes read off a socket, with writes discarded
+struct Recorded {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl AsyncRead for Recorded {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let len = (this.data.len() - this.position).min(buf.remaining());
+        buf.put_slice(&this.data[this.position..this.position + len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Recorded {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Read every message off a stream of frames
+async fn read_all<S: AsyncRead + AsyncWrite + Unpin>(stream: S, config: WebSocketConfig) -> usize {
+    let mut ws_stream = WebSocketStream::from_raw_socket(stream, Role::Server, Some(config)).await;
+    let mut len = 0;
+    while let Some(Ok(msg)) = ws_stream.next().await {
+        len += msg.len();
+    }
+    len
+}
+
+fn unmask(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unmask");
+    for len in [1_024, 64 * 1_024] {
+        let mut data = payload(len).into_bytes();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(format!("tungstenite/{len}"), |b| {
+            b.iter(|| unmask_words32(black_box(&mut data), MASK))
+        });
+        group.bench_function(format!("frames/{len}"), |b| {
+            b.iter(|| {
+                let len = data.len();
+                frames::unmask(black_box(&mut data), 0, 0, len, MASK)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn utf8(c: &mut Criterion) {
+    let mut group = c.benchmark_group("utf8");
+    for len in [1_024, 64 * 1_024] {
+        let text = payload(len).into_bytes();
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        group.bench_function(format!("std/{len}"), |b| {
+            b.iter(|| std::str::from_utf8(black_box(&text)).is_ok())
+        });
+        group.bench_function(format!("simdutf8/{len}"), |b| {
+            b.iter(|| simdutf8::basic::from_utf8(black_box(&text)).is_ok())
+        });
+    }
+    group.finish();
+}
+
+fn receive(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let text = payload(4 * 1_024);
+    let frames = masked_frames(&text, FRAMES);
+    let mut group = c.benchmark_group("receive");
+    group.throughput(Throughput::Bytes(frames.len() as u64));
+
+    group.bench_function("tungstenite", |b| {
+        b.iter_batched(
+            || Recorded {
+                data: frames.clone(),
+                position: 0,
+            },
+            |stream| runtime.block_on(read_all(stream, WebSocketConfig::default())),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("frames", |b| {
+        b.iter_batched(
+            || {
+                let mut data = HEAD.to_vec();
+                data.extend_from_slice(&frames);
+                ClientFrames::new(Recorded { data, position: 0 }, usize::MAX)
+            },
+            |mut stream| {
+                runtime.block_on(async {
+                    let mut head = [0; HEAD.len()];
+                    stream.read_exact(&mut head).await.unwrap();
+                    let text_frames = stream.text_frames();
+                    let config = WebSocketConfig::default().accept_unmasked_frames(true);
+                    let mut ws_stream =
+                        WebSocketStream::from_raw_socket(stream, Role::Server, Some(config)).await;
+                    let mut len = 0;
+                    while let Some(Ok(msg)) = ws_stream.next().await {
+                        len += text_frames.restore(msg).len();
+                    }
+                    len
+                })
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, unmask, utf8, receive);
+criterion_main!(benches);
@@ -0,0 +1,347 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Admin endpoint for operators of headless servers
+//!
+//! With the `admin` setting, the server listens on a loopback port for HTTP
+//! `POST` requests carrying one JSON command each, so a dedicated server can be
+//! managed with `curl` without going through the host:
+//!
+//! ```text
+//! curl -H 'Content-Type: application/json' -H 'Authorization: Bearer TOKEN' \
+//!      -d '{"command":"kick","connection_id":3}' http://127.0.0.1:PORT/
+//! ```
+//!
+//! | command            | fields                                        |
+//! |--------------------|-----------------------------------------------|
+//! | `list_connections` |                                               |
+//! | `kick`             | `connection_id`, `code`?, `reason`?           |
+//! | `ban`              | `ip`, `duration_ms`? (0 for ever), `reason`?  |
+//! | `unban`            | `ip`                                          |
+//! | `list_bans`        |                                               |
+//! | `stats`            |                                               |
+//! | `set_log_level`    | `level`, a tracing filter directive           |
+//! | `list_rooms`       |                                               |
+//!
+//! Replies are JSON objects with `ok` true and the command's result, or `ok`
+//! false and an `error`. Requests must have a JSON content type, which browsers
+//! can't send across origins without a preflight the endpoint never answers.
+//!
+//! With `dashboard` on, a `GET` of `/` serves a status page bundled into the
+//! library. It polls the commands above to show live connections, throughput and
+//! room membership, asking for the token if one is set.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::logging;
+use crate::rewind;
+use crate::server::Shared;
+use crate::settings::AdminSettings;
+use crate::types::DwebbleWSResult;
+
+/// Time allowed to send a whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request body accepted
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Most headers parsed from a request head
+const MAX_HEADERS: usize = 64;
+
+/// Status page served with the `dashboard` setting
+const DASHBOARD: &str = include_str!("admin/dashboard.html");
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    ListConnections,
+    Kick {
+        connection_id: u64,
+        /// Close code, or a plain close without one
+        code: Option<u16>,
+        #[serde(default)]
+        reason: String,
+    },
+    Ban {
+        ip: IpAddr,
+        #[serde(default)]
+        duration_ms: u64,
+        #[serde(default)]
+        reason: String,
+    },
+    Unban {
+        ip: IpAddr,
+    },
+    ListBans,
+    Stats,
+    SetLogLevel {
+        level: String,
+    },
+    ListRooms,
+}
+
+/// Answer admin requests on `listener` until the runtime shuts down
+pub async fn run(shared: Arc<Shared>, listener: TcpListener, settings: AdminSettings) {
+    let settings = Arc::new(settings);
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::error!("Admin accept error: {}", e);
+                continue;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        let settings = Arc::clone(&settings);
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, addr, &shared, &settings).await {
+                tracing::debug!("Admin request from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Answer one request, then close the connection
+async fn serve(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    shared: &Shared,
+    settings: &AdminSettings,
+) -> std::io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream, settings))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    let (status, reply) = match request {
+        Err((status, error)) => (status, json!({ "ok": false, "error": error })),
+        Ok(AdminRequest::Dashboard) => {
+            return respond(&mut stream, "200 OK", "text/html; charset=utf-8", DASHBOARD).await;
+        }
+        Ok(AdminRequest::Command { authorization, .. })
+            if !authorized(authorization.as_deref(), &settings.token) =>
+        {
+            (
+                "401 Unauthorized",
+                json!({ "ok": false, "error": "Missing or wrong bearer token" }),
+            )
+        }
+        Ok(AdminRequest::Command { body, .. }) => match serde_json::from_slice::<Command>(&body) {
+            Ok(command) => match execute(shared, command) {
+                Ok(Value::Object(mut result)) => {
+                    result.insert("ok".to_string(), Value::Bool(true));
+                    ("200 OK", Value::Object(result))
+                }
+                Ok(_) => ("200 OK", json!({ "ok": true })),
+                Err(error) => ("400 Bad Request", json!({ "ok": false, "error": error })),
+            },
+            Err(e) => (
+                "400 Bad Request",
+                json!({ "ok": false, "error": e.to_string() }),
+            ),
+        },
+    };
+    tracing::debug!("Admin request from {}: {}", addr, status);
+    respond(&mut stream, status, "application/json", &reply.to_string()).await
+}
+
+/// Write a response with `body`, then close the connection
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// A request to the admin endpoint
+enum AdminRequest {
+    /// A `GET` of the dashboard page
+    Dashboard,
+    Command {
+        authorization: Option<String>,
+        body: Vec<u8>,
+    },
+}
+
+/// Read a request off `stream`, or the status and error to refuse it with
+async fn read_request(
+    stream: &mut TcpStream,
+    settings: &AdminSettings,
+) -> std::io::Result<Result<AdminRequest, (&'static str, &'static str)>> {
+    let mut buffer = rewind::read_head(stream).await?;
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    let head_len = match request.parse(&buffer) {
+        Ok(httparse::Status::Complete(len)) => len,
+        _ => return Ok(Err(("400 Bad Request", "Malformed request"))),
+    };
+    if settings.dashboard && request.method == Some("GET") && request.path == Some("/") {
+        return Ok(Ok(AdminRequest::Dashboard));
+    }
+    if request.method != Some("POST") {
+        return Ok(Err(("405 Method Not Allowed", "Commands must be POSTed")));
+    }
+
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .and_then(|h| std::str::from_utf8(h.value).ok())
+    };
+    let json = header("Content-Type").is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !json {
+        return Ok(Err((
+            "415 Unsupported Media Type",
+            "Content-Type must be application/json",
+        )));
+    }
+    let Some(content_length) = header("Content-Length").and_then(|len| len.parse().ok()) else {
+        return Ok(Err(("411 Length Required", "Missing Content-Length")));
+    };
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Err(("413 Payload Too Large", "Command too large")));
+    }
+    let authorization = header("Authorization").map(str::to_string);
+
+    let mut body = buffer.split_off(head_len);
+    if body.len() < content_length {
+        let read = body.len();
+        body.resize(content_length, 0);
+        stream.read_exact(&mut body[read..]).await?;
+    }
+    body.truncate(content_length);
+    Ok(Ok(AdminRequest::Command {
+        authorization,
+        body,
+    }))
+}
+
+/// Whether an `Authorization` header presents the bearer `token`. Digests are
+/// compared rather than the tokens, so the time taken reveals nothing of the token.
+fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    if token.is_empty() {
+        return true;
+    }
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| {
+            digest(&SHA256, presented.trim().as_bytes()).as_ref()
+                == digest(&SHA256, token.as_bytes()).as_ref()
+        })
+}
+
+/// Run a command, returning its result
+fn execute(shared: &Shared, command: Command) -> Result<Value, String> {
+    match command {
+        Command::ListConnections => {
+            let presence = shared.presence.lock();
+            let connections: Vec<Value> = shared
+                .connections
+                .values()
+                .iter()
+                .map(|conn| {
+                    let (raw_sent, _) = conn.compression.sent_bytes();
+                    let (_, raw_received) = conn.compression.received_bytes();
+                    json!({
+                        "id": conn.id,
+                        "remote_addr": conn.remote_addr,
+                        "subprotocol": conn.subprotocol,
+                        "user_id": presence.user_id(conn.id),
+                        "encrypted": conn.encrypted(),
+                        "queued_bytes": conn.pending_bytes(),
+                        "bytes_sent": raw_sent,
+                        "bytes_received": raw_received,
+                    })
+                })
+                .collect();
+            Ok(json!({ "connections": connections }))
+        }
+        Command::Kick {
+            connection_id,
+            code,
+            reason,
+        } => {
+            let close = code.map(|code| (code, reason.as_str()));
+            match shared.close_connection(connection_id, close) {
+                DwebbleWSResult::Ok => Ok(Value::Null),
+                _ => Err(format!("No connection {}", connection_id)),
+            }
+        }
+        Command::Ban {
+            ip,
+            duration_ms,
+            reason,
+        } => {
+            let duration = (duration_ms > 0).then(|| Duration::from_millis(duration_ms));
+            shared.ban_ip(ip, duration, &reason);
+            Ok(Value::Null)
+        }
+        Command::Unban { ip } => {
+            if !shared.bans.lock().unban(ip) {
+                return Err(format!("{} is not banned", ip));
+            }
+            Ok(Value::Null)
+        }
+        Command::ListBans => {
+            let now = Instant::now();
+            let bans: Vec<Value> = shared
+                .bans
+                .lock()
+                .list()
+                .map(|(ip, ban)| {
+                    let remaining_ms = ban
+                        .until
+                        .map(|until| until.saturating_duration_since(now).as_millis() as u64);
+                    json!({ "ip": ip, "remaining_ms": remaining_ms, "reason": ban.reason })
+                })
+                .collect();
+            Ok(json!({ "bans": bans }))
+        }
+        Command::Stats => Ok(json!({
+            "connections": shared.connections.len(),
+            "suspended_sessions": shared.sessions.lock().suspended().len(),
+            "dropped_events": shared.event_budget.lock().dropped(),
+            "bans": shared.bans.lock().count(),
+            "histograms": shared.histograms.summary(),
+        })),
+        Command::SetLogLevel { level } => {
+            logging::set_level(&level)?;
+            tracing::info!("Log level set to '{}' over the admin endpoint", level);
+            shared.settings.write().log_level = Some(level);
+            Ok(Value::Null)
+        }
+        Command::ListRooms => {
+            let rooms: Vec<Value> = shared
+                .rooms
+                .lock()
+                .all()
+                .map(|(room, members)| json!({ "room": room, "members": members }))
+                .collect();
+            Ok(json!({ "rooms": rooms }))
+        }
+    }
+}
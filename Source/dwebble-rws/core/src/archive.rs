@@ -0,0 +1,343 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! SQLite message archive for post-match review
+//!
+//! Messages are inserted into the `messages` table of the configured database:
+//! those received from connections, each room broadcast once (connection ID 0)
+//! and, with `include_outbound`, each message sent to a connection. Binary
+//! payloads are stored as the host sent or received them, without control-frame
+//! escaping. Rows past the retention limits are deleted every
+//! `prune_interval_ms`, as messages are archived.
+//!
+//! The database is written by a thread of the archive's own, so archiving a message
+//! costs the game thread and the runtime's workers a copy of its payload. The thread
+//! inserts the messages queued meanwhile in one transaction, and runs queries in turn
+//! with them, so a query sees every message archived before it. Messages that find
+//! its queue full are dropped and counted in a warning.
+//!
+//! Queries return their rows back to back, oldest first, each laid out as
+//! (little-endian):
+//!
+//! | field         | type |
+//! |---------------|------|
+//! | id            | u64  |
+//! | timestamp_ms  | u64  |
+//! | connection_id | u64  |
+//! | direction     | u8 (0 = inbound, 1 = outbound) |
+//! | kind          | u8 (0 = binary, 1 = text) |
+//! | room_len      | u16 (0 for a connection's message) |
+//! | room          | `room_len` bytes of UTF-8 |
+//! | payload_len   | u32  |
+//! | payload       | `payload_len` bytes |
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use serde::Deserialize;
+
+use crate::journal::{Direction, PayloadKind};
+use crate::settings::ArchiveSettings;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        timestamp_ms INTEGER NOT NULL,
+        connection_id INTEGER NOT NULL,
+        room TEXT,
+        direction INTEGER NOT NULL,
+        kind INTEGER NOT NULL,
+        payload BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_timestamp ON messages (timestamp_ms);
+    CREATE INDEX IF NOT EXISTS messages_room ON messages (room, timestamp_ms);
+    CREATE INDEX IF NOT EXISTS messages_connection ON messages (connection_id, timestamp_ms);
+";
+
+/// Commands queued for the writer thread at most
+const QUEUE_LEN: usize = 16 * 1024;
+/// Messages inserted in one transaction at most
+const BATCH_LEN: usize = 1024;
+
+/// Filters of an archive query; unset fields match every message
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArchiveQuery {
+    /// Only broadcasts to this room
+    pub room: Option<String>,
+    /// Only messages of this connection
+    pub connection_id: Option<u64>,
+    /// Only messages archived at or after this time, in milliseconds since the Unix epoch
+    pub from_ms: Option<u64>,
+    /// Only messages archived before this time, in milliseconds since the Unix epoch
+    pub to_ms: Option<u64>,
+    /// Only messages after this row ID, to page through larger results
+    pub after_id: u64,
+    /// Rows returned at most
+    pub limit: usize,
+}
+
+impl Default for ArchiveQuery {
+    fn default() -> Self {
+        Self {
+            room: None,
+            connection_id: None,
+            from_ms: None,
+            to_ms: None,
+            after_id: 0,
+            limit: 1_000,
+        }
+    }
+}
+
+impl ArchiveQuery {
+    /// Parse a query from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+struct Row {
+    timestamp_ms: u64,
+    connection_id: u64,
+    room: Option<String>,
+    direction: Direction,
+    kind: PayloadKind,
+    payload: Vec<u8>,
+}
+
+enum Command {
+    Insert(Row),
+    Query(ArchiveQuery, mpsc::Sender<rusqlite::Result<Vec<u8>>>),
+}
+
+struct Writer {
+    settings: ArchiveSettings,
+    connection: Connection,
+    last_prune: Instant,
+}
+
+/// Message archive in an SQLite database
+pub struct Archive {
+    settings: ArchiveSettings,
+    tx: Option<SyncSender<Command>>,
+    writer: Option<JoinHandle<()>>,
+    /// Messages dropped as the writer's queue was full, since the last warning
+    dropped: Arc<AtomicU64>,
+}
+
+impl Archive {
+    /// Open or create the database, applying the retention limits at once
+    pub fn open(settings: ArchiveSettings) -> rusqlite::Result<Self> {
+        let connection = Connection::open(&settings.path)?;
+        connection.execute_batch(SCHEMA)?;
+        let writer = Writer {
+            settings: settings.clone(),
+            connection,
+            last_prune: Instant::now(),
+        };
+        writer.prune()?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let dropped = Arc::clone(&dropped);
+            thread::Builder::new()
+                .name("dwebble-archive".to_string())
+                .spawn(move || writer.run(rx, &dropped))
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?
+        };
+        Ok(Self {
+            settings,
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped,
+        })
+    }
+
+    /// Insert a message, as the host sent or received it, if its direction is archived.
+    /// `room` is set for room broadcasts.
+    pub fn record(
+        &self,
+        connection_id: u64,
+        room: Option<&str>,
+        direction: Direction,
+        kind: PayloadKind,
+        payload: &[u8],
+    ) {
+        if direction == Direction::Outbound && room.is_none() && !self.settings.include_outbound {
+            return;
+        }
+        let Some(tx) = &self.tx else { return };
+        let row = Row {
+            timestamp_ms: now_ms(),
+            connection_id,
+            room: room.map(str::to_string),
+            direction,
+            kind,
+            payload: payload.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(Command::Insert(row)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Read the rows matching `query`, encoded as laid out in the module docs
+    pub fn query(&self, query: &ArchiveQuery) -> rusqlite::Result<Vec<u8>> {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let tx = self.tx.as_ref().ok_or(rusqlite::Error::InvalidQuery)?;
+        tx.send(Command::Query(query.clone(), reply_tx))
+            .map_err(|_| rusqlite::Error::InvalidQuery)?;
+        reply_rx.recv().map_err(|_| rusqlite::Error::InvalidQuery)?
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        // The writer inserts what is queued and stops once the queue is closed
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Writer {
+    /// Run queued commands until the archive is dropped, a batch at a time
+    fn run(mut self, rx: Receiver<Command>, dropped: &AtomicU64) {
+        while let Ok(command) = rx.recv() {
+            if let Err(e) = self.batch(command, &rx) {
+                tracing::error!("Archive write failed: {}", e);
+            }
+            if self.last_prune.elapsed().as_millis() as u64 >= self.settings.prune_interval_ms {
+                self.last_prune = Instant::now();
+                if let Err(e) = self.prune() {
+                    tracing::error!("Archive prune failed: {}", e);
+                }
+            }
+            let dropped = dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                tracing::warn!("Dropped {} archive messages over a full write queue", dropped);
+            }
+        }
+    }
+
+    /// Run `first` and the commands queued after it in one transaction
+    fn batch(&mut self, first: Command, rx: &Receiver<Command>) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        let mut next = Some(first);
+        let mut inserted = 0;
+        while let Some(command) = next {
+            match command {
+                Command::Insert(row) => {
+                    if let Err(e) = insert(&transaction, &row) {
+                        tracing::error!("Archive write failed: {}", e);
+                    }
+                    inserted += 1;
+                }
+                Command::Query(query, reply) => {
+                    let _ = reply.send(select(&transaction, &query));
+                }
+            }
+            next = match inserted < BATCH_LEN {
+                true => rx.try_recv().ok(),
+                false => None,
+            };
+        }
+        transaction.commit()
+    }
+
+    /// Delete the rows past `max_age_ms` and `max_messages`
+    fn prune(&self) -> rusqlite::Result<()> {
+        if self.settings.max_age_ms > 0 {
+            let cutoff = now_ms().saturating_sub(self.settings.max_age_ms);
+            self.connection.execute(
+                "DELETE FROM messages WHERE timestamp_ms < ?1",
+                params![cutoff as i64],
+            )?;
+        }
+        if self.settings.max_messages > 0 {
+            self.connection.execute(
+                "DELETE FROM messages WHERE id <= (SELECT MAX(id) FROM messages) - ?1",
+                params![self.settings.max_messages.min(i64::MAX as u64) as i64],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn insert(connection: &Connection, row: &Row) -> rusqlite::Result<()> {
+    connection
+        .prepare_cached(
+            "INSERT INTO messages (timestamp_ms, connection_id, room, direction, kind, payload)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(params![
+            row.timestamp_ms as i64,
+            row.connection_id as i64,
+            row.room,
+            row.direction as u8,
+            row.kind as u8,
+            row.payload
+        ])?;
+    Ok(())
+}
+
+/// The rows matching `query`, encoded as laid out in the module docs
+fn select(connection: &Connection, query: &ArchiveQuery) -> rusqlite::Result<Vec<u8>> {
+    let mut sql = "SELECT id, timestamp_ms, connection_id, room, direction, kind, payload
+                   FROM messages WHERE id > ?"
+        .to_string();
+    let mut values = vec![Value::Integer(query.after_id as i64)];
+    if let Some(room) = &query.room {
+        sql.push_str(" AND room = ?");
+        values.push(Value::Text(room.clone()));
+    }
+    if let Some(connection_id) = query.connection_id {
+        sql.push_str(" AND connection_id = ?");
+        values.push(Value::Integer(connection_id as i64));
+    }
+    if let Some(from_ms) = query.from_ms {
+        sql.push_str(" AND timestamp_ms >= ?");
+        values.push(Value::Integer(from_ms as i64));
+    }
+    if let Some(to_ms) = query.to_ms {
+        sql.push_str(" AND timestamp_ms < ?");
+        values.push(Value::Integer(to_ms as i64));
+    }
+    sql.push_str(" ORDER BY id LIMIT ?");
+    values.push(Value::Integer(query.limit.min(i64::MAX as usize) as i64));
+
+    let mut statement = connection.prepare(&sql)?;
+    let mut rows = statement.query(params_from_iter(values))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let room: Option<String> = row.get(3)?;
+        let room = room.unwrap_or_default();
+        let payload: Vec<u8> = row.get(6)?;
+        out.extend_from_slice(&row.get::<_, i64>(0)?.to_le_bytes());
+        out.extend_from_slice(&row.get::<_, i64>(1)?.to_le_bytes());
+        out.extend_from_slice(&row.get::<_, i64>(2)?.to_le_bytes());
+        out.push(row.get(4)?);
+        out.push(row.get(5)?);
+        out.extend_from_slice(&(room.len() as u16).to_le_bytes());
+        out.extend_from_slice(room.as_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&payload);
+    }
+    Ok(out)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
@@ -0,0 +1,67 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! IP address bans
+//!
+//! Connections to the WebSocket listener from a banned address are dropped as
+//! soon as they are accepted, before any bytes are read. Bans last until they
+//! expire or are lifted, and are kept across restarts of the server.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Close code for connections closed by a ban (policy violation)
+pub const CLOSE_CODE: u16 = 1008;
+
+/// A banned address
+pub struct Ban {
+    /// When the ban expires, or never
+    pub until: Option<Instant>,
+    pub reason: String,
+}
+
+/// Banned addresses, IPv4-mapped IPv6 addresses as IPv4
+#[derive(Default)]
+pub struct Bans {
+    bans: HashMap<IpAddr, Ban>,
+}
+
+impl Bans {
+    /// Ban `ip` for `duration`, or until lifted, replacing an earlier ban
+    pub fn ban(&mut self, ip: IpAddr, duration: Option<Duration>, reason: &str) {
+        let ban = Ban {
+            until: duration.map(|d| Instant::now() + d),
+            reason: reason.to_string(),
+        };
+        self.bans.insert(ip.to_canonical(), ban);
+    }
+
+    /// Lift the ban of `ip`. Returns false if it wasn't banned.
+    pub fn unban(&mut self, ip: IpAddr) -> bool {
+        self.bans.remove(&ip.to_canonical()).is_some()
+    }
+
+    pub fn is_banned(&mut self, ip: IpAddr) -> bool {
+        self.expire();
+        self.bans.contains_key(&ip.to_canonical())
+    }
+
+    /// Addresses banned now, with their bans
+    pub fn list(&mut self) -> impl Iterator<Item = (&IpAddr, &Ban)> {
+        self.expire();
+        self.bans.iter()
+    }
+
+    pub fn count(&mut self) -> usize {
+        self.expire();
+        self.bans.len()
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.bans
+            .retain(|_, ban| ban.until.is_none_or(|until| until > now));
+    }
+}
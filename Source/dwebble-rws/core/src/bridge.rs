@@ -0,0 +1,359 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Bridge mode: relay accepted connections to upstream WebSocket servers
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::connection::Connection;
+use crate::server::{ServerEvent, Shared};
+use crate::settings::{BalanceStrategy, BridgeSettings};
+use crate::types::DwebbleWSEventType;
+
+/// Close code sent to clients when the upstream cannot be reached or fails
+const UPSTREAM_FAILURE_CODE: u16 = 1011;
+
+/// Handshake headers generated per hop, never copied to the upstream request
+const HOP_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "content-length",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+    "sec-websocket-accept",
+    "sec-websocket-protocol",
+];
+
+/// An upstream server and its load
+struct Target {
+    url: String,
+    healthy: AtomicBool,
+    /// Bridged connections currently relayed to this upstream
+    active: AtomicUsize,
+}
+
+/// Chooses an upstream for each bridged connection
+pub struct Balancer {
+    targets: Vec<Target>,
+    strategy: BalanceStrategy,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(bridge: &BridgeSettings) -> Self {
+        Self {
+            targets: bridge
+                .upstream_urls()
+                .into_iter()
+                .map(|url| Target {
+                    url: url.to_string(),
+                    healthy: AtomicBool::new(true),
+                    active: AtomicUsize::new(0),
+                })
+                .collect(),
+            strategy: bridge.balance,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pick an upstream, preferring healthy ones. Connections with a sticky key
+    /// always map to the same upstream while the set of healthy upstreams is unchanged.
+    fn pick(&self, sticky_key: Option<&str>) -> usize {
+        let mut candidates: Vec<usize> = (0..self.targets.len())
+            .filter(|&i| self.targets[i].healthy.load(Ordering::Relaxed))
+            .collect();
+        if candidates.is_empty() {
+            // Nothing known to be healthy; let the connection attempt decide
+            candidates = (0..self.targets.len()).collect();
+        }
+
+        if let Some(key) = sticky_key {
+            // Rendezvous hashing: highest score for the key wins
+            return candidates
+                .into_iter()
+                .max_by_key(|&i| {
+                    let mut hasher = DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    self.targets[i].url.hash(&mut hasher);
+                    hasher.finish()
+                })
+                .unwrap();
+        }
+
+        match self.strategy {
+            BalanceStrategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            BalanceStrategy::LeastConnections => candidates
+                .into_iter()
+                .min_by_key(|&i| self.targets[i].active.load(Ordering::Relaxed))
+                .unwrap(),
+        }
+    }
+
+    fn set_healthy(&self, index: usize, healthy: bool) {
+        let target = &self.targets[index];
+        if target.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                tracing::info!("Upstream {} is healthy", target.url);
+            } else {
+                tracing::warn!("Upstream {} is unhealthy", target.url);
+            }
+        }
+    }
+}
+
+/// Counts a bridged connection against its upstream for its lifetime
+struct ActiveGuard {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        if let Some(balancer) = &self.shared.balancer {
+            balancer.targets[self.index]
+                .active
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Client-side half of a bridged connection
+pub struct Upstream {
+    tx: mpsc::UnboundedSender<Message>,
+    _task: JoinHandle<()>,
+}
+
+impl Upstream {
+    /// Connect `conn` to an upstream in the background.
+    ///
+    /// `headers` are the client's handshake headers, forwarded when the bridge
+    /// settings allow it. `session_token` is used as the sticky key when configured.
+    pub fn spawn(
+        shared: Arc<Shared>,
+        conn: Arc<Connection>,
+        bridge: &BridgeSettings,
+        headers: Option<HeaderMap>,
+        session_token: Option<&str>,
+        addr: SocketAddr,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let sticky_key = match &bridge.sticky_header {
+            Some(name) => headers
+                .as_ref()
+                .and_then(|h| h.get(name.as_str()))
+                .and_then(|v| v.to_str().ok()),
+            None if bridge.sticky_session => session_token,
+            None => None,
+        };
+        let balancer = shared
+            .balancer
+            .as_ref()
+            .expect("bridge mode without a balancer");
+        let index = balancer.pick(sticky_key);
+        balancer.targets[index]
+            .active
+            .fetch_add(1, Ordering::Relaxed);
+
+        let request = upstream_request(
+            &balancer.targets[index].url,
+            bridge.forward_headers,
+            headers,
+            addr,
+            conn.subprotocol.as_deref(),
+        );
+        let guard = ActiveGuard {
+            shared: Arc::clone(&shared),
+            index,
+        };
+        let task = tokio::spawn(run(shared, conn, request, rx, guard));
+        Self { tx, _task: task }
+    }
+
+    /// Relay a message from the client to the upstream
+    pub fn forward(&self, msg: Message) {
+        let _ = self.tx.send(msg);
+    }
+}
+
+/// Build the upstream handshake request for a client
+fn upstream_request(
+    url: &str,
+    forward_headers: bool,
+    headers: Option<HeaderMap>,
+    addr: SocketAddr,
+    subprotocol: Option<&str>,
+) -> Result<Request, String> {
+    let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+
+    let out = request.headers_mut();
+    if let Some(headers) = headers.filter(|_| forward_headers) {
+        for (name, value) in &headers {
+            if !HOP_HEADERS.contains(&name.as_str()) {
+                out.append(name, value.clone());
+            }
+        }
+    }
+
+    let forwarded_for = match out.get("X-Forwarded-For").and_then(|v| v.to_str().ok()) {
+        Some(chain) => format!("{}, {}", chain, addr.ip()),
+        None => addr.ip().to_string(),
+    };
+    if let Ok(value) = forwarded_for.parse() {
+        out.insert("X-Forwarded-For", value);
+    }
+
+    if let Some(value) = subprotocol.and_then(|p| p.parse().ok()) {
+        out.insert("Sec-WebSocket-Protocol", value);
+    }
+
+    Ok(request)
+}
+
+async fn run(
+    shared: Arc<Shared>,
+    conn: Arc<Connection>,
+    request: Result<Request, String>,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    guard: ActiveGuard,
+) {
+    let ws_stream = match connect(&shared, request).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            // Stop routing new connections here until a health check succeeds
+            if let Some(balancer) = &shared.balancer {
+                balancer.set_healthy(guard.index, false);
+            }
+            fail(&shared, &conn, format!("Upstream connection failed: {}", e));
+            return;
+        }
+    };
+    tracing::info!("Bridged connection {} to upstream {}", conn.id, guard.index);
+
+    let (mut write, mut read) = ws_stream.split();
+    loop {
+        tokio::select! {
+            next = rx.recv() => match next {
+                Some(msg) => {
+                    if let Err(e) = write.send(msg).await {
+                        fail(&shared, &conn, format!("Upstream send failed: {}", e));
+                        return;
+                    }
+                }
+                None => {
+                    // The client is gone; close the upstream side too
+                    let _ = write.send(Message::Close(None)).await;
+                    return;
+                }
+            },
+            next = read.next() => match next {
+                Some(Ok(msg @ (Message::Binary(_) | Message::Text(_)))) => {
+                    shared.send_message(conn.id, msg);
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    match frame {
+                        Some(frame) => conn.close_with(frame.code.into(), &frame.reason),
+                        None => conn.close(),
+                    }
+                    return;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    fail(&shared, &conn, format!("Upstream error: {}", e));
+                    return;
+                }
+                None => {
+                    fail(&shared, &conn, "Upstream closed without a closing handshake".into());
+                    return;
+                }
+            },
+        }
+    }
+}
+
+/// Complete the upstream handshake within the server's handshake timeout
+async fn connect(
+    shared: &Shared,
+    request: Result<Request, String>,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, String> {
+    let request = request?;
+    let timeout_ms = shared.settings.read().handshake_timeout_ms.max(1);
+    match tokio::time::timeout(
+        Duration::from_millis(timeout_ms),
+        tokio_tungstenite::connect_async(request),
+    )
+    .await
+    {
+        Ok(Ok((ws_stream, _))) => Ok(ws_stream),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("Handshake timed out".to_string()),
+    }
+}
+
+/// Periodically probe every upstream with a handshake and update its health.
+/// Runs until the runtime shuts down.
+pub async fn run_health_checks(shared: Arc<Shared>, interval_ms: u64) {
+    let Some(balancer) = &shared.balancer else {
+        return;
+    };
+    let mut interval = tokio::time::interval(Duration::from_millis(interval_ms));
+
+    loop {
+        interval.tick().await;
+
+        let probes = balancer.targets.iter().map(|target| {
+            let shared = &shared;
+            async move {
+                let request = target.url.as_str().into_client_request();
+                match connect(shared, request.map_err(|e| e.to_string())).await {
+                    Ok(mut ws_stream) => {
+                        let _ = ws_stream.close(None).await;
+                        true
+                    }
+                    Err(e) => {
+                        tracing::debug!("Health check for {} failed: {}", target.url, e);
+                        false
+                    }
+                }
+            }
+        });
+        let results = futures_util::future::join_all(probes).await;
+
+        for (index, healthy) in results.into_iter().enumerate() {
+            balancer.set_healthy(index, healthy);
+        }
+    }
+}
+
+/// Report an upstream failure and drop the client with a bad-gateway close
+fn fail(shared: &Shared, conn: &Connection, error: String) {
+    tracing::warn!("Bridge error for connection {}: {}", conn.id, error);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::Error,
+        connection_id: conn.id,
+        data: None,
+        error: Some(error),
+        request_id: 0,
+    });
+    conn.terminate(UPSTREAM_FAILURE_CODE, "Bad gateway");
+}
@@ -0,0 +1,88 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Server-wide budget of message events
+//!
+//! When thousands of clients send at once, the host would otherwise face every
+//! message in one frame. Message events beyond the budget of the current
+//! interval are dropped, counted and reported in an `EventsDropped` event, as are
+//! those that find the event queue's overflow list full.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::EventBudgetSettings;
+use crate::types::DwebbleWSEventType;
+
+/// How often dropped events are reported
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Message events raised in the current interval, and those dropped over the budget
+#[derive(Default)]
+pub struct EventBudget {
+    interval_start: Option<Instant>,
+    spent: u32,
+    /// Dropped since the last report
+    unreported: u64,
+    dropped: u64,
+}
+
+impl EventBudget {
+    /// Spend one message event of the budget. Returns false, counting the event as
+    /// dropped, if the interval's budget is spent.
+    pub fn spend(&mut self, settings: &EventBudgetSettings) -> bool {
+        let now = Instant::now();
+        let interval = Duration::from_millis(settings.interval_ms);
+        match self.interval_start {
+            Some(start) if now.duration_since(start) < interval => {}
+            _ => {
+                self.interval_start = Some(now);
+                self.spent = 0;
+            }
+        }
+
+        if self.spent >= settings.max_events {
+            self.unreported += 1;
+            self.dropped += 1;
+            return false;
+        }
+        self.spent += 1;
+        true
+    }
+
+    /// Count a message event dropped as the event queue's overflow list was full
+    pub fn overflowed(&mut self) {
+        self.unreported += 1;
+        self.dropped += 1;
+    }
+
+    /// Message events dropped since the server was created
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Periodically raise `EventsDropped` for dropped message events.
+/// Runs until the runtime shuts down.
+pub async fn run(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let unreported = std::mem::take(&mut shared.event_budget.lock().unreported);
+        if unreported == 0 {
+            continue;
+        }
+        tracing::warn!("Dropped {} message events over the event budget or queue", unreported);
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::EventsDropped,
+            connection_id: 0,
+            data: None,
+            error: None,
+            request_id: unreported,
+        });
+    }
+}
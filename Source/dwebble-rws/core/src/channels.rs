@@ -0,0 +1,327 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Virtual channels with per-channel delivery guarantees
+//!
+//! Messages sent on a channel are binary messages wrapped in an envelope
+//! carrying a sequence number (little-endian):
+//!
+//! | field    | type |
+//! |----------|------|
+//! | magic    | 4 bytes: `DWCH` for messages, `DWCA` for acknowledgements |
+//! | channel  | u8   |
+//! | flags    | u8: 1 = reliable, 2 = acknowledgement requested (0 for acknowledgements) |
+//! | sequence | u32  |
+//! | payload  | remaining bytes (messages only) |
+//!
+//! Reliable channels deliver every message once and in order. The receiver
+//! acknowledges the highest sequence it has delivered, and the sender keeps
+//! unacknowledged messages to send again when a session resumes after its
+//! socket dropped. Sequenced channels deliver only messages newer than the last
+//! one delivered ("latest wins"); when they request acknowledgements, each
+//! message is acknowledged on its own, which only feeds the round-trip time.
+//!
+//! Sequences start at 1 on both sides of a connection and wrap around.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::control;
+use crate::settings::{ChannelMode, ChannelSettings};
+use crate::types::DwebbleWSChannelStats;
+
+const MESSAGE_MAGIC: &[u8; 4] = control::CHANNEL_MESSAGE;
+const ACK_MAGIC: &[u8; 4] = control::CHANNEL_ACK;
+const HEADER_LEN: usize = 10;
+
+const FLAG_RELIABLE: u8 = 1;
+const FLAG_ACK_REQUESTED: u8 = 2;
+
+/// Reliable messages held per channel while an earlier one is missing
+const MAX_PENDING: usize = 1024;
+
+/// Send times kept per sequenced channel to match acknowledgements against
+const MAX_TIMED: usize = 256;
+
+/// Whether sequence `a` comes after `b`, allowing for wrap-around
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+fn header(magic: &[u8; 4], channel: u8, flags: u8, sequence: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_LEN);
+    data.extend_from_slice(magic);
+    data.push(channel);
+    data.push(flags);
+    data.extend_from_slice(&sequence.to_le_bytes());
+    data
+}
+
+struct Unacked {
+    sequence: u32,
+    frame: Vec<u8>,
+    sent_at: Instant,
+    /// Sent more than once, so its acknowledgement says nothing about the round trip
+    retransmitted: bool,
+}
+
+#[derive(Default)]
+struct Channel {
+    /// Sequence of the last message sent
+    last_out: u32,
+    /// Reliable messages awaiting acknowledgement, oldest first
+    unacked: VecDeque<Unacked>,
+    /// Sequenced messages awaiting acknowledgement, oldest first
+    timed: VecDeque<(u32, Instant)>,
+    /// Sequence of the last message delivered
+    last_in: u32,
+    /// Reliable messages that arrived ahead of a missing one
+    pending: HashMap<u32, Vec<u8>>,
+    rtt: Option<Duration>,
+    stats: DwebbleWSChannelStats,
+}
+
+impl Channel {
+    fn sample_rtt(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+}
+
+/// Messages delivered by an inbound frame
+pub struct Inbound {
+    pub channel: u8,
+    /// Payloads to deliver, in order
+    pub messages: Vec<Vec<u8>>,
+    /// Acknowledgement to send back
+    pub ack: Option<Vec<u8>>,
+}
+
+/// Channel state of one side of a connection
+#[derive(Default)]
+pub struct Endpoint {
+    channels: HashMap<u8, Channel>,
+}
+
+impl Endpoint {
+    /// Wrap a payload for `channel`, keeping it for retransmission if the
+    /// channel is reliable. Returns `None` if the channel is not configured.
+    pub fn send(
+        &mut self,
+        settings: &[ChannelSettings],
+        channel: u8,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        let config = settings.get(usize::from(channel))?;
+        let state = self.channels.entry(channel).or_default();
+        state.last_out = state.last_out.wrapping_add(1);
+        let sequence = state.last_out;
+
+        let reliable = config.mode == ChannelMode::Reliable;
+        let flags = match (reliable, config.acks) {
+            (true, _) => FLAG_RELIABLE | FLAG_ACK_REQUESTED,
+            (false, true) => FLAG_ACK_REQUESTED,
+            (false, false) => 0,
+        };
+        let mut frame = header(MESSAGE_MAGIC, channel, flags, sequence);
+        frame.extend_from_slice(payload);
+
+        state.stats.messages_sent += 1;
+        state.stats.bytes_sent += payload.len() as u64;
+
+        let now = Instant::now();
+        if reliable {
+            state.unacked.push_back(Unacked {
+                sequence,
+                frame: frame.clone(),
+                sent_at: now,
+                retransmitted: false,
+            });
+            if state.unacked.len() > config.max_unacked.max(1) {
+                state.unacked.pop_front();
+                state.stats.messages_abandoned += 1;
+            }
+        } else if config.acks {
+            state.timed.push_back((sequence, now));
+            if state.timed.len() > MAX_TIMED {
+                state.timed.pop_front();
+            }
+        }
+        Some(frame)
+    }
+
+    /// Handle an inbound frame. Returns `None` if it is not a channel frame.
+    pub fn receive(&mut self, data: &[u8]) -> Option<Inbound> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let magic = &data[..4];
+        let channel = data[4];
+        let flags = data[5];
+        let sequence = u32::from_le_bytes(data[6..HEADER_LEN].try_into().unwrap());
+
+        if magic == ACK_MAGIC {
+            self.on_ack(channel, sequence);
+            return Some(Inbound {
+                channel,
+                messages: Vec::new(),
+                ack: None,
+            });
+        }
+        if magic != MESSAGE_MAGIC {
+            return None;
+        }
+
+        let payload = &data[HEADER_LEN..];
+        let state = self.channels.entry(channel).or_default();
+        let mut messages = Vec::new();
+        let ack;
+
+        if flags & FLAG_RELIABLE != 0 {
+            let expected = state.last_in.wrapping_add(1);
+            if sequence == expected {
+                messages.push(payload.to_vec());
+                state.last_in = sequence;
+                while let Some(next) = state.pending.remove(&state.last_in.wrapping_add(1)) {
+                    messages.push(next);
+                    state.last_in = state.last_in.wrapping_add(1);
+                }
+            } else if is_newer(sequence, expected) {
+                // Not acknowledged, so the sender still has it if this is dropped
+                if state.pending.len() < MAX_PENDING {
+                    state
+                        .pending
+                        .entry(sequence)
+                        .or_insert_with(|| payload.to_vec());
+                }
+            } else {
+                state.stats.duplicates_dropped += 1;
+            }
+            // Acknowledge everything delivered so far, also answering duplicates
+            // whose acknowledgement may have been lost
+            ack = (state.last_in != 0).then(|| header(ACK_MAGIC, channel, 0, state.last_in));
+        } else {
+            if is_newer(sequence, state.last_in) {
+                messages.push(payload.to_vec());
+                state.last_in = sequence;
+            } else {
+                state.stats.stale_dropped += 1;
+            }
+            ack =
+                (flags & FLAG_ACK_REQUESTED != 0).then(|| header(ACK_MAGIC, channel, 0, sequence));
+        }
+
+        state.stats.messages_received += messages.len() as u64;
+        state.stats.bytes_received += messages.iter().map(|m| m.len() as u64).sum::<u64>();
+        Some(Inbound {
+            channel,
+            messages,
+            ack,
+        })
+    }
+
+    fn on_ack(&mut self, channel: u8, sequence: u32) {
+        let Some(state) = self.channels.get_mut(&channel) else {
+            return;
+        };
+        let now = Instant::now();
+
+        // Reliable acknowledgements cover every message up to the sequence
+        let mut latest = None;
+        while let Some(front) = state.unacked.front() {
+            if is_newer(front.sequence, sequence) {
+                break;
+            }
+            latest = state.unacked.pop_front();
+            state.stats.messages_acked += 1;
+        }
+        if let Some(acked) = latest.filter(|acked| !acked.retransmitted) {
+            state.sample_rtt(now - acked.sent_at);
+        }
+
+        // Sequenced acknowledgements cover only their own message; older ones
+        // were delivered or dropped by now
+        if let Some(index) = state.timed.iter().position(|&(s, _)| s == sequence) {
+            let (_, sent_at) = state.timed[index];
+            state.timed.drain(..=index);
+            state.stats.messages_acked += 1;
+            state.sample_rtt(now - sent_at);
+        }
+    }
+
+    /// Frames of every unacknowledged reliable message, to send again on a new socket
+    pub fn retransmit(&mut self) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for state in self.channels.values_mut() {
+            for unacked in &mut state.unacked {
+                unacked.retransmitted = true;
+                frames.push(unacked.frame.clone());
+            }
+            state.stats.messages_retransmitted += state.unacked.len() as u64;
+        }
+        frames
+    }
+
+    /// Statistics of a channel, zero if nothing was sent or received on it yet
+    pub fn stats(&self, channel: u8) -> DwebbleWSChannelStats {
+        match self.channels.get(&channel) {
+            Some(state) => DwebbleWSChannelStats {
+                messages_unacked: state.unacked.len() as u64,
+                rtt_us: state.rtt.map_or(0, |rtt| rtt.as_micros() as u64),
+                ..state.stats
+            },
+            None => DwebbleWSChannelStats::default(),
+        }
+    }
+}
+
+/// Channel state of every server connection
+#[derive(Default)]
+pub struct Channels {
+    endpoints: HashMap<u64, Endpoint>,
+}
+
+impl Channels {
+    pub fn send(
+        &mut self,
+        settings: &[ChannelSettings],
+        connection_id: u64,
+        channel: u8,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.endpoints
+            .entry(connection_id)
+            .or_default()
+            .send(settings, channel, payload)
+    }
+
+    pub fn receive(&mut self, connection_id: u64, data: &[u8]) -> Option<Inbound> {
+        self.endpoints
+            .entry(connection_id)
+            .or_default()
+            .receive(data)
+    }
+
+    pub fn retransmit(&mut self, connection_id: u64) -> Vec<Vec<u8>> {
+        self.endpoints
+            .get_mut(&connection_id)
+            .map_or_else(Vec::new, Endpoint::retransmit)
+    }
+
+    pub fn stats(&self, connection_id: u64, channel: u8) -> DwebbleWSChannelStats {
+        self.endpoints
+            .get(&connection_id)
+            .map_or_else(DwebbleWSChannelStats::default, |e| e.stats(channel))
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.endpoints.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.endpoints.clear();
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Scripted chaos scenarios for resilience testing
+//!
+//! A scenario is a JSON object with a list of steps, each run at an offset from
+//! the moment the scenario was scheduled:
+//!
+//! ```json
+//! {"steps": [
+//!     {"at_ms": 1000, "action": "disconnect", "percent": 50},
+//!     {"at_ms": 2000, "action": "stall_writers", "percent": 100, "duration_ms": 500},
+//!     {"at_ms": 3000, "action": "refuse_handshakes", "duration_ms": 2000}
+//! ]}
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::connection::Connection;
+use crate::netsim::Rng;
+use crate::server::Shared;
+
+/// Reason reported for connections dropped by a chaos step
+pub const DISCONNECT_REASON: &str = "Chaos disconnect";
+
+/// Close code sent when a chaos disconnect is not abrupt
+const CLOSE_CODE: u16 = 1001;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    /// Offset from scheduling the scenario, in milliseconds
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Drop a share of the live connections
+    Disconnect {
+        percent: f64,
+        /// Drop without a closing handshake (as a network failure) instead of closing
+        #[serde(default = "default_abrupt")]
+        abrupt: bool,
+    },
+    /// Stop writing to a share of the live connections for a while
+    StallWriters { percent: f64, duration_ms: u64 },
+    /// Reject every new handshake with 503 for a while
+    RefuseHandshakes { duration_ms: u64 },
+}
+
+fn default_abrupt() -> bool {
+    true
+}
+
+impl Scenario {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Run the steps of a scenario on their schedule
+pub(crate) async fn run(shared: Arc<Shared>, scenario: Scenario) {
+    let started = Instant::now();
+    let mut steps = scenario.steps;
+    steps.sort_by_key(|step| step.at_ms);
+
+    let mut rng = Rng::new();
+    for step in steps {
+        tokio::time::sleep_until(started + Duration::from_millis(step.at_ms)).await;
+        apply(&shared, &step.action, &mut rng);
+    }
+}
+
+fn apply(shared: &Shared, action: &Action, rng: &mut Rng) {
+    match *action {
+        Action::Disconnect { percent, abrupt } => {
+            let targets = pick(shared, percent, rng);
+            tracing::warn!("Chaos: disconnecting {} connection(s)", targets.len());
+            for conn in targets {
+                if abrupt {
+                    conn.sever(DISCONNECT_REASON);
+                } else {
+                    conn.terminate(CLOSE_CODE, DISCONNECT_REASON);
+                }
+            }
+        }
+        Action::StallWriters {
+            percent,
+            duration_ms,
+        } => {
+            let until = Instant::now() + Duration::from_millis(duration_ms);
+            let targets = pick(shared, percent, rng);
+            tracing::warn!(
+                "Chaos: stalling {} writer(s) for {} ms",
+                targets.len(),
+                duration_ms
+            );
+            for conn in targets {
+                conn.set_stall(Some(until));
+            }
+        }
+        Action::RefuseHandshakes { duration_ms } => {
+            tracing::warn!("Chaos: refusing handshakes for {} ms", duration_ms);
+            *shared.refuse_handshakes_until.lock() =
+                Some(Instant::now() + Duration::from_millis(duration_ms));
+        }
+    }
+}
+
+/// Pick `percent` of the live connections at random (rounded to the nearest count)
+fn pick(shared: &Shared, percent: f64, rng: &mut Rng) -> Vec<Arc<Connection>> {
+    let mut conns: Vec<Arc<Connection>> = shared.connections.values();
+    let count = ((conns.len() as f64 * percent.clamp(0.0, 100.0) / 100.0).round() as usize)
+        .min(conns.len());
+
+    // Partial Fisher-Yates shuffle
+    for i in 0..count {
+        let j = i + (rng.next_u64() % (conns.len() - i) as u64) as usize;
+        conns.swap(i, j);
+    }
+    conns.truncate(count);
+    conns
+}
@@ -0,0 +1,95 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Payload checksums and checksum trailers
+//!
+//! With the `checksum` setting's `verify_trailer`, clients end each binary message
+//! with the little-endian u32 checksum of the bytes before it. The trailer is the
+//! outermost layer under encryption: added after stamping and signing, and checked
+//! and stripped before anything else reads the message.
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::settings::ChecksumAlgorithm;
+
+const TRAILER_LEN: usize = 4;
+
+pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> u32 {
+    match algorithm {
+        ChecksumAlgorithm::Crc32 => crc32fast::hash(data),
+        ChecksumAlgorithm::Xxh32 => xxhash_rust::xxh32::xxh32(data, 0),
+    }
+}
+
+/// A binary message with its checksum trailer appended; other messages as they are
+pub fn append(algorithm: ChecksumAlgorithm, msg: Message) -> Message {
+    match msg {
+        Message::Binary(data) => {
+            let mut trailed = Vec::with_capacity(data.len() + TRAILER_LEN);
+            trailed.extend_from_slice(&data);
+            trailed.extend_from_slice(&compute(algorithm, &data).to_le_bytes());
+            Message::Binary(trailed.into())
+        }
+        msg => msg,
+    }
+}
+
+/// The payload of a message ending with its checksum trailer, or why it doesn't
+pub fn strip(algorithm: ChecksumAlgorithm, data: &[u8]) -> Result<&[u8], String> {
+    let Some(split) = data.len().checked_sub(TRAILER_LEN) else {
+        return Err("No checksum trailer".to_string());
+    };
+    let (payload, trailer) = data.split_at(split);
+    let expected = u32::from_le_bytes(trailer.try_into().unwrap());
+    let actual = compute(algorithm, payload);
+    if actual == expected {
+        Ok(payload)
+    } else {
+        Err(format!(
+            "Checksum mismatch: trailer {:08x}, payload {:08x}",
+            expected, actual
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Crc32, ChecksumAlgorithm::Xxh32];
+
+    fn trailed(algorithm: ChecksumAlgorithm, payload: &[u8]) -> Vec<u8> {
+        match append(algorithm, Message::Binary(payload.to_vec().into())) {
+            Message::Binary(data) => data.to_vec(),
+            msg => panic!("Expected a binary message, got {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn trailer_is_stripped() {
+        for algorithm in ALGORITHMS {
+            for payload in [&b""[..], b"payload", &[0xff; 1000]] {
+                let data = trailed(algorithm, payload);
+                assert_eq!(data.len(), payload.len() + TRAILER_LEN);
+                assert_eq!(strip(algorithm, &data), Ok(payload));
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_messages_are_refused() {
+        for algorithm in ALGORITHMS {
+            let mut data = trailed(algorithm, b"payload");
+            data[0] ^= 1;
+            assert!(strip(algorithm, &data).is_err());
+            assert!(strip(algorithm, b"abc").is_err());
+        }
+    }
+
+    #[test]
+    fn text_messages_get_no_trailer() {
+        let msg = append(ChecksumAlgorithm::Crc32, Message::Text("text".into()));
+        assert_eq!(msg, Message::Text("text".into()));
+    }
+}
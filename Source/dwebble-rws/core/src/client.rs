@@ -0,0 +1,761 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebSocket client connections
+
+use std::fmt::Write;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::{self, Message};
+#[cfg(feature = "tls")]
+use tokio_tungstenite::Connector;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::channels::Endpoint;
+use crate::checksum;
+use crate::control;
+use crate::delta::Replica;
+use crate::encryption::{self, Exchange, Opened, Opener, Role, Sealer};
+use crate::freshness::{self, Guard};
+use crate::offline::{self, OfflineQueue};
+use crate::pool;
+use crate::receipts;
+use crate::runtime;
+use crate::server::ServerEvent;
+use crate::settings::{
+    CertificateVerification, ChannelSettings, ChecksumAlgorithm, ClientSettings, ClientTlsSettings,
+    OfflineQueueSettings, Settings, ThreadSettings, Utf8Policy,
+};
+use crate::signing::Signer;
+use crate::types::{
+    DwebbleWSCertificateVerification, DwebbleWSChannelStats, DwebbleWSClientInfo,
+    DwebbleWSEventType, DwebbleWSResult,
+};
+use crate::utf8;
+use crate::version::{self, Version};
+
+/// A client connection driven by a background task.
+///
+/// Events use the server event types: `ClientConnected` once the handshake
+/// completes, `MessageReceived` for each message (`ChannelMessage` for virtual
+/// channel messages, `StateUpdated` for state sync updates), `Error` and finally
+/// `ClientDisconnected`. The connection id of client events is always 0.
+/// With an offline queue, the client reconnects after the connection drops:
+/// `ClientDisconnected` has a request ID of 1 then, each failed attempt raises
+/// `Error`, and `ClientConnected` follows once it is back. Messages sent meanwhile
+/// are queued, raising `MessageDropped` for those it has no room for.
+/// With application-layer encryption, messages are held back until the key
+/// exchange with the server completes, and the keys follow the server's
+/// rotations. With a signing key, messages from the server that fail
+/// verification raise `SignatureInvalid` and are dropped, and with replay
+/// protection, stale or replayed ones raise `ReplayRejected`.
+pub struct Client {
+    tx: mpsc::UnboundedSender<Message>,
+    event_rx: Mutex<mpsc::UnboundedReceiver<ServerEvent>>,
+    channel_settings: Vec<ChannelSettings>,
+    channels: Arc<Mutex<Endpoint>>,
+    utf8_policy: Utf8Policy,
+    /// Binary payloads of the host are escaped from control frames
+    control_frames: bool,
+    signer: Arc<Mutex<Option<Signer>>>,
+    task: JoinHandle<()>,
+    /// The runtime of a client dialing a server, which a loopback client shares with its
+    /// server instead
+    runtime: Option<Runtime>,
+    info: DwebbleWSClientInfo,
+}
+
+impl Client {
+    /// Spawn a client on `runtime` that completes `connect` and then relays messages.
+    /// `settings` are those of the server, for its virtual channels, state sync,
+    /// encryption and replay protection.
+    pub fn spawn<S, F>(runtime: &Handle, settings: &Settings, connect: F) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        F: Future<Output = Result<WebSocketStream<S>, tungstenite::Error>> + Send + 'static,
+    {
+        let mut connect = Some(connect);
+        Self::spawn_with(runtime, settings, move || connect.take(), None)
+    }
+
+    /// Spawn a client that connects with the futures of `connect`, again each time the
+    /// connection drops if it has an offline queue, until `connect` returns `None`
+    fn spawn_with<S, C, F>(
+        runtime: &Handle,
+        settings: &Settings,
+        connect: C,
+        offline_queue: Option<OfflineQueueSettings>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        C: FnMut() -> Option<F> + Send + 'static,
+        F: Future<Output = Result<WebSocketStream<S>, tungstenite::Error>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let channel_settings = settings.channels.clone();
+        let channels = Arc::new(Mutex::new(Endpoint::default()));
+        let receive_channels = (!channel_settings.is_empty()).then(|| Arc::clone(&channels));
+        let replica = settings.delta.is_some().then(Replica::default);
+        let receipts = settings.receipts;
+        let psk = settings.encryption.as_ref().map(|e| e.psk.clone().into_bytes());
+        let replay_window = settings.replay_protection.as_ref().map(|r| r.window_ms);
+        let trailer = settings.checksum.filter(|c| c.verify_trailer).map(|c| c.algorithm);
+        let version = settings.protocol_version.map(|v| Version {
+            major: v.major,
+            minor: v.minor,
+        });
+        let signer = Arc::new(Mutex::new(None));
+        let control_frames = settings.control_frames;
+
+        let task = runtime.spawn(run(
+            connect,
+            rx,
+            event_tx,
+            receive_channels,
+            replica,
+            receipts,
+            psk,
+            Arc::clone(&signer),
+            replay_window,
+            trailer,
+            version,
+            control_frames,
+            offline_queue,
+        ));
+
+        Self {
+            tx,
+            event_rx: Mutex::new(event_rx),
+            channel_settings,
+            channels,
+            utf8_policy: settings.utf8_policy,
+            control_frames,
+            signer,
+            task,
+            runtime: None,
+            info: DwebbleWSClientInfo::default(),
+        }
+    }
+
+    /// Dial the server at `url` (`ws://`, or `wss://` with the `tls` feature) on a
+    /// runtime of the client's own. Returns `InvalidParam` if the URL or a header of
+    /// `settings` is invalid, or if their certificate verification can't be set up.
+    pub fn connect(url: &str, settings: &ClientSettings) -> Result<Self, DwebbleWSResult> {
+        let request = upgrade_request(url, settings).map_err(|e| {
+            tracing::error!("Invalid request to {}: {}", url, e);
+            DwebbleWSResult::InvalidParam
+        })?;
+        let secure = request.uri().scheme_str() == Some("wss");
+        let mut connect = Some(dial(request, secure, &settings.tls)?);
+        // One worker is plenty for one connection
+        let threads = ThreadSettings {
+            worker_threads: 1,
+            ..Default::default()
+        };
+        let runtime =
+            runtime::build(Some(threads), None).map_err(|_| DwebbleWSResult::RuntimeError)?;
+
+        let timeout = Duration::from_millis(settings.handshake_timeout_ms);
+        let (url, reconnect) = (url.to_string(), settings.clone());
+        let connect = move || {
+            // Reconnect attempts dial again with the same request and verification
+            let connect = match connect.take() {
+                Some(connect) => connect,
+                None => {
+                    let request = upgrade_request(&url, &reconnect).ok()?;
+                    dial(request, secure, &reconnect.tls).ok()?
+                }
+            };
+            Some(async move {
+                let connected = if timeout.is_zero() {
+                    connect.await
+                } else {
+                    tokio::time::timeout(timeout, connect).await.unwrap_or_else(|_| {
+                        let e = io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out");
+                        Err(tungstenite::Error::Io(e))
+                    })
+                };
+                connected.map(|(ws_stream, _)| ws_stream)
+            })
+        };
+        let mut client = Self::spawn_with(
+            runtime.handle(),
+            &settings.server,
+            connect,
+            settings.offline_queue,
+        );
+        client.runtime = Some(runtime);
+        if secure {
+            client.info.certificate_verification = match settings.tls.verification {
+                CertificateVerification::MozillaRoots => {
+                    DwebbleWSCertificateVerification::MozillaRoots
+                }
+                CertificateVerification::SystemRoots => {
+                    DwebbleWSCertificateVerification::SystemRoots
+                }
+                CertificateVerification::CaBundle => DwebbleWSCertificateVerification::CaBundle,
+                CertificateVerification::Pinned => DwebbleWSCertificateVerification::Pinned,
+                CertificateVerification::Disabled => DwebbleWSCertificateVerification::Disabled,
+            };
+        }
+        Ok(client)
+    }
+
+    pub fn info(&self) -> DwebbleWSClientInfo {
+        self.info
+    }
+
+    /// Drive a `single-thread` build's runtime for up to `budget`, if the client has its own
+    pub fn tick(&self, budget: Duration) {
+        if let Some(rt) = &self.runtime {
+            runtime::tick(rt, budget);
+        }
+    }
+
+    pub fn send(&self, data: &[u8]) -> bool {
+        let data = match self.control_frames {
+            true => control::escape(data),
+            false => data.to_vec(),
+        };
+        self.tx.send(Message::Binary(data.into())).is_ok()
+    }
+
+    /// Send text, handled like the server's text sends if it is not valid UTF-8
+    pub fn send_text(&self, text: &[u8]) -> DwebbleWSResult {
+        let Some(msg) = utf8::message(self.utf8_policy, text) else {
+            return DwebbleWSResult::InvalidUtf8;
+        };
+        if self.tx.send(msg).is_ok() {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::ConnectionClosed
+        }
+    }
+
+    /// Send a payload on a virtual channel
+    pub fn send_channel(&self, channel: u8, payload: &[u8]) -> DwebbleWSResult {
+        let frame = self
+            .channels
+            .lock()
+            .send(&self.channel_settings, channel, payload);
+        let Some(frame) = frame else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        if self.tx.send(Message::Binary(frame.into())).is_ok() {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::ConnectionClosed
+        }
+    }
+
+    pub fn channel_stats(&self, channel: u8) -> DwebbleWSChannelStats {
+        self.channels.lock().stats(channel)
+    }
+
+    /// Start signing messages with `key`, or stop with `None`
+    pub fn set_signing_key(&self, key: Option<&[u8]>) {
+        *self.signer.lock() = key.map(|key| Signer::new(Role::Client, key));
+    }
+
+    pub fn close(&self) {
+        let _ = self.tx.send(Message::Close(None));
+    }
+
+    pub fn poll_event(&self) -> Option<ServerEvent> {
+        self.event_rx.lock().try_recv().ok()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// The handshake with the server of `request`, verifying a `secure` one's certificate
+/// as `settings` choose
+#[cfg(feature = "tls")]
+pub(crate) fn dial(
+    request: Request,
+    secure: bool,
+    settings: &ClientTlsSettings,
+) -> Result<impl Future<Output = Result<Dialed, tungstenite::Error>>, DwebbleWSResult> {
+    // tokio-tungstenite's own connector trusts the Mozilla root certificates
+    let connector = if secure && settings.verification != CertificateVerification::MozillaRoots {
+        if settings.verification == CertificateVerification::Disabled {
+            tracing::warn!("Server certificate verification is disabled");
+        }
+        let config = crate::tls::client_config(settings).map_err(|e| {
+            tracing::error!("{}", e);
+            DwebbleWSResult::InvalidParam
+        })?;
+        Some(Connector::Rustls(Arc::new(config)))
+    } else {
+        None
+    };
+    Ok(tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector))
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn dial(
+    request: Request,
+    _secure: bool,
+    settings: &ClientTlsSettings,
+) -> Result<impl Future<Output = Result<Dialed, tungstenite::Error>>, DwebbleWSResult> {
+    if settings.verification != CertificateVerification::MozillaRoots {
+        tracing::error!("Certificate verification settings require the `tls` feature");
+        return Err(DwebbleWSResult::InvalidParam);
+    }
+    Ok(tokio_tungstenite::connect_async(request))
+}
+
+/// A connection to a server with the response to its upgrade request
+pub(crate) type Dialed = (WebSocketStream<MaybeTlsStream<TcpStream>>, Response);
+
+/// The upgrade request for `url` with the query parameters, headers, cookies and
+/// subprotocols of `settings`
+pub(crate) fn upgrade_request(url: &str, settings: &ClientSettings) -> Result<Request, String> {
+    let mut url = url.to_string();
+    for (name, value) in &settings.query {
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(&encode_component(name));
+        url.push('=');
+        url.push_str(&encode_component(value));
+    }
+    let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+
+    let headers = request.headers_mut();
+    let value = |value: &str| HeaderValue::from_str(value).map_err(|e| e.to_string());
+    for (name, header_value) in &settings.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+        headers.append(name, value(header_value)?);
+    }
+    if !settings.cookies.is_empty() {
+        let cookies: Vec<_> = settings
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        headers.append(header::COOKIE, value(&cookies.join("; "))?);
+    }
+    if !settings.subprotocols.is_empty() {
+        let subprotocols = settings.subprotocols.join(", ");
+        headers.insert(header::SEC_WEBSOCKET_PROTOCOL, value(&subprotocols)?);
+    }
+
+    Ok(request)
+}
+
+/// Percent-encode all but the unreserved characters of RFC 3986
+fn encode_component(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// Stamp, sign, checksum and encrypt a message as the connection's settings and keys
+/// require
+fn protect(
+    stamp: bool,
+    signer: &Mutex<Option<Signer>>,
+    trailer: Option<ChecksumAlgorithm>,
+    sealer: &mut Option<Sealer>,
+    msg: Message,
+) -> Message {
+    let msg = if stamp { freshness::stamp(msg) } else { msg };
+    let msg = match signer.lock().as_mut() {
+        Some(signer) => signer.sign(msg),
+        None => msg,
+    };
+    let msg = match trailer {
+        Some(algorithm) => checksum::append(algorithm, msg),
+        None => msg,
+    };
+    encryption::seal(sealer, msg)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run<S, C, F>(
+    mut connect: C,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    event_tx: mpsc::UnboundedSender<ServerEvent>,
+    channels: Option<Arc<Mutex<Endpoint>>>,
+    mut replica: Option<Replica>,
+    receipts: bool,
+    // The pre-shared key, if the server encrypts
+    server_psk: Option<Vec<u8>>,
+    signer: Arc<Mutex<Option<Signer>>>,
+    replay_window: Option<u64>,
+    // The checksum of the trailer on binary messages, if the server verifies them
+    trailer: Option<ChecksumAlgorithm>,
+    // The version offered first on each connection, if the server negotiates one
+    version: Option<Version>,
+    control_frames: bool,
+    offline_queue: Option<OfflineQueueSettings>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    C: FnMut() -> Option<F>,
+    F: Future<Output = Result<WebSocketStream<S>, tungstenite::Error>>,
+{
+    let push_with_id = |event_type, data, error, request_id| {
+        let _ = event_tx.send(ServerEvent {
+            event_type,
+            connection_id: 0,
+            data,
+            error,
+            request_id,
+        });
+    };
+    let push = |event_type, data, error| push_with_id(event_type, data, error, 0);
+
+    let mut queue = offline_queue
+        .map(|settings| OfflineQueue::new(settings, control_frames, event_tx.clone()));
+    let mut reconnecting = false;
+    loop {
+        if reconnecting {
+            if let Some(queue) = queue.as_mut() {
+                let backoff = queue.backoff();
+                if offline::hold(backoff, &mut rx, queue).await.is_none() {
+                    break;
+                }
+            }
+        }
+        reconnecting = true;
+
+        let Some(connecting) = connect() else { break };
+        let connected = match queue.as_mut() {
+            Some(queue) => match offline::hold(connecting, &mut rx, queue).await {
+                Some(connected) => connected,
+                None => break,
+            },
+            None => connecting.await,
+        };
+        let ws_stream = match connected {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                push(DwebbleWSEventType::Error, None, Some(e.to_string()));
+                if queue.is_none() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if let Some(queue) = queue.as_mut() {
+            queue.connected();
+        }
+        push(DwebbleWSEventType::ClientConnected, None, None);
+
+        let closed = connection(
+            ws_stream,
+            &mut rx,
+            &push,
+            &push_with_id,
+            queue.as_mut(),
+            channels.as_ref(),
+            replica.as_mut(),
+            receipts,
+            server_psk.clone(),
+            &signer,
+            replay_window,
+            trailer,
+            version,
+            control_frames,
+        )
+        .await;
+        if closed || queue.is_none() {
+            break;
+        }
+        push_with_id(DwebbleWSEventType::ClientDisconnected, None, None, 1);
+
+        // The server's side of channels and state sync starts over with the next connection
+        if let Some(channels) = &channels {
+            *channels.lock() = Endpoint::default();
+        }
+        if let Some(replica) = replica.as_mut() {
+            *replica = Replica::default();
+        }
+    }
+
+    push(DwebbleWSEventType::ClientDisconnected, None, None);
+}
+
+/// Relay messages over a connection to the server until it ends. Returns whether the
+/// host closed the client, or dropped it.
+#[allow(clippy::too_many_arguments)]
+async fn connection<S>(
+    ws_stream: WebSocketStream<S>,
+    rx: &mut mpsc::UnboundedReceiver<Message>,
+    push: &impl Fn(DwebbleWSEventType, Option<Vec<u8>>, Option<String>),
+    push_with_id: &impl Fn(DwebbleWSEventType, Option<Vec<u8>>, Option<String>, u64),
+    mut queue: Option<&mut OfflineQueue>,
+    channels: Option<&Arc<Mutex<Endpoint>>>,
+    mut replica: Option<&mut Replica>,
+    receipts: bool,
+    // Set until the key exchange completed, if the server encrypts
+    mut psk: Option<Vec<u8>>,
+    signer: &Mutex<Option<Signer>>,
+    replay_window: Option<u64>,
+    trailer: Option<ChecksumAlgorithm>,
+    version: Option<Version>,
+    control_frames: bool,
+) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut write, mut read) = ws_stream.split();
+    // Set once either side starts the closing handshake; the stream is read until
+    // it ends so the reply Close frame is flushed
+    let mut closing = false;
+    // Set once the host starts the closing handshake, or drops the client
+    let mut closed = false;
+    let mut sealer = None;
+    let mut opener = None;
+    // Kept for key rotations, and the key to open with once the server's rotation ends
+    let mut rekey_psk = Vec::new();
+    let mut next_opener: Option<Opener> = None;
+    let mut nonces = Guard::default();
+    let stamp = replay_window.is_some();
+    let mut version_offer = version.map(|v| Message::Binary(version::frame(v).into()));
+
+    loop {
+        // The version offer goes before anything else, once the key exchange completed
+        let offer = psk.is_none().then(|| version_offer.take()).flatten();
+        if let Some(msg) = offer {
+            if write.send(protect(stamp, signer, trailer, &mut sealer, msg)).await.is_err() {
+                break;
+            }
+            continue;
+        }
+        // Messages queued while disconnected go first, once the key exchange completed
+        let held = (!closing && psk.is_none())
+            .then(|| queue.as_mut().and_then(|q| q.pop()))
+            .flatten();
+        if let Some(msg) = held {
+            if write.send(protect(stamp, signer, trailer, &mut sealer, msg.clone())).await.is_err() {
+                if let Some(queue) = queue.as_mut() {
+                    queue.requeue(msg);
+                }
+                break;
+            }
+            continue;
+        }
+
+        tokio::select! {
+            outbound = rx.recv(), if !closing && psk.is_none() => {
+                let Some(msg) = outbound else {
+                    closed = true;
+                    break;
+                };
+                closing = matches!(msg, Message::Close(_));
+                closed |= closing;
+                let retry = (queue.is_some() && !closing).then(|| msg.clone());
+                if write.send(protect(stamp, signer, trailer, &mut sealer, msg)).await.is_err() {
+                    if let (Some(queue), Some(msg)) = (queue.as_mut(), retry) {
+                        queue.requeue(msg);
+                    }
+                    break;
+                }
+            }
+            inbound = read.next() => {
+                let inbound = match inbound {
+                    // The server's first message is its half of the key exchange
+                    Some(Ok(msg)) if psk.is_some() && (msg.is_binary() || msg.is_text()) => {
+                        let psk = psk.take().unwrap_or_default();
+                        let keys = Exchange::start(Role::Client).and_then(|(exchange, hello)| {
+                            exchange.finish(&msg, &psk).map(|keys| (keys, hello))
+                        });
+                        let ((keys_sealer, keys_opener), hello) = match keys {
+                            Ok(keys) => keys,
+                            Err(e) => {
+                                let error = format!("Key exchange failed: {}", e);
+                                push(DwebbleWSEventType::Error, None, Some(error));
+                                break;
+                            }
+                        };
+                        sealer = Some(keys_sealer);
+                        opener = Some(keys_opener);
+                        rekey_psk = psk;
+                        if write.send(Message::Binary(hello.into())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(Ok(msg)) => match encryption::open(&mut opener, msg) {
+                        Ok(Opened::Message(msg)) => Some(Ok(msg)),
+                        // The server rotates keys: answer under the current key, then seal
+                        // with the new one
+                        Ok(Opened::Rekey(msg)) => {
+                            let keys = Exchange::start(Role::Client).and_then(|(exchange, hello)| {
+                                exchange.finish(&msg, &rekey_psk).map(|keys| (keys, hello))
+                            });
+                            let (Ok(((keys_sealer, keys_opener), hello)), Some(current)) =
+                                (keys, sealer.as_mut())
+                            else {
+                                let error = "Key rotation failed".to_string();
+                                push(DwebbleWSEventType::Error, None, Some(error));
+                                break;
+                            };
+                            let answer = current.seal_rekey(&hello);
+                            sealer = Some(keys_sealer);
+                            next_opener = Some(keys_opener);
+                            if write.send(answer).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        Ok(Opened::Rekeyed) => {
+                            let Some(keys) = next_opener.take() else {
+                                let error = "Unexpected end of key rotation".to_string();
+                                push(DwebbleWSEventType::Error, None, Some(error));
+                                break;
+                            };
+                            opener = Some(keys);
+                            continue;
+                        }
+                        Err(e) => {
+                            push(DwebbleWSEventType::Error, None, Some(e));
+                            break;
+                        }
+                    },
+                    other => other,
+                };
+                let inbound = match inbound {
+                    Some(Ok(msg)) => {
+                        let verified = match signer.lock().as_mut() {
+                            Some(signer) => signer.verify(msg.clone()),
+                            None => Ok(msg.clone()),
+                        };
+                        let checked = match (verified, replay_window) {
+                            (Ok(msg), Some(window_ms)) => nonces
+                                .check(window_ms, msg)
+                                .map_err(|e| (DwebbleWSEventType::ReplayRejected, e)),
+                            (Ok(msg), None) => Ok(msg),
+                            (Err(e), _) => Err((DwebbleWSEventType::SignatureInvalid, e)),
+                        };
+                        match checked {
+                            Ok(msg) => Some(Ok(msg)),
+                            Err((event_type, e)) => {
+                                push(event_type, Some(msg.into_data().to_vec()), Some(e));
+                                continue;
+                            }
+                        }
+                    }
+                    other => other,
+                };
+
+                match inbound {
+                    Some(Ok(Message::Binary(data))) => {
+                        if let Some((sequence, payload, ack)) =
+                            receipts.then(|| receipts::receive(&data)).flatten()
+                        {
+                            push_with_id(
+                                DwebbleWSEventType::MessageReceived,
+                                Some(pool::copy(payload)),
+                                None,
+                                sequence,
+                            );
+                            let ack = Message::Binary(ack.into());
+                            let ack = protect(stamp, signer, trailer, &mut sealer, ack);
+                            if write.send(ack).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        if let Some(update) = replica.as_mut().and_then(|r| r.receive(&data)) {
+                            match update {
+                                Ok(applied) => {
+                                    push_with_id(
+                                        DwebbleWSEventType::StateUpdated,
+                                        Some(applied.state),
+                                        None,
+                                        u64::from(applied.key),
+                                    );
+                                    let ack = Message::Binary(applied.ack.into());
+                                    let ack = protect(stamp, signer, trailer, &mut sealer, ack);
+                                    if write.send(ack).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                Err(e) => push(DwebbleWSEventType::Error, None, Some(e)),
+                            }
+                            continue;
+                        }
+                        let inbound = channels.as_ref().and_then(|c| c.lock().receive(&data));
+                        let Some(inbound) = inbound else {
+                            // Control frames of features this client doesn't use
+                            let data = match control::unescape(&data) {
+                                _ if !control_frames => &data,
+                                Some(payload) => payload,
+                                None if control::is_control(&data) => continue,
+                                None => &data,
+                            };
+                            let data = pool::copy(data);
+                            push(DwebbleWSEventType::MessageReceived, Some(data), None);
+                            continue;
+                        };
+                        let channel = u64::from(inbound.channel);
+                        for payload in inbound.messages {
+                            push_with_id(
+                                DwebbleWSEventType::ChannelMessage,
+                                Some(payload),
+                                None,
+                                channel,
+                            );
+                        }
+                        if let Some(ack) = inbound.ack {
+                            let ack = Message::Binary(ack.into());
+                            let ack = protect(stamp, signer, trailer, &mut sealer, ack);
+                            if write.send(ack).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        push(
+                            DwebbleWSEventType::MessageReceived,
+                            Some(pool::copy(text.as_bytes())),
+                            None,
+                        );
+                    }
+                    Some(Ok(Message::Close(_))) => closing = true,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        if !closing {
+                            push(DwebbleWSEventType::Error, None, Some(e.to_string()));
+                        }
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    closed
+}
@@ -0,0 +1,397 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Cross-instance fan-out of room broadcasts, topic publications and presence
+//!
+//! Every instance publishes its room broadcasts and topic publications to shared
+//! backend channels and delivers those of sibling instances to its local room
+//! members and topic subscribers. Instances also announce themselves on a
+//! presence channel with periodic heartbeats, so each one knows which siblings
+//! are alive, along with the logical users connected to them. A backend is a
+//! task that publishes the frames it receives on a channel and hands every
+//! frame it receives from the backend to [`deliver`].
+//!
+//! Frames start with a common header (little-endian):
+//!
+//! | field   | type |
+//! |---------|------|
+//! | origin  | 16 bytes (instance id of the publisher) |
+//! | kind    | u8   |
+//!
+//! Room frames (kind 0 = binary, 1 = text) and topic frames (kind 7 = binary,
+//! 8 = text) continue with:
+//!
+//! | field    | type |
+//! |----------|------|
+//! | name_len | u16  |
+//! | name     | `name_len` bytes of UTF-8 (room or topic) |
+//! | payload  | remaining bytes |
+//!
+//! Instance frames (kind 2 = heartbeat, 3 = leaving) have no body. User frames
+//! (kind 4 = online, 5 = offline) carry the user id as UTF-8. When an instance
+//! sees a new sibling it announces all of its users and publishes a sync
+//! request (kind 6, no body) asking every sibling to announce theirs again.
+
+mod nats;
+#[cfg(feature = "redis")]
+mod redis;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::presence::Changes;
+use crate::server::{ServerEvent, Shared};
+use crate::settings::ClusterSettings;
+use crate::types::DwebbleWSEventType;
+use crate::utf8;
+
+const ORIGIN_LEN: usize = 16;
+const HEADER_LEN: usize = ORIGIN_LEN + 1;
+const ADDRESSED_HEADER_LEN: usize = HEADER_LEN + 2;
+
+const KIND_BINARY: u8 = 0;
+const KIND_TEXT: u8 = 1;
+const KIND_HEARTBEAT: u8 = 2;
+const KIND_LEAVING: u8 = 3;
+const KIND_USER_ONLINE: u8 = 4;
+const KIND_USER_OFFLINE: u8 = 5;
+const KIND_SYNC: u8 = 6;
+const KIND_TOPIC_BINARY: u8 = 7;
+const KIND_TOPIC_TEXT: u8 = 8;
+
+/// How often this instance announces itself
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Siblings silent for this long are considered gone
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Random identity of a server instance, generated when it is created
+pub type InstanceId = [u8; ORIGIN_LEN];
+
+/// Backend channel (Redis channel or NATS subject suffix) a frame is published on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Rooms,
+    Topics,
+    Presence,
+}
+
+impl Channel {
+    pub(crate) const ALL: [Channel; 3] = [Channel::Rooms, Channel::Topics, Channel::Presence];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Rooms => "rooms",
+            Channel::Topics => "topics",
+            Channel::Presence => "presence",
+        }
+    }
+}
+
+/// A frame waiting to be published
+pub type Outbound = (Channel, Vec<u8>);
+
+/// This instance's membership in a cluster
+pub struct Cluster {
+    settings: ClusterSettings,
+    instance_id: InstanceId,
+    outbound: Mutex<Option<mpsc::UnboundedSender<Outbound>>>,
+    backend_task: Mutex<Option<JoinHandle<()>>>,
+    /// Last heartbeat seen from each sibling instance
+    peers: Mutex<HashMap<InstanceId, Instant>>,
+}
+
+impl Cluster {
+    /// Fails if the backend was not compiled in or its settings are invalid
+    pub fn new(settings: ClusterSettings) -> Result<Self, String> {
+        match &settings {
+            ClusterSettings::Redis(_) if !cfg!(feature = "redis") => {
+                return Err("built without the `redis` feature".to_string());
+            }
+            ClusterSettings::Redis(_) => {}
+            ClusterSettings::Nats(nats) => nats::validate(nats)?,
+        }
+
+        let mut instance_id: InstanceId = [0; ORIGIN_LEN];
+        SystemRandom::new()
+            .fill(&mut instance_id)
+            .map_err(|_| "failed to generate an instance id".to_string())?;
+
+        Ok(Self {
+            settings,
+            instance_id,
+            outbound: Mutex::new(None),
+            backend_task: Mutex::new(None),
+            peers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Spawn the backend and heartbeat tasks on the server runtime
+    pub(crate) fn start(&self, runtime: &Runtime, shared: Arc<Shared>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.outbound.lock() = Some(tx);
+
+        let task = match &self.settings {
+            #[cfg(feature = "redis")]
+            ClusterSettings::Redis(settings) => {
+                runtime.spawn(redis::run(Arc::clone(&shared), settings.clone(), rx))
+            }
+            #[cfg(not(feature = "redis"))]
+            ClusterSettings::Redis(_) => unreachable!("rejected by Cluster::new"),
+            ClusterSettings::Nats(settings) => {
+                runtime.spawn(nats::run(Arc::clone(&shared), settings.clone(), rx))
+            }
+        };
+        *self.backend_task.lock() = Some(task);
+
+        runtime.spawn(run_heartbeat(shared));
+    }
+
+    /// Announce that this instance is leaving and stop publishing.
+    /// Returns the backend task, which ends once the announcement is sent.
+    pub fn stop(&self) -> Option<JoinHandle<()>> {
+        self.publish(Channel::Presence, self.frame(KIND_LEAVING, 0));
+        self.outbound.lock().take();
+        self.peers.lock().clear();
+        self.backend_task.lock().take()
+    }
+
+    /// Publish a room broadcast to the sibling instances
+    pub fn publish_room(&self, room: &str, msg: &Message) {
+        self.publish_addressed(Channel::Rooms, [KIND_BINARY, KIND_TEXT], room, msg);
+    }
+
+    /// Publish a topic publication to the sibling instances
+    pub fn publish_topic(&self, topic: &str, msg: &Message) {
+        self.publish_addressed(
+            Channel::Topics,
+            [KIND_TOPIC_BINARY, KIND_TOPIC_TEXT],
+            topic,
+            msg,
+        );
+    }
+
+    /// Publish a message addressed to a room or topic. `kinds` are the binary and text kinds.
+    fn publish_addressed(&self, channel: Channel, kinds: [u8; 2], name: &str, msg: &Message) {
+        let (kind, payload): (u8, &[u8]) = match msg {
+            Message::Binary(data) => (kinds[0], data),
+            Message::Text(text) => (kinds[1], text.as_bytes()),
+            _ => return,
+        };
+        let Ok(name_len) = u16::try_from(name.len()) else {
+            tracing::warn!("Name too long to publish to the cluster: {}", name);
+            return;
+        };
+
+        let mut frame = self.frame(kind, 2 + name.len() + payload.len());
+        frame.extend_from_slice(&name_len.to_le_bytes());
+        frame.extend_from_slice(name.as_bytes());
+        frame.extend_from_slice(payload);
+        self.publish(channel, frame);
+    }
+
+    /// Announce that a user's first connection to this instance was added,
+    /// or its last one removed
+    pub fn publish_presence(&self, user_id: &str, online: bool) {
+        let kind = if online {
+            KIND_USER_ONLINE
+        } else {
+            KIND_USER_OFFLINE
+        };
+        let mut frame = self.frame(kind, user_id.len());
+        frame.extend_from_slice(user_id.as_bytes());
+        self.publish(Channel::Presence, frame);
+    }
+
+    /// Start a frame of `kind` with room for a body of `body_len` bytes
+    fn frame(&self, kind: u8, body_len: usize) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_LEN + body_len);
+        frame.extend_from_slice(&self.instance_id);
+        frame.push(kind);
+        frame
+    }
+
+    fn publish(&self, channel: Channel, frame: Vec<u8>) {
+        if let Some(outbound) = self.outbound.lock().as_ref() {
+            let _ = outbound.send((channel, frame));
+        }
+    }
+
+    fn seen(&self, shared: &Shared, origin: InstanceId) {
+        let joined = self.peers.lock().insert(origin, Instant::now()).is_none();
+        if joined {
+            tracing::info!("Cluster instance {} joined", hex(&origin));
+
+            // The sibling may not know our users, or may have forgotten them
+            // after timing us out; catch up in both directions
+            self.announce_users(shared);
+            self.publish(Channel::Presence, self.frame(KIND_SYNC, 0));
+        }
+    }
+
+    /// Announce every user connected to this instance
+    fn announce_users(&self, shared: &Shared) {
+        let users: Vec<String> = shared
+            .presence
+            .lock()
+            .local_users()
+            .map(str::to_string)
+            .collect();
+        for user_id in users {
+            self.publish_presence(&user_id, true);
+        }
+    }
+
+    fn left(&self, shared: &Shared, origin: &InstanceId) {
+        if self.peers.lock().remove(origin).is_some() {
+            tracing::info!("Cluster instance {} left", hex(origin));
+        }
+        remove_instance(shared, origin);
+    }
+}
+
+/// Take the users of a sibling instance offline
+fn remove_instance(shared: &Shared, origin: &InstanceId) {
+    let left = shared.presence.lock().remove_instance(origin);
+    shared.apply_presence(
+        0,
+        Changes {
+            left,
+            ..Changes::default()
+        },
+    );
+}
+
+/// Deliver a frame received from the backend
+pub(crate) fn deliver(shared: &Shared, frame: &[u8]) {
+    let Some(cluster) = &shared.cluster else {
+        return;
+    };
+    if frame.len() < HEADER_LEN {
+        return;
+    }
+    let origin: InstanceId = frame[..ORIGIN_LEN].try_into().unwrap();
+    if origin == cluster.instance_id {
+        return;
+    }
+
+    let kind = frame[ORIGIN_LEN];
+    if kind == KIND_LEAVING {
+        cluster.left(shared, &origin);
+        return;
+    }
+
+    // Any traffic shows the sibling is alive
+    cluster.seen(shared, origin);
+    match kind {
+        KIND_BINARY | KIND_TEXT => {
+            if let Some((room, msg)) = parse_addressed(frame, kind == KIND_TEXT) {
+                shared.send_to_room(room, &msg);
+            }
+        }
+        KIND_TOPIC_BINARY | KIND_TOPIC_TEXT => {
+            if let Some((topic, msg)) = parse_addressed(frame, kind == KIND_TOPIC_TEXT) {
+                shared.send_to_topic(topic, &msg);
+            }
+        }
+        KIND_SYNC => cluster.announce_users(shared),
+        KIND_USER_ONLINE | KIND_USER_OFFLINE => {
+            if let Ok(user_id) = std::str::from_utf8(&frame[HEADER_LEN..]) {
+                deliver_user(shared, origin, kind == KIND_USER_ONLINE, user_id);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn deliver_user(shared: &Shared, origin: InstanceId, online: bool, user_id: &str) {
+    let mut presence = shared.presence.lock();
+    let (changed, event_type) = if online {
+        (
+            presence.remote_joined(origin, user_id),
+            DwebbleWSEventType::PresenceJoined,
+        )
+    } else {
+        (
+            presence.remote_left(&origin, user_id),
+            DwebbleWSEventType::PresenceLeft,
+        )
+    };
+    drop(presence);
+
+    if changed {
+        shared.push_presence(event_type, 0, user_id.to_string());
+    }
+}
+
+/// Split a room or topic frame into its name and message
+fn parse_addressed(frame: &[u8], text: bool) -> Option<(&str, Message)> {
+    if frame.len() < ADDRESSED_HEADER_LEN {
+        return None;
+    }
+    let name_len = u16::from_le_bytes([frame[HEADER_LEN], frame[HEADER_LEN + 1]]) as usize;
+    let name = frame.get(ADDRESSED_HEADER_LEN..ADDRESSED_HEADER_LEN + name_len)?;
+    let name = std::str::from_utf8(name).ok()?;
+    let payload = &frame[ADDRESSED_HEADER_LEN + name_len..];
+
+    let msg = if text {
+        Message::Text(utf8::str(payload)?.into())
+    } else {
+        Message::Binary(payload.to_vec().into())
+    };
+    Some((name, msg))
+}
+
+/// Announce this instance periodically and forget siblings that went silent.
+/// Runs until the runtime shuts down.
+async fn run_heartbeat(shared: Arc<Shared>) {
+    let Some(cluster) = &shared.cluster else {
+        return;
+    };
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        cluster.publish(Channel::Presence, cluster.frame(KIND_HEARTBEAT, 0));
+
+        let now = Instant::now();
+        let mut timed_out = Vec::new();
+        cluster.peers.lock().retain(|origin, last_seen| {
+            let alive = now.duration_since(*last_seen) < PEER_TIMEOUT;
+            if !alive {
+                tracing::warn!("Cluster instance {} timed out", hex(origin));
+                timed_out.push(*origin);
+            }
+            alive
+        });
+        for origin in timed_out {
+            remove_instance(&shared, &origin);
+        }
+    }
+}
+
+/// Report a backend failure to the game (connection id 0)
+fn report_error(shared: &Shared, error: String) {
+    tracing::error!("{}", error);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::Error,
+        connection_id: 0,
+        data: None,
+        error: Some(error),
+        request_id: 0,
+    });
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,284 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! NATS core cluster backend
+//!
+//! Speaks the NATS client protocol directly over TCP, so it needs no extra
+//! dependency. TLS connections to the NATS server are not supported.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::{Channel, Outbound};
+use crate::server::Shared;
+use crate::settings::NatsClusterSettings;
+
+/// Delay before reconnecting after the connection to NATS is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Port used when the URL has none
+const DEFAULT_PORT: u16 = 4222;
+
+/// Longest protocol line accepted from the server
+const MAX_LINE_LEN: u64 = 64 * 1024;
+
+/// Where and how to connect, parsed from the settings URL
+struct Endpoint {
+    address: String,
+    user: Option<String>,
+    pass: Option<String>,
+    token: Option<String>,
+}
+
+impl Endpoint {
+    /// Parse `nats://[user:password@ | token@]host[:port]`
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("nats://")
+            .ok_or_else(|| format!("NATS URL must start with nats://: {}", url))?
+            .trim_end_matches('/');
+        let (credentials, host) = match rest.rsplit_once('@') {
+            Some((credentials, host)) => (Some(credentials), host),
+            None => (None, rest),
+        };
+        if host.is_empty() {
+            return Err(format!("NATS URL has no host: {}", url));
+        }
+
+        // A trailing `:port` unless the colon belongs to a bracketed IPv6 address
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if !port.ends_with(']') => {
+                port.parse::<u16>()
+                    .map_err(|_| format!("Invalid port in NATS URL: {}", url))?;
+                host.to_string()
+            }
+            _ => format!("{}:{}", host, DEFAULT_PORT),
+        };
+
+        let (user, pass, token) = match credentials {
+            Some(credentials) => match credentials.split_once(':') {
+                Some((user, pass)) => (Some(user.to_string()), Some(pass.to_string()), None),
+                None => (None, None, Some(credentials.to_string())),
+            },
+            None => (None, None, None),
+        };
+
+        Ok(Self {
+            address,
+            user,
+            pass,
+            token,
+        })
+    }
+}
+
+/// Check the settings before the server is created
+pub fn validate(settings: &NatsClusterSettings) -> Result<(), String> {
+    Endpoint::parse(&settings.url)?;
+    let prefix = &settings.subject_prefix;
+    if prefix.is_empty() || prefix.contains(|c: char| c.is_whitespace() || c == '*' || c == '>') {
+        return Err(format!("Invalid NATS subject prefix: {:?}", prefix));
+    }
+    Ok(())
+}
+
+/// Publish outbound frames and deliver frames from sibling instances,
+/// reconnecting whenever the connection drops. Runs until the runtime shuts down.
+pub async fn run(
+    shared: Arc<Shared>,
+    settings: NatsClusterSettings,
+    mut outbound: mpsc::UnboundedReceiver<Outbound>,
+) {
+    let endpoint = match Endpoint::parse(&settings.url) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            super::report_error(&shared, e);
+            return;
+        }
+    };
+    let mut reported = false;
+
+    loop {
+        let result = session(&shared, &settings, &endpoint, &mut outbound, &mut reported);
+        match result.await {
+            Ok(()) => return,
+            Err(e) => {
+                // Report once per outage rather than on every retry
+                if !reported {
+                    super::report_error(&shared, format!("NATS cluster backend error: {}", e));
+                    reported = true;
+                }
+            }
+        }
+
+        // Broadcasts made while disconnected are lost
+        tokio::time::sleep(RECONNECT_DELAY).await;
+        while outbound.try_recv().is_ok() {}
+        if outbound.is_closed() {
+            return;
+        }
+    }
+}
+
+/// One connected period. Returns Ok once the cluster is stopped.
+async fn session(
+    shared: &Shared,
+    settings: &NatsClusterSettings,
+    endpoint: &Endpoint,
+    outbound: &mut mpsc::UnboundedReceiver<Outbound>,
+    reported: &mut bool,
+) -> io::Result<()> {
+    let stream = TcpStream::connect(&endpoint.address).await?;
+    let _ = stream.set_nodelay(true);
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+
+    let line = read_line(&mut read).await?;
+    let info = line
+        .strip_prefix("INFO ")
+        .ok_or_else(|| unexpected(&line))?;
+    let info: serde_json::Value =
+        serde_json::from_str(info).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if info["tls_required"].as_bool() == Some(true) {
+        return Err(io::Error::other(
+            "server requires TLS, which is not supported",
+        ));
+    }
+
+    let mut connect = json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": "dwebble-rws",
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol": 1,
+    });
+    if let Some(user) = &endpoint.user {
+        connect["user"] = json!(user);
+    }
+    if let Some(pass) = &endpoint.pass {
+        connect["pass"] = json!(pass);
+    }
+    if let Some(token) = &endpoint.token {
+        connect["auth_token"] = json!(token);
+    }
+    write
+        .write_all(format!("CONNECT {}\r\nPING\r\n", connect).as_bytes())
+        .await?;
+
+    // The PONG confirms the server accepted CONNECT
+    loop {
+        let line = read_line(&mut read).await?;
+        match line.split_ascii_whitespace().next() {
+            Some("PONG") => break,
+            Some("PING") => write.write_all(b"PONG\r\n").await?,
+            Some("-ERR") => return Err(io::Error::other(line)),
+            _ => {}
+        }
+    }
+
+    for (sid, channel) in Channel::ALL.into_iter().enumerate() {
+        let sub = format!("SUB {} {}\r\n", subject(settings, channel), sid + 1);
+        write.write_all(sub.as_bytes()).await?;
+    }
+    tracing::info!("Joined NATS cluster at {}", endpoint.address);
+    *reported = false;
+
+    // Reading and writing run side by side; the reader hands PINGs to the writer
+    let (pong_tx, pong_rx) = mpsc::unbounded_channel();
+    tokio::select! {
+        result = receive(shared, &mut read, &pong_tx) => result,
+        result = send(&mut write, settings, outbound, pong_rx) => result,
+    }
+}
+
+/// Deliver published frames until the connection fails
+async fn receive(
+    shared: &Shared,
+    read: &mut BufReader<OwnedReadHalf>,
+    pong: &mpsc::UnboundedSender<()>,
+) -> io::Result<()> {
+    loop {
+        let line = read_line(read).await?;
+        let mut parts = line.split_ascii_whitespace();
+        match parts.next() {
+            Some("MSG") => {
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                let len = parts
+                    .last()
+                    .and_then(|len| len.parse::<usize>().ok())
+                    .ok_or_else(|| unexpected(&line))?;
+                let mut payload = vec![0; len + 2];
+                read.read_exact(&mut payload).await?;
+                payload.truncate(len);
+                super::deliver(shared, &payload);
+            }
+            Some("PING") => {
+                let _ = pong.send(());
+            }
+            Some("-ERR") => return Err(io::Error::other(line)),
+            // +OK, PONG and INFO updates need no action
+            _ => {}
+        }
+    }
+}
+
+/// Publish outbound frames and answer PINGs. Returns Ok once the cluster is stopped.
+async fn send(
+    write: &mut OwnedWriteHalf,
+    settings: &NatsClusterSettings,
+    outbound: &mut mpsc::UnboundedReceiver<Outbound>,
+    mut pongs: mpsc::UnboundedReceiver<()>,
+) -> io::Result<()> {
+    loop {
+        tokio::select! {
+            next = outbound.recv() => {
+                let Some((channel, frame)) = next else {
+                    return Ok(());
+                };
+                let header = format!("PUB {} {}\r\n", subject(settings, channel), frame.len());
+                let mut buf = Vec::with_capacity(header.len() + frame.len() + 2);
+                buf.extend_from_slice(header.as_bytes());
+                buf.extend_from_slice(&frame);
+                buf.extend_from_slice(b"\r\n");
+                write.write_all(&buf).await?;
+            }
+            Some(()) = pongs.recv() => write.write_all(b"PONG\r\n").await?,
+        }
+    }
+}
+
+fn subject(settings: &NatsClusterSettings, channel: Channel) -> String {
+    format!("{}.{}", settings.subject_prefix, channel.name())
+}
+
+/// Read one protocol line without its line ending
+async fn read_line(read: &mut BufReader<OwnedReadHalf>) -> io::Result<String> {
+    let mut line = String::new();
+    if (&mut *read).take(MAX_LINE_LEN).read_line(&mut line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "protocol line too long",
+        ));
+    }
+    line.truncate(line.trim_end().len());
+    Ok(line)
+}
+
+fn unexpected(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected protocol line: {}", line),
+    )
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Redis pub/sub cluster backend
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use tokio::sync::mpsc;
+
+use super::{Channel, Outbound};
+use crate::server::Shared;
+use crate::settings::RedisClusterSettings;
+
+/// Delay before reconnecting after the connection to Redis is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Publish outbound frames and deliver frames from sibling instances,
+/// reconnecting whenever the connection drops. Runs until the runtime shuts down.
+pub async fn run(
+    shared: Arc<Shared>,
+    settings: RedisClusterSettings,
+    mut outbound: mpsc::UnboundedReceiver<Outbound>,
+) {
+    let mut reported = false;
+
+    loop {
+        let result = session(&shared, &settings, &mut outbound, &mut reported);
+        match result.await {
+            Ok(()) => return,
+            Err(e) => {
+                // Report once per outage rather than on every retry
+                if !reported {
+                    super::report_error(&shared, format!("Redis cluster backend error: {}", e));
+                    reported = true;
+                }
+            }
+        }
+
+        // Broadcasts made while disconnected are lost
+        tokio::time::sleep(RECONNECT_DELAY).await;
+        while outbound.try_recv().is_ok() {}
+        if outbound.is_closed() {
+            return;
+        }
+    }
+}
+
+/// One connected period. Returns Ok once the cluster is stopped.
+async fn session(
+    shared: &Shared,
+    settings: &RedisClusterSettings,
+    outbound: &mut mpsc::UnboundedReceiver<Outbound>,
+    reported: &mut bool,
+) -> redis::RedisResult<()> {
+    let channel_name = |channel: Channel| format!("{}{}", settings.channel_prefix, channel.name());
+
+    let client = redis::Client::open(settings.url.as_str())?;
+    let mut publisher = client.get_multiplexed_async_connection().await?;
+    let mut pubsub = client.get_async_pubsub().await?;
+    for channel in Channel::ALL {
+        pubsub.subscribe(channel_name(channel)).await?;
+    }
+    tracing::info!("Joined Redis cluster channels {}*", settings.channel_prefix);
+    *reported = false;
+
+    let mut messages = pubsub.on_message();
+    loop {
+        tokio::select! {
+            next = outbound.recv() => {
+                let Some((channel, frame)) = next else {
+                    return Ok(());
+                };
+                publisher.publish::<_, _, ()>(channel_name(channel), frame).await?;
+            }
+            msg = messages.next() => {
+                let Some(msg) = msg else {
+                    return Err((redis::ErrorKind::Io, "subscription closed").into());
+                };
+                super::deliver(shared, msg.get_payload_bytes());
+            }
+        }
+    }
+}
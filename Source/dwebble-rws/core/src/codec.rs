@@ -0,0 +1,157 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Typed message envelopes in MessagePack or CBOR
+//!
+//! An envelope is a two-element array of the message type ID (unsigned integer
+//! up to u32) and the payload (byte string), so clients can use any standard
+//! MessagePack or CBOR library:
+//!
+//! ```text
+//! [type_id, payload]
+//! ```
+//!
+//! Integers and lengths may use any valid width when decoding; encoding uses
+//! the shortest one.
+
+use crate::settings::TypedCodec;
+
+/// Wrap a payload in an envelope
+pub fn encode(codec: TypedCodec, type_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 16);
+    match codec {
+        TypedCodec::Msgpack => {
+            data.push(0x92);
+            match type_id {
+                0..=0x7f => data.push(type_id as u8),
+                0x80..=0xff => data.extend_from_slice(&[0xcc, type_id as u8]),
+                0x100..=0xffff => {
+                    data.push(0xcd);
+                    data.extend_from_slice(&(type_id as u16).to_be_bytes());
+                }
+                _ => {
+                    data.push(0xce);
+                    data.extend_from_slice(&type_id.to_be_bytes());
+                }
+            }
+            match payload.len() {
+                len @ 0..=0xff => data.extend_from_slice(&[0xc4, len as u8]),
+                len @ 0x100..=0xffff => {
+                    data.push(0xc5);
+                    data.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+                len => {
+                    data.push(0xc6);
+                    data.extend_from_slice(&(len as u32).to_be_bytes());
+                }
+            }
+        }
+        TypedCodec::Cbor => {
+            data.push(0x82);
+            cbor_head(&mut data, 0, u64::from(type_id));
+            cbor_head(&mut data, 2, payload.len() as u64);
+        }
+    }
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Unwrap an envelope. Returns None unless `data` is exactly one valid envelope.
+pub fn decode(codec: TypedCodec, data: &[u8]) -> Option<(u32, &[u8])> {
+    let mut reader = Reader { data };
+    let (type_id, len) = match codec {
+        TypedCodec::Msgpack => {
+            if reader.byte()? != 0x92 {
+                return None;
+            }
+            let type_id = match reader.byte()? {
+                b @ 0x00..=0x7f => u64::from(b),
+                0xcc => reader.uint(1)?,
+                0xcd => reader.uint(2)?,
+                0xce => reader.uint(4)?,
+                0xcf => reader.uint(8)?,
+                _ => return None,
+            };
+            let len = match reader.byte()? {
+                0xc4 => reader.uint(1)?,
+                0xc5 => reader.uint(2)?,
+                0xc6 => reader.uint(4)?,
+                _ => return None,
+            };
+            (type_id, len)
+        }
+        TypedCodec::Cbor => {
+            if reader.byte()? != 0x82 {
+                return None;
+            }
+            (reader.cbor_head(0)?, reader.cbor_head(2)?)
+        }
+    };
+
+    let type_id = u32::try_from(type_id).ok()?;
+    if reader.data.len() as u64 != len {
+        return None;
+    }
+    Some((type_id, reader.data))
+}
+
+/// Write a CBOR initial byte and argument
+fn cbor_head(data: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => data.push(major | value as u8),
+        24..=0xff => data.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            data.push(major | 25);
+            data.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            data.push(major | 26);
+            data.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            data.push(major | 27);
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(byte)
+    }
+
+    /// Read a big-endian unsigned integer of `width` bytes
+    fn uint(&mut self, width: usize) -> Option<u64> {
+        if self.data.len() < width {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(width);
+        self.data = rest;
+        Some(bytes.iter().fold(0, |value, &b| value << 8 | u64::from(b)))
+    }
+
+    /// Read a CBOR item head of the given major type, returning its argument
+    fn cbor_head(&mut self, major: u8) -> Option<u64> {
+        let initial = self.byte()?;
+        if initial >> 5 != major {
+            return None;
+        }
+        match initial & 0x1f {
+            info @ 0..=23 => Some(u64::from(info)),
+            24 => self.uint(1),
+            25 => self.uint(2),
+            26 => self.uint(4),
+            27 => self.uint(8),
+            // Indefinite lengths and reserved values
+            _ => None,
+        }
+    }
+}
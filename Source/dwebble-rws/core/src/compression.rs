@@ -0,0 +1,286 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Message compression negotiated per connection
+//!
+//! Each connection counts the payload bytes of its data messages before and after
+//! compression in either direction, so the bandwidth saved can be weighed against
+//! the CPU spent. Connections without compression count both the same.
+//!
+//! With the `zstd` setting, a client asks for compression by appending the
+//! configured suffix to a supported subprotocol, e.g. `game.v1+zstd`. Every text
+//! and binary message in either direction is then sent as a binary message:
+//!
+//! | field   | type |
+//! |---------|------|
+//! | flags   | u8: bit 0 set if the payload is compressed, bit 1 set for text |
+//! | payload | remaining bytes: a zstd frame with its content size, or the message as is |
+//!
+//! Messages below `min_size`, and those that don't shrink, go out uncompressed.
+//! Both sides compress with the dictionary the host set before the handshake, if
+//! any. Compression applies after signing and before encryption on the way out.
+//!
+//! A dictionary can be trained in the library from sample payloads, e.g. messages
+//! of recorded traffic, and exported as a blob to ship with clients.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "zstd")]
+use std::sync::Arc;
+
+#[cfg(feature = "zstd")]
+use parking_lot::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::types::DwebbleWSCompression;
+#[cfg(feature = "zstd")]
+use crate::utf8;
+
+/// Close code for messages that cannot be decompressed (invalid frame payload data)
+pub const INVALID_PAYLOAD_CLOSE_CODE: u16 = 1007;
+
+/// Flag of a compressed payload
+#[cfg(feature = "zstd")]
+const COMPRESSED: u8 = 1 << 0;
+/// Flag of a text message
+#[cfg(feature = "zstd")]
+const TEXT: u8 = 1 << 1;
+
+/// Decompressed size allowed without a `max_message_size`, tungstenite's default limit
+#[cfg(feature = "zstd")]
+const DEFAULT_MAX_SIZE: usize = 64 << 20;
+
+/// A zstd dictionary set by the host
+#[cfg(feature = "zstd")]
+pub struct Dictionary {
+    pub data: Vec<u8>,
+    /// ID in the dictionary's header, 0 for raw content
+    id: u32,
+}
+
+#[cfg(feature = "zstd")]
+impl Dictionary {
+    pub fn new(data: Vec<u8>) -> Self {
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&data).map_or(0, |id| id.get());
+        Self { data, id }
+    }
+}
+
+/// Collects sample payloads and trains a zstd dictionary from them
+#[cfg(feature = "zstd")]
+#[derive(Default)]
+pub struct DictionaryTrainer {
+    /// Samples back to back
+    samples: Vec<u8>,
+    sizes: Vec<usize>,
+}
+
+#[cfg(feature = "zstd")]
+impl DictionaryTrainer {
+    pub fn add_sample(&mut self, sample: &[u8]) {
+        self.samples.extend_from_slice(sample);
+        self.sizes.push(sample.len());
+    }
+
+    /// Train a dictionary of up to `max_size` bytes from the samples so far. Fails
+    /// with too few samples, or too little content in them, to train on.
+    pub fn train(&self, max_size: usize) -> std::io::Result<Vec<u8>> {
+        zstd::dict::from_continuous(&self.samples, &self.sizes, max_size)
+    }
+}
+
+/// zstd compression negotiated for a connection
+#[cfg(feature = "zstd")]
+pub struct Zstd {
+    pub level: i32,
+    /// Smallest payload worth compressing, in bytes
+    pub min_size: usize,
+    /// Largest payload a received message may decompress to (0 for the default)
+    pub max_size: usize,
+    pub dictionary: Option<Arc<Dictionary>>,
+}
+
+/// Compression of a connection's messages and its byte counters
+#[derive(Default)]
+pub struct Compression {
+    #[cfg(feature = "zstd")]
+    zstd: Mutex<Option<Arc<Zstd>>>,
+    raw_sent: AtomicU64,
+    compressed_sent: AtomicU64,
+    raw_received: AtomicU64,
+    compressed_received: AtomicU64,
+}
+
+impl Compression {
+    /// Compress the connection's messages with zstd from now on
+    #[cfg(feature = "zstd")]
+    pub fn set_zstd(&self, zstd: Zstd) {
+        *self.zstd.lock() = Some(Arc::new(zstd));
+    }
+
+    pub fn mode(&self) -> DwebbleWSCompression {
+        #[cfg(feature = "zstd")]
+        if self.zstd.lock().is_some() {
+            return DwebbleWSCompression::Zstd;
+        }
+        DwebbleWSCompression::None
+    }
+
+    /// Compression level and dictionary ID, 0 without compression or a dictionary
+    pub fn parameters(&self) -> (i32, u32) {
+        #[cfg(feature = "zstd")]
+        if let Some(zstd) = self.zstd.lock().as_ref() {
+            let dictionary_id = zstd.dictionary.as_ref().map_or(0, |d| d.id);
+            return (zstd.level, dictionary_id);
+        }
+        (0, 0)
+    }
+
+    /// Encoder for the connection's writer
+    pub fn encoder(&self) -> Encoder {
+        Encoder {
+            #[cfg(feature = "zstd")]
+            zstd: self.zstd.lock().clone().map(|zstd| {
+                let dictionary = zstd.dictionary.as_ref().map_or(&[][..], |d| &d.data[..]);
+                let compressor = zstd::bulk::Compressor::with_dictionary(zstd.level, dictionary)
+                    .inspect_err(|e| tracing::error!("Failed to create zstd compressor: {}", e))
+                    .ok();
+                (zstd, compressor)
+            }),
+        }
+    }
+
+    /// Decoder for the connection's reader
+    pub fn decoder(&self) -> Decoder {
+        Decoder {
+            #[cfg(feature = "zstd")]
+            zstd: self.zstd.lock().clone().map(|zstd| {
+                let dictionary = zstd.dictionary.as_ref().map_or(&[][..], |d| &d.data[..]);
+                let decompressor = zstd::bulk::Decompressor::with_dictionary(dictionary)
+                    .inspect_err(|e| tracing::error!("Failed to create zstd decompressor: {}", e))
+                    .ok();
+                (zstd, decompressor)
+            }),
+        }
+    }
+
+    /// Count a data message sent, `raw` bytes before compression and `compressed` after
+    pub fn sent(&self, raw: usize, compressed: usize) {
+        self.raw_sent.fetch_add(raw as u64, Ordering::Relaxed);
+        self.compressed_sent
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+
+    /// Count a data message received, `compressed` bytes as read and `raw` once
+    /// decompressed
+    pub fn received(&self, compressed: usize, raw: usize) {
+        self.compressed_received
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+        self.raw_received.fetch_add(raw as u64, Ordering::Relaxed);
+    }
+
+    /// Bytes sent before and after compression
+    pub fn sent_bytes(&self) -> (u64, u64) {
+        (
+            self.raw_sent.load(Ordering::Relaxed),
+            self.compressed_sent.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Bytes received before and after decompression
+    pub fn received_bytes(&self) -> (u64, u64) {
+        (
+            self.compressed_received.load(Ordering::Relaxed),
+            self.raw_received.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Compresses the data messages a connection's writer sends
+pub struct Encoder {
+    #[cfg(feature = "zstd")]
+    zstd: Option<(Arc<Zstd>, Option<zstd::bulk::Compressor<'static>>)>,
+}
+
+impl Encoder {
+    /// Compress a data message; other messages pass as they are
+    pub fn encode(&mut self, msg: Message) -> Message {
+        #[cfg(feature = "zstd")]
+        if let Some((zstd, compressor)) = &mut self.zstd {
+            let (flags, payload) = match &msg {
+                Message::Text(text) => (TEXT, text.as_bytes()),
+                Message::Binary(data) => (0, &data[..]),
+                _ => return msg,
+            };
+            let compressed = compressor
+                .as_mut()
+                .filter(|_| payload.len() >= zstd.min_size)
+                .and_then(|compressor| compressor.compress(payload).ok())
+                .filter(|compressed| compressed.len() < payload.len());
+            let (flags, payload) = match &compressed {
+                Some(compressed) => (flags | COMPRESSED, &compressed[..]),
+                None => (flags, payload),
+            };
+
+            let mut frame = Vec::with_capacity(1 + payload.len());
+            frame.push(flags);
+            frame.extend_from_slice(payload);
+            return Message::Binary(frame.into());
+        }
+        msg
+    }
+}
+
+/// Decompresses the data messages a connection's reader receives
+pub struct Decoder {
+    #[cfg(feature = "zstd")]
+    zstd: Option<(Arc<Zstd>, Option<zstd::bulk::Decompressor<'static>>)>,
+}
+
+impl Decoder {
+    /// Decompress a data message; other messages pass as they are. Fails if the
+    /// message is not a valid compressed message.
+    pub fn decode(&mut self, msg: Message) -> Result<Message, String> {
+        #[cfg(feature = "zstd")]
+        if let Some((zstd, decompressor)) = &mut self.zstd {
+            let frame = match &msg {
+                Message::Binary(data) => &data[..],
+                Message::Text(_) => return Err("text message not in a frame".to_string()),
+                _ => return Ok(msg),
+            };
+            let Some((&flags, payload)) = frame.split_first() else {
+                return Err("empty frame".to_string());
+            };
+
+            let payload = if flags & COMPRESSED == 0 {
+                payload.to_vec()
+            } else {
+                let Some(decompressor) = decompressor else {
+                    return Err("no decompressor".to_string());
+                };
+                let max_size = match zstd.max_size {
+                    0 => DEFAULT_MAX_SIZE,
+                    max_size => max_size,
+                };
+                let size = zstd::zstd_safe::get_frame_content_size(payload)
+                    .map_err(|_| "invalid zstd frame".to_string())?
+                    .ok_or("zstd frame without a content size")?;
+                if size > max_size as u64 {
+                    return Err(format!("decompresses to {} bytes, over {}", size, max_size));
+                }
+                decompressor
+                    .decompress(payload, size as usize)
+                    .map_err(|e| e.to_string())?
+            };
+
+            return if flags & TEXT == 0 {
+                Ok(Message::Binary(payload.into()))
+            } else {
+                utf8::string(payload)
+                    .map(|text| Message::Text(text.into()))
+                    .ok_or_else(|| "text is not valid UTF-8".to_string())
+            };
+        }
+        Ok(msg)
+    }
+}
@@ -0,0 +1,524 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebSocket connection management
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::compression::Compression;
+use crate::fingerprint::Fingerprint;
+use crate::migration::Migration;
+use crate::settings::NetworkSimSettings;
+use crate::version::Version;
+use crate::watchdog;
+
+/// Unique connection ID generator
+static CONNECTION_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_connection_id() -> u64 {
+    CONNECTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Whether a connection ID was ever handed out
+pub fn is_issued(connection_id: u64) -> bool {
+    connection_id != 0 && connection_id < CONNECTION_ID_COUNTER.load(Ordering::Relaxed)
+}
+
+/// An entry of a connection's send queue
+pub enum Queued {
+    Message(Message),
+    /// A message sent under a dedupe key with its sequence, skipped if a later message
+    /// was sent under the key before the writer got to it
+    Keyed(String, u64),
+    /// A message dropped if the writer hasn't got to it by the deadline
+    Expiring(Message, Instant),
+}
+
+/// How a data message is queued
+#[derive(Debug, Clone, Copy)]
+pub enum Queueing<'a> {
+    /// In order, until written
+    Plain,
+    /// Replacing the message queued under the same dedupe key if that hasn't been sent
+    Keyed(&'a str),
+    /// Dropped if not sent by the deadline
+    Expiring(Instant),
+}
+
+/// Close code for connections not authenticated in time (policy violation)
+pub const AUTHENTICATION_CLOSE_CODE: u16 = 1008;
+
+/// Messages of a new connection held back until the host acknowledges it
+enum Hold {
+    /// Messages are delivered as they arrive
+    Off,
+    /// Waiting for the acknowledgement, pausing reads once `limit` messages are held
+    Holding { messages: Vec<Message>, limit: usize },
+    /// Acknowledged, with the held messages the reader has yet to deliver
+    Released(Vec<Message>),
+}
+
+/// Represents a single WebSocket connection
+pub struct Connection {
+    pub id: u64,
+    pub remote_addr: String,
+    pub subprotocol: Option<String>,
+    pub tx: mpsc::UnboundedSender<Queued>,
+    /// Payload bytes queued but not yet written to the socket
+    pending_bytes: AtomicUsize,
+    /// When the writer last wrote or the send queue last filled, in `watchdog::now_ms`
+    progress_ms: AtomicU64,
+    /// Latest message queued under each dedupe key, with its sequence
+    keyed: Mutex<HashMap<String, (u64, Message)>>,
+    keyed_sequence: AtomicU64,
+    /// Messages dropped from the send queue past their deadline
+    expired: AtomicU64,
+    /// When the send queue first exceeded the slow-client threshold
+    pub over_limit_since: Mutex<Option<Instant>>,
+    /// Close frame and reason recorded by a server-side termination
+    termination: Mutex<Option<(CloseFrame, String)>>,
+    terminated: Notify,
+    closed_by_server: AtomicBool,
+    /// Reason the connection was dropped without a closing handshake
+    severed: Mutex<Option<String>>,
+    /// Simulated network conditions overriding the server-wide setting
+    pub network_sim: Mutex<Option<NetworkSimSettings>>,
+    /// The writer holds back queued messages until this time
+    stalled_until: Mutex<Option<tokio::time::Instant>>,
+    /// Fingerprints of the client taken during the handshake
+    pub fingerprint: Fingerprint,
+    /// Whether the current socket completed an application-layer key exchange
+    encrypted: AtomicBool,
+    rekey: Notify,
+    /// Whether a key rotation is under way
+    rekeying: AtomicBool,
+    key_rotations: AtomicU64,
+    /// Compression of the connection's messages and its byte counters
+    pub compression: Compression,
+    /// The reader stops taking frames off the socket while set
+    read_paused: AtomicBool,
+    read_toggled: Notify,
+    hold: Mutex<Hold>,
+    authenticated: AtomicBool,
+    /// Messages the reader may still read before the connection is authenticated
+    login_messages: AtomicUsize,
+    /// Whether the client's next message is read as its protocol version offer
+    awaiting_version: AtomicBool,
+    /// Protocol version negotiated with the client
+    version: Mutex<Option<Version>>,
+    /// Pending request to move the connection to another server
+    migration: Mutex<Option<Migration>>,
+    migrating: Notify,
+}
+
+impl Connection {
+    pub fn new(
+        remote_addr: String,
+        subprotocol: Option<String>,
+        tx: mpsc::UnboundedSender<Queued>,
+    ) -> Self {
+        Self::with_id(next_connection_id(), remote_addr, subprotocol, tx)
+    }
+
+    /// Create a connection reusing an existing logical id (session resume)
+    pub fn with_id(
+        id: u64,
+        remote_addr: String,
+        subprotocol: Option<String>,
+        tx: mpsc::UnboundedSender<Queued>,
+    ) -> Self {
+        Self {
+            id,
+            remote_addr,
+            subprotocol,
+            tx,
+            pending_bytes: AtomicUsize::new(0),
+            progress_ms: AtomicU64::new(watchdog::now_ms()),
+            keyed: Mutex::new(HashMap::new()),
+            keyed_sequence: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            over_limit_since: Mutex::new(None),
+            termination: Mutex::new(None),
+            terminated: Notify::new(),
+            closed_by_server: AtomicBool::new(false),
+            severed: Mutex::new(None),
+            network_sim: Mutex::new(None),
+            stalled_until: Mutex::new(None),
+            fingerprint: Fingerprint::default(),
+            encrypted: AtomicBool::new(false),
+            rekey: Notify::new(),
+            rekeying: AtomicBool::new(false),
+            key_rotations: AtomicU64::new(0),
+            compression: Compression::default(),
+            read_paused: AtomicBool::new(false),
+            read_toggled: Notify::new(),
+            hold: Mutex::new(Hold::Off),
+            authenticated: AtomicBool::new(true),
+            login_messages: AtomicUsize::new(0),
+            awaiting_version: AtomicBool::new(false),
+            version: Mutex::new(None),
+            migration: Mutex::new(None),
+            migrating: Notify::new(),
+        }
+    }
+
+    pub fn close(&self) {
+        self.closed_by_server.store(true, Ordering::Relaxed);
+        self.queue(Message::Close(None));
+    }
+
+    pub fn close_with(&self, code: u16, reason: &str) {
+        self.closed_by_server.store(true, Ordering::Relaxed);
+        self.queue(Message::Close(Some(close_frame(code, reason))));
+    }
+
+    /// Whether the server initiated closing this connection
+    pub fn closed_by_server(&self) -> bool {
+        self.closed_by_server.load(Ordering::Relaxed)
+    }
+
+    pub fn queue(&self, msg: Message) -> bool {
+        self.push(msg.len(), Queued::Message(msg))
+    }
+
+    /// Queue a message the writer drops if it hasn't got to it by `deadline`
+    pub fn queue_expiring(&self, msg: Message, deadline: Instant) -> bool {
+        self.push(msg.len(), Queued::Expiring(msg, deadline))
+    }
+
+    fn push(&self, len: usize, queued: Queued) -> bool {
+        if self.pending_bytes.fetch_add(len, Ordering::Relaxed) == 0 {
+            self.progress_ms.store(watchdog::now_ms(), Ordering::Relaxed);
+        }
+        if self.tx.send(queued).is_ok() {
+            true
+        } else {
+            self.pending_bytes.fetch_sub(len, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Queue a message under a dedupe key, replacing the message queued under it
+    /// earlier if the writer hasn't taken that one yet
+    pub fn queue_keyed(&self, key: &str, msg: Message) -> bool {
+        let len = msg.len();
+        let sequence = self.keyed_sequence.fetch_add(1, Ordering::Relaxed);
+        let mut keyed = self.keyed.lock();
+        self.pending_bytes.fetch_add(len, Ordering::Relaxed);
+        if self.tx.send(Queued::Keyed(key.to_string(), sequence)).is_err() {
+            self.pending_bytes.fetch_sub(len, Ordering::Relaxed);
+            return false;
+        }
+        // The new message goes out at its own place at the back of the queue, so it
+        // stays in order with messages queued in between, e.g. for signing counters
+        if let Some((_, replaced)) = keyed.insert(key.to_string(), (sequence, msg)) {
+            self.pending_bytes.fetch_sub(replaced.len(), Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Payload bytes of the message queued under a dedupe key, 0 if there is none
+    pub fn keyed_len(&self, key: &str) -> usize {
+        self.keyed.lock().get(key).map_or(0, |(_, msg)| msg.len())
+    }
+
+    /// Called by the writer for each send queue entry. Returns the message to write,
+    /// or `None` if a later message replaced it or it expired.
+    pub fn dequeue(&self, queued: Queued) -> Option<Message> {
+        match queued {
+            Queued::Message(msg) => Some(msg),
+            Queued::Expiring(msg, deadline) => {
+                if Instant::now() < deadline {
+                    return Some(msg);
+                }
+                self.pending_bytes.fetch_sub(msg.len(), Ordering::Relaxed);
+                self.expired.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Queued::Keyed(key, sequence) => {
+                let mut keyed = self.keyed.lock();
+                if keyed.get(&key).is_some_and(|(latest, _)| *latest == sequence) {
+                    keyed.remove(&key).map(|(_, msg)| msg)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Called by the writer once a queued message of `len` bytes has been written
+    pub fn mark_written_len(&self, len: usize) {
+        self.pending_bytes.fetch_sub(len, Ordering::Relaxed);
+        self.progress_ms.store(watchdog::now_ms(), Ordering::Relaxed);
+    }
+
+    /// When the writer last wrote, or the send queue last filled, in `watchdog::now_ms`
+    pub fn progress_ms(&self) -> u64 {
+        self.progress_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Messages dropped from the send queue past their deadline
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    pub fn set_encrypted(&self) {
+        self.encrypted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.encrypted.load(Ordering::Relaxed)
+    }
+
+    /// Ask the connection's reader to rotate the encryption keys. A request made
+    /// during a rotation starts another once it ends.
+    pub fn rekey(&self) {
+        self.rekey.notify_one();
+    }
+
+    /// Resolves with the next call to `rekey`
+    pub async fn rekey_requested(&self) {
+        self.rekey.notified().await
+    }
+
+    pub fn set_rekeying(&self) {
+        self.rekeying.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a key rotation is under way
+    pub fn rekeying(&self) -> bool {
+        self.rekeying.load(Ordering::Relaxed)
+    }
+
+    /// End a key rotation, returning the number of rotations so far
+    pub fn rekeyed(&self) -> u64 {
+        self.rekeying.store(false, Ordering::Relaxed);
+        self.key_rotations.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn key_rotations(&self) -> u64 {
+        self.key_rotations.load(Ordering::Relaxed)
+    }
+
+    /// Tear the connection down without waiting for the send queue to drain.
+    /// The first termination wins; later calls are ignored.
+    pub fn terminate(&self, code: u16, reason: &str) {
+        let mut termination = self.termination.lock();
+        if termination.is_none() {
+            self.closed_by_server.store(true, Ordering::Relaxed);
+            *termination = Some((close_frame(code, reason), reason.to_string()));
+            self.terminated.notify_one();
+        }
+    }
+
+    /// Drop the connection without a closing handshake, as if the network failed.
+    /// Sessions are suspended as for any abnormal drop.
+    pub fn sever(&self, reason: &str) {
+        let mut severed = self.severed.lock();
+        if severed.is_none() {
+            *severed = Some(reason.to_string());
+            self.terminated.notify_one();
+        }
+    }
+
+    /// Reason given to `sever`, if the connection was severed
+    pub fn severed(&self) -> Option<String> {
+        self.severed.lock().clone()
+    }
+
+    /// Pause writing until `until` (`None` to resume immediately)
+    pub fn set_stall(&self, until: Option<tokio::time::Instant>) {
+        *self.stalled_until.lock() = until;
+    }
+
+    /// When a stalled writer may resume, if it is stalled
+    pub fn stalled_until(&self) -> Option<tokio::time::Instant> {
+        self.stalled_until
+            .lock()
+            .filter(|until| *until > tokio::time::Instant::now())
+    }
+
+    /// Resolves once `terminate` or `sever` has been called
+    pub async fn terminated(&self) {
+        self.terminated.notified().await
+    }
+
+    /// Stop or resume reading frames. Unread data stays in the socket buffers, so a
+    /// client that keeps sending is eventually held back by TCP flow control.
+    pub fn set_read_paused(&self, paused: bool) {
+        self.read_paused.store(paused, Ordering::Relaxed);
+        self.read_toggled.notify_one();
+    }
+
+    pub fn read_paused(&self) -> bool {
+        self.read_paused.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once reading may have been paused or resumed
+    pub async fn read_toggled(&self) {
+        self.read_toggled.notified().await
+    }
+
+    /// Hold the connection's messages until `acknowledge`, pausing reads once `limit`
+    /// messages are held
+    pub fn start_holding(&self, limit: usize) {
+        *self.hold.lock() = Hold::Holding {
+            messages: Vec::new(),
+            limit,
+        };
+    }
+
+    /// Hold `msg` if messages are held, behind any the reader has yet to deliver, or
+    /// give it back for delivery
+    pub fn hold(&self, msg: Message) -> Option<Message> {
+        match &mut *self.hold.lock() {
+            Hold::Off => Some(msg),
+            Hold::Holding { messages, .. } | Hold::Released(messages) => {
+                messages.push(msg);
+                None
+            }
+        }
+    }
+
+    /// Whether reads wait for the acknowledgement, with as many messages held as allowed
+    pub fn hold_full(&self) -> bool {
+        match &*self.hold.lock() {
+            Hold::Holding { messages, limit } => messages.len() >= *limit,
+            _ => false,
+        }
+    }
+
+    /// Release the held messages to the reader. Returns false if none were held.
+    pub fn acknowledge(&self) -> bool {
+        let mut hold = self.hold.lock();
+        let Hold::Holding { messages, .. } = &mut *hold else {
+            return false;
+        };
+        *hold = Hold::Released(std::mem::take(messages));
+        self.read_toggled.notify_one();
+        true
+    }
+
+    /// Take the messages released by `acknowledge`, or every held message if `all`,
+    /// e.g. once the client is gone
+    pub fn take_held(&self, all: bool) -> Vec<Message> {
+        let mut hold = self.hold.lock();
+        match std::mem::replace(&mut *hold, Hold::Off) {
+            Hold::Released(messages) => messages,
+            Hold::Holding { messages, .. } if all => messages,
+            holding => {
+                *hold = holding;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Pause reads after `login_messages` messages until `authenticate`
+    pub fn require_authentication(&self, login_messages: usize) {
+        self.login_messages.store(login_messages, Ordering::Relaxed);
+        self.authenticated.store(false, Ordering::Relaxed);
+    }
+
+    pub fn authenticate(&self) {
+        self.authenticated.store(true, Ordering::Relaxed);
+        self.read_toggled.notify_one();
+    }
+
+    pub fn authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    /// Count a message read before authentication against the login messages
+    pub fn read_unauthenticated(&self) {
+        if !self.authenticated() {
+            let _ = self.login_messages.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                n.checked_sub(1)
+            });
+        }
+    }
+
+    /// Whether reads wait for authentication, with every login message read
+    pub fn awaiting_authentication(&self) -> bool {
+        !self.authenticated() && self.login_messages.load(Ordering::Relaxed) == 0
+    }
+
+    /// Read the client's next message as its protocol version offer
+    pub fn expect_version(&self) {
+        self.awaiting_version.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a message is the client's version offer, clearing the expectation
+    pub fn take_version_offer(&self) -> bool {
+        self.awaiting_version.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn set_version(&self, version: Version) {
+        *self.version.lock() = Some(version);
+    }
+
+    /// Protocol version negotiated with the client, if any
+    pub fn version(&self) -> Option<Version> {
+        *self.version.lock()
+    }
+
+    /// Ask the connection's reader to hand it over to another server. Gives the
+    /// request back if another migration is already pending.
+    pub fn migrate(&self, migration: Migration) -> Result<(), Migration> {
+        let mut pending = self.migration.lock();
+        if pending.is_some() {
+            return Err(migration);
+        }
+        *pending = Some(migration);
+        self.migrating.notify_one();
+        Ok(())
+    }
+
+    /// Resolves with the next request passed to `migrate`
+    pub async fn migrating(&self) -> Migration {
+        loop {
+            self.migrating.notified().await;
+            if let Some(migration) = self.migration.lock().take() {
+                return migration;
+            }
+        }
+    }
+
+    /// Close frame to send and disconnect reason, if the server terminated the connection
+    pub fn termination(&self) -> Option<(CloseFrame, String)> {
+        self.termination.lock().clone()
+    }
+}
+
+/// Longest close reason, in bytes, that fits a control frame after the code
+const MAX_CLOSE_REASON: usize = 123;
+
+/// Whether a server may send `code` in a Close frame: not below 1000, not reserved
+/// (1004-1006, 1015, 1016-2999) and not past 4999
+pub fn is_sendable_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Close frame with `reason` cut to `MAX_CLOSE_REASON` bytes on a char boundary
+fn close_frame(code: u16, reason: &str) -> CloseFrame {
+    let mut len = reason.len().min(MAX_CLOSE_REASON);
+    while !reason.is_char_boundary(len) {
+        len -= 1;
+    }
+    CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason[..len].to_string().into(),
+    }
+}
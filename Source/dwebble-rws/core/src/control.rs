@@ -0,0 +1,98 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Control frames of the library's own protocol features
+//!
+//! Binary messages starting with `DW` and two uppercase ASCII letters are the
+//! library's. The four bytes are the frame's opcode:
+//!
+//! | opcode | frame |
+//! |--------|-------|
+//! | `DWCH`, `DWCA` | virtual channel message and acknowledgement |
+//! | `DWST`, `DWSA` | state sync update and acknowledgement |
+//! | `DWSQ`, `DWSK` | sequenced send and delivery receipt |
+//! | `DWRQ`, `DWRS` | request and response |
+//! | `DWKX`, `DWEN` | encryption key exchange and encrypted message |
+//! | `DWNC` | replay protection envelope |
+//! | `DWSN` | signed message |
+//! | `DWRT` | round trip time probe (ping payload) |
+//! | `DWVN` | protocol version offer and answer |
+//! | `DWES` | escaped application payload |
+//!
+//! Each feature takes its own frames while it is on. With the `control_frames`
+//! setting, the range is reserved: frames no feature took, of a feature that is off
+//! or of opcodes this version doesn't know, are dropped instead of raised, and an
+//! application payload that happens to start like a control frame is sent behind
+//! `DWES`, which the receiving side strips before raising it. Without it, application
+//! payloads pass through as they are. Text messages are never control frames.
+
+/// First two bytes of every control frame
+const PREFIX: &[u8; 2] = b"DW";
+
+pub const CHANNEL_MESSAGE: &[u8; 4] = b"DWCH";
+pub const CHANNEL_ACK: &[u8; 4] = b"DWCA";
+pub const STATE_UPDATE: &[u8; 4] = b"DWST";
+pub const STATE_ACK: &[u8; 4] = b"DWSA";
+pub const SEQUENCED: &[u8; 4] = b"DWSQ";
+pub const RECEIPT: &[u8; 4] = b"DWSK";
+pub const REQUEST: &[u8; 4] = b"DWRQ";
+pub const RESPONSE: &[u8; 4] = b"DWRS";
+pub const KEY_EXCHANGE: &[u8; 4] = b"DWKX";
+pub const ENCRYPTED: &[u8; 4] = b"DWEN";
+pub const NONCE: &[u8; 4] = b"DWNC";
+pub const SIGNED: &[u8; 4] = b"DWSN";
+pub const RTT_PROBE: &[u8; 4] = b"DWRT";
+pub const VERSION: &[u8; 4] = b"DWVN";
+pub const ESCAPE: &[u8; 4] = b"DWES";
+
+/// Whether a binary payload is in the reserved range of control frames
+pub fn is_control(data: &[u8]) -> bool {
+    data.len() >= ESCAPE.len()
+        && data.starts_with(PREFIX)
+        && data[PREFIX.len()..ESCAPE.len()].iter().all(u8::is_ascii_uppercase)
+}
+
+/// An application payload as sent, behind `DWES` if it would pass for a control frame
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    if !is_control(data) {
+        return data.to_vec();
+    }
+    let mut escaped = Vec::with_capacity(ESCAPE.len() + data.len());
+    escaped.extend_from_slice(ESCAPE);
+    escaped.extend_from_slice(data);
+    escaped
+}
+
+/// The application payload of a control frame, if it is an escaped one
+pub fn unescape(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(ESCAPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn application_payloads_pass_through() {
+        for data in [&b""[..], b"DW", b"DWab", b"DW1X", b"hello"] {
+            assert!(!is_control(data));
+            assert_eq!(escape(data), data);
+        }
+    }
+
+    #[test]
+    fn payloads_like_control_frames_round_trip() {
+        for data in [&b"DWCH"[..], b"DWZZpayload", ESCAPE] {
+            assert!(is_control(data));
+            let escaped = escape(data);
+            assert!(escaped.starts_with(ESCAPE));
+            assert_eq!(unescape(&escaped), Some(data));
+        }
+    }
+
+    #[test]
+    fn other_frames_are_not_escaped_ones() {
+        assert_eq!(unescape(b"DWCHpayload"), None);
+    }
+}
@@ -0,0 +1,276 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Delta-compressed state sync
+//!
+//! The host sends keyed state blobs to a connection. Each update is a binary
+//! message carrying either the full state or a delta against a version the
+//! client acknowledged (little-endian):
+//!
+//! | field   | type |
+//! |---------|------|
+//! | magic   | 4 bytes: `DWST` for updates, `DWSA` for acknowledgements |
+//! | key     | u32  |
+//! | version | u32: counts up from 1 per key |
+//! | base    | u32: version the delta applies to, 0 for a full state (updates only) |
+//! | payload | full state or delta (updates only) |
+//!
+//! Clients acknowledge every version they apply with `DWSA`, key and version.
+//! A delta starts with the new length (u32) followed by runs until the end,
+//! each a LEB128 count of bytes kept from the base since the previous run, a
+//! LEB128 length and that many new bytes. Bytes after the last run come from
+//! the base up to the new length.
+//!
+//! Deltas are only sent when smaller than the state itself, and a full state
+//! goes out periodically so clients that lost their copy recover.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::control;
+use crate::settings::DeltaSettings;
+
+const UPDATE_MAGIC: &[u8; 4] = control::STATE_UPDATE;
+const ACK_MAGIC: &[u8; 4] = control::STATE_ACK;
+const UPDATE_HEADER_LEN: usize = 16;
+const ACK_LEN: usize = 12;
+
+/// Equal bytes that end a run; shorter gaps cost less inside the run than a new one
+const MERGE_GAP: usize = 4;
+
+/// Sent versions per key remembered until acknowledged
+const MAX_UNACKED_VERSIONS: usize = 64;
+
+/// Applied versions per key a client keeps as delta bases
+const MAX_BASES: usize = 64;
+
+/// Delta turning `base` into `new`
+pub fn encode(base: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(new.len() as u32).to_le_bytes());
+
+    let same = |pos: usize| pos < base.len() && base[pos] == new[pos];
+    let mut pos = 0;
+    let mut copied_to = 0;
+    while pos < new.len() {
+        if same(pos) {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos;
+        let mut end = pos;
+        let mut gap = 0;
+        while pos < new.len() && gap < MERGE_GAP {
+            if same(pos) {
+                gap += 1;
+            } else {
+                gap = 0;
+                end = pos + 1;
+            }
+            pos += 1;
+        }
+
+        write_varint(&mut delta, start - copied_to);
+        write_varint(&mut delta, end - start);
+        delta.extend_from_slice(&new[start..end]);
+        copied_to = end;
+        pos = end;
+    }
+    delta
+}
+
+/// Apply a delta to `base`. Returns `None` if it is malformed or does not fit the base.
+pub fn apply(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let new_len = u32::from_le_bytes(delta.get(..4)?.try_into().ok()?) as usize;
+    let mut rest = &delta[4..];
+    let mut state = Vec::with_capacity(new_len.min(base.len() + rest.len()));
+
+    while !rest.is_empty() {
+        let kept = read_varint(&mut rest)?;
+        let len = read_varint(&mut rest)?;
+        state.extend_from_slice(base.get(state.len()..state.len().checked_add(kept)?)?);
+        state.extend_from_slice(rest.get(..len)?);
+        rest = &rest[len..];
+    }
+    if state.len() < new_len {
+        state.extend_from_slice(base.get(state.len()..new_len)?);
+    }
+    (state.len() == new_len).then_some(state)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= usize::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn update_header(key: u32, version: u32, base: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(UPDATE_HEADER_LEN);
+    data.extend_from_slice(UPDATE_MAGIC);
+    data.extend_from_slice(&key.to_le_bytes());
+    data.extend_from_slice(&version.to_le_bytes());
+    data.extend_from_slice(&base.to_le_bytes());
+    data
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[derive(Default)]
+struct Sent {
+    version: u32,
+    /// Updates since the last full state
+    since_full: u32,
+    /// Latest version the client acknowledged
+    acked: Option<(u32, Vec<u8>)>,
+    /// Versions sent after it, oldest first
+    unacked: VecDeque<(u32, Vec<u8>)>,
+}
+
+/// Versions of every connection's state sent by the server
+#[derive(Default)]
+pub struct States {
+    sent: HashMap<(u64, u32), Sent>,
+}
+
+impl States {
+    /// Wrap the next version of a state for `connection_id`
+    pub fn update(
+        &mut self,
+        settings: &DeltaSettings,
+        connection_id: u64,
+        key: u32,
+        state: &[u8],
+    ) -> Vec<u8> {
+        let sent = self.sent.entry((connection_id, key)).or_default();
+        sent.version = sent.version.wrapping_add(1).max(1);
+        let version = sent.version;
+
+        let due_full = settings.full_snapshot_interval > 0
+            && sent.since_full + 1 >= settings.full_snapshot_interval;
+        let delta = match &sent.acked {
+            Some((base, acked)) if !due_full => {
+                Some((*base, encode(acked, state))).filter(|(_, d)| d.len() < state.len())
+            }
+            _ => None,
+        };
+
+        let frame = match delta {
+            Some((base, delta)) => {
+                sent.since_full += 1;
+                let mut frame = update_header(key, version, base);
+                frame.extend_from_slice(&delta);
+                frame
+            }
+            None => {
+                sent.since_full = 0;
+                let mut frame = update_header(key, version, 0);
+                frame.extend_from_slice(state);
+                frame
+            }
+        };
+
+        sent.unacked.push_back((version, state.to_vec()));
+        if sent.unacked.len() > MAX_UNACKED_VERSIONS {
+            sent.unacked.pop_front();
+        }
+        frame
+    }
+
+    /// Handle an acknowledgement from `connection_id`. Returns false if `data` is not one.
+    pub fn ack(&mut self, connection_id: u64, data: &[u8]) -> bool {
+        if data.len() != ACK_LEN || &data[..4] != ACK_MAGIC {
+            return false;
+        }
+        let key = read_u32(data, 4);
+        let version = read_u32(data, 8);
+
+        if let Some(sent) = self.sent.get_mut(&(connection_id, key)) {
+            if let Some(index) = sent.unacked.iter().position(|(v, _)| *v == version) {
+                sent.acked = sent.unacked.drain(..=index).next_back();
+            }
+        }
+        true
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.sent.retain(|(id, _), _| *id != connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.sent.clear();
+    }
+}
+
+/// An applied state update
+pub struct Applied {
+    pub key: u32,
+    pub state: Vec<u8>,
+    /// Acknowledgement to send back
+    pub ack: Vec<u8>,
+}
+
+/// A client's copy of the server's states
+#[derive(Default)]
+pub struct Replica {
+    /// Recently applied versions per key, oldest first
+    bases: HashMap<u32, VecDeque<(u32, Vec<u8>)>>,
+}
+
+impl Replica {
+    /// Apply a state update. Returns `None` if `data` is not one, or an error
+    /// if its base version is unknown.
+    pub fn receive(&mut self, data: &[u8]) -> Option<Result<Applied, String>> {
+        if data.len() < UPDATE_HEADER_LEN || &data[..4] != UPDATE_MAGIC {
+            return None;
+        }
+        let key = read_u32(data, 4);
+        let version = read_u32(data, 8);
+        let base = read_u32(data, 12);
+        let payload = &data[UPDATE_HEADER_LEN..];
+
+        let bases = self.bases.entry(key).or_default();
+        let state = if base == 0 {
+            payload.to_vec()
+        } else {
+            let known = bases.iter().find(|(v, _)| *v == base);
+            match known.and_then(|(_, base_state)| apply(base_state, payload)) {
+                Some(state) => state,
+                None => {
+                    return Some(Err(format!(
+                        "Cannot apply version {} of state {}: base version {} unknown or delta malformed",
+                        version, key, base
+                    )))
+                }
+            }
+        };
+
+        bases.push_back((version, state.clone()));
+        if bases.len() > MAX_BASES {
+            bases.pop_front();
+        }
+
+        let mut ack = Vec::with_capacity(ACK_LEN);
+        ack.extend_from_slice(ACK_MAGIC);
+        ack.extend_from_slice(&key.to_le_bytes());
+        ack.extend_from_slice(&version.to_le_bytes());
+        Some(Ok(Applied { key, state, ack }))
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! LAN discovery over mDNS (DNS-SD)
+//!
+//! A host announces a service such as `mygame` as an instance of
+//! `_mygame._tcp.local.` on every interface, with its port and string metadata
+//! in the TXT record. Browsers of the same service name keep the list of
+//! instances currently on the network. The mDNS responder runs on its own
+//! thread, so neither side needs a running server, and is unavailable in
+//! `single-thread` builds.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// A service instance found on the network
+#[derive(Debug, Clone, Serialize)]
+pub struct Service {
+    /// Instance name, unique on the network
+    pub name: String,
+    pub host: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// DNS-SD service type of a service name
+fn service_type(service_name: &str) -> Result<String, mdns_sd::Error> {
+    let valid = (1..=15).contains(&service_name.len())
+        && service_name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !service_name.starts_with('-')
+        && !service_name.ends_with('-');
+    if !valid {
+        return Err(mdns_sd::Error::Msg(format!(
+            "Invalid service name '{}': expected 1-15 letters, digits and inner hyphens",
+            service_name
+        )));
+    }
+    Ok(format!("_{}._tcp.local.", service_name))
+}
+
+/// Start an mDNS responder on a thread of its own
+fn daemon() -> Result<ServiceDaemon, mdns_sd::Error> {
+    if cfg!(feature = "single-thread") {
+        return Err(mdns_sd::Error::Msg(
+            "LAN discovery unavailable: built with the `single-thread` feature".to_string(),
+        ));
+    }
+    ServiceDaemon::new()
+}
+
+/// An announced service, withdrawn when dropped
+pub struct Announcement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Announcement {
+    /// Announce `port` under `service_name` (1-15 letters, digits and hyphens)
+    pub fn start(
+        service_name: &str,
+        port: u16,
+        metadata: HashMap<String, String>,
+    ) -> Result<Self, mdns_sd::Error> {
+        let ty_domain = service_type(service_name)?;
+        // Instance names must be unique on the network, so several hosts on one
+        // machine each get their own
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let instance = format!("{}-{:x}-{:x}", service_name, std::process::id(), nanos);
+        let host_name = format!("{}.local.", instance);
+
+        let info = ServiceInfo::new(&ty_domain, &instance, &host_name, "", port, metadata)?
+            .enable_addr_auto();
+        let fullname = info.get_fullname().to_string();
+
+        let daemon = daemon()?;
+        if let Err(e) = daemon.register(info) {
+            let _ = daemon.shutdown();
+            return Err(e);
+        }
+        tracing::info!("Announcing {} on port {}", fullname, port);
+
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        // Goodbye packets tell browsers the service is gone right away
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+        tracing::info!("Withdrew {}", self.fullname);
+    }
+}
+
+/// Instances of a service on the network, kept up to date until dropped
+pub struct Browser {
+    daemon: ServiceDaemon,
+    services: Arc<Mutex<BTreeMap<String, Service>>>,
+}
+
+impl Browser {
+    pub fn start(service_name: &str) -> Result<Self, mdns_sd::Error> {
+        let ty_domain = service_type(service_name)?;
+        let daemon = daemon()?;
+        let events = match daemon.browse(&ty_domain) {
+            Ok(events) => events,
+            Err(e) => {
+                let _ = daemon.shutdown();
+                return Err(e);
+            }
+        };
+
+        let services = Arc::new(Mutex::new(BTreeMap::new()));
+        let found = Arc::clone(&services);
+        // Ends once the daemon shuts down and drops its end of the channel
+        std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let mut addresses: Vec<IpAddr> =
+                            info.get_addresses().iter().copied().collect();
+                        addresses.sort();
+                        let service = Service {
+                            name: instance_name(info.get_fullname(), info.get_type()),
+                            host: info.get_hostname().to_string(),
+                            addresses,
+                            port: info.get_port(),
+                            metadata: info
+                                .get_properties()
+                                .iter()
+                                .map(|p| (p.key().to_string(), p.val_str().to_string()))
+                                .collect(),
+                        };
+                        found
+                            .lock()
+                            .insert(info.get_fullname().to_string(), service);
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        found.lock().remove(&fullname);
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self { daemon, services })
+    }
+
+    /// Instances currently on the network, ordered by name
+    pub fn services(&self) -> Vec<Service> {
+        self.services.lock().values().cloned().collect()
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Instance part of a full service name
+fn instance_name(fullname: &str, ty_domain: &str) -> String {
+    fullname
+        .strip_suffix(ty_domain)
+        .map_or(fullname, |name| name.trim_end_matches('.'))
+        .to_string()
+}
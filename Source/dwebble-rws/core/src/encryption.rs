@@ -0,0 +1,252 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Application-layer encryption
+//!
+//! For deployments where TLS is not an option, WebSocket connections can
+//! encrypt their messages themselves. Once connected, the server sends its
+//! ephemeral X25519 public key and the client answers with its own, both as a
+//! binary message of the magic `DWKX` followed by the 32-byte key. Each side
+//! derives a key per direction from the shared secret with HKDF-SHA256, salted
+//! with the pre-shared key if one is configured. Every text and binary message
+//! after that is sent as a binary message (little-endian):
+//!
+//! | field      | type |
+//! |------------|------|
+//! | magic      | 4 bytes: `DWEN` |
+//! | counter    | u64: counts up from 0 per direction |
+//! | ciphertext | ChaCha20-Poly1305 of a kind byte and the payload, plus tag |
+//!
+//! The nonce is 4 zero bytes followed by the counter, and the magic and counter
+//! are authenticated with the message. Counters must increase, so replayed
+//! messages are rejected. Without a pre-shared key the exchange is not
+//! authenticated: it stops eavesdroppers, not an active man in the middle.
+//!
+//! Kinds 0 and 1 are binary and text messages. The server rotates the keys of a
+//! long session with a fresh exchange carried in encrypted messages:
+//!
+//! 1. The server sends a rekey message (kind 2) whose payload is its announcement
+//!    (`DWKX` and a new public key).
+//! 2. The client answers with a rekey message of its own announcement, and seals
+//!    everything after it with the new key.
+//! 3. The server opens everything after the answer with the new key, and sends a
+//!    rekeyed message (kind 3, no payload) as its last under the old key.
+//!
+//! Counters start from 0 again under the new keys.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{control, utf8};
+
+const EXCHANGE_MAGIC: &[u8; 4] = control::KEY_EXCHANGE;
+const MESSAGE_MAGIC: &[u8; 4] = control::ENCRYPTED;
+const PUBLIC_KEY_LEN: usize = 32;
+const HEADER_LEN: usize = 12;
+
+/// Close code of connections failing the key exchange or decryption
+pub const PROTOCOL_ERROR_CLOSE_CODE: u16 = 1002;
+
+const KIND_BINARY: u8 = 0;
+const KIND_TEXT: u8 = 1;
+const KIND_REKEY: u8 = 2;
+const KIND_REKEYED: u8 = 3;
+
+const SERVER_TO_CLIENT: &[u8] = b"dwebble server to client";
+const CLIENT_TO_SERVER: &[u8] = b"dwebble client to server";
+
+/// Side of the connection an exchange is for
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// A key exchange awaiting the peer's public key
+pub struct Exchange {
+    role: Role,
+    private_key: EphemeralPrivateKey,
+}
+
+impl Exchange {
+    /// Generate an ephemeral key pair, returning the message announcing the public key
+    pub fn start(role: Role) -> Result<(Self, Vec<u8>), String> {
+        let rng = SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| "key generation failed".to_string())?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| "key generation failed".to_string())?;
+
+        let mut hello = Vec::with_capacity(EXCHANGE_MAGIC.len() + PUBLIC_KEY_LEN);
+        hello.extend_from_slice(EXCHANGE_MAGIC);
+        hello.extend_from_slice(public_key.as_ref());
+        Ok((Self { role, private_key }, hello))
+    }
+
+    /// Derive the keys from the peer's announcement
+    pub fn finish(self, msg: &Message, psk: &[u8]) -> Result<(Sealer, Opener), String> {
+        let peer_key = match msg {
+            Message::Binary(data)
+                if data.len() == EXCHANGE_MAGIC.len() + PUBLIC_KEY_LEN
+                    && data.starts_with(EXCHANGE_MAGIC) =>
+            {
+                &data[EXCHANGE_MAGIC.len()..]
+            }
+            _ => return Err("expected a key exchange message".to_string()),
+        };
+
+        let (sealing, opening) = match self.role {
+            Role::Server => (SERVER_TO_CLIENT, CLIENT_TO_SERVER),
+            Role::Client => (CLIENT_TO_SERVER, SERVER_TO_CLIENT),
+        };
+        let derive = |secret: &[u8], info: &[u8]| {
+            let info = [info];
+            let prk = Salt::new(HKDF_SHA256, psk).extract(secret);
+            let okm = prk.expand(&info, &CHACHA20_POLY1305).ok()?;
+            Some(LessSafeKey::new(UnboundKey::from(okm)))
+        };
+        let keys = agreement::agree_ephemeral(
+            self.private_key,
+            &UnparsedPublicKey::new(&X25519, peer_key),
+            |secret| derive(secret, sealing).zip(derive(secret, opening)),
+        );
+        let Ok(Some((sealing, opening))) = keys else {
+            return Err("invalid public key".to_string());
+        };
+
+        Ok((
+            Sealer {
+                key: sealing,
+                counter: 0,
+            },
+            Opener {
+                key: opening,
+                next_counter: 0,
+            },
+        ))
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypts the messages sent in one direction
+pub struct Sealer {
+    key: LessSafeKey,
+    counter: u64,
+}
+
+impl Sealer {
+    /// Encrypt a text or binary message; other messages are returned unchanged
+    pub fn seal(&mut self, msg: Message) -> Message {
+        let (kind, payload): (u8, &[u8]) = match &msg {
+            Message::Binary(data) => (KIND_BINARY, data),
+            Message::Text(text) => (KIND_TEXT, text.as_bytes()),
+            _ => return msg,
+        };
+        self.seal_kind(kind, payload)
+    }
+
+    /// Encrypt a rekey message carrying an exchange's announcement
+    pub fn seal_rekey(&mut self, hello: &[u8]) -> Message {
+        self.seal_kind(KIND_REKEY, hello)
+    }
+
+    /// Encrypt the rekeyed message, to be the last sealed with this key
+    pub fn seal_rekeyed(&mut self) -> Message {
+        self.seal_kind(KIND_REKEYED, &[])
+    }
+
+    fn seal_kind(&mut self, kind: u8, payload: &[u8]) -> Message {
+        let mut frame = Vec::with_capacity(HEADER_LEN + 1 + payload.len() + 16);
+        frame.extend_from_slice(MESSAGE_MAGIC);
+        frame.extend_from_slice(&self.counter.to_le_bytes());
+        let mut sealed = Vec::with_capacity(1 + payload.len() + 16);
+        sealed.push(kind);
+        sealed.extend_from_slice(payload);
+        self.key
+            .seal_in_place_append_tag(nonce(self.counter), Aad::from(&frame[..]), &mut sealed)
+            .expect("message too large to encrypt");
+        frame.extend_from_slice(&sealed);
+
+        self.counter += 1;
+        Message::Binary(frame.into())
+    }
+}
+
+/// Encrypt `msg` if the connection's keys are established
+pub fn seal(sealer: &mut Option<Sealer>, msg: Message) -> Message {
+    match sealer {
+        Some(sealer) => sealer.seal(msg),
+        None => msg,
+    }
+}
+
+/// A decrypted message
+pub enum Opened {
+    Message(Message),
+    /// The peer's half of a key rotation, to finish an `Exchange` with
+    Rekey(Message),
+    /// The peer seals with its new key from the next message on
+    Rekeyed,
+}
+
+/// Decrypts the messages received from one direction
+pub struct Opener {
+    key: LessSafeKey,
+    next_counter: u64,
+}
+
+impl Opener {
+    /// Decrypt a text or binary message; other messages are returned unchanged.
+    /// Fails for messages that are not encrypted, were tampered with or replayed.
+    pub fn open(&mut self, msg: Message) -> Result<Opened, String> {
+        let data = match msg {
+            Message::Binary(data) => data,
+            Message::Text(_) => return Err("unencrypted text message".to_string()),
+            _ => return Ok(Opened::Message(msg)),
+        };
+        if data.len() < HEADER_LEN || &data[..4] != MESSAGE_MAGIC {
+            return Err("unencrypted binary message".to_string());
+        }
+
+        let counter = u64::from_le_bytes(data[4..HEADER_LEN].try_into().unwrap());
+        if counter < self.next_counter {
+            return Err(format!("replayed message (counter {})", counter));
+        }
+        let mut sealed = data[HEADER_LEN..].to_vec();
+        let opened = self
+            .key
+            .open_in_place(nonce(counter), Aad::from(&data[..HEADER_LEN]), &mut sealed)
+            .map_err(|_| "message failed authentication".to_string())?;
+        self.next_counter = counter + 1;
+
+        match opened.split_first() {
+            Some((&KIND_BINARY, payload)) => {
+                Ok(Opened::Message(Message::Binary(payload.to_vec().into())))
+            }
+            Some((&KIND_TEXT, payload)) => utf8::str(payload)
+                .map(|text| Opened::Message(Message::Text(text.into())))
+                .ok_or_else(|| "encrypted text message is not valid UTF-8".to_string()),
+            Some((&KIND_REKEY, hello)) => Ok(Opened::Rekey(Message::Binary(hello.to_vec().into()))),
+            Some((&KIND_REKEYED, [])) => Ok(Opened::Rekeyed),
+            _ => Err("unknown message kind".to_string()),
+        }
+    }
+}
+
+/// Decrypt `msg` if the connection's keys are established
+pub fn open(opener: &mut Option<Opener>, msg: Message) -> Result<Opened, String> {
+    match opener {
+        Some(opener) => opener.open(msg),
+        None => Ok(Opened::Message(msg)),
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Per-room event queues
+//!
+//! The host can open an event queue for a room and poll the events of the
+//! room's members on a thread of its own, e.g. one per match, instead of
+//! demultiplexing the server's queue. A connection's events go to the queue of
+//! a room it is in (the earliest opened if there are several) and to the
+//! server's queue otherwise. A connection stays bound to its queue until its
+//! `ClientDisconnected` event, so that event reaches the same queue.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+
+use crate::server::{QueuedEvent, ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+
+/// Open event queues and the connections bound to them
+#[derive(Default)]
+pub struct EventQueues {
+    next_id: u64,
+    /// Rooms and senders of the open queues, in the order they were opened
+    queues: BTreeMap<u64, (String, mpsc::UnboundedSender<QueuedEvent>)>,
+    bound: HashMap<u64, u64>,
+}
+
+impl EventQueues {
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    fn open(&mut self, room: &str) -> (u64, mpsc::UnboundedReceiver<QueuedEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.next_id += 1;
+        self.queues.insert(self.next_id, (room.to_string(), tx));
+        (self.next_id, rx)
+    }
+
+    /// Close a queue. Returns the connections that were bound to it.
+    fn close(&mut self, queue_id: u64) -> Vec<u64> {
+        self.queues.remove(&queue_id);
+        let unbound: Vec<u64> = self
+            .bound
+            .iter()
+            .filter(|(_, queue)| **queue == queue_id)
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        for connection_id in &unbound {
+            self.bound.remove(connection_id);
+        }
+        unbound
+    }
+
+    /// Open queues with their rooms, earliest first
+    pub fn rooms(&self) -> Vec<(u64, String)> {
+        self.queues
+            .iter()
+            .map(|(queue_id, (room, _))| (*queue_id, room.clone()))
+            .collect()
+    }
+
+    /// Route a connection's events to a queue, or to the server's with `None`
+    pub fn bind(&mut self, connection_id: u64, queue_id: Option<u64>) {
+        match queue_id {
+            Some(queue_id) => self.bound.insert(connection_id, queue_id),
+            None => self.bound.remove(&connection_id),
+        };
+    }
+
+    pub fn unbind_all(&mut self) {
+        self.bound.clear();
+    }
+
+    /// Deliver an event to the queue its connection is bound to. Gives the event back
+    /// if it belongs on the server's queue.
+    pub fn route(&mut self, queued: QueuedEvent) -> Result<(), QueuedEvent> {
+        let event = &queued.event;
+        let Some(&queue_id) = self.bound.get(&event.connection_id) else {
+            return Err(queued);
+        };
+        if event.event_type == DwebbleWSEventType::ClientDisconnected {
+            self.bound.remove(&event.connection_id);
+        }
+        match self.queues.get(&queue_id) {
+            Some((_, tx)) => tx.send(queued).map_err(|e| e.0),
+            None => Err(queued),
+        }
+    }
+}
+
+/// An open event queue, closed when dropped
+pub struct EventQueue {
+    id: u64,
+    shared: Arc<Shared>,
+    rx: Mutex<mpsc::UnboundedReceiver<QueuedEvent>>,
+}
+
+impl EventQueue {
+    /// Open a queue for the events of a room's members
+    pub(crate) fn open(shared: &Arc<Shared>, room: &str) -> Self {
+        let (id, rx) = shared.event_queues.lock().open(room);
+        let members = shared.rooms.lock().members(room);
+        for connection_id in members {
+            shared.bind_event_queue(connection_id);
+        }
+
+        Self {
+            id,
+            shared: Arc::clone(shared),
+            rx: Mutex::new(rx),
+        }
+    }
+
+    pub fn poll_event(&self) -> Option<ServerEvent> {
+        let queued = self.rx.lock().try_recv().ok()?;
+        Some(self.shared.polled(queued))
+    }
+
+    /// The checksum of a polled event's data, with the `checksum` setting (0 otherwise)
+    pub fn event_checksum(&self, event: &ServerEvent) -> u32 {
+        self.shared.event_checksum(event)
+    }
+}
+
+impl Drop for EventQueue {
+    fn drop(&mut self) {
+        let unbound = self.shared.event_queues.lock().close(self.id);
+        // Members of another room with a queue move to that queue
+        for connection_id in unbound {
+            self.shared.bind_event_queue(connection_id);
+        }
+    }
+}
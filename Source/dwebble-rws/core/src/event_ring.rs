@@ -0,0 +1,344 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! The server's event queue
+//!
+//! Events are pushed from the runtime's workers and polled from the game thread,
+//! often thousands a frame. A ring buffer of fixed capacity takes them without
+//! allocating or locking: a push claims a slot with one compare-and-swap and a
+//! poll takes it with another (Vyukov's bounded MPMC queue), and polling many at
+//! once takes the whole batch under one update of the backlog counters. Pushes
+//! never wait for the host, so events that don't fit while the ring is full go to
+//! an overflow list behind a lock, which then takes every event until the host
+//! has drained it. Each pusher's events are polled in the order it pushed them:
+//! the overflow is only polled once the ring is empty, not while a push into the
+//! ring is still writing its slot, and then only for the events it held before the
+//! ring was found empty. Those spilled since may follow events pushed into the ring
+//! meanwhile.
+//!
+//! The overflow holds a limited number of events. Pushes past it are refused and
+//! left to the caller to drop, except those forced in, which the server uses for
+//! the events a host can't do without.
+
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+/// Why the ring had no value to poll
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    /// Every push into the ring was polled
+    Empty,
+    /// The next value's push claimed its slot but hasn't written it yet
+    Pending,
+}
+
+struct Overflow<T> {
+    values: VecDeque<T>,
+    /// Values ever polled from the overflow
+    drained: usize,
+}
+
+/// An atomic on a cache line of its own, so pushers and the poller don't contend
+/// on one line for the two ends of the ring
+#[repr(align(64))]
+struct Padded(AtomicUsize);
+
+struct Slot<T> {
+    /// Position of the push the slot awaits while free, that position + 1 while full
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Bounded queue of many pushers and any number of pollers, with a bounded overflow
+pub struct EventRing<T> {
+    slots: Box<[Slot<T>]>,
+    /// Position of the next push
+    tail: Padded,
+    /// Position of the next poll
+    head: Padded,
+    overflow: Mutex<Overflow<T>>,
+    /// Values ever pushed into the overflow
+    spilled: AtomicUsize,
+    /// Values the overflow holds before pushes are refused (0 for no limit)
+    overflow_limit: usize,
+    /// The overflow holds events, which new events must queue behind
+    overflowing: AtomicBool,
+}
+
+// Values move between threads through slots only one thread owns at a time
+unsafe impl<T: Send> Send for EventRing<T> {}
+unsafe impl<T: Send> Sync for EventRing<T> {}
+
+impl<T> EventRing<T> {
+    /// A ring of at least `capacity` slots, rounded up to a power of two, and an
+    /// overflow of `overflow_limit` values (0 for no limit)
+    pub fn new(capacity: usize, overflow_limit: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        Self {
+            slots: (0..capacity)
+                .map(|seq| Slot {
+                    seq: AtomicUsize::new(seq),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            tail: Padded(AtomicUsize::new(0)),
+            head: Padded(AtomicUsize::new(0)),
+            overflow: Mutex::new(Overflow {
+                values: VecDeque::new(),
+                drained: 0,
+            }),
+            spilled: AtomicUsize::new(0),
+            overflow_limit,
+            overflowing: AtomicBool::new(false),
+        }
+    }
+
+    /// Push `value`, handing it back if the ring and overflow are both full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.push_with(value, false)
+    }
+
+    /// Push `value`, into the overflow past its limit if need be
+    pub fn force_push(&self, value: T) {
+        let _ = self.push_with(value, true);
+    }
+
+    fn push_with(&self, value: T, force: bool) -> Result<(), T> {
+        if self.overflowing.load(Ordering::Acquire) {
+            return self.spill(value, force);
+        }
+        match self.try_push(value) {
+            Ok(()) => Ok(()),
+            Err(value) => self.spill(value, force),
+        }
+    }
+
+    fn spill(&self, value: T, force: bool) -> Result<(), T> {
+        let mut overflow = self.overflow.lock();
+        if !force && self.overflow_limit > 0 && overflow.values.len() >= self.overflow_limit {
+            return Err(value);
+        }
+        overflow.values.push_back(value);
+        // Counted after this pusher's pushes into the ring, which a poll reading the
+        // count then finds in the ring
+        self.spilled.fetch_add(1, Ordering::Release);
+        // Set under the lock, so a poll emptying the overflow can't clear it after
+        self.overflowing.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn try_push(&self, value: T) -> Result<(), T> {
+        let mask = self.slots.len() - 1;
+        let mut pos = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match seq.wrapping_sub(pos) as isize {
+                0 => match self.tail.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds the value of a lap ago: the ring is full
+                lag if lag < 0 => return Err(value),
+                _ => pos = self.tail.0.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let spilled = self.spilled.load(Ordering::Acquire);
+        match self.try_pop() {
+            Ok(value) => return Some(value),
+            Err(Stop::Pending) => return None,
+            Err(Stop::Empty) => {}
+        }
+        if !self.overflowing.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut overflow = self.overflow.lock();
+        if spilled == overflow.drained {
+            return None;
+        }
+        let value = overflow.values.pop_front();
+        self.drained(&mut overflow, 1);
+        value
+    }
+
+    /// Move up to `max` values to `out`, taking the overflow's lock at most once
+    pub fn pop_into(&self, out: &mut Vec<T>, max: usize) {
+        let spilled = self.spilled.load(Ordering::Acquire);
+        let start = out.len();
+        let mut stop = Stop::Empty;
+        while out.len() - start < max {
+            match self.try_pop() {
+                Ok(value) => out.push(value),
+                Err(reason) => {
+                    stop = reason;
+                    break;
+                }
+            }
+        }
+        if out.len() - start == max
+            || stop == Stop::Pending
+            || !self.overflowing.load(Ordering::Acquire)
+        {
+            return;
+        }
+        let mut overflow = self.overflow.lock();
+        // Only those spilled before the ring was found empty
+        let ready = spilled.wrapping_sub(overflow.drained);
+        let take = ready.min(max - (out.len() - start));
+        out.extend(overflow.values.drain(..take));
+        self.drained(&mut overflow, take);
+    }
+
+    fn drained(&self, overflow: &mut Overflow<T>, count: usize) {
+        overflow.drained = overflow.drained.wrapping_add(count);
+        if overflow.values.is_empty() {
+            self.overflowing.store(false, Ordering::Release);
+        }
+    }
+
+    fn try_pop(&self) -> Result<T, Stop> {
+        let mask = self.slots.len() - 1;
+        let mut pos = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match seq.wrapping_sub(pos.wrapping_add(1)) as isize {
+                0 => match self.head.0.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        // Free for the push a lap ahead
+                        slot.seq
+                            .store(pos.wrapping_add(self.slots.len()), Ordering::Release);
+                        return Ok(value);
+                    }
+                    Err(current) => pos = current,
+                },
+                // Not yet pushed: the ring is empty, unless a push claimed the slot and
+                // is still writing it
+                lag if lag < 0 => {
+                    return Err(match self.tail.0.load(Ordering::Acquire) == pos {
+                        true => Stop::Empty,
+                        false => Stop::Pending,
+                    })
+                }
+                _ => pos = self.head.0.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+impl<T> Drop for EventRing<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    fn drain(ring: &EventRing<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| ring.pop()).collect()
+    }
+
+    #[test]
+    fn overflow_keeps_push_order() {
+        let ring = EventRing::new(2, 0);
+        for value in 0..5 {
+            ring.push(value).unwrap();
+        }
+        assert_eq!(ring.pop(), Some(0));
+        // The ring has room again, but the overflow still holds older events
+        ring.push(5).unwrap();
+        assert_eq!(drain(&ring), [1, 2, 3, 4, 5]);
+
+        ring.push(6).unwrap();
+        assert_eq!(drain(&ring), [6]);
+    }
+
+    #[test]
+    fn overflow_limit_refuses_pushes() {
+        let ring = EventRing::new(2, 2);
+        for value in 0..4 {
+            ring.push(value).unwrap();
+        }
+        assert_eq!(ring.push(4), Err(4));
+        ring.force_push(5);
+        assert_eq!(drain(&ring), [0, 1, 2, 3, 5]);
+        ring.push(6).unwrap();
+        assert_eq!(drain(&ring), [6]);
+    }
+
+    #[test]
+    fn pop_into_takes_at_most_max() {
+        let ring = EventRing::new(4, 0);
+        for value in 0..10 {
+            ring.push(value).unwrap();
+        }
+        let mut out = vec![];
+        ring.pop_into(&mut out, 3);
+        assert_eq!(out, [0, 1, 2]);
+        ring.pop_into(&mut out, 5);
+        assert_eq!(out, [0, 1, 2, 3, 4, 5, 6, 7]);
+        ring.pop_into(&mut out, 5);
+        assert_eq!(out, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn each_pusher_is_polled_in_order() {
+        const PUSHERS: u32 = 4;
+        const PUSHES: u32 = 20_000;
+        let ring = Arc::new(EventRing::new(64, 0));
+        let pushers: Vec<_> = (0..PUSHERS)
+            .map(|pusher| {
+                let ring = Arc::clone(&ring);
+                thread::spawn(move || {
+                    for i in 0..PUSHES {
+                        ring.push(pusher << 24 | i).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = [0; PUSHERS as usize];
+        let mut out = vec![];
+        while next.iter().any(|&n| n < PUSHES) {
+            out.clear();
+            ring.pop_into(&mut out, 100);
+            for value in &out {
+                let pusher = (value >> 24) as usize;
+                assert_eq!(value & 0xff_ffff, next[pusher]);
+                next[pusher] += 1;
+            }
+        }
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        assert_eq!(ring.pop(), None);
+    }
+}
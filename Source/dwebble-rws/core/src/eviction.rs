@@ -0,0 +1,50 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Slow-client eviction policy
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::server::Shared;
+
+/// How often send queues are inspected
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Periodically terminate connections whose send queue stayed above the
+/// configured threshold for too long. Runs until the runtime shuts down.
+pub async fn run(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(policy) = shared.settings.read().slow_client.clone() else {
+            continue;
+        };
+
+        let now = Instant::now();
+        let limit = Duration::from_millis(policy.duration_ms);
+
+        for conn in shared.connections.values() {
+            let mut since = conn.over_limit_since.lock();
+
+            if conn.pending_bytes() <= policy.max_queued_bytes {
+                *since = None;
+                continue;
+            }
+
+            let started = *since.get_or_insert(now);
+            if now.duration_since(started) >= limit {
+                tracing::warn!(
+                    "Evicting slow client {} (id: {}, {} bytes queued)",
+                    conn.remote_addr,
+                    conn.id,
+                    conn.pending_bytes()
+                );
+                conn.terminate(policy.close_code, &policy.close_reason);
+            }
+        }
+    }
+}
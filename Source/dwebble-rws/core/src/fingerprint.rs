@@ -0,0 +1,176 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Client fingerprints for anti-abuse tooling
+//!
+//! Two fingerprints are taken of each WebSocket client, so bot frameworks
+//! masquerading as the official client stand out even with the right headers:
+//!
+//! - With TLS, the JA3 hash of the ClientHello: the MD5 of the TLS version, cipher
+//!   suites, extensions, supported groups and point formats in the order sent,
+//!   GREASE values left out. It matches the hashes published for known clients.
+//! - The order of the handshake's request header names, which HTTP libraries keep
+//!   but rarely let callers change: the first 8 bytes (big-endian) of the SHA-256
+//!   of the lowercase names joined by commas.
+
+use ring::digest::{digest, SHA256};
+#[cfg(feature = "tls")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+
+/// Content type of TLS handshake records
+#[cfg(feature = "tls")]
+const HANDSHAKE: u8 = 22;
+/// Handshake type of a ClientHello
+#[cfg(feature = "tls")]
+const CLIENT_HELLO: u8 = 1;
+/// Largest TLS record accepted, plaintext with room for expansion
+#[cfg(feature = "tls")]
+const MAX_RECORD_LEN: usize = 16 * 1024 + 2048;
+
+#[cfg(feature = "tls")]
+const SUPPORTED_GROUPS: u16 = 10;
+#[cfg(feature = "tls")]
+const EC_POINT_FORMATS: u16 = 11;
+
+/// Fingerprints of a WebSocket client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fingerprint {
+    /// JA3 hash, all zero without TLS
+    pub tls: [u8; 16],
+    pub headers: u64,
+}
+
+/// Read the first TLS record off a stream, which should hold the ClientHello. A
+/// stream not starting with a handshake record yields what was read so far.
+#[cfg(feature = "tls")]
+pub async fn read_record<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Vec<u8>> {
+    let mut record = vec![0; 5];
+    stream.read_exact(&mut record).await?;
+    if record[0] != HANDSHAKE {
+        return Ok(record);
+    }
+
+    let len = usize::from(u16::from_be_bytes([record[3], record[4]]));
+    if len > MAX_RECORD_LEN {
+        return Ok(record);
+    }
+    record.resize(5 + len, 0);
+    stream.read_exact(&mut record[5..]).await?;
+    Ok(record)
+}
+
+/// JA3 hash of a record holding a ClientHello, `None` if it doesn't hold a whole one
+#[cfg(feature = "tls")]
+pub fn ja3(record: &[u8]) -> Option<[u8; 16]> {
+    use md5::{Digest, Md5};
+
+    let description = ja3_description(record)?;
+    Some(Md5::digest(description.as_bytes()).into())
+}
+
+/// The JA3 string of a ClientHello record, e.g. `771,4865-4866,0-23-65281,29-23,0`
+#[cfg(feature = "tls")]
+fn ja3_description(record: &[u8]) -> Option<String> {
+    let mut reader = Reader(record);
+    if reader.u8()? != HANDSHAKE {
+        return None;
+    }
+    reader.take(4)?;
+    if reader.u8()? != CLIENT_HELLO {
+        return None;
+    }
+    reader.take(3)?;
+    let version = reader.u16()?;
+    reader.take(32)?;
+    let session_id_len = reader.u8()?;
+    reader.take(usize::from(session_id_len))?;
+    let cipher_suites_len = reader.u16()?;
+    let cipher_suites = u16_list(reader.take(usize::from(cipher_suites_len))?);
+    let compression_len = reader.u8()?;
+    reader.take(usize::from(compression_len))?;
+
+    let mut extensions = Vec::new();
+    let mut groups = Vec::new();
+    let mut point_formats = Vec::new();
+    // ClientHellos without extensions end after the compression methods
+    if let Some(extensions_len) = reader.u16() {
+        let mut reader = Reader(reader.take(usize::from(extensions_len))?);
+        while !reader.0.is_empty() {
+            let extension = reader.u16()?;
+            let len = reader.u16()?;
+            let mut data = Reader(reader.take(usize::from(len))?);
+            if !is_grease(extension) {
+                extensions.push(extension);
+            }
+            match extension {
+                SUPPORTED_GROUPS => {
+                    let len = data.u16()?;
+                    groups = u16_list(data.take(usize::from(len))?);
+                }
+                EC_POINT_FORMATS => {
+                    let len = data.u8()?;
+                    point_formats = data.take(usize::from(len))?.to_vec();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let join = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<_>>().join("-");
+    Some(format!(
+        "{},{},{},{},{}",
+        version,
+        join(&mut cipher_suites.iter().map(u16::to_string)),
+        join(&mut extensions.iter().map(u16::to_string)),
+        join(&mut groups.iter().map(u16::to_string)),
+        join(&mut point_formats.iter().map(u8::to_string)),
+    ))
+}
+
+/// Big-endian values of a list, GREASE values left out
+#[cfg(feature = "tls")]
+fn u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .filter(|value| !is_grease(*value))
+        .collect()
+}
+
+/// Whether a value is one of the reserved GREASE values (RFC 8701), e.g. 0x1a1a
+#[cfg(feature = "tls")]
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Reads big-endian fields off the front of a slice
+#[cfg(feature = "tls")]
+struct Reader<'a>(&'a [u8]);
+
+#[cfg(feature = "tls")]
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Fingerprint of the order of a request's header names
+pub fn header_order(headers: &HeaderMap) -> u64 {
+    let names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+    let hash = digest(&SHA256, names.join(",").as_bytes());
+    u64::from_be_bytes(hash.as_ref()[..8].try_into().unwrap())
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Replay protection
+//!
+//! With replay protection enabled, every text and binary message in either
+//! direction is sent as a binary message (little-endian):
+//!
+//! | field     | type |
+//! |-----------|------|
+//! | magic     | 4 bytes: `DWNC` |
+//! | nonce     | u64: random per message |
+//! | timestamp | u64: the sender's Unix time in milliseconds |
+//! | kind      | u8: 0 binary, 1 text |
+//! | payload   | remaining bytes |
+//!
+//! The receiver rejects messages whose timestamp is further than the
+//! acceptance window from its own clock, and messages repeating a nonce it
+//! accepted within the window, so both clocks must agree to within it. The
+//! envelope is added before signing: without a signing key or encryption, a
+//! man in the middle can still rewrite it.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::rand::{self, SystemRandom};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{control, utf8};
+
+const MAGIC: &[u8; 4] = control::NONCE;
+const HEADER_LEN: usize = 21;
+
+const KIND_BINARY: u8 = 0;
+const KIND_TEXT: u8 = 1;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Wrap a text or binary message in a fresh envelope; other messages are returned
+/// unchanged
+pub fn stamp(msg: Message) -> Message {
+    let (kind, payload): (u8, &[u8]) = match &msg {
+        Message::Binary(data) => (KIND_BINARY, data),
+        Message::Text(text) => (KIND_TEXT, text.as_bytes()),
+        _ => return msg,
+    };
+    let nonce: [u8; 8] = rand::generate(&SystemRandom::new())
+        .map(|nonce| nonce.expose())
+        .expect("random nonce generation failed");
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(MAGIC);
+    frame.extend_from_slice(&nonce);
+    frame.extend_from_slice(&now_ms().to_le_bytes());
+    frame.push(kind);
+    frame.extend_from_slice(payload);
+    Message::Binary(frame.into())
+}
+
+/// Nonces a peer used within the acceptance window
+#[derive(Default)]
+pub struct Guard {
+    nonces: HashSet<u64>,
+    /// Accepted nonces by timestamp, to forget them once they leave the window
+    expiry: BTreeSet<(u64, u64)>,
+}
+
+impl Guard {
+    /// Check a text or binary message from the peer, returning it unwrapped; other
+    /// messages are returned unchanged
+    pub fn check(&mut self, window_ms: u64, msg: Message) -> Result<Message, String> {
+        let data = match msg {
+            Message::Binary(data) => data,
+            Message::Text(_) => return Err("text message without a nonce".to_string()),
+            _ => return Ok(msg),
+        };
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err("binary message without a nonce".to_string());
+        }
+
+        let nonce = u64::from_le_bytes(data[4..12].try_into().unwrap());
+        let timestamp = u64::from_le_bytes(data[12..20].try_into().unwrap());
+        let now = now_ms();
+        if now.abs_diff(timestamp) > window_ms {
+            return Err(format!(
+                "timestamp {} is {}ms off the receiver's clock",
+                timestamp,
+                now.abs_diff(timestamp)
+            ));
+        }
+
+        let cutoff = now.saturating_sub(window_ms);
+        while let Some(&(oldest, nonce)) = self.expiry.first() {
+            if oldest >= cutoff {
+                break;
+            }
+            self.expiry.pop_first();
+            self.nonces.remove(&nonce);
+        }
+        if !self.nonces.insert(nonce) {
+            return Err(format!("replayed nonce {:016x}", nonce));
+        }
+        self.expiry.insert((timestamp, nonce));
+
+        let payload = data[HEADER_LEN..].to_vec();
+        match data[20] {
+            KIND_BINARY => Ok(Message::Binary(payload.into())),
+            KIND_TEXT => utf8::string(payload)
+                .map(|text| Message::Text(text.into()))
+                .ok_or_else(|| "text message is not valid UTF-8".to_string()),
+            _ => Err("unknown message kind".to_string()),
+        }
+    }
+}
+
+/// Nonces seen from each of the server's connections
+#[derive(Default)]
+pub struct Guards {
+    connections: HashMap<u64, Guard>,
+}
+
+impl Guards {
+    pub fn check(
+        &mut self,
+        connection_id: u64,
+        window_ms: u64,
+        msg: Message,
+    ) -> Result<Message, String> {
+        self.connections
+            .entry(connection_id)
+            .or_default()
+            .check(window_ms, msg)
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.connections.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+}
@@ -0,0 +1,52 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Recent broadcasts of each room, for chat backfill
+//!
+//! Messages broadcast to a room, by the host or a clustered sibling, are kept as
+//! sent (control-frame escaping included) so they can be replayed to connections
+//! joining later as they are. A room keeps its history while it has no members.
+
+use std::collections::{HashMap, VecDeque};
+
+use tokio_tungstenite::tungstenite::Message;
+
+/// Latest messages of each room that had a broadcast, oldest first
+#[derive(Default)]
+pub struct History {
+    rooms: HashMap<String, VecDeque<Message>>,
+}
+
+impl History {
+    /// Add a broadcast to a room's history, dropping its oldest messages past
+    /// `max_messages`
+    pub fn record(&mut self, room: &str, msg: &Message, max_messages: usize) {
+        if max_messages == 0 || !(msg.is_binary() || msg.is_text()) {
+            return;
+        }
+        let messages = self.rooms.entry(room.to_string()).or_default();
+        messages.push_back(msg.clone());
+        while messages.len() > max_messages {
+            messages.pop_front();
+        }
+    }
+
+    /// Up to `max` of the latest messages of a room, oldest first
+    pub fn latest(&self, room: &str, max: usize) -> Vec<Message> {
+        let Some(messages) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        let skip = messages.len().saturating_sub(max);
+        messages.iter().skip(skip).cloned().collect()
+    }
+
+    /// Forget a room's history. Returns false if it had none.
+    pub fn remove(&mut self, room: &str) -> bool {
+        self.rooms.remove(room).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.rooms.clear();
+    }
+}
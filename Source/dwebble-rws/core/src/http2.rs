@@ -0,0 +1,220 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebSockets over HTTP/2 (RFC 8441)
+//!
+//! Clients speaking HTTP/2 (negotiated with ALPN `h2` over TLS, or with prior
+//! knowledge in plain text) open each WebSocket with an extended CONNECT request
+//! on its own stream. After the handshake checks shared with HTTP/1.1 upgrades,
+//! the stream's DATA frames carry the WebSocket frames, and the connection is
+//! served like any other.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use h2::ext::Protocol;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::http::{Method, Request, Response, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Bytes;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::migration::Rehome;
+use crate::server::{self, Handshake, Shared};
+
+/// Start of the HTTP/2 connection preface
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\n";
+
+/// Extended CONNECT protocol of WebSockets
+const WEBSOCKET_PROTOCOL: &str = "websocket";
+
+/// A WebSocket's byte stream carried in the DATA frames of an HTTP/2 stream
+struct H2Stream {
+    recv: RecvStream,
+    send: SendStream<Bytes>,
+    /// Received data not yet read
+    buffered: Bytes,
+}
+
+/// The stream is multiplexed over a connection that stays with its server
+impl Rehome for H2Stream {
+    const MIGRATABLE: bool = false;
+}
+
+impl AsyncRead for H2Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.buffered.is_empty() {
+            match ready!(this.recv.poll_data(cx)) {
+                Some(Ok(data)) => {
+                    let _ = this.recv.flow_control().release_capacity(data.len());
+                    this.buffered = data;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let len = this.buffered.len().min(buf.remaining());
+        buf.put_slice(&this.buffered.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for H2Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        this.send.reserve_capacity(buf.len());
+        match ready!(this.send.poll_capacity(cx)) {
+            Some(Ok(capacity)) => {
+                let len = capacity.min(buf.len());
+                this.send
+                    .send_data(Bytes::copy_from_slice(&buf[..len]), false)
+                    .map_err(io::Error::other)?;
+                Poll::Ready(Ok(len))
+            }
+            Some(Err(e)) => Poll::Ready(Err(io::Error::other(e))),
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let _ = self.get_mut().send.send_data(Bytes::new(), true);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Serve an HTTP/2 connection, running each WebSocket on it until the client goes away.
+/// `tls_fingerprint` is the JA3 hash of a TLS client, zero without TLS.
+pub async fn serve<S>(
+    stream: S,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls_fingerprint: [u8; 16],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut connection = h2::server::Builder::new()
+        .enable_connect_protocol()
+        .handshake::<_, Bytes>(stream)
+        .await?;
+
+    while let Some(accepted) = connection.accept().await {
+        let (request, respond) = accepted?;
+        let shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            if let Err(e) = handle_stream(request, respond, addr, shared, tls_fingerprint).await {
+                tracing::error!("HTTP/2 WebSocket error from {}: {}", addr, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_stream(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls_fingerprint: [u8; 16],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let is_websocket = request.method() == Method::CONNECT
+        && request
+            .extensions()
+            .get::<Protocol>()
+            .is_some_and(|p| p.as_str() == WEBSOCKET_PROTOCOL);
+    if !is_websocket {
+        shared.handshake_failed(
+            addr,
+            Some(request.uri().path()),
+            None,
+            "Expected a WebSocket CONNECT".to_string(),
+        );
+        refuse(
+            respond,
+            StatusCode::BAD_REQUEST,
+            "Expected a WebSocket CONNECT",
+        )?;
+        return Ok(());
+    }
+
+    let (parts, recv) = request.into_parts();
+    let request = Request::from_parts(parts, ());
+    let settings = shared.settings.read().clone();
+    let mut handshake = Handshake::default();
+    handshake.fingerprint.tls = tls_fingerprint;
+    let response = match server::negotiate(
+        &shared,
+        &settings,
+        &request,
+        Response::new(()),
+        &mut handshake,
+    ) {
+        Ok(response) => response,
+        Err(rejection) => {
+            let reason = rejection.body().clone().unwrap_or_default();
+            let header = handshake.rejection.and_then(|(_, header)| header);
+            shared.handshake_failed(addr, handshake.path.as_deref(), header, reason.clone());
+            refuse(respond, rejection.status(), &reason)?;
+            return Ok(());
+        }
+    };
+
+    let send = match respond.send_response(response, false) {
+        Ok(send) => send,
+        Err(e) => {
+            // Hand a claimed session back so the client can retry
+            if let Some(id) = handshake.resumed_id {
+                shared.sessions.lock().suspend(id);
+            }
+            return Err(e.into());
+        }
+    };
+    let stream = H2Stream {
+        recv,
+        send,
+        buffered: Bytes::new(),
+    };
+    let ws_stream = WebSocketStream::from_raw_socket(
+        stream,
+        Role::Server,
+        Some(server::websocket_config(&settings)),
+    )
+    .await;
+    server::serve_websocket(ws_stream, addr, shared, handshake).await
+}
+
+/// Answer a request with an error status and reason
+fn refuse(
+    mut respond: SendResponse<Bytes>,
+    status: StatusCode,
+    reason: &str,
+) -> Result<(), h2::Error> {
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    let mut send = respond.send_response(response, false)?;
+    send.send_data(Bytes::copy_from_slice(reason.as_bytes()), true)
+}
@@ -0,0 +1,476 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Persistent message journal with file rotation
+//!
+//! Records are appended to `journal-<first sequence>.log` files in the
+//! configured directory. Each record is laid out as (little-endian):
+//!
+//! | field         | type |
+//! |---------------|------|
+//! | sequence      | u64  |
+//! | timestamp_us  | u64  |
+//! | connection_id | u64  |
+//! | direction     | u8 (0 = inbound, 1 = outbound) |
+//! | kind          | u8 (0 = binary, 1 = text) |
+//! | payload_len   | u32  |
+//! | payload       | `payload_len` bytes |
+//!
+//! Records are written by a thread of the journal's own, so recording one costs the
+//! game thread and the runtime's workers a copy of its payload. The thread flushes
+//! the file once it has written every record queued meanwhile. Records that find
+//! its queue full are dropped and counted in a warning.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use crate::settings::JournalSettings;
+
+const FILE_PREFIX: &str = "journal-";
+const FILE_SUFFIX: &str = ".log";
+const HEADER_LEN: usize = 8 + 8 + 8 + 1 + 1 + 4;
+/// Records queued for the writer thread at most
+const QUEUE_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound = 0,
+    Outbound = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Binary = 0,
+    Text = 1,
+}
+
+struct Record {
+    timestamp_us: u64,
+    connection_id: u64,
+    direction: Direction,
+    kind: PayloadKind,
+    payload: Vec<u8>,
+}
+
+enum Command {
+    Append(Record),
+    /// Flush the file, answering once every record queued before is written
+    Flush(mpsc::Sender<io::Result<()>>),
+}
+
+struct Writer {
+    settings: JournalSettings,
+    file: BufWriter<File>,
+    file_len: u64,
+    next_seq: u64,
+}
+
+/// Append-only journal of connection traffic
+pub struct Journal {
+    settings: JournalSettings,
+    tx: Option<SyncSender<Command>>,
+    writer: Option<JoinHandle<()>>,
+    /// Records dropped as the writer's queue was full, since the last warning
+    dropped: Arc<AtomicU64>,
+    selected: Mutex<HashSet<u64>>,
+    rooms: Mutex<HashSet<String>>,
+}
+
+impl Journal {
+    /// Open the journal directory, continuing after the last recorded sequence
+    pub fn open(settings: JournalSettings) -> io::Result<Self> {
+        let dir = PathBuf::from(&settings.directory);
+        fs::create_dir_all(&dir)?;
+
+        let next_seq = match list_files(&dir)?.last() {
+            Some((first_seq, path)) => last_sequence(path)?.map_or(*first_seq, |s| s + 1),
+            None => 1,
+        };
+
+        let writer = Writer {
+            settings: settings.clone(),
+            file: BufWriter::new(create_file(&dir, next_seq)?),
+            file_len: 0,
+            next_seq,
+        };
+        writer.prune()?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let dropped = Arc::clone(&dropped);
+            thread::Builder::new()
+                .name("dwebble-journal".to_string())
+                .spawn(move || writer.run(rx, &dropped))?
+        };
+        Ok(Self {
+            rooms: Mutex::new(settings.rooms.iter().cloned().collect()),
+            settings,
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped,
+            selected: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Include or exclude a connection when `all_connections` is off
+    pub fn select(&self, connection_id: u64, enabled: bool) {
+        let mut selected = self.selected.lock();
+        if enabled {
+            selected.insert(connection_id);
+        } else {
+            selected.remove(&connection_id);
+        }
+    }
+
+    /// Include or exclude the members of a room when `all_connections` is off
+    pub fn select_room(&self, room: &str, enabled: bool) {
+        let mut rooms = self.rooms.lock();
+        if enabled {
+            rooms.insert(room.to_string());
+        } else {
+            rooms.remove(room);
+        }
+    }
+
+    /// Forget a connection that closed
+    pub fn remove_connection(&self, connection_id: u64) {
+        self.selected.lock().remove(&connection_id);
+    }
+
+    /// Whether to journal a message of a connection, which `in_room` tells the rooms of
+    fn wants(
+        &self,
+        connection_id: u64,
+        direction: Direction,
+        in_room: impl Fn(&str) -> bool,
+    ) -> bool {
+        if direction == Direction::Outbound && !self.settings.include_outbound {
+            return false;
+        }
+        self.settings.all_connections
+            || self.selected.lock().contains(&connection_id)
+            || self.rooms.lock().iter().any(|room| in_room(room))
+    }
+
+    /// Append a record if the connection and direction are journaled. `in_room` tells
+    /// whether the connection is a member of a room.
+    pub fn record(
+        &self,
+        connection_id: u64,
+        direction: Direction,
+        kind: PayloadKind,
+        payload: &[u8],
+        in_room: impl Fn(&str) -> bool,
+    ) {
+        if !self.wants(connection_id, direction, in_room) {
+            return;
+        }
+        let Some(tx) = &self.tx else { return };
+        let record = Record {
+            timestamp_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_micros() as u64),
+            connection_id,
+            direction,
+            kind,
+            payload: payload.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(Command::Append(record)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Read up to `max_records` raw records starting at sequence `from_seq`
+    pub fn read(&self, from_seq: u64, max_records: usize) -> io::Result<Vec<u8>> {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let stopped = || io::Error::other("Journal writer stopped");
+        let tx = self.tx.as_ref().ok_or_else(stopped)?;
+        tx.send(Command::Flush(reply_tx)).map_err(|_| stopped())?;
+        reply_rx.recv().map_err(|_| stopped())??;
+
+        let files = list_files(Path::new(&self.settings.directory))?;
+        let mut out = Vec::new();
+        let mut count = 0;
+
+        for (i, (_, path)) in files.iter().enumerate() {
+            // Skip files that end before the requested range
+            if files.get(i + 1).is_some_and(|(next, _)| *next <= from_seq) {
+                continue;
+            }
+
+            let mut reader = BufReader::new(File::open(path)?);
+            while count < max_records {
+                let Some((seq, record)) = read_record(&mut reader)? else {
+                    break;
+                };
+                if seq >= from_seq {
+                    out.extend_from_slice(&record);
+                    count += 1;
+                }
+            }
+            if count >= max_records {
+                break;
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        // The writer writes what is queued and stops once the queue is closed
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Writer {
+    /// Write queued records until the journal is dropped, flushing after each batch
+    fn run(mut self, rx: Receiver<Command>, dropped: &AtomicU64) {
+        while let Ok(command) = rx.recv() {
+            let mut next = Some(command);
+            while let Some(command) = next {
+                match command {
+                    Command::Append(record) => {
+                        if let Err(e) = self.append(&record) {
+                            tracing::error!("Journal write failed: {}", e);
+                        }
+                    }
+                    Command::Flush(reply) => {
+                        let _ = reply.send(self.file.flush());
+                    }
+                }
+                next = rx.try_recv().ok();
+            }
+            if let Err(e) = self.file.flush() {
+                tracing::error!("Journal write failed: {}", e);
+            }
+            let dropped = dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                tracing::warn!("Dropped {} journal records over a full write queue", dropped);
+            }
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        if self.file_len >= self.settings.max_file_bytes {
+            self.file.flush()?;
+            self.file = BufWriter::new(create_file(
+                Path::new(&self.settings.directory),
+                self.next_seq,
+            )?);
+            self.file_len = 0;
+            self.prune()?;
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let payload = &record.payload;
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(&seq.to_le_bytes());
+        header[8..16].copy_from_slice(&record.timestamp_us.to_le_bytes());
+        header[16..24].copy_from_slice(&record.connection_id.to_le_bytes());
+        header[24] = record.direction as u8;
+        header[25] = record.kind as u8;
+        header[26..30].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+
+        self.file.write_all(&header)?;
+        self.file.write_all(payload)?;
+        self.file_len += (HEADER_LEN + payload.len()) as u64;
+        Ok(())
+    }
+
+    /// Delete the oldest files beyond `max_files`
+    fn prune(&self) -> io::Result<()> {
+        if self.settings.max_files == 0 {
+            return Ok(());
+        }
+        let files = list_files(Path::new(&self.settings.directory))?;
+        let excess = files.len().saturating_sub(self.settings.max_files);
+        for (_, path) in files.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn create_file(dir: &Path, first_seq: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{FILE_PREFIX}{first_seq:020}{FILE_SUFFIX}")))
+}
+
+/// Journal files sorted by their first sequence number
+fn list_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files: Vec<(u64, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let seq = name
+                .strip_prefix(FILE_PREFIX)?
+                .strip_suffix(FILE_SUFFIX)?
+                .parse()
+                .ok()?;
+            Some((seq, entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(seq, _)| *seq);
+    Ok(files)
+}
+
+fn last_sequence(path: &Path) -> io::Result<Option<u64>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut last = None;
+    while let Some((seq, _)) = read_record(&mut reader)? {
+        last = Some(seq);
+    }
+    Ok(last)
+}
+
+/// Read one raw record. Returns `None` at end of file or on a truncated tail.
+fn read_record(reader: &mut impl Read) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let mut header = [0u8; HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let seq = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let len = u32::from_le_bytes(header[26..30].try_into().unwrap()) as usize;
+
+    let mut record = Vec::with_capacity(HEADER_LEN + len);
+    record.extend_from_slice(&header);
+    record.resize(HEADER_LEN + len, 0);
+    match reader.read_exact(&mut record[HEADER_LEN..]) {
+        Ok(()) => Ok(Some((seq, record))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (sequence, connection, direction, kind, payload) of raw records
+    fn parse(mut data: &[u8]) -> Vec<(u64, u64, u8, u8, Vec<u8>)> {
+        let mut records = vec![];
+        while let Some((seq, record)) = read_record(&mut data).unwrap() {
+            let connection_id = u64::from_le_bytes(record[16..24].try_into().unwrap());
+            let payload = record[HEADER_LEN..].to_vec();
+            records.push((seq, connection_id, record[24], record[25], payload));
+        }
+        records
+    }
+
+    fn settings(dir: &tempfile::TempDir) -> JournalSettings {
+        JournalSettings {
+            directory: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn record(journal: &Journal, connection_id: u64, direction: Direction, payload: &[u8]) {
+        journal.record(connection_id, direction, PayloadKind::Binary, payload, |_| false);
+    }
+
+    #[test]
+    fn records_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(JournalSettings {
+            include_outbound: true,
+            ..settings(&dir)
+        })
+        .unwrap();
+        record(&journal, 1, Direction::Inbound, b"first");
+        journal.record(2, Direction::Outbound, PayloadKind::Text, b"second", |_| false);
+        record(&journal, 1, Direction::Inbound, b"");
+
+        assert_eq!(
+            parse(&journal.read(1, 10).unwrap()),
+            [
+                (1, 1, 0, 0, b"first".to_vec()),
+                (2, 2, 1, 1, b"second".to_vec()),
+                (3, 1, 0, 0, vec![]),
+            ]
+        );
+        let seqs = |data: Vec<u8>| parse(&data).into_iter().map(|r| r.0).collect::<Vec<_>>();
+        assert_eq!(seqs(journal.read(2, 10).unwrap()), [2, 3]);
+        assert_eq!(seqs(journal.read(1, 2).unwrap()), [1, 2]);
+    }
+
+    #[test]
+    fn reopening_continues_the_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(settings(&dir)).unwrap();
+        record(&journal, 1, Direction::Inbound, b"a");
+        record(&journal, 1, Direction::Inbound, b"b");
+        drop(journal);
+
+        let journal = Journal::open(settings(&dir)).unwrap();
+        record(&journal, 1, Direction::Inbound, b"c");
+        let records = parse(&journal.read(1, 10).unwrap());
+        assert_eq!(records.iter().map(|r| r.0).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(records[2].4, b"c");
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(JournalSettings {
+            max_file_bytes: 1,
+            max_files: 2,
+            ..settings(&dir)
+        })
+        .unwrap();
+        for payload in [b"1", b"2", b"3", b"4", b"5"] {
+            record(&journal, 1, Direction::Inbound, payload);
+        }
+        let records = parse(&journal.read(1, 10).unwrap());
+        assert_eq!(records.iter().map(|r| r.0).collect::<Vec<_>>(), [4, 5]);
+        assert_eq!(list_files(dir.path()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn only_selected_connections_and_rooms_are_journaled() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(JournalSettings {
+            all_connections: false,
+            rooms: vec!["lobby".to_string()],
+            ..settings(&dir)
+        })
+        .unwrap();
+        let in_lobby = |room: &str| room == "lobby";
+        journal.select(2, true);
+
+        record(&journal, 1, Direction::Inbound, b"unselected");
+        record(&journal, 2, Direction::Inbound, b"selected");
+        record(&journal, 2, Direction::Outbound, b"outbound");
+        journal.record(3, Direction::Inbound, PayloadKind::Binary, b"member", in_lobby);
+
+        journal.remove_connection(2);
+        journal.select_room("lobby", false);
+        record(&journal, 2, Direction::Inbound, b"closed");
+        journal.record(3, Direction::Inbound, PayloadKind::Binary, b"left", in_lobby);
+
+        let records = parse(&journal.read(1, 10).unwrap());
+        let payloads: Vec<_> = records.into_iter().map(|r| r.4).collect();
+        assert_eq!(payloads, [b"selected".to_vec(), b"member".to_vec()]);
+    }
+}
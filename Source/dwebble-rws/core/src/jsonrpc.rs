@@ -0,0 +1,285 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! JSON-RPC 2.0 framing for text messages
+//!
+//! Each valid request or notification raises an `RpcCall` event. Requests get
+//! a call ID (the event's request ID) that the host answers with a result or an
+//! error; notifications have call ID 0 and get no response. Malformed messages
+//! are answered with the spec's error responses without involving the host.
+//! Responses to a batch are sent together once every call in it is answered.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::{DwebbleWSEventType, DwebbleWSResult};
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+
+/// A request awaiting the host's response
+struct Call {
+    connection_id: u64,
+    /// The request's `id`, echoed in the response
+    id: Value,
+    batch: Option<u64>,
+}
+
+/// A batch whose responses are held back until every call is answered
+struct Batch {
+    connection_id: u64,
+    unanswered: usize,
+    responses: Vec<Value>,
+}
+
+/// Calls awaiting a response
+pub struct RpcCalls {
+    next_id: u64,
+    calls: HashMap<u64, Call>,
+    batches: HashMap<u64, Batch>,
+}
+
+impl Default for RpcCalls {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            calls: HashMap::new(),
+            batches: HashMap::new(),
+        }
+    }
+}
+
+impl RpcCalls {
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Forget the calls of a closed connection
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.calls
+            .retain(|_, call| call.connection_id != connection_id);
+        self.batches
+            .retain(|_, batch| batch.connection_id != connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+        self.batches.clear();
+    }
+}
+
+/// A structurally valid request or notification
+struct Parsed {
+    method: String,
+    params: Option<Value>,
+    /// Absent for notifications
+    id: Option<Value>,
+}
+
+/// Handle an inbound text message
+pub fn on_message(shared: &Shared, connection_id: u64, text: &str) {
+    let items = match serde_json::from_str::<Value>(text) {
+        Err(_) => {
+            send(
+                shared,
+                connection_id,
+                &error_response(Value::Null, PARSE_ERROR, "Parse error"),
+            );
+            return;
+        }
+        Ok(Value::Array(items)) if items.is_empty() => {
+            send(shared, connection_id, &invalid_request(Value::Null));
+            return;
+        }
+        Ok(Value::Array(items)) => items,
+        Ok(item) => {
+            match parse(item) {
+                Ok(parsed) => {
+                    let call_id = parsed
+                        .id
+                        .clone()
+                        .map(|id| register(shared, connection_id, id, None));
+                    raise(shared, connection_id, parsed, call_id);
+                }
+                Err(response) => {
+                    send(shared, connection_id, &response);
+                }
+            }
+            return;
+        }
+    };
+
+    let mut errors = Vec::new();
+    let mut calls = Vec::new();
+    for item in items {
+        match parse(item) {
+            Ok(parsed) => calls.push(parsed),
+            Err(response) => errors.push(response),
+        }
+    }
+
+    let unanswered = calls.iter().filter(|call| call.id.is_some()).count();
+    let batch = if unanswered == 0 {
+        // Only notifications and errors: nothing to wait for
+        if !errors.is_empty() {
+            send(shared, connection_id, &Value::Array(errors));
+        }
+        None
+    } else {
+        let mut rpc = shared.rpc.lock();
+        let batch_id = rpc.next_id();
+        rpc.batches.insert(
+            batch_id,
+            Batch {
+                connection_id,
+                unanswered,
+                responses: errors,
+            },
+        );
+        Some(batch_id)
+    };
+
+    for parsed in calls {
+        let call_id = parsed
+            .id
+            .clone()
+            .map(|id| register(shared, connection_id, id, batch));
+        raise(shared, connection_id, parsed, call_id);
+    }
+}
+
+/// Answer a call with a result (any JSON value)
+pub fn reply(shared: &Shared, call_id: u64, result: Value) -> DwebbleWSResult {
+    respond(
+        shared,
+        call_id,
+        |id| json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+    )
+}
+
+/// Answer a call with an error
+pub fn reply_error(
+    shared: &Shared,
+    call_id: u64,
+    code: i32,
+    message: &str,
+    data: Option<Value>,
+) -> DwebbleWSResult {
+    respond(shared, call_id, |id| {
+        let mut response = error_response(id, code, message);
+        if let Some(data) = data {
+            response["error"]["data"] = data;
+        }
+        response
+    })
+}
+
+fn respond(shared: &Shared, call_id: u64, build: impl FnOnce(Value) -> Value) -> DwebbleWSResult {
+    let (connection_id, response) = {
+        let mut rpc = shared.rpc.lock();
+        let Some(call) = rpc.calls.remove(&call_id) else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        let response = build(call.id);
+
+        let Some(batch_id) = call.batch else {
+            drop(rpc);
+            return send(shared, call.connection_id, &response);
+        };
+        let Some(batch) = rpc.batches.get_mut(&batch_id) else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        batch.responses.push(response);
+        batch.unanswered -= 1;
+        if batch.unanswered > 0 {
+            return DwebbleWSResult::Ok;
+        }
+        let batch = rpc.batches.remove(&batch_id).unwrap();
+        (batch.connection_id, Value::Array(batch.responses))
+    };
+    send(shared, connection_id, &response)
+}
+
+fn register(shared: &Shared, connection_id: u64, id: Value, batch: Option<u64>) -> u64 {
+    let mut rpc = shared.rpc.lock();
+    let call_id = rpc.next_id();
+    rpc.calls.insert(
+        call_id,
+        Call {
+            connection_id,
+            id,
+            batch,
+        },
+    );
+    call_id
+}
+
+/// Raise the `RpcCall` event of a request (with its call ID) or notification
+fn raise(shared: &Shared, connection_id: u64, parsed: Parsed, call_id: Option<u64>) {
+    let params = parsed
+        .params
+        .map(|params| params.to_string().into_bytes())
+        .unwrap_or_default();
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::RpcCall,
+        connection_id,
+        data: Some(params),
+        error: Some(parsed.method),
+        request_id: call_id.unwrap_or(0),
+    });
+}
+
+/// Validate a request object, or build the error response for it
+fn parse(item: Value) -> Result<Parsed, Value> {
+    let Value::Object(mut object) = item else {
+        return Err(invalid_request(Value::Null));
+    };
+
+    let id = match object.remove("id") {
+        Some(id @ (Value::String(_) | Value::Number(_) | Value::Null)) => Some(id),
+        Some(_) => return Err(invalid_request(Value::Null)),
+        None => None,
+    };
+    let invalid = |object: &Map<String, Value>| {
+        let version_ok = object.get("jsonrpc").and_then(Value::as_str) == Some("2.0");
+        let method_ok = object.get("method").is_some_and(Value::is_string);
+        let params_ok = object
+            .get("params")
+            .is_none_or(|params| params.is_array() || params.is_object());
+        !(version_ok && method_ok && params_ok)
+    };
+    if invalid(&object) {
+        return Err(invalid_request(id.unwrap_or(Value::Null)));
+    }
+
+    let Some(Value::String(method)) = object.remove("method") else {
+        unreachable!("checked above");
+    };
+    Ok(Parsed {
+        method,
+        params: object.remove("params"),
+        id,
+    })
+}
+
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+fn invalid_request(id: Value) -> Value {
+    error_response(id, INVALID_REQUEST, "Invalid Request")
+}
+
+fn send(shared: &Shared, connection_id: u64, response: &Value) -> DwebbleWSResult {
+    shared.send_message(connection_id, Message::Text(response.to_string().into()))
+}
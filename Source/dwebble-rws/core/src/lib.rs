@@ -0,0 +1,104 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebSocket server and client for games
+//!
+//! The server, its connections and everything they speak, with a safe Rust API:
+//! create a [`Server`] from a [`ServerConfig`], start it and take its events
+//! with [`Server::poll_event`] once a frame or by awaiting [`Server::next_event`].
+//! The `dwebble-rws` crate exposes the same API to C++ as a C ABI; the plain
+//! enums and structs in [`types`] are shared with it as they are.
+//!
+//! A server may be shared between threads, e.g. in an `Arc`, and used from all of
+//! them at once. Starting and stopping it block while its runtime starts or shuts
+//! down, so async code calls them from a blocking thread, e.g. with
+//! `tokio::task::spawn_blocking`.
+
+mod admin;
+#[cfg(feature = "archive")]
+pub mod archive;
+mod bans;
+mod bridge;
+mod budget;
+mod channels;
+pub mod chaos;
+pub mod checksum;
+pub mod client;
+mod codec;
+pub mod compression;
+pub mod cluster;
+mod connection;
+mod connections;
+mod control;
+mod delta;
+pub mod discovery;
+mod encryption;
+pub mod event_queues;
+mod event_ring;
+mod eviction;
+mod fingerprint;
+pub mod frames;
+mod freshness;
+#[cfg(feature = "http2")]
+mod http2;
+pub mod journal;
+mod jsonrpc;
+pub mod loadtest;
+pub mod logging;
+mod histogram;
+mod history;
+pub mod maintenance;
+mod metrics;
+pub mod middleware;
+mod migration;
+mod mock;
+mod mqtt;
+mod netsim;
+mod offline;
+mod pipes;
+#[cfg(feature = "port-mapping")]
+mod portmap;
+pub mod pool;
+mod presence;
+mod ratelimit;
+mod raw;
+mod receipts;
+pub mod recording;
+mod requests;
+mod resources;
+mod rewind;
+mod rooms;
+#[cfg(feature = "webrtc")]
+mod rtc;
+pub mod runtime;
+pub mod server;
+pub mod service_pool;
+mod session;
+pub mod settings;
+mod signing;
+mod socketio;
+mod sse;
+mod stun;
+mod tags;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod topics;
+pub mod types;
+mod utf8;
+mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "webtransport")]
+mod webtransport;
+pub mod watchdog;
+pub mod webhooks;
+
+pub use client::Client;
+pub use connection::is_sendable_close_code;
+pub use server::{Listen, Server, ServerConfig, ServerEvent};
+pub use service_pool::ServicePool;
+pub use settings::Settings;
+pub use tokio_tungstenite::tungstenite::Message;
@@ -0,0 +1,256 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Built-in load-test client harness
+//!
+//! Each client sends binary messages whose first 8 bytes are the send time in
+//! microseconds since the test started (little-endian). Any binary message
+//! received back with that prefix is counted as a latency sample, so latency is
+//! only reported when the server echoes messages.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::runtime;
+use crate::types::{DwebbleWSLoadTestStats, DwebbleWSResult};
+
+/// Bytes of the send timestamp at the start of each payload
+const TIMESTAMP_LEN: usize = 8;
+
+/// How long stopping waits for clients to finish their closing handshake
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct LoadTestConfig {
+    pub url: String,
+    pub num_clients: u32,
+    /// Messages per second sent by each client (0 to only hold connections open)
+    pub msgs_per_sec: u32,
+    pub payload_len: usize,
+}
+
+#[derive(Default)]
+struct Stats {
+    active_clients: AtomicU64,
+    connect_failures: AtomicU64,
+    disconnects: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    latency_samples: AtomicU64,
+    latency_total_us: AtomicU64,
+    latency_min_us: AtomicU64,
+    latency_max_us: AtomicU64,
+}
+
+impl Stats {
+    fn record_latency(&self, latency_us: u64) {
+        self.latency_samples.fetch_add(1, Ordering::Relaxed);
+        self.latency_total_us
+            .fetch_add(latency_us, Ordering::Relaxed);
+        self.latency_min_us.fetch_min(latency_us, Ordering::Relaxed);
+        self.latency_max_us.fetch_max(latency_us, Ordering::Relaxed);
+    }
+}
+
+/// A running load test
+pub struct LoadTest {
+    runtime: Option<tokio::runtime::Runtime>,
+    stats: Arc<Stats>,
+    started: Instant,
+    shutdown_tx: watch::Sender<bool>,
+    clients: Vec<JoinHandle<()>>,
+}
+
+impl LoadTest {
+    pub fn start(config: LoadTestConfig) -> Result<Self, DwebbleWSResult> {
+        if config.num_clients == 0 {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+
+        let runtime = runtime::build(None, None).map_err(|_| DwebbleWSResult::RuntimeError)?;
+
+        let stats = Arc::new(Stats {
+            latency_min_us: AtomicU64::new(u64::MAX),
+            ..Default::default()
+        });
+        let started = Instant::now();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let config = Arc::new(config);
+
+        let clients = (0..config.num_clients)
+            .map(|_| {
+                runtime.spawn(run_client(
+                    Arc::clone(&config),
+                    Arc::clone(&stats),
+                    started,
+                    shutdown_rx.clone(),
+                ))
+            })
+            .collect();
+
+        tracing::info!(
+            "Load test started: {} clients -> {} ({} msg/s, {} bytes)",
+            config.num_clients,
+            config.url,
+            config.msgs_per_sec,
+            config.payload_len
+        );
+
+        Ok(Self {
+            runtime: Some(runtime),
+            stats,
+            started,
+            shutdown_tx,
+            clients,
+        })
+    }
+
+    /// Snapshot of the aggregate statistics so far
+    pub fn stats(&self) -> DwebbleWSLoadTestStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed();
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+
+        let messages_sent = load(&self.stats.messages_sent);
+        let messages_received = load(&self.stats.messages_received);
+        let latency_samples = load(&self.stats.latency_samples);
+
+        DwebbleWSLoadTestStats {
+            elapsed_ms: elapsed.as_millis() as u64,
+            active_clients: load(&self.stats.active_clients),
+            connect_failures: load(&self.stats.connect_failures),
+            disconnects: load(&self.stats.disconnects),
+            messages_sent,
+            messages_received,
+            bytes_sent: load(&self.stats.bytes_sent),
+            bytes_received: load(&self.stats.bytes_received),
+            send_rate: messages_sent as f64 / secs,
+            receive_rate: messages_received as f64 / secs,
+            latency_samples,
+            latency_min_us: if latency_samples > 0 {
+                load(&self.stats.latency_min_us)
+            } else {
+                0
+            },
+            latency_avg_us: load(&self.stats.latency_total_us)
+                .checked_div(latency_samples)
+                .unwrap_or(0),
+            latency_max_us: load(&self.stats.latency_max_us),
+        }
+    }
+
+    /// Drive a `single-thread` build's runtime for up to `budget`
+    pub fn tick(&self, budget: Duration) {
+        if let Some(rt) = &self.runtime {
+            runtime::tick(rt, budget);
+        }
+    }
+
+    /// Close every client and shut the runtime down
+    pub fn stop(&mut self) {
+        let Some(runtime) = self.runtime.take() else {
+            return;
+        };
+
+        let _ = self.shutdown_tx.send(true);
+        let clients = std::mem::take(&mut self.clients);
+        runtime.block_on(async move {
+            let _ =
+                tokio::time::timeout(STOP_TIMEOUT, futures_util::future::join_all(clients)).await;
+        });
+        runtime.shutdown_background();
+
+        tracing::info!("Load test stopped");
+    }
+}
+
+impl Drop for LoadTest {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn run_client(
+    config: Arc<LoadTestConfig>,
+    stats: Arc<Stats>,
+    started: Instant,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    let ws_stream = match tokio_tungstenite::connect_async(config.url.as_str()).await {
+        Ok((ws_stream, _)) => ws_stream,
+        Err(e) => {
+            tracing::debug!("Load test client failed to connect: {}", e);
+            stats.connect_failures.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    stats.active_clients.fetch_add(1, Ordering::Relaxed);
+
+    let (mut write, mut read) = ws_stream.split();
+
+    let mut ticker = (config.msgs_per_sec > 0).then(|| {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs_f64(1.0 / config.msgs_per_sec as f64));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+    let mut payload = vec![0u8; config.payload_len.max(TIMESTAMP_LEN)];
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.changed() => {
+                let _ = write.send(Message::Close(None)).await;
+                break;
+            }
+            _ = tick(ticker.as_mut()) => {
+                let now_us = started.elapsed().as_micros() as u64;
+                payload[..TIMESTAMP_LEN].copy_from_slice(&now_us.to_le_bytes());
+                if write.send(Message::Binary(payload.clone().into())).await.is_err() {
+                    break;
+                }
+                stats.messages_sent.fetch_add(1, Ordering::Relaxed);
+                stats.bytes_sent.fetch_add(payload.len() as u64, Ordering::Relaxed);
+            }
+            msg = read.next() => match msg {
+                Some(Ok(Message::Binary(data))) => {
+                    stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                    stats.bytes_received.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    if let Some(sent_us) = data.get(..TIMESTAMP_LEN) {
+                        let sent_us = u64::from_le_bytes(sent_us.try_into().unwrap());
+                        let now_us = started.elapsed().as_micros() as u64;
+                        if sent_us <= now_us {
+                            stats.record_latency(now_us - sent_us);
+                        }
+                    }
+                }
+                Some(Ok(Message::Text(text))) => {
+                    stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                    stats.bytes_received.fetch_add(text.len() as u64, Ordering::Relaxed);
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            }
+        }
+    }
+
+    stats.active_clients.fetch_sub(1, Ordering::Relaxed);
+    stats.disconnects.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Wait for the next send tick, or forever if the client does not send
+async fn tick(ticker: Option<&mut tokio::time::Interval>) {
+    match ticker {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,37 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Tracing setup with a reloadable filter
+
+use std::sync::OnceLock;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install the global subscriber. Later calls are no-ops.
+pub fn init() {
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+
+    if tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .try_init()
+        .is_ok()
+    {
+        let _ = FILTER_HANDLE.set(handle);
+    }
+}
+
+/// Replace the active filter. Does nothing if tracing was not initialized.
+pub fn set_level(directive: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directive).map_err(|e| e.to_string())?;
+
+    match FILTER_HANDLE.get() {
+        Some(handle) => handle.reload(filter).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Scheduled maintenance shutdowns
+//!
+//! A shutdown is scheduled for a wall-clock time, with warnings broadcast as text
+//! to every connection at offsets before it, e.g. 300, 60 and 10 seconds. At the
+//! time, new handshakes are refused, every connection is closed with the
+//! `close_code` and `close_reason` settings and suspended sessions end. Once the
+//! connections are gone, or the close grace period is over, a `ShutdownDue` event
+//! tells the host to stop the server.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+
+/// Placeholder in the warning message replaced with the seconds left
+const SECONDS_PLACEHOLDER: &str = "{seconds}";
+
+/// A maintenance shutdown and its warnings
+pub struct ShutdownSchedule {
+    pub at: SystemTime,
+    /// Broadcast before the shutdown, `{seconds}` replaced with the seconds left
+    /// (empty for no warnings)
+    pub warning_message: String,
+    /// Seconds before the shutdown to broadcast the warning at
+    pub warning_intervals_s: Vec<u32>,
+}
+
+/// Broadcast the warnings still ahead on their schedule, then close every
+/// connection and raise `ShutdownDue`
+pub(crate) async fn run(shared: Arc<Shared>, schedule: ShutdownSchedule) {
+    let remaining = schedule
+        .at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    let deadline = Instant::now() + remaining;
+    tracing::info!("Shutdown scheduled in {}s", remaining.as_secs());
+
+    let mut intervals = schedule.warning_intervals_s;
+    intervals.sort_unstable_by(|a, b| b.cmp(a));
+    intervals.dedup();
+    if !schedule.warning_message.is_empty() {
+        for seconds in intervals {
+            let lead = Duration::from_secs(u64::from(seconds));
+            if lead > remaining {
+                continue;
+            }
+            tokio::time::sleep_until(deadline - lead).await;
+            let text = schedule
+                .warning_message
+                .replace(SECONDS_PLACEHOLDER, &seconds.to_string());
+            warn(&shared, &text);
+        }
+    }
+    tokio::time::sleep_until(deadline).await;
+
+    shared.shutting_down.store(true, Ordering::Relaxed);
+    let (code, reason, grace_ms) = {
+        let settings = shared.settings.read();
+        (
+            settings.close_code,
+            settings.close_reason.clone(),
+            settings.close_grace_ms,
+        )
+    };
+    let conns = shared.connections.values();
+    for conn in &conns {
+        conn.close_with(code, &reason);
+    }
+    let connections = conns.len() as u64;
+    tracing::info!("Scheduled shutdown: closing {} connections", connections);
+    let suspended = shared.sessions.lock().suspended();
+    for connection_id in suspended {
+        shared.close_connection(connection_id, None);
+    }
+
+    // Give clients a moment to complete the closing handshake, as a stop does
+    let grace_deadline = Instant::now() + Duration::from_millis(grace_ms);
+    while !shared.connections.is_empty() && Instant::now() < grace_deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ShutdownDue,
+        connection_id: 0,
+        data: None,
+        error: None,
+        request_id: connections,
+    });
+}
+
+/// Broadcast a warning to every live connection
+fn warn(shared: &Shared, text: &str) {
+    tracing::info!("Shutdown warning: {}", text);
+    let connection_ids: Vec<u64> = shared.connections.ids();
+    for connection_id in connection_ids {
+        shared.send_message(connection_id, Message::text(text));
+    }
+}
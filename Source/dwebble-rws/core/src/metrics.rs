@@ -0,0 +1,337 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! StatsD metrics
+//!
+//! With the `statsd` setting, the server pushes its metrics over UDP to a StatsD
+//! agent every interval, for fleets monitored by pushing rather than scraping.
+//! Counters are the change over the interval:
+//!
+//! | metric                          | type    |                                       |
+//! |---------------------------------|---------|---------------------------------------|
+//! | `connections`                   | gauge   | open connections                      |
+//! | `sessions.suspended`            | gauge   | sessions awaiting their client        |
+//! | `queued_bytes`                  | gauge   | bytes queued to send, all connections |
+//! | `connections.opened`            | counter |                                       |
+//! | `connections.closed`            | counter |                                       |
+//! | `handshakes.failed`             | counter | including TLS handshakes              |
+//! | `errors`                        | counter | `Error` events                        |
+//! | `messages.received`             | counter |                                       |
+//! | `messages.sent`                 | counter |                                       |
+//! | `bytes.received`                | counter | before decompression                  |
+//! | `bytes.sent`                    | counter | after compression                     |
+//! | `rtt.p50` ... `rtt.max`         | gauge   | round trip times in ms: p50, p95, p99 |
+//! | `message_size.received.p50` ... | gauge   | p50, p90, p99 and p999 in bytes       |
+//! | `message_size.sent.p50` ...     | gauge   |                                       |
+//! | `poll_latency_us.p50` ...       | gauge   | event wait for the host, microseconds |
+//!
+//! Round trip times are measured by pinging every connection once an interval;
+//! the percentiles are of the pongs received over the interval, and left out if
+//! there were none, as are the histogram percentiles, which are of the values
+//! recorded over the interval. The `tags` setting adds DogStatsD tags to every
+//! metric.
+
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tokio::net::UdpSocket;
+use tokio_tungstenite::tungstenite::{Bytes, Message};
+
+use crate::control;
+use crate::histogram::Snapshot;
+use crate::server::{ServerEvent, Shared};
+use crate::settings::StatsdSettings;
+use crate::types::DwebbleWSEventType;
+
+/// Prefix of the payload of pings measuring round trip times
+const PROBE_MAGIC: &[u8; 4] = control::RTT_PROBE;
+
+/// Round trip time samples kept per interval, enough for the percentiles
+const MAX_RTT_SAMPLES: usize = 65_536;
+
+/// Counters of a server's traffic, pushed to a StatsD agent
+pub struct Metrics {
+    settings: StatsdSettings,
+    /// Time probe payloads are measured from
+    epoch: Instant,
+    connections_opened: AtomicU64,
+    connections_closed: AtomicU64,
+    handshakes_failed: AtomicU64,
+    errors: AtomicU64,
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Round trip times measured since the last push, in microseconds
+    rtt_us: Mutex<Vec<u64>>,
+}
+
+/// Counter values, in the order of `COUNTER_NAMES`
+type Counts = [u64; 8];
+
+/// Counter values and histogram buckets at one time
+struct Totals {
+    counts: Counts,
+    histograms: Vec<Snapshot>,
+}
+
+impl Totals {
+    fn take(shared: &Shared, metrics: &Metrics) -> Self {
+        Self {
+            counts: metrics.counts(),
+            histograms: shared
+                .histograms
+                .named()
+                .iter()
+                .map(|(_, histogram)| histogram.snapshot())
+                .collect(),
+        }
+    }
+}
+
+const COUNTER_NAMES: [&str; 8] = [
+    "connections.opened",
+    "connections.closed",
+    "handshakes.failed",
+    "errors",
+    "messages.received",
+    "messages.sent",
+    "bytes.received",
+    "bytes.sent",
+];
+
+impl Metrics {
+    pub fn new(settings: StatsdSettings) -> Self {
+        Self {
+            settings,
+            epoch: Instant::now(),
+            connections_opened: AtomicU64::new(0),
+            connections_closed: AtomicU64::new(0),
+            handshakes_failed: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            messages_received: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            rtt_us: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Count a server event
+    pub fn observe(&self, event: &ServerEvent) {
+        let counter = match event.event_type {
+            DwebbleWSEventType::ClientConnected => &self.connections_opened,
+            DwebbleWSEventType::ClientDisconnected => &self.connections_closed,
+            DwebbleWSEventType::HandshakeFailed | DwebbleWSEventType::TlsHandshakeFailed => {
+                &self.handshakes_failed
+            }
+            DwebbleWSEventType::Error => &self.errors,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a data message received, `len` bytes as read
+    pub fn received(&self, len: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Count a data message sent, `len` bytes as written
+    pub fn sent(&self, len: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// A ping measuring the round trip time to a connection
+    fn probe(&self) -> Message {
+        let mut payload = PROBE_MAGIC.to_vec();
+        let sent_us = self.epoch.elapsed().as_micros() as u64;
+        payload.extend_from_slice(&sent_us.to_be_bytes());
+        Message::Ping(Bytes::from(payload))
+    }
+
+    /// Take a round trip time sample from a pong answering a probe; other pongs
+    /// are ignored
+    pub fn pong(&self, payload: &[u8]) {
+        let Some(sent_us) = payload
+            .strip_prefix(PROBE_MAGIC)
+            .and_then(|sent| <[u8; 8]>::try_from(sent).ok())
+            .map(u64::from_be_bytes)
+        else {
+            return;
+        };
+        let now_us = self.epoch.elapsed().as_micros() as u64;
+        let mut samples = self.rtt_us.lock();
+        if samples.len() < MAX_RTT_SAMPLES {
+            samples.push(now_us.saturating_sub(sent_us));
+        }
+    }
+
+    fn counts(&self) -> Counts {
+        [
+            &self.connections_opened,
+            &self.connections_closed,
+            &self.handshakes_failed,
+            &self.errors,
+            &self.messages_received,
+            &self.messages_sent,
+            &self.bytes_received,
+            &self.bytes_sent,
+        ]
+        .map(|counter| counter.load(Ordering::Relaxed))
+    }
+}
+
+/// Push metrics and probe round trip times every interval until the runtime shuts down
+pub async fn run(shared: Arc<Shared>) {
+    let Some(metrics) = &shared.metrics else {
+        return;
+    };
+    let settings = &metrics.settings;
+    let mut socket: Option<UdpSocket> = None;
+
+    // Counts from earlier runs were pushed then
+    let mut last = Totals::take(&shared, metrics);
+    metrics.rtt_us.lock().clear();
+    let interval = Duration::from_millis(settings.interval_ms);
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let totals = Totals::take(&shared, metrics);
+        let lines = report(&shared, metrics, &totals, &last);
+        last = totals;
+        if let Err(e) = push(
+            &mut socket,
+            &settings.address,
+            &lines,
+            settings.max_packet_size,
+        )
+        .await
+        {
+            tracing::debug!("Failed to send metrics to '{}': {}", settings.address, e);
+        }
+
+        let probe = metrics.probe();
+        for conn in shared.connections.values() {
+            conn.queue(probe.clone());
+        }
+    }
+}
+
+/// Metric lines of the interval since `last` was taken
+fn report(shared: &Shared, metrics: &Metrics, totals: &Totals, last: &Totals) -> Vec<String> {
+    let settings = &metrics.settings;
+    let tags = if settings.tags.is_empty() {
+        String::new()
+    } else {
+        format!("|#{}", settings.tags.join(","))
+    };
+    let mut lines = Vec::new();
+    let mut line = |name: &str, value: String, kind: &str| {
+        let mut metric = String::new();
+        if !settings.prefix.is_empty() {
+            let _ = write!(metric, "{}.", settings.prefix);
+        }
+        let _ = write!(metric, "{}:{}|{}{}", name, value, kind, tags);
+        lines.push(metric);
+    };
+
+    let (connections, queued_bytes) = {
+        let conns = shared.connections.values();
+        let queued: usize = conns.iter().map(|conn| conn.pending_bytes()).sum();
+        (conns.len(), queued)
+    };
+    let suspended = shared.sessions.lock().suspended().len();
+    line("connections", connections.to_string(), "g");
+    line("sessions.suspended", suspended.to_string(), "g");
+    line("queued_bytes", queued_bytes.to_string(), "g");
+    for ((name, count), last) in COUNTER_NAMES.iter().zip(totals.counts).zip(last.counts) {
+        line(name, count.wrapping_sub(last).to_string(), "c");
+    }
+    let histograms = shared.histograms.named();
+    for (((name, _), now), then) in histograms
+        .iter()
+        .zip(&totals.histograms)
+        .zip(&last.histograms)
+    {
+        let interval = now.since(then);
+        if interval.count() > 0 {
+            for (percentile, value) in interval.percentiles() {
+                line(&format!("{}.{}", name, percentile), value.to_string(), "g");
+            }
+        }
+    }
+
+    let mut samples = std::mem::take(&mut *metrics.rtt_us.lock());
+    if !samples.is_empty() {
+        samples.sort_unstable();
+        let ms = |us: u64| format!("{:.3}", us as f64 / 1000.0);
+        for (name, percentile) in [("rtt.p50", 50), ("rtt.p95", 95), ("rtt.p99", 99)] {
+            let rank = (samples.len() * percentile).div_ceil(100).max(1) - 1;
+            line(name, ms(samples[rank]), "g");
+        }
+        line("rtt.max", ms(samples[samples.len() - 1]), "g");
+    }
+    lines
+}
+
+/// Send metric lines to the agent at `address`, binding a socket of its address
+/// family first if needed. The address is looked up again each push, following DNS
+/// changes.
+async fn push(
+    socket: &mut Option<UdpSocket>,
+    address: &str,
+    lines: &[String],
+    max_packet_size: usize,
+) -> std::io::Result<()> {
+    let addr = tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or(std::io::ErrorKind::NotFound)?;
+    let bound = socket
+        .as_ref()
+        .and_then(|socket| socket.local_addr().ok())
+        .is_some_and(|local| local.is_ipv4() == addr.is_ipv4());
+    if !bound {
+        let local: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        *socket = Some(UdpSocket::bind(local).await?);
+    }
+    let Some(socket) = socket.as_ref() else {
+        unreachable!("a socket was just bound");
+    };
+    for packet in packets(lines, max_packet_size) {
+        socket.send_to(packet.as_bytes(), addr).await?;
+    }
+    Ok(())
+}
+
+/// Join metric lines into packets of at most `max_size` bytes, one per line if
+/// they don't fit together
+fn packets(lines: &[String], max_size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > max_size {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
@@ -0,0 +1,130 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Host middleware run on every message
+//!
+//! Middleware are host callbacks ordered by priority, ties in registration
+//! order. Inbound messages pass through them in ascending order before the
+//! library looks at them, and outbound messages in descending order after the
+//! host or the library sent them, so layers nest: with decryption before
+//! decompression on the way in, compression comes before encryption on the way
+//! out. Each callback can pass a message on, replace it or drop it, which ends
+//! the chain.
+
+use std::sync::Arc;
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::types::DwebbleWSMiddlewareAction;
+
+/// A message passing through a middleware callback
+pub struct MiddlewareMessage<'a> {
+    pub inbound: bool,
+    pub connection_id: u64,
+    /// Whether the message is text; a replacement is text if this is still set
+    pub is_text: bool,
+    pub data: &'a [u8],
+    /// Data replacing the message's with `Modify`, empty if left unset
+    pub replacement: Option<Vec<u8>>,
+}
+
+/// Called with every message, on any thread, to pass it on, replace it or drop it
+pub type Callback =
+    Arc<dyn Fn(&mut MiddlewareMessage) -> DwebbleWSMiddlewareAction + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Middleware {
+    id: u64,
+    priority: i32,
+    callback: Callback,
+}
+
+/// Registered middleware, ordered
+#[derive(Default)]
+pub struct Chain {
+    last_id: u64,
+    /// Shared with messages in flight, so callbacks may change the chain
+    middleware: Arc<Vec<Middleware>>,
+}
+
+impl Chain {
+    /// Register a callback, returning its ID
+    pub fn add(&mut self, callback: Callback, priority: i32) -> u64 {
+        self.last_id += 1;
+        let mut middleware = Vec::clone(&self.middleware);
+        middleware.push(Middleware {
+            id: self.last_id,
+            priority,
+            callback,
+        });
+        // Stable, so equal priorities keep registration order
+        middleware.sort_by_key(|m| m.priority);
+        self.middleware = Arc::new(middleware);
+        self.last_id
+    }
+
+    /// Unregister a callback. Returns false if the ID is unknown.
+    pub fn remove(&mut self, id: u64) -> bool {
+        if !self.middleware.iter().any(|m| m.id == id) {
+            return false;
+        }
+        let middleware = self.middleware.iter().filter(|m| m.id != id).cloned();
+        self.middleware = Arc::new(middleware.collect());
+        true
+    }
+
+    /// The current middleware, `None` if there is none
+    pub fn snapshot(&self) -> Option<Arc<Vec<Middleware>>> {
+        (!self.middleware.is_empty()).then(|| Arc::clone(&self.middleware))
+    }
+}
+
+/// Pass a message through `middleware`. Returns `None` if it was dropped.
+pub fn run(
+    middleware: &[Middleware],
+    inbound: bool,
+    connection_id: u64,
+    mut msg: Message,
+) -> Option<Message> {
+    let mut order: Box<dyn Iterator<Item = &Middleware>> = if inbound {
+        Box::new(middleware.iter())
+    } else {
+        Box::new(middleware.iter().rev())
+    };
+
+    order.try_for_each(|m| {
+        let (is_text, data): (bool, &[u8]) = match &msg {
+            Message::Text(text) => (true, text.as_bytes()),
+            Message::Binary(data) => (false, data),
+            _ => return Some(()),
+        };
+        let mut message = MiddlewareMessage {
+            inbound,
+            connection_id,
+            is_text,
+            data,
+            replacement: None,
+        };
+        let action = (m.callback)(&mut message);
+        let replacement = message.replacement;
+
+        match action {
+            DwebbleWSMiddlewareAction::Pass => {}
+            DwebbleWSMiddlewareAction::Modify => {
+                let data = replacement.unwrap_or_default();
+                msg = if message.is_text {
+                    match String::from_utf8(data) {
+                        Ok(text) => Message::Text(text.into()),
+                        Err(e) => Message::Binary(e.into_bytes().into()),
+                    }
+                } else {
+                    Message::Binary(data.into())
+                };
+            }
+            DwebbleWSMiddlewareAction::Drop => return None,
+        }
+        Some(())
+    })?;
+    Some(msg)
+}
@@ -0,0 +1,91 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Moving live connections between servers in the same process
+//!
+//! A migrating connection leaves its reader loop between messages, so the
+//! WebSocket stream moves as a whole, together with partly read frames, queued
+//! messages and encryption keys. Its socket is registered anew with the target
+//! server's runtime, so the connection outlives the server it came from.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::DuplexStream;
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio_tungstenite::MaybeTlsStream;
+
+use crate::server::Shared;
+use crate::types::DwebbleWSResult;
+
+/// A request to hand a connection over to another server
+pub struct Migration {
+    pub shared: Arc<Shared>,
+    /// Runtime of the target server, which takes over the connection's tasks
+    pub runtime: Handle,
+    pub done: oneshot::Sender<DwebbleWSResult>,
+}
+
+/// Streams a connection can carry to another server's runtime
+pub trait Rehome {
+    /// Whether the stream can leave the server that accepted it
+    const MIGRATABLE: bool = true;
+
+    /// Register the underlying socket with the runtime of the current context
+    fn rehome(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Rehome for TcpStream {
+    fn rehome(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        let socket = std::os::fd::AsFd::as_fd(self).try_clone_to_owned()?;
+        #[cfg(windows)]
+        let socket = std::os::windows::io::AsSocket::as_socket(self).try_clone_to_owned()?;
+        let socket = std::net::TcpStream::from(socket);
+        socket.set_nonblocking(true)?;
+        // Dropping the original deregisters it from the old runtime; the duplicate
+        // keeps the socket open
+        *self = TcpStream::from_std(socket)?;
+        Ok(())
+    }
+}
+
+/// Loopback streams live in memory and work on any runtime
+impl Rehome for DuplexStream {}
+
+/// Streams of connections the server dialed
+impl Rehome for MaybeTlsStream<TcpStream> {
+    fn rehome(&mut self) -> io::Result<()> {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.rehome(),
+            #[cfg(feature = "tls")]
+            MaybeTlsStream::Rustls(stream) => stream.get_mut().0.rehome(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Remote address of a connection the server dialed
+pub fn peer_addr(stream: &MaybeTlsStream<TcpStream>) -> Option<SocketAddr> {
+    match stream {
+        MaybeTlsStream::Plain(stream) => stream.peer_addr().ok(),
+        #[cfg(feature = "tls")]
+        MaybeTlsStream::Rustls(stream) => stream.get_ref().0.peer_addr().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<S: Rehome> Rehome for tokio_rustls::server::TlsStream<S> {
+    const MIGRATABLE: bool = S::MIGRATABLE;
+
+    fn rehome(&mut self) -> io::Result<()> {
+        self.get_mut().0.rehome()
+    }
+}
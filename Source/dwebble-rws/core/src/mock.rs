@@ -0,0 +1,87 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Built-in mock server behaviors (echo, broadcast-echo, scripted replies)
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::Shared;
+use crate::settings::{MockMode, MockRule, MockSettings};
+
+impl MockRule {
+    fn matches(&self, payload: &[u8]) -> bool {
+        if let Some(equals) = &self.equals {
+            if payload != equals.as_bytes() {
+                return false;
+            }
+        }
+        if let Some(contains) = &self.contains {
+            let needle = contains.as_bytes();
+            if !needle.is_empty() && !payload.windows(needle.len()).any(|w| w == needle) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn reply_message(&self) -> Message {
+        if self.binary {
+            Message::Binary(self.reply.clone().into_bytes().into())
+        } else {
+            Message::Text(self.reply.clone().into())
+        }
+    }
+}
+
+/// React to a data message received from `connection_id`
+pub fn on_message(shared: &Arc<Shared>, mock: &MockSettings, connection_id: u64, msg: &Message) {
+    match mock.mode {
+        MockMode::Echo => {
+            shared.send_message(connection_id, msg.clone());
+        }
+        MockMode::BroadcastEcho => broadcast(shared, msg),
+        MockMode::Scripted => {
+            let payload: &[u8] = match msg {
+                Message::Text(text) => text.as_bytes(),
+                Message::Binary(data) => data,
+                _ => return,
+            };
+            let Some(rule) = mock.rules.iter().find(|rule| rule.matches(payload)) else {
+                return;
+            };
+
+            let reply = rule.reply_message();
+            let broadcast_reply = rule.broadcast;
+            if rule.delay_ms == 0 {
+                send_reply(shared, connection_id, reply, broadcast_reply);
+                return;
+            }
+
+            let shared = Arc::clone(shared);
+            let delay = Duration::from_millis(rule.delay_ms);
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                send_reply(&shared, connection_id, reply, broadcast_reply);
+            });
+        }
+    }
+}
+
+fn send_reply(shared: &Shared, connection_id: u64, reply: Message, broadcast_reply: bool) {
+    if broadcast_reply {
+        broadcast(shared, &reply);
+    } else {
+        shared.send_message(connection_id, reply);
+    }
+}
+
+fn broadcast(shared: &Shared, msg: &Message) {
+    let ids: Vec<u64> = shared.connections.ids();
+    for id in ids {
+        shared.send_message(id, msg.clone());
+    }
+}
@@ -0,0 +1,546 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Minimal MQTT 3.1.1 broker over WebSocket
+//!
+//! Connections that negotiate the `mqtt` subprotocol speak MQTT in binary
+//! frames. The broker supports CONNECT, SUBSCRIBE, UNSUBSCRIBE and PUBLISH with
+//! QoS 0 and 1, retained messages, last wills and keep-alive. QoS 1 deliveries
+//! are not retried, and sessions are not persisted across connections.
+//!
+//! Every client publication raises an `MqttPublish` event; the host publishes
+//! with `dwebble_rws_server_mqtt_publish`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+
+/// WebSocket subprotocol of MQTT clients
+pub const SUBPROTOCOL: &str = "mqtt";
+
+/// How often keep-alive deadlines are checked
+const KEEP_ALIVE_CHECK: Duration = Duration::from_secs(1);
+
+/// Close code sent to clients that break the protocol or miss their keep-alive
+const PROTOCOL_ERROR_CLOSE_CODE: u16 = 1002;
+
+/// Control packet types
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// CONNACK return code for an unsupported protocol level
+const UNACCEPTABLE_PROTOCOL_VERSION: u8 = 1;
+
+/// SUBACK return code for a rejected subscription
+const SUBSCRIPTION_FAILURE: u8 = 0x80;
+
+/// An application message
+#[derive(Clone)]
+pub struct Publication {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+struct Client {
+    /// Received bytes not yet forming a complete packet
+    buffer: Vec<u8>,
+    connected: bool,
+    keep_alive: Option<Duration>,
+    last_packet: Instant,
+    /// Granted QoS of each topic filter
+    subscriptions: HashMap<String, u8>,
+    next_packet_id: u16,
+    /// Published if the connection ends without DISCONNECT
+    will: Option<Publication>,
+}
+
+impl Client {
+    fn packet_id(&mut self) -> u16 {
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        self.next_packet_id
+    }
+}
+
+/// MQTT clients and retained messages
+#[derive(Default)]
+pub struct Mqtt {
+    clients: HashMap<u64, Client>,
+    retained: HashMap<String, Publication>,
+}
+
+impl Mqtt {
+    pub fn is_client(&self, connection_id: u64) -> bool {
+        self.clients.contains_key(&connection_id)
+    }
+
+    /// Forget a closed connection. Returns its will if it ended without DISCONNECT.
+    pub fn remove_connection(&mut self, connection_id: u64) -> Option<Publication> {
+        self.clients.remove(&connection_id)?.will
+    }
+
+    pub fn clear(&mut self) {
+        self.clients.clear();
+        self.retained.clear();
+    }
+}
+
+/// Start tracking a connection that negotiated the MQTT subprotocol
+pub fn on_open(shared: &Shared, connection_id: u64) {
+    shared.mqtt.lock().clients.insert(
+        connection_id,
+        Client {
+            buffer: Vec::new(),
+            connected: false,
+            keep_alive: None,
+            last_packet: Instant::now(),
+            subscriptions: HashMap::new(),
+            next_packet_id: 0,
+            will: None,
+        },
+    );
+}
+
+/// Handle inbound bytes, which may hold partial or several packets
+pub fn on_data(shared: &Shared, connection_id: u64, data: &[u8]) {
+    let packets = {
+        let mut mqtt = shared.mqtt.lock();
+        let Some(client) = mqtt.clients.get_mut(&connection_id) else {
+            return;
+        };
+        client.buffer.extend_from_slice(data);
+        split_packets(&mut client.buffer)
+    };
+
+    let Some(packets) = packets else {
+        close(shared, connection_id, "malformed packet");
+        return;
+    };
+    for (header, body) in packets {
+        if let Err(reason) = on_packet(shared, connection_id, header, &body) {
+            close(shared, connection_id, reason);
+            return;
+        }
+    }
+}
+
+/// Deliver a publication to matching subscribers, and to the host as an
+/// `MqttPublish` event if a client published it
+pub fn route(shared: &Shared, publication: Publication, publisher: Option<u64>) {
+    let deliveries: Vec<(u64, Vec<u8>)> = {
+        let mut mqtt = shared.mqtt.lock();
+        if publication.retain {
+            if publication.payload.is_empty() {
+                mqtt.retained.remove(&publication.topic);
+            } else {
+                mqtt.retained
+                    .insert(publication.topic.clone(), publication.clone());
+            }
+        }
+
+        mqtt.clients
+            .iter_mut()
+            .filter(|(_, client)| client.connected)
+            .filter_map(|(&id, client)| {
+                let granted = client
+                    .subscriptions
+                    .iter()
+                    .filter(|(filter, _)| matches(filter, &publication.topic))
+                    .map(|(_, &qos)| qos)
+                    .max()?;
+                let qos = granted.min(publication.qos);
+                Some((id, encode_publish(client, &publication, qos, false)))
+            })
+            .collect()
+    };
+
+    for (connection_id, packet) in deliveries {
+        send(shared, connection_id, packet);
+    }
+
+    if let Some(connection_id) = publisher {
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::MqttPublish,
+            connection_id,
+            data: Some(publication.payload),
+            error: Some(publication.topic),
+            request_id: 0,
+        });
+    }
+}
+
+/// Close clients whose keep-alive expired. Runs until the runtime shuts down.
+pub async fn run_keep_alive(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(KEEP_ALIVE_CHECK);
+
+    loop {
+        interval.tick().await;
+
+        let expired: Vec<u64> = shared
+            .mqtt
+            .lock()
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                // Clients get one and a half keep-alive periods, per the spec
+                client
+                    .keep_alive
+                    .is_some_and(|keep_alive| client.last_packet.elapsed() > keep_alive * 3 / 2)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for connection_id in expired {
+            close(&shared, connection_id, "keep-alive timeout");
+        }
+    }
+}
+
+/// Whether a topic name is valid to publish to
+pub fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && !topic.contains(['+', '#', '\0'])
+}
+
+fn on_packet(
+    shared: &Shared,
+    connection_id: u64,
+    header: u8,
+    body: &[u8],
+) -> Result<(), &'static str> {
+    let packet_type = header >> 4;
+    let mut reader = Reader { data: body };
+
+    let connected = {
+        let mut mqtt = shared.mqtt.lock();
+        let client = mqtt
+            .clients
+            .get_mut(&connection_id)
+            .ok_or("unknown client")?;
+        client.last_packet = Instant::now();
+        client.connected
+    };
+    if connected == (packet_type == CONNECT) {
+        return Err("unexpected packet");
+    }
+
+    match packet_type {
+        CONNECT => {
+            let protocol = reader.string()?;
+            let level = reader.byte()?;
+            if protocol != "MQTT" || level != 4 {
+                send(
+                    shared,
+                    connection_id,
+                    vec![CONNACK << 4, 2, 0, UNACCEPTABLE_PROTOCOL_VERSION],
+                );
+                return Err("unsupported protocol version");
+            }
+            let flags = reader.byte()?;
+            let keep_alive = reader.u16()?;
+            let _client_id = reader.string()?;
+            let will = if flags & 0x04 != 0 {
+                let topic = reader.string()?;
+                let payload = reader.binary()?.to_vec();
+                if !is_valid_topic(&topic) {
+                    return Err("invalid will topic");
+                }
+                Some(Publication {
+                    topic,
+                    payload,
+                    qos: ((flags >> 3) & 0x03).min(1),
+                    retain: flags & 0x20 != 0,
+                })
+            } else {
+                None
+            };
+            // Credentials are accepted as is
+
+            {
+                let mut mqtt = shared.mqtt.lock();
+                let client = mqtt
+                    .clients
+                    .get_mut(&connection_id)
+                    .ok_or("unknown client")?;
+                client.connected = true;
+                client.keep_alive =
+                    (keep_alive > 0).then(|| Duration::from_secs(u64::from(keep_alive)));
+                client.will = will;
+            }
+            send(shared, connection_id, vec![CONNACK << 4, 2, 0, 0]);
+        }
+        PUBLISH => {
+            let qos = (header >> 1) & 0x03;
+            if qos > 1 {
+                return Err("QoS 2 is not supported");
+            }
+            let topic = reader.string()?;
+            if !is_valid_topic(&topic) {
+                return Err("invalid topic");
+            }
+            let packet_id = if qos > 0 { Some(reader.u16()?) } else { None };
+            let publication = Publication {
+                topic,
+                payload: reader.data.to_vec(),
+                qos,
+                retain: header & 0x01 != 0,
+            };
+
+            if let Some(packet_id) = packet_id {
+                let [hi, lo] = packet_id.to_be_bytes();
+                send(shared, connection_id, vec![PUBACK << 4, 2, hi, lo]);
+            }
+            route(shared, publication, Some(connection_id));
+        }
+        // Deliveries are not retried, so acknowledgements need no bookkeeping
+        PUBACK => {}
+        SUBSCRIBE => {
+            let packet_id = reader.u16()?;
+            let mut filters = Vec::new();
+            while !reader.data.is_empty() {
+                let filter = reader.string()?;
+                let qos = reader.byte()?;
+                filters.push((filter, qos));
+            }
+            if filters.is_empty() {
+                return Err("empty subscription");
+            }
+
+            let mut codes = Vec::with_capacity(filters.len());
+            let mut retained = Vec::new();
+            {
+                let mut mqtt = shared.mqtt.lock();
+                let mqtt = &mut *mqtt;
+                let client = mqtt
+                    .clients
+                    .get_mut(&connection_id)
+                    .ok_or("unknown client")?;
+                for (filter, qos) in filters {
+                    if !is_valid_filter(&filter) || qos > 2 {
+                        codes.push(SUBSCRIPTION_FAILURE);
+                        continue;
+                    }
+                    let granted = qos.min(1);
+                    for publication in mqtt.retained.values() {
+                        if matches(&filter, &publication.topic) {
+                            let qos = granted.min(publication.qos);
+                            retained.push(encode_publish(client, publication, qos, true));
+                        }
+                    }
+                    client.subscriptions.insert(filter, granted);
+                    codes.push(granted);
+                }
+            }
+
+            let [hi, lo] = packet_id.to_be_bytes();
+            let mut suback = vec![SUBACK << 4];
+            encode_length(&mut suback, 2 + codes.len());
+            suback.extend_from_slice(&[hi, lo]);
+            suback.extend_from_slice(&codes);
+            send(shared, connection_id, suback);
+            for packet in retained {
+                send(shared, connection_id, packet);
+            }
+        }
+        UNSUBSCRIBE => {
+            let packet_id = reader.u16()?;
+            let mut filters = Vec::new();
+            while !reader.data.is_empty() {
+                filters.push(reader.string()?);
+            }
+            if let Some(client) = shared.mqtt.lock().clients.get_mut(&connection_id) {
+                for filter in filters {
+                    client.subscriptions.remove(&filter);
+                }
+            }
+            let [hi, lo] = packet_id.to_be_bytes();
+            send(shared, connection_id, vec![UNSUBACK << 4, 2, hi, lo]);
+        }
+        PINGREQ => send(shared, connection_id, vec![PINGRESP << 4, 0]),
+        DISCONNECT => {
+            // A clean disconnect discards the will
+            if let Some(client) = shared.mqtt.lock().clients.get_mut(&connection_id) {
+                client.will = None;
+            }
+            if let Some(conn) = shared.connections.get(connection_id) {
+                conn.close();
+            }
+        }
+        _ => return Err("unsupported packet type"),
+    }
+    Ok(())
+}
+
+/// Take every complete packet off the front of `buffer`.
+/// Returns None if the stream is malformed.
+fn split_packets(buffer: &mut Vec<u8>) -> Option<Vec<(u8, Vec<u8>)>> {
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let rest = &buffer[offset..];
+        let Some(&header) = rest.first() else {
+            break;
+        };
+
+        // Remaining length: up to four bytes, seven bits each
+        let mut length = 0usize;
+        let mut length_bytes = 0;
+        let complete = loop {
+            let Some(&byte) = rest.get(1 + length_bytes) else {
+                break false;
+            };
+            length |= usize::from(byte & 0x7f) << (7 * length_bytes);
+            length_bytes += 1;
+            if byte & 0x80 == 0 {
+                break true;
+            }
+            if length_bytes == 4 {
+                return None;
+            }
+        };
+        if !complete {
+            break;
+        }
+
+        let start = 1 + length_bytes;
+        if rest.len() < start + length {
+            break;
+        }
+        packets.push((header, rest[start..start + length].to_vec()));
+        offset += start + length;
+    }
+
+    buffer.drain(..offset);
+    Some(packets)
+}
+
+fn encode_length(packet: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_publish(
+    client: &mut Client,
+    publication: &Publication,
+    qos: u8,
+    retain: bool,
+) -> Vec<u8> {
+    let topic = publication.topic.as_bytes();
+    let packet_id = (qos > 0).then(|| client.packet_id());
+    let length =
+        2 + topic.len() + if packet_id.is_some() { 2 } else { 0 } + publication.payload.len();
+
+    let mut packet = Vec::with_capacity(5 + length);
+    packet.push(PUBLISH << 4 | qos << 1 | u8::from(retain));
+    encode_length(&mut packet, length);
+    packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+    packet.extend_from_slice(topic);
+    if let Some(packet_id) = packet_id {
+        packet.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    packet.extend_from_slice(&publication.payload);
+    packet
+}
+
+/// A topic filter: `+` matches one level and a trailing `#` any remaining levels
+fn is_valid_filter(filter: &str) -> bool {
+    if filter.is_empty() || filter.contains('\0') {
+        return false;
+    }
+    let levels: Vec<&str> = filter.split('/').collect();
+    levels.iter().enumerate().all(|(i, level)| match *level {
+        "#" => i == levels.len() - 1,
+        "+" => true,
+        level => !level.contains(['+', '#']),
+    })
+}
+
+fn matches(filter: &str, topic: &str) -> bool {
+    // Wildcards do not match topics reserved for the broker
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        if level == "#" {
+            return true;
+        }
+        match topic_levels.next() {
+            Some(topic_level) if level == "+" || level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+fn send(shared: &Shared, connection_id: u64, packet: Vec<u8>) {
+    shared.send_message(connection_id, Message::Binary(packet.into()));
+}
+
+fn close(shared: &Shared, connection_id: u64, reason: &str) {
+    if let Some(conn) = shared.connections.get(connection_id) {
+        tracing::warn!("Closing MQTT client {}: {}", connection_id, reason);
+        conn.terminate(PROTOCOL_ERROR_CLOSE_CODE, reason);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if self.data.len() < len {
+            return Err("truncated packet");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Length-prefixed binary data
+    fn binary(&mut self) -> Result<&'a [u8], &'static str> {
+        let len = self.u16()?;
+        self.take(usize::from(len))
+    }
+
+    /// Length-prefixed UTF-8 string
+    fn string(&mut self) -> Result<String, &'static str> {
+        let bytes = self.binary()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "invalid UTF-8 string")
+    }
+}
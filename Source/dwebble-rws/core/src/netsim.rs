@@ -0,0 +1,155 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Network condition simulation (latency, jitter, loss, bandwidth caps)
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::time::Instant;
+
+use crate::server::Shared;
+use crate::settings::NetworkSimSettings;
+
+/// How often connections are checked for simulated disconnects
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reason reported for connections dropped by the simulation
+pub const DISCONNECT_REASON: &str = "Simulated network failure";
+
+/// Small non-cryptographic PRNG (xorshift64*) for fault decisions
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new() -> Self {
+        let mut seed = [0u8; 8];
+        let _ = SystemRandom::new().fill(&mut seed);
+        Self(u64::from_le_bytes(seed) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform value in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether an event with probability `p` happens
+    pub fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+}
+
+/// Messages held back until their simulated delivery time, in order.
+///
+/// With no simulation active, items are ready immediately.
+pub struct DelayQueue<T> {
+    rng: Rng,
+    queue: VecDeque<(Instant, T)>,
+    /// Delivery time of the last queued item; later items never overtake it
+    last_delivery: Instant,
+    /// When the simulated link is free to carry the next item
+    link_free: Instant,
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            rng: Rng::new(),
+            queue: VecDeque::new(),
+            last_delivery: now,
+            link_free: now,
+        }
+    }
+
+    /// Queue an item of `len` bytes. Returns false if the simulation dropped it;
+    /// only `droppable` items (data messages) are ever dropped.
+    pub fn push(
+        &mut self,
+        sim: Option<NetworkSimSettings>,
+        item: T,
+        len: usize,
+        droppable: bool,
+    ) -> bool {
+        let now = Instant::now();
+        let Some(sim) = sim else {
+            let at = now.max(self.last_delivery);
+            self.last_delivery = at;
+            self.queue.push_back((at, item));
+            return true;
+        };
+
+        if droppable && self.rng.chance(sim.drop_rate) {
+            return false;
+        }
+
+        let jitter_ms = match sim.jitter_ms {
+            0 => 0,
+            jitter => self.rng.next_u64() % (jitter + 1),
+        };
+        let mut at =
+            (now + Duration::from_millis(sim.latency_ms + jitter_ms)).max(self.last_delivery);
+
+        if sim.bandwidth_bytes_per_sec > 0 {
+            at = at.max(self.link_free);
+            self.link_free =
+                at + Duration::from_secs_f64(len as f64 / sim.bandwidth_bytes_per_sec as f64);
+        }
+
+        self.last_delivery = at;
+        self.queue.push_back((at, item));
+        true
+    }
+
+    /// Take every queued item regardless of its delivery time
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.queue.drain(..).map(|(_, item)| item)
+    }
+
+    /// Wait for the next item to become deliverable. Pending while empty.
+    pub async fn ready(&mut self) -> T {
+        let Some(&(at, _)) = self.queue.front() else {
+            return std::future::pending().await;
+        };
+        // A timer due already still waits for the next tick of the clock
+        if at > Instant::now() {
+            tokio::time::sleep_until(at).await;
+        }
+        self.queue.pop_front().unwrap().1
+    }
+}
+
+/// Periodically drop connections at random according to `disconnect_rate`.
+/// Runs until the runtime shuts down.
+pub async fn run(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut rng = Rng::new();
+    let per_tick = CHECK_INTERVAL.as_secs_f64();
+
+    loop {
+        interval.tick().await;
+
+        for conn in shared.connections.values() {
+            let Some(sim) = shared.network_sim(&conn) else {
+                continue;
+            };
+            if rng.chance(sim.disconnect_rate * per_tick) {
+                tracing::info!(
+                    "Simulating network failure for {} (id: {})",
+                    conn.remote_addr,
+                    conn.id
+                );
+                conn.sever(DISCONNECT_REASON);
+            }
+        }
+    }
+}
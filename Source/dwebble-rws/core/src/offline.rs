@@ -0,0 +1,138 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Offline send queue of clients
+//!
+//! A client with an offline queue reconnects whenever its connection drops.
+//! Messages the host sends while it is disconnected wait in the queue, which drops
+//! its oldest message to make room past `max_messages`, and go out in order once
+//! the connection is back. With the `control_frames` setting, control frames are
+//! not held: the virtual channel, state sync and receipt frames of a connection
+//! mean nothing to the next one.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::control;
+use crate::pool;
+use crate::server::ServerEvent;
+use crate::settings::OfflineQueueSettings;
+use crate::types::DwebbleWSEventType;
+
+pub struct OfflineQueue {
+    settings: OfflineQueueSettings,
+    /// Binary payloads of the host are escaped from control frames
+    control_frames: bool,
+    messages: VecDeque<Message>,
+    event_tx: mpsc::UnboundedSender<ServerEvent>,
+    /// Messages dropped for want of room so far
+    dropped: u64,
+    /// Delay before the next reconnect attempt
+    delay_ms: u64,
+}
+
+impl OfflineQueue {
+    pub fn new(
+        settings: OfflineQueueSettings,
+        control_frames: bool,
+        event_tx: mpsc::UnboundedSender<ServerEvent>,
+    ) -> Self {
+        Self {
+            settings,
+            control_frames,
+            messages: VecDeque::new(),
+            event_tx,
+            dropped: 0,
+            delay_ms: settings.reconnect_delay_ms,
+        }
+    }
+
+    /// Hold `msg` until the client reconnects
+    pub fn push(&mut self, msg: Message) {
+        if let Message::Binary(data) = &msg {
+            let reserved = self.control_frames && control::is_control(data);
+            if reserved && control::unescape(data).is_none() {
+                return;
+            }
+        }
+        self.messages.push_back(msg);
+        if self.messages.len() > self.settings.max_messages {
+            if let Some(oldest) = self.messages.pop_front() {
+                self.drop_message(oldest);
+            }
+        }
+    }
+
+    /// Put back a message the connection dropped before it was sent, to go first on
+    /// the next one
+    pub fn requeue(&mut self, msg: Message) {
+        if self.messages.len() < self.settings.max_messages {
+            self.messages.push_front(msg);
+        } else {
+            self.drop_message(msg);
+        }
+    }
+
+    /// The oldest message held
+    pub fn pop(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    /// Start the reconnect delays over once a connection succeeds
+    pub fn connected(&mut self) {
+        self.delay_ms = self.settings.reconnect_delay_ms;
+    }
+
+    /// Wait before the next reconnect attempt, doubling the wait of the one after
+    pub fn backoff(&mut self) -> impl Future<Output = ()> {
+        let delay = Duration::from_millis(self.delay_ms);
+        self.delay_ms = self
+            .delay_ms
+            .saturating_mul(2)
+            .min(self.settings.max_reconnect_delay_ms);
+        tokio::time::sleep(delay)
+    }
+
+    fn drop_message(&mut self, msg: Message) {
+        self.dropped += 1;
+        let data = match &msg {
+            Message::Binary(data) if self.control_frames => {
+                pool::copy(control::unescape(data).unwrap_or(data))
+            }
+            Message::Binary(data) => pool::copy(data),
+            Message::Text(text) => pool::copy(text.as_bytes()),
+            _ => return,
+        };
+        let _ = self.event_tx.send(ServerEvent {
+            event_type: DwebbleWSEventType::MessageDropped,
+            connection_id: 0,
+            data: Some(data),
+            error: None,
+            request_id: self.dropped,
+        });
+    }
+}
+
+/// Run `until`, holding the messages the host sends meanwhile in `queue`. `None` if
+/// the host closed or dropped the client first.
+pub async fn hold<T>(
+    until: impl Future<Output = T>,
+    rx: &mut mpsc::UnboundedReceiver<Message>,
+    queue: &mut OfflineQueue,
+) -> Option<T> {
+    tokio::pin!(until);
+    loop {
+        tokio::select! {
+            done = &mut until => return Some(done),
+            outbound = rx.recv() => match outbound {
+                Some(Message::Close(_)) | None => return None,
+                Some(msg) => queue.push(msg),
+            },
+        }
+    }
+}
@@ -0,0 +1,45 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Relay mode: pairs of connections piped to each other
+//!
+//! Text and binary messages from a piped connection go to its partner as they are,
+//! without raising events, so a rendezvous server can relay peers that cannot reach
+//! each other directly. A pipe ends when the host removes it or either connection
+//! ends; the remaining connection's messages reach the host again.
+
+use std::collections::HashMap;
+
+/// The partner of each piped connection, both ways
+#[derive(Default)]
+pub struct Pipes {
+    partners: HashMap<u64, u64>,
+}
+
+impl Pipes {
+    /// Pipe two connections. Returns false if either is piped already.
+    pub fn pair(&mut self, a: u64, b: u64) -> bool {
+        if a == b || self.partners.contains_key(&a) || self.partners.contains_key(&b) {
+            return false;
+        }
+        self.partners.insert(a, b);
+        self.partners.insert(b, a);
+        true
+    }
+
+    pub fn partner(&self, connection_id: u64) -> Option<u64> {
+        self.partners.get(&connection_id).copied()
+    }
+
+    /// Remove the pipe of a connection. Returns its partner, if it was piped.
+    pub fn remove_connection(&mut self, connection_id: u64) -> Option<u64> {
+        let partner = self.partners.remove(&connection_id)?;
+        self.partners.remove(&partner);
+        Some(partner)
+    }
+
+    pub fn clear(&mut self) {
+        self.partners.clear();
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Pooled event payload buffers
+//!
+//! The payloads of received messages are copied into buffers taken from free lists
+//! of a few size classes, and go back to them once the host polls the next event.
+//! Under steady traffic of small messages, events reuse the same few allocations
+//! instead of allocating one each. Payloads larger than the largest class are not
+//! pooled.
+
+use parking_lot::Mutex;
+
+use crate::types::DwebbleWSPoolStats;
+
+/// Buffer capacities, smallest first
+const CLASSES: [usize; 6] = [64, 256, 1024, 4096, 16 * 1024, 64 * 1024];
+
+/// Free buffers kept per class; further returned buffers are freed
+const MAX_FREE: usize = 256;
+
+struct Pool {
+    free: [Vec<Vec<u8>>; CLASSES.len()],
+    stats: DwebbleWSPoolStats,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    free: [const { Vec::new() }; CLASSES.len()],
+    stats: DwebbleWSPoolStats {
+        hits: 0,
+        misses: 0,
+        unpooled: 0,
+        free_buffers: 0,
+        free_bytes: 0,
+    },
+});
+
+/// Copy a payload into a pooled buffer
+pub fn copy(data: &[u8]) -> Vec<u8> {
+    let Some(class) = CLASSES.iter().position(|&size| data.len() <= size) else {
+        POOL.lock().stats.unpooled += 1;
+        return data.to_vec();
+    };
+
+    let reused = {
+        let mut pool = POOL.lock();
+        let buf = pool.free[class].pop();
+        match buf {
+            Some(_) => {
+                pool.stats.hits += 1;
+                pool.stats.free_buffers -= 1;
+                pool.stats.free_bytes -= CLASSES[class] as u64;
+            }
+            None => pool.stats.misses += 1,
+        }
+        buf
+    };
+    let mut buf = reused.unwrap_or_else(|| Vec::with_capacity(CLASSES[class]));
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Give a buffer back to its free list. Buffers that are not of a class's capacity
+/// are just freed.
+pub fn release(mut buf: Vec<u8>) {
+    let Some(class) = CLASSES.iter().position(|&size| buf.capacity() == size) else {
+        return;
+    };
+    buf.clear();
+
+    let mut pool = POOL.lock();
+    if pool.free[class].len() < MAX_FREE {
+        pool.free[class].push(buf);
+        pool.stats.free_buffers += 1;
+        pool.stats.free_bytes += CLASSES[class] as u64;
+    }
+}
+
+pub fn stats() -> DwebbleWSPoolStats {
+    POOL.lock().stats
+}
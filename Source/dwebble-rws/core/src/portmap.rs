@@ -0,0 +1,303 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Gateway port mapping
+//!
+//! A player-hosted server behind a home router asks the router to forward a
+//! public TCP port to its WebSocket listener: over UPnP IGD when a gateway
+//! answers SSDP discovery, otherwise over NAT-PMP (RFC 6886). The mapping is
+//! renewed halfway through its lease and removed when the server stops.
+//!
+//! The external address is reported in a `PortMapped` event once the mapping is
+//! in place, and again if a renewal changes it; failures raise `Error` events.
+
+use std::error::Error as StdError;
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use igd_next::aio::tokio::{search_gateway, Tokio};
+use igd_next::{PortMappingProtocol, SearchOptions};
+use tokio::net::UdpSocket;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::PortMappingSettings;
+use crate::types::DwebbleWSEventType;
+
+/// How long to wait for a UPnP gateway to answer discovery
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Port NAT-PMP gateways listen on
+const NAT_PMP_PORT: u16 = 5351;
+
+/// Wait before the first NAT-PMP retransmission, doubled for each one after
+const NAT_PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// NAT-PMP requests sent before giving up
+const NAT_PMP_ATTEMPTS: u32 = 4;
+
+/// Description of the mapping shown in the router's UPnP table
+const DESCRIPTION: &str = "dwebble";
+
+type Error = Box<dyn StdError + Send + Sync>;
+
+/// A port mapping kept alive on the local gateway
+pub struct PortMapping {
+    stop_tx: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl PortMapping {
+    /// Map a port to `listener` and keep renewing it until stopped
+    pub fn start(
+        runtime: &Runtime,
+        shared: Arc<Shared>,
+        settings: PortMappingSettings,
+        listener: SocketAddr,
+    ) -> Self {
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let task = runtime.spawn(run(shared, settings, listener, stop_rx));
+        Self { stop_tx, task }
+    }
+
+    /// Stop renewing and remove the mapping, returning the task to wait on
+    pub fn stop(self) -> JoinHandle<()> {
+        let _ = self.stop_tx.send(true);
+        self.task
+    }
+}
+
+async fn run(
+    shared: Arc<Shared>,
+    settings: PortMappingSettings,
+    listener: SocketAddr,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    let external_port = match settings.external_port {
+        0 => listener.port(),
+        port => port,
+    };
+    let (gateway, local) = match discover(&settings, listener).await {
+        Ok(found) => found,
+        Err(e) => return report(&shared, e),
+    };
+    let mut external = match gateway.map(local, external_port, settings.lease_s).await {
+        Ok(external) => external,
+        Err(e) => return report(&shared, e),
+    };
+    announce(&shared, external);
+
+    let renew_interval = Duration::from_secs(u64::from(settings.lease_s / 2).max(1));
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(renew_interval) => {
+                match gateway.map(local, external.port(), settings.lease_s).await {
+                    Ok(renewed) if renewed != external => {
+                        external = renewed;
+                        announce(&shared, external);
+                    }
+                    Ok(_) => {}
+                    Err(e) => report(&shared, e),
+                }
+            }
+            _ = stop_rx.changed() => {
+                break;
+            }
+        }
+    }
+
+    *shared.mapped_addr.lock() = None;
+    match gateway.unmap(local, external.port()).await {
+        Ok(()) => tracing::info!("Removed port mapping of {}", external),
+        Err(e) => tracing::warn!("Failed to remove port mapping of {}: {}", external, e),
+    }
+}
+
+fn announce(shared: &Shared, external: SocketAddr) {
+    tracing::info!("Gateway forwards {} to the server", external);
+    *shared.mapped_addr.lock() = Some(external);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::PortMapped,
+        connection_id: 0,
+        data: Some(external.to_string().into_bytes()),
+        error: None,
+        request_id: 0,
+    });
+}
+
+fn report(shared: &Shared, error: impl Display) {
+    let message = format!("Port mapping failed: {}", error);
+    tracing::warn!("{}", message);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::Error,
+        connection_id: 0,
+        data: None,
+        error: Some(message),
+        request_id: 0,
+    });
+}
+
+enum Gateway {
+    Upnp(igd_next::aio::Gateway<Tokio>),
+    NatPmp(SocketAddr),
+}
+
+/// Find the gateway and the LAN address it should forward to
+async fn discover(
+    settings: &PortMappingSettings,
+    listener: SocketAddr,
+) -> Result<(Gateway, SocketAddr), Error> {
+    let options = SearchOptions {
+        timeout: Some(SEARCH_TIMEOUT),
+        ..Default::default()
+    };
+    let gateway = match search_gateway(options).await {
+        Ok(gateway) => Gateway::Upnp(gateway),
+        Err(e) => {
+            tracing::debug!("No UPnP gateway, trying NAT-PMP: {}", e);
+            Gateway::NatPmp(nat_pmp_gateway(settings, listener).await?)
+        }
+    };
+
+    let ip = local_ip(listener, gateway.addr()).await?;
+    Ok((gateway, SocketAddr::new(IpAddr::V4(ip), listener.port())))
+}
+
+impl Gateway {
+    fn addr(&self) -> SocketAddr {
+        match self {
+            Self::Upnp(gateway) => gateway.addr,
+            Self::NatPmp(addr) => *addr,
+        }
+    }
+
+    /// Forward `external_port` to `local`, returning the external address
+    async fn map(
+        &self,
+        local: SocketAddr,
+        external_port: u16,
+        lease_s: u32,
+    ) -> Result<SocketAddr, Error> {
+        match self {
+            Self::Upnp(gateway) => {
+                gateway
+                    .add_port(
+                        PortMappingProtocol::TCP,
+                        external_port,
+                        local,
+                        lease_s,
+                        DESCRIPTION,
+                    )
+                    .await?;
+                let ip = gateway.get_external_ip().await?;
+                Ok(SocketAddr::new(ip, external_port))
+            }
+            Self::NatPmp(addr) => {
+                let response = nat_pmp_request(*addr, &[0, 0], 12).await?;
+                let ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+                let port = nat_pmp_map(*addr, local.port(), external_port, lease_s).await?;
+                Ok(SocketAddr::new(IpAddr::V4(ip), port))
+            }
+        }
+    }
+
+    async fn unmap(&self, local: SocketAddr, external_port: u16) -> Result<(), Error> {
+        match self {
+            Self::Upnp(gateway) => {
+                gateway
+                    .remove_port(PortMappingProtocol::TCP, external_port)
+                    .await?;
+            }
+            // A zero lifetime deletes the mapping
+            Self::NatPmp(addr) => {
+                nat_pmp_map(*addr, local.port(), 0, 0).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Request a TCP mapping over NAT-PMP, returning the external port granted
+async fn nat_pmp_map(
+    gateway: SocketAddr,
+    internal_port: u16,
+    external_port: u16,
+    lifetime_s: u32,
+) -> Result<u16, Error> {
+    let mut request = vec![0, 2, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lifetime_s.to_be_bytes());
+
+    let response = nat_pmp_request(gateway, &request, 16).await?;
+    Ok(u16::from_be_bytes([response[10], response[11]]))
+}
+
+/// Send a NAT-PMP request until the gateway answers it successfully
+async fn nat_pmp_request(
+    gateway: SocketAddr,
+    request: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(gateway).await?;
+
+    let mut timeout = NAT_PMP_INITIAL_TIMEOUT;
+    let mut response = vec![0u8; len];
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request).await?;
+        if let Ok(received) = tokio::time::timeout(timeout, socket.recv(&mut response)).await {
+            let received = received?;
+            // Responses echo the opcode with the high bit set
+            if received < len || response[0] != 0 || response[1] != request[1] | 0x80 {
+                return Err("Malformed NAT-PMP response".into());
+            }
+            return match u16::from_be_bytes([response[2], response[3]]) {
+                0 => Ok(response),
+                code => Err(format!("NAT-PMP gateway refused the request (code {})", code).into()),
+            };
+        }
+        timeout *= 2;
+    }
+    Err(format!("No UPnP or NAT-PMP gateway answered (tried {})", gateway).into())
+}
+
+/// NAT-PMP gateway from the settings, or the first host of the local /24 subnet
+async fn nat_pmp_gateway(
+    settings: &PortMappingSettings,
+    listener: SocketAddr,
+) -> Result<SocketAddr, Error> {
+    if !settings.nat_pmp_gateway.is_empty() {
+        let ip: IpAddr = settings.nat_pmp_gateway.parse()?;
+        return Ok(SocketAddr::new(ip, NAT_PMP_PORT));
+    }
+
+    // Any public address finds the interface of the default route
+    let ip = local_ip(listener, SocketAddr::from(([1, 1, 1, 1], NAT_PMP_PORT))).await?;
+    let [a, b, c, _] = ip.octets();
+    Ok(SocketAddr::from(([a, b, c, 1], NAT_PMP_PORT)))
+}
+
+/// LAN address of the listener as seen from `peer`
+async fn local_ip(listener: SocketAddr, peer: SocketAddr) -> Result<Ipv4Addr, Error> {
+    match listener.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => return Ok(ip),
+        IpAddr::V6(ip) if !ip.is_unspecified() => {
+            return Err("Port mapping requires an IPv4 listener".into());
+        }
+        _ => {}
+    }
+
+    // Connecting a UDP socket picks the route without sending anything
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(peer).await?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => Err("No IPv4 route to the gateway".into()),
+    }
+}
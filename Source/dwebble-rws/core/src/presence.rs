@@ -0,0 +1,145 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Which logical users are online, on this instance and its cluster siblings
+//!
+//! A user is online while at least one connection identified as that user is
+//! open on any instance. Connections are identified by the game (typically
+//! after authenticating them); sibling instances announce their users through
+//! the cluster presence channel.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::cluster::InstanceId;
+
+/// Online users, indexed for the queries the server needs
+#[derive(Default)]
+pub struct Presence {
+    /// User id of each identified local connection
+    users: HashMap<u64, String>,
+    /// Local connections of each user
+    connections: HashMap<String, HashSet<u64>>,
+    /// Users online on each sibling instance
+    remote: HashMap<InstanceId, HashSet<String>>,
+}
+
+/// Online status changes caused by an update
+#[derive(Default)]
+pub struct Changes {
+    /// Users that came online
+    pub joined: Vec<String>,
+    /// Users that went offline
+    pub left: Vec<String>,
+    /// Users whose first local connection was added (to announce to siblings)
+    pub local_joined: Vec<String>,
+    /// Users whose last local connection was removed (to announce to siblings)
+    pub local_left: Vec<String>,
+}
+
+impl Presence {
+    /// Identify a local connection as `user_id`, replacing any previous identity
+    pub fn identify(&mut self, connection_id: u64, user_id: &str) -> Changes {
+        if self.users.get(&connection_id).map(String::as_str) == Some(user_id) {
+            return Changes::default();
+        }
+
+        let mut changes = self.remove(connection_id);
+        let online = self.is_online(user_id);
+        let connections = self.connections.entry(user_id.to_string()).or_default();
+        if connections.is_empty() {
+            changes.local_joined.push(user_id.to_string());
+        }
+        connections.insert(connection_id);
+        self.users.insert(connection_id, user_id.to_string());
+
+        if !online {
+            changes.joined.push(user_id.to_string());
+        }
+        changes
+    }
+
+    /// Forget a local connection's identity
+    pub fn remove(&mut self, connection_id: u64) -> Changes {
+        let mut changes = Changes::default();
+        let Some(user_id) = self.users.remove(&connection_id) else {
+            return changes;
+        };
+
+        if let Some(connections) = self.connections.get_mut(&user_id) {
+            connections.remove(&connection_id);
+            if connections.is_empty() {
+                self.connections.remove(&user_id);
+                changes.local_left.push(user_id.clone());
+                if !self.is_online(&user_id) {
+                    changes.left.push(user_id);
+                }
+            }
+        }
+        changes
+    }
+
+    /// A sibling instance announced a user. Returns true if the user came online.
+    pub fn remote_joined(&mut self, instance: InstanceId, user_id: &str) -> bool {
+        let online = self.is_online(user_id);
+        self.remote
+            .entry(instance)
+            .or_default()
+            .insert(user_id.to_string());
+        !online
+    }
+
+    /// A sibling instance lost a user. Returns true if the user went offline.
+    pub fn remote_left(&mut self, instance: &InstanceId, user_id: &str) -> bool {
+        let Some(users) = self.remote.get_mut(instance) else {
+            return false;
+        };
+        if !users.remove(user_id) {
+            return false;
+        }
+        if users.is_empty() {
+            self.remote.remove(instance);
+        }
+        !self.is_online(user_id)
+    }
+
+    /// A sibling instance is gone. Returns the users that went offline with it.
+    pub fn remove_instance(&mut self, instance: &InstanceId) -> Vec<String> {
+        let users = self.remote.remove(instance).unwrap_or_default();
+        users
+            .into_iter()
+            .filter(|user_id| !self.is_online(user_id))
+            .collect()
+    }
+
+    pub fn is_online(&self, user_id: &str) -> bool {
+        self.connections.contains_key(user_id)
+            || self.remote.values().any(|users| users.contains(user_id))
+    }
+
+    pub fn user_id(&self, connection_id: u64) -> Option<&str> {
+        self.users.get(&connection_id).map(String::as_str)
+    }
+
+    /// Users with a connection on this instance
+    pub fn local_users(&self) -> impl Iterator<Item = &str> {
+        self.connections.keys().map(String::as_str)
+    }
+
+    /// Every online user, sorted
+    pub fn online_users(&self) -> Vec<String> {
+        let mut users: HashSet<&str> = self.local_users().collect();
+        for remote in self.remote.values() {
+            users.extend(remote.iter().map(String::as_str));
+        }
+        let mut users: Vec<String> = users.into_iter().map(str::to_string).collect();
+        users.sort_unstable();
+        users
+    }
+
+    pub fn clear(&mut self) {
+        self.users.clear();
+        self.connections.clear();
+        self.remote.clear();
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Inbound rate limits per connection
+//!
+//! Each connection gets a token bucket per limit, holding up to a second's
+//! worth of messages or bytes, so short bursts pass while a flood is held to
+//! the configured rate.
+
+use std::time::{Duration, Instant};
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::connection::Connection;
+use crate::server::{ServerEvent, Shared};
+use crate::settings::{RateLimitAction, RateLimitSettings};
+use crate::types::DwebbleWSEventType;
+
+/// Close code for connections closed over their rate limit (policy violation)
+pub const CLOSE_CODE: u16 = 1008;
+
+/// Reason given when a connection is closed over its rate limit
+pub const CLOSE_REASON: &str = "Rate limit exceeded";
+
+/// Token buckets of one connection
+pub struct RateLimiter {
+    messages: f64,
+    bytes: f64,
+    refilled: Instant,
+    /// Whether the last message had to wait, so a run of delays raises one event
+    pub delaying: bool,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            messages: f64::INFINITY,
+            bytes: f64::INFINITY,
+            refilled: Instant::now(),
+            delaying: false,
+        }
+    }
+
+    /// Take a message of `len` bytes out of the buckets. Returns the exceeded limit
+    /// if the buckets don't hold enough; `overdraw` takes it anyway, and the limit
+    /// comes with how long the connection must wait to pay the debt back.
+    pub fn take(
+        &mut self,
+        limits: &RateLimitSettings,
+        len: usize,
+        overdraw: bool,
+    ) -> Result<(), (&'static str, Duration)> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+
+        let message_rate = f64::from(limits.messages_per_sec);
+        let byte_rate = limits.bytes_per_sec as f64;
+        self.messages = refill(self.messages, message_rate, elapsed);
+        self.bytes = refill(self.bytes, byte_rate, elapsed);
+
+        let exceeded = [
+            (
+                "messages_per_sec",
+                shortfall(self.messages, 1.0, message_rate),
+            ),
+            (
+                "bytes_per_sec",
+                shortfall(self.bytes, len as f64, byte_rate),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, wait_s)| *wait_s > 0.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+        if exceeded.is_none() || overdraw {
+            self.messages -= 1.0;
+            self.bytes -= len as f64;
+        }
+
+        match exceeded {
+            Some((limit, wait_s)) => Err((limit, Duration::from_secs_f64(wait_s))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Seconds until a bucket at `level` holds `cost` (0 if it does already)
+fn shortfall(level: f64, cost: f64, rate: f64) -> f64 {
+    if level >= cost {
+        0.0
+    } else {
+        (cost - level) / rate
+    }
+}
+
+/// Bucket level after `elapsed` seconds at `rate`, holding at most a second's worth
+fn refill(level: f64, rate: f64, elapsed: f64) -> f64 {
+    if rate <= 0.0 {
+        return f64::INFINITY;
+    }
+    (level + rate * elapsed).min(rate)
+}
+
+/// Apply the connection's rate limits to a data message read from it, raising
+/// `RateLimited` if it is over them. Returns whether to deliver the message; a
+/// delayed message is delivered once the wait is over.
+pub async fn check(
+    shared: &Shared,
+    conn: &Connection,
+    limiter: &mut RateLimiter,
+    msg: &Message,
+) -> bool {
+    let Some(limits) = shared.settings.read().rate_limit else {
+        return true;
+    };
+    let delay = limits.action == RateLimitAction::Delay;
+    let (limit, wait) = match limiter.take(&limits, msg.len(), delay) {
+        Ok(()) => {
+            limiter.delaying = false;
+            return true;
+        }
+        Err(exceeded) => exceeded,
+    };
+    if conn.termination().is_some() {
+        // Already closing, e.g. over an earlier message
+        return false;
+    }
+
+    // A run of delayed messages raises one event
+    if !(delay && limiter.delaying) {
+        tracing::warn!(
+            "Client {} (id: {}) exceeded {}",
+            conn.remote_addr,
+            conn.id,
+            limit
+        );
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::RateLimited,
+            connection_id: conn.id,
+            data: (limits.action == RateLimitAction::Drop)
+                .then(|| msg.clone().into_data().to_vec()),
+            error: Some(limit.to_string()),
+            request_id: limits.action as u64,
+        });
+    }
+
+    match limits.action {
+        RateLimitAction::Delay => {
+            limiter.delaying = true;
+            tokio::time::sleep(wait).await;
+            true
+        }
+        RateLimitAction::Drop => false,
+        RateLimitAction::Close => {
+            conn.terminate(CLOSE_CODE, CLOSE_REASON);
+            false
+        }
+    }
+}
@@ -0,0 +1,342 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Raw TCP and UDP listeners
+//!
+//! Trusted server-to-server links can skip WebSocket framing and exchange frames
+//! of a 4-byte big-endian payload length followed by the payload, either over a
+//! TCP stream or packed into UDP datagrams (a datagram may carry several frames,
+//! but a frame never spans datagrams).
+//!
+//! Each TCP stream and each UDP peer address is a connection like any other: it
+//! raises `ClientConnected` (with `raw-tcp` or `raw-udp` as its subprotocol) and
+//! `ClientDisconnected` events, and receives direct sends, room broadcasts and
+//! topic publishes. Frames are received as binary messages; text messages are
+//! sent as their UTF-8 bytes. There is no handshake, so origins are not checked.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::connection::{Connection, Queued};
+use crate::server::{self, ServerEvent, Shared};
+use crate::settings::Settings;
+use crate::types::DwebbleWSEventType;
+
+/// Subprotocol reported for raw TCP connections
+pub const TCP_SUBPROTOCOL: &str = "raw-tcp";
+
+/// Subprotocol reported for raw UDP peers
+pub const UDP_SUBPROTOCOL: &str = "raw-udp";
+
+/// Size of the length prefix of each frame
+const LENGTH_SIZE: usize = 4;
+
+/// Largest inbound frame when no message size limit is set
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// Largest UDP payload
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Accept raw TCP connections until the server stops
+pub async fn run_tcp(shared: Arc<Shared>, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(serve_tcp(stream, addr, Arc::clone(&shared)));
+            }
+            Err(e) => {
+                tracing::error!("Raw TCP accept error: {}", e);
+            }
+        }
+    }
+}
+
+async fn serve_tcp(stream: TcpStream, addr: SocketAddr, shared: Arc<Shared>) {
+    let settings = shared.settings.read().clone();
+    if let Err((_, reason)) = shared.admit_peer(&settings) {
+        tracing::warn!("Refused raw TCP peer {}: {}", addr, reason);
+        return;
+    }
+    let _ = stream.set_nodelay(true);
+
+    let (conn, mut rx) = open(&shared, addr, TCP_SUBPROTOCOL);
+    let connection_id = conn.id;
+    let (read, mut write) = stream.into_split();
+    let reading = read_frames(
+        BufReader::new(read),
+        &shared,
+        connection_id,
+        max_message_size(&settings),
+    );
+    tokio::pin!(reading);
+
+    let error = loop {
+        tokio::select! {
+            next = rx.recv() => {
+                let Some(queued) = next else { break None };
+                let Some(msg) = conn.dequeue(queued) else { continue };
+                let len = msg.len();
+                let closing = msg.is_close();
+                let written = match payload(&msg) {
+                    Some(payload) => write_frame(&mut write, payload).await,
+                    None => Ok(()),
+                };
+                conn.mark_written_len(len);
+                if closing {
+                    break None;
+                }
+                if let Err(e) = written {
+                    break Some(e.to_string());
+                }
+            }
+            e = &mut reading => {
+                break (e.kind() != io::ErrorKind::UnexpectedEof).then(|| e.to_string());
+            }
+            _ = conn.terminated() => {
+                break None;
+            }
+        }
+    };
+    let _ = write.shutdown().await;
+
+    close(&shared, &conn, addr, error);
+}
+
+/// Deliver frames read off a stream until it fails, returning the failure
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut read: R,
+    shared: &Arc<Shared>,
+    connection_id: u64,
+    limit: usize,
+) -> io::Error {
+    loop {
+        let mut length = [0u8; LENGTH_SIZE];
+        if let Err(e) = read.read_exact(&mut length).await {
+            return e;
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length > limit {
+            return io::Error::new(io::ErrorKind::InvalidData, "Frame too large");
+        }
+
+        let mut data = vec![0u8; length];
+        if let Err(e) = read.read_exact(&mut data).await {
+            return e;
+        }
+        server::deliver_inbound(shared, connection_id, Message::Binary(data.into()), None);
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(write: &mut W, payload: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large"))?;
+    write.write_all(&length.to_be_bytes()).await?;
+    write.write_all(payload).await
+}
+
+/// Receive raw UDP datagrams until the server stops, serving each peer address as a connection
+pub async fn run_udp(shared: Arc<Shared>, socket: UdpSocket, idle_timeout_ms: u64) {
+    let socket = Arc::new(socket);
+    let mut peers: HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!("Raw UDP receive error: {}", e);
+                continue;
+            }
+        };
+
+        // A peer whose connection has ended starts a new one
+        let mut datagram = buf[..len].to_vec();
+        if let Some(peer) = peers.get(&addr) {
+            match peer.send(datagram) {
+                Ok(()) => continue,
+                Err(mpsc::error::SendError(returned)) => datagram = returned,
+            }
+        }
+        peers.retain(|_, peer| !peer.is_closed());
+
+        let settings = shared.settings.read().clone();
+        if let Err((_, reason)) = shared.admit_peer(&settings) {
+            tracing::warn!("Refused raw UDP peer {}: {}", addr, reason);
+            continue;
+        }
+
+        let (datagram_tx, datagram_rx) = mpsc::unbounded_channel();
+        let _ = datagram_tx.send(datagram);
+        peers.insert(addr, datagram_tx);
+        let (conn, rx) = open(&shared, addr, UDP_SUBPROTOCOL);
+        tokio::spawn(serve_udp_peer(
+            Arc::clone(&socket),
+            addr,
+            Arc::clone(&shared),
+            conn,
+            rx,
+            datagram_rx,
+            idle_timeout_ms,
+        ));
+    }
+}
+
+async fn serve_udp_peer(
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    conn: Arc<Connection>,
+    mut rx: mpsc::UnboundedReceiver<Queued>,
+    mut datagrams: mpsc::UnboundedReceiver<Vec<u8>>,
+    idle_timeout_ms: u64,
+) {
+    let limit = max_message_size(&shared.settings.read());
+    let connection_id = conn.id;
+    let idle_timeout = Duration::from_millis(idle_timeout_ms);
+    let idle = tokio::time::sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    loop {
+        tokio::select! {
+            next = rx.recv() => {
+                let Some(queued) = next else { break };
+                let Some(msg) = conn.dequeue(queued) else { continue };
+                let len = msg.len();
+                let closing = msg.is_close();
+                if let Some(payload) = payload(&msg) {
+                    if let Err(e) = send_datagram(&socket, addr, payload).await {
+                        tracing::warn!("Dropped raw UDP message to {}: {}", addr, e);
+                    }
+                }
+                conn.mark_written_len(len);
+                if closing {
+                    break;
+                }
+            }
+            next = datagrams.recv() => {
+                let Some(datagram) = next else { break };
+                idle.as_mut().reset(Instant::now() + idle_timeout);
+                match split_frames(&datagram, limit) {
+                    Some(frames) => {
+                        for frame in frames {
+                            let msg = Message::Binary(frame.to_vec().into());
+                            server::deliver_inbound(&shared, connection_id, msg, None);
+                        }
+                    }
+                    None => {
+                        tracing::warn!("Dropped malformed raw UDP datagram from {}", addr);
+                    }
+                }
+            }
+            _ = &mut idle, if idle_timeout_ms > 0 => {
+                tracing::info!("Idle timeout for {} (id: {})", addr, connection_id);
+                break;
+            }
+            _ = conn.terminated() => {
+                break;
+            }
+        }
+    }
+
+    close(&shared, &conn, addr, None);
+}
+
+/// Split a datagram into its frames, or None if it is malformed
+fn split_frames(mut datagram: &[u8], limit: usize) -> Option<Vec<&[u8]>> {
+    let mut frames = Vec::new();
+    while !datagram.is_empty() {
+        let (length, rest) = datagram.split_first_chunk::<LENGTH_SIZE>()?;
+        let length = u32::from_be_bytes(*length) as usize;
+        if length > limit || length > rest.len() {
+            return None;
+        }
+        let (frame, rest) = rest.split_at(length);
+        frames.push(frame);
+        datagram = rest;
+    }
+    Some(frames)
+}
+
+async fn send_datagram(socket: &UdpSocket, addr: SocketAddr, payload: &[u8]) -> io::Result<()> {
+    if LENGTH_SIZE + payload.len() > MAX_DATAGRAM_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Message too large for a datagram",
+        ));
+    }
+
+    let mut datagram = Vec::with_capacity(LENGTH_SIZE + payload.len());
+    datagram.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    datagram.extend_from_slice(payload);
+    socket.send_to(&datagram, addr).await.map(|_| ())
+}
+
+/// Payload of a data message, or None for control messages
+fn payload(msg: &Message) -> Option<&[u8]> {
+    match msg {
+        Message::Binary(data) => Some(data),
+        Message::Text(text) => Some(text.as_bytes()),
+        _ => None,
+    }
+}
+
+fn max_message_size(settings: &Settings) -> usize {
+    match settings.max_message_size {
+        0 => DEFAULT_MAX_MESSAGE_SIZE,
+        size => size,
+    }
+}
+
+/// Register a raw connection and raise its connect event
+fn open(
+    shared: &Shared,
+    addr: SocketAddr,
+    subprotocol: &str,
+) -> (Arc<Connection>, mpsc::UnboundedReceiver<Queued>) {
+    let (tx, rx) = mpsc::unbounded_channel::<Queued>();
+    let conn = Arc::new(Connection::new(
+        addr.to_string(),
+        Some(subprotocol.to_string()),
+        tx,
+    ));
+
+    shared.connections.insert(Arc::clone(&conn));
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientConnected,
+        connection_id: conn.id,
+        data: Some(subprotocol.as_bytes().to_vec()),
+        error: None,
+        request_id: 0,
+    });
+    tracing::info!("Raw peer connected: {} (id: {})", addr, conn.id);
+
+    (conn, rx)
+}
+
+/// Unregister a raw connection and raise its disconnect event
+fn close(shared: &Shared, conn: &Connection, addr: SocketAddr, error: Option<String>) {
+    shared.connections.remove(conn.id);
+    shared.forget_connection(conn.id);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientDisconnected,
+        connection_id: conn.id,
+        data: None,
+        error: conn
+            .termination()
+            .map(|(_, reason)| reason)
+            .or(conn.severed())
+            .or(error),
+        request_id: 0,
+    });
+    tracing::info!("Raw peer disconnected: {} (id: {})", addr, conn.id);
+}
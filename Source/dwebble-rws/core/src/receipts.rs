@@ -0,0 +1,103 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Delivery receipts for sequenced sends
+//!
+//! Sequenced sends are binary messages wrapped in an envelope carrying a
+//! per-connection sequence number, which the client acknowledges on receipt
+//! (little-endian):
+//!
+//! | field    | type |
+//! |----------|------|
+//! | magic    | 4 bytes: `DWSQ` for messages, `DWSK` for acknowledgements |
+//! | sequence | u64: counts up from 1 per connection |
+//! | payload  | remaining bytes (messages only) |
+//!
+//! The server keeps the highest sequence acknowledged by each connection.
+//! Sequences of sends that failed are skipped, so a client that acknowledged a
+//! sequence has seen every successfully sent message before it unless the
+//! network simulation or slow-client policy dropped one.
+
+use std::collections::HashMap;
+
+use crate::control;
+
+const MESSAGE_MAGIC: &[u8; 4] = control::SEQUENCED;
+const ACK_MAGIC: &[u8; 4] = control::RECEIPT;
+const HEADER_LEN: usize = 12;
+
+#[derive(Default)]
+struct Sequences {
+    last_sent: u64,
+    last_acked: u64,
+}
+
+/// Sequences sent to and acknowledged by every connection
+#[derive(Default)]
+pub struct Receipts {
+    connections: HashMap<u64, Sequences>,
+}
+
+impl Receipts {
+    /// Wrap a payload in the next sequence of `connection_id`
+    pub fn wrap(&mut self, connection_id: u64, payload: &[u8]) -> (u64, Vec<u8>) {
+        let sequences = self.connections.entry(connection_id).or_default();
+        sequences.last_sent += 1;
+
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(MESSAGE_MAGIC);
+        data.extend_from_slice(&sequences.last_sent.to_le_bytes());
+        data.extend_from_slice(payload);
+        (sequences.last_sent, data)
+    }
+
+    /// Handle an acknowledgement from `connection_id`. Returns false if `data` is not one.
+    pub fn ack(&mut self, connection_id: u64, data: &[u8]) -> bool {
+        if data.len() != HEADER_LEN || &data[..4] != ACK_MAGIC {
+            return false;
+        }
+        let sequence = u64::from_le_bytes(data[4..].try_into().unwrap());
+
+        // Sequences never sent cannot be acknowledged
+        if let Some(sequences) = self.connections.get_mut(&connection_id) {
+            if sequence <= sequences.last_sent {
+                sequences.last_acked = sequences.last_acked.max(sequence);
+            }
+        }
+        true
+    }
+
+    /// Highest sequence `connection_id` acknowledged, 0 if none
+    pub fn acked(&self, connection_id: u64) -> u64 {
+        self.connections
+            .get(&connection_id)
+            .map_or(0, |sequences| sequences.last_acked)
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.connections.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+}
+
+/// Sequence and payload of a sequenced message, with the acknowledgement to
+/// send back. Returns `None` if `data` is not one.
+pub fn receive(data: &[u8]) -> Option<(u64, &[u8], Vec<u8>)> {
+    if data.len() < HEADER_LEN || &data[..4] != MESSAGE_MAGIC {
+        return None;
+    }
+    let sequence = &data[4..HEADER_LEN];
+
+    let mut ack = Vec::with_capacity(HEADER_LEN);
+    ack.extend_from_slice(ACK_MAGIC);
+    ack.extend_from_slice(sequence);
+    Some((
+        u64::from_le_bytes(sequence.try_into().unwrap()),
+        &data[HEADER_LEN..],
+        ack,
+    ))
+}
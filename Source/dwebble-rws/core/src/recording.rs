@@ -0,0 +1,241 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Event recording and deterministic replay
+//!
+//! A recording starts with an 8-byte magic followed by records laid out as
+//! (little-endian):
+//!
+//! | field         | type |
+//! |---------------|------|
+//! | offset_us     | u64 (time since the server started) |
+//! | event_type    | u8   |
+//! | connection_id | u64  |
+//! | request_id    | u64 (absent in version 1 recordings) |
+//! | data_len      | u32 (`u32::MAX` for no data) |
+//! | data          | `data_len` bytes |
+//! | error_len     | u32 (`u32::MAX` for no error) |
+//! | error         | `error_len` bytes of UTF-8 |
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+
+const MAGIC: &[u8; 8] = b"DWBLREC2";
+const MAGIC_V1: &[u8; 8] = b"DWBLREC1";
+const NONE_LEN: u32 = u32::MAX;
+
+/// Appends every emitted event to a recording file
+pub struct Recorder {
+    file: Mutex<BufWriter<File>>,
+    started: Mutex<Instant>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.flush()?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            started: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Restart the recording clock (called when the server starts)
+    pub fn reset_clock(&self) {
+        *self.started.lock() = Instant::now();
+    }
+
+    pub fn record(&self, event: &ServerEvent) {
+        let offset_us = self.started.lock().elapsed().as_micros() as u64;
+        if let Err(e) = self.write(offset_us, event) {
+            tracing::error!("Event recording failed: {}", e);
+        }
+    }
+
+    fn write(&self, offset_us: u64, event: &ServerEvent) -> io::Result<()> {
+        let mut file = self.file.lock();
+        file.write_all(&offset_us.to_le_bytes())?;
+        file.write_all(&[event.event_type as u8])?;
+        file.write_all(&event.connection_id.to_le_bytes())?;
+        file.write_all(&event.request_id.to_le_bytes())?;
+        write_optional(&mut *file, event.data.as_deref())?;
+        write_optional(&mut *file, event.error.as_deref().map(str::as_bytes))?;
+        file.flush()
+    }
+}
+
+fn write_optional(w: &mut impl Write, bytes: Option<&[u8]>) -> io::Result<()> {
+    match bytes {
+        Some(bytes) => {
+            w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            w.write_all(bytes)
+        }
+        None => w.write_all(&NONE_LEN.to_le_bytes()),
+    }
+}
+
+/// A recorded event with its offset from the start of the recording
+#[derive(Clone)]
+pub struct RecordedEvent {
+    pub offset: Duration,
+    pub event: ServerEvent,
+}
+
+/// Load every event of a recording
+pub fn load(path: &str) -> io::Result<Vec<RecordedEvent>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let has_request_id = match &magic {
+        MAGIC => true,
+        MAGIC_V1 => false,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a dwebble recording",
+            ))
+        }
+    };
+
+    let mut events = Vec::new();
+    while let Some(event) = read_event(&mut reader, has_request_id)? {
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn read_event(r: &mut impl Read, has_request_id: bool) -> io::Result<Option<RecordedEvent>> {
+    let mut offset = [0u8; 8];
+    match r.read_exact(&mut offset) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut event_type = [0u8; 1];
+    r.read_exact(&mut event_type)?;
+    let mut connection_id = [0u8; 8];
+    r.read_exact(&mut connection_id)?;
+    let mut request_id = [0u8; 8];
+    if has_request_id {
+        r.read_exact(&mut request_id)?;
+    }
+
+    let data = read_optional(r)?;
+    let error = read_optional(r)?.map(|e| String::from_utf8_lossy(&e).into_owned());
+
+    Ok(Some(RecordedEvent {
+        offset: Duration::from_micros(u64::from_le_bytes(offset)),
+        event: ServerEvent {
+            event_type: DwebbleWSEventType::from_u8(event_type[0]),
+            connection_id: u64::from_le_bytes(connection_id),
+            data,
+            error,
+            request_id: u64::from_le_bytes(request_id),
+        },
+    }))
+}
+
+fn read_optional(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len == NONE_LEN {
+        return Ok(None);
+    }
+
+    let mut bytes = vec![0u8; len as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+/// Recorded events to play back instead of accepting connections
+pub struct Replay {
+    pub events: Vec<RecordedEvent>,
+    pub speed: f64,
+}
+
+/// Emit recorded events on their original schedule, scaled by `speed`
+pub(crate) async fn replay(shared: Arc<Shared>, events: Vec<RecordedEvent>, speed: f64) {
+    let started = tokio::time::Instant::now();
+    let speed = if speed > 0.0 { speed } else { 1.0 };
+
+    for recorded in events {
+        tokio::time::sleep_until(started + recorded.offset.div_f64(speed)).await;
+        shared.push_event(recorded.event);
+    }
+
+    tracing::info!("Replay finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_events_load_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.rec");
+        let path = path.to_str().unwrap();
+        let events = [
+            ServerEvent {
+                event_type: DwebbleWSEventType::ClientConnected,
+                connection_id: 1,
+                data: None,
+                error: None,
+                request_id: 0,
+            },
+            ServerEvent {
+                event_type: DwebbleWSEventType::MessageReceived,
+                connection_id: 1,
+                data: Some(b"payload".to_vec()),
+                error: None,
+                request_id: 7,
+            },
+            ServerEvent {
+                event_type: DwebbleWSEventType::ClientDisconnected,
+                connection_id: 1,
+                data: Some(vec![]),
+                error: Some("Going away".to_string()),
+                request_id: 0,
+            },
+        ];
+
+        let recorder = Recorder::create(path).unwrap();
+        for event in &events {
+            recorder.record(event);
+        }
+        drop(recorder);
+
+        let loaded = load(path).unwrap();
+        assert_eq!(loaded.len(), events.len());
+        for (loaded, event) in loaded.iter().zip(&events) {
+            assert_eq!(loaded.event.event_type, event.event_type);
+            assert_eq!(loaded.event.connection_id, event.connection_id);
+            assert_eq!(loaded.event.data, event.data);
+            assert_eq!(loaded.event.error, event.error);
+            assert_eq!(loaded.event.request_id, event.request_id);
+        }
+        assert!(loaded.windows(2).all(|w| w[0].offset <= w[1].offset));
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("other.rec");
+        std::fs::write(&path, b"NOTARECORDING").unwrap();
+        let e = load(path.to_str().unwrap()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Request/response correlation
+//!
+//! Requests are binary messages wrapped in an envelope carrying a request ID.
+//! Clients answer with the same envelope and ID (little-endian):
+//!
+//! | field      | type |
+//! |------------|------|
+//! | magic      | 4 bytes: `DWRQ` for requests, `DWRS` for responses |
+//! | request_id | u64  |
+//! | payload    | remaining bytes |
+//!
+//! Only inbound messages answering a pending request of their own connection
+//! are treated as responses, so other traffic is unaffected.
+
+use std::collections::HashMap;
+
+use tokio::task::JoinHandle;
+
+use crate::control;
+
+const REQUEST_MAGIC: &[u8; 4] = control::REQUEST;
+const RESPONSE_MAGIC: &[u8; 4] = control::RESPONSE;
+const ENVELOPE_LEN: usize = 12;
+
+struct Pending {
+    connection_id: u64,
+    timeout: JoinHandle<()>,
+}
+
+/// Requests awaiting a response
+pub struct Requests {
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+}
+
+impl Default for Requests {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl Requests {
+    /// Reserve the ID of a new request
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn insert(&mut self, request_id: u64, connection_id: u64, timeout: JoinHandle<()>) {
+        self.pending.insert(
+            request_id,
+            Pending {
+                connection_id,
+                timeout,
+            },
+        );
+    }
+
+    /// Stop waiting for a request. Returns false if it was not pending.
+    pub fn remove(&mut self, request_id: u64) -> bool {
+        match self.pending.remove(&request_id) {
+            Some(pending) => {
+                pending.timeout.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Match an inbound message against the connection's pending requests.
+    /// Returns the request ID and the response payload.
+    pub fn take_response<'a>(
+        &mut self,
+        connection_id: u64,
+        data: &'a [u8],
+    ) -> Option<(u64, &'a [u8])> {
+        if data.len() < ENVELOPE_LEN || &data[..4] != RESPONSE_MAGIC {
+            return None;
+        }
+        let request_id = u64::from_le_bytes(data[4..ENVELOPE_LEN].try_into().unwrap());
+        self.complete(connection_id, request_id)
+            .then(|| (request_id, &data[ENVELOPE_LEN..]))
+    }
+
+    /// Stop waiting for a request answered by `connection_id`. Returns false
+    /// if it was not pending or belongs to another connection.
+    pub fn complete(&mut self, connection_id: u64, request_id: u64) -> bool {
+        match self.pending.get(&request_id) {
+            Some(pending) if pending.connection_id == connection_id => self.remove(request_id),
+            _ => false,
+        }
+    }
+
+    /// Stop waiting for every request of a connection. Returns their IDs.
+    pub fn remove_connection(&mut self, connection_id: u64) -> Vec<u64> {
+        let ids: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.connection_id == connection_id)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in &ids {
+            self.remove(*id);
+        }
+        ids
+    }
+
+    /// Number of requests awaiting a response
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn clear(&mut self) {
+        for (_, pending) in self.pending.drain() {
+            pending.timeout.abort();
+        }
+    }
+}
+
+/// Wrap a payload in a request envelope
+pub fn encode_request(request_id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(ENVELOPE_LEN + payload.len());
+    data.extend_from_slice(REQUEST_MAGIC);
+    data.extend_from_slice(&request_id.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
@@ -0,0 +1,86 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Resource usage of a server
+//!
+//! Threads and tasks come from the runtime's metrics; memory is approximated by
+//! the bytes held in the server's queues and buffers, which is what grows with
+//! traffic. With the `resource_report_ms` setting, a `ResourceReport` event
+//! carries the same figures as JSON at that interval.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::{DwebbleWSEventType, DwebbleWSResourceUsage};
+
+/// Resource usage of a server now, its runtime's figures left at 0 if it isn't running
+pub fn usage(shared: &Shared, runtime: Option<&Handle>) -> DwebbleWSResourceUsage {
+    let mut usage = DwebbleWSResourceUsage::default();
+    if let Some(runtime) = runtime {
+        let metrics = runtime.metrics();
+        // A current-thread runtime's one worker is the host's thread that ticks it
+        if runtime.runtime_flavor() != RuntimeFlavor::CurrentThread {
+            usage.worker_threads = metrics.num_workers() as u64;
+        }
+        usage.tasks = metrics.num_alive_tasks() as u64;
+        usage.queued_tasks = metrics.global_queue_depth() as u64;
+        #[cfg(target_has_atomic = "64")]
+        {
+            usage.busy_ms = (0..metrics.num_workers())
+                .map(|worker| metrics.worker_total_busy_duration(worker))
+                .sum::<Duration>()
+                .as_millis() as u64;
+        }
+        if shared.settings.read().watchdog.is_some() {
+            usage.helper_threads = 1;
+        }
+    }
+
+    let conns = shared.connections.values();
+    usage.connections = conns.len() as u64;
+    usage.send_queue_bytes = conns.iter().map(|conn| conn.pending_bytes() as u64).sum();
+    usage.session_buffer_bytes = shared.sessions.lock().buffered_bytes() as u64;
+    usage.queued_events = shared.event_backlog.load(Ordering::Relaxed) as u64;
+    usage.event_queue_bytes = shared.event_backlog_bytes.load(Ordering::Relaxed) as u64;
+    usage
+}
+
+/// Raise `ResourceReport` every `interval` until the runtime shuts down
+pub async fn report(shared: Arc<Shared>, interval: Duration) {
+    let runtime = Handle::current();
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let usage = usage(&shared, Some(&runtime));
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::ResourceReport,
+            connection_id: 0,
+            data: Some(to_json(&usage).into_bytes()),
+            error: None,
+            request_id: 0,
+        });
+    }
+}
+
+fn to_json(usage: &DwebbleWSResourceUsage) -> String {
+    json!({
+        "worker_threads": usage.worker_threads,
+        "helper_threads": usage.helper_threads,
+        "tasks": usage.tasks,
+        "queued_tasks": usage.queued_tasks,
+        "busy_ms": usage.busy_ms,
+        "connections": usage.connections,
+        "send_queue_bytes": usage.send_queue_bytes,
+        "session_buffer_bytes": usage.session_buffer_bytes,
+        "queued_events": usage.queued_events,
+        "event_queue_bytes": usage.event_queue_bytes,
+    })
+    .to_string()
+}
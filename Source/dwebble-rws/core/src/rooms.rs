@@ -0,0 +1,91 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Named rooms grouping connections for broadcasts
+
+use std::collections::{HashMap, HashSet};
+
+/// Room membership, indexed both ways
+#[derive(Default)]
+pub struct Rooms {
+    members: HashMap<String, HashSet<u64>>,
+    joined: HashMap<u64, HashSet<String>>,
+}
+
+impl Rooms {
+    /// Add a connection to a room. Returns false if it was already a member.
+    pub fn join(&mut self, room: &str, connection_id: u64) -> bool {
+        let added = self
+            .members
+            .entry(room.to_string())
+            .or_default()
+            .insert(connection_id);
+        if added {
+            self.joined
+                .entry(connection_id)
+                .or_default()
+                .insert(room.to_string());
+        }
+        added
+    }
+
+    /// Remove a connection from a room. Returns false if it was not a member.
+    pub fn leave(&mut self, room: &str, connection_id: u64) -> bool {
+        let Some(members) = self.members.get_mut(room) else {
+            return false;
+        };
+        if !members.remove(&connection_id) {
+            return false;
+        }
+        if members.is_empty() {
+            self.members.remove(room);
+        }
+        if let Some(rooms) = self.joined.get_mut(&connection_id) {
+            rooms.remove(room);
+            if rooms.is_empty() {
+                self.joined.remove(&connection_id);
+            }
+        }
+        true
+    }
+
+    /// Remove a connection from every room it joined
+    pub fn leave_all(&mut self, connection_id: u64) {
+        for room in self.joined.remove(&connection_id).unwrap_or_default() {
+            if let Some(members) = self.members.get_mut(&room) {
+                members.remove(&connection_id);
+                if members.is_empty() {
+                    self.members.remove(&room);
+                }
+            }
+        }
+    }
+
+    /// Whether a connection is in a room
+    pub fn contains(&self, room: &str, connection_id: u64) -> bool {
+        self.members
+            .get(room)
+            .is_some_and(|members| members.contains(&connection_id))
+    }
+
+    /// Connections in a room
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        self.members
+            .get(room)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Every room with its members
+    pub fn all(&self) -> impl Iterator<Item = (&str, &HashSet<u64>)> {
+        self.members
+            .iter()
+            .map(|(room, members)| (room.as_str(), members))
+    }
+
+    pub fn clear(&mut self) {
+        self.members.clear();
+        self.joined.clear();
+    }
+}
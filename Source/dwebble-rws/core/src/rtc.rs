@@ -0,0 +1,589 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebRTC data channels signaled over WebSocket connections
+//!
+//! A client opens a peer connection by sending its offer as a text message on
+//! its WebSocket connection, and trickles its ICE candidates the same way:
+//!
+//! ```json
+//! {"rtc": "offer", "sdp": "v=0..."}
+//! {"rtc": "candidate", "candidate": {"candidate": "candidate:...", "sdpMid": "0", "sdpMLineIndex": 0}}
+//! ```
+//!
+//! The server replies with `{"rtc": "answer", "sdp": ...}` and candidates of its
+//! own. These messages are consumed by the signaling layer instead of raising
+//! `MessageReceived`. Only data channels are negotiated: media sections of the
+//! offer are rejected, and renegotiation is not supported.
+//!
+//! Every data channel the client opens (reliable or not, as the client chooses)
+//! is a connection like any other: it raises `ClientConnected` with
+//! `webrtc/<label>` as its subprotocol and the signaling connection's ID as its
+//! request ID, receives direct sends, room broadcasts and topic publishes, and
+//! raises `ClientDisconnected` when the channel or the signaling connection closes.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::HEXUPPER;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::{Bytes, Message};
+use webrtc_data::data_channel::{self, DataChannel};
+use webrtc_dtls::config::{ClientAuthType, ExtendedMasterSecretType};
+use webrtc_dtls::conn::DTLSConn;
+use webrtc_dtls::crypto::Certificate;
+use webrtc_ice::agent::agent_config::AgentConfig;
+use webrtc_ice::agent::Agent;
+use webrtc_ice::candidate::candidate_base::unmarshal_candidate;
+use webrtc_ice::candidate::Candidate;
+use webrtc_ice::mdns::MulticastDnsMode;
+use webrtc_ice::network_type::NetworkType;
+use webrtc_ice::url::Url;
+use webrtc_sctp::association::{self, Association};
+use webrtc_sctp::stream::Stream;
+use webrtc_util::Conn;
+
+use crate::connection::{Connection, Queued};
+use crate::server::{self, ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+use crate::utf8;
+
+/// Subprotocol prefix reported for data channel connections
+pub const SUBPROTOCOL: &str = "webrtc";
+
+/// SCTP port of the data channel association
+const SCTP_PORT: u16 = 5000;
+
+/// Largest data channel message in either direction
+const MAX_MESSAGE_SIZE: u32 = 256 * 1024;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Signaling message sent by a client
+#[derive(Deserialize)]
+#[serde(tag = "rtc", rename_all = "lowercase")]
+enum Signal {
+    Offer { sdp: String },
+    Candidate { candidate: IceCandidate },
+}
+
+/// Signaling message sent to a client
+#[derive(Serialize)]
+#[serde(tag = "rtc", rename_all = "lowercase")]
+enum Reply {
+    Answer { sdp: String },
+    Candidate { candidate: IceCandidate },
+}
+
+/// An ICE candidate in the shape of the browser's `RTCIceCandidateInit`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IceCandidate {
+    candidate: String,
+    #[serde(default)]
+    sdp_mid: Option<String>,
+    #[serde(default, rename = "sdpMLineIndex")]
+    sdp_m_line_index: Option<u16>,
+}
+
+/// Peer connections negotiated on signaling connections
+#[derive(Default)]
+pub struct Peers {
+    /// Signals queued for the peer connection of each signaling connection
+    peers: HashMap<u64, mpsc::UnboundedSender<Signal>>,
+    /// Connections that are data channels rather than signaling connections
+    channels: HashSet<u64>,
+}
+
+impl Peers {
+    /// Forget a connection, closing the peer connection it signaled
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.peers.remove(&connection_id);
+        self.channels.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+        self.channels.clear();
+    }
+}
+
+/// Handle a text message if it is a signaling message, returning whether it was
+pub fn on_message(shared: &Arc<Shared>, connection_id: u64, text: &str) -> bool {
+    let Ok(signal) = serde_json::from_str::<Signal>(text) else {
+        return false;
+    };
+
+    let mut peers = shared.rtc.lock();
+    if peers.channels.contains(&connection_id) {
+        return false;
+    }
+    let signals = peers.peers.entry(connection_id).or_insert_with(|| {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let shared = Arc::clone(shared);
+        tokio::spawn(async move {
+            run_peer(Arc::clone(&shared), connection_id, rx).await;
+            // A later offer on the same connection starts a new peer connection
+            shared.rtc.lock().peers.remove(&connection_id);
+        });
+        tx
+    });
+    let _ = signals.send(signal);
+    true
+}
+
+/// What the server needs from a client's offer
+struct Offer {
+    ice_ufrag: String,
+    ice_pwd: String,
+    /// SHA-256 fingerprint of the client's DTLS certificate
+    fingerprint: Vec<u8>,
+    /// Whether the client takes the DTLS client role
+    client_active: bool,
+    sections: Vec<Section>,
+}
+
+/// A media section of an offer
+struct Section {
+    /// The `m=` line after `m=`
+    media: String,
+    mid: Option<String>,
+    /// Whether this is the section the data channels are accepted on
+    data_channel: bool,
+}
+
+impl Offer {
+    fn parse(sdp: &str) -> Result<Self, Error> {
+        let mut ice_ufrag = None;
+        let mut ice_pwd = None;
+        let mut fingerprint = None;
+        let mut client_active = false;
+        let mut sections: Vec<Section> = Vec::new();
+
+        for line in sdp.lines().map(str::trim_end) {
+            if let Some(media) = line.strip_prefix("m=") {
+                let data_channel = media.starts_with("application ")
+                    && media.ends_with(" webrtc-datachannel")
+                    && !sections.iter().any(|s| s.data_channel);
+                sections.push(Section {
+                    media: media.to_string(),
+                    mid: None,
+                    data_channel,
+                });
+                continue;
+            }
+
+            let Some(attribute) = line.strip_prefix("a=") else {
+                continue;
+            };
+            let (name, value) = attribute.split_once(':').unwrap_or((attribute, ""));
+            match name {
+                "ice-ufrag" if ice_ufrag.is_none() => ice_ufrag = Some(value.to_string()),
+                "ice-pwd" if ice_pwd.is_none() => ice_pwd = Some(value.to_string()),
+                "fingerprint" => {
+                    if let Some(hex) = value.strip_prefix("sha-256 ") {
+                        let hex = hex.replace(':', "").to_uppercase();
+                        fingerprint = HEXUPPER.decode(hex.as_bytes()).ok();
+                    }
+                }
+                "setup" => client_active |= value == "active",
+                "mid" => {
+                    if let Some(section) = sections.last_mut() {
+                        section.mid = Some(value.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !sections.iter().any(|s| s.data_channel) {
+            return Err("Offer has no data channel section".into());
+        }
+        Ok(Self {
+            ice_ufrag: ice_ufrag.ok_or("Offer has no ICE username fragment")?,
+            ice_pwd: ice_pwd.ok_or("Offer has no ICE password")?,
+            fingerprint: fingerprint.ok_or("Offer has no SHA-256 certificate fingerprint")?,
+            client_active,
+            sections,
+        })
+    }
+
+    /// Media ID and index of the data channel section
+    fn data_channel(&self) -> (Option<String>, u16) {
+        let index = self
+            .sections
+            .iter()
+            .position(|s| s.data_channel)
+            .unwrap_or_default();
+        (self.sections[index].mid.clone(), index as u16)
+    }
+
+    /// Answer accepting the data channel section and rejecting every other
+    fn answer(&self, ice_ufrag: &str, ice_pwd: &str, fingerprint: &[u8]) -> String {
+        let session_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let fingerprint = fingerprint
+            .iter()
+            .map(|byte| HEXUPPER.encode(&[*byte]))
+            .collect::<Vec<_>>()
+            .join(":");
+        let setup = if self.client_active {
+            "passive"
+        } else {
+            "active"
+        };
+
+        let mut sdp = format!(
+            "v=0\r\no=- {} 2 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n",
+            session_id
+        );
+        if let (Some(mid), _) = self.data_channel() {
+            sdp += &format!("a=group:BUNDLE {}\r\n", mid);
+        }
+        for section in &self.sections {
+            if section.data_channel {
+                sdp += "m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n";
+                sdp += "c=IN IP4 0.0.0.0\r\n";
+                sdp += &format!("a=ice-ufrag:{}\r\na=ice-pwd:{}\r\n", ice_ufrag, ice_pwd);
+                sdp += "a=ice-options:trickle\r\n";
+                sdp += &format!("a=fingerprint:sha-256 {}\r\n", fingerprint);
+                sdp += &format!("a=setup:{}\r\n", setup);
+            } else {
+                // A zero port rejects the section
+                let mut fields = section.media.splitn(3, ' ');
+                let media = fields.next().unwrap_or_default();
+                let protocol = fields.nth(1).unwrap_or_default();
+                sdp += &format!("m={} 0 {}\r\nc=IN IP4 0.0.0.0\r\n", media, protocol);
+            }
+            if let Some(mid) = &section.mid {
+                sdp += &format!("a=mid:{}\r\n", mid);
+            }
+            if section.data_channel {
+                sdp += &format!("a=sctp-port:{}\r\n", SCTP_PORT);
+                sdp += &format!("a=max-message-size:{}\r\n", MAX_MESSAGE_SIZE);
+            }
+        }
+        sdp
+    }
+}
+
+/// Negotiate a connection's peer connection and apply its signals until it is forgotten
+async fn run_peer(
+    shared: Arc<Shared>,
+    connection_id: u64,
+    mut signals: mpsc::UnboundedReceiver<Signal>,
+) {
+    let offer = match signals.recv().await {
+        Some(Signal::Offer { sdp }) => match Offer::parse(&sdp) {
+            Ok(offer) => offer,
+            Err(e) => return report(&shared, connection_id, e),
+        },
+        Some(Signal::Candidate { .. }) => {
+            return report(&shared, connection_id, "Candidate received before an offer");
+        }
+        None => return,
+    };
+    let (agent, certificate) = match answer(&shared, connection_id, &offer).await {
+        Ok(answered) => answered,
+        Err(e) => return report(&shared, connection_id, e),
+    };
+
+    // Dropping the senders cancels a connectivity check still in progress and
+    // closes the data channels
+    let (cancel_tx, cancel_rx) = mpsc::channel(1);
+    let (open_tx, open_rx) = watch::channel(());
+    let connecting = connect(
+        Arc::clone(&shared),
+        connection_id,
+        Arc::clone(&agent),
+        offer,
+        certificate,
+        cancel_rx,
+        open_rx,
+    );
+    tokio::pin!(connecting);
+
+    loop {
+        tokio::select! {
+            next = signals.recv() => match next {
+                Some(Signal::Candidate { candidate }) => {
+                    if let Err(e) = add_candidate(&agent, &candidate) {
+                        report(&shared, connection_id, e);
+                    }
+                }
+                Some(Signal::Offer { .. }) => {
+                    report(&shared, connection_id, "Renegotiation is not supported");
+                }
+                None => break,
+            },
+            result = &mut connecting => {
+                if let Err(e) = result {
+                    report(&shared, connection_id, e);
+                }
+                break;
+            }
+        }
+    }
+
+    drop(cancel_tx);
+    drop(open_tx);
+    let _ = agent.close().await;
+}
+
+/// Answer an offer and start gathering candidates, returning the ICE agent and DTLS certificate
+async fn answer(
+    shared: &Arc<Shared>,
+    connection_id: u64,
+    offer: &Offer,
+) -> Result<(Arc<Agent>, Certificate), Error> {
+    let ice_servers = shared
+        .settings
+        .read()
+        .webrtc
+        .as_ref()
+        .map(|webrtc| webrtc.ice_servers.clone())
+        .unwrap_or_default();
+    let urls = ice_servers
+        .iter()
+        .map(|url| Url::parse_url(url))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let agent = Arc::new(
+        Agent::new(AgentConfig {
+            urls,
+            network_types: vec![NetworkType::Udp4, NetworkType::Udp6],
+            // Browsers hide their host addresses behind mDNS names
+            multicast_dns_mode: MulticastDnsMode::QueryOnly,
+            ..Default::default()
+        })
+        .await?,
+    );
+
+    let (mid, index) = offer.data_channel();
+    let candidate_shared = Arc::clone(shared);
+    agent.on_candidate(Box::new(move |candidate| {
+        if let Some(candidate) = candidate {
+            let candidate = IceCandidate {
+                candidate: format!("candidate:{}", candidate.marshal()),
+                sdp_mid: mid.clone(),
+                sdp_m_line_index: Some(index),
+            };
+            send_reply(
+                &candidate_shared,
+                connection_id,
+                &Reply::Candidate { candidate },
+            );
+        }
+        Box::pin(async {})
+    }));
+
+    let certificate = Certificate::generate_self_signed(vec!["dwebble".to_string()])?;
+    let fingerprint = ring::digest::digest(&ring::digest::SHA256, &certificate.certificate[0].0);
+    let (ice_ufrag, ice_pwd) = agent.get_local_user_credentials().await;
+    let sdp = offer.answer(&ice_ufrag, &ice_pwd, fingerprint.as_ref());
+    send_reply(shared, connection_id, &Reply::Answer { sdp });
+
+    agent.gather_candidates()?;
+    Ok((agent, certificate))
+}
+
+fn add_candidate(agent: &Agent, candidate: &IceCandidate) -> Result<(), Error> {
+    let raw = candidate.candidate.trim_start_matches("candidate:");
+    // An empty candidate marks the end of the client's candidates
+    if raw.is_empty() {
+        return Ok(());
+    }
+    let candidate: Arc<dyn Candidate + Send + Sync> = Arc::new(unmarshal_candidate(raw)?);
+    agent.add_remote_candidate(&candidate)?;
+    Ok(())
+}
+
+/// Establish ICE, DTLS and SCTP, then serve every data channel the client opens
+async fn connect(
+    shared: Arc<Shared>,
+    connection_id: u64,
+    agent: Arc<Agent>,
+    offer: Offer,
+    certificate: Certificate,
+    cancel_rx: mpsc::Receiver<()>,
+    open: watch::Receiver<()>,
+) -> Result<(), Error> {
+    let ice_conn: Arc<dyn Conn + Send + Sync> = agent
+        .accept(cancel_rx, offer.ice_ufrag, offer.ice_pwd)
+        .await?;
+
+    let config = webrtc_dtls::config::Config {
+        certificates: vec![certificate],
+        extended_master_secret: ExtendedMasterSecretType::Require,
+        client_auth: ClientAuthType::RequireAnyClientCert,
+        // Certificates are self-signed and checked against the offer's fingerprint instead
+        insecure_skip_verify: true,
+        ..Default::default()
+    };
+    let dtls_conn = DTLSConn::new(ice_conn, config, !offer.client_active, None).await?;
+    let verified = dtls_conn
+        .connection_state()
+        .await
+        .peer_certificates
+        .first()
+        .is_some_and(|der| {
+            ring::digest::digest(&ring::digest::SHA256, der).as_ref() == offer.fingerprint
+        });
+    if !verified {
+        let _ = dtls_conn.close().await;
+        return Err("DTLS certificate does not match the offer's fingerprint".into());
+    }
+
+    let association = Association::client(association::Config {
+        net_conn: Arc::new(dtls_conn),
+        max_receive_buffer_size: 0,
+        max_message_size: MAX_MESSAGE_SIZE,
+        name: format!("dwebble-{}", connection_id),
+    })
+    .await?;
+    tracing::info!("WebRTC peer connected on connection {}", connection_id);
+
+    while let Some(stream) = association.accept_stream().await {
+        tokio::spawn(serve_channel(
+            Arc::clone(&shared),
+            connection_id,
+            stream,
+            open.clone(),
+        ));
+    }
+    let _ = association.close().await;
+    Ok(())
+}
+
+/// Send a signaling message to a client
+fn send_reply(shared: &Shared, connection_id: u64, reply: &Reply) {
+    if let Ok(json) = serde_json::to_string(reply) {
+        shared.send_message(connection_id, Message::Text(json.into()));
+    }
+}
+
+/// Report a signaling or transport failure
+fn report(shared: &Shared, connection_id: u64, error: impl Display) {
+    tracing::warn!("WebRTC error for connection {}: {}", connection_id, error);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::Error,
+        connection_id,
+        data: None,
+        error: Some(error.to_string()),
+        request_id: 0,
+    });
+}
+
+/// Serve a data channel opened by the client as a connection until it closes
+async fn serve_channel(
+    shared: Arc<Shared>,
+    signaling_id: u64,
+    stream: Arc<Stream>,
+    mut open: watch::Receiver<()>,
+) {
+    let channel = match DataChannel::server(stream, data_channel::Config::default()).await {
+        Ok(channel) => channel,
+        Err(e) => return report(&shared, signaling_id, e),
+    };
+    let remote_addr = match shared.connections.get(signaling_id) {
+        Some(signaling) => signaling.remote_addr.clone(),
+        None => return,
+    };
+    let label = channel.config.label.clone();
+    let subprotocol = format!("{}/{}", SUBPROTOCOL, label);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Queued>();
+    let conn = Arc::new(Connection::new(remote_addr, Some(subprotocol.clone()), tx));
+    let connection_id = conn.id;
+
+    shared.rtc.lock().channels.insert(connection_id);
+    shared.connections.insert(Arc::clone(&conn));
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientConnected,
+        connection_id,
+        data: Some(subprotocol.into_bytes()),
+        error: None,
+        request_id: signaling_id,
+    });
+    tracing::info!(
+        "Data channel '{}' opened on connection {} (id: {})",
+        label,
+        signaling_id,
+        connection_id
+    );
+
+    let reading = read_messages(&channel, &shared, connection_id);
+    tokio::pin!(reading);
+    loop {
+        tokio::select! {
+            next = rx.recv() => {
+                let Some(queued) = next else { break };
+                let Some(msg) = conn.dequeue(queued) else { continue };
+                let len = msg.len();
+                let closing = msg.is_close();
+                let sent = match msg {
+                    Message::Text(text) => {
+                        channel.write_data_channel(&Bytes::from(text), true).await
+                    }
+                    Message::Binary(data) => channel.write_data_channel(&data, false).await,
+                    _ => Ok(0),
+                };
+                conn.mark_written_len(len);
+                if closing {
+                    break;
+                }
+                if let Err(e) = sent {
+                    tracing::debug!("Data channel send failed (id: {}): {}", connection_id, e);
+                }
+            }
+            _ = &mut reading => {
+                break;
+            }
+            _ = open.changed() => {
+                break;
+            }
+            _ = conn.terminated() => {
+                break;
+            }
+        }
+    }
+    let _ = channel.close().await;
+
+    shared.connections.remove(connection_id);
+    shared.forget_connection(connection_id);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientDisconnected,
+        connection_id,
+        data: None,
+        error: conn
+            .termination()
+            .map(|(_, reason)| reason)
+            .or(conn.severed()),
+        request_id: 0,
+    });
+    tracing::info!("Data channel closed (id: {})", connection_id);
+}
+
+/// Deliver messages read off a data channel until it closes
+async fn read_messages(channel: &DataChannel, shared: &Arc<Shared>, connection_id: u64) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE as usize];
+    while let Ok((len, is_string)) = channel.read_data_channel(&mut buf).await {
+        let msg = if is_string {
+            let policy = shared.settings.read().utf8_policy;
+            match utf8::message(policy, &buf[..len]) {
+                Some(msg) => msg,
+                None => {
+                    report(shared, connection_id, "text message is not valid UTF-8");
+                    continue;
+                }
+            }
+        } else {
+            Message::Binary(buf[..len].to_vec().into())
+        };
+        server::deliver_inbound(shared, connection_id, msg, None);
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Async runtimes of servers and load tests
+//!
+//! By default each runtime runs on worker threads of its own. Platforms that
+//! don't allow the library to create threads build with the `single-thread`
+//! feature instead: the runtime then has no workers and only makes progress
+//! while the host ticks it from a thread of its own, e.g. once per frame.
+//! Resolving host names still uses a blocking thread, so connect to addresses
+//! there.
+//!
+//! A server's `threads` setting pins its runtime's threads to cores and sets
+//! their priority as each starts, keeping them off the cores the engine gives
+//! its render and audio threads. Platforms the library can't do that on, such
+//! as consoles, use the thread start callback to apply their own.
+
+use std::io;
+#[cfg(not(feature = "single-thread"))]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+
+#[cfg(not(feature = "single-thread"))]
+use crate::settings::ThreadPriority;
+use crate::settings::ThreadSettings;
+
+/// Called on each runtime thread as it starts, with the order it started in
+pub type ThreadCallback = Arc<dyn Fn(u32) + Send + Sync>;
+
+/// Create a runtime for a server or load test, its threads configured by `threads`
+/// and announced to `callback`
+#[cfg(feature = "single-thread")]
+pub fn build(
+    _threads: Option<ThreadSettings>,
+    _callback: Option<ThreadCallback>,
+) -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+/// Create a runtime for a server or load test, its threads configured by `threads`
+/// and announced to `callback`
+#[cfg(not(feature = "single-thread"))]
+pub fn build(
+    threads: Option<ThreadSettings>,
+    callback: Option<ThreadCallback>,
+) -> io::Result<Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    let threads = threads.unwrap_or_default();
+    if threads.worker_threads > 0 {
+        builder.worker_threads(threads.worker_threads);
+    }
+    let started = AtomicUsize::new(0);
+    let warned = AtomicBool::new(false);
+    builder.on_thread_start(move || {
+        let index = started.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = configure_thread(&threads, index) {
+            // Blocking threads come and go, so only the first failure is logged
+            if !warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!("Failed to configure runtime thread: {}", e);
+            }
+        }
+        if let Some(callback) = &callback {
+            callback(index as u32);
+        }
+    });
+    builder.build()
+}
+
+/// Pin the calling thread, the `index`th the runtime started, and set its priority,
+/// each whether or not the other fails
+#[cfg(not(feature = "single-thread"))]
+fn configure_thread(threads: &ThreadSettings, index: usize) -> io::Result<()> {
+    let pinned = match threads.cores.len() {
+        0 => Ok(()),
+        len => os::pin(threads.cores[index % len]),
+    };
+    let prioritized = match threads.priority {
+        ThreadPriority::Normal => Ok(()),
+        priority => os::set_priority(priority),
+    };
+    pinned.and(prioritized)
+}
+
+#[cfg(all(
+    not(feature = "single-thread"),
+    any(target_os = "linux", target_os = "android")
+))]
+mod os {
+    use std::io;
+
+    use crate::settings::ThreadPriority;
+
+    pub fn pin(core: usize) -> io::Result<()> {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(core, &mut set);
+            // 0 is the calling thread
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Linux nice values are per thread; raising the priority above normal needs
+    /// `CAP_SYS_NICE`
+    pub fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+        let nice = match priority {
+            ThreadPriority::Lowest => 10,
+            ThreadPriority::BelowNormal => 5,
+            ThreadPriority::Normal => 0,
+            ThreadPriority::AboveNormal => -5,
+            ThreadPriority::Highest => -10,
+        };
+        unsafe {
+            let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+            if libc::setpriority(libc::PRIO_PROCESS, tid, nice) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(not(feature = "single-thread"), windows))]
+mod os {
+    use std::io;
+
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadAffinityMask, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST,
+        THREAD_PRIORITY_NORMAL,
+    };
+
+    use crate::settings::ThreadPriority;
+
+    /// Cores past the first 64 are in other processor groups, which the mask can't name
+    pub fn pin(core: usize) -> io::Result<()> {
+        if core >= usize::BITS as usize {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << core) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+        let priority = match priority {
+            ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+            ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+            ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+            ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+        };
+        if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    not(feature = "single-thread"),
+    not(any(target_os = "linux", target_os = "android", windows))
+))]
+mod os {
+    use std::io;
+
+    use crate::settings::ThreadPriority;
+
+    pub fn pin(_core: usize) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn set_priority(_priority: ThreadPriority) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Run ready tasks and I/O for up to `budget` on the calling thread. Returns at
+/// once when the runtime drives itself.
+pub fn tick(runtime: &Runtime, budget: Duration) {
+    if cfg!(feature = "single-thread") {
+        runtime.block_on(async {
+            // Yield first so a zero budget still gets one pass over the tasks
+            tokio::task::yield_now().await;
+            tokio::time::sleep(budget).await;
+        });
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! JSON Schema validation of inbound messages
+//!
+//! Each schema applies to the connections that negotiated its subprotocol.
+//! Their text messages must be JSON documents matching it; binary messages are
+//! not validated. A message that does not validate raises `ValidationFailed`
+//! and is dropped, or delivered anyway if its schema only flags failures.
+
+use std::sync::Arc;
+
+use jsonschema::Validator;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::{SchemaSettings, ValidationAction};
+use crate::types::DwebbleWSEventType;
+
+/// Validation errors listed in a `ValidationFailed` event, the rest counted
+const MAX_REPORTED_ERRORS: usize = 8;
+
+/// A compiled schema
+pub struct Schema {
+    subprotocol: String,
+    action: ValidationAction,
+    validator: Validator,
+}
+
+impl Schema {
+    pub fn compile(settings: &SchemaSettings) -> Result<Self, String> {
+        let schema = match &settings.schema {
+            Some(schema) => schema.clone(),
+            None => {
+                let json = std::fs::read(&settings.path).map_err(|e| e.to_string())?;
+                serde_json::from_slice(&json).map_err(|e| e.to_string())?
+            }
+        };
+        let validator = jsonschema::validator_for(&schema).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            subprotocol: settings.subprotocol.clone(),
+            action: settings.action,
+            validator,
+        })
+    }
+
+    /// Describe why `text` does not validate, `None` if it does
+    fn check(&self, text: &str) -> Option<String> {
+        let instance: serde_json::Value = match serde_json::from_str(text) {
+            Ok(instance) => instance,
+            Err(e) => return Some(format!("not valid JSON: {}", e)),
+        };
+
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&instance)
+            .map(|e| match e.instance_path.as_str() {
+                "" => e.to_string(),
+                path => format!("{}: {}", path, e),
+            })
+            .collect();
+        if errors.is_empty() {
+            return None;
+        }
+
+        let mut details = errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("; ");
+        if errors.len() > MAX_REPORTED_ERRORS {
+            details += &format!("; and {} more", errors.len() - MAX_REPORTED_ERRORS);
+        }
+        Some(details)
+    }
+}
+
+/// Compile every configured schema
+pub fn compile_all(settings: &[SchemaSettings]) -> Result<Vec<Arc<Schema>>, String> {
+    settings
+        .iter()
+        .map(|schema| {
+            Schema::compile(schema).map(Arc::new).map_err(|e| {
+                format!(
+                    "Invalid schema for subprotocol '{}': {}",
+                    schema.subprotocol, e
+                )
+            })
+        })
+        .collect()
+}
+
+/// Validate an inbound message against the schema of its connection's
+/// subprotocol. Returns `None` if it was rejected.
+pub(crate) fn validate(shared: &Shared, connection_id: u64, msg: Message) -> Option<Message> {
+    let Message::Text(text) = &msg else {
+        return Some(msg);
+    };
+    if shared.schemas.read().is_empty() {
+        return Some(msg);
+    }
+
+    let subprotocol = shared
+        .connections
+        .get(connection_id)
+        .and_then(|conn| conn.subprotocol.clone())
+        .unwrap_or_default();
+    let schema = shared
+        .schemas
+        .read()
+        .iter()
+        .find(|schema| schema.subprotocol == subprotocol)
+        .cloned();
+    let Some(schema) = schema else {
+        return Some(msg);
+    };
+    let Some(details) = schema.check(text) else {
+        return Some(msg);
+    };
+
+    let deliver = schema.action == ValidationAction::Flag;
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ValidationFailed,
+        connection_id,
+        data: Some(text.as_bytes().to_vec()),
+        error: Some(details),
+        request_id: u64::from(deliver),
+    });
+    deliver.then_some(msg)
+}
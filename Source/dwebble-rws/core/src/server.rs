@@ -0,0 +1,4210 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebSocket Server implementation
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tokio_tungstenite::tungstenite::http::{HeaderMap, Response as HttpResponse, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::error::{Error as WsError, ProtocolError};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::bridge::{self, Balancer, Upstream};
+use crate::admin;
+use crate::bans::{self, Bans};
+use crate::budget::{self, EventBudget};
+use crate::channels::Channels;
+use crate::chaos::{self, Scenario};
+use crate::checksum;
+use crate::codec;
+use crate::compression;
+use crate::cluster::Cluster;
+use crate::client::{self, Client};
+use crate::connection::{self, Connection, Queued, Queueing};
+use crate::connections::ConnectionMap;
+use crate::control;
+use crate::delta::States;
+use crate::encryption::{self, Exchange, Opened, Opener, Role, Sealer};
+use crate::freshness::{self, Guards};
+use crate::eviction;
+use crate::fingerprint::{self, Fingerprint};
+use crate::frames::{ClientFrames, TextFrames};
+use crate::histogram::Histograms;
+use crate::journal::{Direction, Journal, PayloadKind};
+use crate::logging;
+use crate::maintenance::{self, ShutdownSchedule};
+use crate::metrics::{self, Metrics};
+use crate::middleware::{self, Chain};
+use crate::migration::{self, Migration, Rehome};
+use crate::mock;
+use crate::mqtt::{self, Mqtt, Publication};
+use crate::recording::{self, Recorder, Replay};
+use crate::pipes::Pipes;
+use crate::presence::{Changes, Presence};
+use crate::raw;
+use crate::receipts::Receipts;
+use crate::history::History;
+use crate::jsonrpc::{self, RpcCalls};
+use crate::requests::{self, Requests};
+use crate::resources;
+use crate::event_queues::{EventQueue, EventQueues};
+use crate::event_ring::EventRing;
+use crate::rooms::Rooms;
+use crate::tags::Tags;
+use crate::topics::{self, Topics};
+use crate::session::{self, SessionStore};
+use crate::signing::Signers;
+use crate::socketio::{self, SocketIo};
+use crate::sse;
+use crate::stun;
+#[cfg(feature = "http2")]
+use crate::http2;
+use crate::rewind::{self, Rewind};
+use crate::netsim::{self, DelayQueue};
+use crate::pool;
+use crate::ratelimit::{self, RateLimiter};
+use crate::runtime::{self, ThreadCallback};
+use crate::settings::{
+    ChannelMode, ClientSettings, MockSettings, NetworkSimSettings, Settings, SettingsUpdate,
+};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::watchdog::{self, HealthCallback, Watchdog};
+use crate::webhooks::Webhooks;
+use crate::types::{
+    DwebbleWSChannelStats, DwebbleWSConnectionInfo, DwebbleWSEventType, DwebbleWSResourceUsage,
+    DwebbleWSResult, DwebbleWSServerState, DwebbleWSStopStats, DwebbleWSTagStats,
+};
+use crate::utf8;
+use crate::version;
+#[cfg(feature = "webtransport")]
+use crate::webtransport::{self, Sessions};
+#[cfg(feature = "webrtc")]
+use crate::rtc::{self, Peers};
+#[cfg(feature = "wasm")]
+use crate::wasm::{self, Filter};
+#[cfg(feature = "schema")]
+use crate::schema::{self, Schema};
+#[cfg(feature = "archive")]
+use crate::archive::{Archive, ArchiveQuery};
+#[cfg(feature = "zstd")]
+use crate::compression::{Dictionary, Zstd};
+#[cfg(feature = "port-mapping")]
+use crate::portmap::PortMapping;
+
+/// In-memory pipe capacity for loopback connections
+const LOOPBACK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Request URL used for the loopback handshake
+const LOOPBACK_URL: &str = "ws://loopback/";
+
+/// How often a stalled writer checks whether its stall was lifted
+const STALL_RECHECK: Duration = Duration::from_millis(50);
+
+/// How long a closing connection waits for its writer and Close frame
+const CLOSE_FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Kind of the main listener in `listen_addrs`
+const WEBSOCKET_LISTENER: &str = "websocket";
+
+/// Disconnect reason the source server reports for a migrated connection
+const MIGRATED_REASON: &str = "Migrated to another server";
+
+/// How long stopping waits for the cluster backend to flush
+const CLUSTER_FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long stopping waits for webhook endpoints to take the queued notifications
+const WEBHOOK_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long stopping waits for the gateway to remove the port mapping
+#[cfg(feature = "port-mapping")]
+const PORT_MAPPING_REMOVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stands in for the acceptor in builds without the `tls` feature, where there is none
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+enum TlsAcceptor {}
+
+/// Internal event for the event queue
+#[derive(Debug, Clone)]
+pub struct ServerEvent {
+    pub event_type: DwebbleWSEventType,
+    pub connection_id: u64,
+    pub data: Option<Vec<u8>>,
+    pub error: Option<String>,
+    /// Request the event belongs to (0 if none)
+    pub request_id: u64,
+}
+
+impl ServerEvent {
+    /// Bytes of the event's data and error message
+    pub fn payload_len(&self) -> usize {
+        self.data.as_ref().map_or(0, Vec::len) + self.error.as_ref().map_or(0, String::len)
+    }
+}
+
+/// An event waiting for the host, with when it was queued
+pub struct QueuedEvent {
+    pub event: ServerEvent,
+    pub queued_at: Instant,
+}
+
+/// Server configuration
+pub struct ServerConfig {
+    pub port: u16,
+    pub bind_address: String,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+    pub settings: Settings,
+    pub journal: Option<Journal>,
+    /// Database of the `archive` setting
+    #[cfg(feature = "archive")]
+    pub archive: Option<Archive>,
+    pub recorder: Option<Recorder>,
+    pub replay: Option<Replay>,
+    pub cluster: Option<Cluster>,
+    pub webhooks: Option<Webhooks>,
+    /// Modules of the `wasm_filters` setting
+    #[cfg(feature = "wasm")]
+    pub wasm_filters: Vec<Arc<Filter>>,
+    /// Compiled `schemas` setting
+    #[cfg(feature = "schema")]
+    pub schemas: Vec<Arc<Schema>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 0,
+            bind_address: "127.0.0.1".to_string(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            settings: Settings::default(),
+            journal: None,
+            #[cfg(feature = "archive")]
+            archive: None,
+            recorder: None,
+            replay: None,
+            cluster: None,
+            webhooks: None,
+            #[cfg(feature = "wasm")]
+            wasm_filters: vec![],
+            #[cfg(feature = "schema")]
+            schemas: vec![],
+        }
+    }
+}
+
+/// State shared between the server handle and its connection tasks
+pub(crate) struct Shared {
+    pub connections: ConnectionMap,
+    /// Events for the server's queue, polled by the host
+    pub events: EventRing<QueuedEvent>,
+    /// Notified when the server's queue takes an event while empty
+    pub event_ready: Notify,
+    /// Event types raised to the host, one bit per type value
+    pub event_mask: AtomicU64,
+    /// Message events raised against the `event_budget` setting
+    pub event_budget: Mutex<EventBudget>,
+    /// Per-room event queues taking the events of their rooms' members
+    pub event_queues: Mutex<EventQueues>,
+    /// Addresses refused by the WebSocket listener, kept across restarts
+    pub bans: Mutex<Bans>,
+    pub settings: RwLock<Settings>,
+    pub sessions: Mutex<SessionStore>,
+    pub rooms: Mutex<Rooms>,
+    /// Latest broadcasts of each room, with the `history` setting
+    pub history: Mutex<History>,
+    pub tags: Mutex<Tags>,
+    /// Connections relaying their messages to each other
+    pub pipes: Mutex<Pipes>,
+    pub presence: Mutex<Presence>,
+    pub topics: Mutex<Topics>,
+    pub requests: Mutex<Requests>,
+    /// JSON-RPC calls awaiting the host's response
+    pub rpc: Mutex<RpcCalls>,
+    /// Socket.IO clients and acknowledgements awaiting the host
+    pub socket_io: Mutex<SocketIo>,
+    /// MQTT clients and retained messages
+    pub mqtt: Mutex<Mqtt>,
+    /// Sequence and acknowledgement state of virtual channels
+    pub channels: Mutex<Channels>,
+    /// Versions of keyed state sent to each connection
+    pub states: Mutex<States>,
+    /// Sequences of sequenced sends and their acknowledgements
+    pub receipts: Mutex<Receipts>,
+    /// HMAC keys of connections whose messages are signed
+    pub signers: Mutex<Signers>,
+    /// Nonces recently accepted from each connection, with replay protection
+    pub nonces: Mutex<Guards>,
+    /// Host callbacks run on every message, kept across restarts
+    pub middleware: Mutex<Chain>,
+    /// Why the latest failed send to each connection failed
+    pub send_errors: Mutex<HashMap<u64, String>>,
+    /// Established WebTransport sessions
+    #[cfg(feature = "webtransport")]
+    pub webtransport: Mutex<Sessions>,
+    /// WebRTC peer connections and their data channels
+    #[cfg(feature = "webrtc")]
+    pub rtc: Mutex<Peers>,
+    /// WebAssembly filters run on inbound messages, replaced on settings updates
+    #[cfg(feature = "wasm")]
+    pub wasm_filters: RwLock<Vec<Arc<Filter>>>,
+    /// Schemas validating inbound messages, replaced on settings updates
+    #[cfg(feature = "schema")]
+    pub schemas: RwLock<Vec<Arc<Schema>>>,
+    /// Dictionary zstd compression negotiated from now on uses
+    #[cfg(feature = "zstd")]
+    pub zstd_dictionary: RwLock<Option<Arc<Dictionary>>>,
+    pub journal: Option<Journal>,
+    #[cfg(feature = "archive")]
+    pub archive: Option<Archive>,
+    pub recorder: Option<Recorder>,
+    /// Built-in mock behavior replying to client messages
+    pub mock: Option<MockSettings>,
+    /// Upstream selection for bridge mode
+    pub balancer: Option<Balancer>,
+    /// Fan-out of room broadcasts to sibling instances
+    pub cluster: Option<Cluster>,
+    /// Lifecycle notifications to HTTP endpoints
+    pub webhooks: Option<Webhooks>,
+    /// Traffic counters pushed to a StatsD agent
+    pub metrics: Option<Metrics>,
+    /// Message sizes and event latency
+    pub histograms: Histograms,
+    /// Events on the server's queue not yet polled
+    pub event_backlog: AtomicUsize,
+    /// Data and error message bytes of those events
+    pub event_backlog_bytes: AtomicUsize,
+    /// When the server's queue last took an event while empty, in `watchdog::now_ms`
+    pub backlog_ms: AtomicU64,
+    /// When the host last polled the server's queue, in `watchdog::now_ms`
+    pub polled_ms: AtomicU64,
+    /// Called with each health warning of the watchdog
+    pub health_callback: Mutex<Option<HealthCallback>>,
+    /// New handshakes are rejected until this time (chaos testing)
+    pub refuse_handshakes_until: Mutex<Option<tokio::time::Instant>>,
+    /// A scheduled shutdown is due: new handshakes are rejected
+    pub shutting_down: AtomicBool,
+    /// External address the gateway forwards to the server, while mapped
+    pub mapped_addr: Mutex<Option<SocketAddr>>,
+    /// Public address last reported by a STUN server
+    pub public_addr: Mutex<Option<SocketAddr>>,
+    /// Where the server is in its lifecycle, a `DwebbleWSServerState`
+    pub state: AtomicU8,
+}
+
+impl Shared {
+    pub fn state(&self) -> DwebbleWSServerState {
+        DwebbleWSServerState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    pub fn set_state(&self, state: DwebbleWSServerState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn push_event(&self, event: ServerEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&event);
+        }
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.observe(&event);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.observe(&event);
+        }
+        if self.event_mask.load(Ordering::Relaxed) & (1 << event.event_type as u8) == 0 {
+            return;
+        }
+        if event.event_type.is_message() {
+            let budget = self.settings.read().event_budget;
+            if budget.is_some_and(|budget| !self.event_budget.lock().spend(&budget)) {
+                return;
+            }
+        }
+        let queued = QueuedEvent {
+            event,
+            queued_at: Instant::now(),
+        };
+        if let Err(queued) = self.event_queues.lock().route(queued) {
+            // Counted first, so a poll taking the event never finds the count at zero
+            let bytes = queued.event.payload_len();
+            let first = self.event_backlog.fetch_add(1, Ordering::Relaxed) == 0;
+            if first {
+                self.backlog_ms.store(watchdog::now_ms(), Ordering::Relaxed);
+            }
+            self.event_backlog_bytes.fetch_add(bytes, Ordering::Relaxed);
+            // The host would lose track of connections without the others
+            if !queued.event.event_type.is_message() {
+                self.events.force_push(queued);
+            } else if self.events.push(queued).is_err() {
+                self.event_backlog.fetch_sub(1, Ordering::Relaxed);
+                self.event_backlog_bytes.fetch_sub(bytes, Ordering::Relaxed);
+                self.event_budget.lock().overflowed();
+                return;
+            }
+            // Tasks awaiting events only wait on an empty queue
+            if first {
+                self.event_ready.notify_one();
+            }
+        }
+    }
+
+    /// Take an event the host polled off its queue, timing its wait
+    pub fn polled(&self, queued: QueuedEvent) -> ServerEvent {
+        let waited = queued.queued_at.elapsed().as_micros() as u64;
+        self.histograms.poll_latency_us.record(waited);
+        queued.event
+    }
+
+    /// The checksum of an event's data, with the `checksum` setting (0 otherwise)
+    pub fn event_checksum(&self, event: &ServerEvent) -> u32 {
+        let algorithm = self.settings.read().checksum.map(|c| c.algorithm);
+        match (algorithm, &event.data) {
+            (Some(algorithm), Some(data)) => checksum::compute(algorithm, data),
+            _ => 0,
+        }
+    }
+
+    /// Raise `HandshakeFailed` for a client whose WebSocket handshake was refused or failed
+    pub fn handshake_failed(
+        &self,
+        addr: SocketAddr,
+        path: Option<&str>,
+        header: Option<&str>,
+        reason: String,
+    ) {
+        let data = serde_json::json!({
+            "remote_addr": addr.to_string(),
+            "path": path,
+            "header": header,
+        });
+        self.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::HandshakeFailed,
+            connection_id: 0,
+            data: Some(data.to_string().into_bytes()),
+            error: Some(reason),
+            request_id: 0,
+        });
+    }
+
+    /// Route a connection's events to the queue of the earliest opened queue's room it
+    /// is in, or to the server's queue
+    pub fn bind_event_queue(&self, connection_id: u64) {
+        let queues = {
+            let queues = self.event_queues.lock();
+            if queues.is_empty() {
+                return;
+            }
+            queues.rooms()
+        };
+        let queue_id = {
+            let rooms = self.rooms.lock();
+            queues
+                .into_iter()
+                .find(|(_, room)| rooms.contains(room, connection_id))
+                .map(|(queue_id, _)| queue_id)
+        };
+        self.event_queues.lock().bind(connection_id, queue_id);
+    }
+
+    /// A host's binary payload as sent, behind an escape if control frames are
+    /// reserved and it would pass for one
+    pub fn escape(&self, data: &[u8]) -> Vec<u8> {
+        match self.settings.read().control_frames {
+            true => control::escape(data),
+            false => data.to_vec(),
+        }
+    }
+
+    /// A message's payload as the host or client sent it, without its escape
+    pub fn unescape<'a>(&self, kind: PayloadKind, data: &'a [u8]) -> &'a [u8] {
+        match kind {
+            PayloadKind::Binary if self.settings.read().control_frames => {
+                control::unescape(data).unwrap_or(data)
+            }
+            _ => data,
+        }
+    }
+
+    pub fn record_journal(
+        &self,
+        connection_id: u64,
+        direction: Direction,
+        kind: PayloadKind,
+        data: &[u8],
+    ) {
+        if let Some(journal) = &self.journal {
+            let in_room = |room: &str| self.rooms.lock().contains(room, connection_id);
+            journal.record(connection_id, direction, kind, data, in_room);
+        }
+    }
+
+    /// Journal and archive a message queued for a connection
+    fn record_outbound(&self, connection_id: u64, msg: &Message) {
+        let (kind, payload): (PayloadKind, &[u8]) = match msg {
+            Message::Text(text) => (PayloadKind::Text, text.as_bytes()),
+            Message::Binary(data) => (PayloadKind::Binary, data),
+            _ => return,
+        };
+        self.record_journal(connection_id, Direction::Outbound, kind, payload);
+        #[cfg(feature = "archive")]
+        if let Some(archive) = &self.archive {
+            let payload = self.unescape(kind, payload);
+            archive.record(connection_id, None, Direction::Outbound, kind, payload);
+        }
+    }
+
+    /// Queue a data message and journal it, buffering it if the connection's session is
+    /// suspended
+    pub fn send_message(&self, connection_id: u64, msg: Message) -> DwebbleWSResult {
+        self.send_message_as(connection_id, Queueing::Plain, msg)
+    }
+
+    /// Queue and journal a data message like `send_message`, e.g. under a dedupe key
+    /// replacing the one queued under the key earlier if that hasn't been sent yet
+    pub fn send_message_as(
+        &self,
+        connection_id: u64,
+        queueing: Queueing,
+        msg: Message,
+    ) -> DwebbleWSResult {
+        self.queue_message(connection_id, queueing, msg, true)
+    }
+
+    /// Queue a control frame of the library's like `send_message`, past the middleware
+    /// and without journaling or archiving it
+    pub fn send_control(&self, connection_id: u64, frame: Vec<u8>) -> DwebbleWSResult {
+        self.queue_message(connection_id, Queueing::Plain, Message::Binary(frame.into()), false)
+    }
+
+    fn queue_message(
+        &self,
+        connection_id: u64,
+        queueing: Queueing,
+        msg: Message,
+        host: bool,
+    ) -> DwebbleWSResult {
+        // The library's control frames are not the middleware's to rewrite or drop
+        let msg = if host {
+            match self.run_middleware(false, connection_id, msg) {
+                Some(msg) => msg,
+                None => return DwebbleWSResult::Ok,
+            }
+        } else {
+            msg
+        };
+        // Journaled and archived as the middleware passed it, once it is queued
+        let recorded = host.then(|| msg.clone());
+        let msg = self.stamp(msg);
+
+        // Signed under the connection's shard lock, so messages are queued in counter order
+        let conns = self.connections.shard(connection_id);
+        let msg = self.signers.lock().sign(connection_id, msg);
+        let result = if let Some(conn) = conns.get(connection_id) {
+            let limit = self.settings.read().send_queue_limit;
+            let queued = conn.pending_bytes();
+            // A replaced message leaves the queue, making room for its replacement
+            let replaced = match queueing {
+                Queueing::Keyed(key) => conn.keyed_len(key),
+                _ => 0,
+            };
+            let over = |limit: &usize| (queued + msg.len()).saturating_sub(replaced) > *limit;
+            if let Some(limit) = limit.filter(over) {
+                self.set_send_error(
+                    connection_id,
+                    format!(
+                        "{} bytes queued, {} more would exceed the limit of {}",
+                        queued,
+                        msg.len(),
+                        limit
+                    ),
+                );
+                DwebbleWSResult::QueueFull
+            } else {
+                let queued = match queueing {
+                    Queueing::Plain => conn.queue(msg),
+                    Queueing::Keyed(key) => conn.queue_keyed(key, msg),
+                    Queueing::Expiring(deadline) => conn.queue_expiring(msg, deadline),
+                };
+                if queued {
+                    DwebbleWSResult::Ok
+                } else {
+                    self.set_send_error(connection_id, "the socket has closed".to_string());
+                    DwebbleWSResult::ConnectionClosed
+                }
+            }
+        } else if self.buffer_for_session(connection_id, msg) {
+            DwebbleWSResult::Ok
+        } else if self.sessions.lock().token(connection_id).is_some() {
+            self.set_send_error(
+                connection_id,
+                "the session is suspended and buffers no messages".to_string(),
+            );
+            DwebbleWSResult::ConnectionClosed
+        } else if connection::is_issued(connection_id) {
+            DwebbleWSResult::ConnectionClosed
+        } else {
+            DwebbleWSResult::InvalidHandle
+        };
+        drop(conns);
+
+        if let (DwebbleWSResult::Ok, Some(msg)) = (result, recorded) {
+            self.record_outbound(connection_id, &msg);
+        }
+        result
+    }
+
+    fn set_send_error(&self, connection_id: u64, error: String) {
+        tracing::debug!("Send to connection {} failed: {}", connection_id, error);
+        self.send_errors.lock().insert(connection_id, error);
+    }
+
+    /// Wrap an outbound message in a nonce and timestamp envelope, with replay protection
+    pub fn stamp(&self, msg: Message) -> Message {
+        if self.settings.read().replay_protection.is_some() {
+            freshness::stamp(msg)
+        } else {
+            msg
+        }
+    }
+
+    /// Pass a message through the host's middleware. Returns `None` if it was dropped.
+    pub fn run_middleware(
+        &self,
+        inbound: bool,
+        connection_id: u64,
+        msg: Message,
+    ) -> Option<Message> {
+        let middleware = self.middleware.lock().snapshot();
+        match middleware {
+            Some(middleware) => middleware::run(&middleware, inbound, connection_id, msg),
+            None => Some(msg),
+        }
+    }
+
+    /// Send a message to every member of a room on this instance
+    pub fn send_to_room(&self, room: &str, msg: &Message) {
+        let max_messages = self.settings.read().history.map(|h| h.max_messages);
+        // Recorded under the rooms lock, so a joining connection gets each broadcast
+        // either from the history or as a member, never both
+        let members = {
+            let rooms = self.rooms.lock();
+            if let Some(max_messages) = max_messages {
+                self.history.lock().record(room, msg, max_messages);
+            }
+            rooms.members(room)
+        };
+        #[cfg(feature = "archive")]
+        if let Some(archive) = &self.archive {
+            let (kind, payload): (PayloadKind, &[u8]) = match msg {
+                Message::Text(text) => (PayloadKind::Text, text.as_bytes()),
+                Message::Binary(data) => (PayloadKind::Binary, data),
+                _ => (PayloadKind::Binary, &[]),
+            };
+            let payload = self.unescape(kind, payload);
+            archive.record(0, Some(room), Direction::Outbound, kind, payload);
+        }
+        for connection_id in members {
+            self.send_message(connection_id, msg.clone());
+        }
+    }
+
+    /// Send a message to every subscriber of a topic on this instance.
+    /// The host receives it as a `TopicMessage` event.
+    pub fn send_to_topic(&self, topic: &str, msg: &Message) {
+        let subscribers = self.topics.lock().subscribers(topic);
+        for subscriber in subscribers {
+            if subscriber != topics::HOST {
+                self.send_message(subscriber, msg.clone());
+                continue;
+            }
+            let data = match msg {
+                Message::Text(text) => text.as_bytes().to_vec(),
+                Message::Binary(data) => data.to_vec(),
+                _ => continue,
+            };
+            self.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::TopicMessage,
+                connection_id: 0,
+                data: Some(data),
+                error: Some(topic.to_string()),
+                request_id: 0,
+            });
+        }
+    }
+
+    /// Remove an ended connection from its rooms, topics and the presence registry,
+    /// and fail its pending requests
+    pub fn forget_connection(&self, connection_id: u64) {
+        self.rooms.lock().leave_all(connection_id);
+        self.tags.lock().remove_connection(connection_id);
+        self.pipes.lock().remove_connection(connection_id);
+        self.topics.lock().unsubscribe_all(connection_id);
+        self.rpc.lock().remove_connection(connection_id);
+        self.socket_io.lock().remove_connection(connection_id);
+        self.channels.lock().remove_connection(connection_id);
+        self.states.lock().remove_connection(connection_id);
+        self.receipts.lock().remove_connection(connection_id);
+        self.signers.lock().remove_connection(connection_id);
+        self.nonces.lock().remove_connection(connection_id);
+        self.send_errors.lock().remove(&connection_id);
+        if let Some(journal) = &self.journal {
+            journal.remove_connection(connection_id);
+        }
+        #[cfg(feature = "webtransport")]
+        self.webtransport.lock().remove_connection(connection_id);
+        #[cfg(feature = "webrtc")]
+        self.rtc.lock().remove_connection(connection_id);
+        let will = self.mqtt.lock().remove_connection(connection_id);
+        if let Some(will) = will {
+            mqtt::route(self, will, Some(connection_id));
+        }
+        let changes = self.presence.lock().remove(connection_id);
+        self.apply_presence(connection_id, changes);
+
+        let abandoned = self.requests.lock().remove_connection(connection_id);
+        for request_id in abandoned {
+            self.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::RequestTimedOut,
+                connection_id,
+                data: None,
+                error: Some("Connection closed".to_string()),
+                request_id,
+            });
+        }
+    }
+
+    /// Close a connection, with a Close frame of `close` if given, or end its
+    /// suspended session
+    pub fn close_connection(
+        &self,
+        connection_id: u64,
+        close: Option<(u16, &str)>,
+    ) -> DwebbleWSResult {
+        let mut conns = self.connections.shard(connection_id);
+        if let Some(conn) = conns.remove(connection_id) {
+            match close {
+                Some((code, reason)) => conn.close_with(code, reason),
+                None => conn.close(),
+            }
+            DwebbleWSResult::Ok
+        } else if self.sessions.lock().token(connection_id).is_some() {
+            // Suspended session: end it without waiting for the grace period
+            self.sessions.lock().end(connection_id);
+            self.forget_connection(connection_id);
+            self.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::ClientDisconnected,
+                connection_id,
+                data: None,
+                error: None,
+                request_id: 0,
+            });
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidHandle
+        }
+    }
+
+    /// Close every connection from `ip` with `code` and `reason`
+    pub fn kick_ip(&self, ip: IpAddr, code: u16, reason: &str) {
+        let connection_ids: Vec<u64> = self
+            .connections
+            .values()
+            .iter()
+            .filter(|conn| {
+                conn.remote_addr
+                    .parse::<SocketAddr>()
+                    .is_ok_and(|addr| addr.ip().to_canonical() == ip.to_canonical())
+            })
+            .map(|conn| conn.id)
+            .collect();
+        for connection_id in connection_ids {
+            self.close_connection(connection_id, Some((code, reason)));
+        }
+    }
+
+    /// Refuse connections from `ip` for `duration`, or until unbanned, and close those
+    /// open with `reason`
+    pub fn ban_ip(&self, ip: IpAddr, duration: Option<Duration>, reason: &str) {
+        tracing::info!("Banned {}: {}", ip, reason);
+        self.bans.lock().ban(ip, duration, reason);
+        self.kick_ip(ip, bans::CLOSE_CODE, reason);
+        if let Some(webhooks) = &self.webhooks {
+            let duration_ms = duration.map(|d| d.as_millis() as u64);
+            webhooks.notify(
+                "banned",
+                serde_json::json!({ "ip": ip, "duration_ms": duration_ms, "reason": reason }),
+            );
+        }
+    }
+
+    /// Raise presence events for local changes and announce them to sibling instances
+    pub fn apply_presence(&self, connection_id: u64, changes: Changes) {
+        if let Some(cluster) = &self.cluster {
+            for user_id in &changes.local_left {
+                cluster.publish_presence(user_id, false);
+            }
+            for user_id in &changes.local_joined {
+                cluster.publish_presence(user_id, true);
+            }
+        }
+        for user_id in changes.left {
+            self.push_presence(DwebbleWSEventType::PresenceLeft, connection_id, user_id);
+        }
+        for user_id in changes.joined {
+            self.push_presence(DwebbleWSEventType::PresenceJoined, connection_id, user_id);
+        }
+    }
+
+    /// Raise a presence event. `connection_id` is 0 for users on sibling instances.
+    pub fn push_presence(&self, event_type: DwebbleWSEventType, connection_id: u64, user_id: String) {
+        self.push_event(ServerEvent {
+            event_type,
+            connection_id,
+            data: Some(user_id.into_bytes()),
+            error: None,
+            request_id: 0,
+        });
+    }
+
+    /// Check whether a new client may connect, returning the status and reason to refuse it with
+    pub fn admit(
+        &self,
+        settings: &Settings,
+        origin: Option<&str>,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        if !settings.is_origin_allowed(origin) {
+            return Err((StatusCode::FORBIDDEN, "Origin not allowed"));
+        }
+        self.admit_peer(settings)
+    }
+
+    /// Whether a new connection from a trusted peer without an origin may be accepted
+    pub fn admit_peer(&self, settings: &Settings) -> Result<(), (StatusCode, &'static str)> {
+        let refusing = self
+            .refuse_handshakes_until
+            .lock()
+            .is_some_and(|until| tokio::time::Instant::now() < until);
+        if refusing {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Handshakes refused"));
+        }
+        if self.shutting_down.load(Ordering::Relaxed) {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down"));
+        }
+
+        if settings.max_connections > 0
+            && self.connections.len() >= settings.max_connections
+        {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Too many connections"));
+        }
+        Ok(())
+    }
+
+    /// Whether a connection is open or its session is suspended
+    fn is_known(&self, connection_id: u64) -> bool {
+        self.connections.contains(connection_id)
+            || self.sessions.lock().token(connection_id).is_some()
+    }
+
+    /// Simulated network conditions for `conn`: its own override, else the server-wide setting
+    pub fn network_sim(&self, conn: &Connection) -> Option<NetworkSimSettings> {
+        let overridden = *conn.network_sim.lock();
+        overridden.or(self.settings.read().network_sim)
+    }
+
+    /// Buffer a message for a session without a live socket.
+    /// Callers must hold the connections lock so a concurrent resume cannot miss it.
+    fn buffer_for_session(&self, connection_id: u64, msg: Message) -> bool {
+        let limits = self
+            .settings
+            .read()
+            .sessions
+            .as_ref()
+            .map(|s| (s.buffer_max_messages, s.buffer_max_bytes));
+
+        match limits {
+            Some((max_messages, max_bytes)) if max_messages > 0 => self
+                .sessions
+                .lock()
+                .buffer(connection_id, msg, max_messages, max_bytes),
+            _ => false,
+        }
+    }
+}
+
+/// Called at the end of every stop, on the thread of a `stop_async`
+pub type StoppedCallback = Arc<dyn Fn(&DwebbleWSStopStats) + Send + Sync>;
+
+/// What a stop takes over from the server, so it can finish on another thread
+struct Stopping {
+    shared: Arc<Shared>,
+    shutdown_tx: Option<mpsc::Sender<()>>,
+    runtime: Option<tokio::runtime::Runtime>,
+    #[cfg(feature = "port-mapping")]
+    port_mapping: Option<PortMapping>,
+    stopped_callback: Option<StoppedCallback>,
+}
+
+impl Stopping {
+    /// Close the connections, clear the server's state and shut the runtime down. A
+    /// forced stop skips the closing handshakes and flushes and aborts tasks at once.
+    fn finish(mut self, force: bool) {
+        let shared = Arc::clone(&self.shared);
+        let runtime = self.runtime.take();
+
+        if let (Some(shutdown_tx), Some(rt)) = (self.shutdown_tx.take(), runtime.as_ref()) {
+            rt.block_on(async {
+                let _ = shutdown_tx.send(()).await;
+            });
+        }
+
+        let (code, reason, grace_ms, shutdown_timeout_ms) = {
+            let settings = shared.settings.read();
+            (
+                settings.close_code,
+                settings.close_reason.clone(),
+                settings.close_grace_ms,
+                settings.shutdown_timeout_ms,
+            )
+        };
+
+        let connections = shared.connections.len() as u64;
+        if !force {
+            // Send Close frames and give clients a moment to complete the closing handshake
+            for conn in shared.connections.values() {
+                conn.close_with(code, &reason);
+            }
+
+            if let Some(rt) = runtime.as_ref() {
+                let shared = Arc::clone(&shared);
+                rt.block_on(async move {
+                    let deadline = tokio::time::Instant::now() + Duration::from_millis(grace_ms);
+                    while !shared.connections.is_empty()
+                        && tokio::time::Instant::now() < deadline
+                    {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                });
+            }
+        }
+        let force_closed = shared.connections.len() as u64;
+
+        if let Some(cluster) = &shared.cluster {
+            if let (Some(task), Some(rt), false) = (cluster.stop(), runtime.as_ref(), force) {
+                // Give the backend a moment to publish the leave announcement
+                rt.block_on(async {
+                    let _ = tokio::time::timeout(CLUSTER_FLUSH_TIMEOUT, task).await;
+                });
+            }
+        }
+
+        if let Some(webhooks) = &shared.webhooks {
+            let stats = serde_json::json!({
+                "connections": connections,
+                "force_closed": force_closed,
+            });
+            if let (Some(task), Some(rt), false) = (webhooks.stop(stats), runtime.as_ref(), force) {
+                // Give the endpoints a moment to take the queued notifications
+                rt.block_on(async {
+                    let _ = tokio::time::timeout(WEBHOOK_FLUSH_TIMEOUT, task).await;
+                });
+            }
+        }
+
+        #[cfg(feature = "port-mapping")]
+        if let (Some(port_mapping), Some(rt), false) =
+            (self.port_mapping.take(), runtime.as_ref(), force)
+        {
+            // Remove the mapping rather than leave it until the lease runs out
+            let task = port_mapping.stop();
+            rt.block_on(async {
+                let _ = tokio::time::timeout(PORT_MAPPING_REMOVE_TIMEOUT, task).await;
+            });
+        }
+
+        shared.connections.clear();
+        shared.sessions.lock().clear();
+        shared.rooms.lock().clear();
+        shared.history.lock().clear();
+        shared.tags.lock().clear();
+        shared.pipes.lock().clear();
+        shared.event_queues.lock().unbind_all();
+        shared.presence.lock().clear();
+        shared.topics.lock().clear();
+        shared.requests.lock().clear();
+        shared.rpc.lock().clear();
+        shared.socket_io.lock().clear();
+        shared.mqtt.lock().clear();
+        shared.channels.lock().clear();
+        shared.states.lock().clear();
+        shared.receipts.lock().clear();
+        shared.signers.lock().clear();
+        shared.nonces.lock().clear();
+        shared.send_errors.lock().clear();
+        #[cfg(feature = "webtransport")]
+        shared.webtransport.lock().clear();
+        #[cfg(feature = "webrtc")]
+        shared.rtc.lock().clear();
+
+        if let Some(rt) = runtime {
+            if force {
+                rt.shutdown_background();
+            } else {
+                rt.shutdown_timeout(Duration::from_millis(shutdown_timeout_ms));
+            }
+            shared.set_state(DwebbleWSServerState::Stopped);
+            self.stopped(DwebbleWSStopStats {
+                connections,
+                force_closed,
+            });
+        }
+    }
+
+    /// Report a finished stop with a `ServerStopped` event and the stopped callback
+    fn stopped(&self, stats: DwebbleWSStopStats) {
+        tracing::info!(
+            "Server stopped ({} connections, {} force-closed)",
+            stats.connections,
+            stats.force_closed
+        );
+        let data = format!(
+            r#"{{"connections":{},"force_closed":{}}}"#,
+            stats.connections, stats.force_closed
+        );
+        self.shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::ServerStopped,
+            connection_id: 0,
+            data: Some(data.into_bytes()),
+            error: None,
+            request_id: 0,
+        });
+
+        if let Some(callback) = &self.stopped_callback {
+            callback(&stats);
+        }
+    }
+}
+
+/// Address and TLS settings of a listener taking over with `rebind`
+pub struct Listen {
+    pub port: u16,
+    /// `None` keeps the current address
+    pub bind_address: Option<String>,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+/// WebSocket Server
+///
+/// Every method takes `&self` and may be called from any thread. Starting, rebinding
+/// and stopping change the server one at a time: while one is in progress the others
+/// fail with `Busy`.
+pub struct Server {
+    /// Recorded events played back in place of network I/O
+    replay: Option<Replay>,
+    shared: Arc<Shared>,
+    /// Held read-locked by the calls that use it, so a stop waits for them to return
+    runtime: RwLock<Option<Runtime>>,
+    /// Held by the start, rebind or stop in progress
+    lifecycle: Mutex<Lifecycle>,
+    actual_port: Mutex<u16>,
+    /// Bound address of each listener, by protocol
+    listen_addrs: Mutex<Vec<(&'static str, SocketAddr)>>,
+    chaos_tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// Scheduled maintenance shutdown
+    shutdown_task: Mutex<Option<JoinHandle<()>>>,
+    stopped_callback: Mutex<Option<StoppedCallback>>,
+    /// Run on each runtime thread as it starts
+    thread_callback: Mutex<Option<ThreadCallback>>,
+}
+
+/// What starting, rebinding and stopping a server change
+struct Lifecycle {
+    /// Address, port and TLS settings of the next start
+    config: ServerConfig,
+    shutdown_tx: Option<mpsc::Sender<()>>,
+    #[cfg(feature = "port-mapping")]
+    port_mapping: Option<PortMapping>,
+    /// Thread checking the running server for stalls
+    watchdog: Option<Watchdog>,
+    /// Thread of a `stop_async` in progress
+    stopping: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Lifecycle {
+    /// Block until a `stop_async` in progress is done
+    fn wait_for_stop(&mut self) {
+        if let Some(thread) = self.stopping.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Server {
+    pub fn new(mut config: ServerConfig) -> Self {
+        if let Some(level) = &config.settings.log_level {
+            if let Err(e) = logging::set_level(level) {
+                tracing::warn!("Invalid log level '{}': {}", level, e);
+            }
+        }
+
+        let mock = config.settings.mock.clone();
+        let balancer = config.settings.bridge.as_ref().map(Balancer::new);
+        let metrics = config.settings.statsd.clone().map(Metrics::new);
+        let shared = Arc::new(Shared {
+            connections: ConnectionMap::new(config.settings.connection_shards),
+            events: EventRing::new(
+                config.settings.event_queue_capacity,
+                config.settings.event_overflow_limit,
+            ),
+            event_ready: Notify::new(),
+            event_mask: AtomicU64::new(u64::MAX),
+            event_budget: Mutex::new(EventBudget::default()),
+            event_queues: Mutex::new(EventQueues::default()),
+            bans: Mutex::new(Bans::default()),
+            settings: RwLock::new(std::mem::take(&mut config.settings)),
+            sessions: Mutex::new(SessionStore::default()),
+            rooms: Mutex::new(Rooms::default()),
+            history: Mutex::new(History::default()),
+            tags: Mutex::new(Tags::default()),
+            pipes: Mutex::new(Pipes::default()),
+            presence: Mutex::new(Presence::default()),
+            topics: Mutex::new(Topics::default()),
+            requests: Mutex::new(Requests::default()),
+            rpc: Mutex::new(RpcCalls::default()),
+            socket_io: Mutex::new(SocketIo::default()),
+            mqtt: Mutex::new(Mqtt::default()),
+            channels: Mutex::new(Channels::default()),
+            states: Mutex::new(States::default()),
+            receipts: Mutex::new(Receipts::default()),
+            signers: Mutex::new(Signers::default()),
+            nonces: Mutex::new(Guards::default()),
+            middleware: Mutex::new(Chain::default()),
+            send_errors: Mutex::new(HashMap::new()),
+            #[cfg(feature = "webtransport")]
+            webtransport: Mutex::new(Sessions::default()),
+            #[cfg(feature = "webrtc")]
+            rtc: Mutex::new(Peers::default()),
+            #[cfg(feature = "wasm")]
+            wasm_filters: RwLock::new(std::mem::take(&mut config.wasm_filters)),
+            #[cfg(feature = "schema")]
+            schemas: RwLock::new(std::mem::take(&mut config.schemas)),
+            #[cfg(feature = "zstd")]
+            zstd_dictionary: RwLock::new(None),
+            journal: config.journal.take(),
+            #[cfg(feature = "archive")]
+            archive: config.archive.take(),
+            recorder: config.recorder.take(),
+            mock,
+            balancer,
+            cluster: config.cluster.take(),
+            webhooks: config.webhooks.take(),
+            metrics,
+            histograms: Histograms::default(),
+            event_backlog: AtomicUsize::new(0),
+            event_backlog_bytes: AtomicUsize::new(0),
+            backlog_ms: AtomicU64::new(0),
+            polled_ms: AtomicU64::new(watchdog::now_ms()),
+            health_callback: Mutex::new(None),
+            refuse_handshakes_until: Mutex::new(None),
+            shutting_down: AtomicBool::new(false),
+            mapped_addr: Mutex::new(None),
+            public_addr: Mutex::new(None),
+            state: AtomicU8::new(DwebbleWSServerState::Created as u8),
+        });
+
+        Self {
+            replay: config.replay.take(),
+            shared,
+            runtime: RwLock::new(None),
+            lifecycle: Mutex::new(Lifecycle {
+                config,
+                shutdown_tx: None,
+                #[cfg(feature = "port-mapping")]
+                port_mapping: None,
+                watchdog: None,
+                stopping: None,
+            }),
+            actual_port: Mutex::new(0),
+            listen_addrs: Mutex::new(Vec::new()),
+            chaos_tasks: Mutex::new(Vec::new()),
+            shutdown_task: Mutex::new(None),
+            stopped_callback: Mutex::new(None),
+            thread_callback: Mutex::new(None),
+        }
+    }
+
+    /// The lifecycle to change, or `Busy` while another thread is changing it
+    fn lifecycle(&self) -> Result<MutexGuard<'_, Lifecycle>, DwebbleWSResult> {
+        self.lifecycle.try_lock().ok_or(DwebbleWSResult::Busy)
+    }
+
+    /// The runtime, if running. Locked recursively so a call from a callback on another
+    /// thread can't deadlock with a stop waiting for the lock.
+    fn runtime(&self) -> RwLockReadGuard<'_, Option<Runtime>> {
+        self.runtime.read_recursive()
+    }
+
+    pub fn start(&self) -> DwebbleWSResult {
+        let mut lifecycle = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle,
+            Err(result) => return result,
+        };
+        if self.runtime().is_some() {
+            return DwebbleWSResult::AlreadyRunning;
+        }
+        lifecycle.wait_for_stop();
+
+        let previous = self.shared.state();
+        self.shared.set_state(DwebbleWSServerState::Starting);
+        let result = self.launch(&mut lifecycle);
+        self.shared.set_state(match result {
+            DwebbleWSResult::Ok => DwebbleWSServerState::Running,
+            _ => previous,
+        });
+        result
+    }
+
+    /// Where the server is in its lifecycle
+    pub fn state(&self) -> DwebbleWSServerState {
+        self.shared.state()
+    }
+
+    /// Whether calls acting on connections may go ahead: a starting server has none
+    /// yet, and a draining or stopped one has closed them
+    fn is_running(&self) -> bool {
+        self.state() == DwebbleWSServerState::Running
+    }
+
+    /// Start the runtime, bind the listeners and spawn their tasks
+    fn launch(&self, lifecycle: &mut Lifecycle) -> DwebbleWSResult {
+        // A shutdown that came due in the last run is over
+        self.cancel_shutdown();
+
+        let threads = self.shared.settings.read().threads.clone();
+        let runtime = match runtime::build(threads, self.thread_callback.lock().clone()) {
+            Ok(rt) => rt,
+            Err(_) => return DwebbleWSResult::RuntimeError,
+        };
+
+        if let Some(recorder) = &self.shared.recorder {
+            recorder.reset_clock();
+        }
+
+        // Replay mode emits recorded events without any network I/O
+        if let Some(replay) = &self.replay {
+            runtime.spawn(recording::replay(
+                Arc::clone(&self.shared),
+                replay.events.clone(),
+                replay.speed,
+            ));
+            tracing::info!("Replaying {} recorded events", replay.events.len());
+
+            *self.runtime.write() = Some(runtime);
+            return DwebbleWSResult::Ok;
+        }
+
+        let config = &mut lifecycle.config;
+        let addr = format!("{}:{}", config.bind_address, config.port);
+        let listener = match runtime.block_on(TcpListener::bind(&addr)) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::error!("Failed to bind to {}: {}", addr, e);
+                return DwebbleWSResult::BindFailed;
+            }
+        };
+
+        let local_addr = listener.local_addr().unwrap();
+        *self.actual_port.lock() = local_addr.port();
+        let mut listen_addrs = vec![(WEBSOCKET_LISTENER, local_addr)];
+
+        tracing::info!("WebSocket server listening on {}", local_addr);
+
+        #[cfg(feature = "webtransport")]
+        let webtransport = self.shared.settings.read().webtransport.clone();
+        #[cfg(feature = "webtransport")]
+        if let (Some(webtransport), Some(tls)) = (webtransport, &config.tls) {
+            let port = match webtransport.port {
+                0 => local_addr.port(),
+                port => port,
+            };
+            let addr = SocketAddr::new(local_addr.ip(), port);
+            let endpoint = {
+                let _guard = runtime.enter();
+                webtransport::bind(&tls.server_config, addr)
+            };
+            match endpoint {
+                Ok(endpoint) => {
+                    tracing::info!("WebTransport listening on {}", addr);
+                    listen_addrs.push(("webtransport", addr));
+                    runtime.spawn(webtransport::run(
+                        Arc::clone(&self.shared),
+                        endpoint,
+                        webtransport.path,
+                    ));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to bind WebTransport to {}: {}", addr, e);
+                    return DwebbleWSResult::BindFailed;
+                }
+            }
+        }
+
+        if let Some(raw) = self.shared.settings.read().raw.clone() {
+            if raw.tcp_port != 0 {
+                let addr = format!("{}:{}", config.bind_address, raw.tcp_port);
+                match runtime.block_on(TcpListener::bind(&addr)) {
+                    Ok(listener) => {
+                        tracing::info!("Raw TCP listening on {}", addr);
+                        listen_addrs.extend(listener.local_addr().map(|a| ("raw_tcp", a)));
+                        runtime.spawn(raw::run_tcp(Arc::clone(&self.shared), listener));
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to bind raw TCP to {}: {}", addr, e);
+                        return DwebbleWSResult::BindFailed;
+                    }
+                }
+            }
+            if raw.udp_port != 0 {
+                let addr = format!("{}:{}", config.bind_address, raw.udp_port);
+                match runtime.block_on(UdpSocket::bind(&addr)) {
+                    Ok(socket) => {
+                        tracing::info!("Raw UDP listening on {}", addr);
+                        listen_addrs.extend(socket.local_addr().map(|a| ("raw_udp", a)));
+                        runtime.spawn(raw::run_udp(
+                            Arc::clone(&self.shared),
+                            socket,
+                            raw.udp_idle_timeout_ms,
+                        ));
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to bind raw UDP to {}: {}", addr, e);
+                        return DwebbleWSResult::BindFailed;
+                    }
+                }
+            }
+        }
+
+        if let Some(admin) = self.shared.settings.read().admin.clone() {
+            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, admin.port));
+            match runtime.block_on(TcpListener::bind(addr)) {
+                Ok(listener) => {
+                    tracing::info!("Admin endpoint listening on {}", addr);
+                    listen_addrs.extend(listener.local_addr().map(|a| ("admin", a)));
+                    runtime.spawn(admin::run(Arc::clone(&self.shared), listener, admin));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to bind the admin endpoint to {}: {}", addr, e);
+                    return DwebbleWSResult::BindFailed;
+                }
+            }
+        }
+
+        runtime.spawn(eviction::run(Arc::clone(&self.shared)));
+        runtime.spawn(budget::run(Arc::clone(&self.shared)));
+        runtime.spawn(session::run(Arc::clone(&self.shared)));
+        runtime.spawn(netsim::run(Arc::clone(&self.shared)));
+        if let Some(cluster) = &self.shared.cluster {
+            cluster.start(&runtime, Arc::clone(&self.shared));
+        }
+        #[cfg(feature = "port-mapping")]
+        if let Some(port_mapping) = self.shared.settings.read().port_mapping.clone() {
+            lifecycle.port_mapping = Some(PortMapping::start(
+                &runtime,
+                Arc::clone(&self.shared),
+                port_mapping,
+                local_addr,
+            ));
+        }
+        if let Some(socket_io) = &self.shared.settings.read().socket_io {
+            runtime.spawn(socketio::run_heartbeat(
+                Arc::clone(&self.shared),
+                socket_io.clone(),
+            ));
+        }
+        if self.shared.settings.read().mqtt {
+            runtime.spawn(mqtt::run_keep_alive(Arc::clone(&self.shared)));
+        }
+        if let Some(bridge) = &self.shared.settings.read().bridge {
+            if bridge.health_check_interval_ms > 0 {
+                runtime.spawn(bridge::run_health_checks(
+                    Arc::clone(&self.shared),
+                    bridge.health_check_interval_ms,
+                ));
+            }
+        }
+
+        // Kept in the config, so a restart serves TLS again
+        #[cfg(feature = "tls")]
+        let tls_acceptor = self.tls_acceptor(lifecycle.config.tls.as_ref());
+        #[cfg(not(feature = "tls"))]
+        let tls_acceptor = None;
+        lifecycle.shutdown_tx = Some(self.accept(&runtime, listener, tls_acceptor));
+
+        if let Some(webhooks) = &self.shared.webhooks {
+            webhooks.start(&runtime, Arc::clone(&self.shared), local_addr);
+        }
+        if self.shared.metrics.is_some() {
+            runtime.spawn(metrics::run(Arc::clone(&self.shared)));
+        }
+        let resource_report_ms = self.shared.settings.read().resource_report_ms;
+        if resource_report_ms > 0 {
+            let interval = Duration::from_millis(resource_report_ms);
+            runtime.spawn(resources::report(Arc::clone(&self.shared), interval));
+        }
+        if let Some(settings) = self.shared.settings.read().watchdog.clone() {
+            let shared = Arc::clone(&self.shared);
+            match Watchdog::start(runtime.handle().clone(), shared, settings) {
+                Ok(watchdog) => lifecycle.watchdog = Some(watchdog),
+                Err(e) => tracing::error!("Failed to start the watchdog thread: {}", e),
+            }
+        }
+
+        *self.listen_addrs.lock() = listen_addrs;
+        *self.runtime.write() = Some(runtime);
+        DwebbleWSResult::Ok
+    }
+
+    /// Acceptor of a TLS configuration, offering HTTP/2 over ALPN if the `http2` setting is on
+    #[cfg(feature = "tls")]
+    fn tls_acceptor(&self, tls: Option<&TlsConfig>) -> Option<TlsAcceptor> {
+        let http2 = self.shared.settings.read().http2;
+        tls.map(|c| if http2 { c.http2_acceptor() } else { c.acceptor.clone() })
+    }
+
+    /// Accept connections from `listener` until a message on the returned sender
+    fn accept(
+        &self,
+        runtime: &Runtime,
+        listener: TcpListener,
+        tls_acceptor: Option<TlsAcceptor>,
+    ) -> mpsc::Sender<()> {
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        let shared = Arc::clone(&self.shared);
+
+        runtime.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        tracing::info!("Listener shutdown signal received");
+                        break;
+                    }
+                    result = listener.accept() => {
+                        match result {
+                            Ok((stream, addr)) => {
+                                if shared.bans.lock().is_banned(addr.ip()) {
+                                    tracing::debug!("Dropped connection from banned {}", addr);
+                                    continue;
+                                }
+                                let shared = Arc::clone(&shared);
+                                let tls_acceptor = tls_acceptor.clone();
+
+                                tokio::spawn(async move {
+                                    if let Err(e) = handle_connection(
+                                        stream,
+                                        addr,
+                                        shared,
+                                        tls_acceptor,
+                                    ).await {
+                                        tracing::error!("Connection error from {}: {}", addr, e);
+                                    }
+                                });
+                            }
+                            Err(e) => {
+                                tracing::error!("Accept error: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        shutdown_tx
+    }
+
+    /// Listen on a new address, with new TLS settings, in place of the current listener.
+    /// Connections already accepted carry on; if the new address can't be bound the old
+    /// listener stays.
+    pub fn rebind(&self, listen: Listen) -> DwebbleWSResult {
+        let mut lifecycle = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle,
+            Err(result) => return result,
+        };
+        let runtime = self.runtime();
+        let Some(runtime) = runtime.as_ref() else {
+            return DwebbleWSResult::NotRunning;
+        };
+
+        let bind_address = listen
+            .bind_address
+            .unwrap_or_else(|| lifecycle.config.bind_address.clone());
+        let addr = format!("{}:{}", bind_address, listen.port);
+        let listener = match runtime.block_on(TcpListener::bind(&addr)) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::error!("Failed to rebind to {}: {}", addr, e);
+                return DwebbleWSResult::BindFailed;
+            }
+        };
+        let local_addr = listener.local_addr().unwrap();
+
+        if let Some(shutdown_tx) = lifecycle.shutdown_tx.take() {
+            runtime.block_on(async {
+                let _ = shutdown_tx.send(()).await;
+            });
+        }
+        #[cfg(feature = "tls")]
+        let tls_acceptor = self.tls_acceptor(listen.tls.as_ref());
+        #[cfg(not(feature = "tls"))]
+        let tls_acceptor = None;
+        lifecycle.shutdown_tx = Some(self.accept(runtime, listener, tls_acceptor));
+
+        tracing::info!("WebSocket server rebound to {}", local_addr);
+        lifecycle.config.bind_address = bind_address;
+        lifecycle.config.port = listen.port;
+        *self.actual_port.lock() = local_addr.port();
+        for (kind, addr) in self.listen_addrs.lock().iter_mut() {
+            if *kind == WEBSOCKET_LISTENER {
+                *addr = local_addr;
+            }
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Close connections gracefully, wait for the runtime to shut down and report it
+    pub fn stop(&self) -> DwebbleWSResult {
+        match self.lifecycle() {
+            Ok(mut lifecycle) => {
+                self.begin_stop(&mut lifecycle).finish(false);
+                DwebbleWSResult::Ok
+            }
+            Err(result) => result,
+        }
+    }
+
+    /// Like `stop`, but on a thread of its own; the `ServerStopped` event tells when it is
+    /// done. `single-thread` builds stop on the calling thread instead.
+    pub fn stop_async(&self) -> DwebbleWSResult {
+        let mut lifecycle = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle,
+            Err(result) => return result,
+        };
+        if self.runtime().is_none() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        let stopping = self.begin_stop(&mut lifecycle);
+        if cfg!(feature = "single-thread") {
+            stopping.finish(false);
+            return DwebbleWSResult::Ok;
+        }
+        match std::thread::Builder::new()
+            .name("dwebble-stop".to_string())
+            .spawn(move || stopping.finish(false))
+        {
+            Ok(thread) => {
+                lifecycle.stopping = Some(thread);
+                DwebbleWSResult::Ok
+            }
+            Err(e) => {
+                tracing::error!("Failed to start the stopping thread: {}", e);
+                DwebbleWSResult::RuntimeError
+            }
+        }
+    }
+
+    /// Stop without Close frames or waiting: tasks are aborted and every open connection
+    /// counts as force-closed
+    pub fn force_stop(&self) -> DwebbleWSResult {
+        let mut lifecycle = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle,
+            Err(result) => return result,
+        };
+        if self.runtime().is_none() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        self.begin_stop(&mut lifecycle).finish(true);
+        DwebbleWSResult::Ok
+    }
+
+    /// Take what a stop needs out of the server, after any stop still in progress. Waits
+    /// for the calls using the runtime to return.
+    fn begin_stop(&self, lifecycle: &mut Lifecycle) -> Stopping {
+        lifecycle.wait_for_stop();
+        if let Some(watchdog) = lifecycle.watchdog.take() {
+            watchdog.stop();
+        }
+        *self.actual_port.lock() = 0;
+        self.listen_addrs.lock().clear();
+        *self.shared.public_addr.lock() = None;
+
+        let runtime = self.runtime.write().take();
+        if runtime.is_some() {
+            self.shared.set_state(DwebbleWSServerState::Draining);
+        }
+        Stopping {
+            shared: Arc::clone(&self.shared),
+            shutdown_tx: lifecycle.shutdown_tx.take(),
+            runtime,
+            #[cfg(feature = "port-mapping")]
+            port_mapping: lifecycle.port_mapping.take(),
+            stopped_callback: self.stopped_callback.lock().clone(),
+        }
+    }
+
+    /// Register a callback run at the end of every stop, replacing any earlier one.
+    /// `None` removes it.
+    pub fn set_stopped_callback(&self, callback: Option<StoppedCallback>) {
+        *self.stopped_callback.lock() = callback;
+    }
+
+    /// Register a callback run on each runtime thread as it starts, replacing any earlier
+    /// one from the next start. `None` removes it. Fails with `AlreadyRunning` while the
+    /// runtime's threads may still call the earlier one.
+    pub fn set_thread_callback(&self, callback: Option<ThreadCallback>) -> DwebbleWSResult {
+        let lifecycle = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle,
+            Err(result) => return result,
+        };
+        let stopping = lifecycle.stopping.as_ref().is_some_and(|thread| !thread.is_finished());
+        if self.runtime().is_some() || stopping {
+            return DwebbleWSResult::AlreadyRunning;
+        }
+        *self.thread_callback.lock() = callback;
+        DwebbleWSResult::Ok
+    }
+
+    /// Register a callback run with each health warning of the watchdog, replacing any
+    /// earlier one once a call in progress returns. `None` removes it.
+    pub fn set_health_callback(&self, callback: Option<HealthCallback>) {
+        *self.shared.health_callback.lock() = callback;
+    }
+
+    /// Drive a `single-thread` build's runtime for up to `budget`. Does nothing in
+    /// other builds.
+    pub fn tick(&self, budget: Duration) -> DwebbleWSResult {
+        match self.runtime().as_ref() {
+            Some(rt) => {
+                runtime::tick(rt, budget);
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::NotRunning,
+        }
+    }
+
+    /// Raise only the event types whose bit is set in `mask` (bit N for type value N).
+    /// Recordings still capture every event.
+    pub fn set_event_mask(&self, mask: u64) {
+        self.shared.event_mask.store(mask, Ordering::Relaxed);
+    }
+
+    pub fn poll_event(&self) -> Option<ServerEvent> {
+        self.shared
+            .polled_ms
+            .store(watchdog::now_ms(), Ordering::Relaxed);
+        let queued = self.shared.events.pop()?;
+        self.shared.event_backlog.fetch_sub(1, Ordering::Relaxed);
+        self.shared
+            .event_backlog_bytes
+            .fetch_sub(queued.event.payload_len(), Ordering::Relaxed);
+        Some(self.shared.polled(queued))
+    }
+
+    /// Wait for the next event, for hosts taking events on a task instead of polling
+    /// each frame
+    pub async fn next_event(&self) -> ServerEvent {
+        loop {
+            let notified = self.shared.event_ready.notified();
+            tokio::pin!(notified);
+            // Registered before looking, so an event arriving in between wakes it
+            notified.as_mut().enable();
+            if let Some(event) = self.poll_event() {
+                return event;
+            }
+            // Events are counted before they are queued: one is on its way
+            if self.shared.event_backlog.load(Ordering::Relaxed) > 0 {
+                tokio::task::yield_now().await;
+                continue;
+            }
+            notified.await;
+        }
+    }
+
+    /// The checksum of a polled event's data, with the `checksum` setting (0 otherwise)
+    pub fn event_checksum(&self, event: &ServerEvent) -> u32 {
+        self.shared.event_checksum(event)
+    }
+
+    /// Poll up to `max` events at once, updating the backlog once for them all
+    pub fn poll_events(&self, max: usize) -> Vec<ServerEvent> {
+        self.shared
+            .polled_ms
+            .store(watchdog::now_ms(), Ordering::Relaxed);
+        let mut queued = Vec::new();
+        self.shared.events.pop_into(&mut queued, max);
+        if queued.is_empty() {
+            return vec![];
+        }
+        let bytes: usize = queued.iter().map(|queued| queued.event.payload_len()).sum();
+        self.shared
+            .event_backlog
+            .fetch_sub(queued.len(), Ordering::Relaxed);
+        self.shared
+            .event_backlog_bytes
+            .fetch_sub(bytes, Ordering::Relaxed);
+        queued
+            .into_iter()
+            .map(|queued| self.shared.polled(queued))
+            .collect()
+    }
+
+    /// Threads, tasks and the bytes held in queues and buffers
+    pub fn resource_usage(&self) -> DwebbleWSResourceUsage {
+        let runtime = self.runtime();
+        resources::usage(&self.shared, runtime.as_ref().map(|rt| rt.handle()))
+    }
+
+    /// Summaries of the message size and event latency histograms, as JSON
+    pub fn histograms(&self) -> serde_json::Value {
+        self.shared.histograms.summary()
+    }
+
+    /// Why the latest failed send to a connection failed, kept until it is removed
+    pub fn last_send_error(&self, connection_id: u64) -> Option<String> {
+        self.shared.send_errors.lock().get(&connection_id).cloned()
+    }
+
+    /// Queue binary data for a connection. Returns `ConnectionClosed` if it has closed
+    /// or is suspended without a replay buffer, `QueueFull` if its send queue would go
+    /// over the limit, `InvalidHandle` if the ID was never issued, and `NotRunning`
+    /// unless the server is running.
+    pub fn send(&self, connection_id: u64, data: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared
+            .send_message(connection_id, Message::Binary(self.shared.escape(data).into()))
+    }
+
+    /// Queue binary data for a connection under a dedupe key, replacing the message
+    /// queued under the key earlier if that hasn't been sent yet. Returns as `send`.
+    pub fn send_with_key(&self, connection_id: u64, key: &str, data: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.send_message_as(
+            connection_id,
+            Queueing::Keyed(key),
+            Message::Binary(self.shared.escape(data).into()),
+        )
+    }
+
+    /// Queue binary data for a connection, dropping it if it hasn't been sent within
+    /// `ttl`. Returns as `send`.
+    pub fn send_with_ttl(&self, connection_id: u64, data: &[u8], ttl: Duration) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.send_message_as(
+            connection_id,
+            Queueing::Expiring(Instant::now() + ttl),
+            Message::Binary(self.shared.escape(data).into()),
+        )
+    }
+
+    /// Send a payload wrapped in a typed envelope using the configured codec.
+    /// Returns `InvalidParam` if no codec is configured.
+    pub fn send_typed(&self, connection_id: u64, type_id: u32, payload: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        let Some(typed_codec) = self.shared.settings.read().typed_codec else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let data = codec::encode(typed_codec, type_id, payload);
+        self.shared
+            .send_message(connection_id, Message::Binary(data.into()))
+    }
+
+    /// Send a payload on a virtual channel. Returns `InvalidParam` if the channel is
+    /// not configured.
+    pub fn send_channel(&self, connection_id: u64, channel: u8, payload: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        let (frame, reliable) = {
+            let settings = self.shared.settings.read();
+            let mut channels = self.shared.channels.lock();
+            let frame = channels.send(&settings.channels, connection_id, channel, payload);
+            let reliable = settings
+                .channels
+                .get(usize::from(channel))
+                .is_some_and(|c| c.mode == ChannelMode::Reliable);
+            (frame, reliable)
+        };
+        let Some(frame) = frame else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let result = self.shared.send_control(connection_id, frame);
+        if result == DwebbleWSResult::ConnectionClosed {
+            if self.shared.sessions.lock().token(connection_id).is_none() {
+                // No such connection, so nothing will ever acknowledge it
+                self.shared.channels.lock().remove_connection(connection_id);
+            } else if reliable {
+                // Suspended without a replay buffer; it goes out again on resume
+                return DwebbleWSResult::Ok;
+            }
+        }
+        result
+    }
+
+    /// Statistics of a virtual channel of a connection
+    pub fn channel_stats(&self, connection_id: u64, channel: u8) -> DwebbleWSChannelStats {
+        self.shared.channels.lock().stats(connection_id, channel)
+    }
+
+    /// Register a middleware callback run on every message, returning its ID
+    pub fn add_middleware(&self, callback: middleware::Callback, priority: i32) -> u64 {
+        self.shared.middleware.lock().add(callback, priority)
+    }
+
+    /// Unregister a middleware callback. Returns false if the ID is unknown.
+    pub fn remove_middleware(&self, id: u64) -> bool {
+        self.shared.middleware.lock().remove(id)
+    }
+
+    /// Send the latest version of a keyed state, as a delta against the version the
+    /// client last acknowledged where that is smaller. Returns `InvalidParam` if state
+    /// sync is disabled.
+    pub fn send_state(&self, connection_id: u64, key: u32, state: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        let frame = {
+            let settings = self.shared.settings.read();
+            let Some(delta) = &settings.delta else {
+                return DwebbleWSResult::InvalidParam;
+            };
+            if self.replay.is_some() {
+                return DwebbleWSResult::Ok;
+            }
+            self.shared
+                .states
+                .lock()
+                .update(delta, connection_id, key, state)
+        };
+
+        let result = self.shared.send_control(connection_id, frame);
+        if result == DwebbleWSResult::ConnectionClosed
+            && self.shared.sessions.lock().token(connection_id).is_none()
+        {
+            self.shared.states.lock().remove_connection(connection_id);
+        }
+        result
+    }
+
+    /// Send binary data numbered with the connection's next sequence, which the
+    /// client acknowledges. Returns `InvalidParam` if receipts are disabled.
+    pub fn send_sequenced(
+        &self,
+        connection_id: u64,
+        payload: &[u8],
+    ) -> Result<u64, DwebbleWSResult> {
+        if !self.is_running() {
+            return Err(DwebbleWSResult::NotRunning);
+        }
+        if !self.shared.settings.read().receipts {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+        let (sequence, data) = self.shared.receipts.lock().wrap(connection_id, payload);
+        if self.replay.is_some() {
+            return Ok(sequence);
+        }
+
+        match self.shared.send_control(connection_id, data) {
+            DwebbleWSResult::Ok => Ok(sequence),
+            result => {
+                if result == DwebbleWSResult::ConnectionClosed
+                    && self.shared.sessions.lock().token(connection_id).is_none()
+                {
+                    self.shared.receipts.lock().remove_connection(connection_id);
+                }
+                Err(result)
+            }
+        }
+    }
+
+    /// Highest sequence a connection acknowledged, 0 if none
+    pub fn acked_sequence(&self, connection_id: u64) -> u64 {
+        self.shared.receipts.lock().acked(connection_id)
+    }
+
+    /// Sign a connection's messages with an HMAC key and require its messages to be
+    /// signed with it (`None` to stop)
+    pub fn set_signing_key(&self, connection_id: u64, key: Option<&[u8]>) -> DwebbleWSResult {
+        if !self.shared.is_known(connection_id) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+        self.shared.signers.lock().set_key(connection_id, key);
+        DwebbleWSResult::Ok
+    }
+
+    /// Make a text message of `text` under the UTF-8 policy. Returns `InvalidUtf8` if
+    /// the policy rejects it.
+    pub fn text_message(&self, text: &[u8]) -> Result<Message, DwebbleWSResult> {
+        let policy = self.shared.settings.read().utf8_policy;
+        utf8::message(policy, text).ok_or(DwebbleWSResult::InvalidUtf8)
+    }
+
+    pub fn send_text(&self, connection_id: u64, text: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        let msg = match self.text_message(text) {
+            Ok(msg) => msg,
+            Err(result) => return result,
+        };
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.send_message(connection_id, msg)
+    }
+
+    pub fn disconnect(&self, connection_id: u64) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.close_connection(connection_id, None)
+    }
+
+    /// Stop or resume reading a connection's messages, e.g. while the host works through
+    /// a backlog. Messages already read are still delivered.
+    pub fn set_read_paused(&self, connection_id: u64, paused: bool) -> DwebbleWSResult {
+        match self.shared.connections.get(connection_id) {
+            Some(conn) => {
+                conn.set_read_paused(paused);
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidHandle,
+        }
+    }
+
+    /// Deliver the messages a connection held back under the `hold_messages` setting and
+    /// stop holding them. Does nothing for a connection holding none.
+    pub fn acknowledge_connection(&self, connection_id: u64) -> DwebbleWSResult {
+        match self.shared.connections.get(connection_id) {
+            Some(conn) => {
+                conn.acknowledge();
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidHandle,
+        }
+    }
+
+    /// Mark a connection authenticated under the `authentication` setting, resuming the
+    /// delivery of its messages after the login messages
+    pub fn mark_authenticated(&self, connection_id: u64) -> DwebbleWSResult {
+        match self.shared.connections.get(connection_id) {
+            Some(conn) => {
+                conn.authenticate();
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidHandle,
+        }
+    }
+
+    /// Rotate a connection's encryption keys with a fresh key exchange, raising
+    /// `KeyRotated` once both directions use the new keys. Returns `InvalidParam` if
+    /// the connection has not completed a key exchange.
+    pub fn rekey(&self, connection_id: u64) -> DwebbleWSResult {
+        match self.shared.connections.get(connection_id) {
+            Some(conn) if conn.encrypted() => {
+                conn.rekey();
+                DwebbleWSResult::Ok
+            }
+            Some(_) => DwebbleWSResult::InvalidParam,
+            None => DwebbleWSResult::InvalidHandle,
+        }
+    }
+
+    /// Hand a live connection over to `target`, a server running in the same process.
+    /// The socket, queued messages and encryption keys move along, so the client
+    /// stays connected under the same ID. Rooms, topics, sessions and protocol state
+    /// stay behind. Connections on HTTP/2, in bridge mode or still exchanging or
+    /// rotating keys can't migrate.
+    pub fn migrate(&self, connection_id: u64, target: &Server) -> DwebbleWSResult {
+        let (runtime, target_runtime) = (self.runtime(), target.runtime());
+        let (Some(runtime), Some(target_runtime)) = (&*runtime, &*target_runtime) else {
+            return DwebbleWSResult::NotRunning;
+        };
+        if Arc::ptr_eq(&self.shared, &target.shared) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        let Some(conn) = self.shared.connections.get(connection_id) else {
+            return DwebbleWSResult::InvalidHandle;
+        };
+
+        let (done, result) = oneshot::channel();
+        let migration = Migration {
+            shared: Arc::clone(&target.shared),
+            runtime: target_runtime.handle().clone(),
+            done,
+        };
+        if conn.migrate(migration).is_err() {
+            return DwebbleWSResult::InvalidParam;
+        }
+        // The reader drops the request if the connection closes first
+        runtime
+            .block_on(result)
+            .unwrap_or(DwebbleWSResult::ConnectionClosed)
+    }
+
+    /// Close every connection with `code` and `reason`, and end suspended sessions
+    /// Returns `InvalidParam` if `code` may not be sent in a Close frame.
+    pub fn kick_all(&self, code: u16, reason: &str) -> DwebbleWSResult {
+        if !connection::is_sendable_close_code(code) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let mut connection_ids: Vec<u64> = self.shared.connections.ids();
+        connection_ids.extend(self.shared.sessions.lock().suspended());
+        for connection_id in connection_ids {
+            self.shared.close_connection(connection_id, Some((code, reason)));
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Close every connection from `ip` with `code` and `reason`
+    /// Returns `InvalidParam` if `code` may not be sent in a Close frame.
+    pub fn kick_ip(&self, ip: IpAddr, code: u16, reason: &str) -> DwebbleWSResult {
+        if !connection::is_sendable_close_code(code) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.kick_ip(ip, code, reason);
+        DwebbleWSResult::Ok
+    }
+
+    /// Refuse connections from `ip` for `duration`, or until unbanned, and close those
+    /// open with `reason`
+    pub fn ban_ip(&self, ip: IpAddr, duration: Option<Duration>, reason: &str) -> DwebbleWSResult {
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        self.shared.ban_ip(ip, duration, reason);
+        DwebbleWSResult::Ok
+    }
+
+    /// Lift the ban of `ip`. Returns `InvalidParam` if it isn't banned.
+    pub fn unban_ip(&self, ip: IpAddr) -> DwebbleWSResult {
+        if self.shared.bans.lock().unban(ip) {
+            tracing::info!("Unbanned {}", ip);
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// Close every member of a room with `code` and `reason`, ending the sessions of
+    /// suspended members
+    /// Returns `InvalidParam` if `code` may not be sent in a Close frame.
+    pub fn kick_room(&self, room: &str, code: u16, reason: &str) -> DwebbleWSResult {
+        if !connection::is_sendable_close_code(code) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let members = self.shared.rooms.lock().members(room);
+        for connection_id in members {
+            self.shared.close_connection(connection_id, Some((code, reason)));
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Add a connection to a room. Members stay in their rooms while their session is suspended.
+    /// With the `history` setting's `replay_on_join`, a new member is sent the room's history.
+    pub fn join_room(&self, connection_id: u64, room: &str) -> DwebbleWSResult {
+        if !self.shared.is_known(connection_id) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+
+        let replay_on_join = self.shared.settings.read().history.is_some_and(|h| h.replay_on_join);
+        let history = {
+            let mut rooms = self.shared.rooms.lock();
+            let joined = rooms.join(room, connection_id);
+            if joined && replay_on_join {
+                self.shared.history.lock().latest(room, usize::MAX)
+            } else {
+                Vec::new()
+            }
+        };
+        for msg in history {
+            self.shared.send_message(connection_id, msg);
+        }
+        self.shared.bind_event_queue(connection_id);
+        DwebbleWSResult::Ok
+    }
+
+    pub fn leave_room(&self, connection_id: u64, room: &str) -> DwebbleWSResult {
+        if self.shared.rooms.lock().leave(room, connection_id) {
+            self.shared.bind_event_queue(connection_id);
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// Up to `max` of the latest messages broadcast to a room, oldest first, as the
+    /// host sent them. Empty without the `history` setting.
+    pub fn history(&self, room: &str, max: usize) -> Vec<Message> {
+        let history = self.shared.history.lock().latest(room, max);
+        history
+            .into_iter()
+            .map(|msg| match msg {
+                Message::Binary(data) => {
+                    let payload = self.shared.unescape(PayloadKind::Binary, &data);
+                    Message::Binary(data.slice_ref(payload))
+                }
+                msg => msg,
+            })
+            .collect()
+    }
+
+    /// Forget a room's history, e.g. when its match ends. Returns `InvalidParam` if
+    /// it has none.
+    pub fn clear_history(&self, room: &str) -> DwebbleWSResult {
+        if self.shared.history.lock().remove(room) {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// Open a queue taking the events of a room's members, to poll on another thread
+    pub fn open_event_queue(&self, room: &str) -> Result<EventQueue, DwebbleWSResult> {
+        if room.is_empty() {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+
+        Ok(EventQueue::open(&self.shared, room))
+    }
+
+    /// Tag a connection. Tags stay while its session is suspended.
+    pub fn set_tag(&self, connection_id: u64, tag: &str) -> DwebbleWSResult {
+        if !self.shared.is_known(connection_id) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+        if tag.is_empty() {
+            return DwebbleWSResult::InvalidParam;
+        }
+
+        self.shared.tags.lock().add(connection_id, tag);
+        DwebbleWSResult::Ok
+    }
+
+    pub fn clear_tag(&self, connection_id: u64, tag: &str) -> DwebbleWSResult {
+        if self.shared.tags.lock().remove(connection_id, tag) {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    pub fn tags(&self, connection_id: u64) -> Vec<String> {
+        self.shared.tags.lock().tags(connection_id)
+    }
+
+    /// Send a message to every connection with a tag on this instance
+    pub fn broadcast_tag(&self, tag: &str, msg: Message) -> DwebbleWSResult {
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let msg = escape_message(&self.shared, msg);
+        let tagged = self.shared.tags.lock().tagged(tag);
+        for connection_id in tagged {
+            self.shared.send_message(connection_id, msg.clone());
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Close every connection with a tag with `code` and `reason`, ending the sessions
+    /// of suspended ones. Returns `InvalidParam` if `code` may not be sent in a Close frame.
+    pub fn kick_tag(&self, tag: &str, code: u16, reason: &str) -> DwebbleWSResult {
+        if !connection::is_sendable_close_code(code) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let tagged = self.shared.tags.lock().tagged(tag);
+        for connection_id in tagged {
+            self.shared.close_connection(connection_id, Some((code, reason)));
+        }
+        DwebbleWSResult::Ok
+    }
+
+    pub fn tag_stats(&self, tag: &str) -> DwebbleWSTagStats {
+        let tagged = self.shared.tags.lock().tagged(tag);
+        let mut stats = DwebbleWSTagStats::default();
+        for connection_id in tagged {
+            match self.shared.connections.get(connection_id) {
+                Some(conn) => {
+                    stats.connections += 1;
+                    stats.queued_bytes += conn.pending_bytes() as u64;
+                }
+                None => stats.suspended += 1,
+            }
+        }
+        stats
+    }
+
+    /// Relay the text and binary messages of two connections to each other instead
+    /// of raising events for them, until `unpipe` or either connection ends. Returns
+    /// `InvalidParam` if they are the same connection or either is piped already.
+    pub fn pipe(&self, a: u64, b: u64) -> DwebbleWSResult {
+        if !self.shared.is_known(a) || !self.shared.is_known(b) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+
+        if self.shared.pipes.lock().pair(a, b) {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// End the pipe of a connection; both connections' messages reach the host again
+    pub fn unpipe(&self, connection_id: u64) -> DwebbleWSResult {
+        if self.shared.pipes.lock().remove_connection(connection_id).is_some() {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// Send a message to every member of a room, including members on sibling instances
+    pub fn broadcast_room(&self, room: &str, msg: Message) -> DwebbleWSResult {
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let msg = escape_message(&self.shared, msg);
+        self.shared.send_to_room(room, &msg);
+        if let Some(cluster) = &self.shared.cluster {
+            cluster.publish_room(room, &msg);
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Send a request and wait up to `timeout` for the client's response.
+    /// Returns the request ID; the outcome is reported by a `ResponseReceived`
+    /// or `RequestTimedOut` event carrying it.
+    pub fn request(
+        &self,
+        connection_id: u64,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<u64, DwebbleWSResult> {
+        let request_id = self.track_request(connection_id, timeout)?;
+
+        // Replayed connections cannot answer; the request simply times out
+        if self.replay.is_none() {
+            let data = requests::encode_request(request_id, payload);
+            let result = self.shared.send_control(connection_id, data);
+            if result != DwebbleWSResult::Ok {
+                self.shared.requests.lock().remove(request_id);
+                return Err(result);
+            }
+        }
+        Ok(request_id)
+    }
+
+    /// Register a pending request that raises `RequestTimedOut` unless answered in time
+    fn track_request(&self, connection_id: u64, timeout: Duration) -> Result<u64, DwebbleWSResult> {
+        let runtime = self.runtime();
+        let Some(runtime) = runtime.as_ref() else {
+            return Err(DwebbleWSResult::NotRunning);
+        };
+
+        let mut requests = self.shared.requests.lock();
+        let request_id = requests.next_id();
+        let shared = Arc::clone(&self.shared);
+        let timeout_task = runtime.spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if shared.requests.lock().remove(request_id) {
+                shared.push_event(ServerEvent {
+                    event_type: DwebbleWSEventType::RequestTimedOut,
+                    connection_id,
+                    data: None,
+                    error: None,
+                    request_id,
+                });
+            }
+        });
+        requests.insert(request_id, connection_id, timeout_task);
+        Ok(request_id)
+    }
+
+    /// Emit a Socket.IO event to a client. `args_json` is a JSON array of arguments.
+    pub fn socketio_emit(
+        &self,
+        connection_id: u64,
+        event: &str,
+        args_json: &str,
+    ) -> DwebbleWSResult {
+        let Some(args) = parse_args(args_json) else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        socketio::emit(&self.shared, connection_id, event, args, None)
+    }
+
+    /// Emit a Socket.IO event and wait up to `timeout` for the client's
+    /// acknowledgement, reported like a request's response.
+    pub fn socketio_emit_with_ack(
+        &self,
+        connection_id: u64,
+        event: &str,
+        args_json: &str,
+        timeout: Duration,
+    ) -> Result<u64, DwebbleWSResult> {
+        let Some(args) = parse_args(args_json) else {
+            return Err(DwebbleWSResult::InvalidParam);
+        };
+
+        let request_id = self.track_request(connection_id, timeout)?;
+        if self.replay.is_none() {
+            let ack_id = Some(request_id);
+            let result = socketio::emit(&self.shared, connection_id, event, args, ack_id);
+            if result != DwebbleWSResult::Ok {
+                self.shared.requests.lock().remove(request_id);
+                return Err(result);
+            }
+        }
+        Ok(request_id)
+    }
+
+    /// Acknowledge a client's Socket.IO event. `args_json` is a JSON array of arguments.
+    pub fn socketio_ack(&self, ack: u64, args_json: &str) -> DwebbleWSResult {
+        let Some(args) = parse_args(args_json) else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        socketio::ack(&self.shared, ack, args)
+    }
+
+    /// Subscribe a connection, or the host with `topics::HOST`, to a topic pattern.
+    /// Connections stay subscribed while their session is suspended.
+    pub fn subscribe(&self, subscriber: u64, pattern: &str) -> DwebbleWSResult {
+        if !topics::is_valid_pattern(pattern) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if subscriber != topics::HOST && !self.shared.is_known(subscriber) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+
+        self.shared.topics.lock().subscribe(subscriber, pattern);
+        DwebbleWSResult::Ok
+    }
+
+    pub fn unsubscribe(&self, subscriber: u64, pattern: &str) -> DwebbleWSResult {
+        if self.shared.topics.lock().unsubscribe(subscriber, pattern) {
+            DwebbleWSResult::Ok
+        } else {
+            DwebbleWSResult::InvalidParam
+        }
+    }
+
+    /// Send a message to every subscriber of a topic, including subscribers on sibling instances
+    pub fn publish(&self, topic: &str, msg: Message) -> DwebbleWSResult {
+        if !topics::is_valid_topic(topic) {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let msg = escape_message(&self.shared, msg);
+        self.shared.send_to_topic(topic, &msg);
+        if let Some(cluster) = &self.shared.cluster {
+            cluster.publish_topic(topic, &msg);
+        }
+        DwebbleWSResult::Ok
+    }
+
+    /// Send an unreliable datagram to a WebTransport connection
+    pub fn send_datagram(&self, connection_id: u64, data: &[u8]) -> DwebbleWSResult {
+        if !self.is_running() {
+            return DwebbleWSResult::NotRunning;
+        }
+
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        #[cfg(feature = "webtransport")]
+        return webtransport::send_datagram(&self.shared, connection_id, data);
+
+        #[cfg(not(feature = "webtransport"))]
+        {
+            let _ = (connection_id, data);
+            DwebbleWSResult::InvalidHandle
+        }
+    }
+
+    /// Publish a message to the MQTT clients subscribed to `topic`. QoS is 0 or 1;
+    /// a retained message replaces the topic's previous one (an empty payload clears it).
+    pub fn mqtt_publish(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        qos: u8,
+        retain: bool,
+    ) -> DwebbleWSResult {
+        if !mqtt::is_valid_topic(topic) || qos > 1 {
+            return DwebbleWSResult::InvalidParam;
+        }
+        if self.replay.is_some() {
+            return DwebbleWSResult::Ok;
+        }
+
+        let publication = Publication {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+        };
+        mqtt::route(&self.shared, publication, None);
+        DwebbleWSResult::Ok
+    }
+
+    /// Answer a JSON-RPC call with a result given as JSON text
+    pub fn rpc_reply(&self, call_id: u64, result_json: &str) -> DwebbleWSResult {
+        let Ok(result) = serde_json::from_str(result_json) else {
+            return DwebbleWSResult::InvalidParam;
+        };
+        jsonrpc::reply(&self.shared, call_id, result)
+    }
+
+    /// Answer a JSON-RPC call with an error, optionally carrying data given as JSON text
+    pub fn rpc_error(
+        &self,
+        call_id: u64,
+        code: i32,
+        message: &str,
+        data_json: Option<&str>,
+    ) -> DwebbleWSResult {
+        let data = match data_json.map(serde_json::from_str).transpose() {
+            Ok(data) => data,
+            Err(_) => return DwebbleWSResult::InvalidParam,
+        };
+        jsonrpc::reply_error(&self.shared, call_id, code, message, data)
+    }
+
+    /// Identify a connection as a logical user, or clear its identity with `None`.
+    /// Users stay online while their session is suspended.
+    pub fn set_user_id(&self, connection_id: u64, user_id: Option<&str>) -> DwebbleWSResult {
+        if !self.shared.is_known(connection_id) {
+            return DwebbleWSResult::InvalidHandle;
+        }
+
+        let changes = {
+            let mut presence = self.shared.presence.lock();
+            match user_id {
+                Some(user_id) => presence.identify(connection_id, user_id),
+                None => presence.remove(connection_id),
+            }
+        };
+        self.shared.apply_presence(connection_id, changes);
+        DwebbleWSResult::Ok
+    }
+
+    pub fn user_id(&self, connection_id: u64) -> Option<String> {
+        self.shared
+            .presence
+            .lock()
+            .user_id(connection_id)
+            .map(str::to_string)
+    }
+
+    /// Whether a user is online on this instance or a sibling instance
+    pub fn is_user_online(&self, user_id: &str) -> bool {
+        self.shared.presence.lock().is_online(user_id)
+    }
+
+    /// Online users, sorted. With a room, only users with a member
+    /// connection on this instance are listed.
+    pub fn online_users(&self, room: Option<&str>) -> Vec<String> {
+        let Some(room) = room else {
+            return self.shared.presence.lock().online_users();
+        };
+
+        let members = self.shared.rooms.lock().members(room);
+        let presence = self.shared.presence.lock();
+        let mut users: Vec<String> = members
+            .into_iter()
+            .filter_map(|connection_id| presence.user_id(connection_id))
+            .map(str::to_string)
+            .collect();
+        users.sort_unstable();
+        users.dedup();
+        users
+    }
+
+    pub fn get_actual_port(&self) -> u16 {
+        *self.actual_port.lock()
+    }
+
+    pub fn get_connection_count(&self) -> usize {
+        self.shared.connections.len()
+    }
+
+    /// Message events dropped over the `event_budget` setting since the server was
+    /// created
+    pub fn dropped_event_count(&self) -> u64 {
+        self.shared.event_budget.lock().dropped()
+    }
+
+    pub fn connection_info(&self, connection_id: u64) -> Option<DwebbleWSConnectionInfo> {
+        let conn = self.shared.connections.get(connection_id)?;
+        let (raw_bytes_sent, compressed_bytes_sent) = conn.compression.sent_bytes();
+        let (compressed_bytes_received, raw_bytes_received) = conn.compression.received_bytes();
+        let (compression_level, dictionary_id) = conn.compression.parameters();
+        let version = conn.version();
+        Some(DwebbleWSConnectionInfo {
+            encrypted: conn.encrypted(),
+            key_rotations: conn.key_rotations(),
+            tls_fingerprint: conn.fingerprint.tls,
+            header_fingerprint: conn.fingerprint.headers,
+            queued_bytes: conn.pending_bytes() as u64,
+            expired_messages: conn.expired(),
+            compression: conn.compression.mode(),
+            compression_level,
+            dictionary_id,
+            raw_bytes_sent,
+            compressed_bytes_sent,
+            compressed_bytes_received,
+            raw_bytes_received,
+            version_negotiated: version.is_some(),
+            version_major: version.map_or(0, |v| v.major),
+            version_minor: version.map_or(0, |v| v.minor),
+        })
+    }
+
+    pub fn session_token(&self, connection_id: u64) -> Option<String> {
+        self.shared
+            .sessions
+            .lock()
+            .token(connection_id)
+            .map(str::to_string)
+    }
+
+    /// Include or exclude a connection from the journal
+    pub fn journal_select(&self, connection_id: u64, enabled: bool) -> DwebbleWSResult {
+        match &self.shared.journal {
+            Some(journal) => {
+                journal.select(connection_id, enabled);
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidParam,
+        }
+    }
+
+    /// Include or exclude the members of a room from the journal
+    pub fn journal_select_room(&self, room: &str, enabled: bool) -> DwebbleWSResult {
+        match &self.shared.journal {
+            Some(journal) => {
+                journal.select_room(room, enabled);
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidParam,
+        }
+    }
+
+    /// Read raw journal records starting at sequence `from_seq`
+    pub fn journal_read(
+        &self,
+        from_seq: u64,
+        max_records: usize,
+    ) -> Result<Vec<u8>, DwebbleWSResult> {
+        let journal = self
+            .shared
+            .journal
+            .as_ref()
+            .ok_or(DwebbleWSResult::InvalidParam)?;
+        journal.read(from_seq, max_records).map_err(|e| {
+            tracing::error!("Journal read failed: {}", e);
+            DwebbleWSResult::RuntimeError
+        })
+    }
+
+    /// Read the archived messages matching `query`, encoded as laid out in the
+    /// `archive` module. Returns `InvalidParam` without the `archive` setting.
+    #[cfg(feature = "archive")]
+    pub fn archive_query(&self, query: &ArchiveQuery) -> Result<Vec<u8>, DwebbleWSResult> {
+        let archive = self
+            .shared
+            .archive
+            .as_ref()
+            .ok_or(DwebbleWSResult::InvalidParam)?;
+        archive.query(query).map_err(|e| {
+            tracing::error!("Archive query failed: {}", e);
+            DwebbleWSResult::RuntimeError
+        })
+    }
+
+    /// Override the simulated network conditions of one connection (`None` to follow
+    /// the server-wide setting again). Applies to the current socket only.
+    pub fn set_network_sim(
+        &self,
+        connection_id: u64,
+        sim: Option<NetworkSimSettings>,
+    ) -> DwebbleWSResult {
+        match self.shared.connections.get(connection_id) {
+            Some(conn) => {
+                *conn.network_sim.lock() = sim;
+                DwebbleWSResult::Ok
+            }
+            None => DwebbleWSResult::InvalidHandle,
+        }
+    }
+
+    /// Run a chaos scenario, its step offsets counting from now
+    pub fn schedule_chaos(&self, scenario: Scenario) -> DwebbleWSResult {
+        let runtime = self.runtime();
+        let Some(runtime) = runtime.as_ref() else {
+            return DwebbleWSResult::NotRunning;
+        };
+
+        let mut tasks = self.chaos_tasks.lock();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(runtime.spawn(chaos::run(Arc::clone(&self.shared), scenario)));
+        DwebbleWSResult::Ok
+    }
+
+    /// Schedule a maintenance shutdown, replacing any scheduled before. Warnings are
+    /// broadcast on the way; at the time, connections are closed and `ShutdownDue`
+    /// tells the host to stop the server.
+    pub fn schedule_shutdown(&self, schedule: ShutdownSchedule) -> DwebbleWSResult {
+        let runtime = self.runtime();
+        let Some(runtime) = runtime.as_ref() else {
+            return DwebbleWSResult::NotRunning;
+        };
+
+        self.cancel_shutdown();
+        let task = runtime.spawn(maintenance::run(Arc::clone(&self.shared), schedule));
+        *self.shutdown_task.lock() = Some(task);
+        DwebbleWSResult::Ok
+    }
+
+    /// Cancel a scheduled shutdown, taking new handshakes again if it was already due
+    pub fn cancel_shutdown(&self) {
+        if let Some(task) = self.shutdown_task.lock().take() {
+            task.abort();
+        }
+        self.shared.shutting_down.store(false, Ordering::Relaxed);
+    }
+
+    /// Ask a STUN server for the server's public address, reported as a
+    /// `PublicEndpoint` event (or `Error` if the server cannot be reached)
+    pub fn discover_public_endpoint(&self, stun_server: &str) -> DwebbleWSResult {
+        // A bind address given as a host name probes from every interface
+        let ip = match self.lifecycle() {
+            Ok(lifecycle) => lifecycle.config.bind_address.parse(),
+            Err(result) => return result,
+        };
+        let ip = ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let runtime = self.runtime();
+        let Some(runtime) = runtime.as_ref() else {
+            return DwebbleWSResult::NotRunning;
+        };
+
+        let shared = Arc::clone(&self.shared);
+        let stun_server = stun_server.to_string();
+        let local = SocketAddr::new(ip, self.get_actual_port());
+        runtime.spawn(async move {
+            let event = match stun::discover(&stun_server, local).await {
+                Ok(addr) => {
+                    tracing::info!("Public endpoint is {} (via {})", addr, stun_server);
+                    *shared.public_addr.lock() = Some(addr);
+                    ServerEvent {
+                        event_type: DwebbleWSEventType::PublicEndpoint,
+                        connection_id: 0,
+                        data: Some(addr.to_string().into_bytes()),
+                        error: None,
+                        request_id: 0,
+                    }
+                }
+                Err(e) => {
+                    let message = format!("Public endpoint discovery failed: {}", e);
+                    tracing::warn!("{}", message);
+                    ServerEvent {
+                        event_type: DwebbleWSEventType::Error,
+                        connection_id: 0,
+                        data: None,
+                        error: Some(message),
+                        request_id: 0,
+                    }
+                }
+            };
+            shared.push_event(event);
+        });
+        DwebbleWSResult::Ok
+    }
+
+    /// Abort scheduled chaos scenarios and lift any stall or handshake refusal they caused
+    pub fn cancel_chaos(&self) {
+        for task in self.chaos_tasks.lock().drain(..) {
+            task.abort();
+        }
+        *self.shared.refuse_handshakes_until.lock() = None;
+        for conn in self.shared.connections.values() {
+            conn.set_stall(None);
+        }
+    }
+
+    /// Set the dictionary of zstd compression negotiated from now on (`None` for none);
+    /// connections keep the dictionary they negotiated. Returns `InvalidParam` without
+    /// the `zstd` setting.
+    pub fn set_zstd_dictionary(&self, dictionary: Option<Vec<u8>>) -> DwebbleWSResult {
+        if self.shared.settings.read().zstd.is_none() {
+            return DwebbleWSResult::InvalidParam;
+        }
+        #[cfg(feature = "zstd")]
+        {
+            *self.shared.zstd_dictionary.write() =
+                dictionary.map(|data| Arc::new(Dictionary::new(data)));
+        }
+        #[cfg(not(feature = "zstd"))]
+        let _ = dictionary;
+        DwebbleWSResult::Ok
+    }
+
+    /// The dictionary of zstd compression negotiated from now on, empty for none.
+    /// Returns `InvalidParam` without the `zstd` setting.
+    pub fn zstd_dictionary(&self) -> Result<Vec<u8>, DwebbleWSResult> {
+        if self.shared.settings.read().zstd.is_none() {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+        #[cfg(feature = "zstd")]
+        if let Some(dictionary) = self.shared.zstd_dictionary.read().as_ref() {
+            return Ok(dictionary.data.clone());
+        }
+        Ok(Vec::new())
+    }
+
+    /// Apply hot-changeable settings. Existing connections are kept.
+    pub fn update_settings(&self, update: SettingsUpdate) -> DwebbleWSResult {
+        if let Some(level) = &update.log_level {
+            if let Err(e) = logging::set_level(level) {
+                tracing::error!("Invalid log level '{}': {}", level, e);
+                return DwebbleWSResult::InvalidParam;
+            }
+        }
+
+        // Compiled before anything is replaced, so an invalid update changes nothing
+        #[cfg(feature = "schema")]
+        let schemas = match update.schemas.as_deref().map(schema::compile_all) {
+            Some(Ok(schemas)) => Some(schemas),
+            Some(Err(e)) => {
+                tracing::error!("{}", e);
+                return DwebbleWSResult::InvalidParam;
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "schema"))]
+        if update.schemas.as_ref().is_some_and(|schemas| !schemas.is_empty()) {
+            tracing::error!("Schema validation unavailable: built without the `schema` feature");
+            return DwebbleWSResult::InvalidParam;
+        }
+
+        if let Some(filters) = &update.wasm_filters {
+            #[cfg(feature = "wasm")]
+            match wasm::load_all(filters) {
+                Ok(filters) => *self.shared.wasm_filters.write() = filters,
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    return DwebbleWSResult::InvalidParam;
+                }
+            }
+            #[cfg(not(feature = "wasm"))]
+            if !filters.is_empty() {
+                tracing::error!("WASM filters unavailable: built without the `wasm` feature");
+                return DwebbleWSResult::InvalidParam;
+            }
+        }
+
+        #[cfg(feature = "schema")]
+        if let Some(schemas) = schemas {
+            *self.shared.schemas.write() = schemas;
+        }
+
+        update.apply_to(&mut self.shared.settings.write());
+        DwebbleWSResult::Ok
+    }
+
+    /// Connect an in-memory client that goes through the full handshake and event
+    /// path without TCP or TLS. Not available until started or in replay mode.
+    pub fn connect_loopback(&self) -> Result<Client, DwebbleWSResult> {
+        let runtime = self.runtime();
+        let runtime = runtime.as_ref().ok_or(DwebbleWSResult::NotRunning)?;
+        if self.replay.is_some() {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+
+        let (server_io, client_io) = tokio::io::duplex(LOOPBACK_BUFFER_SIZE);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let shared = Arc::clone(&self.shared);
+
+        runtime.spawn(async move {
+            if let Err(e) = handle_websocket(server_io, addr, shared, None, [0; 16]).await {
+                tracing::error!("Loopback connection error: {}", e);
+            }
+        });
+
+        let settings = self.shared.settings.read();
+        Ok(Client::spawn(runtime.handle(), &settings, async move {
+            tokio_tungstenite::client_async(LOOPBACK_URL, client_io)
+                .await
+                .map(|(ws_stream, _)| ws_stream)
+        }))
+    }
+
+    /// Dial the server at `url` (`ws://`, or `wss://` with the `tls` feature), with the
+    /// headers, handshake timeout and certificate verification of `settings`, and serve
+    /// the connection like an accepted one. Returns its connection ID, which the
+    /// `ClientConnected` event carries once the handshake completes, or an `Error` and
+    /// `ClientDisconnected` event if it fails. Returns `InvalidParam` if the URL or
+    /// settings are invalid, and `NotRunning` unless the server is running.
+    pub fn dial(&self, url: &str, settings: &ClientSettings) -> Result<u64, DwebbleWSResult> {
+        if !self.is_running() {
+            return Err(DwebbleWSResult::NotRunning);
+        }
+        let runtime = self.runtime();
+        let runtime = runtime.as_ref().ok_or(DwebbleWSResult::NotRunning)?;
+        if self.replay.is_some() {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+
+        let request = client::upgrade_request(url, settings).map_err(|e| {
+            tracing::error!("Invalid request to {}: {}", url, e);
+            DwebbleWSResult::InvalidParam
+        })?;
+        let secure = request.uri().scheme_str() == Some("wss");
+        let connect = client::dial(request, secure, &settings.tls)?;
+
+        let connection_id = connection::next_connection_id();
+        let shared = Arc::clone(&self.shared);
+        let (timeout_ms, url) = (settings.handshake_timeout_ms, url.to_string());
+        runtime.spawn(async move {
+            let dialed = with_timeout(timeout_ms, connect)
+                .await
+                .and_then(|r| r.map_err(Into::into));
+            let (ws_stream, response) = match dialed {
+                Ok(dialed) => dialed,
+                Err(e) => {
+                    tracing::warn!("Failed to dial {} (id: {}): {}", url, connection_id, e);
+                    for (event_type, error) in [
+                        (DwebbleWSEventType::Error, Some(e.to_string())),
+                        (DwebbleWSEventType::ClientDisconnected, None),
+                    ] {
+                        shared.push_event(ServerEvent {
+                            event_type,
+                            connection_id,
+                            data: None,
+                            error,
+                            request_id: 0,
+                        });
+                    }
+                    return;
+                }
+            };
+
+            let addr = migration::peer_addr(ws_stream.get_ref())
+                .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+            let handshake = Handshake {
+                selected_protocol: response
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|p| p.to_str().ok())
+                    .map(str::to_string),
+                dialed_id: Some(connection_id),
+                ..Default::default()
+            };
+            if let Err(e) = serve_websocket(ws_stream, addr, shared, handshake).await {
+                tracing::error!("Dialed connection error: {}", e);
+            }
+        });
+        Ok(connection_id)
+    }
+
+    /// Bound addresses of the listeners and the external addresses found through the
+    /// gateway and STUN, as JSON
+    pub fn listen_addrs(&self) -> serde_json::Value {
+        let listeners: Vec<_> = self
+            .listen_addrs
+            .lock()
+            .iter()
+            .map(|(kind, addr)| serde_json::json!({ "kind": kind, "addr": addr.to_string() }))
+            .collect();
+        let external = |addr: &Mutex<Option<SocketAddr>>| addr.lock().map(|a| a.to_string());
+        serde_json::json!({
+            "listeners": listeners,
+            "mapped": external(&self.shared.mapped_addr),
+            "public": external(&self.shared.public_addr),
+        })
+    }
+
+    pub fn info(&self) -> String {
+        let bind_address = self.lifecycle.lock().config.bind_address.clone();
+        format!("{}:{}", bind_address, self.get_actual_port())
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match tls_acceptor {
+        #[cfg(feature = "tls")]
+        Some(acceptor) => {
+            let handshake_timeout = shared.settings.read().handshake_timeout_ms;
+            // The ClientHello is read ahead to fingerprint the client, then replayed
+            let accept = async {
+                let mut stream = stream;
+                let record = fingerprint::read_record(&mut stream).await?;
+                let tls_fingerprint = fingerprint::ja3(&record).unwrap_or_default();
+                let tls_stream = acceptor.accept(Rewind::new(record, stream)).await?;
+                Ok::<_, std::io::Error>((tls_stream, tls_fingerprint))
+            };
+            let accepted = with_timeout(handshake_timeout, accept)
+                .await
+                .and_then(|r| r.map_err(Into::into));
+            let (tls_stream, tls_fingerprint) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    shared.push_event(ServerEvent {
+                        event_type: DwebbleWSEventType::TlsHandshakeFailed,
+                        connection_id: 0,
+                        data: Some(addr.to_string().into_bytes()),
+                        error: Some(e.to_string()),
+                        request_id: 0,
+                    });
+                    return Err(e);
+                }
+            };
+            handle_request(tls_stream, addr, shared, tls_fingerprint).await
+        }
+        _ => handle_request(stream, addr, shared, [0; 16]).await,
+    }
+}
+
+/// Serve HTTP/2 or the event stream endpoint if the request asks for it, otherwise the
+/// WebSocket. `tls_fingerprint` is the JA3 hash of a TLS client, zero without TLS.
+async fn handle_request<S>(
+    mut stream: S,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls_fingerprint: [u8; 16],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Rehome + Unpin + Send + 'static,
+{
+    let (sse_settings, handshake_timeout) = {
+        let settings = shared.settings.read();
+        (settings.sse.clone(), settings.handshake_timeout_ms)
+    };
+    // The head also names the path of a failed WebSocket handshake
+    let head = with_timeout(handshake_timeout, rewind::read_head(&mut stream))
+        .await
+        .and_then(|r| r.map_err(Into::into));
+    let head = match head {
+        Ok(head) => head,
+        Err(e) => {
+            shared.handshake_failed(addr, None, None, e.to_string());
+            return Err(e);
+        }
+    };
+    #[cfg(feature = "http2")]
+    if shared.settings.read().http2 && head.starts_with(http2::PREFACE) {
+        return http2::serve(Rewind::new(head, stream), addr, shared, tls_fingerprint).await;
+    }
+    match sse_settings.and_then(|sse_settings| sse::parse_request(&head, &sse_settings)) {
+        Some(request) => sse::serve(stream, addr, shared, request).await,
+        None => {
+            let path = rewind::request_path(&head);
+            let stream = Rewind::new(head, stream);
+            handle_websocket(stream, addr, shared, path, tls_fingerprint).await
+        }
+    }
+}
+
+/// Run a handshake future, failing if it takes longer than `timeout_ms` (0 to disable)
+async fn with_timeout<F: std::future::Future>(
+    timeout_ms: u64,
+    future: F,
+) -> Result<F::Output, Box<dyn std::error::Error + Send + Sync>> {
+    if timeout_ms == 0 {
+        return Ok(future.await);
+    }
+
+    tokio::time::timeout(Duration::from_millis(timeout_ms), future)
+        .await
+        .map_err(|_| "Handshake timed out".into())
+}
+
+fn reject(status: StatusCode, reason: &str) -> HttpResponse<Option<String>> {
+    let mut response = HttpResponse::new(Some(reason.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Outcome of negotiating a WebSocket handshake
+#[derive(Default)]
+pub(crate) struct Handshake {
+    pub selected_protocol: Option<String>,
+    /// Connection ID of the session the client resumed
+    pub resumed_id: Option<u64>,
+    pub issued_token: Option<String>,
+    /// Request headers kept for the bridge upstream
+    pub request_headers: Option<HeaderMap>,
+    /// Request path, once the request got as far as negotiating
+    pub path: Option<String>,
+    /// Why negotiating refused the request, and the header at fault
+    pub rejection: Option<(String, Option<&'static str>)>,
+    /// Whether the client asked for zstd compression with the subprotocol suffix
+    pub zstd: bool,
+    pub fingerprint: Fingerprint,
+    /// Connection ID reserved for a connection the server dialed
+    pub dialed_id: Option<u64>,
+    /// Text frames of an accepted client checked for UTF-8 as they were read
+    pub text_frames: TextFrames,
+}
+
+/// Handle origin checks, connection limits, sessions and subprotocol negotiation for a
+/// handshake request, adding the negotiated headers to `response`
+#[allow(clippy::result_large_err)]
+pub(crate) fn negotiate(
+    shared: &Shared,
+    settings: &Settings,
+    req: &Request,
+    mut response: Response,
+    handshake: &mut Handshake,
+) -> Result<Response, HttpResponse<Option<String>>> {
+    handshake.path = Some(req.uri().path().to_string());
+    handshake.fingerprint.headers = fingerprint::header_order(req.headers());
+    let origin = req.headers().get("Origin").and_then(|o| o.to_str().ok());
+    if let Err((status, reason)) = shared.admit(settings, origin) {
+        let header = (!settings.is_origin_allowed(origin)).then_some("Origin");
+        handshake.rejection = Some((reason.to_string(), header));
+        return Err(reject(status, reason));
+    }
+
+    if settings.sessions.is_some() {
+        let presented = req
+            .headers()
+            .get(session::SESSION_HEADER)
+            .and_then(|t| t.to_str().ok())
+            .or_else(|| session::token_from_query(req.uri().query()));
+
+        let token = match presented {
+            Some(token) => {
+                handshake.resumed_id = shared.sessions.lock().resume(token);
+                if handshake.resumed_id.is_some() {
+                    token.to_string()
+                } else {
+                    session::generate_token()
+                }
+            }
+            None => session::generate_token(),
+        };
+
+        if let Ok(value) = token.parse() {
+            response.headers_mut().insert(session::SESSION_HEADER, value);
+        }
+        handshake.issued_token = Some(token);
+    }
+
+    if settings.bridge.is_some() {
+        handshake.request_headers = Some(req.headers().clone());
+    }
+
+    if !settings.subprotocols.is_empty() || settings.mqtt {
+        if let Some(protocols) = req.headers().get("Sec-WebSocket-Protocol") {
+            if let Ok(protocols_str) = protocols.to_str() {
+                // The server's order of preference decides, MQTT after the listed ones
+                let rank = |protocol: &str| {
+                    settings.subprotocols.iter().position(|s| s == protocol).or(
+                        (settings.mqtt && protocol == mqtt::SUBPROTOCOL)
+                            .then_some(settings.subprotocols.len()),
+                    )
+                };
+                // The host sees the subprotocol without the compression suffix
+                let suffix = settings.zstd.as_ref().map(|z| z.subprotocol_suffix.as_str());
+                let selected = protocols_str
+                    .split(',')
+                    .map(|s| s.trim())
+                    .filter_map(|requested| {
+                        let stripped = suffix.and_then(|s| requested.strip_suffix(s));
+                        let (protocol, zstd) = match stripped {
+                            Some(protocol) => (protocol, true),
+                            None => (requested, false),
+                        };
+                        Some((rank(protocol)?, requested, protocol, zstd))
+                    })
+                    .min_by_key(|(rank, ..)| *rank);
+                if let Some((_, requested, protocol, zstd)) = selected {
+                    handshake.selected_protocol = Some(protocol.to_string());
+                    handshake.zstd = zstd;
+                    response
+                        .headers_mut()
+                        .insert("Sec-WebSocket-Protocol", requested.parse().unwrap());
+                }
+            }
+        }
+    }
+    Ok(response)
+}
+
+/// WebSocket protocol limits from the settings
+pub(crate) fn websocket_config(settings: &Settings) -> WebSocketConfig {
+    let mut ws_config = WebSocketConfig::default();
+    if settings.max_message_size > 0 {
+        ws_config = ws_config
+            .max_message_size(Some(settings.max_message_size))
+            .max_frame_size(Some(settings.max_message_size));
+    }
+    ws_config
+}
+
+/// Serve a WebSocket handshake and then the connection. `path` is the request path, if
+/// it was read off the stream already.
+async fn handle_websocket<S>(
+    stream: S,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    path: Option<String>,
+    tls_fingerprint: [u8; 16],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Rehome + Unpin + Send + 'static,
+{
+    let settings = shared.settings.read().clone();
+    let mut negotiated = Handshake::default();
+    negotiated.fingerprint.tls = tls_fingerprint;
+    #[allow(clippy::result_large_err)]
+    let callback = |req: &Request, response: Response| {
+        negotiate(&shared, &settings, req, response, &mut negotiated)
+    };
+
+    // Frames are unmasked and text checked ahead of tungstenite
+    let ws_config = websocket_config(&settings).accept_unmasked_frames(true);
+    let stream = ClientFrames::new(stream, ws_config.max_frame_size.unwrap_or(usize::MAX));
+    let text_frames = stream.text_frames();
+    let handshake = with_timeout(
+        settings.handshake_timeout_ms,
+        tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(ws_config)),
+    )
+    .await
+    .and_then(|r| r.map_err(Into::into));
+
+    match handshake {
+        Ok(ws_stream) => {
+            negotiated.text_frames = text_frames;
+            serve_websocket(ws_stream, addr, shared, negotiated).await
+        }
+        Err(e) => {
+            // Hand a claimed session back so the client can retry
+            if let Some(id) = negotiated.resumed_id {
+                shared.sessions.lock().suspend(id);
+            }
+            let (reason, header) = match negotiated.rejection {
+                Some((reason, header)) => (reason, header.map(str::to_string)),
+                None => (e.to_string(), offending_header(e.as_ref())),
+            };
+            let path = negotiated.path.or(path);
+            shared.handshake_failed(addr, path.as_deref(), header.as_deref(), reason);
+            Err(e)
+        }
+    }
+}
+
+/// Request header at fault for a handshake error, if the error names one
+fn offending_header(error: &(dyn std::error::Error + 'static)) -> Option<String> {
+    let Some(WsError::Protocol(error)) = error.downcast_ref::<WsError>() else {
+        return None;
+    };
+    let header = match error {
+        ProtocolError::MissingConnectionUpgradeHeader => "Connection",
+        ProtocolError::MissingUpgradeWebSocketHeader => "Upgrade",
+        ProtocolError::MissingSecWebSocketVersionHeader => "Sec-WebSocket-Version",
+        ProtocolError::MissingSecWebSocketKey => "Sec-WebSocket-Key",
+        ProtocolError::InvalidHeader(name) => return Some(name.to_string()),
+        _ => return None,
+    };
+    Some(header.to_string())
+}
+
+/// Run an accepted WebSocket connection until it closes
+pub(crate) async fn serve_websocket<S>(
+    mut ws_stream: WebSocketStream<S>,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    handshake: Handshake,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Rehome + Unpin + Send + 'static,
+{
+    let settings = shared.settings.read().clone();
+    let Handshake {
+        selected_protocol,
+        resumed_id,
+        issued_token,
+        request_headers,
+        #[cfg(feature = "zstd")]
+        zstd,
+        fingerprint,
+        dialed_id,
+        text_frames,
+        ..
+    } = handshake;
+    // The server plays the client in a connection it dialed, so the features a client
+    // opts into with the server are left out
+    let accepted = dialed_id.is_none();
+    let (tx, rx) = mpsc::unbounded_channel::<Queued>();
+
+    // With encryption, the server's key goes out first and the writer holds everything
+    // else back until the client's key arrived
+    let psk = settings
+        .encryption
+        .as_ref()
+        .filter(|_| accepted)
+        .map(|e| e.psk.clone().into_bytes());
+    let mut exchange = None;
+    let mut sealer_rx = None;
+    if psk.is_some() {
+        let (server_exchange, hello) = Exchange::start(Role::Server)?;
+        ws_stream.send(Message::Binary(hello.into())).await?;
+        let (sealer_tx, keys_rx) = oneshot::channel();
+        exchange = Some((server_exchange, sealer_tx));
+        sealer_rx = Some(keys_rx);
+    }
+
+    let mut conn = match resumed_id.or(dialed_id) {
+        Some(id) => Connection::with_id(id, addr.to_string(), selected_protocol, tx),
+        None => Connection::new(addr.to_string(), selected_protocol, tx),
+    };
+    conn.fingerprint = fingerprint;
+    let conn = Arc::new(conn);
+    let connection_id = conn.id;
+    #[cfg(feature = "zstd")]
+    if let (Some(zstd_settings), true) = (&settings.zstd, zstd) {
+        conn.compression.set_zstd(Zstd {
+            level: zstd_settings.level,
+            min_size: zstd_settings.min_size,
+            max_size: settings.max_message_size,
+            dictionary: shared.zstd_dictionary.read().clone(),
+        });
+    }
+
+    if let (Some(token), None) = (&issued_token, resumed_id) {
+        shared.sessions.lock().insert(token.clone(), connection_id);
+    }
+
+    // Add to the connections map, replaying messages buffered while the session was suspended.
+    // Unacknowledged reliable channel messages go first, as the socket may have lost them.
+    {
+        let retransmit = match resumed_id {
+            Some(_) => shared.channels.lock().retransmit(connection_id),
+            None => Vec::new(),
+        };
+        let retransmit: Vec<_> = retransmit
+            .into_iter()
+            .filter_map(|frame| {
+                shared.run_middleware(false, connection_id, Message::Binary(frame.into()))
+            })
+            .map(|msg| shared.stamp(msg))
+            .collect();
+
+        let mut conns = shared.connections.shard(connection_id);
+        if resumed_id.is_some() {
+            for msg in retransmit {
+                conn.queue(shared.signers.lock().sign(connection_id, msg));
+            }
+            for msg in shared.sessions.lock().take_buffer(connection_id) {
+                conn.queue(msg);
+            }
+        }
+        conns.insert(Arc::clone(&conn));
+    }
+
+    // Socket.IO clients expect the Engine.IO handshake before anything else
+    let opened = accepted && resumed_id.is_none();
+    if let (Some(socket_io), true) = (&settings.socket_io, opened) {
+        socketio::on_open(&shared, socket_io, connection_id);
+    }
+
+    let is_mqtt = conn.subprotocol.as_deref() == Some(mqtt::SUBPROTOCOL);
+    if settings.mqtt && is_mqtt && opened {
+        mqtt::on_open(&shared, connection_id);
+    }
+
+    if settings.hold_messages > 0 && opened {
+        conn.start_holding(settings.hold_messages);
+    }
+    if let (Some(authentication), true) = (&settings.authentication, opened) {
+        conn.require_authentication(authentication.login_messages);
+    }
+    if settings.protocol_version.is_some() && opened {
+        conn.expect_version();
+    }
+
+    // Notify connected, carrying the negotiated subprotocol as payload
+    shared.push_event(ServerEvent {
+        event_type: if resumed_id.is_some() {
+            DwebbleWSEventType::SessionResumed
+        } else {
+            DwebbleWSEventType::ClientConnected
+        },
+        connection_id,
+        data: conn.subprotocol.as_ref().map(|p| p.as_bytes().to_vec()),
+        error: None,
+        request_id: u64::from(!accepted),
+    });
+
+    if resumed_id.is_some() {
+        tracing::info!("Session resumed: {} (id: {})", addr, connection_id);
+    } else if !accepted {
+        tracing::info!("Dialed server connected: {} (id: {})", addr, connection_id);
+    } else {
+        tracing::info!("Client connected: {} (id: {})", addr, connection_id);
+    }
+
+    // In bridge mode, relay client messages to an upstream server instead of raising events
+    let upstream = settings.bridge.as_ref().filter(|_| accepted).map(|bridge| {
+        Upstream::spawn(
+            Arc::clone(&shared),
+            Arc::clone(&conn),
+            bridge,
+            request_headers,
+            issued_token.as_deref(),
+            addr,
+        )
+    });
+
+    let link = Link {
+        conn,
+        addr,
+        rx,
+        outbound: DelayQueue::new(),
+        inbound: DelayQueue::new(),
+        sealer: None,
+        opener: None,
+        exchange,
+        sealer_rx,
+        psk,
+        upstream,
+        text_frames,
+    };
+    run_websocket(ws_stream, shared, link).await
+}
+
+/// Take over a connection migrated from another server, raising `ClientConnected`.
+/// Boxed, as serving a connection may migrate it again.
+fn serve_migrated<S>(
+    ws_stream: WebSocketStream<S>,
+    shared: Arc<Shared>,
+    link: Link,
+) -> BoxFuture<'static, Result<(), Box<dyn std::error::Error + Send + Sync>>>
+where
+    S: AsyncRead + AsyncWrite + Rehome + Unpin + Send + 'static,
+{
+    Box::pin(async move {
+        let conn = Arc::clone(&link.conn);
+        shared.connections.insert(Arc::clone(&conn));
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::ClientConnected,
+            connection_id: conn.id,
+            data: conn.subprotocol.as_ref().map(|p| p.as_bytes().to_vec()),
+            error: None,
+            request_id: 0,
+        });
+
+        tracing::info!("Client migrated in: {} (id: {})", link.addr, conn.id);
+        run_websocket(ws_stream, shared, link).await
+    })
+}
+
+/// Socket-side state of a connection. Once the key exchange is done and the connection
+/// isn't bridged, all of it can move to another server.
+struct Link {
+    conn: Arc<Connection>,
+    addr: SocketAddr,
+    rx: mpsc::UnboundedReceiver<Queued>,
+    /// Messages held back by the network simulation in either direction
+    outbound: DelayQueue<Message>,
+    inbound: DelayQueue<Message>,
+    sealer: Option<Sealer>,
+    opener: Option<Opener>,
+    /// Key exchange still waiting for the client's key
+    exchange: Option<(Exchange, oneshot::Sender<Sealer>)>,
+    sealer_rx: Option<oneshot::Receiver<Sealer>>,
+    psk: Option<Vec<u8>>,
+    upstream: Option<Upstream>,
+    text_frames: TextFrames,
+}
+
+/// Step of a key rotation for the writer to seal and send
+enum RekeyStep {
+    /// Send the server's announcement
+    Announce(Vec<u8>),
+    /// Send the rekeyed message, then seal with the new key
+    Switch(Box<Sealer>),
+}
+
+/// Serve a registered connection's socket until it closes or migrates
+async fn run_websocket<S>(
+    ws_stream: WebSocketStream<S>,
+    shared: Arc<Shared>,
+    link: Link,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Rehome + Unpin + Send + 'static,
+{
+    let Link {
+        conn,
+        addr,
+        mut rx,
+        mut outbound,
+        mut inbound,
+        mut sealer,
+        mut opener,
+        mut exchange,
+        mut sealer_rx,
+        psk,
+        upstream,
+        text_frames,
+    } = link;
+    let connection_id = conn.id;
+    let (handshake_timeout_ms, rekey_interval_ms, authentication_timeout_ms) = {
+        let settings = shared.settings.read();
+        let rekey_interval_ms = settings.encryption.as_ref().map(|e| e.rekey_interval_ms);
+        let authentication_timeout_ms = settings.authentication.as_ref().map(|a| a.timeout_ms);
+        (
+            settings.handshake_timeout_ms,
+            rekey_interval_ms.unwrap_or(0),
+            authentication_timeout_ms.unwrap_or(0),
+        )
+    };
+    let exchange_deadline =
+        tokio::time::Instant::now() + Duration::from_millis(handshake_timeout_ms);
+    let mut authentication_deadline = (authentication_timeout_ms > 0 && !conn.authenticated())
+        .then(|| tokio::time::Instant::now() + Duration::from_millis(authentication_timeout_ms));
+    let (mut write, mut read) = ws_stream.split();
+    let (rekey_tx, mut rekey_rx) = mpsc::unbounded_channel::<RekeyStep>();
+
+    // Spawn writer task, the only owner of the sink; tungstenite answers pings as the
+    // reader reads. Stopping it hands back the sink and its queues, for a migration or
+    // a last Close frame.
+    let (stop_writer, mut writer_stopped) = oneshot::channel::<()>();
+    let mut write_handle = {
+        let conn = Arc::clone(&conn);
+        let shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            let mut encoder = conn.compression.encoder();
+            loop {
+                // Write what is due before taking more off the send queue, where
+                // messages can still be replaced or expire
+                tokio::select! {
+                    biased;
+                    _ = &mut writer_stopped => break,
+                    Some(step) = rekey_rx.recv() => {
+                        if let Some(keys) = sealer_rx.take() {
+                            let Ok(keys) = keys.await else { break };
+                            sealer = Some(keys);
+                        }
+                        let Some(current) = sealer.as_mut() else { continue };
+                        let (msg, next) = match step {
+                            RekeyStep::Announce(hello) => (current.seal_rekey(&hello), None),
+                            RekeyStep::Switch(next) => (current.seal_rekeyed(), Some(*next)),
+                        };
+                        let sent = write.send(msg).await.is_ok();
+                        if let Some(next) = next {
+                            sealer = Some(next);
+                            let rotations = conn.rekeyed();
+                            tracing::debug!("Rotated keys of connection {}", conn.id);
+                            shared.push_event(ServerEvent {
+                                event_type: DwebbleWSEventType::KeyRotated,
+                                connection_id: conn.id,
+                                data: None,
+                                error: None,
+                                request_id: rotations,
+                            });
+                        }
+                        if !sent {
+                            break;
+                        }
+                    }
+                    msg = outbound.ready() => {
+                        // Re-check periodically so a cancelled stall resumes promptly
+                        while let Some(until) = conn.stalled_until() {
+                            let recheck = tokio::time::Instant::now() + STALL_RECHECK;
+                            tokio::time::sleep_until(until.min(recheck)).await;
+                        }
+                        if let Some(keys) = sealer_rx.take() {
+                            let Ok(keys) = keys.await else { break };
+                            sealer = Some(keys);
+                        }
+                        let len = msg.len();
+                        let data = msg.is_binary() || msg.is_text();
+                        let msg = encoder.encode(msg);
+                        if data {
+                            conn.compression.sent(len, msg.len());
+                            shared.histograms.sent_size.record(msg.len() as u64);
+                            if let Some(metrics) = &shared.metrics {
+                                metrics.sent(msg.len());
+                            }
+                        }
+                        let msg = encryption::seal(&mut sealer, msg);
+                        let sent = write.send(msg).await.is_ok();
+                        conn.mark_written_len(len);
+                        if !sent {
+                            break;
+                        }
+                    }
+                    next = rx.recv() => {
+                        let Some(queued) = next else { break };
+                        let Some(msg) = conn.dequeue(queued) else { continue };
+                        let len = msg.len();
+                        let droppable = msg.is_binary() || msg.is_text();
+                        if !outbound.push(shared.network_sim(&conn), msg, len, droppable) {
+                            conn.mark_written_len(len);
+                        }
+                    }
+                }
+            }
+            // Keys sent before anything needed sealing
+            if let Some(mut keys) = sealer_rx {
+                sealer = sealer.or(keys.try_recv().ok());
+            }
+            (rx, outbound, sealer, write)
+        })
+    };
+
+    // Whether the client ended the connection deliberately with a Close frame
+    let mut client_closed = false;
+    // Whether the connection was closed for being idle
+    let mut idled = false;
+    let mut migration = None;
+    let mut limiter = RateLimiter::new();
+    let mut decoder = conn.compression.decoder();
+    // Key rotation awaiting the client's answer and its deadline, and when the keys
+    // were last set
+    let mut rekey: Option<(Exchange, tokio::time::Instant)> = None;
+    let mut keyed_at = tokio::time::Instant::now();
+
+    // Read messages
+    loop {
+        // Messages the host acknowledged go before anything read after them
+        for msg in conn.take_held(false) {
+            deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+        }
+
+        let idle_timeout = shared.settings.read().idle_timeout_ms;
+        let paused = conn.read_paused() || conn.hold_full() || conn.awaiting_authentication();
+        let idle = async {
+            // A paused connection isn't idle, the host just isn't listening
+            if idle_timeout == 0 || paused {
+                std::future::pending::<()>().await
+            } else {
+                tokio::time::sleep(Duration::from_millis(idle_timeout)).await
+            }
+        };
+        let scheduled_rekey = (rekey_interval_ms > 0)
+            .then(|| keyed_at + Duration::from_millis(rekey_interval_ms));
+        let rekey_due = async {
+            match scheduled_rekey {
+                Some(at) => tokio::select! {
+                    _ = conn.rekey_requested() => {}
+                    _ = tokio::time::sleep_until(at) => {}
+                },
+                None => conn.rekey_requested().await,
+            }
+        };
+        let rekey_deadline = rekey.as_ref().map(|(_, deadline)| *deadline);
+
+        let result = tokio::select! {
+            next = read.next(), if !paused => match next {
+                Some(result) => result,
+                None => {
+                    for msg in conn.take_held(true) {
+                        deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+                    }
+                    // Messages still in simulated flight arrive before the stream ends
+                    for msg in inbound.drain() {
+                        if let Message::Close(_) = msg {
+                            client_closed = true;
+                        }
+                        deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+                    }
+                    break;
+                }
+            },
+            msg = inbound.ready() => {
+                let close = msg.is_close();
+                // A client leaving before the acknowledgement has its messages delivered
+                // ahead of the close
+                let held = if close { conn.take_held(true) } else { Vec::new() };
+                for msg in held {
+                    deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+                }
+                if let Some(msg) = conn.hold(msg) {
+                    deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+                }
+                if close {
+                    client_closed = true;
+                    break;
+                }
+                continue;
+            }
+            _ = idle => {
+                tracing::info!("Idle timeout for {} (id: {})", addr, connection_id);
+                idled = true;
+                break;
+            }
+            _ = conn.read_toggled() => continue,
+            _ = conn.terminated() => {
+                break;
+            }
+            request = conn.migrating() => {
+                // Bridged connections and pending key exchanges are tied to this server
+                if !S::MIGRATABLE || exchange.is_some() || conn.rekeying() || upstream.is_some()
+                {
+                    let _ = request.done.send(DwebbleWSResult::InvalidParam);
+                    continue;
+                }
+                migration = Some(request);
+                break;
+            }
+            _ = tokio::time::sleep_until(exchange_deadline),
+                if exchange.is_some() && handshake_timeout_ms > 0 =>
+            {
+                tracing::warn!("Key exchange timed out for {} (id: {})", addr, connection_id);
+                exchange = None;
+                conn.terminate(encryption::PROTOCOL_ERROR_CLOSE_CODE, "Key exchange timed out");
+                continue;
+            }
+            _ = rekey_due, if opener.is_some() && rekey.is_none() => {
+                match Exchange::start(Role::Server) {
+                    Ok((server_exchange, hello)) => {
+                        conn.set_rekeying();
+                        let _ = rekey_tx.send(RekeyStep::Announce(hello));
+                        let deadline = tokio::time::Instant::now()
+                            + Duration::from_millis(handshake_timeout_ms);
+                        rekey = Some((server_exchange, deadline));
+                    }
+                    Err(e) => tracing::error!("Failed to start key rotation: {}", e),
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(authentication_deadline.unwrap_or(exchange_deadline)),
+                if authentication_deadline.is_some() && !conn.authenticated() =>
+            {
+                tracing::info!("Authentication timed out for {} (id: {})", addr, connection_id);
+                authentication_deadline = None;
+                conn.terminate(connection::AUTHENTICATION_CLOSE_CODE, "Authentication timed out");
+                continue;
+            }
+            _ = tokio::time::sleep_until(rekey_deadline.unwrap_or(exchange_deadline)),
+                if rekey_deadline.is_some() && handshake_timeout_ms > 0 =>
+            {
+                tracing::warn!("Key rotation timed out for {} (id: {})", addr, connection_id);
+                rekey = None;
+                conn.terminate(encryption::PROTOCOL_ERROR_CLOSE_CODE, "Key rotation timed out");
+                continue;
+            }
+        };
+
+        match result.map(|msg| text_frames.restore(msg)) {
+            Ok(msg) => match msg {
+                // The client's first message completes the key exchange
+                Message::Binary(_) | Message::Text(_) if exchange.is_some() => {
+                    let (server_exchange, sealer_tx) = exchange.take().unwrap();
+                    match server_exchange.finish(&msg, psk.as_deref().unwrap_or_default()) {
+                        Ok((sealer, keys)) => {
+                            opener = Some(keys);
+                            conn.set_encrypted();
+                            let _ = sealer_tx.send(sealer);
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "Key exchange with {} (id: {}) failed: {}",
+                                addr,
+                                connection_id,
+                                e
+                            );
+                            conn.terminate(
+                                encryption::PROTOCOL_ERROR_CLOSE_CODE,
+                                "Key exchange failed",
+                            );
+                        }
+                    }
+                }
+                Message::Binary(_) | Message::Text(_) | Message::Close(_) => {
+                    // The key exchange failed and the connection is closing
+                    if opener.is_none() && psk.is_some() && !msg.is_close() {
+                        continue;
+                    }
+                    let msg = match encryption::open(&mut opener, msg) {
+                        Ok(Opened::Message(msg)) => msg,
+                        // The client's answer to a key rotation, its last message under
+                        // the old key
+                        Ok(Opened::Rekey(answer)) if rekey.is_some() => {
+                            let (server_exchange, _) = rekey.take().unwrap();
+                            let psk = psk.as_deref().unwrap_or_default();
+                            match server_exchange.finish(&answer, psk) {
+                                Ok((sealer, keys)) => {
+                                    opener = Some(keys);
+                                    keyed_at = tokio::time::Instant::now();
+                                    let _ = rekey_tx.send(RekeyStep::Switch(Box::new(sealer)));
+                                }
+                                Err(e) => {
+                                    tracing::warn!(
+                                        "Key rotation with {} (id: {}) failed: {}",
+                                        addr,
+                                        connection_id,
+                                        e
+                                    );
+                                    conn.terminate(
+                                        encryption::PROTOCOL_ERROR_CLOSE_CODE,
+                                        "Key rotation failed",
+                                    );
+                                }
+                            }
+                            continue;
+                        }
+                        Ok(Opened::Rekey(_) | Opened::Rekeyed) => {
+                            tracing::warn!(
+                                "Unexpected key rotation message from {} (id: {})",
+                                addr,
+                                connection_id
+                            );
+                            conn.terminate(
+                                encryption::PROTOCOL_ERROR_CLOSE_CODE,
+                                "Unexpected key rotation",
+                            );
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "Undecryptable message from {} (id: {}): {}",
+                                addr,
+                                connection_id,
+                                e
+                            );
+                            conn.terminate(
+                                encryption::PROTOCOL_ERROR_CLOSE_CODE,
+                                "Decryption failed",
+                            );
+                            continue;
+                        }
+                    };
+                    let len = msg.len();
+                    let msg = match decoder.decode(msg) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Undecompressable message from {} (id: {}): {}",
+                                addr,
+                                connection_id,
+                                e
+                            );
+                            conn.terminate(
+                                compression::INVALID_PAYLOAD_CLOSE_CODE,
+                                "Decompression failed",
+                            );
+                            continue;
+                        }
+                    };
+                    if !msg.is_close() {
+                        conn.compression.received(len, msg.len());
+                        shared.histograms.received_size.record(len as u64);
+                        if let Some(metrics) = &shared.metrics {
+                            metrics.received(len);
+                        }
+                    }
+                    let admitted = msg.is_close()
+                        || ratelimit::check(&shared, &conn, &mut limiter, &msg).await;
+                    if !admitted {
+                        continue;
+                    }
+                    let len = msg.len();
+                    let droppable = !msg.is_close();
+                    if droppable {
+                        conn.read_unauthenticated();
+                    }
+                    inbound.push(shared.network_sim(&conn), msg, len, droppable);
+                }
+                Message::Pong(data) => {
+                    if let Some(metrics) = &shared.metrics {
+                        metrics.pong(&data);
+                    }
+                }
+                _ => {}
+            },
+            Err(e) => {
+                tracing::error!("Read error from {}: {}", addr, e);
+                shared.push_event(ServerEvent {
+                    event_type: DwebbleWSEventType::Error,
+                    connection_id,
+                    data: None,
+                    error: Some(e.to_string()),
+                    request_id: 0,
+                });
+                break;
+            }
+        }
+    }
+
+    // The host has had `ClientConnected`, so it gets what the client sent before leaving
+    for msg in conn.take_held(true) {
+        deliver_inbound(&shared, connection_id, msg, upstream.as_ref());
+    }
+
+    if let Some(migration) = migration {
+        let _ = stop_writer.send(());
+        let (rx, outbound, sealer, write) = write_handle.await?;
+        let Ok(mut ws_stream) = read.reunite(write) else {
+            unreachable!("both halves come from the same stream");
+        };
+
+        shared.connections.remove(connection_id);
+        shared.sessions.lock().end(connection_id);
+        shared.forget_connection(connection_id);
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::ClientDisconnected,
+            connection_id,
+            data: None,
+            error: Some(MIGRATED_REASON.to_string()),
+            request_id: 0,
+        });
+
+        let rehomed = {
+            let _context = migration.runtime.enter();
+            ws_stream.get_mut().rehome()
+        };
+        if let Err(e) = rehomed {
+            // The socket keeps working for as long as this server runs
+            tracing::warn!("Socket of {} (id: {}) stays behind: {}", addr, connection_id, e);
+        }
+
+        let link = Link {
+            conn,
+            addr,
+            rx,
+            outbound,
+            inbound,
+            sealer,
+            opener,
+            exchange: None,
+            sealer_rx: None,
+            psk,
+            upstream: None,
+            text_frames,
+        };
+        migration.runtime.spawn(async move {
+            if let Err(e) = serve_migrated(ws_stream, migration.shared, link).await {
+                tracing::error!("Migrated connection error: {}", e);
+            }
+        });
+        let _ = migration.done.send(DwebbleWSResult::Ok);
+
+        tracing::info!("Client migrated out: {} (id: {})", addr, connection_id);
+        return Ok(());
+    }
+
+    // Cleanup
+    shared.connections.remove(connection_id);
+
+    // Best-effort Close frame for idle connections and those terminated by the server
+    // (unless severed), once the writer is done with the message in hand
+    let severed = conn.severed();
+    let termination = conn.termination();
+    let close = match (&termination, &severed) {
+        (Some((frame, _)), None) => Some(Some(frame.clone())),
+        _ if idled => Some(None),
+        _ => None,
+    };
+    match close {
+        Some(frame) => {
+            let _ = stop_writer.send(());
+            let deadline = tokio::time::Instant::now() + CLOSE_FRAME_TIMEOUT;
+            match tokio::time::timeout_at(deadline, &mut write_handle).await {
+                Ok(Ok((_, _, _, mut write))) => {
+                    let close = write.send(Message::Close(frame));
+                    let _ = tokio::time::timeout_at(deadline, close).await;
+                }
+                _ => write_handle.abort(),
+            }
+        }
+        None => write_handle.abort(),
+    }
+
+    // Abnormal drops keep the session alive for a reconnect
+    let suspended = !client_closed
+        && !conn.closed_by_server()
+        && shared.settings.read().sessions.is_some()
+        && shared.sessions.lock().suspend(connection_id);
+
+    if suspended {
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::SessionSuspended,
+            connection_id,
+            data: None,
+            error: None,
+            request_id: 0,
+        });
+
+        tracing::info!("Session suspended: {} (id: {})", addr, connection_id);
+        return Ok(());
+    }
+
+    shared.sessions.lock().end(connection_id);
+    shared.forget_connection(connection_id);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientDisconnected,
+        connection_id,
+        data: None,
+        error: termination.map(|(_, reason)| reason).or(severed),
+        request_id: 0,
+    });
+
+    tracing::info!("Client disconnected: {} (id: {})", addr, connection_id);
+
+    Ok(())
+}
+
+/// Journal an inbound message and raise its `MessageReceived` event, or relay it upstream
+pub(crate) fn deliver_inbound(
+    shared: &Arc<Shared>,
+    connection_id: u64,
+    msg: Message,
+    upstream: Option<&Upstream>,
+) {
+    let trailer = shared.settings.read().checksum.filter(|c| c.verify_trailer);
+    let msg = match (trailer, msg) {
+        (Some(checksum), Message::Binary(data)) => match checksum::strip(checksum.algorithm, &data) {
+            Ok(payload) => Message::Binary(data.slice_ref(payload)),
+            Err(e) => {
+                shared.push_event(ServerEvent {
+                    event_type: DwebbleWSEventType::ChecksumMismatch,
+                    connection_id,
+                    data: Some(data.to_vec()),
+                    error: Some(e),
+                    request_id: 0,
+                });
+                return;
+            }
+        },
+        (_, msg) => msg,
+    };
+    let verified = shared.signers.lock().verify(connection_id, msg.clone());
+    let msg = match verified {
+        Ok(msg) => msg,
+        Err(e) => {
+            shared.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::SignatureInvalid,
+                connection_id,
+                data: Some(msg.into_data().to_vec()),
+                error: Some(e),
+                request_id: 0,
+            });
+            return;
+        }
+    };
+    let window_ms = shared.settings.read().replay_protection.as_ref().map(|r| r.window_ms);
+    let checked = match window_ms {
+        Some(window_ms) => shared.nonces.lock().check(connection_id, window_ms, msg.clone()),
+        None => Ok(msg.clone()),
+    };
+    let msg = match checked {
+        Ok(msg) => msg,
+        Err(e) => {
+            shared.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::ReplayRejected,
+                connection_id,
+                data: Some(msg.into_data().to_vec()),
+                error: Some(e),
+                request_id: 0,
+            });
+            return;
+        }
+    };
+    let Some(msg) = version::check(shared, connection_id, msg) else {
+        return;
+    };
+    let Some(msg) = take_control(shared, connection_id, msg, upstream) else {
+        return;
+    };
+    let Some(msg) = shared.run_middleware(true, connection_id, msg) else {
+        return;
+    };
+    #[cfg(feature = "wasm")]
+    let Some(msg) = wasm::filter(shared, connection_id, msg) else {
+        return;
+    };
+    #[cfg(feature = "schema")]
+    let Some(msg) = schema::validate(shared, connection_id, msg) else {
+        return;
+    };
+    let (kind, data): (PayloadKind, &[u8]) = match &msg {
+        Message::Binary(data) => (PayloadKind::Binary, data),
+        Message::Text(text) => (PayloadKind::Text, text.as_bytes()),
+        _ => {
+            // Pass the client's closing handshake on to the upstream
+            if let Some(upstream) = upstream {
+                upstream.forward(msg);
+            }
+            return;
+        }
+    };
+
+    shared.record_journal(connection_id, Direction::Inbound, kind, data);
+    #[cfg(feature = "archive")]
+    if let Some(archive) = &shared.archive {
+        archive.record(connection_id, None, Direction::Inbound, kind, data);
+    }
+
+    let partner = shared.pipes.lock().partner(connection_id);
+    if let Some(partner) = partner {
+        shared.send_message(partner, msg);
+        return;
+    }
+
+    if kind == PayloadKind::Binary && shared.mqtt.lock().is_client(connection_id) {
+        mqtt::on_data(shared, connection_id, data);
+        return;
+    }
+
+    if let Some(upstream) = upstream {
+        upstream.forward(escape_message(shared, msg));
+        return;
+    }
+
+    if let Message::Text(text) = &msg {
+        #[cfg(feature = "webrtc")]
+        if shared.settings.read().webrtc.is_some() && rtc::on_message(shared, connection_id, text) {
+            return;
+        }
+        if shared.settings.read().socket_io.is_some() {
+            socketio::on_message(shared, connection_id, text);
+            return;
+        }
+        if shared.settings.read().json_rpc {
+            jsonrpc::on_message(shared, connection_id, text);
+            return;
+        }
+    }
+
+    if kind == PayloadKind::Binary {
+        let typed_codec = shared.settings.read().typed_codec;
+        if let Some((type_id, payload)) = typed_codec.and_then(|c| codec::decode(c, data)) {
+            shared.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::TypedMessage,
+                connection_id,
+                data: Some(pool::copy(payload)),
+                error: None,
+                request_id: u64::from(type_id),
+            });
+            return;
+        }
+    }
+
+    if let Some(mock) = &shared.mock {
+        mock::on_message(shared, mock, connection_id, &msg);
+    }
+
+    let data = pool::copy(data);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::MessageReceived,
+        connection_id,
+        data: Some(data),
+        error: None,
+        request_id: 0,
+    });
+}
+
+/// Handle a message if it is one of the library's control frames, before any of the
+/// host's stages see it. Returns the message to deliver otherwise, an escaped
+/// application payload unescaped.
+fn take_control(
+    shared: &Arc<Shared>,
+    connection_id: u64,
+    msg: Message,
+    upstream: Option<&Upstream>,
+) -> Option<Message> {
+    let Message::Binary(data) = &msg else {
+        return Some(msg);
+    };
+    // MQTT packets are the host's, whatever their first bytes
+    if shared.mqtt.lock().is_client(connection_id) {
+        return Some(msg);
+    }
+
+    let response = shared
+        .requests
+        .lock()
+        .take_response(connection_id, data)
+        .map(|(request_id, payload)| (request_id, pool::copy(payload)));
+    if let Some((request_id, payload)) = response {
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::ResponseReceived,
+            connection_id,
+            data: Some(payload),
+            error: None,
+            request_id,
+        });
+        return None;
+    }
+
+    let reserved = shared.settings.read().control_frames;
+    if let Some(upstream) = upstream {
+        // The upstream answers the other frames of its features
+        if control::is_control(data) && control::unescape(data).is_none() {
+            upstream.forward(msg);
+            return None;
+        }
+    } else {
+        if shared.settings.read().receipts && shared.receipts.lock().ack(connection_id, data) {
+            return None;
+        }
+        if shared.settings.read().delta.is_some() && shared.states.lock().ack(connection_id, data)
+        {
+            return None;
+        }
+        if !shared.settings.read().channels.is_empty() {
+            let inbound = shared.channels.lock().receive(connection_id, data);
+            if let Some(inbound) = inbound {
+                if let Some(ack) = inbound.ack {
+                    shared.send_control(connection_id, ack);
+                }
+                for payload in inbound.messages {
+                    shared.push_event(ServerEvent {
+                        event_type: DwebbleWSEventType::ChannelMessage,
+                        connection_id,
+                        data: Some(payload),
+                        error: None,
+                        request_id: u64::from(inbound.channel),
+                    });
+                }
+                return None;
+            }
+        }
+    }
+
+    // Control frames no enabled feature took are the library's, not the host's
+    if !reserved || !control::is_control(data) {
+        return Some(msg);
+    }
+    match control::unescape(data) {
+        Some(payload) => Some(Message::Binary(data.slice_ref(payload))),
+        None => {
+            tracing::debug!("Dropped a control frame from connection {}", connection_id);
+            None
+        }
+    }
+}
+
+/// A host message as sent, its binary payload escaped if control frames are reserved
+/// and it passes for one
+fn escape_message(shared: &Shared, msg: Message) -> Message {
+    match msg {
+        Message::Binary(data) if control::is_control(&data) => {
+            Message::Binary(shared.escape(&data).into())
+        }
+        msg => msg,
+    }
+}
+
+/// Parse a JSON array of Socket.IO event arguments
+fn parse_args(args_json: &str) -> Option<Vec<serde_json::Value>> {
+    match serde_json::from_str(args_json) {
+        Ok(serde_json::Value::Array(args)) => Some(args),
+        _ => None,
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
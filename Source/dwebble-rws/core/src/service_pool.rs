@@ -0,0 +1,338 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Pool of client connections to backend services
+//!
+//! A game server fanning out calls to platform services keeps a few connections
+//! open to each of their URLs. Requests go out in the envelope of the server's own
+//! requests (`DWRQ`, u64 request ID, payload) on the next healthy connection in
+//! turn, and the service answers with the same layout using the `DWRS` magic;
+//! other messages from the services are ignored.
+//!
+//! A connection is healthy while it is open and answers its health check pings:
+//! one still unanswered at the next check gets the connection closed. Closed
+//! connections fail their requests in flight and are reopened after a delay.
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::client;
+use crate::pool;
+use crate::requests::{self, Requests};
+use crate::runtime;
+use crate::server::ServerEvent;
+use crate::settings::{ServicePoolSettings, ThreadSettings};
+use crate::types::{DwebbleWSEventType, DwebbleWSResult, DwebbleWSServicePoolStats};
+
+/// A connection of the pool
+struct Slot {
+    url: String,
+    /// Sender to the connection's task while it is open and healthy
+    tx: Mutex<Option<mpsc::UnboundedSender<Message>>>,
+}
+
+struct Shared {
+    slots: Vec<Slot>,
+    /// Requests in flight, by the index of the connection they went out on
+    requests: Mutex<Requests>,
+    event_tx: mpsc::UnboundedSender<ServerEvent>,
+    /// Index of the connection the next request tries first
+    next: AtomicUsize,
+}
+
+impl Shared {
+    fn push(
+        &self,
+        event_type: DwebbleWSEventType,
+        index: usize,
+        data: Option<Vec<u8>>,
+        error: Option<String>,
+        request_id: u64,
+    ) {
+        let _ = self.event_tx.send(ServerEvent {
+            event_type,
+            connection_id: index as u64,
+            data,
+            error,
+            request_id,
+        });
+    }
+}
+
+/// Connections to a set of backend URLs, reopened whenever they close.
+///
+/// Events use the server event types with the connection's index in the pool as
+/// connection ID (`connections_per_url` per URL, in the order of `urls`):
+/// `ClientConnected` and `ClientDisconnected` as connections open and close,
+/// `Error` for each failed attempt, and `ResponseReceived` or `RequestTimedOut`
+/// for each request.
+pub struct ServicePool {
+    shared: Arc<Shared>,
+    event_rx: Mutex<mpsc::UnboundedReceiver<ServerEvent>>,
+    runtime: Option<Runtime>,
+}
+
+impl ServicePool {
+    /// Open the connections of `settings` on a runtime of the pool's own. Returns
+    /// `InvalidParam` if there is no URL or a URL or the client settings are invalid.
+    pub fn new(settings: ServicePoolSettings) -> Result<Self, DwebbleWSResult> {
+        if settings.urls.is_empty() || settings.connections_per_url == 0 {
+            return Err(DwebbleWSResult::InvalidParam);
+        }
+        // Fail now on what every attempt would fail on
+        for url in &settings.urls {
+            let request = client::upgrade_request(url, &settings.client).map_err(|e| {
+                tracing::error!("Invalid request to {}: {}", url, e);
+                DwebbleWSResult::InvalidParam
+            })?;
+            let secure = request.uri().scheme_str() == Some("wss");
+            drop(client::dial(request, secure, &settings.client.tls)?);
+        }
+
+        let threads = ThreadSettings {
+            worker_threads: 1,
+            ..Default::default()
+        };
+        let runtime =
+            runtime::build(Some(threads), None).map_err(|_| DwebbleWSResult::RuntimeError)?;
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let slots = settings
+            .urls
+            .iter()
+            .flat_map(|url| (0..settings.connections_per_url).map(move |_| url))
+            .map(|url| Slot {
+                url: url.clone(),
+                tx: Mutex::new(None),
+            })
+            .collect();
+        let shared = Arc::new(Shared {
+            slots,
+            requests: Mutex::new(Requests::default()),
+            event_tx,
+            next: AtomicUsize::new(0),
+        });
+
+        let settings = Arc::new(settings);
+        for index in 0..shared.slots.len() {
+            runtime.spawn(maintain(Arc::clone(&shared), index, Arc::clone(&settings)));
+        }
+
+        Ok(Self {
+            shared,
+            event_rx: Mutex::new(event_rx),
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Send a request on a healthy connection and wait up to `timeout` for the
+    /// response. Returns the request ID; the outcome is reported by a
+    /// `ResponseReceived` or `RequestTimedOut` event carrying it. Returns
+    /// `ConnectionClosed` if no connection is healthy.
+    pub fn request(&self, payload: &[u8], timeout: Duration) -> Result<u64, DwebbleWSResult> {
+        let Some(runtime) = &self.runtime else {
+            return Err(DwebbleWSResult::NotRunning);
+        };
+        let slots = &self.shared.slots;
+        let start = self.shared.next.fetch_add(1, Ordering::Relaxed);
+        let picked = (0..slots.len())
+            .map(|offset| (start + offset) % slots.len())
+            .find_map(|index| Some((index, slots[index].tx.lock().clone()?)));
+        let Some((index, tx)) = picked else {
+            return Err(DwebbleWSResult::ConnectionClosed);
+        };
+
+        let mut requests = self.shared.requests.lock();
+        let request_id = requests.next_id();
+        let shared = Arc::clone(&self.shared);
+        let timeout_task = runtime.spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if shared.requests.lock().remove(request_id) {
+                shared.push(
+                    DwebbleWSEventType::RequestTimedOut,
+                    index,
+                    None,
+                    None,
+                    request_id,
+                );
+            }
+        });
+        requests.insert(request_id, index as u64, timeout_task);
+
+        let data = requests::encode_request(request_id, payload);
+        if tx.send(Message::Binary(data.into())).is_err() {
+            requests.remove(request_id);
+            return Err(DwebbleWSResult::ConnectionClosed);
+        }
+        Ok(request_id)
+    }
+
+    pub fn stats(&self) -> DwebbleWSServicePoolStats {
+        let slots = &self.shared.slots;
+        DwebbleWSServicePoolStats {
+            connections: slots.len() as u32,
+            healthy_connections: slots.iter().filter(|s| s.tx.lock().is_some()).count() as u32,
+            pending_requests: self.shared.requests.lock().pending() as u64,
+        }
+    }
+
+    /// Drive a `single-thread` build's runtime for up to `budget`
+    pub fn tick(&self, budget: Duration) {
+        if let Some(rt) = &self.runtime {
+            runtime::tick(rt, budget);
+        }
+    }
+
+    pub fn poll_event(&self) -> Option<ServerEvent> {
+        self.event_rx.lock().try_recv().ok()
+    }
+}
+
+impl Drop for ServicePool {
+    fn drop(&mut self) {
+        self.shared.requests.lock().clear();
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Keep the pool's `index`th connection open, reopening it whenever it closes.
+/// Runs until the runtime shuts down.
+async fn maintain(shared: Arc<Shared>, index: usize, settings: Arc<ServicePoolSettings>) {
+    let url = &shared.slots[index].url;
+    let reconnect_delay = Duration::from_millis(settings.reconnect_delay_ms);
+    loop {
+        match open(url, &settings).await {
+            Ok(ws_stream) => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                *shared.slots[index].tx.lock() = Some(tx);
+                shared.push(DwebbleWSEventType::ClientConnected, index, None, None, 0);
+
+                let reason = serve(&shared, index, ws_stream, rx, &settings).await;
+
+                *shared.slots[index].tx.lock() = None;
+                let failed = shared.requests.lock().remove_connection(index as u64);
+                for request_id in failed {
+                    shared.push(
+                        DwebbleWSEventType::RequestTimedOut,
+                        index,
+                        None,
+                        None,
+                        request_id,
+                    );
+                }
+                tracing::warn!("Connection to {} closed: {}", url, reason);
+                shared.push(
+                    DwebbleWSEventType::ClientDisconnected,
+                    index,
+                    None,
+                    Some(reason),
+                    0,
+                );
+            }
+            Err(e) => {
+                let error = format!("Failed to connect to {}: {}", url, e);
+                shared.push(DwebbleWSEventType::Error, index, None, Some(error), 0);
+            }
+        }
+        tokio::time::sleep(reconnect_delay).await;
+    }
+}
+
+type Stream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Dial `url` with the client settings of the pool
+async fn open(url: &str, settings: &ServicePoolSettings) -> Result<Stream, tungstenite::Error> {
+    let request = client::upgrade_request(url, &settings.client)
+        .map_err(|e| tungstenite::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+    let secure = request.uri().scheme_str() == Some("wss");
+    let connect = client::dial(request, secure, &settings.client.tls).map_err(|_| {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "Invalid TLS settings");
+        tungstenite::Error::Io(e)
+    })?;
+    let timeout = Duration::from_millis(settings.client.handshake_timeout_ms);
+    let connected = if timeout.is_zero() {
+        connect.await
+    } else {
+        tokio::time::timeout(timeout, connect)
+            .await
+            .unwrap_or_else(|_| {
+                let e = io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out");
+                Err(tungstenite::Error::Io(e))
+            })
+    };
+    connected.map(|(ws_stream, _)| ws_stream)
+}
+
+/// Relay requests and responses over an open connection until it closes or fails a
+/// health check. Returns why it ended.
+async fn serve(
+    shared: &Shared,
+    index: usize,
+    ws_stream: Stream,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    settings: &ServicePoolSettings,
+) -> String {
+    let (mut write, mut read) = ws_stream.split();
+    let health_check = Duration::from_millis(settings.health_check_ms);
+    let mut health = tokio::time::interval(health_check.max(Duration::from_millis(1)));
+    health.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes at once
+    health.tick().await;
+    let mut awaiting_pong = false;
+
+    loop {
+        tokio::select! {
+            outbound = rx.recv() => {
+                let Some(msg) = outbound else {
+                    return "Pool dropped".to_string();
+                };
+                if let Err(e) = write.send(msg).await {
+                    return e.to_string();
+                }
+            }
+            inbound = read.next() => match inbound {
+                Some(Ok(Message::Binary(data))) => {
+                    let response = shared.requests.lock().take_response(index as u64, &data);
+                    if let Some((request_id, payload)) = response {
+                        let data = Some(pool::copy(payload));
+                        shared.push(DwebbleWSEventType::ResponseReceived, index, data, None, request_id);
+                    }
+                }
+                Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                Some(Ok(Message::Close(frame))) => {
+                    let _ = write.close().await;
+                    return match frame {
+                        Some(frame) if !frame.reason.is_empty() => frame.reason.to_string(),
+                        _ => "Closed by the service".to_string(),
+                    };
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return e.to_string(),
+                None => return "Connection lost".to_string(),
+            },
+            _ = health.tick(), if !health_check.is_zero() => {
+                if awaiting_pong {
+                    let _ = write.close().await;
+                    return "Health check timed out".to_string();
+                }
+                awaiting_pong = true;
+                if let Err(e) = write.send(Message::Ping(Default::default())).await {
+                    return e.to_string();
+                }
+            }
+        }
+    }
+}
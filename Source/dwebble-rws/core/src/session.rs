@@ -0,0 +1,227 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Session tokens and reconnect grace period
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::types::DwebbleWSEventType;
+
+/// Handshake header carrying the session token (request and response)
+pub const SESSION_HEADER: &str = "Dwebble-Session";
+
+/// Query parameter accepted in place of the header (for browser clients)
+pub const SESSION_QUERY_PARAM: &str = "dwebble_session";
+
+/// How often suspended sessions are checked for expiry
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Session {
+    connection_id: u64,
+    /// Set while the socket is gone and the session waits for a reconnect
+    suspended_since: Option<Instant>,
+    /// Outbound messages queued while there is no live socket
+    buffer: VecDeque<Message>,
+    buffered_bytes: usize,
+}
+
+/// Maps session tokens to logical connection ids
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: HashMap<String, Session>,
+    tokens: HashMap<u64, String>,
+}
+
+impl SessionStore {
+    /// Register a new active session for `connection_id`
+    pub fn insert(&mut self, token: String, connection_id: u64) {
+        self.tokens.insert(connection_id, token.clone());
+        self.sessions.insert(
+            token,
+            Session {
+                connection_id,
+                suspended_since: None,
+                buffer: VecDeque::new(),
+                buffered_bytes: 0,
+            },
+        );
+    }
+
+    /// Claim a suspended session. Returns its connection id.
+    pub fn resume(&mut self, token: &str) -> Option<u64> {
+        let session = self.sessions.get_mut(token)?;
+        session.suspended_since.take()?;
+        Some(session.connection_id)
+    }
+
+    /// Keep the session alive after its socket dropped. Returns false if there is no session.
+    pub fn suspend(&mut self, connection_id: u64) -> bool {
+        let Some(token) = self.tokens.get(&connection_id) else {
+            return false;
+        };
+        match self.sessions.get_mut(token) {
+            Some(session) => {
+                session.suspended_since = Some(Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget the session of `connection_id`
+    pub fn end(&mut self, connection_id: u64) {
+        if let Some(token) = self.tokens.remove(&connection_id) {
+            self.sessions.remove(&token);
+        }
+    }
+
+    /// Queue a message for replay on resume, dropping the oldest messages to stay
+    /// within the limits. Returns false if `connection_id` has no session.
+    pub fn buffer(
+        &mut self,
+        connection_id: u64,
+        msg: Message,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> bool {
+        let Some(session) = self
+            .tokens
+            .get(&connection_id)
+            .and_then(|t| self.sessions.get_mut(t))
+        else {
+            return false;
+        };
+
+        session.buffered_bytes += msg.len();
+        session.buffer.push_back(msg);
+
+        let mut dropped = 0;
+        while session.buffer.len() > max_messages || session.buffered_bytes > max_bytes {
+            match session.buffer.pop_front() {
+                Some(old) => {
+                    session.buffered_bytes -= old.len();
+                    dropped += 1;
+                }
+                None => break,
+            }
+        }
+        if dropped > 0 {
+            tracing::warn!(
+                "Session buffer full, dropped {} oldest message(s) (id: {})",
+                dropped,
+                connection_id
+            );
+        }
+        true
+    }
+
+    /// Bytes buffered over all sessions
+    pub fn buffered_bytes(&self) -> usize {
+        self.sessions.values().map(|s| s.buffered_bytes).sum()
+    }
+
+    /// Take the messages buffered for `connection_id`, in send order
+    pub fn take_buffer(&mut self, connection_id: u64) -> VecDeque<Message> {
+        self.tokens
+            .get(&connection_id)
+            .and_then(|t| self.sessions.get_mut(t))
+            .map(|s| {
+                s.buffered_bytes = 0;
+                std::mem::take(&mut s.buffer)
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn token(&self, connection_id: u64) -> Option<&str> {
+        self.tokens.get(&connection_id).map(String::as_str)
+    }
+
+    /// Connection ids of the sessions waiting for a reconnect
+    pub fn suspended(&self) -> Vec<u64> {
+        self.sessions
+            .values()
+            .filter(|s| s.suspended_since.is_some())
+            .map(|s| s.connection_id)
+            .collect()
+    }
+
+    /// Remove sessions suspended for longer than `grace`. Returns their connection ids.
+    pub fn expire(&mut self, grace: Duration) -> Vec<u64> {
+        let now = Instant::now();
+        let expired: Vec<u64> = self
+            .sessions
+            .values()
+            .filter(|s| {
+                s.suspended_since
+                    .is_some_and(|t| now.duration_since(t) >= grace)
+            })
+            .map(|s| s.connection_id)
+            .collect();
+
+        for id in &expired {
+            self.end(*id);
+        }
+        expired
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+        self.tokens.clear();
+    }
+}
+
+/// Generate a random session token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random source unavailable");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Extract a session token from the handshake query string
+pub fn token_from_query(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == SESSION_QUERY_PARAM)
+        .map(|(_, value)| value)
+}
+
+/// Periodically expire suspended sessions, emitting `ClientDisconnected` for each.
+/// Runs until the runtime shuts down.
+pub async fn run(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Disabling sessions at runtime expires suspended sessions immediately
+        let grace = shared
+            .settings
+            .read()
+            .sessions
+            .as_ref()
+            .map_or(Duration::ZERO, |s| Duration::from_millis(s.grace_period_ms));
+
+        let expired = shared.sessions.lock().expire(grace);
+        for connection_id in expired {
+            tracing::info!("Session expired (id: {})", connection_id);
+            shared.forget_connection(connection_id);
+            shared.push_event(ServerEvent {
+                event_type: DwebbleWSEventType::ClientDisconnected,
+                connection_id,
+                data: None,
+                error: Some("Session expired".to_string()),
+                request_id: 0,
+            });
+        }
+    }
+}
@@ -0,0 +1,1413 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Extended server settings passed as JSON
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::connection::is_sendable_close_code;
+
+/// Extended server settings.
+///
+/// Parsed from `DwebbleWSServerConfig::settings_json` at create time. Missing
+/// fields fall back to their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Supported subprotocols, in order of preference: of those a client offers, the
+    /// one listed first is selected, whatever the client's order
+    pub subprotocols: Vec<String>,
+    /// Maximum number of concurrent connections (0 for unlimited)
+    pub max_connections: usize,
+    /// Maximum size of an incoming message in bytes (0 for the library default)
+    pub max_message_size: usize,
+    /// Time allowed for the TLS and WebSocket handshakes in milliseconds (0 to disable)
+    pub handshake_timeout_ms: u64,
+    /// Close connections that receive nothing for this long in milliseconds (0 to disable)
+    pub idle_timeout_ms: u64,
+    /// Hold up to this many messages of a new WebSocket connection until the host
+    /// acknowledges its `ClientConnected` event, pausing reads once that many are held
+    /// (0 to deliver at once). Applies to connections accepted after a change.
+    pub hold_messages: usize,
+    /// Stop delivering a new WebSocket connection's messages after its login messages
+    /// until the host marks it authenticated (null to disable). Applies to connections
+    /// accepted after a change.
+    pub authentication: Option<AuthenticationSettings>,
+    /// Negotiate a protocol version in the first message of the library's clients
+    /// (null to disable). Create-time only.
+    pub protocol_version: Option<ProtocolVersionSettings>,
+    /// Reserve binary messages starting with `DW` and two uppercase ASCII letters for
+    /// the library's control frames: host payloads that start like one are sent behind
+    /// a `DWES` escape, and those from clients that no enabled feature takes are
+    /// dropped. Off, binary payloads pass through byte for byte, and only the frames of
+    /// enabled features are taken. Clients must use the same setting.
+    pub control_frames: bool,
+    /// Events the server's queue holds without locking, rounded up to a power of two;
+    /// more spill over into a slower overflow list until the host catches up.
+    /// Create-time only.
+    pub event_queue_capacity: usize,
+    /// Events the overflow list holds before message events are dropped and reported
+    /// in `EventsDropped` events; other events are always queued (0 for no limit).
+    /// Create-time only.
+    pub event_overflow_limit: usize,
+    /// Shards of the map of open connections, each behind a lock of its own, rounded up
+    /// to a power of two (0 for four per core). Create-time only.
+    pub connection_shards: usize,
+    /// Raise a `ResourceReport` event this often, in milliseconds (0 to disable).
+    /// Create-time only.
+    pub resource_report_ms: u64,
+    /// Allowed `Origin` header values (empty to allow any origin)
+    pub allowed_origins: Vec<String>,
+    /// Encrypt the messages of WebSocket connections in the library, for deployments
+    /// without TLS (null to disable). Create-time only.
+    pub encryption: Option<EncryptionSettings>,
+    /// Reject replayed and stale messages using a nonce and timestamp envelope in both
+    /// directions (null to disable). Create-time only.
+    pub replay_protection: Option<ReplayProtectionSettings>,
+    /// Checksum the data of polled events, e.g. to detect corruption by middleboxes
+    /// on plaintext links (null to disable). Create-time only.
+    pub checksum: Option<ChecksumSettings>,
+    /// Tracing filter directive, e.g. "info" or "dwebble_rws=debug"
+    pub log_level: Option<String>,
+    /// Close code sent to clients when the server stops. Codes a server may not send
+    /// (below 1000, 1004-1006, 1015-2999 and past 4999) are refused.
+    #[serde(deserialize_with = "close_code")]
+    pub close_code: u16,
+    /// Close reason sent to clients when the server stops
+    pub close_reason: String,
+    /// Time to wait for clients to complete the closing handshake on stop, in milliseconds
+    pub close_grace_ms: u64,
+    /// Time to wait for the server's tasks to finish once connections are closed on stop,
+    /// in milliseconds; tasks still running are then abandoned
+    pub shutdown_timeout_ms: u64,
+    /// Evict clients that cannot keep up with their send queue (null to disable)
+    pub slow_client: Option<SlowClientPolicy>,
+    /// Payload bytes a connection's send queue may hold; sends beyond it fail with
+    /// `QueueFull` (null for no limit)
+    pub send_queue_limit: Option<usize>,
+    /// Limit the rate of messages each connection may send (null to disable)
+    pub rate_limit: Option<RateLimitSettings>,
+    /// Server-wide cap on message events raised per interval (null for no cap)
+    pub event_budget: Option<EventBudgetSettings>,
+    /// Handling of text that is not valid UTF-8, in text sends and in text read off
+    /// WebRTC data channels
+    pub utf8_policy: Utf8Policy,
+    /// Session tokens with a reconnect grace period (null to disable)
+    pub sessions: Option<SessionSettings>,
+    /// Simulated network conditions for every connection (null to disable)
+    pub network_sim: Option<NetworkSimSettings>,
+    /// Treat text messages as JSON-RPC 2.0 requests, raising `RpcCall` events
+    /// instead of `MessageReceived`
+    pub json_rpc: bool,
+    /// Decode binary messages in this typed envelope format into `TypedMessage`
+    /// events, and encode typed sends with it (null to disable)
+    pub typed_codec: Option<TypedCodec>,
+    /// Virtual channels for channel sends, indexed by channel ID (empty to disable).
+    /// Create-time only.
+    pub channels: Vec<ChannelSettings>,
+    /// Delta-compressed keyed state sync for state sends (null to disable). Create-time only.
+    pub delta: Option<DeltaSettings>,
+    /// zstd compression of every message for clients asking for it with a subprotocol
+    /// suffix (null to disable). Requires the `zstd` feature. Create-time only.
+    pub zstd: Option<ZstdSettings>,
+    /// Number sequenced sends per connection and track the highest sequence each client
+    /// acknowledged. Create-time only.
+    pub receipts: bool,
+    /// Keep the latest broadcasts of each room for `dwebble_rws_server_get_history`
+    /// and late joiners (null to disable). Create-time only.
+    pub history: Option<HistorySettings>,
+    /// WebAssembly modules filtering inbound messages, run in order. Requires the `wasm`
+    /// feature.
+    pub wasm_filters: Vec<WasmFilterSettings>,
+    /// JSON Schemas validating inbound text messages, per subprotocol. Requires the
+    /// `schema` feature.
+    pub schemas: Vec<SchemaSettings>,
+    /// Speak the Socket.IO protocol instead of raw messages (null to disable). Create-time only.
+    pub socket_io: Option<SocketIoSettings>,
+    /// Act as an MQTT 3.1.1 broker for connections negotiating the `mqtt` subprotocol.
+    /// Create-time only.
+    pub mqtt: bool,
+    /// Serve Server-Sent Events on the WebSocket listener (null to disable). Create-time only.
+    pub sse: Option<SseSettings>,
+    /// Also accept WebSockets bootstrapped over HTTP/2 (RFC 8441) on the WebSocket listener,
+    /// negotiated over ALPN with TLS or with prior knowledge without. Requires the `http2`
+    /// feature. Create-time only.
+    pub http2: bool,
+    /// Accept WebTransport sessions over HTTP/3 (null to disable). Requires TLS and the
+    /// `webtransport` feature. Create-time only.
+    pub webtransport: Option<WebTransportSettings>,
+    /// Host WebRTC data channels negotiated over WebSocket connections (null to disable).
+    /// Requires the `webrtc` feature. Create-time only.
+    pub webrtc: Option<WebRtcSettings>,
+    /// Accept length-prefixed raw TCP streams and UDP datagrams from trusted peers
+    /// (null to disable). Create-time only.
+    pub raw: Option<RawSettings>,
+    /// On-disk message journal (null to disable). Create-time only.
+    pub journal: Option<JournalSettings>,
+    /// SQLite archive of connection messages and room broadcasts (null to disable).
+    /// Requires the `archive` feature. Create-time only.
+    pub archive: Option<ArchiveSettings>,
+    /// Record every event to a file for later replay (null to disable). Create-time only.
+    pub record: Option<RecordSettings>,
+    /// Replay a recording instead of listening on the network (null to disable). Create-time only.
+    pub replay: Option<ReplaySettings>,
+    /// Built-in mock server behavior (null to disable). Create-time only.
+    pub mock: Option<MockSettings>,
+    /// Relay every connection to an upstream server (null to disable). Create-time only.
+    pub bridge: Option<BridgeSettings>,
+    /// Share room broadcasts with sibling server instances (null to disable). Create-time only.
+    pub cluster: Option<ClusterSettings>,
+    /// Forward a port on the local gateway to the WebSocket listener while running, over
+    /// UPnP or NAT-PMP (null to disable). Requires the `port-mapping` feature. Create-time only.
+    pub port_mapping: Option<PortMappingSettings>,
+    /// Accept JSON admin commands over HTTP on a loopback port (null to disable).
+    /// Create-time only.
+    pub admin: Option<AdminSettings>,
+    /// POST lifecycle notifications to HTTP endpoints (null to disable). Create-time only.
+    pub webhooks: Option<WebhookSettings>,
+    /// Push metrics to a StatsD or DogStatsD agent over UDP (null to disable).
+    /// Create-time only.
+    pub statsd: Option<StatsdSettings>,
+    /// Warn of a stalled runtime, writer or event queue (null to disable). Unavailable
+    /// in `single-thread` builds. Create-time only.
+    pub watchdog: Option<WatchdogSettings>,
+    /// Worker thread count, core affinity and OS priority of the server's runtime (null
+    /// for the runtime's defaults). Unavailable in `single-thread` builds. Create-time
+    /// only.
+    pub threads: Option<ThreadSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            subprotocols: vec![],
+            max_connections: 0,
+            max_message_size: 0,
+            handshake_timeout_ms: 10_000,
+            idle_timeout_ms: 0,
+            hold_messages: 0,
+            authentication: None,
+            protocol_version: None,
+            control_frames: false,
+            event_queue_capacity: 8192,
+            event_overflow_limit: 262_144,
+            connection_shards: 0,
+            resource_report_ms: 0,
+            allowed_origins: vec![],
+            encryption: None,
+            replay_protection: None,
+            checksum: None,
+            log_level: None,
+            close_code: 1001,
+            close_reason: "Server shutting down".to_string(),
+            close_grace_ms: 1_000,
+            shutdown_timeout_ms: 5_000,
+            slow_client: None,
+            send_queue_limit: None,
+            rate_limit: None,
+            event_budget: None,
+            utf8_policy: Utf8Policy::default(),
+            sessions: None,
+            network_sim: None,
+            json_rpc: false,
+            typed_codec: None,
+            channels: vec![],
+            delta: None,
+            zstd: None,
+            receipts: false,
+            history: None,
+            wasm_filters: vec![],
+            schemas: vec![],
+            socket_io: None,
+            mqtt: false,
+            sse: None,
+            http2: false,
+            webtransport: None,
+            webrtc: None,
+            raw: None,
+            journal: None,
+            archive: None,
+            record: None,
+            replay: None,
+            mock: None,
+            bridge: None,
+            cluster: None,
+            port_mapping: None,
+            admin: None,
+            webhooks: None,
+            statsd: None,
+            watchdog: None,
+            threads: None,
+        }
+    }
+}
+
+/// Policy for evicting connections whose send queue stays too large
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SlowClientPolicy {
+    /// Queued payload bytes above which a connection counts as slow
+    pub max_queued_bytes: usize,
+    /// How long the queue may stay above the threshold before eviction, in milliseconds
+    pub duration_ms: u64,
+    /// Close code sent to evicted clients
+    #[serde(deserialize_with = "close_code")]
+    pub close_code: u16,
+    /// Close reason sent to evicted clients and reported in the disconnect event
+    pub close_reason: String,
+}
+
+impl Default for SlowClientPolicy {
+    fn default() -> Self {
+        Self {
+            max_queued_bytes: 4 * 1024 * 1024,
+            duration_ms: 5_000,
+            close_code: 1013,
+            close_reason: "Slow client".to_string(),
+        }
+    }
+}
+
+/// Inbound rate limits per connection. A connection may send up to a second's worth
+/// at once.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Data messages per second (0 for no limit)
+    pub messages_per_sec: u32,
+    /// Data message payload bytes per second (0 for no limit)
+    pub bytes_per_sec: u64,
+    /// What happens to messages over the limits
+    pub action: RateLimitAction,
+}
+
+/// Handling of messages over a connection's rate limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAction {
+    /// Stop reading until the connection is back within its limits, holding the
+    /// client back with TCP flow control
+    #[default]
+    Delay,
+    /// Drop the message
+    Drop,
+    /// Close the connection with code 1008 (policy violation)
+    Close,
+}
+
+/// Server-wide budget of message events. Message events over it are dropped and
+/// reported in `EventsDropped` events.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct EventBudgetSettings {
+    /// Message events raised per interval, over all connections
+    pub max_events: u32,
+    /// Length of an interval, in milliseconds
+    pub interval_ms: u64,
+}
+
+impl Default for EventBudgetSettings {
+    fn default() -> Self {
+        Self {
+            max_events: 1_000,
+            interval_ms: 100,
+        }
+    }
+}
+
+/// Deserialize a close code, refusing those a server may not send
+fn close_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    check_close_code(u16::deserialize(deserializer)?)
+}
+
+fn optional_close_code<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<u16>::deserialize(deserializer)?
+        .map(check_close_code)
+        .transpose()
+}
+
+fn check_close_code<E: serde::de::Error>(code: u16) -> Result<u16, E> {
+    if !is_sendable_close_code(code) {
+        return Err(E::custom(format!(
+            "close code {} is reserved or out of range",
+            code
+        )));
+    }
+    Ok(code)
+}
+
+impl Settings {
+    /// Parse settings from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Check an `Origin` header value against the allowed origins
+    pub fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        if self.allowed_origins.is_empty() {
+            return true;
+        }
+        origin.is_some_and(|o| self.allowed_origins.iter().any(|a| a == "*" || a == o))
+    }
+}
+
+/// Session layer settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// How long a dropped session waits for its client to reconnect, in milliseconds
+    pub grace_period_ms: u64,
+    /// Maximum messages buffered for replay while suspended (0 disables buffering)
+    pub buffer_max_messages: usize,
+    /// Maximum payload bytes buffered for replay while suspended
+    pub buffer_max_bytes: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            grace_period_ms: 30_000,
+            buffer_max_messages: 0,
+            buffer_max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Authentication of new connections by the host
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthenticationSettings {
+    /// Messages delivered before the connection is authenticated, e.g. a login message;
+    /// reads pause after them
+    pub login_messages: usize,
+    /// Close connections not authenticated within this long, in milliseconds (0 to wait
+    /// indefinitely)
+    pub timeout_ms: u64,
+}
+
+impl Default for AuthenticationSettings {
+    fn default() -> Self {
+        Self {
+            login_messages: 1,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// Protocol version handshake with new connections
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ProtocolVersionSettings {
+    /// Major version of the application protocol; clients must offer the same one
+    pub major: u16,
+    /// Minor version of the application protocol, the newest understood
+    pub minor: u16,
+    /// Oldest minor version still accepted
+    pub min_minor: u16,
+    /// Close connections whose first message is not a version offer. Otherwise such a
+    /// connection goes on without a negotiated version.
+    pub required: bool,
+    /// Close code sent to clients offering an incompatible version
+    #[serde(deserialize_with = "close_code")]
+    pub close_code: u16,
+}
+
+impl Default for ProtocolVersionSettings {
+    fn default() -> Self {
+        Self {
+            major: 1,
+            minor: 0,
+            min_minor: 0,
+            required: false,
+            close_code: 1002,
+        }
+    }
+}
+
+/// Simulated network conditions (fault injection for QA)
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkSimSettings {
+    /// Delay added to every message in both directions, in milliseconds
+    pub latency_ms: u64,
+    /// Extra random delay of up to this many milliseconds
+    pub jitter_ms: u64,
+    /// Probability (0-1) that a data message is dropped
+    pub drop_rate: f64,
+    /// Probability (0-1) per second that a connection drops without a closing handshake
+    pub disconnect_rate: f64,
+    /// Throughput cap per direction in bytes per second (0 for unlimited)
+    pub bandwidth_bytes_per_sec: u64,
+}
+
+/// Message journal settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JournalSettings {
+    /// Directory holding the journal files
+    pub directory: String,
+    /// Journal every connection, or only those selected via FFI and the members of
+    /// selected rooms
+    pub all_connections: bool,
+    /// Rooms whose members are journaled when `all_connections` is off, besides those
+    /// selected via FFI
+    pub rooms: Vec<String>,
+    /// Also journal messages sent by the server
+    pub include_outbound: bool,
+    /// Start a new file once the current one reaches this size
+    pub max_file_bytes: u64,
+    /// Number of files kept before the oldest is deleted (0 to keep all)
+    pub max_files: usize,
+}
+
+impl Default for JournalSettings {
+    fn default() -> Self {
+        Self {
+            directory: "journal".to_string(),
+            all_connections: true,
+            rooms: vec![],
+            include_outbound: false,
+            max_file_bytes: 64 * 1024 * 1024,
+            max_files: 8,
+        }
+    }
+}
+
+/// SQLite message archive settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    /// Database file, created if it does not exist
+    pub path: String,
+    /// Also archive each message sent to a connection, one row per member for room
+    /// broadcasts
+    pub include_outbound: bool,
+    /// Delete messages older than this, in milliseconds (0 to keep them)
+    pub max_age_ms: u64,
+    /// Delete the oldest messages beyond this many (0 for no limit)
+    pub max_messages: u64,
+    /// How often the retention limits are applied, in milliseconds
+    pub prune_interval_ms: u64,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            path: "archive.db".to_string(),
+            include_outbound: false,
+            max_age_ms: 0,
+            max_messages: 1_000_000,
+            prune_interval_ms: 60_000,
+        }
+    }
+}
+
+/// Event recording settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RecordSettings {
+    /// Recording file to create (overwritten if it exists)
+    pub path: String,
+}
+
+/// Replay mode settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplaySettings {
+    /// Recording file to play back
+    pub path: String,
+    /// Playback speed multiplier (1.0 for the original schedule)
+    pub speed: f64,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            speed: 1.0,
+        }
+    }
+}
+
+/// Built-in mock server behaviors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MockMode {
+    /// Reflect every message back to its sender
+    Echo,
+    /// Send every message to all connections, including the sender
+    BroadcastEcho,
+    /// Reply according to `rules`
+    Scripted,
+}
+
+/// Mock server settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockSettings {
+    pub mode: MockMode,
+    /// Rules for the scripted mode; the first matching rule replies
+    #[serde(default)]
+    pub rules: Vec<MockRule>,
+}
+
+/// Scripted reply to messages matching a pattern
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MockRule {
+    /// Match messages equal to this payload
+    pub equals: Option<String>,
+    /// Match messages containing this payload (a rule without patterns matches everything)
+    pub contains: Option<String>,
+    /// Reply payload
+    pub reply: String,
+    /// Send the reply as a binary message instead of text
+    pub binary: bool,
+    /// Send the reply to every connection instead of only the sender
+    pub broadcast: bool,
+    /// Delay before replying, in milliseconds
+    pub delay_ms: u64,
+}
+
+/// Bridge (reverse proxy) settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BridgeSettings {
+    /// Upstream server URL (ws:// or wss://), used when `upstreams` is empty
+    pub url: String,
+    /// Upstream server URLs to spread connections across
+    pub upstreams: Vec<String>,
+    /// How connections without a sticky key are spread across healthy upstreams
+    pub balance: BalanceStrategy,
+    /// Route connections by this handshake header so equal values reach the same upstream
+    pub sticky_header: Option<String>,
+    /// Route connections by session token (requires `sessions`) when no sticky header is set
+    pub sticky_session: bool,
+    /// How often each upstream is probed with a handshake, in milliseconds (0 to disable)
+    pub health_check_interval_ms: u64,
+    /// Forward the client's handshake headers (cookies, authorization, ...) to the upstream
+    pub forward_headers: bool,
+}
+
+impl Default for BridgeSettings {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            upstreams: vec![],
+            balance: BalanceStrategy::RoundRobin,
+            sticky_header: None,
+            sticky_session: false,
+            health_check_interval_ms: 5_000,
+            forward_headers: true,
+        }
+    }
+}
+
+impl BridgeSettings {
+    /// Configured upstream URLs
+    pub fn upstream_urls(&self) -> Vec<&str> {
+        if self.upstreams.is_empty() {
+            vec![self.url.as_str()]
+        } else {
+            self.upstreams.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+/// Upstream selection strategy for bridge mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    RoundRobin,
+    LeastConnections,
+}
+
+/// Clustering backend, selected by the `backend` field
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum ClusterSettings {
+    /// Redis pub/sub (requires the `redis` cargo feature)
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    Redis(RedisClusterSettings),
+    /// NATS core subjects
+    Nats(NatsClusterSettings),
+}
+
+/// Redis pub/sub clustering settings
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+#[serde(default)]
+pub struct RedisClusterSettings {
+    /// Server URL, including any credentials: `redis://[user:password@]host:port[/db]`
+    pub url: String,
+    /// Prefix of the channel names, to share a Redis server between clusters
+    pub channel_prefix: String,
+}
+
+impl Default for RedisClusterSettings {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+            channel_prefix: "dwebble:".to_string(),
+        }
+    }
+}
+
+/// NATS clustering settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NatsClusterSettings {
+    /// Server URL, including any credentials: `nats://[user:password@ | token@]host:port`
+    pub url: String,
+    /// Prefix of the subjects, to share a NATS server between clusters
+    pub subject_prefix: String,
+}
+
+impl Default for NatsClusterSettings {
+    fn default() -> Self {
+        Self {
+            url: "nats://127.0.0.1:4222".to_string(),
+            subject_prefix: "dwebble".to_string(),
+        }
+    }
+}
+
+/// Handling of text that is not valid UTF-8
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Utf8Policy {
+    /// Reject it: sends fail with `InvalidUtf8`, received text is dropped
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    #[default]
+    Lossy,
+    /// Pass the bytes through unchanged as a binary message
+    Binary,
+}
+
+/// Serialization format of typed message envelopes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypedCodec {
+    Msgpack,
+    Cbor,
+}
+
+/// Delivery guarantees of a virtual channel
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChannelSettings {
+    pub mode: ChannelMode,
+    /// Have the peer acknowledge sequenced messages, measuring the round-trip time.
+    /// Reliable messages are always acknowledged.
+    pub acks: bool,
+    /// Unacknowledged reliable messages kept for retransmission per connection;
+    /// the oldest are abandoned beyond this
+    pub max_unacked: usize,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            mode: ChannelMode::Reliable,
+            acks: false,
+            max_unacked: 1024,
+        }
+    }
+}
+
+/// Delivery mode of a virtual channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelMode {
+    /// Every message once and in order, sent again after a session resumes (e.g. chat)
+    Reliable,
+    /// Only messages newer than the last one delivered, the rest dropped on arrival
+    /// (e.g. state snapshots)
+    Sequenced,
+}
+
+/// Application-layer encryption settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EncryptionSettings {
+    /// Secret shared with clients and mixed into the derived keys, so only peers knowing
+    /// it can complete the key exchange (empty for none, which stops eavesdroppers but
+    /// not an active man in the middle)
+    pub psk: String,
+    /// Rotate each connection's keys this often, in milliseconds (0 to rotate only on
+    /// request)
+    pub rekey_interval_ms: u64,
+}
+
+/// Replay protection settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplayProtectionSettings {
+    /// Accept messages whose timestamp is within this many milliseconds of the
+    /// receiver's clock, remembering their nonces for as long
+    pub window_ms: u64,
+}
+
+impl Default for ReplayProtectionSettings {
+    fn default() -> Self {
+        Self { window_ms: 30_000 }
+    }
+}
+
+/// Payload checksum settings
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct ChecksumSettings {
+    pub algorithm: ChecksumAlgorithm,
+    /// Require each binary message from a client to end with the little-endian u32
+    /// checksum of the rest, as the library's client sends them with this setting.
+    /// The checksum is stripped before the message goes further; a message without
+    /// a matching one is dropped and raises `ChecksumMismatch`.
+    pub verify_trailer: bool,
+}
+
+/// Hash of payload checksums
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE), as computed by zlib
+    #[default]
+    Crc32,
+    /// 32-bit xxHash with a seed of 0, faster on large payloads
+    Xxh32,
+}
+
+/// Room message history settings
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Broadcasts kept per room
+    pub max_messages: usize,
+    /// Send a room's history to each connection joining it, before anything else
+    /// broadcast to the room
+    pub replay_on_join: bool,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            max_messages: 50,
+            replay_on_join: true,
+        }
+    }
+}
+
+/// Delta-compressed state sync settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeltaSettings {
+    /// Send a full state instead of a delta every this many updates of a key, so
+    /// clients that lost their copy recover (0 for only when none was acknowledged)
+    pub full_snapshot_interval: u32,
+}
+
+impl Default for DeltaSettings {
+    fn default() -> Self {
+        Self {
+            full_snapshot_interval: 100,
+        }
+    }
+}
+
+/// A WebAssembly message filter
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WasmFilterSettings {
+    /// Path of the `.wasm` module
+    pub path: String,
+    /// Only filter messages of connections in any of these rooms (empty for all)
+    pub rooms: Vec<String>,
+    /// Instructions a module may execute per message (roughly one fuel each)
+    pub fuel: u64,
+}
+
+impl Default for WasmFilterSettings {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            rooms: vec![],
+            fuel: 10_000_000,
+        }
+    }
+}
+
+/// A JSON Schema for the messages of a subprotocol
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchemaSettings {
+    /// Subprotocol whose connections are validated (empty for connections that
+    /// negotiated none)
+    pub subprotocol: String,
+    /// The schema itself
+    pub schema: Option<serde_json::Value>,
+    /// Path of a file holding the schema, if `schema` is not given
+    pub path: String,
+    pub action: ValidationAction,
+}
+
+impl Default for SchemaSettings {
+    fn default() -> Self {
+        Self {
+            subprotocol: String::new(),
+            schema: None,
+            path: String::new(),
+            action: ValidationAction::Reject,
+        }
+    }
+}
+
+/// What happens to a message that does not validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationAction {
+    /// Drop the message
+    Reject,
+    /// Deliver the message anyway
+    Flag,
+}
+
+/// Socket.IO compatibility mode settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SocketIoSettings {
+    /// How often clients are pinged, in milliseconds
+    pub ping_interval_ms: u64,
+    /// How long a client may take to answer a ping before it is closed, in milliseconds
+    pub ping_timeout_ms: u64,
+}
+
+impl Default for SocketIoSettings {
+    fn default() -> Self {
+        Self {
+            ping_interval_ms: 25_000,
+            ping_timeout_ms: 20_000,
+        }
+    }
+}
+
+/// zstd message compression settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ZstdSettings {
+    /// Suffix a client appends to a supported subprotocol to ask for compression
+    pub subprotocol_suffix: String,
+    /// Compression level, from 1 (fastest) to 22
+    pub level: i32,
+    /// Messages smaller than this go out uncompressed, in bytes
+    pub min_size: usize,
+}
+
+impl Default for ZstdSettings {
+    fn default() -> Self {
+        Self {
+            subprotocol_suffix: "+zstd".to_string(),
+            level: 3,
+            min_size: 32,
+        }
+    }
+}
+
+/// Server-Sent Events endpoint settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SseSettings {
+    /// Request path of the event stream
+    pub path: String,
+    /// How often an idle stream gets a keep-alive comment, in milliseconds (0 to disable)
+    pub keep_alive_ms: u64,
+}
+
+impl Default for SseSettings {
+    fn default() -> Self {
+        Self {
+            path: "/events".to_string(),
+            keep_alive_ms: 15_000,
+        }
+    }
+}
+
+/// WebTransport listener settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebTransportSettings {
+    /// UDP port to listen on (0 for the WebSocket listener's port)
+    pub port: u16,
+    /// Request path sessions are opened on
+    pub path: String,
+}
+
+impl Default for WebTransportSettings {
+    fn default() -> Self {
+        Self {
+            port: 0,
+            path: "/".to_string(),
+        }
+    }
+}
+
+/// WebRTC data channel settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WebRtcSettings {
+    /// STUN server URLs used to gather ICE candidates, e.g. "stun:stun.l.google.com:19302"
+    /// (empty for host candidates only)
+    pub ice_servers: Vec<String>,
+}
+
+/// Raw TCP and UDP listener settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RawSettings {
+    /// TCP port to listen on (0 to disable TCP)
+    pub tcp_port: u16,
+    /// UDP port to listen on (0 to disable UDP)
+    pub udp_port: u16,
+    /// Disconnect a UDP peer after this long without a datagram, in milliseconds (0 to disable)
+    pub udp_idle_timeout_ms: u64,
+}
+
+impl Default for RawSettings {
+    fn default() -> Self {
+        Self {
+            tcp_port: 0,
+            udp_port: 0,
+            udp_idle_timeout_ms: 30_000,
+        }
+    }
+}
+
+/// Gateway port mapping settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PortMappingSettings {
+    /// External port to request (0 for the listener's own port)
+    pub external_port: u16,
+    /// Lifetime of the mapping requested from the gateway, in seconds; renewed halfway through
+    pub lease_s: u32,
+    /// NAT-PMP gateway address, used when no UPnP gateway answers (empty for the first host
+    /// of the local /24 subnet, e.g. 192.168.1.1)
+    pub nat_pmp_gateway: String,
+}
+
+impl Default for PortMappingSettings {
+    fn default() -> Self {
+        Self {
+            external_port: 0,
+            lease_s: 3600,
+            nat_pmp_gateway: String::new(),
+        }
+    }
+}
+
+/// Admin endpoint settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminSettings {
+    /// Port to listen on at 127.0.0.1 (0 for any free port)
+    pub port: u16,
+    /// Bearer token requests must present in an `Authorization` header (empty for none)
+    pub token: String,
+    /// Serve a status dashboard page to browsers at `/`
+    pub dashboard: bool,
+}
+
+/// Webhook notification settings
+///
+/// Notifications are `started`, `stopped`, `connections_high`, `error_rate_high`
+/// and `banned`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// Names the server in notifications (empty for the WebSocket listener's address)
+    pub source: String,
+    /// Connection count raising `connections_high` (0 to disable), raised again once the
+    /// count has dropped below it
+    pub max_connections: usize,
+    /// `Error`, `TlsHandshakeFailed` and `HandshakeFailed` events over the last minute
+    /// raising `error_rate_high` (0 to disable), raised at most once a minute
+    pub max_errors_per_min: u64,
+    /// Retries of a failed delivery, each waiting twice as long as the one before
+    pub max_retries: u32,
+    /// Wait before the first retry, in milliseconds
+    pub retry_delay_ms: u64,
+    /// Time allowed for each delivery attempt, in milliseconds
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            source: String::new(),
+            max_connections: 0,
+            max_errors_per_min: 0,
+            max_retries: 3,
+            retry_delay_ms: 1000,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// An endpoint notified by webhooks
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WebhookEndpoint {
+    /// `http://` or `https://` URL notifications are POSTed to
+    pub url: String,
+    /// Key signing each body with HMAC-SHA256, sent as `X-Dwebble-Signature: sha256=<hex>`
+    /// (empty to leave notifications unsigned)
+    pub secret: String,
+    /// Notifications to send, e.g. `["stopped", "banned"]` (empty for all)
+    pub events: Vec<String>,
+}
+
+/// StatsD metrics settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsdSettings {
+    /// Agent address, `host:port`
+    pub address: String,
+    /// Prepended to every metric name with a dot (empty for none)
+    pub prefix: String,
+    /// How often metrics are pushed and round trip times probed, in milliseconds
+    pub interval_ms: u64,
+    /// DogStatsD tags added to every metric, e.g. `["env:prod", "region:eu"]` (empty
+    /// for plain StatsD)
+    pub tags: Vec<String>,
+    /// Largest UDP packet sent; metrics are batched into packets up to this size
+    pub max_packet_size: usize,
+}
+
+impl Default for StatsdSettings {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: "dwebble".to_string(),
+            interval_ms: 10_000,
+            tags: vec![],
+            max_packet_size: 1432,
+        }
+    }
+}
+
+/// Health watchdog settings. A stall of 0 milliseconds disables its check.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchdogSettings {
+    /// How often the watchdog checks, in milliseconds
+    pub check_interval_ms: u64,
+    /// Time a task spawned on the runtime may wait to run, in milliseconds
+    pub runtime_stall_ms: u64,
+    /// Time a connection's writer may go without writing while messages are queued, in
+    /// milliseconds
+    pub writer_stall_ms: u64,
+    /// Time events may wait on the server's queue without the host polling it, in
+    /// milliseconds
+    pub event_queue_stall_ms: u64,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            check_interval_ms: 500,
+            runtime_stall_ms: 2_000,
+            writer_stall_ms: 10_000,
+            event_queue_stall_ms: 5_000,
+        }
+    }
+}
+
+/// Threads of a server's runtime. Cores and priorities are set on Windows, Linux and
+/// Android; elsewhere the host applies its own from the thread start callback.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ThreadSettings {
+    /// Worker threads of the runtime (0 for one per core)
+    pub worker_threads: usize,
+    /// Cores to pin the runtime's threads to, each thread to the next core in turn
+    /// (empty to leave them unpinned). Threads started for blocking work count too.
+    pub cores: Vec<usize>,
+    /// OS priority of the runtime's threads
+    pub priority: ThreadPriority,
+}
+
+/// OS priority of a thread, mapped to the platform's nearest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadPriority {
+    Lowest,
+    BelowNormal,
+    /// Left as the OS starts threads
+    #[default]
+    Normal,
+    AboveNormal,
+    Highest,
+}
+
+/// Settings of a client dialing a server, passed as JSON when connecting
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClientSettings {
+    /// Headers added to the upgrade request, e.g. `Authorization`
+    pub headers: BTreeMap<String, String>,
+    /// Cookies sent in the upgrade request's `Cookie` header
+    pub cookies: BTreeMap<String, String>,
+    /// Query parameters appended to the URL, percent-encoded
+    pub query: BTreeMap<String, String>,
+    /// Subprotocols offered, in order of preference
+    pub subprotocols: Vec<String>,
+    /// Time allowed to connect and complete the TLS and WebSocket handshakes, in
+    /// milliseconds (0 to wait indefinitely)
+    pub handshake_timeout_ms: u64,
+    /// Verification of the server's certificate with `wss://` URLs
+    pub tls: ClientTlsSettings,
+    /// Reconnect after the connection drops, queueing messages sent meanwhile (null
+    /// to stay disconnected)
+    pub offline_queue: Option<OfflineQueueSettings>,
+    /// Settings of the server dialed, for its virtual channels, state sync, receipts,
+    /// encryption, replay protection, checksum trailers and protocol version
+    pub server: Settings,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            headers: BTreeMap::new(),
+            cookies: BTreeMap::new(),
+            query: BTreeMap::new(),
+            subprotocols: vec![],
+            handshake_timeout_ms: 10_000,
+            tls: ClientTlsSettings::default(),
+            offline_queue: None,
+            server: Settings::default(),
+        }
+    }
+}
+
+/// Messages a client holds while disconnected, sent in order once it reconnects
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct OfflineQueueSettings {
+    /// Messages held at most; the oldest is dropped to make room for a new one
+    pub max_messages: usize,
+    /// Delay before the first reconnect attempt, in milliseconds, doubled after each
+    /// failed attempt
+    pub reconnect_delay_ms: u64,
+    /// Longest delay between reconnect attempts, in milliseconds
+    pub max_reconnect_delay_ms: u64,
+}
+
+impl Default for OfflineQueueSettings {
+    fn default() -> Self {
+        Self {
+            max_messages: 256,
+            reconnect_delay_ms: 1_000,
+            max_reconnect_delay_ms: 30_000,
+        }
+    }
+}
+
+/// Verification of a server's certificate by a client. Requires the `tls` feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClientTlsSettings {
+    pub verification: CertificateVerification,
+    /// PEM certificates of the CAs trusted with `ca_bundle`
+    pub ca_pem: String,
+    /// Base64 SHA-256 hashes of the DER SubjectPublicKeyInfo of the server certificates
+    /// accepted with `pinned`
+    pub spki_pins: Vec<String>,
+}
+
+/// What a client trusts a server's certificate by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateVerification {
+    /// A chain to the Mozilla root certificates built into the library
+    #[default]
+    MozillaRoots,
+    /// A chain to the root certificates of the operating system's store
+    SystemRoots,
+    /// A chain to the CAs of `ca_pem`, e.g. a staging environment's private CA
+    CaBundle,
+    /// A certificate whose public key hashes to one of `spki_pins`, whatever signed it
+    Pinned,
+    /// Any certificate. For development only: it lets anyone impersonate the server.
+    Disabled,
+}
+
+impl ClientSettings {
+    /// Parse settings from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Settings of a pool of connections to backend services, passed as JSON when
+/// creating it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServicePoolSettings {
+    /// URLs of the services (`ws://`, or `wss://` with the `tls` feature)
+    pub urls: Vec<String>,
+    /// Connections kept open to each URL
+    pub connections_per_url: usize,
+    /// Interval of the ping health checks, in milliseconds (0 to disable). A
+    /// connection that hasn't answered by the next check is closed.
+    pub health_check_ms: u64,
+    /// Delay before reopening a connection that closed or failed to open, in
+    /// milliseconds
+    pub reconnect_delay_ms: u64,
+    /// Headers, cookies, query parameters, subprotocols, handshake timeout and
+    /// certificate verification of the connections; the rest is ignored
+    pub client: ClientSettings,
+}
+
+impl Default for ServicePoolSettings {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            connections_per_url: 1,
+            health_check_ms: 5_000,
+            reconnect_delay_ms: 1_000,
+            client: ClientSettings::default(),
+        }
+    }
+}
+
+impl ServicePoolSettings {
+    /// Parse settings from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Hot-changeable subset of [`Settings`] applied to a running server.
+///
+/// Only fields present in the JSON are changed. Unknown fields (including
+/// settings that require a restart) are rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub subprotocols: Option<Vec<String>>,
+    pub max_connections: Option<usize>,
+    pub max_message_size: Option<usize>,
+    pub handshake_timeout_ms: Option<u64>,
+    pub idle_timeout_ms: Option<u64>,
+    pub hold_messages: Option<usize>,
+    pub authentication: Option<Option<AuthenticationSettings>>,
+    pub allowed_origins: Option<Vec<String>>,
+    pub log_level: Option<String>,
+    #[serde(default, deserialize_with = "optional_close_code")]
+    pub close_code: Option<u16>,
+    pub close_reason: Option<String>,
+    pub close_grace_ms: Option<u64>,
+    pub shutdown_timeout_ms: Option<u64>,
+    pub slow_client: Option<Option<SlowClientPolicy>>,
+    pub send_queue_limit: Option<Option<usize>>,
+    pub rate_limit: Option<Option<RateLimitSettings>>,
+    pub event_budget: Option<Option<EventBudgetSettings>>,
+    pub utf8_policy: Option<Utf8Policy>,
+    pub sessions: Option<Option<SessionSettings>>,
+    pub network_sim: Option<Option<NetworkSimSettings>>,
+    pub json_rpc: Option<bool>,
+    pub typed_codec: Option<Option<TypedCodec>>,
+    pub wasm_filters: Option<Vec<WasmFilterSettings>>,
+    pub schemas: Option<Vec<SchemaSettings>>,
+}
+
+impl SettingsUpdate {
+    /// Parse an update from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Apply the present fields to `settings`
+    pub fn apply_to(self, settings: &mut Settings) {
+        if let Some(v) = self.subprotocols {
+            settings.subprotocols = v;
+        }
+        if let Some(v) = self.max_connections {
+            settings.max_connections = v;
+        }
+        if let Some(v) = self.max_message_size {
+            settings.max_message_size = v;
+        }
+        if let Some(v) = self.handshake_timeout_ms {
+            settings.handshake_timeout_ms = v;
+        }
+        if let Some(v) = self.idle_timeout_ms {
+            settings.idle_timeout_ms = v;
+        }
+        if let Some(v) = self.hold_messages {
+            settings.hold_messages = v;
+        }
+        if let Some(v) = self.authentication {
+            settings.authentication = v;
+        }
+        if let Some(v) = self.allowed_origins {
+            settings.allowed_origins = v;
+        }
+        if let Some(v) = self.log_level {
+            settings.log_level = Some(v);
+        }
+        if let Some(v) = self.close_code {
+            settings.close_code = v;
+        }
+        if let Some(v) = self.close_reason {
+            settings.close_reason = v;
+        }
+        if let Some(v) = self.close_grace_ms {
+            settings.close_grace_ms = v;
+        }
+        if let Some(v) = self.shutdown_timeout_ms {
+            settings.shutdown_timeout_ms = v;
+        }
+        if let Some(v) = self.slow_client {
+            settings.slow_client = v;
+        }
+        if let Some(v) = self.send_queue_limit {
+            settings.send_queue_limit = v;
+        }
+        if let Some(v) = self.rate_limit {
+            settings.rate_limit = v;
+        }
+        if let Some(v) = self.event_budget {
+            settings.event_budget = v;
+        }
+        if let Some(v) = self.utf8_policy {
+            settings.utf8_policy = v;
+        }
+        if let Some(v) = self.sessions {
+            settings.sessions = v;
+        }
+        if let Some(v) = self.network_sim {
+            settings.network_sim = v;
+        }
+        if let Some(v) = self.json_rpc {
+            settings.json_rpc = v;
+        }
+        if let Some(v) = self.typed_codec {
+            settings.typed_codec = v;
+        }
+        if let Some(v) = self.wasm_filters {
+            settings.wasm_filters = v;
+        }
+        if let Some(v) = self.schemas {
+            settings.schemas = v;
+        }
+    }
+}
+
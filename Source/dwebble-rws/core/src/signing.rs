@@ -0,0 +1,170 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! HMAC message signing
+//!
+//! Once the host sets a signing key for a connection, typically after
+//! authenticating it, every text and binary message in either direction is
+//! sent as a signed binary message (little-endian):
+//!
+//! | field   | type |
+//! |---------|------|
+//! | magic   | 4 bytes: `DWSN` |
+//! | counter | u64: counts up from 1 per direction, restarting when the key changes |
+//! | kind    | u8: 0 binary, 1 text |
+//! | payload | remaining bytes before the tag |
+//! | tag     | 32 bytes: HMAC-SHA256 of the sender's direction byte and the frame before it |
+//!
+//! The direction byte is 0 from the server and 1 from the client, so a peer
+//! cannot reflect the other side's messages back, and counters must increase,
+//! so replayed messages are rejected. Signing applies after outbound middleware
+//! and verification before inbound middleware. Messages still in flight when
+//! the key is set arrive unsigned and are rejected.
+
+use std::collections::HashMap;
+
+use ring::hmac;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::control;
+use crate::encryption::Role;
+use crate::utf8;
+
+const MAGIC: &[u8; 4] = control::SIGNED;
+const HEADER_LEN: usize = 13;
+const TAG_LEN: usize = 32;
+
+const KIND_BINARY: u8 = 0;
+const KIND_TEXT: u8 = 1;
+
+/// Byte identifying the sender of a message in its tag
+fn direction(from: Role) -> u8 {
+    match from {
+        Role::Server => 0,
+        Role::Client => 1,
+    }
+}
+
+/// Signs and verifies the messages of one connection
+pub struct Signer {
+    key: hmac::Key,
+    role: Role,
+    last_sent: u64,
+    last_received: u64,
+}
+
+impl Signer {
+    pub fn new(role: Role, key: &[u8]) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            role,
+            last_sent: 0,
+            last_received: 0,
+        }
+    }
+
+    /// Sign a text or binary message; other messages are returned unchanged
+    pub fn sign(&mut self, msg: Message) -> Message {
+        let (kind, payload): (u8, &[u8]) = match &msg {
+            Message::Binary(data) => (KIND_BINARY, data),
+            Message::Text(text) => (KIND_TEXT, text.as_bytes()),
+            _ => return msg,
+        };
+        self.last_sent += 1;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len() + TAG_LEN);
+        frame.extend_from_slice(MAGIC);
+        frame.extend_from_slice(&self.last_sent.to_le_bytes());
+        frame.push(kind);
+        frame.extend_from_slice(payload);
+        let mut context = hmac::Context::with_key(&self.key);
+        context.update(&[direction(self.role)]);
+        context.update(&frame);
+        frame.extend_from_slice(context.sign().as_ref());
+        Message::Binary(frame.into())
+    }
+
+    /// Verify a text or binary message from the peer, returning it unwrapped; other
+    /// messages are returned unchanged
+    pub fn verify(&mut self, msg: Message) -> Result<Message, String> {
+        let data = match msg {
+            Message::Binary(data) => data,
+            Message::Text(_) => return Err("unsigned text message".to_string()),
+            _ => return Ok(msg),
+        };
+        if data.len() < HEADER_LEN + TAG_LEN || &data[..4] != MAGIC {
+            return Err("unsigned binary message".to_string());
+        }
+
+        let (frame, tag) = data.split_at(data.len() - TAG_LEN);
+        let peer = match self.role {
+            Role::Server => Role::Client,
+            Role::Client => Role::Server,
+        };
+        let mut signed = Vec::with_capacity(1 + frame.len());
+        signed.push(direction(peer));
+        signed.extend_from_slice(frame);
+        hmac::verify(&self.key, &signed, tag).map_err(|_| "signature mismatch".to_string())?;
+
+        let counter = u64::from_le_bytes(frame[4..12].try_into().unwrap());
+        if counter <= self.last_received {
+            return Err(format!("replayed message (counter {})", counter));
+        }
+        self.last_received = counter;
+
+        let payload = frame[HEADER_LEN..].to_vec();
+        match frame[12] {
+            KIND_BINARY => Ok(Message::Binary(payload.into())),
+            KIND_TEXT => utf8::string(payload)
+                .map(|text| Message::Text(text.into()))
+                .ok_or_else(|| "signed text message is not valid UTF-8".to_string()),
+            _ => Err("unknown message kind".to_string()),
+        }
+    }
+}
+
+/// Signing keys of the server's connections
+#[derive(Default)]
+pub struct Signers {
+    connections: HashMap<u64, Signer>,
+}
+
+impl Signers {
+    /// Start signing a connection's messages with `key`, or stop with `None`
+    pub fn set_key(&mut self, connection_id: u64, key: Option<&[u8]>) {
+        match key {
+            Some(key) => {
+                let signer = Signer::new(Role::Server, key);
+                self.connections.insert(connection_id, signer);
+            }
+            None => {
+                self.connections.remove(&connection_id);
+            }
+        }
+    }
+
+    /// Sign a message to `connection_id` if it has a key
+    pub fn sign(&mut self, connection_id: u64, msg: Message) -> Message {
+        match self.connections.get_mut(&connection_id) {
+            Some(signer) => signer.sign(msg),
+            None => msg,
+        }
+    }
+
+    /// Verify a message from `connection_id` if it has a key
+    pub fn verify(&mut self, connection_id: u64, msg: Message) -> Result<Message, String> {
+        match self.connections.get_mut(&connection_id) {
+            Some(signer) => signer.verify(msg),
+            None => Ok(msg),
+        }
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.connections.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+}
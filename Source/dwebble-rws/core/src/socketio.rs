@@ -0,0 +1,389 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Socket.IO compatibility mode (Socket.IO protocol v5 over Engine.IO v4)
+//!
+//! Only the WebSocket transport is supported, so clients must connect with
+//! `transports: ["websocket"]`. Clients may join the main namespace (`/`) only,
+//! and events with binary attachments are not supported.
+//!
+//! Client events raise `SocketIoEvent` events with the event name and the
+//! arguments as a JSON array. Events sent with an acknowledgement callback get
+//! an ack ID (the event's request ID) that the host answers with
+//! `dwebble_rws_server_socketio_ack`. Joining the namespace raises a `connect`
+//! event whose arguments hold the client's auth payload, if any.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::server::{ServerEvent, Shared};
+use crate::session;
+use crate::settings::SocketIoSettings;
+use crate::types::{DwebbleWSEventType, DwebbleWSResult};
+
+/// Reported to clients as `maxPayload` when no message size limit is set
+/// (the WebSocket library's default limit)
+const DEFAULT_MAX_PAYLOAD: usize = 64 << 20;
+
+/// Close code sent to clients that stop answering pings
+const PING_TIMEOUT_CLOSE_CODE: u16 = 1000;
+
+/// Engine.IO packet types
+const ENGINE_OPEN: char = '0';
+const ENGINE_CLOSE: char = '1';
+const ENGINE_PING: char = '2';
+const ENGINE_PONG: char = '3';
+const ENGINE_MESSAGE: char = '4';
+
+/// Socket.IO packet types
+const CONNECT: char = '0';
+const DISCONNECT: char = '1';
+const EVENT: char = '2';
+const ACK: char = '3';
+const CONNECT_ERROR: char = '4';
+
+struct Client {
+    sid: String,
+    /// Whether the client joined the main namespace
+    connected: bool,
+    last_pong: Instant,
+}
+
+/// An event awaiting the host's acknowledgement
+struct PendingAck {
+    connection_id: u64,
+    /// The client's ack ID
+    ack_id: u64,
+}
+
+/// Socket.IO clients and pending acknowledgements
+pub struct SocketIo {
+    next_id: u64,
+    clients: HashMap<u64, Client>,
+    acks: HashMap<u64, PendingAck>,
+}
+
+impl Default for SocketIo {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            clients: HashMap::new(),
+            acks: HashMap::new(),
+        }
+    }
+}
+
+impl SocketIo {
+    /// Forget a closed connection and its pending acknowledgements
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.clients.remove(&connection_id);
+        self.acks
+            .retain(|_, ack| ack.connection_id != connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.clients.clear();
+        self.acks.clear();
+    }
+}
+
+/// A decoded Socket.IO packet
+struct Packet<'a> {
+    kind: char,
+    namespace: &'a str,
+    ack_id: Option<u64>,
+    data: &'a str,
+}
+
+/// Start the Engine.IO session of a new connection
+pub fn on_open(shared: &Shared, settings: &SocketIoSettings, connection_id: u64) {
+    let sid = session::generate_token();
+    let max_payload = match shared.settings.read().max_message_size {
+        0 => DEFAULT_MAX_PAYLOAD,
+        size => size,
+    };
+    let handshake = json!({
+        "sid": sid,
+        "upgrades": [],
+        "pingInterval": settings.ping_interval_ms,
+        "pingTimeout": settings.ping_timeout_ms,
+        "maxPayload": max_payload,
+    });
+
+    shared.socket_io.lock().clients.insert(
+        connection_id,
+        Client {
+            sid,
+            connected: false,
+            last_pong: Instant::now(),
+        },
+    );
+    send(
+        shared,
+        connection_id,
+        format!("{}{}", ENGINE_OPEN, handshake),
+    );
+}
+
+/// Handle an inbound Engine.IO packet
+pub fn on_message(shared: &Shared, connection_id: u64, text: &str) {
+    let mut chars = text.chars();
+    let Some(kind) = chars.next() else {
+        return;
+    };
+    let rest = chars.as_str();
+
+    match kind {
+        ENGINE_MESSAGE => on_packet(shared, connection_id, rest),
+        ENGINE_PONG => {
+            if let Some(client) = shared.socket_io.lock().clients.get_mut(&connection_id) {
+                client.last_pong = Instant::now();
+            }
+        }
+        // Probes and pings from older clients
+        ENGINE_PING => send(shared, connection_id, format!("{}{}", ENGINE_PONG, rest)),
+        ENGINE_CLOSE => {
+            if let Some(conn) = shared.connections.get(connection_id) {
+                conn.close();
+            }
+        }
+        _ => tracing::debug!(
+            "Ignoring Engine.IO packet from {}: {:?}",
+            connection_id,
+            text
+        ),
+    }
+}
+
+/// Send an event to a client, optionally requesting an acknowledgement with `ack_id`
+pub fn emit(
+    shared: &Shared,
+    connection_id: u64,
+    event: &str,
+    args: Vec<Value>,
+    ack_id: Option<u64>,
+) -> DwebbleWSResult {
+    let connected = shared
+        .socket_io
+        .lock()
+        .clients
+        .get(&connection_id)
+        .is_some_and(|client| client.connected);
+    if !connected {
+        return DwebbleWSResult::InvalidHandle;
+    }
+
+    let mut payload = vec![Value::String(event.to_string())];
+    payload.extend(args);
+    let ack_id = ack_id.map(|id| id.to_string()).unwrap_or_default();
+    let packet = format!(
+        "{}{}{}{}",
+        ENGINE_MESSAGE,
+        EVENT,
+        ack_id,
+        Value::Array(payload)
+    );
+    shared.send_message(connection_id, Message::Text(packet.into()))
+}
+
+/// Acknowledge a client event with the given arguments
+pub fn ack(shared: &Shared, ack: u64, args: Vec<Value>) -> DwebbleWSResult {
+    let Some(pending) = shared.socket_io.lock().acks.remove(&ack) else {
+        return DwebbleWSResult::InvalidParam;
+    };
+
+    let packet = format!(
+        "{}{}{}{}",
+        ENGINE_MESSAGE,
+        ACK,
+        pending.ack_id,
+        Value::Array(args)
+    );
+    shared.send_message(pending.connection_id, Message::Text(packet.into()))
+}
+
+/// Ping every client, closing those that stopped answering.
+/// Runs until the runtime shuts down.
+pub async fn run_heartbeat(shared: Arc<Shared>, settings: SocketIoSettings) {
+    let ping_interval = Duration::from_millis(settings.ping_interval_ms.max(1));
+    let deadline = ping_interval + Duration::from_millis(settings.ping_timeout_ms);
+    let mut interval = tokio::time::interval(ping_interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let mut alive = Vec::new();
+        let mut expired = Vec::new();
+        for (&connection_id, client) in &shared.socket_io.lock().clients {
+            if client.last_pong.elapsed() < deadline {
+                alive.push(connection_id);
+            } else {
+                expired.push(connection_id);
+            }
+        }
+
+        for connection_id in alive {
+            send(&shared, connection_id, ENGINE_PING.to_string());
+        }
+        for connection_id in expired {
+            if let Some(conn) = shared.connections.get(connection_id) {
+                tracing::info!("Socket.IO ping timeout (id: {})", connection_id);
+                conn.terminate(PING_TIMEOUT_CLOSE_CODE, "ping timeout");
+            }
+        }
+    }
+}
+
+fn on_packet(shared: &Shared, connection_id: u64, text: &str) {
+    let Some(packet) = parse(text) else {
+        tracing::debug!(
+            "Ignoring Socket.IO packet from {}: {:?}",
+            connection_id,
+            text
+        );
+        return;
+    };
+
+    if packet.kind == CONNECT {
+        if packet.namespace != "/" {
+            let error = json!({ "message": "Invalid namespace" });
+            let reply = format!(
+                "{}{}{},{}",
+                ENGINE_MESSAGE, CONNECT_ERROR, packet.namespace, error
+            );
+            send(shared, connection_id, reply);
+            return;
+        }
+        let sid = {
+            let mut socket_io = shared.socket_io.lock();
+            let Some(client) = socket_io.clients.get_mut(&connection_id) else {
+                return;
+            };
+            client.connected = true;
+            client.sid.clone()
+        };
+        let reply = format!("{}{}{}", ENGINE_MESSAGE, CONNECT, json!({ "sid": sid }));
+        send(shared, connection_id, reply);
+
+        let auth = serde_json::from_str::<Value>(packet.data).ok();
+        let args = Value::Array(auth.into_iter().collect());
+        raise(shared, connection_id, "connect".to_string(), args, 0);
+        return;
+    }
+
+    let connected = shared
+        .socket_io
+        .lock()
+        .clients
+        .get(&connection_id)
+        .is_some_and(|client| client.connected);
+    if !connected || packet.namespace != "/" {
+        return;
+    }
+
+    match packet.kind {
+        EVENT => {
+            let Ok(Value::Array(mut args)) = serde_json::from_str::<Value>(packet.data) else {
+                return;
+            };
+            if !args.first().is_some_and(Value::is_string) {
+                return;
+            }
+            let Value::String(event) = args.remove(0) else {
+                return;
+            };
+
+            let ack = packet.ack_id.map_or(0, |ack_id| {
+                let mut socket_io = shared.socket_io.lock();
+                let ack = socket_io.next_id;
+                socket_io.next_id += 1;
+                socket_io.acks.insert(
+                    ack,
+                    PendingAck {
+                        connection_id,
+                        ack_id,
+                    },
+                );
+                ack
+            });
+            raise(shared, connection_id, event, Value::Array(args), ack);
+        }
+        ACK => {
+            // Acknowledgement of an event emitted with `emit_with_ack`
+            let Some(request_id) = packet.ack_id else {
+                return;
+            };
+            if shared.requests.lock().complete(connection_id, request_id) {
+                shared.push_event(ServerEvent {
+                    event_type: DwebbleWSEventType::ResponseReceived,
+                    connection_id,
+                    data: Some(packet.data.as_bytes().to_vec()),
+                    error: None,
+                    request_id,
+                });
+            }
+        }
+        DISCONNECT => {
+            if let Some(client) = shared.socket_io.lock().clients.get_mut(&connection_id) {
+                client.connected = false;
+            }
+        }
+        _ => tracing::debug!(
+            "Unsupported Socket.IO packet from {}: {:?}",
+            connection_id,
+            text
+        ),
+    }
+}
+
+/// Decode `<type>[<namespace>,][<ack id>][<JSON>]`. Packets with binary
+/// attachments are rejected.
+fn parse(text: &str) -> Option<Packet<'_>> {
+    let mut chars = text.chars();
+    let kind = chars.next()?;
+    if !matches!(kind, CONNECT | DISCONNECT | EVENT | ACK) {
+        return None;
+    }
+    let mut rest = chars.as_str();
+
+    let mut namespace = "/";
+    if rest.starts_with('/') {
+        let (ns, after) = rest.split_once(',').unwrap_or((rest, ""));
+        namespace = ns;
+        rest = after;
+    }
+
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    let ack_id = match digits {
+        0 => None,
+        _ => Some(rest[..digits].parse().ok()?),
+    };
+
+    Some(Packet {
+        kind,
+        namespace,
+        ack_id,
+        data: &rest[digits..],
+    })
+}
+
+/// Raise the `SocketIoEvent` event of a client event
+fn raise(shared: &Shared, connection_id: u64, event: String, args: Value, ack: u64) {
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::SocketIoEvent,
+        connection_id,
+        data: Some(args.to_string().into_bytes()),
+        error: Some(event),
+        request_id: ack,
+    });
+}
+
+fn send(shared: &Shared, connection_id: u64, packet: String) {
+    shared.send_message(connection_id, Message::Text(packet.into()));
+}
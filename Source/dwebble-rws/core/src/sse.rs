@@ -0,0 +1,219 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Server-Sent Events endpoint
+//!
+//! Clients that cannot keep a WebSocket open (for example dashboards behind
+//! proxies that break upgrades) can open a read-only event stream with a plain
+//! `GET` of the configured path on the WebSocket listener. Requests to that path
+//! that ask for a WebSocket upgrade are still upgraded.
+//!
+//! Each stream is a connection like any other: it raises `ClientConnected` (with
+//! `sse` as its subprotocol) and `ClientDisconnected` events, and receives direct
+//! sends, room broadcasts and topic publishes. Rooms named by `room` query
+//! parameters are joined on connect. Text messages are sent as `message` events
+//! and binary messages as `binary` events carrying base64.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use data_encoding::BASE64;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::connection::{Connection, Queued};
+use crate::server::{ServerEvent, Shared};
+use crate::settings::SseSettings;
+use crate::types::DwebbleWSEventType;
+
+/// Subprotocol reported for event stream connections
+pub const SUBPROTOCOL: &str = "sse";
+
+/// Query parameter naming a room to join on connect (may repeat)
+const ROOM_QUERY_PARAM: &str = "room";
+
+/// Most headers parsed from a request head
+const MAX_HEADERS: usize = 64;
+
+/// An event stream request
+pub struct SseRequest {
+    origin: Option<String>,
+    rooms: Vec<String>,
+}
+
+/// Parse a request head, returning the request if it asks for the event stream
+pub fn parse_request(head: &[u8], settings: &SseSettings) -> Option<SseRequest> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    if !request.parse(head).ok()?.is_complete() || request.method != Some("GET") {
+        return None;
+    }
+
+    let (path, query) = request.path?.split_once('?').unwrap_or((request.path?, ""));
+    if path != settings.path {
+        return None;
+    }
+
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .and_then(|h| std::str::from_utf8(h.value).ok())
+    };
+    if header("Upgrade").is_some() {
+        return None;
+    }
+
+    let rooms = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, value)| *key == ROOM_QUERY_PARAM && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+        .collect();
+
+    Some(SseRequest {
+        origin: header("Origin").map(str::to_string),
+        rooms,
+    })
+}
+
+/// Serve an event stream until the client goes away or the connection is closed
+pub async fn serve<S>(
+    mut stream: S,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    request: SseRequest,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let settings = shared.settings.read().clone();
+    if let Err((status, reason)) = shared.admit(&settings, request.origin.as_deref()) {
+        let rejection = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason.len(),
+            reason
+        );
+        stream.write_all(rejection.as_bytes()).await?;
+        stream.shutdown().await?;
+        return Ok(());
+    }
+
+    let mut response = String::from(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: close\r\n\
+         X-Accel-Buffering: no\r\n",
+    );
+    if let Some(origin) = &request.origin {
+        response.push_str(&format!("Access-Control-Allow-Origin: {}\r\n", origin));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Queued>();
+    let conn = Arc::new(Connection::new(
+        addr.to_string(),
+        Some(SUBPROTOCOL.to_string()),
+        tx,
+    ));
+    let connection_id = conn.id;
+
+    shared.connections.insert(Arc::clone(&conn));
+    for room in &request.rooms {
+        shared.rooms.lock().join(room, connection_id);
+    }
+
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientConnected,
+        connection_id,
+        data: Some(SUBPROTOCOL.as_bytes().to_vec()),
+        error: None,
+        request_id: 0,
+    });
+    tracing::info!("Event stream opened: {} (id: {})", addr, connection_id);
+
+    let keep_alive_ms = settings.sse.as_ref().map_or(0, |sse| sse.keep_alive_ms);
+    let keep_alive_period = Duration::from_millis(keep_alive_ms.max(1));
+    let mut keep_alive = tokio::time::interval(keep_alive_period);
+    keep_alive.reset();
+
+    let (mut read, mut write) = tokio::io::split(stream);
+    let mut discard = [0u8; 256];
+    loop {
+        tokio::select! {
+            next = rx.recv() => {
+                let Some(queued) = next else { break };
+                let Some(msg) = conn.dequeue(queued) else { continue };
+                let len = msg.len();
+                let closing = msg.is_close();
+                let written = match encode(&msg) {
+                    Some(event) => write.write_all(event.as_bytes()).await.is_ok(),
+                    None => true,
+                };
+                conn.mark_written_len(len);
+                if closing || !written {
+                    break;
+                }
+            }
+            _ = keep_alive.tick(), if keep_alive_ms > 0 => {
+                if write.write_all(b": keep-alive\n\n").await.is_err() {
+                    break;
+                }
+            }
+            // The client has nothing more to say; reading only detects it going away
+            read = read.read(&mut discard) => {
+                if !matches!(read, Ok(len) if len > 0) {
+                    break;
+                }
+            }
+            _ = conn.terminated() => {
+                break;
+            }
+        }
+    }
+    let _ = write.shutdown().await;
+
+    shared.connections.remove(connection_id);
+    shared.forget_connection(connection_id);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientDisconnected,
+        connection_id,
+        data: None,
+        error: conn
+            .termination()
+            .map(|(_, reason)| reason)
+            .or(conn.severed()),
+        request_id: 0,
+    });
+    tracing::info!("Event stream closed: {} (id: {})", addr, connection_id);
+
+    Ok(())
+}
+
+/// Format a message as an event, or None for control messages
+fn encode(msg: &Message) -> Option<String> {
+    let (event, data) = match msg {
+        Message::Text(text) => (None, text.to_string()),
+        Message::Binary(data) => (Some("binary"), BASE64.encode(data)),
+        _ => return None,
+    };
+
+    let mut out = String::new();
+    if let Some(event) = event {
+        out.push_str(&format!("event: {}\n", event));
+    }
+    for line in data.split('\n') {
+        out.push_str("data: ");
+        out.push_str(line.strip_suffix('\r').unwrap_or(line));
+        out.push('\n');
+    }
+    out.push('\n');
+    Some(out)
+}
@@ -0,0 +1,174 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Public endpoint discovery over STUN (RFC 8489)
+//!
+//! A binding request sent to a STUN server comes back with the address the
+//! server saw it from, which behind a NAT is the public side of the mapping.
+//! The request goes out over UDP from the listener's port number where that is
+//! free, so on port-preserving NATs the reported port is the listener's too.
+
+use std::error::Error as StdError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::net::UdpSocket;
+
+/// Port STUN servers listen on when the address has none
+const DEFAULT_PORT: u16 = 3478;
+
+/// Wait before the first retransmission, doubled for each one after
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Binding requests sent before giving up
+const ATTEMPTS: u32 = 4;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+type Error = Box<dyn StdError + Send + Sync>;
+
+/// Ask `stun_server` (`host[:port]`) for the public address of `local`
+pub async fn discover(stun_server: &str, local: SocketAddr) -> Result<SocketAddr, Error> {
+    let server = resolve(stun_server).await?;
+    let socket = bind(local, server.is_ipv4()).await?;
+    socket.connect(server).await?;
+
+    let mut transaction_id = [0u8; 12];
+    SystemRandom::new()
+        .fill(&mut transaction_id)
+        .map_err(|_| "Failed to generate a STUN transaction ID")?;
+
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+
+    let mut rto = INITIAL_RTO;
+    let mut response = [0u8; 1024];
+    for _ in 0..ATTEMPTS {
+        socket.send(&request).await?;
+        let deadline = tokio::time::Instant::now() + rto;
+        // Stray datagrams on the socket are skipped until the deadline
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut response)).await
+        {
+            if let Some(addr) = parse_response(&response[..received?], &transaction_id)? {
+                return Ok(addr);
+            }
+        }
+        rto *= 2;
+    }
+    Err(format!("STUN server {} did not answer", server).into())
+}
+
+async fn resolve(stun_server: &str) -> Result<SocketAddr, Error> {
+    // A bare IPv6 address has colons but no port
+    let has_port = stun_server
+        .rsplit_once(':')
+        .is_some_and(|(host, _)| !host.contains(':') || host.ends_with(']'));
+    let addr = if has_port {
+        tokio::net::lookup_host(stun_server).await?.next()
+    } else {
+        let host = stun_server.trim_start_matches('[').trim_end_matches(']');
+        tokio::net::lookup_host((host, DEFAULT_PORT)).await?.next()
+    };
+    addr.ok_or_else(|| format!("No address found for STUN server {}", stun_server).into())
+}
+
+/// UDP socket on the listener's address and port, or any port if that is taken
+async fn bind(local: SocketAddr, ipv4: bool) -> Result<UdpSocket, Error> {
+    let ip = match local.ip() {
+        ip if !ip.is_unspecified() && ip.is_ipv4() == ipv4 => ip,
+        _ if ipv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        _ => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    match UdpSocket::bind((ip, local.port())).await {
+        Ok(socket) => Ok(socket),
+        Err(e) => {
+            tracing::debug!("UDP port {} unavailable for STUN: {}", local.port(), e);
+            Ok(UdpSocket::bind((ip, 0)).await?)
+        }
+    }
+}
+
+/// Mapped address of a binding response, `None` if the datagram is not one for
+/// this transaction
+fn parse_response(data: &[u8], transaction_id: &[u8; 12]) -> Result<Option<SocketAddr>, Error> {
+    if data.len() < 20
+        || data[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || data[8..20] != transaction_id[..]
+    {
+        return Ok(None);
+    }
+    let message_type = u16::from_be_bytes([data[0], data[1]]);
+    if message_type != BINDING_SUCCESS {
+        return Err(format!(
+            "STUN server rejected the request (type {:#06x})",
+            message_type
+        )
+        .into());
+    }
+
+    let len = usize::from(u16::from_be_bytes([data[2], data[3]]));
+    let attributes = data.get(20..20 + len).ok_or("Truncated STUN response")?;
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let len = usize::from(u16::from_be_bytes([
+            attributes[offset + 2],
+            attributes[offset + 3],
+        ]));
+        let Some(value) = attributes.get(offset + 4..offset + 4 + len) else {
+            break;
+        };
+        match kind {
+            XOR_MAPPED_ADDRESS => {
+                if let Some(addr) = decode_address(value, Some(transaction_id)) {
+                    return Ok(Some(addr));
+                }
+            }
+            // Servers predating RFC 5389 only send the plain form
+            MAPPED_ADDRESS => mapped = decode_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to 4 bytes
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    match mapped {
+        Some(addr) => Ok(Some(addr)),
+        None => Err("STUN response carries no mapped address".into()),
+    }
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS value, XORed with the cookie and transaction
+/// ID if given
+fn decode_address(value: &[u8], transaction_id: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let mut key = [0u8; 16];
+    if let Some(transaction_id) = transaction_id {
+        key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        key[4..].copy_from_slice(transaction_id);
+    }
+
+    let port = u16::from_be_bytes([value.get(2)? ^ key[0], value.get(3)? ^ key[1]]);
+    let ip = match value.get(1)? {
+        1 => {
+            let mut octets = <[u8; 4]>::try_from(value.get(4..8)?).ok()?;
+            octets.iter_mut().zip(key).for_each(|(b, k)| *b ^= k);
+            IpAddr::from(octets)
+        }
+        2 => {
+            let mut octets = <[u8; 16]>::try_from(value.get(4..20)?).ok()?;
+            octets.iter_mut().zip(key).for_each(|(b, k)| *b ^= k);
+            IpAddr::from(octets)
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,63 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! String tags labelling connections
+//!
+//! Lighter than rooms: tags are not shared with clustered instances and are
+//! looked up by scanning the tagged connections.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Tags of each tagged connection
+#[derive(Default)]
+pub struct Tags {
+    connections: HashMap<u64, BTreeSet<String>>,
+}
+
+impl Tags {
+    /// Tag a connection. Returns false if it already had the tag.
+    pub fn add(&mut self, connection_id: u64, tag: &str) -> bool {
+        self.connections
+            .entry(connection_id)
+            .or_default()
+            .insert(tag.to_string())
+    }
+
+    /// Remove a tag from a connection. Returns false if it did not have the tag.
+    pub fn remove(&mut self, connection_id: u64, tag: &str) -> bool {
+        let Some(tags) = self.connections.get_mut(&connection_id) else {
+            return false;
+        };
+        let removed = tags.remove(tag);
+        if tags.is_empty() {
+            self.connections.remove(&connection_id);
+        }
+        removed
+    }
+
+    /// Tags of a connection, sorted
+    pub fn tags(&self, connection_id: u64) -> Vec<String> {
+        self.connections
+            .get(&connection_id)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Connections with a tag
+    pub fn tagged(&self, tag: &str) -> Vec<u64> {
+        self.connections
+            .iter()
+            .filter(|(_, tags)| tags.contains(tag))
+            .map(|(connection_id, _)| *connection_id)
+            .collect()
+    }
+
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.connections.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+}
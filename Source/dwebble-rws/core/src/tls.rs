@@ -0,0 +1,238 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! TLS configuration using rustls with ring
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use data_encoding::BASE64;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::settings::{CertificateVerification, ClientTlsSettings};
+
+/// TLS configuration for the server
+pub struct TlsConfig {
+    pub acceptor: TlsAcceptor,
+    /// The rustls configuration behind `acceptor`, for listeners with their own ALPN
+    pub server_config: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Create TLS config from certificate and private key PEM files
+    pub fn from_pem_files(cert_path: &str, key_path: &str) -> Result<Self, TlsError> {
+        let certs = load_certs(cert_path)?;
+        let key = load_private_key(key_path)?;
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| TlsError::Config(e.to_string()))?;
+
+        let server_config = Arc::new(config);
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::clone(&server_config)),
+            server_config,
+        })
+    }
+
+    /// An acceptor for the same certificate that offers HTTP/2 ahead of HTTP/1.1 over ALPN
+    pub fn http2_acceptor(&self) -> TlsAcceptor {
+        let mut config = (*self.server_config).clone();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+/// Connector for outgoing TLS connections, trusting the Mozilla root certificates
+pub fn connector() -> TlsConnector {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Client configuration verifying servers as `settings` choose
+pub fn client_config(settings: &ClientTlsSettings) -> Result<ClientConfig, TlsError> {
+    let mut roots = RootCertStore::empty();
+    match settings.verification {
+        CertificateVerification::MozillaRoots => {
+            roots.roots = webpki_roots::TLS_SERVER_ROOTS.to_vec();
+        }
+        CertificateVerification::SystemRoots => {
+            let native = rustls_native_certs::load_native_certs();
+            for e in &native.errors {
+                tracing::warn!("Failed to load a system root certificate: {}", e);
+            }
+            let (_, ignored) = roots.add_parsable_certificates(native.certs);
+            if ignored > 0 {
+                tracing::warn!("Ignored {} unparsable system root certificates", ignored);
+            }
+            if roots.is_empty() {
+                return Err(TlsError::CertLoad("No system root certificate".to_string()));
+            }
+        }
+        CertificateVerification::CaBundle => {
+            let certs = rustls_pemfile::certs(&mut settings.ca_pem.as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| TlsError::CertLoad(e.to_string()))?;
+            for cert in certs {
+                roots.add(cert).map_err(|e| TlsError::CertLoad(e.to_string()))?;
+            }
+            if roots.is_empty() {
+                return Err(TlsError::CertLoad("No certificate in the CA bundle".to_string()));
+            }
+        }
+        CertificateVerification::Pinned | CertificateVerification::Disabled => {
+            let pins = match settings.verification {
+                CertificateVerification::Pinned => pins(&settings.spki_pins)?,
+                _ => Vec::new(),
+            };
+            let verifier = UnchainedVerifier {
+                pins,
+                algorithms: crypto::ring::default_provider().signature_verification_algorithms,
+            };
+            return Ok(ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth());
+        }
+    }
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Decode base64 SHA-256 hashes, refusing an empty list that would pin nothing
+fn pins(encoded: &[String]) -> Result<Vec<Vec<u8>>, TlsError> {
+    if encoded.is_empty() {
+        return Err(TlsError::Config("No SPKI pin".to_string()));
+    }
+    encoded
+        .iter()
+        .map(|pin| match BASE64.decode(pin.as_bytes()) {
+            Ok(hash) if hash.len() == ring::digest::SHA256_OUTPUT_LEN => Ok(hash),
+            _ => Err(TlsError::Config(format!("Invalid SPKI pin: {}", pin))),
+        })
+        .collect()
+}
+
+/// Verifier that ignores the certificate chain. With pins, the server's certificate
+/// must carry one of the pinned public keys; without, any certificate goes. The
+/// handshake signatures are still checked, so the server holds the key it presents.
+#[derive(Debug)]
+struct UnchainedVerifier {
+    /// SHA-256 hashes of the accepted DER SubjectPublicKeyInfo; empty accepts any
+    pins: Vec<Vec<u8>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for UnchainedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pins.is_empty() {
+            return Ok(ServerCertVerified::assertion());
+        }
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|e| rustls::Error::General(format!("Unparsable certificate: {}", e)))?;
+        let spki = cert.subject_public_key_info();
+        let hash = ring::digest::digest(&ring::digest::SHA256, spki.as_ref());
+        if self.pins.iter().any(|pin| pin.as_slice() == hash.as_ref()) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("Certificate matches no SPKI pin".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Load certificates from a PEM file
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::CertLoad(e.to_string()))?;
+    let mut reader = BufReader::new(file);
+
+    rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::CertLoad(e.to_string()))
+}
+
+/// Load private key from a PEM file
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::KeyLoad(e.to_string()))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| TlsError::KeyLoad(e.to_string()))? {
+            Some(rustls_pemfile::Item::Pkcs1Key(key)) => {
+                return Ok(PrivateKeyDer::Pkcs1(key));
+            }
+            Some(rustls_pemfile::Item::Pkcs8Key(key)) => {
+                return Ok(PrivateKeyDer::Pkcs8(key));
+            }
+            Some(rustls_pemfile::Item::Sec1Key(key)) => {
+                return Ok(PrivateKeyDer::Sec1(key));
+            }
+            None => break,
+            _ => continue,
+        }
+    }
+
+    Err(TlsError::KeyLoad(
+        "No private key found in file".to_string(),
+    ))
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    CertLoad(String),
+    KeyLoad(String),
+    Config(String),
+}
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::CertLoad(e) => write!(f, "Failed to load certificate: {}", e),
+            TlsError::KeyLoad(e) => write!(f, "Failed to load private key: {}", e),
+            TlsError::Config(e) => write!(f, "TLS configuration error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
@@ -0,0 +1,117 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Hierarchical pub/sub topics
+//!
+//! Topics are dot-separated segments (`match.123.score`). Subscription patterns
+//! may use `*` to match exactly one segment, and `>` as the last segment to
+//! match one or more remaining segments (`match.>`).
+
+use std::collections::{HashMap, HashSet};
+
+/// Subscriber ID of the host application (connection IDs start at 1)
+pub const HOST: u64 = 0;
+
+/// Topic subscriptions, indexed both ways
+#[derive(Default)]
+pub struct Topics {
+    subscribers: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<u64, HashSet<String>>,
+}
+
+impl Topics {
+    /// Subscribe to a pattern. Returns false if already subscribed.
+    pub fn subscribe(&mut self, subscriber: u64, pattern: &str) -> bool {
+        let added = self
+            .subscribers
+            .entry(pattern.to_string())
+            .or_default()
+            .insert(subscriber);
+        if added {
+            self.patterns
+                .entry(subscriber)
+                .or_default()
+                .insert(pattern.to_string());
+        }
+        added
+    }
+
+    /// Unsubscribe from a pattern. Returns false if not subscribed.
+    pub fn unsubscribe(&mut self, subscriber: u64, pattern: &str) -> bool {
+        let Some(subscribers) = self.subscribers.get_mut(pattern) else {
+            return false;
+        };
+        if !subscribers.remove(&subscriber) {
+            return false;
+        }
+        if subscribers.is_empty() {
+            self.subscribers.remove(pattern);
+        }
+        if let Some(patterns) = self.patterns.get_mut(&subscriber) {
+            patterns.remove(pattern);
+            if patterns.is_empty() {
+                self.patterns.remove(&subscriber);
+            }
+        }
+        true
+    }
+
+    /// Remove every subscription of a subscriber
+    pub fn unsubscribe_all(&mut self, subscriber: u64) {
+        for pattern in self.patterns.remove(&subscriber).unwrap_or_default() {
+            if let Some(subscribers) = self.subscribers.get_mut(&pattern) {
+                subscribers.remove(&subscriber);
+                if subscribers.is_empty() {
+                    self.subscribers.remove(&pattern);
+                }
+            }
+        }
+    }
+
+    /// Subscribers with at least one pattern matching `topic`
+    pub fn subscribers(&self, topic: &str) -> Vec<u64> {
+        let mut matched = HashSet::new();
+        for (pattern, subscribers) in &self.subscribers {
+            if matches(pattern, topic) {
+                matched.extend(subscribers.iter().copied());
+            }
+        }
+        matched.into_iter().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.subscribers.clear();
+        self.patterns.clear();
+    }
+}
+
+/// A topic to publish to: non-empty segments without wildcards
+pub fn is_valid_topic(topic: &str) -> bool {
+    topic
+        .split('.')
+        .all(|segment| !segment.is_empty() && segment != "*" && segment != ">")
+}
+
+/// A subscription pattern: non-empty segments, `>` only as the last one
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let segments: Vec<&str> = pattern.split('.').collect();
+    segments
+        .iter()
+        .enumerate()
+        .all(|(i, segment)| !segment.is_empty() && (*segment != ">" || i == segments.len() - 1))
+}
+
+fn matches(pattern: &str, topic: &str) -> bool {
+    let mut topic_segments = topic.split('.');
+    for segment in pattern.split('.') {
+        if segment == ">" {
+            return topic_segments.next().is_some();
+        }
+        match topic_segments.next() {
+            Some(topic_segment) if segment == "*" || segment == topic_segment => {}
+            _ => return false,
+        }
+    }
+    topic_segments.next().is_none()
+}
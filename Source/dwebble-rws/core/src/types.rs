@@ -0,0 +1,455 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Types shared between Rust and C++
+//!
+//! Plain enums and structs of the safe API, passed across the C ABI as they are.
+
+/// Result codes of server and client operations
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwebbleWSResult {
+    Ok = 0,
+    InvalidHandle = 1,
+    InvalidParam = 2,
+    AlreadyRunning = 3,
+    NotRunning = 4,
+    BindFailed = 5,
+    TlsError = 6,
+    RuntimeError = 7,
+    SendFailed = 8,
+    /// The connection existed but has closed, or its socket is closing
+    ConnectionClosed = 9,
+    /// The message would take the connection's send queue over `send_queue_limit`
+    QueueFull = 10,
+    /// Text was not valid UTF-8 under the strict `utf8_policy`
+    InvalidUtf8 = 11,
+    /// Another thread is starting, rebinding or stopping the server
+    Busy = 12,
+}
+
+/// WebSocket event types for polling
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwebbleWSEventType {
+    None = 0,
+    ClientConnected = 1,
+    ClientDisconnected = 2,
+    MessageReceived = 3,
+    Error = 4,
+    /// The socket dropped but the session is kept for a reconnect
+    SessionSuspended = 5,
+    /// A client reconnected to a suspended session (same connection ID)
+    SessionResumed = 6,
+    /// A logical user came online (data: user ID)
+    PresenceJoined = 7,
+    /// A logical user went offline on every instance (data: user ID)
+    PresenceLeft = 8,
+    /// A message was published to a topic the host subscribed to
+    /// (data: payload, error message: topic)
+    TopicMessage = 9,
+    /// A client answered a request (data: response payload)
+    ResponseReceived = 10,
+    /// A request got no response in time, or its connection closed first
+    RequestTimedOut = 11,
+    /// A client sent a JSON-RPC request or notification (data: params JSON, empty
+    /// if absent; error message: method; request ID: call ID, 0 for notifications)
+    RpcCall = 12,
+    /// A client sent a typed envelope (data: payload; request ID: message type ID)
+    TypedMessage = 13,
+    /// A Socket.IO client emitted an event (data: arguments as a JSON array;
+    /// error message: event name; request ID: ack ID, 0 if no ack was requested)
+    SocketIoEvent = 14,
+    /// An MQTT client published a message (data: payload, error message: topic)
+    MqttPublish = 15,
+    /// A WebTransport client sent a datagram (data: payload)
+    DatagramReceived = 16,
+    /// The gateway forwards a port to the server (data: external address as `ip:port`)
+    PortMapped = 17,
+    /// A STUN server reported the server's public address (data: `ip:port`)
+    PublicEndpoint = 18,
+    /// A message arrived on a virtual channel (data: payload; request ID: channel ID)
+    ChannelMessage = 19,
+    /// A loopback client applied a state sync update (data: the whole state; request ID: key)
+    StateUpdated = 20,
+    /// A WASM filter rejected an inbound message (data: the message; error message:
+    /// filter path; request ID: the filter's code, -1 if it failed, sign-extended)
+    MessageRejected = 21,
+    /// An inbound message did not match its subprotocol's schema (data: the message;
+    /// error message: what failed; request ID: 1 if it was delivered anyway, else 0)
+    ValidationFailed = 22,
+    /// A message failed HMAC verification and was dropped (data: the message;
+    /// error message: why)
+    SignatureInvalid = 23,
+    /// A message was stale or replayed and was dropped (data: the message; error
+    /// message: why)
+    ReplayRejected = 24,
+    /// The server stopped and its runtime shut down, the last event of a run (data:
+    /// `{"connections": N, "force_closed": M}` as in `DwebbleWSStopStats`)
+    ServerStopped = 25,
+    /// A client failed the TLS handshake, e.g. over a certificate, SNI or protocol
+    /// version mismatch (data: remote address as `ip:port`; error message: why)
+    TlsHandshakeFailed = 26,
+    /// A client's WebSocket handshake was refused or failed (data:
+    /// `{"remote_addr": "ip:port", "path": P, "header": H}`, path and header null if
+    /// unknown; error message: why)
+    HandshakeFailed = 27,
+    /// A connection sent messages faster than the `rate_limit` setting allows (data:
+    /// the message if it was dropped; error message: the exceeded limit, e.g.
+    /// `messages_per_sec`; request ID: 0 if reading was delayed, 1 if the message was
+    /// dropped, 2 if the connection was closed)
+    RateLimited = 28,
+    /// Message events were dropped over the `event_budget` setting or the
+    /// `event_overflow_limit` setting, raised at most every 100ms (request ID: the
+    /// number dropped since the last such event)
+    EventsDropped = 29,
+    /// A connection's encryption keys were rotated (request ID: the number of rotations
+    /// of the connection so far)
+    KeyRotated = 30,
+    /// A scheduled shutdown came due and closed the connections; stop the server now
+    /// (request ID: the number of connections closed)
+    ShutdownDue = 31,
+    /// The watchdog found a stall (data: `runtime`, `writer` or `event_queue`; error
+    /// message: a description; request ID: how long it has lasted, in milliseconds)
+    HealthWarning = 32,
+    /// Resource usage at the `resource_report_ms` interval (data: JSON with the fields
+    /// of `DwebbleWSResourceUsage`)
+    ResourceReport = 33,
+    /// A client's offline queue was full and dropped its oldest message (data: the
+    /// message; request ID: the number the client has dropped so far)
+    MessageDropped = 34,
+    /// A binary message's checksum trailer was missing or did not match, with the
+    /// `checksum` setting's `verify_trailer`; the message was dropped (data: the
+    /// message as received; error: what didn't match)
+    ChecksumMismatch = 35,
+}
+
+impl DwebbleWSEventType {
+    /// Convert a raw discriminant back into an event type (`None` if unknown)
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::ClientConnected,
+            2 => Self::ClientDisconnected,
+            3 => Self::MessageReceived,
+            4 => Self::Error,
+            5 => Self::SessionSuspended,
+            6 => Self::SessionResumed,
+            7 => Self::PresenceJoined,
+            8 => Self::PresenceLeft,
+            9 => Self::TopicMessage,
+            10 => Self::ResponseReceived,
+            11 => Self::RequestTimedOut,
+            12 => Self::RpcCall,
+            13 => Self::TypedMessage,
+            14 => Self::SocketIoEvent,
+            15 => Self::MqttPublish,
+            16 => Self::DatagramReceived,
+            17 => Self::PortMapped,
+            18 => Self::PublicEndpoint,
+            19 => Self::ChannelMessage,
+            20 => Self::StateUpdated,
+            21 => Self::MessageRejected,
+            22 => Self::ValidationFailed,
+            23 => Self::SignatureInvalid,
+            24 => Self::ReplayRejected,
+            25 => Self::ServerStopped,
+            26 => Self::TlsHandshakeFailed,
+            27 => Self::HandshakeFailed,
+            28 => Self::RateLimited,
+            29 => Self::EventsDropped,
+            30 => Self::KeyRotated,
+            31 => Self::ShutdownDue,
+            32 => Self::HealthWarning,
+            33 => Self::ResourceReport,
+            34 => Self::MessageDropped,
+            35 => Self::ChecksumMismatch,
+            _ => Self::None,
+        }
+    }
+
+    /// Whether the event carries a message from a client, and counts against the
+    /// `event_budget` setting
+    pub fn is_message(self) -> bool {
+        matches!(
+            self,
+            Self::MessageReceived
+                | Self::TopicMessage
+                | Self::RpcCall
+                | Self::TypedMessage
+                | Self::SocketIoEvent
+                | Self::MqttPublish
+                | Self::DatagramReceived
+                | Self::ChannelMessage
+        )
+    }
+}
+
+/// Aggregate statistics of a load test
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSLoadTestStats {
+    /// Time since the load test started
+    pub elapsed_ms: u64,
+    /// Clients currently connected
+    pub active_clients: u64,
+    /// Clients that failed to connect
+    pub connect_failures: u64,
+    /// Clients whose connection ended
+    pub disconnects: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Average messages sent per second since the start
+    pub send_rate: f64,
+    /// Average messages received per second since the start
+    pub receive_rate: f64,
+    /// Number of echoed messages the latency figures are based on
+    pub latency_samples: u64,
+    pub latency_min_us: u64,
+    pub latency_avg_us: u64,
+    pub latency_max_us: u64,
+}
+
+/// Statistics of one virtual channel of a connection
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSChannelStats {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Payload bytes, excluding the channel envelope
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Sent messages the peer acknowledged
+    pub messages_acked: u64,
+    /// Reliable messages awaiting acknowledgement
+    pub messages_unacked: u64,
+    /// Reliable messages sent again after a session resumed
+    pub messages_retransmitted: u64,
+    /// Reliable messages given up on because too many were unacknowledged
+    pub messages_abandoned: u64,
+    /// Sequenced messages dropped for arriving after a newer one
+    pub stale_dropped: u64,
+    /// Reliable messages dropped for arriving again
+    pub duplicates_dropped: u64,
+    /// Smoothed acknowledgement round-trip time (0 until the first acknowledgement)
+    pub rtt_us: u64,
+}
+
+/// Compression of a connection's messages
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DwebbleWSCompression {
+    /// Messages go out as they are
+    #[default]
+    None = 0,
+    /// Messages are compressed with zstd, asked for with the `zstd` setting's
+    /// subprotocol suffix
+    Zstd = 1,
+}
+
+/// What a client trusts its server's certificate by
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DwebbleWSCertificateVerification {
+    /// The connection doesn't use TLS
+    #[default]
+    Plaintext = 0,
+    /// A chain to the Mozilla root certificates built into the library
+    MozillaRoots = 1,
+    /// A chain to the root certificates of the operating system's store
+    SystemRoots = 2,
+    /// A chain to the CAs of a bundle the host passed
+    CaBundle = 3,
+    /// A pinned public key
+    Pinned = 4,
+    /// Nothing: verification is disabled
+    Disabled = 5,
+}
+
+/// Statistics of a service pool
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSServicePoolStats {
+    /// Connections of the pool, open or not
+    pub connections: u32,
+    /// Connections open and answering health checks, which requests go out on
+    pub healthy_connections: u32,
+    /// Requests awaiting a response
+    pub pending_requests: u64,
+}
+
+/// Information about a client connection
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSClientInfo {
+    /// What the server's certificate is trusted by
+    pub certificate_verification: DwebbleWSCertificateVerification,
+}
+
+/// Information about a connection
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSConnectionInfo {
+    /// Whether messages are protected by application-layer encryption
+    pub encrypted: bool,
+    /// Times the connection's encryption keys were rotated
+    pub key_rotations: u64,
+    /// JA3 hash of the client's TLS ClientHello, all zero without TLS
+    pub tls_fingerprint: [u8; 16],
+    /// Hash of the order of the handshake's request header names
+    pub header_fingerprint: u64,
+    /// Bytes queued but not yet written to the socket
+    pub queued_bytes: u64,
+    /// Messages sent with a TTL and dropped from the send queue once it ran out
+    pub expired_messages: u64,
+    /// Compression negotiated for the connection's WebSocket messages
+    pub compression: DwebbleWSCompression,
+    /// Compression level (0 without compression)
+    pub compression_level: i32,
+    /// ID of the compression dictionary (0 without one, or for a raw content dictionary)
+    pub dictionary_id: u32,
+    /// Payload bytes of WebSocket data messages sent, before compression
+    pub raw_bytes_sent: u64,
+    /// Payload bytes of WebSocket data messages sent, after compression
+    pub compressed_bytes_sent: u64,
+    /// Payload bytes of WebSocket data messages received, before decompression
+    pub compressed_bytes_received: u64,
+    /// Payload bytes of WebSocket data messages received, after decompression
+    pub raw_bytes_received: u64,
+    /// Whether a protocol version was negotiated under the `protocol_version` setting
+    pub version_negotiated: bool,
+    /// Major version of the negotiated protocol (0 without one)
+    pub version_major: u16,
+    /// Minor version of the negotiated protocol (0 without one)
+    pub version_minor: u16,
+}
+
+/// Statistics of the connections with a tag
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSTagStats {
+    /// Live connections with the tag
+    pub connections: u64,
+    /// Suspended sessions with the tag, awaiting a reconnect
+    pub suspended: u64,
+    /// Bytes queued but not yet written to the tagged connections' sockets
+    pub queued_bytes: u64,
+}
+
+/// Statistics of the pool of event payload buffers, shared by every server and client
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSPoolStats {
+    /// Payloads copied into a reused buffer
+    pub hits: u64,
+    /// Payloads copied into a newly allocated buffer
+    pub misses: u64,
+    /// Payloads too large to pool
+    pub unpooled: u64,
+    /// Buffers waiting in the pool
+    pub free_buffers: u64,
+    /// Capacity of the buffers waiting in the pool, in bytes
+    pub free_bytes: u64,
+}
+
+/// Resource usage of a server. Memory is approximated by the bytes held in its
+/// queues and buffers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSResourceUsage {
+    /// Worker threads of the runtime (0 while stopped or in `single-thread` builds)
+    pub worker_threads: u64,
+    /// Other threads of the server, such as the watchdog's
+    pub helper_threads: u64,
+    /// Tasks alive on the runtime
+    pub tasks: u64,
+    /// Tasks waiting in the runtime's global queue
+    pub queued_tasks: u64,
+    /// Time the workers have spent busy since the server started, in milliseconds
+    pub busy_ms: u64,
+    pub connections: u64,
+    /// Bytes queued to send, over all connections
+    pub send_queue_bytes: u64,
+    /// Bytes buffered for suspended sessions
+    pub session_buffer_bytes: u64,
+    /// Events on the server's queue not yet polled
+    pub queued_events: u64,
+    /// Data and error message bytes of those events
+    pub event_queue_bytes: u64,
+}
+
+/// What a middleware callback does with a message
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwebbleWSMiddlewareAction {
+    /// Hand the message on unchanged
+    Pass = 0,
+    /// Hand `replacement` on instead
+    Modify = 1,
+    /// Discard the message, skipping the rest of the chain
+    Drop = 2,
+}
+
+/// Connections closed by stopping a server
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwebbleWSStopStats {
+    /// Connections open when the server was asked to stop
+    pub connections: u64,
+    /// Connections still open after `close_grace_ms`, dropped without a closing handshake
+    pub force_closed: u64,
+}
+
+/// A stall the watchdog warns of
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwebbleWSHealthIssue {
+    /// The runtime has not run a task spawned on it
+    Runtime = 0,
+    /// A connection's writer has not written its queued messages
+    Writer = 1,
+    /// Events on the server's queue have not been polled
+    EventQueue = 2,
+}
+
+impl DwebbleWSHealthIssue {
+    /// Name of the issue in `HealthWarning` events
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Runtime => "runtime",
+            Self::Writer => "writer",
+            Self::EventQueue => "event_queue",
+        }
+    }
+}
+
+/// Where a server is in its lifecycle
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwebbleWSServerState {
+    /// Created and never started
+    Created = 0,
+    /// Binding its listeners and starting its runtime
+    Starting = 1,
+    /// Accepting connections
+    Running = 2,
+    /// Closing its connections and shutting its runtime down
+    Draining = 3,
+    /// Stopped after running, and may be started again
+    Stopped = 4,
+}
+
+impl DwebbleWSServerState {
+    /// Convert a raw discriminant back into a state (`Created` if unknown)
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Starting,
+            2 => Self::Running,
+            3 => Self::Draining,
+            4 => Self::Stopped,
+            _ => Self::Created,
+        }
+    }
+}
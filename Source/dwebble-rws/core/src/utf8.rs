@@ -0,0 +1,43 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Text that may not be valid UTF-8
+//!
+//! Text the host sends arrives as C strings and text read off WebRTC data channels
+//! as raw bytes, neither checked for UTF-8. Text frames from clients are checked as
+//! they are read (see `frames`), but text that was compressed, encrypted, signed or
+//! relayed by a sibling instance is checked again here once it is unwrapped.
+//!
+//! Checking uses simdutf8, which picks AVX2, SSE4.2 or NEON at runtime and falls
+//! back to the standard library's check on other CPUs.
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::settings::Utf8Policy;
+
+/// Make a text message of `text` under `policy`. Returns `None` if the policy rejects
+/// it.
+pub fn message(policy: Utf8Policy, text: &[u8]) -> Option<Message> {
+    if let Some(text) = str(text) {
+        return Some(Message::Text(text.into()));
+    }
+    match policy {
+        Utf8Policy::Strict => None,
+        Utf8Policy::Lossy => Some(Message::Text(
+            String::from_utf8_lossy(text).into_owned().into(),
+        )),
+        Utf8Policy::Binary => Some(Message::Binary(text.to_vec().into())),
+    }
+}
+
+/// `bytes` as text, if they are UTF-8
+pub fn str(bytes: &[u8]) -> Option<&str> {
+    simdutf8::basic::from_utf8(bytes).ok()
+}
+
+/// `bytes` as a string without copying them, if they are UTF-8
+pub fn string(bytes: Vec<u8>) -> Option<String> {
+    str(&bytes)?;
+    Some(unsafe { String::from_utf8_unchecked(bytes) })
+}
@@ -0,0 +1,111 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Protocol version handshake
+//!
+//! With the `protocol_version` setting, the first message of an accepted connection
+//! may be a `DWVN` frame offering the client's version: the major and minor version
+//! as little-endian u16s. A compatible offer (same major, minor at least
+//! `min_minor`) is answered with a `DWVN` frame carrying the negotiated version,
+//! the lower of both minors; an incompatible one closes the connection with the
+//! configured close code. The library's client makes its offer as soon as it
+//! connects. Connections the server dialed don't negotiate.
+
+use std::fmt;
+
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::control;
+use crate::server::Shared;
+use crate::settings::ProtocolVersionSettings;
+
+/// A protocol version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The `DWVN` frame of a version
+pub fn frame(version: Version) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(control::VERSION.len() + 4);
+    frame.extend_from_slice(control::VERSION);
+    frame.extend_from_slice(&version.major.to_le_bytes());
+    frame.extend_from_slice(&version.minor.to_le_bytes());
+    frame
+}
+
+/// The version of a `DWVN` frame
+fn parse(data: &[u8]) -> Option<Version> {
+    let body = data.strip_prefix(control::VERSION)?;
+    let [major_lo, major_hi, minor_lo, minor_hi] = *body else {
+        return None;
+    };
+    Some(Version {
+        major: u16::from_le_bytes([major_lo, major_hi]),
+        minor: u16::from_le_bytes([minor_lo, minor_hi]),
+    })
+}
+
+/// The version both sides speak, or why the offered one is incompatible
+fn negotiate(settings: &ProtocolVersionSettings, offered: Version) -> Result<Version, String> {
+    if offered.major != settings.major || offered.minor < settings.min_minor {
+        return Err(format!(
+            "Unsupported protocol version {} (supported: {}.{} to {}.{})",
+            offered, settings.major, settings.min_minor, settings.major, settings.minor
+        ));
+    }
+    Ok(Version {
+        major: offered.major,
+        minor: offered.minor.min(settings.minor),
+    })
+}
+
+/// Take a connection's first message as its version offer if it awaits one. Returns
+/// the message if it goes on to the host.
+pub(crate) fn check(shared: &Shared, connection_id: u64, msg: Message) -> Option<Message> {
+    let Some(settings) = shared.settings.read().protocol_version else {
+        return Some(msg);
+    };
+    if !(msg.is_binary() || msg.is_text()) {
+        return Some(msg);
+    }
+    let Some(conn) = shared.connections.get(connection_id) else {
+        return Some(msg);
+    };
+    if !conn.take_version_offer() {
+        return Some(msg);
+    }
+
+    let offered = match &msg {
+        Message::Binary(data) => parse(data),
+        _ => None,
+    };
+    let Some(offered) = offered else {
+        if settings.required {
+            tracing::warn!("No protocol version offered by connection {}", connection_id);
+            conn.terminate(settings.close_code, "No protocol version offered");
+            return None;
+        }
+        return Some(msg);
+    };
+    match negotiate(&settings, offered) {
+        Ok(version) => {
+            tracing::debug!("Connection {} negotiated protocol version {}", connection_id, version);
+            conn.set_version(version);
+            shared.send_control(connection_id, frame(version));
+        }
+        Err(e) => {
+            tracing::warn!("Connection {}: {}", connection_id, e);
+            conn.terminate(settings.close_code, &e);
+        }
+    }
+    None
+}
@@ -0,0 +1,194 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebAssembly message filters
+//!
+//! Filters are WebAssembly modules run on inbound messages by the connection's
+//! task, off the game thread. A module gets no imports and exports its
+//! `memory` and:
+//!
+//! | export   | signature                                  | |
+//! |----------|--------------------------------------------|-|
+//! | `alloc`  | `(len: i32) -> i32`                        | address of `len` writable bytes for the message |
+//! | `filter` | `(ptr: i32, len: i32, is_text: i32) -> i32` | 0 to accept, any other code to reject |
+//!
+//! Both are called once per message. An accepted message is read back from the
+//! module's memory, so filters may rewrite it in place (e.g. masking words).
+//! A trap, including running out of fuel, rejects the message.
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+use wasmi::{Config, Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::WasmFilterSettings;
+use crate::types::DwebbleWSEventType;
+
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    filter: TypedFunc<(i32, i32, i32), i32>,
+}
+
+/// A loaded filter module
+pub struct Filter {
+    settings: WasmFilterSettings,
+    /// Calls are serialized, as an instance runs one at a time
+    instance: Mutex<Instance>,
+}
+
+enum Verdict {
+    /// Accepted, with the content if the filter changed it
+    Accept(Option<Vec<u8>>),
+    Reject(i32),
+    Failed(String),
+}
+
+impl Filter {
+    pub fn load(settings: &WasmFilterSettings) -> Result<Self, String> {
+        let wasm = std::fs::read(&settings.path).map_err(|e| e.to_string())?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm[..]).map_err(|e| e.to_string())?;
+
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(settings.fuel).map_err(|e| e.to_string())?;
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| e.to_string())?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("no `memory` export")?;
+        let alloc = instance
+            .get_typed_func(&store, "alloc")
+            .map_err(|e| format!("`alloc`: {}", e))?;
+        let filter = instance
+            .get_typed_func(&store, "filter")
+            .map_err(|e| format!("`filter`: {}", e))?;
+
+        Ok(Self {
+            settings: settings.clone(),
+            instance: Mutex::new(Instance {
+                store,
+                memory,
+                alloc,
+                filter,
+            }),
+        })
+    }
+
+    fn run(&self, data: &[u8], is_text: bool) -> Verdict {
+        let mut instance = self.instance.lock();
+        let Instance {
+            store,
+            memory,
+            alloc,
+            filter,
+        } = &mut *instance;
+
+        let result = (|| {
+            let len = i32::try_from(data.len()).map_err(|_| "message too large".to_string())?;
+            store
+                .set_fuel(self.settings.fuel)
+                .map_err(|e| e.to_string())?;
+
+            let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+            let offset = ptr as u32 as usize;
+            memory
+                .write(&mut *store, offset, data)
+                .map_err(|e| format!("`alloc` returned an invalid address: {}", e))?;
+
+            let code = filter
+                .call(&mut *store, (ptr, len, i32::from(is_text)))
+                .map_err(|e| e.to_string())?;
+            if code != 0 {
+                return Ok(Verdict::Reject(code));
+            }
+
+            let mut filtered = vec![0; data.len()];
+            memory
+                .read(&*store, offset, &mut filtered)
+                .map_err(|e| e.to_string())?;
+            Ok(Verdict::Accept((filtered != data).then_some(filtered)))
+        })();
+        result.unwrap_or_else(Verdict::Failed)
+    }
+
+    /// Whether the filter applies to a connection, by the rooms it joined
+    fn applies_to(&self, shared: &Shared, connection_id: u64) -> bool {
+        if self.settings.rooms.is_empty() {
+            return true;
+        }
+        let rooms = shared.rooms.lock();
+        self.settings
+            .rooms
+            .iter()
+            .any(|room| rooms.contains(room, connection_id))
+    }
+}
+
+/// Load the modules of every configured filter
+pub fn load_all(settings: &[WasmFilterSettings]) -> Result<Vec<Arc<Filter>>, String> {
+    settings
+        .iter()
+        .map(|filter| {
+            Filter::load(filter)
+                .map(Arc::new)
+                .map_err(|e| format!("Failed to load WASM filter '{}': {}", filter.path, e))
+        })
+        .collect()
+}
+
+/// Run an inbound message through the filters of its connection. Returns `None`
+/// if one rejected it, raising `MessageRejected`.
+pub(crate) fn filter(shared: &Shared, connection_id: u64, mut msg: Message) -> Option<Message> {
+    let filters = shared.wasm_filters.read().clone();
+    for filter in filters {
+        if !filter.applies_to(shared, connection_id) {
+            continue;
+        }
+
+        let (data, is_text): (&[u8], bool) = match &msg {
+            Message::Text(text) => (text.as_bytes(), true),
+            Message::Binary(data) => (data, false),
+            _ => return Some(msg),
+        };
+        let code = match filter.run(data, is_text) {
+            Verdict::Accept(None) => continue,
+            Verdict::Accept(Some(filtered)) => {
+                msg = if is_text {
+                    match String::from_utf8(filtered) {
+                        Ok(text) => Message::Text(text.into()),
+                        Err(e) => Message::Binary(e.into_bytes().into()),
+                    }
+                } else {
+                    Message::Binary(filtered.into())
+                };
+                continue;
+            }
+            Verdict::Reject(code) => code,
+            Verdict::Failed(e) => {
+                tracing::warn!("WASM filter '{}' failed: {}", filter.settings.path, e);
+                -1
+            }
+        };
+
+        shared.push_event(ServerEvent {
+            event_type: DwebbleWSEventType::MessageRejected,
+            connection_id,
+            data: Some(data.to_vec()),
+            error: Some(filter.settings.path.clone()),
+            request_id: i64::from(code) as u64,
+        });
+        return None;
+    }
+    Some(msg)
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Health watchdog
+//!
+//! With the `watchdog` setting, a thread of its own checks a running server for
+//! three kinds of stall, each warned of once until it clears:
+//!
+//! - the runtime not running a task spawned on it, e.g. when a task blocks its
+//!   workers
+//! - a connection's writer not writing while it has messages queued, e.g. when a
+//!   client stops reading
+//! - events waiting on the server's queue while the host hasn't polled it, e.g.
+//!   when the game thread hangs
+//!
+//! Each warning raises a `HealthWarning` event and calls the health callback, if
+//! one is set, from the watchdog thread. The callback still reaches the host when
+//! the event queue is the one stalled.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::WatchdogSettings;
+use crate::types::{DwebbleWSEventType, DwebbleWSHealthIssue};
+
+/// Milliseconds since the library first asked, on a monotonic clock; cheap to keep in
+/// an atomic
+pub fn now_ms() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Called with each health warning, from the watchdog thread, with the connection
+/// and how long it stalled in milliseconds
+pub type HealthCallback = Arc<dyn Fn(DwebbleWSHealthIssue, u64, u64) + Send + Sync>;
+
+/// A running watchdog thread, stopped when the server stops
+pub struct Watchdog {
+    stop_tx: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Watchdog {
+    pub(crate) fn start(
+        runtime: Handle,
+        shared: Arc<Shared>,
+        settings: WatchdogSettings,
+    ) -> std::io::Result<Self> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("dwebble-watchdog".to_string())
+            .spawn(move || run(runtime, shared, settings, stop_rx))?;
+        Ok(Self { stop_tx, thread })
+    }
+
+    pub fn stop(self) {
+        drop(self.stop_tx);
+        let _ = self.thread.join();
+    }
+}
+
+/// Stalls found so far, to warn of each once
+#[derive(Default)]
+struct Warned {
+    runtime: bool,
+    writers: HashSet<u64>,
+    event_queue: bool,
+}
+
+fn run(
+    runtime: Handle,
+    shared: Arc<Shared>,
+    settings: WatchdogSettings,
+    stop_rx: mpsc::Receiver<()>,
+) {
+    let interval = Duration::from_millis(settings.check_interval_ms);
+    let mut warned = Warned::default();
+    // Task spawned on the runtime and whether it has run yet
+    let mut probe: Option<(Instant, Arc<AtomicBool>)> = None;
+    loop {
+        match stop_rx.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+
+        if settings.runtime_stall_ms > 0 {
+            if let Some((spawned, ran)) = &probe {
+                let waited = spawned.elapsed();
+                if ran.load(Ordering::Relaxed) {
+                    probe = None;
+                    warned.runtime = false;
+                } else if waited >= Duration::from_millis(settings.runtime_stall_ms)
+                    && !warned.runtime
+                {
+                    warned.runtime = true;
+                    warn(
+                        &shared,
+                        DwebbleWSHealthIssue::Runtime,
+                        0,
+                        waited.as_millis() as u64,
+                    );
+                }
+            }
+            if probe.is_none() {
+                let ran = Arc::new(AtomicBool::new(false));
+                let flag = Arc::clone(&ran);
+                runtime.spawn(async move { flag.store(true, Ordering::Relaxed) });
+                probe = Some((Instant::now(), ran));
+            }
+        }
+
+        let now = now_ms();
+        if settings.writer_stall_ms > 0 {
+            let stalled: Vec<(u64, u64)> = shared
+                .connections
+                .values()
+                .iter()
+                .filter(|conn| conn.pending_bytes() > 0)
+                .map(|conn| (conn.id, now.saturating_sub(conn.progress_ms())))
+                .filter(|(_, stalled_ms)| *stalled_ms >= settings.writer_stall_ms)
+                .collect();
+            warned
+                .writers
+                .retain(|id| stalled.iter().any(|(stalled_id, _)| stalled_id == id));
+            for (connection_id, stalled_ms) in stalled {
+                if warned.writers.insert(connection_id) {
+                    warn(
+                        &shared,
+                        DwebbleWSHealthIssue::Writer,
+                        connection_id,
+                        stalled_ms,
+                    );
+                }
+            }
+        }
+
+        if settings.event_queue_stall_ms > 0 {
+            // Since the host last polled, or since events began waiting if that's later
+            let since = shared
+                .polled_ms
+                .load(Ordering::Relaxed)
+                .max(shared.backlog_ms.load(Ordering::Relaxed));
+            let stalled_ms = now.saturating_sub(since);
+            let waiting = shared.event_backlog.load(Ordering::Relaxed) > 0;
+            if !waiting || stalled_ms < settings.event_queue_stall_ms {
+                warned.event_queue = false;
+            } else if !warned.event_queue {
+                warned.event_queue = true;
+                warn(&shared, DwebbleWSHealthIssue::EventQueue, 0, stalled_ms);
+            }
+        }
+    }
+}
+
+/// Raise `HealthWarning` and call the health callback
+fn warn(shared: &Shared, issue: DwebbleWSHealthIssue, connection_id: u64, stalled_ms: u64) {
+    let description = match issue {
+        DwebbleWSHealthIssue::Runtime => "The runtime has not run a task",
+        DwebbleWSHealthIssue::Writer => "The writer has not written queued messages",
+        DwebbleWSHealthIssue::EventQueue => "Queued events have not been polled",
+    };
+    tracing::warn!(
+        "{} for {}ms (connection: {})",
+        description,
+        stalled_ms,
+        connection_id
+    );
+
+    // Held while calling, so the callback isn't replaced under a running call
+    let callback = shared.health_callback.lock();
+    if let Some(callback) = &*callback {
+        callback(issue, connection_id, stalled_ms);
+    }
+    drop(callback);
+
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::HealthWarning,
+        connection_id,
+        data: Some(issue.name().as_bytes().to_vec()),
+        error: Some(format!("{} for {}ms", description, stalled_ms)),
+        request_id: stalled_ms,
+    });
+}
@@ -0,0 +1,427 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Webhook notifications of lifecycle events
+//!
+//! With the `webhooks` setting, the server POSTs a JSON notification to each
+//! endpoint that wants it when it starts and stops, when the connection count or
+//! error rate crosses its threshold, and when an address is banned:
+//!
+//! ```json
+//! {"event": "banned", "source": "0.0.0.0:7777", "timestamp_ms": 1700000000000,
+//!  "data": {"ip": "203.0.113.9", "duration_ms": 60000, "reason": "cheating"}}
+//! ```
+//!
+//! The event is also sent in an `X-Dwebble-Event` header, and with a secret the
+//! body's HMAC-SHA256 in `X-Dwebble-Signature: sha256=<hex>`. Deliveries that fail
+//! or get a 5xx or 429 response are retried with exponential backoff. A stop waits
+//! briefly for queued notifications, `stopped` included, to be delivered.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use data_encoding::HEXLOWER;
+use parking_lot::Mutex;
+use ring::hmac;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::server::{ServerEvent, Shared};
+use crate::settings::{WebhookEndpoint, WebhookSettings};
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::types::DwebbleWSEventType;
+
+/// How often the thresholds are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Window of the error rate, in check intervals
+const ERROR_WINDOW: usize = 60;
+
+/// Largest response head read from an endpoint
+const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+
+/// A notification waiting to be delivered
+struct Notification {
+    event: &'static str,
+    body: Arc<Vec<u8>>,
+}
+
+/// Where an endpoint's URL points
+#[derive(Clone)]
+struct Target {
+    tls: bool,
+    host: String,
+    port: u16,
+    /// `host[:port]` as in the URL, for the `Host` header
+    authority: String,
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("{}: not an http:// or https:// URL", url));
+        };
+        if tls && !cfg!(feature = "tls") {
+            return Err(format!("{}: built without the `tls` feature", url));
+        }
+
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port.parse().map_err(|_| format!("{}: invalid port", url))?;
+                (host, port)
+            }
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("{}: missing host", url));
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            authority: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// An endpoint with its parsed URL and signing key
+struct Endpoint {
+    settings: WebhookEndpoint,
+    target: Target,
+    key: Option<hmac::Key>,
+}
+
+impl Endpoint {
+    fn wants(&self, event: &str) -> bool {
+        self.settings.events.is_empty() || self.settings.events.iter().any(|e| e == event)
+    }
+}
+
+/// Threshold state between checks
+#[derive(Default)]
+struct Alerts {
+    connections_high: bool,
+    /// Errors counted in each of the last check intervals
+    errors: VecDeque<u64>,
+    error_rate_raised: Option<Instant>,
+}
+
+/// The server's webhook endpoints and the notifications on their way to them
+pub struct Webhooks {
+    settings: WebhookSettings,
+    endpoints: Vec<Arc<Endpoint>>,
+    /// Names the server in notifications
+    source: Mutex<String>,
+    outbound: Mutex<Option<mpsc::UnboundedSender<Notification>>>,
+    delivery_task: Mutex<Option<JoinHandle<()>>>,
+    /// Error events raised since the last check
+    errors: AtomicU64,
+    alerts: Mutex<Alerts>,
+}
+
+impl Webhooks {
+    /// Fails if an endpoint's URL is invalid
+    pub fn new(settings: WebhookSettings) -> Result<Self, String> {
+        let endpoints = settings
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                Ok(Arc::new(Endpoint {
+                    settings: endpoint.clone(),
+                    target: Target::parse(&endpoint.url)?,
+                    key: (!endpoint.secret.is_empty())
+                        .then(|| hmac::Key::new(hmac::HMAC_SHA256, endpoint.secret.as_bytes())),
+                }))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            source: Mutex::new(settings.source.clone()),
+            settings,
+            endpoints,
+            outbound: Mutex::new(None),
+            delivery_task: Mutex::new(None),
+            errors: AtomicU64::new(0),
+            alerts: Mutex::new(Alerts::default()),
+        })
+    }
+
+    /// Spawn the delivery and threshold tasks on the server runtime, and notify
+    /// `started`. The WebSocket listener's `addr` names the server if no source is set.
+    pub(crate) fn start(&self, runtime: &Runtime, shared: Arc<Shared>, addr: SocketAddr) {
+        if self.settings.source.is_empty() {
+            *self.source.lock() = addr.to_string();
+        }
+        *self.alerts.lock() = Alerts::default();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.outbound.lock() = Some(tx);
+        let task = runtime.spawn(deliver(self.endpoints.clone(), self.settings.clone(), rx));
+        *self.delivery_task.lock() = Some(task);
+        if self.settings.max_connections > 0 || self.settings.max_errors_per_min > 0 {
+            runtime.spawn(run_checks(shared));
+        }
+
+        self.notify("started", json!({ "addr": addr.to_string() }));
+    }
+
+    /// Notify `stopped` and take no more notifications. Returns the delivery task,
+    /// which ends once the queued notifications are delivered or given up on.
+    pub fn stop(&self, stats: Value) -> Option<JoinHandle<()>> {
+        self.notify("stopped", stats);
+        self.outbound.lock().take();
+        self.delivery_task.lock().take()
+    }
+
+    /// Queue a notification for the endpoints that want it
+    pub fn notify(&self, event: &'static str, data: Value) {
+        if !self.endpoints.iter().any(|endpoint| endpoint.wants(event)) {
+            return;
+        }
+        let Some(outbound) = &*self.outbound.lock() else {
+            return;
+        };
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let body = json!({
+            "event": event,
+            "source": *self.source.lock(),
+            "timestamp_ms": timestamp_ms,
+            "data": data,
+        });
+        let _ = outbound.send(Notification {
+            event,
+            body: Arc::new(body.to_string().into_bytes()),
+        });
+    }
+
+    /// Count an event raised by the server towards the error rate
+    pub fn observe(&self, event: &ServerEvent) {
+        if matches!(
+            event.event_type,
+            DwebbleWSEventType::Error
+                | DwebbleWSEventType::TlsHandshakeFailed
+                | DwebbleWSEventType::HandshakeFailed
+        ) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Notify threshold breaches since the last check
+    fn check(&self, connections: usize) {
+        let mut alerts = self.alerts.lock();
+
+        let max_connections = self.settings.max_connections;
+        if max_connections > 0 {
+            let high = connections >= max_connections;
+            if high && !alerts.connections_high {
+                self.notify(
+                    "connections_high",
+                    json!({ "connections": connections, "max_connections": max_connections }),
+                );
+            }
+            alerts.connections_high = high;
+        }
+
+        alerts
+            .errors
+            .push_back(self.errors.swap(0, Ordering::Relaxed));
+        if alerts.errors.len() > ERROR_WINDOW {
+            alerts.errors.pop_front();
+        }
+        let max_errors = self.settings.max_errors_per_min;
+        let errors: u64 = alerts.errors.iter().sum();
+        let quiet = alerts
+            .error_rate_raised
+            .is_none_or(|raised| raised.elapsed() >= CHECK_INTERVAL * ERROR_WINDOW as u32);
+        if max_errors > 0 && errors >= max_errors && quiet {
+            alerts.error_rate_raised = Some(Instant::now());
+            self.notify(
+                "error_rate_high",
+                json!({ "errors_per_min": errors, "max_errors_per_min": max_errors }),
+            );
+        }
+    }
+}
+
+/// Check the thresholds every second. Runs until the runtime shuts down.
+async fn run_checks(shared: Arc<Shared>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some(webhooks) = &shared.webhooks {
+            let connections = shared.connections.len();
+            webhooks.check(connections);
+        }
+    }
+}
+
+/// Deliver notifications as they are queued, until the queue closes and those
+/// in flight are done
+async fn deliver(
+    endpoints: Vec<Arc<Endpoint>>,
+    settings: WebhookSettings,
+    mut rx: mpsc::UnboundedReceiver<Notification>,
+) {
+    let settings = Arc::new(settings);
+    let mut deliveries = JoinSet::new();
+    loop {
+        tokio::select! {
+            notification = rx.recv() => {
+                let Some(notification) = notification else { break };
+                for endpoint in &endpoints {
+                    if !endpoint.wants(notification.event) {
+                        continue;
+                    }
+                    deliveries.spawn(deliver_with_retries(
+                        Arc::clone(endpoint),
+                        Arc::clone(&settings),
+                        notification.event,
+                        Arc::clone(&notification.body),
+                    ));
+                }
+            }
+            Some(_) = deliveries.join_next() => {}
+        }
+    }
+    while deliveries.join_next().await.is_some() {}
+}
+
+/// Deliver a notification to an endpoint, retrying failures
+async fn deliver_with_retries(
+    endpoint: Arc<Endpoint>,
+    settings: Arc<WebhookSettings>,
+    event: &'static str,
+    body: Arc<Vec<u8>>,
+) {
+    let mut delay = Duration::from_millis(settings.retry_delay_ms);
+    for attempt in 0..=settings.max_retries {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+
+        let timeout = Duration::from_millis(settings.timeout_ms);
+        let result = match tokio::time::timeout(timeout, post(&endpoint, event, &body)).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => return,
+            Ok(Ok(status)) if status != 429 && status < 500 => {
+                tracing::warn!(
+                    "Webhook {} refused {} with status {}",
+                    endpoint.settings.url,
+                    event,
+                    status
+                );
+                return;
+            }
+            Ok(Ok(status)) => format!("status {}", status),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timed out".to_string(),
+        };
+        tracing::warn!(
+            "Webhook {} failed to take {} (attempt {}): {}",
+            endpoint.settings.url,
+            event,
+            attempt + 1,
+            result
+        );
+    }
+    tracing::error!(
+        "Gave up delivering {} to webhook {}",
+        event,
+        endpoint.settings.url
+    );
+}
+
+/// POST a notification to an endpoint. Returns the response status.
+async fn post(endpoint: &Endpoint, event: &str, body: &[u8]) -> std::io::Result<u16> {
+    let target = &endpoint.target;
+    let stream = TcpStream::connect((target.host.as_str(), target.port)).await?;
+    if !target.tls {
+        return exchange(stream, endpoint, event, body).await;
+    }
+
+    #[cfg(feature = "tls")]
+    {
+        let server_name = rustls::pki_types::ServerName::try_from(target.host.clone())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let stream = tls::connector().connect(server_name, stream).await?;
+        exchange(stream, endpoint, event, body).await
+    }
+    #[cfg(not(feature = "tls"))]
+    unreachable!("rejected by Target::parse")
+}
+
+/// Write the request and read the response status
+async fn exchange<S>(
+    mut stream: S,
+    endpoint: &Endpoint,
+    event: &str,
+    body: &[u8],
+) -> std::io::Result<u16>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let target = &endpoint.target;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: dwebble-rws\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\
+         X-Dwebble-Event: {}\r\nConnection: close\r\n",
+        target.path,
+        target.authority,
+        body.len(),
+        event
+    );
+    if let Some(key) = &endpoint.key {
+        let signature = hmac::sign(key, body);
+        request.push_str(&format!(
+            "X-Dwebble-Signature: sha256={}\r\n",
+            HEXLOWER.encode(signature.as_ref())
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut response = httparse::Response::new(&mut headers);
+        if let Ok(httparse::Status::Complete(_)) = response.parse(&head) {
+            return response.code.ok_or_else(|| invalid_response("no status"));
+        }
+        if head.len() >= MAX_RESPONSE_HEAD {
+            return Err(invalid_response("response head too large"));
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(invalid_response("connection closed before a response"));
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn invalid_response(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string())
+}
@@ -0,0 +1,404 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! WebTransport (HTTP/3) listener
+//!
+//! Browsers open a session with `new WebTransport("https://<host>:<port><path>")`.
+//! Each session is a connection in the usual event model, reported with
+//! `webtransport` as its subprotocol, and is limited to one per QUIC connection.
+//!
+//! Every stream the client opens (unidirectional or bidirectional) carries one
+//! binary message, read to its end. Messages sent to the connection go out on a
+//! new unidirectional stream each. Datagrams raise `DatagramReceived` events and
+//! are sent with `dwebble_rws_server_send_datagram`.
+
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::Poll;
+
+use h3::ext::Protocol;
+use h3::frame::FrameStream;
+use h3::proto::frame::Frame;
+use h3::stream::BufRecvStream;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::Endpoint;
+use rustls::ServerConfig;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::http::{Method, Response, StatusCode};
+use tokio_tungstenite::tungstenite::{Bytes, Message};
+
+use crate::connection::{Connection, Queued};
+use crate::server::{self, ServerEvent, Shared};
+use crate::types::{DwebbleWSEventType, DwebbleWSResult};
+
+/// Subprotocol reported for WebTransport connections
+pub const SUBPROTOCOL: &str = "webtransport";
+
+/// ALPN protocol of HTTP/3
+const ALPN_H3: &[u8] = b"h3";
+
+/// Stream type of WebTransport unidirectional streams
+const UNI_STREAM_TYPE: u64 = 0x54;
+
+/// Largest inbound stream message when no message size limit is set
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// Application error code closing a QUIC connection without error
+const NO_ERROR: u32 = 0;
+
+type H3Connection = h3::server::Connection<h3_quinn::Connection, Bytes>;
+
+/// An established session
+struct Session {
+    quic: quinn::Connection,
+    /// Prefix of the session's datagrams (its CONNECT stream ID divided by four)
+    quarter_stream_id: u64,
+}
+
+/// Established WebTransport sessions
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<u64, Session>,
+}
+
+impl Sessions {
+    pub fn remove_connection(&mut self, connection_id: u64) {
+        self.sessions.remove(&connection_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+}
+
+/// Incoming work on an established session
+enum Incoming {
+    Stream(Box<BufRecvStream<h3_quinn::BidiStream<Bytes>, Bytes>>),
+    UniStreams(Vec<BufRecvStream<h3_quinn::RecvStream, Bytes>>),
+    Closed,
+}
+
+/// Bind the QUIC endpoint. Must be called within the server's runtime.
+pub fn bind(tls: &ServerConfig, addr: SocketAddr) -> Result<Endpoint, String> {
+    let mut tls = tls.clone();
+    tls.alpn_protocols = vec![ALPN_H3.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(|e| e.to_string())?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    Endpoint::server(config, addr).map_err(|e| e.to_string())
+}
+
+/// Accept QUIC connections until the runtime shuts down
+pub async fn run(shared: Arc<Shared>, endpoint: Endpoint, path: String) {
+    while let Some(incoming) = endpoint.accept().await {
+        let shared = Arc::clone(&shared);
+        let path = path.clone();
+        tokio::spawn(async move {
+            let addr = incoming.remote_address();
+            if let Err(e) = handle_connection(incoming, shared, &path).await {
+                tracing::error!("WebTransport error from {}: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Send a datagram to a session
+pub fn send_datagram(shared: &Shared, connection_id: u64, data: &[u8]) -> DwebbleWSResult {
+    let sessions = shared.webtransport.lock();
+    let Some(session) = sessions.sessions.get(&connection_id) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+
+    let mut datagram = Vec::with_capacity(data.len() + 8);
+    write_varint(&mut datagram, session.quarter_stream_id);
+    datagram.extend_from_slice(data);
+    match session.quic.send_datagram(datagram.into()) {
+        Ok(()) => DwebbleWSResult::Ok,
+        Err(quinn::SendDatagramError::TooLarge) => DwebbleWSResult::InvalidParam,
+        Err(quinn::SendDatagramError::ConnectionLost(_)) => DwebbleWSResult::ConnectionClosed,
+        Err(_) => DwebbleWSResult::SendFailed,
+    }
+}
+
+async fn handle_connection(
+    incoming: quinn::Incoming,
+    shared: Arc<Shared>,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let quic = incoming.await?;
+    let addr = quic.remote_address();
+    let mut h3: H3Connection = h3::server::builder()
+        .enable_webtransport(true)
+        .enable_extended_connect(true)
+        .enable_datagram(true)
+        .max_webtransport_sessions(1)
+        .send_grease(true)
+        .build(h3_quinn::Connection::new(quic.clone()))
+        .await?;
+
+    // Streams may arrive before the CONNECT request that opens their session
+    let mut early_streams = Vec::new();
+    let mut session_stream = loop {
+        let Some(stream) = poll_fn(|cx| h3.poll_accept_request_stream(cx)).await? else {
+            return Ok(());
+        };
+        let mut frames = FrameStream::new(BufRecvStream::new(stream));
+        let frame = poll_fn(|cx| frames.poll_next(cx)).await;
+        if let Ok(Some(Frame::WebTransportStream(_))) = frame {
+            early_streams.push(frames.into_inner());
+            continue;
+        }
+
+        let (request, mut stream) = h3
+            .create_resolver(frames)
+            .accept_with_frame(frame)?
+            .resolve()
+            .await?;
+
+        let is_session = request.method() == Method::CONNECT
+            && request.extensions().get::<Protocol>() == Some(&Protocol::WEB_TRANSPORT);
+        let status = if !is_session || request.uri().path() != path {
+            Err((StatusCode::NOT_FOUND, "Not found"))
+        } else {
+            let settings = shared.settings.read().clone();
+            let origin = request
+                .headers()
+                .get("Origin")
+                .and_then(|o| o.to_str().ok());
+            shared.admit(&settings, origin)
+        };
+
+        let response = Response::builder()
+            .status(status.map_or_else(|(status, _)| status, |_| StatusCode::OK))
+            .header("sec-webtransport-http3-draft", "draft02")
+            .body(())?;
+        stream.send_response(response).await?;
+        match status {
+            Ok(()) => break stream,
+            Err((_, reason)) => {
+                tracing::debug!("Refused WebTransport session from {}: {}", addr, reason);
+                stream.finish().await?;
+            }
+        }
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Queued>();
+    let conn = Arc::new(Connection::new(
+        addr.to_string(),
+        Some(SUBPROTOCOL.to_string()),
+        tx,
+    ));
+    let connection_id = conn.id;
+    let session_id = session_stream.id().into_inner();
+
+    shared.connections.insert(Arc::clone(&conn));
+    shared.webtransport.lock().sessions.insert(
+        connection_id,
+        Session {
+            quic: quic.clone(),
+            quarter_stream_id: session_stream.id().index(),
+        },
+    );
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientConnected,
+        connection_id,
+        data: Some(SUBPROTOCOL.as_bytes().to_vec()),
+        error: None,
+        request_id: 0,
+    });
+    tracing::info!(
+        "WebTransport session opened: {} (id: {})",
+        addr,
+        connection_id
+    );
+
+    for stream in early_streams {
+        tokio::spawn(read_message(Arc::clone(&shared), connection_id, stream));
+    }
+
+    let mut close_reason = None;
+    loop {
+        tokio::select! {
+            next = rx.recv() => {
+                let Some(queued) = next else { break };
+                let Some(msg) = conn.dequeue(queued) else { continue };
+                let len = msg.len();
+                let payload = match msg {
+                    Message::Binary(data) => Some(data),
+                    Message::Text(text) => Some(Bytes::from(text)),
+                    Message::Close(frame) => {
+                        close_reason = frame.map(|f| f.reason.to_string());
+                        conn.mark_written_len(len);
+                        break;
+                    }
+                    _ => None,
+                };
+                let sent = match payload {
+                    Some(payload) => send_stream(&quic, session_id, &payload).await.is_ok(),
+                    None => true,
+                };
+                conn.mark_written_len(len);
+                if !sent {
+                    break;
+                }
+            }
+            incoming = poll_fn(|cx| poll_incoming(&mut h3, cx)) => match incoming {
+                Incoming::Stream(stream) => {
+                    tokio::spawn(read_bidi_stream(Arc::clone(&shared), connection_id, stream));
+                }
+                Incoming::UniStreams(streams) => {
+                    for stream in streams {
+                        tokio::spawn(read_message(Arc::clone(&shared), connection_id, stream));
+                    }
+                }
+                Incoming::Closed => break,
+            },
+            datagram = quic.read_datagram() => {
+                let Ok(datagram) = datagram else { break };
+                let mut payload = &datagram[..];
+                if read_varint(&mut payload).is_some() {
+                    shared.push_event(ServerEvent {
+                        event_type: DwebbleWSEventType::DatagramReceived,
+                        connection_id,
+                        data: Some(payload.to_vec()),
+                        error: None,
+                        request_id: 0,
+                    });
+                }
+            }
+            // Data on the CONNECT stream only carries capsules; its end closes the session
+            data = session_stream.recv_data() => {
+                if !matches!(data, Ok(Some(_))) {
+                    break;
+                }
+            }
+            _ = conn.terminated() => {
+                close_reason = conn.termination().map(|(frame, _)| frame.reason.to_string());
+                break;
+            }
+        }
+    }
+
+    let reason = close_reason.or(conn.severed()).unwrap_or_default();
+    quic.close(NO_ERROR.into(), reason.as_bytes());
+
+    shared.connections.remove(connection_id);
+    shared.forget_connection(connection_id);
+    shared.push_event(ServerEvent {
+        event_type: DwebbleWSEventType::ClientDisconnected,
+        connection_id,
+        data: None,
+        error: conn
+            .termination()
+            .map(|(_, reason)| reason)
+            .or(conn.severed()),
+        request_id: 0,
+    });
+    tracing::info!(
+        "WebTransport session closed: {} (id: {})",
+        addr,
+        connection_id
+    );
+
+    Ok(())
+}
+
+/// Poll for a new stream, taking unidirectional streams h3 buffered for the session
+fn poll_incoming(h3: &mut H3Connection, cx: &mut std::task::Context<'_>) -> Poll<Incoming> {
+    let accepted = h3.poll_accept_request_stream(cx);
+    if let Poll::Ready(Ok(Some(stream))) = accepted {
+        return Poll::Ready(Incoming::Stream(Box::new(BufRecvStream::new(stream))));
+    }
+
+    let uni_streams = &mut h3.inner.accepted_streams_mut().wt_uni_streams;
+    if !uni_streams.is_empty() {
+        let streams = uni_streams.drain(..).map(|(_, stream)| stream).collect();
+        return Poll::Ready(Incoming::UniStreams(streams));
+    }
+
+    match accepted {
+        Poll::Pending => Poll::Pending,
+        _ => Poll::Ready(Incoming::Closed),
+    }
+}
+
+/// Read a client-opened bidirectional stream, ignoring anything but WebTransport streams
+async fn read_bidi_stream(
+    shared: Arc<Shared>,
+    connection_id: u64,
+    stream: Box<BufRecvStream<h3_quinn::BidiStream<Bytes>, Bytes>>,
+) {
+    let mut frames = FrameStream::new(*stream);
+    if let Ok(Some(Frame::WebTransportStream(_))) = poll_fn(|cx| frames.poll_next(cx)).await {
+        read_message(shared, connection_id, frames.into_inner()).await;
+    }
+}
+
+/// Read a stream to its end and deliver it as one message
+async fn read_message<S: AsyncRead + Unpin>(shared: Arc<Shared>, connection_id: u64, stream: S) {
+    let limit = match shared.settings.read().max_message_size {
+        0 => DEFAULT_MAX_MESSAGE_SIZE,
+        size => size,
+    };
+
+    let mut data = Vec::new();
+    let mut stream = stream.take(limit as u64 + 1);
+    if let Err(e) = stream.read_to_end(&mut data).await {
+        tracing::debug!("WebTransport stream error (id: {}): {}", connection_id, e);
+        return;
+    }
+    if data.len() > limit {
+        tracing::warn!(
+            "Dropped oversized WebTransport message (id: {})",
+            connection_id
+        );
+        return;
+    }
+
+    server::deliver_inbound(&shared, connection_id, Message::Binary(data.into()), None);
+}
+
+/// Send one message on a new unidirectional stream
+async fn send_stream(
+    quic: &quinn::Connection,
+    session_id: u64,
+    payload: &[u8],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut header = Vec::with_capacity(16);
+    write_varint(&mut header, UNI_STREAM_TYPE);
+    write_varint(&mut header, session_id);
+
+    let mut stream = quic.open_uni().await?;
+    stream.write_all(&header).await?;
+    stream.write_all(payload).await?;
+    stream.finish()?;
+    Ok(())
+}
+
+/// Append a QUIC variable-length integer
+fn write_varint(buf: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0x3f => buf.push(value as u8),
+        0x40..=0x3fff => buf.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => buf.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes()),
+        _ => buf.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
+
+/// Take a QUIC variable-length integer off the front of `buf`
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let len = 1 << (buf.first()? >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let value = buf[1..len]
+        .iter()
+        .fold(u64::from(buf[0] & 0x3f), |value, &b| {
+            value << 8 | u64::from(b)
+        });
+    *buf = &buf[len..];
+    Some(value)
+}
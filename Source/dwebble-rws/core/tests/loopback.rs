@@ -0,0 +1,384 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Servers and clients talking over loopback
+//!
+//! Runtimes without workers of their own, as in `single-thread` builds, are ticked
+//! from threads of the tests' own, like a host would once per frame.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use dwebble_rws_core::checksum;
+use dwebble_rws_core::client::Client;
+use dwebble_rws_core::server::{Server, ServerConfig, ServerEvent};
+use dwebble_rws_core::service_pool::ServicePool;
+use dwebble_rws_core::settings::{ChecksumAlgorithm, ClientSettings, ServicePoolSettings, Settings};
+use dwebble_rws_core::types::{DwebbleWSEventType, DwebbleWSMiddlewareAction, DwebbleWSResult};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const TICK: Duration = Duration::from_millis(1);
+
+/// Tick `target` from a thread of its own while it lives, unless `threaded`
+fn drive<T: Send + Sync + 'static>(target: T, threaded: bool, tick: fn(&T)) -> Arc<T> {
+    let target = Arc::new(target);
+    if !threaded {
+        let weak = Arc::downgrade(&target);
+        thread::spawn(move || {
+            while let Some(target) = weak.upgrade() {
+                tick(&target);
+            }
+        });
+    }
+    target
+}
+
+/// Whether the runtimes of this build drive themselves
+fn threaded(server: &Server) -> bool {
+    server.resource_usage().worker_threads > 0
+}
+
+fn start(settings: Settings) -> Arc<Server> {
+    let server = Server::new(ServerConfig {
+        settings,
+        ..Default::default()
+    });
+    assert_eq!(server.start(), DwebbleWSResult::Ok);
+    let threaded = threaded(&server);
+    drive(server, threaded, |server| {
+        server.tick(TICK);
+    })
+}
+
+fn url(server: &Server) -> String {
+    format!("ws://127.0.0.1:{}", server.get_actual_port())
+}
+
+/// The next event of `event_type`, skipping others
+fn wait_for(
+    mut poll: impl FnMut() -> Option<ServerEvent>,
+    event_type: DwebbleWSEventType,
+) -> ServerEvent {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        match poll() {
+            Some(event) if event.event_type == event_type => return event,
+            Some(_) => {}
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    }
+    panic!("No {:?} event within {:?}", event_type, TIMEOUT);
+}
+
+/// A client of `server` with the server's ID of its connection
+fn connect(server: &Server, settings: &ClientSettings) -> (Arc<Client>, u64) {
+    let client = Client::connect(&url(server), settings).unwrap();
+    let client = drive(client, threaded(server), |client| client.tick(TICK));
+    wait_for(|| client.poll_event(), DwebbleWSEventType::ClientConnected);
+    let connected = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    (client, connected.connection_id)
+}
+
+fn received(server: &Server) -> Vec<u8> {
+    let event = wait_for(|| server.poll_event(), DwebbleWSEventType::MessageReceived);
+    event.data.unwrap()
+}
+
+type Socket = tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>;
+
+/// The code of the Close frame `socket` reads next
+fn close_code(socket: &mut Socket) -> CloseCode {
+    let Message::Close(Some(frame)) = socket.read().unwrap() else {
+        panic!("Expected a close frame");
+    };
+    frame.code
+}
+
+#[test]
+fn messages_reach_the_server() {
+    let server = start(Settings::default());
+    let (client, connection_id) = connect(&server, &ClientSettings::default());
+
+    let large_text = "é".repeat(100_000);
+    let large_binary = vec![7; 70_000];
+    assert!(client.send(b"\x01\x02binary"));
+    assert_eq!(client.send_text("héllo wörld ✓".as_bytes()), DwebbleWSResult::Ok);
+    assert_eq!(client.send_text(large_text.as_bytes()), DwebbleWSResult::Ok);
+    assert!(client.send(&large_binary));
+
+    let event = wait_for(|| server.poll_event(), DwebbleWSEventType::MessageReceived);
+    assert_eq!(event.connection_id, connection_id);
+    assert_eq!(event.data.unwrap(), b"\x01\x02binary");
+    assert_eq!(received(&server), "héllo wörld ✓".as_bytes());
+    assert_eq!(received(&server), large_text.as_bytes());
+    assert_eq!(received(&server), large_binary);
+}
+
+#[test]
+fn messages_reach_the_client() {
+    let server = start(Settings::default());
+    let (client, connection_id) = connect(&server, &ClientSettings::default());
+
+    assert_eq!(server.send(connection_id, b"binary"), DwebbleWSResult::Ok);
+    assert_eq!(server.send_text(connection_id, "text ✓".as_bytes()), DwebbleWSResult::Ok);
+
+    let event = wait_for(|| client.poll_event(), DwebbleWSEventType::MessageReceived);
+    assert_eq!(event.data.unwrap(), b"binary");
+    let event = wait_for(|| client.poll_event(), DwebbleWSEventType::MessageReceived);
+    assert_eq!(event.data.unwrap(), "text ✓".as_bytes());
+}
+
+#[test]
+fn invalid_text_closes_the_connection() {
+    let server = start(Settings::default());
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    // Sent as a text frame holding invalid UTF-8
+    let invalid = unsafe { String::from_utf8_unchecked(vec![b'a', 0xff, b'b']) };
+    socket.send(Message::Text(invalid.into())).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientDisconnected);
+}
+
+#[test]
+fn reserved_payloads_pass_through_without_control_frames() {
+    let server = start(Settings::default());
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    let connected = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    socket.send(Message::binary(&b"DWZZpayload"[..])).unwrap();
+    socket.send(Message::binary(&b"DWESpayload"[..])).unwrap();
+    assert_eq!(received(&server), b"DWZZpayload");
+    assert_eq!(received(&server), b"DWESpayload");
+
+    let sent = server.send(connected.connection_id, b"DWZZpayload");
+    assert_eq!(sent, DwebbleWSResult::Ok);
+    assert_eq!(socket.read().unwrap(), Message::binary(&b"DWZZpayload"[..]));
+}
+
+#[test]
+fn control_frames_escape_reserved_payloads() {
+    let settings = Settings::from_json(r#"{"control_frames": true}"#).unwrap();
+    let server = start(settings.clone());
+    let client_settings = ClientSettings {
+        server: settings,
+        ..Default::default()
+    };
+    let (client, connection_id) = connect(&server, &client_settings);
+
+    assert!(client.send(b"DWZZpayload"));
+    assert_eq!(received(&server), b"DWZZpayload");
+
+    assert_eq!(server.send(connection_id, b"DWZZpayload"), DwebbleWSResult::Ok);
+    let event = wait_for(|| client.poll_event(), DwebbleWSEventType::MessageReceived);
+    assert_eq!(event.data.unwrap(), b"DWZZpayload");
+
+    // Frames of unknown opcodes are dropped instead of raised
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    socket.send(Message::binary(&b"DWZZpayload"[..])).unwrap();
+    socket.send(Message::binary(&b"after"[..])).unwrap();
+    assert_eq!(received(&server), b"after");
+}
+
+#[test]
+fn kicks_send_a_close_frame_with_a_cut_reason() {
+    let server = start(Settings::default());
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    // Codes a Close frame may not carry are refused
+    assert_eq!(server.kick_all(1005, ""), DwebbleWSResult::InvalidParam);
+    assert_eq!(server.kick_room("room", 999, ""), DwebbleWSResult::InvalidParam);
+
+    let reason = "é".repeat(100);
+    assert_eq!(server.kick_all(4000, &reason), DwebbleWSResult::Ok);
+    let Message::Close(Some(frame)) = socket.read().unwrap() else {
+        panic!("Expected a close frame");
+    };
+    assert_eq!(frame.code, CloseCode::from(4000));
+    assert_eq!(frame.reason.len(), 122);
+    assert!(reason.starts_with(frame.reason.as_str()));
+}
+
+#[test]
+fn subprotocols_follow_the_server_preference() {
+    let settings = Settings::from_json(r#"{"subprotocols": ["game.v2", "game.v1"]}"#).unwrap();
+    let server = start(settings);
+    let mut request = url(&server).into_client_request().unwrap();
+    let offered = "chat, game.v1, game.v2".parse().unwrap();
+    request.headers_mut().insert("Sec-WebSocket-Protocol", offered);
+
+    let (_socket, response) = tungstenite::connect(request).unwrap();
+    assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "game.v2");
+}
+
+#[test]
+fn reserved_close_codes_are_refused() {
+    for code in [999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+        let json = format!(r#"{{"close_code": {}}}"#, code);
+        assert!(Settings::from_json(&json).is_err(), "{}", code);
+    }
+    for code in [1000, 1001, 1003, 1007, 1014, 3000, 4999] {
+        let json = format!(r#"{{"close_code": {}}}"#, code);
+        assert!(Settings::from_json(&json).is_ok(), "{}", code);
+    }
+}
+
+#[test]
+fn control_frames_stay_hidden_from_the_host() {
+    let settings = Settings::from_json(r#"{"control_frames": true}"#).unwrap();
+    let server = start(settings);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&seen);
+    server.add_middleware(
+        Arc::new(move |msg| {
+            recorded.lock().unwrap().push(msg.data.to_vec());
+            DwebbleWSMiddlewareAction::Pass
+        }),
+        0,
+    );
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    let connected = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    let request_id = server
+        .request(connected.connection_id, b"ping", TIMEOUT)
+        .unwrap();
+    let request = socket.read().unwrap().into_data();
+    assert_eq!(&request[..4], b"DWRQ");
+    let mut response = b"DWRS".to_vec();
+    response.extend_from_slice(&request[4..12]);
+    response.extend_from_slice(b"pong");
+    socket.send(Message::binary(response)).unwrap();
+    let event = wait_for(|| server.poll_event(), DwebbleWSEventType::ResponseReceived);
+    assert_eq!(event.request_id, request_id);
+    assert_eq!(event.data.unwrap(), b"pong");
+
+    socket.send(Message::binary(&b"DWZZdropped"[..])).unwrap();
+    socket.send(Message::binary(&b"DWESDWZZpayload"[..])).unwrap();
+    assert_eq!(received(&server), b"DWZZpayload");
+    // Neither the request, the response nor the dropped frame went through the middleware
+    assert_eq!(*seen.lock().unwrap(), [b"DWZZpayload".to_vec()]);
+}
+
+#[test]
+fn kicks_reach_only_their_room_or_tag() {
+    let server = start(Settings::default());
+    let (mut member, _) = tungstenite::connect(url(&server)).unwrap();
+    let member_id = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    let (mut tagged, _) = tungstenite::connect(url(&server)).unwrap();
+    let tagged_id = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    let joined = server.join_room(member_id.connection_id, "room");
+    assert_eq!(joined, DwebbleWSResult::Ok);
+    assert_eq!(server.set_tag(tagged_id.connection_id, "tag"), DwebbleWSResult::Ok);
+    assert_eq!(server.kick_tag("tag", 1016, ""), DwebbleWSResult::InvalidParam);
+
+    assert_eq!(server.kick_room("room", 4001, "room"), DwebbleWSResult::Ok);
+    assert_eq!(close_code(&mut member), CloseCode::from(4001));
+    assert_eq!(server.kick_tag("tag", 4002, "tag"), DwebbleWSResult::Ok);
+    assert_eq!(close_code(&mut tagged), CloseCode::from(4002));
+}
+
+#[test]
+fn signed_messages_need_the_key() {
+    let server = start(Settings::default());
+    let (client, connection_id) = connect(&server, &ClientSettings::default());
+    let key = b"shared secret";
+    assert_eq!(server.set_signing_key(connection_id, Some(key)), DwebbleWSResult::Ok);
+    client.set_signing_key(Some(key));
+
+    assert!(client.send(b"signed"));
+    assert_eq!(received(&server), b"signed");
+    assert_eq!(server.send(connection_id, b"back"), DwebbleWSResult::Ok);
+    let event = wait_for(|| client.poll_event(), DwebbleWSEventType::MessageReceived);
+    assert_eq!(event.data.unwrap(), b"back");
+
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    let connected = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    let signed = server.set_signing_key(connected.connection_id, Some(key));
+    assert_eq!(signed, DwebbleWSResult::Ok);
+    socket.send(Message::binary(&b"unsigned"[..])).unwrap();
+    let event = wait_for(|| server.poll_event(), DwebbleWSEventType::SignatureInvalid);
+    assert_eq!(event.connection_id, connected.connection_id);
+}
+
+#[test]
+fn replayed_nonces_are_rejected() {
+    let settings = Settings::from_json(r#"{"replay_protection": {}}"#).unwrap();
+    let server = start(settings.clone());
+    let client_settings = ClientSettings {
+        server: settings,
+        ..Default::default()
+    };
+    let (client, _) = connect(&server, &client_settings);
+    assert!(client.send(b"stamped"));
+    assert_eq!(received(&server), b"stamped");
+
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let mut envelope = b"DWNC".to_vec();
+    envelope.extend_from_slice(&42u64.to_le_bytes());
+    envelope.extend_from_slice(&(now.as_millis() as u64).to_le_bytes());
+    envelope.push(0);
+    envelope.extend_from_slice(b"once");
+    socket.send(Message::binary(envelope.clone())).unwrap();
+    socket.send(Message::binary(envelope)).unwrap();
+    assert_eq!(received(&server), b"once");
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ReplayRejected);
+}
+
+#[test]
+fn checksum_trailers_are_verified() {
+    let settings = Settings::from_json(r#"{"checksum": {"verify_trailer": true}}"#).unwrap();
+    let server = start(settings.clone());
+    let client_settings = ClientSettings {
+        server: settings,
+        ..Default::default()
+    };
+    let (client, _) = connect(&server, &client_settings);
+    assert!(client.send(b"trailed"));
+    assert_eq!(received(&server), b"trailed");
+
+    let (mut socket, _) = tungstenite::connect(url(&server)).unwrap();
+    wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+    let trailed = checksum::append(ChecksumAlgorithm::Crc32, Message::binary(&b"intact"[..]));
+    socket.send(trailed).unwrap();
+    socket.send(Message::binary(&b"corrupted"[..])).unwrap();
+    assert_eq!(received(&server), b"intact");
+    let event = wait_for(|| server.poll_event(), DwebbleWSEventType::ChecksumMismatch);
+    assert_eq!(event.data.unwrap(), b"corrupted");
+}
+
+#[test]
+fn service_pools_get_their_responses() {
+    let server = start(Settings::default());
+    let pool = ServicePool::new(ServicePoolSettings {
+        urls: vec![url(&server)],
+        ..Default::default()
+    })
+    .unwrap();
+    let pool = drive(pool, threaded(&server), |pool| pool.tick(TICK));
+    wait_for(|| pool.poll_event(), DwebbleWSEventType::ClientConnected);
+    let connected = wait_for(|| server.poll_event(), DwebbleWSEventType::ClientConnected);
+
+    // The server stands in for the service, answering the request by hand
+    let request_id = pool.request(b"ping", TIMEOUT).unwrap();
+    let request = received(&server);
+    assert_eq!(&request[..4], b"DWRQ");
+    assert_eq!(&request[12..], b"ping");
+    let mut response = b"DWRS".to_vec();
+    response.extend_from_slice(&request[4..12]);
+    response.extend_from_slice(b"pong");
+    assert_eq!(server.send(connected.connection_id, &response), DwebbleWSResult::Ok);
+
+    let event = wait_for(|| pool.poll_event(), DwebbleWSEventType::ResponseReceived);
+    assert_eq!(event.request_id, request_id);
+    assert_eq!(event.data.unwrap(), b"pong");
+}
@@ -16,7 +16,7 @@
 #include <cstdint>
 #include <cstddef>
 
-/// Result codes for WebSocket FFI operations
+/// Result codes of server and client operations
 enum class DwebbleWSResult {
   Ok = 0,
   InvalidHandle = 1,
@@ -27,7 +27,24 @@ enum class DwebbleWSResult {
   TlsError = 6,
   RuntimeError = 7,
   SendFailed = 8,
+  /// The connection existed but has closed, or its socket is closing
   ConnectionClosed = 9,
+  /// The message would take the connection's send queue over `send_queue_limit`
+  QueueFull = 10,
+  /// Text was not valid UTF-8 under the strict `utf8_policy`
+  InvalidUtf8 = 11,
+  /// Another thread is starting, rebinding or stopping the server
+  Busy = 12,
+};
+
+/// A stall the watchdog warns of
+enum class DwebbleWSHealthIssue {
+  /// The runtime has not run a task spawned on it
+  Runtime = 0,
+  /// A connection's writer has not written its queued messages
+  Writer = 1,
+  /// Events on the server's queue have not been polled
+  EventQueue = 2,
 };
 
 /// WebSocket event types for polling
@@ -37,43 +54,468 @@ enum class DwebbleWSEventType {
   ClientDisconnected = 2,
   MessageReceived = 3,
   Error = 4,
+  /// The socket dropped but the session is kept for a reconnect
+  SessionSuspended = 5,
+  /// A client reconnected to a suspended session (same connection ID)
+  SessionResumed = 6,
+  /// A logical user came online (data: user ID)
+  PresenceJoined = 7,
+  /// A logical user went offline on every instance (data: user ID)
+  PresenceLeft = 8,
+  /// A message was published to a topic the host subscribed to
+  /// (data: payload, error message: topic)
+  TopicMessage = 9,
+  /// A client answered a request (data: response payload)
+  ResponseReceived = 10,
+  /// A request got no response in time, or its connection closed first
+  RequestTimedOut = 11,
+  /// A client sent a JSON-RPC request or notification (data: params JSON, empty
+  /// if absent; error message: method; request ID: call ID, 0 for notifications)
+  RpcCall = 12,
+  /// A client sent a typed envelope (data: payload; request ID: message type ID)
+  TypedMessage = 13,
+  /// A Socket.IO client emitted an event (data: arguments as a JSON array;
+  /// error message: event name; request ID: ack ID, 0 if no ack was requested)
+  SocketIoEvent = 14,
+  /// An MQTT client published a message (data: payload, error message: topic)
+  MqttPublish = 15,
+  /// A WebTransport client sent a datagram (data: payload)
+  DatagramReceived = 16,
+  /// The gateway forwards a port to the server (data: external address as `ip:port`)
+  PortMapped = 17,
+  /// A STUN server reported the server's public address (data: `ip:port`)
+  PublicEndpoint = 18,
+  /// A message arrived on a virtual channel (data: payload; request ID: channel ID)
+  ChannelMessage = 19,
+  /// A loopback client applied a state sync update (data: the whole state; request ID: key)
+  StateUpdated = 20,
+  /// A WASM filter rejected an inbound message (data: the message; error message:
+  /// filter path; request ID: the filter's code, -1 if it failed, sign-extended)
+  MessageRejected = 21,
+  /// An inbound message did not match its subprotocol's schema (data: the message;
+  /// error message: what failed; request ID: 1 if it was delivered anyway, else 0)
+  ValidationFailed = 22,
+  /// A message failed HMAC verification and was dropped (data: the message;
+  /// error message: why)
+  SignatureInvalid = 23,
+  /// A message was stale or replayed and was dropped (data: the message; error
+  /// message: why)
+  ReplayRejected = 24,
+  /// The server stopped and its runtime shut down, the last event of a run (data:
+  /// `{"connections": N, "force_closed": M}` as in `DwebbleWSStopStats`)
+  ServerStopped = 25,
+  /// A client failed the TLS handshake, e.g. over a certificate, SNI or protocol
+  /// version mismatch (data: remote address as `ip:port`; error message: why)
+  TlsHandshakeFailed = 26,
+  /// A client's WebSocket handshake was refused or failed (data:
+  /// `{"remote_addr": "ip:port", "path": P, "header": H}`, path and header null if
+  /// unknown; error message: why)
+  HandshakeFailed = 27,
+  /// A connection sent messages faster than the `rate_limit` setting allows (data:
+  /// the message if it was dropped; error message: the exceeded limit, e.g.
+  /// `messages_per_sec`; request ID: 0 if reading was delayed, 1 if the message was
+  /// dropped, 2 if the connection was closed)
+  RateLimited = 28,
+  /// Message events were dropped over the `event_budget` setting or the
+  /// `event_overflow_limit` setting, raised at most every 100ms (request ID: the
+  /// number dropped since the last such event)
+  EventsDropped = 29,
+  /// A connection's encryption keys were rotated (request ID: the number of rotations
+  /// of the connection so far)
+  KeyRotated = 30,
+  /// A scheduled shutdown came due and closed the connections; stop the server now
+  /// (request ID: the number of connections closed)
+  ShutdownDue = 31,
+  /// The watchdog found a stall (data: `runtime`, `writer` or `event_queue`; error
+  /// message: a description; request ID: how long it has lasted, in milliseconds)
+  HealthWarning = 32,
+  /// Resource usage at the `resource_report_ms` interval (data: JSON with the fields
+  /// of `DwebbleWSResourceUsage`)
+  ResourceReport = 33,
+  /// A client's offline queue was full and dropped its oldest message (data: the
+  /// message; request ID: the number the client has dropped so far)
+  MessageDropped = 34,
+  /// A binary message's checksum trailer was missing or did not match, with the
+  /// `checksum` setting's `verify_trailer`; the message was dropped (data: the
+  /// message as received; error: what didn't match)
+  ChecksumMismatch = 35,
+};
+
+/// What a middleware callback does with a message
+enum class DwebbleWSMiddlewareAction {
+  /// Hand the message on unchanged
+  Pass = 0,
+  /// Hand `replacement` on instead
+  Modify = 1,
+  /// Discard the message, skipping the rest of the chain
+  Drop = 2,
+};
+
+/// Where a server is in its lifecycle
+enum class DwebbleWSServerState {
+  /// Created and never started
+  Created = 0,
+  /// Binding its listeners and starting its runtime
+  Starting = 1,
+  /// Accepting connections
+  Running = 2,
+  /// Closing its connections and shutting its runtime down
+  Draining = 3,
+  /// Stopped after running, and may be started again
+  Stopped = 4,
+};
+
+/// Compression of a connection's messages
+enum class DwebbleWSCompression {
+  /// Messages go out as they are
+  None = 0,
+  /// Messages are compressed with zstd, asked for with the `zstd` setting's
+  /// subprotocol suffix
+  Zstd = 1,
+};
+
+/// What a client trusts its server's certificate by
+enum class DwebbleWSCertificateVerification {
+  /// The connection doesn't use TLS
+  Plaintext = 0,
+  /// A chain to the Mozilla root certificates built into the library
+  MozillaRoots = 1,
+  /// A chain to the root certificates of the operating system's store
+  SystemRoots = 2,
+  /// A chain to the CAs of a bundle the host passed
+  CaBundle = 3,
+  /// A pinned public key
+  Pinned = 4,
+  /// Nothing: verification is disabled
+  Disabled = 5,
 };
 
-/// WebSocket server handle (opaque pointer)
+/// Host allocation function: returns `size` bytes (at least 1), or null on failure.
+/// May be called from any thread, including several at once.
+using DwebbleWSAllocFn = void*(*)(void *user_data, uintptr_t size);
+
+/// Host function freeing memory from the matching `DwebbleWSAllocFn`
+using DwebbleWSFreeFn = void(*)(void *user_data, void *ptr);
+
+/// WebSocket server handle (opaque, checked on every call)
 using DwebbleWSServerHandle = void*;
 
 /// WebSocket server configuration passed from C++
+///
+/// Each string is UTF-8 of its `_len` field's length in bytes, or null-terminated if
+/// that is 0.
 struct DwebbleWSServerConfig {
   /// Port to listen on (0 for auto)
   uint16_t port;
-  /// Bind address (null-terminated UTF-8)
+  /// Bind address
   const char *bind_address;
-  /// Subprotocols (null-terminated, comma-separated)
+  uintptr_t bind_address_len;
+  /// Subprotocols (comma-separated), in order of preference
   const char *subprotocols;
+  uintptr_t subprotocols_len;
+  /// TLS certificate path (null for no TLS)
+  const char *tls_cert_path;
+  uintptr_t tls_cert_path_len;
+  /// TLS private key path
+  const char *tls_key_path;
+  uintptr_t tls_key_path_len;
+  /// Extended settings as a JSON object (null for defaults)
+  const char *settings_json;
+  uintptr_t settings_json_len;
+};
+
+/// Listener configuration passed to `dwebble_rws_server_rebind`, with strings as
+/// in `DwebbleWSServerConfig`
+struct DwebbleWSBindConfig {
+  /// Port to listen on (0 for auto)
+  uint16_t port;
+  /// Bind address (null to keep the current one)
+  const char *bind_address;
+  uintptr_t bind_address_len;
   /// TLS certificate path (null for no TLS)
   const char *tls_cert_path;
+  uintptr_t tls_cert_path_len;
   /// TLS private key path
   const char *tls_key_path;
+  uintptr_t tls_key_path_len;
+};
+
+/// Connections closed by stopping a server
+struct DwebbleWSStopStats {
+  /// Connections open when the server was asked to stop
+  uint64_t connections;
+  /// Connections still open after `close_grace_ms`, dropped without a closing handshake
+  uint64_t force_closed;
+};
+
+/// Callback run once a server has stopped, on the thread that stopped it (a
+/// background thread for an asynchronous stop)
+using DwebbleWSStoppedCallback = void(*)(void *user_data, const DwebbleWSStopStats *stats);
+
+/// Callback run on the watchdog thread with each health warning: the issue, the
+/// connection of a stalled writer (0 otherwise) and how long the stall has lasted
+using DwebbleWSHealthCallback = void(*)(void *user_data,
+                                        DwebbleWSHealthIssue issue,
+                                        uint64_t connection_id,
+                                        uint64_t stalled_ms);
+
+/// Callback run on each thread of a server's runtime as it starts, before it runs
+/// any task, with the order it started in
+using DwebbleWSThreadCallback = void(*)(void *user_data, uint32_t index);
+
+/// Byte buffer allocated by the library. Free with `dwebble_rws_free_buffer`.
+struct DwebbleWSBuffer {
+  uint8_t *data;
+  uintptr_t len;
 };
 
+/// WebSocket connection handle
+using DwebbleWSConnectionId = uint64_t;
+
 /// WebSocket event data returned from polling
 struct DwebbleWSEvent {
   DwebbleWSEventType event_type;
   /// Connection ID (valid for Connected/Disconnected/MessageReceived)
   uint64_t connection_id;
-  /// Message data pointer (valid for MessageReceived)
+  /// Message data pointer (valid for MessageReceived/DatagramReceived/ChannelMessage).
+  /// For ClientConnected/SessionResumed, the negotiated subprotocol (null if none was selected).
+  /// For PresenceJoined/PresenceLeft, the user ID. For TopicMessage/MqttPublish, the published payload.
+  /// For RpcCall, the params JSON. For SocketIoEvent, the arguments JSON array.
+  /// For PortMapped/PublicEndpoint, the external address as `ip:port`.
+  /// For StateUpdated, the whole state after the update.
+  /// For MessageRejected/ValidationFailed/SignatureInvalid/ReplayRejected/ChecksumMismatch,
+  /// the message. For ServerStopped, the stop statistics as JSON.
   const uint8_t *data;
   /// Message data length
   uintptr_t data_len;
-  /// Error message (valid for Error, null-terminated)
+  /// Error message (valid for Error, null-terminated).
+  /// For ClientDisconnected, the reason if the server closed the connection.
+  /// For TopicMessage/MqttPublish, the topic. For RpcCall, the method.
+  /// For SocketIoEvent, the event name. For MessageRejected, the filter path.
+  /// For ValidationFailed/SignatureInvalid/ReplayRejected/ChecksumMismatch, why the
+  /// message was refused.
   const char *error_message;
+  /// Request ID (valid for ResponseReceived/RequestTimedOut).
+  /// For RpcCall, the call ID to answer (0 for notifications).
+  /// For TypedMessage, the message type ID. For SocketIoEvent, the ack ID (0 if none).
+  /// For ChannelMessage, the channel ID. For StateUpdated, the state key.
+  /// For MessageReceived on a loopback client, the sequence of a sequenced send (0 otherwise).
+  /// For MessageRejected, the filter's code. For ValidationFailed, 1 if the message was
+  /// delivered anyway.
+  /// For ClientConnected of a WebRTC data channel, the signaling connection's ID.
+  /// For ClientConnected of a connection the server dialed, 1.
+  uint64_t request_id;
+  /// With the `checksum` setting, the checksum of the data (0 otherwise)
+  uint32_t checksum;
 };
 
-/// WebSocket connection handle
-using DwebbleWSConnectionId = uint64_t;
+/// Per-room event queue handle (opaque, checked on every call)
+using DwebbleWSEventQueueHandle = void*;
+
+/// Statistics of one virtual channel of a connection
+struct DwebbleWSChannelStats {
+  uint64_t messages_sent;
+  uint64_t messages_received;
+  /// Payload bytes, excluding the channel envelope
+  uint64_t bytes_sent;
+  uint64_t bytes_received;
+  /// Sent messages the peer acknowledged
+  uint64_t messages_acked;
+  /// Reliable messages awaiting acknowledgement
+  uint64_t messages_unacked;
+  /// Reliable messages sent again after a session resumed
+  uint64_t messages_retransmitted;
+  /// Reliable messages given up on because too many were unacknowledged
+  uint64_t messages_abandoned;
+  /// Sequenced messages dropped for arriving after a newer one
+  uint64_t stale_dropped;
+  /// Reliable messages dropped for arriving again
+  uint64_t duplicates_dropped;
+  /// Smoothed acknowledgement round-trip time (0 until the first acknowledgement)
+  uint64_t rtt_us;
+};
+
+/// A message passed to a middleware callback
+struct DwebbleWSMiddlewareMessage {
+  /// True for messages from a client, false for messages to one
+  bool inbound;
+  uint64_t connection_id;
+  /// Whether this is a text message. A callback returning `Modify` may change it;
+  /// replacements that are not valid UTF-8 become binary messages.
+  bool is_text;
+  /// Message content, valid for the duration of the callback
+  const uint8_t *data;
+  uintptr_t data_len;
+  /// New content when returning `Modify`, allocated with `dwebble_rws_alloc_buffer`.
+  /// The library takes ownership of it.
+  DwebbleWSBuffer replacement;
+};
+
+/// Middleware callback, called with the `user_data` it was registered with.
+/// May be called from any thread, including several at once.
+using DwebbleWSMiddlewareCallback = DwebbleWSMiddlewareAction(*)(void *user_data,
+                                                                 DwebbleWSMiddlewareMessage *message);
+
+/// Statistics of the connections with a tag
+struct DwebbleWSTagStats {
+  /// Live connections with the tag
+  uint64_t connections;
+  /// Suspended sessions with the tag, awaiting a reconnect
+  uint64_t suspended;
+  /// Bytes queued but not yet written to the tagged connections' sockets
+  uint64_t queued_bytes;
+};
+
+/// Information about a connection
+struct DwebbleWSConnectionInfo {
+  /// Whether messages are protected by application-layer encryption
+  bool encrypted;
+  /// Times the connection's encryption keys were rotated
+  uint64_t key_rotations;
+  /// JA3 hash of the client's TLS ClientHello, all zero without TLS
+  uint8_t tls_fingerprint[16];
+  /// Hash of the order of the handshake's request header names
+  uint64_t header_fingerprint;
+  /// Bytes queued but not yet written to the socket
+  uint64_t queued_bytes;
+  /// Messages sent with a TTL and dropped from the send queue once it ran out
+  uint64_t expired_messages;
+  /// Compression negotiated for the connection's WebSocket messages
+  DwebbleWSCompression compression;
+  /// Compression level (0 without compression)
+  int32_t compression_level;
+  /// ID of the compression dictionary (0 without one, or for a raw content dictionary)
+  uint32_t dictionary_id;
+  /// Payload bytes of WebSocket data messages sent, before compression
+  uint64_t raw_bytes_sent;
+  /// Payload bytes of WebSocket data messages sent, after compression
+  uint64_t compressed_bytes_sent;
+  /// Payload bytes of WebSocket data messages received, before decompression
+  uint64_t compressed_bytes_received;
+  /// Payload bytes of WebSocket data messages received, after decompression
+  uint64_t raw_bytes_received;
+  /// Whether a protocol version was negotiated under the `protocol_version` setting
+  bool version_negotiated;
+  /// Major version of the negotiated protocol (0 without one)
+  uint16_t version_major;
+  /// Minor version of the negotiated protocol (0 without one)
+  uint16_t version_minor;
+};
+
+/// Resource usage of a server. Memory is approximated by the bytes held in its
+/// queues and buffers.
+struct DwebbleWSResourceUsage {
+  /// Worker threads of the runtime (0 while stopped or in `single-thread` builds)
+  uint64_t worker_threads;
+  /// Other threads of the server, such as the watchdog's
+  uint64_t helper_threads;
+  /// Tasks alive on the runtime
+  uint64_t tasks;
+  /// Tasks waiting in the runtime's global queue
+  uint64_t queued_tasks;
+  /// Time the workers have spent busy since the server started, in milliseconds
+  uint64_t busy_ms;
+  uint64_t connections;
+  /// Bytes queued to send, over all connections
+  uint64_t send_queue_bytes;
+  /// Bytes buffered for suspended sessions
+  uint64_t session_buffer_bytes;
+  /// Events on the server's queue not yet polled
+  uint64_t queued_events;
+  /// Data and error message bytes of those events
+  uint64_t event_queue_bytes;
+};
+
+/// WebSocket client handle (opaque, checked on every call)
+using DwebbleWSClientHandle = void*;
+
+/// Information about a client connection
+struct DwebbleWSClientInfo {
+  /// What the server's certificate is trusted by
+  DwebbleWSCertificateVerification certificate_verification;
+};
+
+/// Service pool handle (opaque, checked on every call)
+using DwebbleWSServicePoolHandle = void*;
+
+/// Statistics of a service pool
+struct DwebbleWSServicePoolStats {
+  /// Connections of the pool, open or not
+  uint32_t connections;
+  /// Connections open and answering health checks, which requests go out on
+  uint32_t healthy_connections;
+  /// Requests awaiting a response
+  uint64_t pending_requests;
+};
+
+/// Load test handle (opaque, checked on every call)
+using DwebbleWSLoadTestHandle = void*;
+
+/// Aggregate statistics of a load test
+struct DwebbleWSLoadTestStats {
+  /// Time since the load test started
+  uint64_t elapsed_ms;
+  /// Clients currently connected
+  uint64_t active_clients;
+  /// Clients that failed to connect
+  uint64_t connect_failures;
+  /// Clients whose connection ended
+  uint64_t disconnects;
+  uint64_t messages_sent;
+  uint64_t messages_received;
+  uint64_t bytes_sent;
+  uint64_t bytes_received;
+  /// Average messages sent per second since the start
+  double send_rate;
+  /// Average messages received per second since the start
+  double receive_rate;
+  /// Number of echoed messages the latency figures are based on
+  uint64_t latency_samples;
+  uint64_t latency_min_us;
+  uint64_t latency_avg_us;
+  uint64_t latency_max_us;
+};
+
+/// zstd dictionary trainer handle (opaque, checked on every call)
+using DwebbleWSDictTrainerHandle = void*;
+
+/// LAN discovery announcement handle (opaque, checked on every call)
+using DwebbleWSAnnouncementHandle = void*;
+
+/// LAN discovery browser handle (opaque, checked on every call)
+using DwebbleWSBrowserHandle = void*;
+
+/// Statistics of the pool of event payload buffers, shared by every server and client
+struct DwebbleWSPoolStats {
+  /// Payloads copied into a reused buffer
+  uint64_t hits;
+  /// Payloads copied into a newly allocated buffer
+  uint64_t misses;
+  /// Payloads too large to pool
+  uint64_t unpooled;
+  /// Buffers waiting in the pool
+  uint64_t free_buffers;
+  /// Capacity of the buffers waiting in the pool, in bytes
+  uint64_t free_bytes;
+};
 
 extern "C" {
 
+/// Allocate the strings and buffers the library hands to the host, and the data of
+/// polled events, with `alloc_fn` and `free_fn`, called with `user_data`. Must be
+/// called before any function that returns a string or buffer; returns `AlreadyRunning` if one already did or an
+/// allocator is already set, and `InvalidParam` if either function is null.
+///
+/// # Safety
+///
+/// - `alloc_fn` and `free_fn` must be callable from any thread with `user_data` for
+///   as long as the library is loaded
+
+DwebbleWSResult dwebble_rws_set_allocator(DwebbleWSAllocFn alloc_fn,
+                                          DwebbleWSFreeFn free_fn,
+                                          void *user_data)
+;
+
 /// Initialize tracing (optional, call once)
  void dwebble_rws_init_tracing() ;
 
@@ -83,33 +525,284 @@ extern "C" {
 /// # Safety
 ///
 /// - `config` must be a valid pointer to a `DwebbleWSServerConfig`
-/// - All string fields in `config` must be valid null-terminated UTF-8 or null
+/// - All string fields in `config` must be null, valid for their `_len` bytes, or
+///   null-terminated if their `_len` is 0
  DwebbleWSServerHandle dwebble_rws_server_create(const DwebbleWSServerConfig *config) ;
 
-/// Destroy a server handle and free resources.
+/// Destroy a server handle and free resources. A server still running is stopped,
+/// once any calls with it on other threads return.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`, or null
-/// - `handle` must not be used after this call
  void dwebble_rws_server_destroy(DwebbleWSServerHandle handle) ;
 
-/// Start the WebSocket server.
+/// Start the WebSocket server. Returns `Busy` if another thread is starting,
+/// rebinding or stopping it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
  DwebbleWSResult dwebble_rws_server_start(DwebbleWSServerHandle handle) ;
 
-/// Stop the WebSocket server.
+/// Stop the WebSocket server, after the calls using its runtime on other threads
+/// return. Returns `Busy` if another thread is starting, rebinding or stopping it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
  DwebbleWSResult dwebble_rws_server_stop(DwebbleWSServerHandle handle) ;
 
+/// Stop the WebSocket server on a background thread and return at once.
+///
+/// The stop closes connections as `dwebble_rws_server_stop` does; the
+/// `ServerStopped` event (and the stopped callback, on that thread) reports when
+/// it is done. Starting the server again or destroying it waits for the stop to
+/// finish. Builds with the `single-thread` feature stop before returning.
+/// Returns `NotRunning` if the server is not running, and `Busy` as
+/// `dwebble_rws_server_stop` does.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_stop_async(DwebbleWSServerHandle handle) ;
+
+/// Stop the WebSocket server immediately: no Close frames are sent and
+/// outstanding tasks are aborted rather than awaited, so every open connection
+/// is dropped and counts as force-closed in the `ServerStopped` event. Returns
+/// `NotRunning` if the server is not running, and `Busy` as
+/// `dwebble_rws_server_stop` does.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_force_stop(DwebbleWSServerHandle handle) ;
+
+/// Move the server's WebSocket listener to a new port, address or TLS
+/// certificate without dropping connections.
+///
+/// The new listener is bound before the old one closes, so existing connections
+/// carry on uninterrupted. If the new address can't be bound (`BindFailed`) or
+/// the certificate can't be loaded (`TlsError`), the old listener stays.
+/// WebTransport, raw and port-mapped listeners keep their ports. Returns
+/// `NotRunning` if the server is not running, and `Busy` if another thread is
+/// starting, rebinding or stopping it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `config` must be a valid pointer to a `DwebbleWSBindConfig` whose string
+///   fields are null, valid for their `_len` bytes, or null-terminated if their
+///   `_len` is 0
+
+DwebbleWSResult dwebble_rws_server_rebind(DwebbleWSServerHandle handle,
+                                          const DwebbleWSBindConfig *config)
+;
+
+/// Run the server's pending work on the calling thread for up to `budget_ms`
+/// milliseconds.
+///
+/// Builds with the `single-thread` feature create no threads of their own, so
+/// the host calls this regularly (e.g. every frame) from one thread while the
+/// server runs; clients opened on the server make progress here too. Other
+/// builds return at once.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_tick(DwebbleWSServerHandle handle, uint32_t budget_ms) ;
+
+/// Register a callback run at the end of every stop of a running server, after
+/// the `ServerStopped` event is queued. By then every connection is closed and
+/// the runtime has shut down, so nothing of the server runs any more. It runs on
+/// the thread that stops the server, which is a background thread for
+/// `dwebble_rws_server_stop_async`. Replaces any earlier callback; null removes it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread
+
+DwebbleWSResult dwebble_rws_server_set_stopped_callback(DwebbleWSServerHandle handle,
+                                                        DwebbleWSStoppedCallback callback,
+                                                        void *user_data)
+;
+
+/// Register a callback run with each health warning of the `watchdog` setting, on
+/// the watchdog thread, as the `HealthWarning` event is raised. It still reaches the
+/// host when the event queue is stalled. Replaces any earlier callback once a call in
+/// progress returns, so its user data may be freed then; null removes it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread
+
+DwebbleWSResult dwebble_rws_server_set_health_callback(DwebbleWSServerHandle handle,
+                                                       DwebbleWSHealthCallback callback,
+                                                       void *user_data)
+;
+
+/// Register a callback run on each thread of the server's runtime as it starts,
+/// before it runs any task, to apply the platform's own core affinity and priority
+/// where the `threads` setting can't. Takes effect from the next start; fails with
+/// `AlreadyRunning` until the server has stopped, so the earlier callback's user
+/// data may be freed once this succeeds, and with `Busy` while another thread is
+/// starting or stopping it. Null removes it. Never called in `single-thread` builds.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread
+
+DwebbleWSResult dwebble_rws_server_set_thread_callback(DwebbleWSServerHandle handle,
+                                                       DwebbleWSThreadCallback callback,
+                                                       void *user_data)
+;
+
+/// Set the dictionary of zstd compression (the `zstd` setting), or compress without one
+/// if `data` is null. Clients must compress with the same dictionary; connections
+/// already open keep the dictionary they negotiated. Returns `InvalidParam` without the
+/// `zstd` setting.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be null or a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_set_zstd_dictionary(DwebbleWSServerHandle handle,
+                                                       const uint8_t *data,
+                                                       uintptr_t data_len)
+;
+
+/// Get the dictionary of zstd compression negotiated from now on, e.g. to ship to
+/// clients; the buffer is empty without one. Returns `InvalidParam` without the `zstd`
+/// setting. Free the buffer with `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+
+DwebbleWSResult dwebble_rws_server_get_zstd_dictionary(DwebbleWSServerHandle handle,
+                                                       DwebbleWSBuffer *out_buffer)
+;
+
+/// Apply hot-changeable settings to a server without dropping connections.
+///
+/// `json` is an object with any of: `subprotocols`, `max_connections`,
+/// `max_message_size`, `handshake_timeout_ms`, `idle_timeout_ms`, `hold_messages`,
+/// `authentication`, `allowed_origins`, `log_level`, `close_code`, `close_reason`,
+/// `close_grace_ms`, `shutdown_timeout_ms`, `slow_client`, `send_queue_limit`,
+/// `rate_limit`, `event_budget`, `utf8_policy`, `sessions`, `network_sim`, `json_rpc`,
+/// `typed_codec`, `wasm_filters`, `schemas`. Handshake-time settings apply to connections accepted
+/// after the update; replaced WASM filters and schemas are reloaded from disk.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string
+ DwebbleWSResult dwebble_rws_server_update_config(DwebbleWSServerHandle handle, const char *json) ;
+
+/// Override the simulated network conditions of one connection.
+///
+/// `json` uses the same fields as the `network_sim` setting (`latency_ms`,
+/// `jitter_ms`, `drop_rate`, `disconnect_rate`, `bandwidth_bytes_per_sec`);
+/// `{}` disables simulation for the connection. Pass null to follow the
+/// server-wide setting again.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string, or null
+
+DwebbleWSResult dwebble_rws_server_set_network_sim(DwebbleWSServerHandle handle,
+                                                   DwebbleWSConnectionId connection_id,
+                                                   const char *json)
+;
+
+/// Schedule a scripted chaos scenario on a running server.
+///
+/// `json` is an object with a `steps` array. Each step has an `at_ms` offset
+/// from now and an `action`:
+/// - `disconnect` with `percent` and optional `abrupt` (default true: drop
+///   without a closing handshake)
+/// - `stall_writers` with `percent` and `duration_ms`
+/// - `refuse_handshakes` with `duration_ms`
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string
+ DwebbleWSResult dwebble_rws_server_schedule_chaos(DwebbleWSServerHandle handle, const char *json) ;
+
+/// Cancel every scheduled chaos scenario, resuming stalled writers and handshakes.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_cancel_chaos(DwebbleWSServerHandle handle) ;
+
+/// Schedule a maintenance shutdown of a running server at `at_unix_ts`, in seconds
+/// since the Unix epoch, replacing any scheduled before. `warning_message` (null for
+/// none) is broadcast as text to every connection `warning_intervals[i]` seconds
+/// before the shutdown, with `{seconds}` replaced by the seconds left; intervals
+/// already past are skipped.
+///
+/// At the time, new handshakes are refused with 503, every connection is closed
+/// with the `close_code` and `close_reason` settings and suspended sessions end.
+/// A `ShutdownDue` event follows once the connections are gone, or after
+/// `close_grace_ms`: stop the server then.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `warning_message` must be null or a valid null-terminated UTF-8 string
+/// - `warning_intervals` must point to `warning_count` values, or be null if it is 0
+
+DwebbleWSResult dwebble_rws_server_schedule_shutdown(DwebbleWSServerHandle handle,
+                                                     uint64_t at_unix_ts,
+                                                     const char *warning_message,
+                                                     const uint32_t *warning_intervals,
+                                                     uintptr_t warning_count)
+;
+
+/// Cancel a scheduled shutdown. If it was already due, new handshakes are taken
+/// again; connections it closed stay closed.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_cancel_shutdown(DwebbleWSServerHandle handle) ;
+
+/// Ask a STUN server (`host[:port]`, port 3478 by default) for the server's
+/// public IP and port, e.g. to register it with a matchmaking backend. The
+/// result arrives as a `PublicEndpoint` event, or an `Error` event on failure.
+/// Returns `Busy` while another thread is starting, rebinding or stopping the server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `stun_server` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_discover_public_endpoint(DwebbleWSServerHandle handle,
+                                                            const char *stun_server)
+;
+
+/// Choose which event types are raised: bit N of `mask` enables the type with value
+/// N (`1 << DwebbleWSEventType`), and all are enabled initially. Disabled events are
+/// dropped at the source instead of queueing for `dwebble_rws_server_poll`, though
+/// recordings still capture them. The mask is kept across restarts.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSResult dwebble_rws_server_set_event_mask(DwebbleWSServerHandle handle, uint64_t mask) ;
+
 /// Poll for the next event. Returns the event in the out parameter.
-/// Returns true if an event was available, false otherwise.
+/// Returns true if an event was available, false otherwise. Its data stays valid
+/// until the next poll of the same server. The events raised before a stop, ending
+/// with `ServerStopped`, can still be polled after it.
 ///
 /// # Safety
 ///
@@ -117,8 +810,67 @@ extern "C" {
 /// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
  bool dwebble_rws_server_poll(DwebbleWSServerHandle handle, DwebbleWSEvent *out_event) ;
 
+/// Poll for up to `max_events` events at once, e.g. every event of a frame, for
+/// less overhead per event than `dwebble_rws_server_poll`. Returns the number of
+/// events written to `out_events`. Their data stays valid until the next batch poll of
+/// the same server, independently of `dwebble_rws_server_poll`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_events` must point to an array of at least `max_events` `DwebbleWSEvent`s
+
+uintptr_t dwebble_rws_server_poll_batch(DwebbleWSServerHandle handle,
+                                        DwebbleWSEvent *out_events,
+                                        uintptr_t max_events)
+;
+
+/// Open a queue taking the events of a room's members, to poll on a thread of its
+/// own with `dwebble_rws_event_queue_poll`, e.g. one per match. Events of a
+/// connection in the room go to its queue instead of `dwebble_rws_server_poll`
+/// (to the earliest opened queue if it is in several rooms with one), up to and
+/// including its `ClientDisconnected` event. Returns null for an empty room.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+
+DwebbleWSEventQueueHandle dwebble_rws_server_open_event_queue(DwebbleWSServerHandle handle,
+                                                              const char *room)
+;
+
+/// Poll for the next event of an event queue. Returns true if an event was
+/// available. Its data stays valid until the next poll of the same queue, and
+/// different queues may be polled concurrently.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_open_event_queue`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+ bool dwebble_rws_event_queue_poll(DwebbleWSEventQueueHandle handle, DwebbleWSEvent *out_event) ;
+
+/// Close an event queue. Events still queued are dropped, and later events of its
+/// room's members go to the server's queue again.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_open_event_queue`, or null
+ void dwebble_rws_event_queue_close(DwebbleWSEventQueueHandle handle) ;
+
 /// Send binary data to a specific connection.
 ///
+/// Binary messages starting with `DW` and two uppercase ASCII letters are the
+/// library's control frames, never raised as `MessageReceived`. Every binary send and
+/// broadcast puts data starting like one behind a `DWES` prefix for the client to
+/// strip, and clients must do the same; the library's client does both.
+///
+/// Returns `ConnectionClosed` if the connection has closed or is suspended without a
+/// replay buffer, `QueueFull` if the message would take its send queue over
+/// `send_queue_limit`, `InvalidHandle` if the connection ID was never issued, and
+/// `NotRunning` unless the server is running. See
+/// `dwebble_rws_server_get_last_send_error` for details.
+///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
@@ -130,55 +882,1479 @@ DwebbleWSResult dwebble_rws_server_send(DwebbleWSServerHandle handle,
                                         uintptr_t data_len)
 ;
 
-/// Send text data to a specific connection.
+/// Send binary data to a specific connection under a dedupe key. If a message queued
+/// under the same key earlier hasn't been sent yet, it is dropped and this one goes out
+/// instead, so "latest state wins" updates don't pile up in a slow client's queue.
+///
+/// Returns as `dwebble_rws_server_send`.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
-/// - `text` must be a valid null-terminated UTF-8 string
+/// - `key` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
 
-DwebbleWSResult dwebble_rws_server_send_text(DwebbleWSServerHandle handle,
-                                             DwebbleWSConnectionId connection_id,
-                                             const char *text)
+DwebbleWSResult dwebble_rws_server_send_with_key(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 const char *key,
+                                                 const uint8_t *data,
+                                                 uintptr_t data_len)
 ;
 
-/// Disconnect a specific connection.
+/// Send binary data to a specific connection with a time-to-live. If the message is
+/// still queued `ttl_ms` milliseconds later, e.g. behind a slow client's backlog, it is
+/// dropped instead of sent, and counted in the connection's `expired_messages`.
+///
+/// Returns as `dwebble_rws_server_send`.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be a valid pointer to `data_len` bytes
 
-DwebbleWSResult dwebble_rws_server_disconnect(DwebbleWSServerHandle handle,
-                                              DwebbleWSConnectionId connection_id)
+DwebbleWSResult dwebble_rws_server_send_with_ttl(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 const uint8_t *data,
+                                                 uintptr_t data_len,
+                                                 uint32_t ttl_ms)
 ;
 
-/// Get the actual port the server is listening to.
+/// Send binary data wrapped in a typed envelope (`[type_id, payload]`) encoded
+/// with the `typed_codec` setting. Returns `InvalidParam` if no codec is configured.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
- uint16_t dwebble_rws_server_get_port(DwebbleWSServerHandle handle) ;
+/// - `data` must be valid for `data_len` bytes
 
-/// Get the number of active connections.
+DwebbleWSResult dwebble_rws_server_send_typed(DwebbleWSServerHandle handle,
+                                              DwebbleWSConnectionId connection_id,
+                                              uint32_t type_id,
+                                              const uint8_t *data,
+                                              uintptr_t data_len)
+;
+
+/// Send binary data on a virtual channel configured in the `channels` setting,
+/// delivered as a `ChannelMessage` event by peers speaking the channel envelope.
+/// Returns `InvalidParam` if the channel is not configured.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
- uintptr_t dwebble_rws_server_get_connection_count(DwebbleWSServerHandle handle) ;
+/// - `data` must be valid for `data_len` bytes
 
-/// Get server info string. Caller must free with `dwebble_rws_free_string`.
+DwebbleWSResult dwebble_rws_server_send_channel(DwebbleWSServerHandle handle,
+                                                DwebbleWSConnectionId connection_id,
+                                                uint8_t channel,
+                                                const uint8_t *data,
+                                                uintptr_t data_len)
+;
+
+/// Get the statistics of a virtual channel of a connection (all zero if
+/// nothing was sent or received on it).
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
- char *dwebble_rws_server_info(DwebbleWSServerHandle handle) ;
+/// - `out_stats` must be a valid pointer to a `DwebbleWSChannelStats`
 
-/// Free a string allocated by this library.
+DwebbleWSResult dwebble_rws_server_channel_stats(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 uint8_t channel,
+                                                 DwebbleWSChannelStats *out_stats)
+;
+
+/// Register a middleware callback run on every text and binary message, with its
+/// ID written to `out_id`. Inbound messages pass through the middleware in
+/// ascending `priority` order (ties in registration order) before the library
+/// handles them, and outbound messages in descending order just before they are
+/// queued, so layers such as compression and encryption nest. A callback can
+/// pass a message on, replace it or drop it. Middleware stays registered until
+/// removed or the server is destroyed.
 ///
 /// # Safety
 ///
-/// - `s` must be a string returned by `dwebble_rws_server_info`, or null
-/// - `s` must not be used after this call
- void dwebble_rws_free_string(char *s) ;
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread until removed
+/// - `out_id` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_server_add_middleware(DwebbleWSServerHandle handle,
+                                                  DwebbleWSMiddlewareCallback callback,
+                                                  void *user_data,
+                                                  int32_t priority,
+                                                  uint64_t *out_id)
+;
+
+/// Unregister a middleware callback. Messages already passing through the chain
+/// may still reach it. Returns `InvalidParam` if the ID is unknown.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_remove_middleware(DwebbleWSServerHandle handle,
+                                                     uint64_t middleware_id)
+;
+
+/// Send the latest version of a keyed state blob to a connection. The library
+/// keeps the versions it sent and sends a delta against the one the client last
+/// acknowledged where that is smaller, with a full state every
+/// `delta.full_snapshot_interval` updates. Loopback clients rebuild the state and
+/// raise `StateUpdated`. Returns `InvalidParam` if the `delta` setting is null.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_send_state(DwebbleWSServerHandle handle,
+                                              DwebbleWSConnectionId connection_id,
+                                              uint32_t key,
+                                              const uint8_t *data,
+                                              uintptr_t data_len)
+;
+
+/// Send binary data numbered with the connection's next sequence, written to
+/// `out_sequence`. The data is wrapped in an envelope (`DWSQ`, u64 sequence,
+/// payload) and the client acknowledges it with `DWSK` and the sequence; loopback
+/// clients do so themselves and raise `MessageReceived` with the sequence as the
+/// request ID. Returns `InvalidParam` if the `receipts` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+/// - `out_sequence` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_server_send_sequenced(DwebbleWSServerHandle handle,
+                                                  DwebbleWSConnectionId connection_id,
+                                                  const uint8_t *data,
+                                                  uintptr_t data_len,
+                                                  uint64_t *out_sequence)
+;
+
+/// Get the highest sequence of sequenced sends a connection acknowledged (0 if none).
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+uint64_t dwebble_rws_server_get_acked_sequence(DwebbleWSServerHandle handle,
+                                               DwebbleWSConnectionId connection_id)
+;
+
+/// Sign a connection's messages with an HMAC-SHA256 key and require the messages
+/// it sends to be signed with the same key, e.g. once it authenticated. Messages
+/// that fail verification are dropped with a `SignatureInvalid` event. Pass a null
+/// or empty key to stop signing.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `key` must be valid for `key_len` bytes (or null)
+
+DwebbleWSResult dwebble_rws_server_set_signing_key(DwebbleWSServerHandle handle,
+                                                   DwebbleWSConnectionId connection_id,
+                                                   const uint8_t *key,
+                                                   uintptr_t key_len)
+;
+
+/// Send an unreliable datagram to a WebTransport connection. Returns `InvalidHandle`
+/// for other connections and `InvalidParam` if the datagram is too large for the path.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_send_datagram(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 const uint8_t *data,
+                                                 uintptr_t data_len)
+;
+
+/// Send text data to a specific connection. Text that is not valid UTF-8 is handled
+/// by the `utf8_policy` setting; the strict policy fails with `InvalidUtf8`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `text` must be a valid null-terminated string
+
+DwebbleWSResult dwebble_rws_server_send_text(DwebbleWSServerHandle handle,
+                                             DwebbleWSConnectionId connection_id,
+                                             const char *text)
+;
+
+/// Send text data given as `text_len` bytes, which may contain null bytes, to a
+/// specific connection. Otherwise like `dwebble_rws_server_send_text`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `text` must be a valid pointer to `text_len` bytes
+
+DwebbleWSResult dwebble_rws_server_send_text_len(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 const uint8_t *text,
+                                                 uintptr_t text_len)
+;
+
+/// Get why the latest failed send to a connection failed, kept until the connection is
+/// removed. Returns null if none failed. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+char *dwebble_rws_server_get_last_send_error(DwebbleWSServerHandle handle,
+                                             DwebbleWSConnectionId connection_id)
+;
+
+/// Disconnect a specific connection. Returns `NotRunning` unless the server is
+/// running, as its connections are closed by a stop.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_disconnect(DwebbleWSServerHandle handle,
+                                              DwebbleWSConnectionId connection_id)
+;
+
+/// Stop reading a connection's messages until `dwebble_rws_server_resume_read`, so
+/// the host can work through a backlog. The client is held back by TCP flow
+/// control once the socket buffers fill up; the idle timeout doesn't apply
+/// meanwhile. Messages already read are still delivered.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_pause_read(DwebbleWSServerHandle handle,
+                                              DwebbleWSConnectionId connection_id)
+;
+
+/// Resume reading a connection paused with `dwebble_rws_server_pause_read`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_resume_read(DwebbleWSServerHandle handle,
+                                               DwebbleWSConnectionId connection_id)
+;
+
+/// Acknowledge a connection's `ClientConnected` event, delivering the messages held
+/// back under the `hold_messages` setting, in order, and any after them as they
+/// arrive. Call it once the host is ready for the connection's messages, e.g. after
+/// setting up its per-player state. Does nothing for a connection holding none.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_acknowledge_connection(DwebbleWSServerHandle handle,
+                                                          DwebbleWSConnectionId connection_id)
+;
+
+/// Mark a connection authenticated, e.g. once its login message checks out. With the
+/// `authentication` setting, a new connection's messages stop being read after its
+/// login messages until this is called, and it is closed with code 1008 if it isn't
+/// called within the setting's `timeout_ms`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_mark_authenticated(DwebbleWSServerHandle handle,
+                                                      DwebbleWSConnectionId connection_id)
+;
+
+/// Rotate a connection's application-layer encryption keys with a fresh key exchange
+/// inside the encrypted session. `KeyRotated` is raised once both directions use the
+/// new keys; a client that doesn't answer within `handshake_timeout_ms` is closed.
+/// Keys also rotate on their own with the `encryption` setting's `rekey_interval_ms`.
+///
+/// Returns `InvalidParam` if the connection has not completed a key exchange.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_rekey(DwebbleWSServerHandle handle,
+                                         DwebbleWSConnectionId connection_id)
+;
+
+/// Hand a live connection over to `target`, another running server in this
+/// process, without the client noticing. The socket, queued messages and
+/// encryption keys move along and the connection keeps its ID. This server raises
+/// `ClientDisconnected` with the error "Migrated to another server", the target
+/// raises `ClientConnected`. Rooms, topics, sessions and protocol state stay
+/// behind, and the target's connection limit and origin checks don't apply.
+///
+/// Returns `InvalidParam` for connections on HTTP/2, in bridge mode or still
+/// exchanging or rotating keys, and `ConnectionClosed` if the connection closed meanwhile.
+///
+/// # Safety
+///
+/// - `handle` and `target` must be valid handles returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_migrate_connection(DwebbleWSServerHandle handle,
+                                                      DwebbleWSConnectionId connection_id,
+                                                      DwebbleWSServerHandle target)
+;
+
+/// Close every connection with a Close frame of `code` and `reason` (null for
+/// none), e.g. at the end of a match. Suspended sessions are ended as well. Each
+/// connection raises `ClientDisconnected` once closed. Returns `InvalidParam` if
+/// `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_kick_all(DwebbleWSServerHandle handle,
+                                            uint16_t code,
+                                            const char *reason)
+;
+
+/// Close every connection from an IP address with a Close frame of `code` and
+/// `reason` (null for none). Returns `InvalidParam` if `ip` is not an IP address or
+/// `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_kick_ip(DwebbleWSServerHandle handle,
+                                           const char *ip,
+                                           uint16_t code,
+                                           const char *reason)
+;
+
+/// Close every member of a room with a Close frame of `code` and `reason` (null
+/// for none). Suspended members have their session ended. Returns `InvalidParam`
+/// if `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_kick_room(DwebbleWSServerHandle handle,
+                                             const char *room,
+                                             uint16_t code,
+                                             const char *reason)
+;
+
+/// Refuse connections from an IP address for `duration_ms` (0 until unbanned), and
+/// close those open with a Close frame of code 1008 and `reason` (null for none).
+/// Bans are kept across restarts. Returns `InvalidParam` if `ip` is not an IP address.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_ban_ip(DwebbleWSServerHandle handle,
+                                          const char *ip,
+                                          uint64_t duration_ms,
+                                          const char *reason)
+;
+
+/// Lift the ban of an IP address. Returns `InvalidParam` if `ip` is not an IP
+/// address or isn't banned.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+ DwebbleWSResult dwebble_rws_server_unban_ip(DwebbleWSServerHandle handle, const char *ip) ;
+
+/// Add a connection to a room. With the `history` setting's `replay_on_join`, a
+/// new member is sent the room's history first.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_room_join(DwebbleWSServerHandle handle,
+                                             DwebbleWSConnectionId connection_id,
+                                             const char *room)
+;
+
+/// Remove a connection from a room. Returns `InvalidParam` if it was not a member.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_room_leave(DwebbleWSServerHandle handle,
+                                              DwebbleWSConnectionId connection_id,
+                                              const char *room)
+;
+
+/// Read up to `max_messages` of the latest messages broadcast to a room, oldest
+/// first, kept with the `history` setting. The messages are returned back to back,
+/// each laid out as (little-endian) a u8 kind (0 = binary, 1 = text), a u32
+/// payload length and the payload. Free the buffer with `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+
+DwebbleWSResult dwebble_rws_server_get_history(DwebbleWSServerHandle handle,
+                                               const char *room,
+                                               uintptr_t max_messages,
+                                               DwebbleWSBuffer *out_buffer)
+;
+
+/// Forget the history of a room. Returns `InvalidParam` if it has none.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+ DwebbleWSResult dwebble_rws_server_clear_history(DwebbleWSServerHandle handle, const char *room) ;
+
+/// Send binary data to every member of a room.
+/// With clustering enabled, members connected to sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_room_broadcast(DwebbleWSServerHandle handle,
+                                                  const char *room,
+                                                  const uint8_t *data,
+                                                  uintptr_t data_len)
+;
+
+/// Send text to every member of a room.
+/// With clustering enabled, members connected to sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` and `text` must be valid null-terminated UTF-8 strings
+
+DwebbleWSResult dwebble_rws_server_room_broadcast_text(DwebbleWSServerHandle handle,
+                                                       const char *room,
+                                                       const char *text)
+;
+
+/// Send text given as `text_len` bytes, which may contain null bytes, to every member
+/// of a room. Otherwise like `dwebble_rws_server_room_broadcast_text`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `text` must be a valid pointer to `text_len` bytes
+
+DwebbleWSResult dwebble_rws_server_room_broadcast_text_len(DwebbleWSServerHandle handle,
+                                                           const char *room,
+                                                           const uint8_t *text,
+                                                           uintptr_t text_len)
+;
+
+/// Tag a connection, e.g. with `team:red`. Tags are a lighter grouping than rooms:
+/// they stay on this instance. Returns `InvalidParam` for an empty tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_set_tag(DwebbleWSServerHandle handle,
+                                           DwebbleWSConnectionId connection_id,
+                                           const char *tag)
+;
+
+/// Remove a tag from a connection. Returns `InvalidParam` if it did not have the tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_clear_tag(DwebbleWSServerHandle handle,
+                                             DwebbleWSConnectionId connection_id,
+                                             const char *tag)
+;
+
+/// Get the tags of a connection as a sorted JSON array. Caller must free with
+/// `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+char *dwebble_rws_server_get_tags(DwebbleWSServerHandle handle,
+                                  DwebbleWSConnectionId connection_id)
+;
+
+/// Send binary data to every connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_tag_broadcast(DwebbleWSServerHandle handle,
+                                                 const char *tag,
+                                                 const uint8_t *data,
+                                                 uintptr_t data_len)
+;
+
+/// Send text to every connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` and `text` must be valid null-terminated UTF-8 strings
+
+DwebbleWSResult dwebble_rws_server_tag_broadcast_text(DwebbleWSServerHandle handle,
+                                                      const char *tag,
+                                                      const char *text)
+;
+
+/// Send text given as `text_len` bytes, which may contain null bytes, to every
+/// connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `text` must be a valid pointer to `text_len` bytes
+
+DwebbleWSResult dwebble_rws_server_tag_broadcast_text_len(DwebbleWSServerHandle handle,
+                                                          const char *tag,
+                                                          const uint8_t *text,
+                                                          uintptr_t text_len)
+;
+
+/// Close every connection with a tag with a Close frame of `code` and `reason`
+/// (null for none). Suspended sessions with the tag are ended. Returns
+/// `InvalidParam` if `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_kick_tag(DwebbleWSServerHandle handle,
+                                            const char *tag,
+                                            uint16_t code,
+                                            const char *reason)
+;
+
+/// Get the statistics of the connections with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `out_stats` must be a valid pointer to a `DwebbleWSTagStats`
+
+DwebbleWSResult dwebble_rws_server_tag_stats(DwebbleWSServerHandle handle,
+                                             const char *tag,
+                                             DwebbleWSTagStats *out_stats)
+;
+
+/// Pipe two connections to each other, e.g. peers meeting at a rendezvous relay:
+/// their text and binary messages go to the other connection as they are, without
+/// `MessageReceived` events, until `dwebble_rws_server_unpipe` or either
+/// connection ends. Returns `InvalidParam` if they are the same connection or
+/// either is piped already.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_pipe(DwebbleWSServerHandle handle,
+                                        DwebbleWSConnectionId connection_a,
+                                        DwebbleWSConnectionId connection_b)
+;
+
+/// End the pipe of a connection; the messages of both connections raise events
+/// again. Returns `InvalidParam` if the connection is not piped.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_unpipe(DwebbleWSServerHandle handle,
+                                          DwebbleWSConnectionId connection_id)
+;
+
+/// Send binary data as a request and wait up to `timeout_ms` for the response.
+/// The request ID is written to `out_request_id`; a `ResponseReceived` or
+/// `RequestTimedOut` event with that ID reports the outcome.
+///
+/// The data is wrapped in an envelope (`DWRQ`, u64 request ID, payload) and the
+/// client must answer with the same layout using the `DWRS` magic.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+/// - `out_request_id` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_server_request(DwebbleWSServerHandle handle,
+                                           DwebbleWSConnectionId connection_id,
+                                           const uint8_t *data,
+                                           uintptr_t data_len,
+                                           uint32_t timeout_ms,
+                                           uint64_t *out_request_id)
+;
+
+/// Subscribe a connection to a topic pattern. Use connection ID 0 to subscribe
+/// the host, which receives matching publications as `TopicMessage` events.
+///
+/// Topics are dot-separated segments. In patterns, `*` matches exactly one
+/// segment and a trailing `>` matches one or more remaining segments.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `pattern` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_subscribe(DwebbleWSServerHandle handle,
+                                             DwebbleWSConnectionId connection_id,
+                                             const char *pattern)
+;
+
+/// Remove a subscription made with `dwebble_rws_server_subscribe`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `pattern` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_unsubscribe(DwebbleWSServerHandle handle,
+                                               DwebbleWSConnectionId connection_id,
+                                               const char *pattern)
+;
+
+/// Publish binary data to every subscriber whose pattern matches `topic`.
+/// With clustering enabled, subscribers on sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `data` must be valid for `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_publish(DwebbleWSServerHandle handle,
+                                           const char *topic,
+                                           const uint8_t *data,
+                                           uintptr_t data_len)
+;
+
+/// Publish text to every subscriber whose pattern matches `topic`.
+/// With clustering enabled, subscribers on sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `text` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_publish_text(DwebbleWSServerHandle handle,
+                                                const char *topic,
+                                                const char *text)
+;
+
+/// Publish text given as `text_len` bytes, which may contain null bytes, to every
+/// subscriber whose pattern matches `topic`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `text` must be a valid pointer to `text_len` bytes
+
+DwebbleWSResult dwebble_rws_server_publish_text_len(DwebbleWSServerHandle handle,
+                                                    const char *topic,
+                                                    const uint8_t *text,
+                                                    uintptr_t text_len)
+;
+
+/// Publish a message to the MQTT clients subscribed to `topic` (QoS 0 or 1).
+/// A retained message is also sent to later subscribers; publishing an empty
+/// retained payload clears it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `data` must be valid for `data_len` bytes
+
+DwebbleWSResult dwebble_rws_server_mqtt_publish(DwebbleWSServerHandle handle,
+                                                const char *topic,
+                                                const uint8_t *data,
+                                                uintptr_t data_len,
+                                                uint8_t qos,
+                                                bool retain)
+;
+
+/// Answer the JSON-RPC call `call_id` (from an `RpcCall` event) with a result.
+/// Returns `InvalidParam` if the call is unknown or already answered, or if
+/// `result_json` is not valid JSON.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `result_json` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_rpc_reply(DwebbleWSServerHandle handle,
+                                             uint64_t call_id,
+                                             const char *result_json)
+;
+
+/// Answer the JSON-RPC call `call_id` (from an `RpcCall` event) with an error.
+/// `data_json` is optional (null to omit) and must be valid JSON otherwise.
+///
+/// Standard codes: -32601 method not found, -32602 invalid params, -32603
+/// internal error; -32000 to -32099 are reserved for server errors.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `message` must be a valid null-terminated UTF-8 string
+/// - `data_json` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_rpc_error(DwebbleWSServerHandle handle,
+                                             uint64_t call_id,
+                                             int32_t code,
+                                             const char *message,
+                                             const char *data_json)
+;
+
+/// Emit a Socket.IO event to a client that joined the main namespace.
+/// `args_json` is a JSON array of the event's arguments (null for none).
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `event` must be a valid null-terminated UTF-8 string
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_socketio_emit(DwebbleWSServerHandle handle,
+                                                 DwebbleWSConnectionId connection_id,
+                                                 const char *event,
+                                                 const char *args_json)
+;
+
+/// Emit a Socket.IO event and wait up to `timeout_ms` for the client's
+/// acknowledgement. The request ID is written to `out_request_id`; a
+/// `ResponseReceived` event (data: the ack arguments as a JSON array) or a
+/// `RequestTimedOut` event with that ID reports the outcome.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `event` must be a valid null-terminated UTF-8 string
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+/// - `out_request_id` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_server_socketio_emit_with_ack(DwebbleWSServerHandle handle,
+                                                          DwebbleWSConnectionId connection_id,
+                                                          const char *event,
+                                                          const char *args_json,
+                                                          uint32_t timeout_ms,
+                                                          uint64_t *out_request_id)
+;
+
+/// Acknowledge the Socket.IO event with ack ID `ack` (from a `SocketIoEvent`
+/// event). `args_json` is a JSON array of the acknowledgement's arguments
+/// (null for none). Returns `InvalidParam` if the ack is unknown or already sent.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_socketio_ack(DwebbleWSServerHandle handle,
+                                                uint64_t ack,
+                                                const char *args_json)
+;
+
+/// Identify a connection as a logical user (typically after authenticating it),
+/// or clear its identity with a null or empty `user_id`.
+///
+/// A user is online while at least one of its connections is open on any
+/// clustered instance; `PresenceJoined`/`PresenceLeft` events report the changes.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `user_id` must be a valid null-terminated UTF-8 string, or null
+
+DwebbleWSResult dwebble_rws_server_set_user_id(DwebbleWSServerHandle handle,
+                                               DwebbleWSConnectionId connection_id,
+                                               const char *user_id)
+;
+
+/// Get the user ID of a connection. Caller must free with `dwebble_rws_free_string`.
+/// Returns null if the connection has not been identified.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+char *dwebble_rws_server_get_user_id(DwebbleWSServerHandle handle,
+                                     DwebbleWSConnectionId connection_id)
+;
+
+/// Check whether a user is online on this instance or any clustered sibling.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `user_id` must be a valid null-terminated UTF-8 string
+ bool dwebble_rws_server_is_user_online(DwebbleWSServerHandle handle, const char *user_id) ;
+
+/// Get the online users as a JSON array of user IDs. Caller must free with
+/// `dwebble_rws_free_string`.
+///
+/// With a null `room`, lists the users online on any clustered instance.
+/// With a room, lists the identified members of that room on this instance.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string, or null
+ char *dwebble_rws_server_get_online_users(DwebbleWSServerHandle handle, const char *room) ;
+
+/// Get the session token of a connection. Caller must free with `dwebble_rws_free_string`.
+/// Returns null if sessions are disabled or the connection is unknown.
+///
+/// Clients present the token on reconnect in the `Dwebble-Session` header or
+/// the `dwebble_session` query parameter.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+char *dwebble_rws_server_get_session_token(DwebbleWSServerHandle handle,
+                                           DwebbleWSConnectionId connection_id)
+;
+
+/// Include or exclude a connection from the message journal.
+/// Only relevant when the journal's `all_connections` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+
+DwebbleWSResult dwebble_rws_server_journal_select(DwebbleWSServerHandle handle,
+                                                  DwebbleWSConnectionId connection_id,
+                                                  bool enabled)
+;
+
+/// Include or exclude the members of a room from the message journal, for as long as
+/// they are members. Only relevant when the journal's `all_connections` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+
+DwebbleWSResult dwebble_rws_server_journal_select_room(DwebbleWSServerHandle handle,
+                                                       const char *room,
+                                                       bool enabled)
+;
+
+/// Read up to `max_records` journal records starting at sequence `from_seq`.
+/// The records are returned back to back in their on-disk layout (see the
+/// `journal` module). Free the buffer with `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+
+DwebbleWSResult dwebble_rws_server_journal_read(DwebbleWSServerHandle handle,
+                                                uint64_t from_seq,
+                                                uintptr_t max_records,
+                                                DwebbleWSBuffer *out_buffer)
+;
+
+/// Query the message archive of the `archive` setting. `query_json` holds the
+/// filters, each optional: `room`, `connection_id`, a time range of `from_ms`
+/// (inclusive) and `to_ms` (exclusive) in milliseconds since the Unix epoch,
+/// `after_id` to page on from the last row read, and `limit` (default 1000). The
+/// rows are returned back to back, oldest first, in the layout of the `archive`
+/// module. Free the buffer with `dwebble_rws_free_buffer`. Returns `InvalidParam`
+/// without the `archive` setting or in builds without the `archive` feature.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `query_json` must be a valid null-terminated UTF-8 string, or null for every row
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+
+DwebbleWSResult dwebble_rws_server_archive_query(DwebbleWSServerHandle handle,
+                                                 const char *query_json,
+                                                 DwebbleWSBuffer *out_buffer)
+;
+
+/// Get the actual port the server is listening to.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ uint16_t dwebble_rws_server_get_port(DwebbleWSServerHandle handle) ;
+
+/// Get where the server is in its lifecycle. Sending to and disconnecting its
+/// connections fail with `NotRunning` unless it is `Running`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_state` must be a valid pointer to a `DwebbleWSServerState`
+
+DwebbleWSResult dwebble_rws_server_get_state(DwebbleWSServerHandle handle,
+                                             DwebbleWSServerState *out_state)
+;
+
+/// Get the number of active connections.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ uintptr_t dwebble_rws_server_get_connection_count(DwebbleWSServerHandle handle) ;
+
+/// Get the number of message events dropped over the `event_budget` setting since
+/// the server was created.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ uint64_t dwebble_rws_server_get_dropped_event_count(DwebbleWSServerHandle handle) ;
+
+/// Get information about a connection. Returns `InvalidHandle` for unknown
+/// connections, including suspended sessions.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_info` must be a valid pointer to a `DwebbleWSConnectionInfo`
+
+DwebbleWSResult dwebble_rws_server_get_connection_info(DwebbleWSServerHandle handle,
+                                                       DwebbleWSConnectionId connection_id,
+                                                       DwebbleWSConnectionInfo *out_info)
+;
+
+/// Get the resource usage of a server: its threads and tasks, and the bytes held in
+/// its send queues, session buffers and event queue. The runtime's figures are 0
+/// while it is stopped.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_usage` must be a valid pointer to a `DwebbleWSResourceUsage`
+
+DwebbleWSResult dwebble_rws_server_get_resource_usage(DwebbleWSServerHandle handle,
+                                                      DwebbleWSResourceUsage *out_usage)
+;
+
+/// Get the addresses the server listens on as JSON, e.g.
+/// `{"listeners": [{"kind": "websocket", "addr": "0.0.0.0:8080"}], "mapped": null,
+/// "public": "203.0.113.7:8080"}`. Listener kinds are `websocket`, `webtransport`,
+/// `raw_tcp` and `raw_udp`; `mapped` is the external address the gateway forwards
+/// (`port_mapping` setting) and `public` the address the last STUN discovery
+/// reported, each null until known. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ char *dwebble_rws_server_get_listen_addrs(DwebbleWSServerHandle handle) ;
+
+/// Get summaries of the server's histograms as JSON, e.g. `{"received_size":
+/// {"count": 120, "min": 12, "max": 4100, "mean": 230, "p50": 180, "p90": 410,
+/// "p99": 2050, "p999": 4100}, "sent_size": {...}, "poll_latency_us": {...}}`.
+/// `received_size` and `sent_size` are the sizes of data messages in bytes as read
+/// and written, and `poll_latency_us` the time events waited for the host to poll
+/// them, in microseconds. Percentiles are within about 3% of the values recorded,
+/// since the server was created. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ char *dwebble_rws_server_get_histograms(DwebbleWSServerHandle handle) ;
+
+/// Get server info string. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ char *dwebble_rws_server_info(DwebbleWSServerHandle handle) ;
+
+/// Connect an in-memory loopback client to a running server.
+/// Returns a client handle or null on failure.
+///
+/// The connection goes through the normal handshake and raises the usual
+/// server events, but uses no TCP socket and no TLS. The client is disconnected
+/// when the server stops.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+ DwebbleWSClientHandle dwebble_rws_server_connect_loopback(DwebbleWSServerHandle handle) ;
+
+/// Dial the server at `url` (`ws://`, or `wss://` with the `tls` feature) from a
+/// running server, which then acts as a peer: the outbound connection shares the
+/// server's connection IDs and events, and is sent to, closed and tagged like an
+/// accepted one. The ID is written to `out_connection_id` at once; a
+/// `ClientConnected` event with a request ID of 1 follows when the handshake
+/// completes, or `Error` and `ClientDisconnected` if it fails.
+///
+/// `settings_json` (optional) sets the `headers`, `cookies`, `query` parameters,
+/// `subprotocols`, `handshake_timeout_ms` and `tls` verification of the connection
+/// as in `dwebble_rws_client_connect`. The server's encryption, sessions,
+/// authentication, message holding, bridge, Socket.IO and MQTT settings apply to
+/// accepted connections only.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `url` must be a valid null-terminated UTF-8 string
+/// - `settings_json` must be a valid null-terminated UTF-8 string, or null
+/// - `out_connection_id` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_server_dial(DwebbleWSServerHandle handle,
+                                        const char *url,
+                                        const char *settings_json,
+                                        DwebbleWSConnectionId *out_connection_id)
+;
+
+/// Connect a client to the server at `url` (`ws://`, or `wss://` with the `tls`
+/// feature). Returns a client handle or null if the URL or settings are invalid;
+/// connection failures are reported by the client's events.
+///
+/// `settings_json` (optional) is an object with any of `headers` and `cookies` for
+/// the upgrade request, e.g. `{"headers": {"Authorization": "Bearer ..."}}`, `query`
+/// parameters appended to the URL, `subprotocols` offered, `handshake_timeout_ms`
+/// (default 10000, 0 to wait indefinitely), `tls`, `offline_queue` and `server`, the
+/// settings of the server dialed for its virtual channels, state sync, receipts,
+/// encryption and replay protection. The client runs on threads of its own.
+///
+/// With `offline_queue`, e.g. `{"max_messages": 256, "reconnect_delay_ms": 1000,
+/// "max_reconnect_delay_ms": 30000}`, the client reconnects whenever the connection
+/// drops, doubling the delay after each failed attempt. Its `ClientDisconnected`
+/// event has a request ID of 1 then, and 0 once the client is closed for good.
+/// Messages sent while disconnected are queued and go out in order on reconnect;
+/// when the queue is full, the oldest is dropped and raised in a `MessageDropped`
+/// event. Virtual channel sends are not queued, and channels and state sync start
+/// over with each connection.
+///
+/// `tls` chooses how a `wss://` server's certificate is verified:
+/// `{"verification": "mozilla_roots"}` (default) trusts the Mozilla root
+/// certificates built into the library, `"system_roots"` the operating system's
+/// store, `"ca_bundle"` the PEM certificates of `ca_pem` only, and `"pinned"` any
+/// certificate whose DER SubjectPublicKeyInfo hashes to one of `spki_pins` (base64
+/// SHA-256), whoever signed it. `"disabled"` accepts any certificate and is meant for
+/// development only. `dwebble_rws_client_get_info` reports the choice.
+///
+/// # Safety
+///
+/// - `url` must be a valid null-terminated UTF-8 string
+/// - `settings_json` must be a valid null-terminated UTF-8 string, or null
+ DwebbleWSClientHandle dwebble_rws_client_connect(const char *url, const char *settings_json) ;
+
+/// Get information about a client's connection
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_info` must be a valid pointer to a `DwebbleWSClientInfo`
+
+DwebbleWSResult dwebble_rws_client_get_info(DwebbleWSClientHandle handle,
+                                            DwebbleWSClientInfo *out_info)
+;
+
+/// Run a client's connection on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed for clients of `dwebble_rws_client_connect` in builds
+/// with the `single-thread` feature; see `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+ DwebbleWSResult dwebble_rws_client_tick(DwebbleWSClientHandle handle, uint32_t budget_ms) ;
+
+/// Destroy a client handle, dropping its connection.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`, or null
+ void dwebble_rws_client_destroy(DwebbleWSClientHandle handle) ;
+
+/// Poll for the next client event. Returns true if an event was available. Its data
+/// stays valid until the next poll of the same client.
+///
+/// Client events use the server event types with a connection id of 0:
+/// `ClientConnected` once the handshake completes, `MessageReceived`,
+/// `ChannelMessage`, `StateUpdated`, `Error` and `ClientDisconnected`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+ bool dwebble_rws_client_poll(DwebbleWSClientHandle handle, DwebbleWSEvent *out_event) ;
+
+/// Send binary data from a client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `data` must be a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_client_send(DwebbleWSClientHandle handle,
+                                        const uint8_t *data,
+                                        uintptr_t data_len)
+;
+
+/// Send text data from a client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `text` must be a valid null-terminated UTF-8 string
+ DwebbleWSResult dwebble_rws_client_send_text(DwebbleWSClientHandle handle, const char *text) ;
+
+/// Send text data given as `text_len` bytes, which may contain null bytes, from a
+/// client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `text` must be a valid pointer to `text_len` bytes
+
+DwebbleWSResult dwebble_rws_client_send_text_len(DwebbleWSClientHandle handle,
+                                                 const uint8_t *text,
+                                                 uintptr_t text_len)
+;
+
+/// Send binary data from a client on a virtual channel, configured like the
+/// server's `channels` setting. Returns `InvalidParam` if the channel is not configured.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `data` must be a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_client_send_channel(DwebbleWSClientHandle handle,
+                                                uint8_t channel,
+                                                const uint8_t *data,
+                                                uintptr_t data_len)
+;
+
+/// Get the statistics of a virtual channel of a client.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSChannelStats`
+
+DwebbleWSResult dwebble_rws_client_channel_stats(DwebbleWSClientHandle handle,
+                                                 uint8_t channel,
+                                                 DwebbleWSChannelStats *out_stats)
+;
+
+/// Sign a client's messages with an HMAC-SHA256 key and require the server's to
+/// be signed with it, matching `dwebble_rws_server_set_signing_key` on the server's
+/// side. Pass a null or empty key to stop signing.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `key` must be valid for `key_len` bytes (or null)
+
+DwebbleWSResult dwebble_rws_client_set_signing_key(DwebbleWSClientHandle handle,
+                                                   const uint8_t *key,
+                                                   uintptr_t key_len)
+;
+
+/// Close a client connection gracefully.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+ DwebbleWSResult dwebble_rws_client_close(DwebbleWSClientHandle handle) ;
+
+/// Open a pool of connections to backend services for `dwebble_rws_service_pool_request`.
+/// Returns a service pool handle, or null if the settings are invalid.
+///
+/// `settings_json` is an object with the `urls` of the services (`ws://` or
+/// `wss://`), the `connections_per_url` kept open (default 1), `health_check_ms`,
+/// the interval of ping health checks (default 5000, 0 to disable),
+/// `reconnect_delay_ms` (default 1000) and `client`, the headers, cookies, query
+/// parameters, subprotocols, handshake timeout and `tls` of the connections as in
+/// `dwebble_rws_client_connect`. A connection that hasn't answered a ping by the
+/// next check is closed, and every closed connection is reopened after the delay.
+/// The pool runs on threads of its own.
+///
+/// Events use the server event types with the connection's index in the pool as
+/// connection ID (`connections_per_url` per URL, in the order of `urls`):
+/// `ClientConnected` and `ClientDisconnected` (error message: why) as connections
+/// open and close, `Error` for each failed attempt, and `ResponseReceived` or
+/// `RequestTimedOut` for each request.
+///
+/// # Safety
+///
+/// - `settings_json` must be a valid null-terminated UTF-8 string
+ DwebbleWSServicePoolHandle dwebble_rws_service_pool_create(const char *settings_json) ;
+
+/// Destroy a service pool handle, closing its connections. Its requests in flight
+/// get no event.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`,
+///   or null
+ void dwebble_rws_service_pool_destroy(DwebbleWSServicePoolHandle handle) ;
+
+/// Send binary data as a request on the next healthy connection of a pool, in turn,
+/// and wait up to `timeout_ms` for the response. The request ID is written to
+/// `out_request_id`; a `ResponseReceived` or `RequestTimedOut` event with that ID
+/// reports the outcome. Returns `ConnectionClosed` if no connection is healthy.
+///
+/// The data is wrapped in an envelope (`DWRQ`, u64 request ID, payload) and the
+/// service must answer with the same layout using the `DWRS` magic, as clients
+/// answer `dwebble_rws_server_request`. Requests of a connection that closes time
+/// out at once.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+/// - `out_request_id` must be a valid pointer to a `u64`
+
+DwebbleWSResult dwebble_rws_service_pool_request(DwebbleWSServicePoolHandle handle,
+                                                 const uint8_t *data,
+                                                 uintptr_t data_len,
+                                                 uint32_t timeout_ms,
+                                                 uint64_t *out_request_id)
+;
+
+/// Get the statistics of a service pool
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSServicePoolStats`
+
+DwebbleWSResult dwebble_rws_service_pool_stats(DwebbleWSServicePoolHandle handle,
+                                               DwebbleWSServicePoolStats *out_stats)
+;
+
+/// Poll for the next event of a service pool. Returns true if an event was available.
+/// Its data stays valid until the next poll of the same pool.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+ bool dwebble_rws_service_pool_poll(DwebbleWSServicePoolHandle handle, DwebbleWSEvent *out_event) ;
+
+/// Run a service pool's connections on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed in builds with the `single-thread` feature; see
+/// `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+
+DwebbleWSResult dwebble_rws_service_pool_tick(DwebbleWSServicePoolHandle handle,
+                                              uint32_t budget_ms)
+;
+
+/// Start a load test of `num_clients` WebSocket clients connecting to `url`.
+/// Returns a load test handle or null on failure.
+///
+/// Each client sends `msgs_per_sec` binary messages per second (0 to only hold
+/// the connection open) of `payload_len` bytes (at least 8). The first 8 bytes
+/// carry a send timestamp, so latency is measured when the server echoes them.
+///
+/// # Safety
+///
+/// - `url` must be a valid null-terminated UTF-8 string (`ws://` or `wss://`)
+
+DwebbleWSLoadTestHandle dwebble_rws_loadtest_start(const char *url,
+                                                   uint32_t num_clients,
+                                                   uint32_t msgs_per_sec,
+                                                   uintptr_t payload_len)
+;
+
+/// Get the aggregate statistics of a running load test.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSLoadTestStats`
+
+DwebbleWSResult dwebble_rws_loadtest_poll(DwebbleWSLoadTestHandle handle,
+                                          DwebbleWSLoadTestStats *out_stats)
+;
+
+/// Run a load test's clients on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed in builds with the `single-thread` feature; see
+/// `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`
+ DwebbleWSResult dwebble_rws_loadtest_tick(DwebbleWSLoadTestHandle handle, uint32_t budget_ms) ;
+
+/// Stop a load test, closing all of its clients, and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`, or null
+ void dwebble_rws_loadtest_stop(DwebbleWSLoadTestHandle handle) ;
+
+/// Create a trainer of zstd dictionaries. Returns a trainer handle, or null in builds
+/// without the `zstd` feature.
+ DwebbleWSDictTrainerHandle dwebble_rws_dict_trainer_create() ;
+
+/// Add a sample payload to train a dictionary on, e.g. a message of recorded traffic.
+/// Samples should be typical of the messages to compress; a few thousand work well.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+
+DwebbleWSResult dwebble_rws_dict_trainer_add_sample(DwebbleWSDictTrainerHandle handle,
+                                                    const uint8_t *data,
+                                                    uintptr_t data_len)
+;
+
+/// Train a dictionary of up to `max_size` bytes from the samples added so far, to set
+/// with `dwebble_rws_server_set_zstd_dictionary` and ship to clients. Returns
+/// `InvalidParam` if there are too few samples to train on. Free the buffer with
+/// `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+
+DwebbleWSResult dwebble_rws_dict_trainer_train(DwebbleWSDictTrainerHandle handle,
+                                               uintptr_t max_size,
+                                               DwebbleWSBuffer *out_buffer)
+;
+
+/// Free a dictionary trainer and its samples.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`, or null
+ void dwebble_rws_dict_trainer_destroy(DwebbleWSDictTrainerHandle handle) ;
+
+/// Announce a service to LAN browsers over mDNS until withdrawn.
+/// Returns an announcement handle or null on failure.
+///
+/// `service_name` (1-15 letters, digits and hyphens, e.g. `mygame`) is what
+/// browsers look for; announce a server's actual port as reported by
+/// `dwebble_rws_server_get_port`. `metadata` is a JSON object of string values
+/// (session name, map, player count, ...) carried in the TXT record.
+///
+/// # Safety
+///
+/// - `service_name` must be a valid null-terminated UTF-8 string
+/// - `metadata` must be a valid null-terminated UTF-8 string, or null
+
+DwebbleWSAnnouncementHandle dwebble_rws_discovery_announce(const char *service_name,
+                                                           uint16_t port,
+                                                           const char *metadata)
+;
+
+/// Withdraw an announced service and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_announce`, or null
+ void dwebble_rws_discovery_withdraw(DwebbleWSAnnouncementHandle handle) ;
+
+/// Start browsing the LAN for announcements of a service.
+/// Returns a browser handle or null on failure.
+///
+/// # Safety
+///
+/// - `service_name` must be a valid null-terminated UTF-8 string
+ DwebbleWSBrowserHandle dwebble_rws_discovery_browse(const char *service_name) ;
+
+/// Get the services a browser currently sees as a JSON array of
+/// `{"name", "host", "addresses", "port", "metadata"}` objects.
+/// Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_browse`
+ char *dwebble_rws_discovery_services(DwebbleWSBrowserHandle handle) ;
+
+/// Stop browsing and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_browse`, or null
+ void dwebble_rws_discovery_browse_stop(DwebbleWSBrowserHandle handle) ;
+
+/// Free a string allocated by this library.
+///
+/// # Safety
+///
+/// - `s` must be a string returned by this library, or null
+/// - `s` must not be used after this call
+ void dwebble_rws_free_string(char *s) ;
+
+/// Allocate a zeroed buffer of `len` bytes, e.g. for a middleware callback's
+/// replacement message. Free with `dwebble_rws_free_buffer` unless handed to the library.
+ DwebbleWSBuffer dwebble_rws_alloc_buffer(uintptr_t len) ;
+
+/// Free a buffer allocated by this library.
+///
+/// # Safety
+///
+/// - `buffer` must be a buffer returned by this library, or have a null `data` pointer
+/// - `buffer` must not be used after this call
+ void dwebble_rws_free_buffer(DwebbleWSBuffer buffer) ;
+
+/// Get the statistics of the pool the payloads of received messages are copied into,
+/// shared by every server and client.
+///
+/// # Safety
+///
+/// - `out_stats` must be a valid pointer to a `DwebbleWSPoolStats`
+ DwebbleWSResult dwebble_rws_pool_stats(DwebbleWSPoolStats *out_stats) ;
 
 }  // extern "C"
@@ -0,0 +1,191 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Host allocator hooks
+//!
+//! Memory the host takes ownership of — returned strings and buffers, and the
+//! replacement buffers middleware hands back — comes from the host's allocator once
+//! one is set, so it shows up in the engine's memory tracking. So does the data of
+//! polled events, which the library copies into the host's allocator and frees on the
+//! next poll. The hooks can only be set before the library hands out any memory, so
+//! nothing is freed by an allocator other than the one that allocated it.
+
+use std::ffi::{c_char, c_void, CString};
+use std::ptr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+use crate::types::DwebbleWSBuffer;
+
+type AllocFn = unsafe extern "C" fn(user_data: *mut c_void, size: usize) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(user_data: *mut c_void, ptr: *mut c_void);
+
+struct Hooks {
+    alloc: AllocFn,
+    free: FreeFn,
+    user_data: *mut c_void,
+}
+
+// The host promises its hooks can be called from any thread
+unsafe impl Send for Hooks {}
+unsafe impl Sync for Hooks {}
+
+/// No hooks are set, and no memory was handed out yet
+const UNSET: u8 = 0;
+/// Memory was handed out from the library's allocator, so no hooks can be set
+const LOCKED: u8 = 1;
+/// Hooks are set, or being stored in `HOOKS`
+const SET: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNSET);
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+/// Install the host's allocator. Returns false if one is already installed or memory
+/// was already handed out.
+pub fn set(alloc: AllocFn, free: FreeFn, user_data: *mut c_void) -> bool {
+    if STATE
+        .compare_exchange(UNSET, SET, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return false;
+    }
+    HOOKS
+        .set(Hooks {
+            alloc,
+            free,
+            user_data,
+        })
+        .is_ok()
+}
+
+/// The hooks to hand out memory from, locking them out if none are set yet
+fn hooks() -> Option<&'static Hooks> {
+    match STATE.compare_exchange(UNSET, LOCKED, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) | Err(LOCKED) => None,
+        // `set` won the state and stores the hooks right after
+        Err(_) => Some(HOOKS.wait()),
+    }
+}
+
+/// Copy `data` into memory allocated for the host. Returns null if the host's
+/// allocator fails.
+fn host_copy(hooks: &Hooks, data: &[u8]) -> *mut u8 {
+    let p = unsafe { (hooks.alloc)(hooks.user_data, data.len().max(1)) } as *mut u8;
+    if !p.is_null() {
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), p, data.len()) };
+    }
+    p
+}
+
+/// Event data copied into the host's allocator, freed when dropped
+pub struct HostCopy {
+    data: *mut u8,
+    hooks: &'static Hooks,
+}
+
+// The block is only read through the pointers handed to the host
+unsafe impl Send for HostCopy {}
+unsafe impl Sync for HostCopy {}
+
+impl HostCopy {
+    /// Copy `data` into the host's allocator. Returns None if no hooks are set or the
+    /// host's allocator fails, leaving the caller to keep its own copy.
+    pub fn new(data: &[u8]) -> Option<Self> {
+        // The library frees event data itself, so copying it leaves the hooks settable
+        if STATE.load(Ordering::Acquire) != SET {
+            return None;
+        }
+        let hooks = HOOKS.wait();
+        let data = host_copy(hooks, data);
+        (!data.is_null()).then_some(Self { data, hooks })
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data
+    }
+}
+
+impl Drop for HostCopy {
+    fn drop(&mut self) {
+        unsafe { (self.hooks.free)(self.hooks.user_data, self.data.cast()) };
+    }
+}
+
+/// Hand a buffer to the host, to be freed with `free_buffer`. The buffer is empty
+/// with a null `data` pointer if the host's allocator fails.
+pub fn buffer(data: Vec<u8>) -> DwebbleWSBuffer {
+    let len = data.len();
+    let p = match hooks() {
+        Some(hooks) => host_copy(hooks, &data),
+        None => Box::into_raw(data.into_boxed_slice()) as *mut u8,
+    };
+    if p.is_null() {
+        return DwebbleWSBuffer::default();
+    }
+    DwebbleWSBuffer { data: p, len }
+}
+
+/// Free a buffer from `buffer`.
+///
+/// # Safety
+///
+/// `buffer` must come from `buffer`, or have a null `data` pointer.
+pub unsafe fn free_buffer(buffer: DwebbleWSBuffer) {
+    if buffer.data.is_null() {
+        return;
+    }
+    match HOOKS.get() {
+        Some(hooks) => (hooks.free)(hooks.user_data, buffer.data.cast()),
+        None => drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        ))),
+    }
+}
+
+/// Take back a buffer from `buffer`, e.g. a middleware replacement
+///
+/// # Safety
+///
+/// `buffer` must come from `buffer`, or have a null `data` pointer.
+pub unsafe fn take_buffer(buffer: DwebbleWSBuffer) -> Option<Vec<u8>> {
+    if buffer.data.is_null() {
+        return None;
+    }
+    match HOOKS.get() {
+        Some(_) => {
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+            free_buffer(buffer);
+            Some(data)
+        }
+        None => {
+            let data = ptr::slice_from_raw_parts_mut(buffer.data, buffer.len);
+            Some(Box::from_raw(data).into_vec())
+        }
+    }
+}
+
+/// Hand a string to the host, to be freed with `free_string`. Returns null if the
+/// host's allocator fails.
+pub fn string(s: CString) -> *mut c_char {
+    match hooks() {
+        Some(hooks) => host_copy(hooks, s.as_bytes_with_nul()).cast(),
+        None => s.into_raw(),
+    }
+}
+
+/// Free a string from `string`.
+///
+/// # Safety
+///
+/// `s` must come from `string`, or be null.
+pub unsafe fn free_string(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    match HOOKS.get() {
+        Some(hooks) => (hooks.free)(hooks.user_data, s.cast()),
+        None => drop(CString::from_raw(s)),
+    }
+}
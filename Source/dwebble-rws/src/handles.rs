@@ -0,0 +1,155 @@
+/*
+ * Copyright 2019-Present tarnishablec. All Rights Reserved.
+ */
+
+//! Generational handles of the objects the host holds
+//!
+//! A handle packs a slot index with the slot's generation, which moves on when
+//! the slot's object is destroyed. A handle used after its object's destruction
+//! then no longer matches its slot and is refused, where a raw pointer would
+//! dangle. Each call holds its own reference to the object, so one destroying it
+//! on another thread only drops it once the call returns.
+
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+/// Bits of a handle holding the slot index; the rest hold the generation
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: usize = usize::MAX >> INDEX_BITS;
+
+struct Slot<T> {
+    /// Generation of the slot's current or next object; never 0, so no handle is null
+    generation: usize,
+    object: Option<Arc<T>>,
+}
+
+struct Slots<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+}
+
+/// The objects of one handle type, kept until removed by their handle
+pub struct Registry<T> {
+    inner: RwLock<Slots<T>>,
+}
+
+impl<T> Registry<T> {
+    pub const fn new() -> Self {
+        Self {
+            inner: RwLock::new(Slots {
+                slots: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    /// Keep `object` and return its handle, or null if every index is taken
+    pub fn insert(&self, object: T) -> *mut c_void {
+        let mut inner = self.inner.write();
+        let index = match inner.free.pop() {
+            Some(index) => index,
+            None if inner.slots.len() <= INDEX_MASK => {
+                inner.slots.push(Slot {
+                    generation: 1,
+                    object: None,
+                });
+                inner.slots.len() - 1
+            }
+            None => return ptr::null_mut(),
+        };
+
+        let slot = &mut inner.slots[index];
+        slot.object = Some(Arc::new(object));
+        ptr::without_provenance_mut(slot.generation << INDEX_BITS | index)
+    }
+
+    /// The object of `handle`, or `None` if the handle is null, stale or was
+    /// never issued
+    pub fn get(&self, handle: *mut c_void) -> Option<Arc<T>> {
+        let (index, generation) = unpack(handle);
+        let inner = self.inner.read();
+        let slot = inner.slots.get(index)?;
+        if slot.generation != generation {
+            return None;
+        }
+        slot.object.clone()
+    }
+
+    /// Take back the object of `handle`, so the handle is refused from now on.
+    /// Returns `None` if the handle is null, stale or was never issued.
+    pub fn remove(&self, handle: *mut c_void) -> Option<Arc<T>> {
+        let (index, generation) = unpack(handle);
+        let mut inner = self.inner.write();
+        let slot = inner.slots.get_mut(index)?;
+        if slot.generation != generation {
+            return None;
+        }
+
+        let object = slot.object.take()?;
+        slot.generation = match (slot.generation + 1) & GENERATION_MASK {
+            0 => 1,
+            next => next,
+        };
+        inner.free.push(index);
+        // Dropped by the caller once the registry is unlocked, or by the last call
+        // still using it
+        Some(object)
+    }
+}
+
+fn unpack(handle: *mut c_void) -> (usize, usize) {
+    let handle = handle.addr();
+    (handle & INDEX_MASK, handle >> INDEX_BITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_find_their_object() {
+        let registry = Registry::new();
+        let a = registry.insert("a");
+        let b = registry.insert("b");
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(registry.get(a).as_deref(), Some(&"a"));
+        assert_eq!(registry.get(b).as_deref(), Some(&"b"));
+    }
+
+    #[test]
+    fn stale_handles_are_refused() {
+        let registry = Registry::new();
+        let old = registry.insert("old");
+        assert_eq!(registry.remove(old).as_deref(), Some(&"old"));
+        assert!(registry.get(old).is_none());
+        assert!(registry.remove(old).is_none());
+
+        // The slot is reused under a new generation
+        let new = registry.insert("new");
+        assert_ne!(new, old);
+        assert!(registry.get(old).is_none());
+        assert!(registry.remove(old).is_none());
+        assert_eq!(registry.get(new).as_deref(), Some(&"new"));
+    }
+
+    #[test]
+    fn unknown_handles_are_refused() {
+        let registry = Registry::new();
+        registry.insert(0);
+        assert!(registry.get(ptr::null_mut()).is_none());
+        assert!(registry.get(ptr::without_provenance_mut(1 << INDEX_BITS | 5)).is_none());
+    }
+
+    #[test]
+    fn removed_objects_live_until_the_last_call_returns() {
+        let registry = Registry::new();
+        let handle = registry.insert(vec![1, 2, 3]);
+        let in_use = registry.get(handle).unwrap();
+        drop(registry.remove(handle));
+        assert_eq!(*in_use, [1, 2, 3]);
+    }
+}
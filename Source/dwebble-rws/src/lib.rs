@@ -12,36 +12,209 @@
 //! - Pointers are valid and properly aligned
 //! - Pointers remain valid for the duration of the call
 //! - String pointers are null-terminated UTF-8
+//!
+//! Any function may be called from any thread, concurrently with the others. The
+//! data of a polled event is kept by the handle it was polled from, until that
+//! handle is polled again or destroyed.
+//! Handles are looked up rather than dereferenced, so a handle used after it was
+//! destroyed is refused with `InvalidHandle`, or what the function returns for a
+//! null handle, instead of reaching freed memory. A handle destroyed while calls
+//! with it are in progress on other threads is freed when the last of them returns.
 
-mod connection;
-mod server;
-mod tls;
+mod allocator;
+mod handles;
 mod types;
 
-use std::ffi::{c_char, CStr, CString};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
 use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use parking_lot::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+use dwebble_rws_core::chaos::Scenario;
+use dwebble_rws_core::client::Client;
+use dwebble_rws_core::cluster::Cluster;
+#[cfg(feature = "zstd")]
+use dwebble_rws_core::compression::DictionaryTrainer;
+use dwebble_rws_core::discovery::{Announcement, Browser};
+use dwebble_rws_core::event_queues::EventQueue;
+use dwebble_rws_core::journal::Journal;
+use dwebble_rws_core::loadtest::{LoadTest, LoadTestConfig};
+use dwebble_rws_core::maintenance::ShutdownSchedule;
+use dwebble_rws_core::middleware::MiddlewareMessage;
+use dwebble_rws_core::recording::{Recorder, Replay};
+use dwebble_rws_core::runtime::ThreadCallback;
+use dwebble_rws_core::server::{Listen, Server, ServerConfig, ServerEvent, StoppedCallback};
+use dwebble_rws_core::service_pool::ServicePool;
+use dwebble_rws_core::settings::{
+    ClientSettings, NetworkSimSettings, ServicePoolSettings, Settings, SettingsUpdate,
+};
+#[cfg(feature = "tls")]
+use dwebble_rws_core::tls::TlsConfig;
+use dwebble_rws_core::watchdog::HealthCallback;
+use dwebble_rws_core::webhooks::Webhooks;
+#[cfg(feature = "schema")]
+use dwebble_rws_core::schema;
+#[cfg(feature = "wasm")]
+use dwebble_rws_core::wasm;
+#[cfg(feature = "archive")]
+use dwebble_rws_core::archive::{Archive, ArchiveQuery};
+use dwebble_rws_core::{is_sendable_close_code, logging, pool, recording};
 
-use crate::server::{Server, ServerConfig};
-use crate::tls::TlsConfig;
+use crate::allocator::HostCopy;
+use crate::handles::Registry;
 use crate::types::*;
 
 /// Stored event data for FFI (to keep strings alive)
 struct EventData {
-    #[allow(dead_code)]
     data: Vec<u8>,
     error: CString,
+    /// Copies in the host's allocator, handed out instead once it set one
+    host_data: Option<HostCopy>,
+    host_error: Option<HostCopy>,
+}
+
+impl Drop for EventData {
+    fn drop(&mut self) {
+        // The host is done with the payload once it polls again
+        pool::release(std::mem::take(&mut self.data));
+    }
+}
+
+/// Host pointer handed back to a callback
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+// The host registers callbacks knowing they run on other threads
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// A server with the data of the latest event and batch of events polled from it, so
+/// polling one server leaves another's alone
+struct ServerHandle {
+    server: Server,
+    current_event: Mutex<Option<EventData>>,
+    batch_data: Mutex<Vec<EventData>>,
+}
+
+impl std::ops::Deref for ServerHandle {
+    type Target = Server;
+
+    fn deref(&self) -> &Server {
+        &self.server
+    }
+}
+
+/// A client with the data of its current event, so clients and servers can be polled
+/// on different threads
+struct ClientHandle {
+    client: Client,
+    current_event: Mutex<Option<EventData>>,
+}
+
+impl From<Client> for ClientHandle {
+    fn from(client: Client) -> Self {
+        Self {
+            client,
+            current_event: Mutex::new(None),
+        }
+    }
+}
+
+impl std::ops::Deref for ClientHandle {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+/// A service pool with the data of its current event
+struct ServicePoolHandle {
+    pool: ServicePool,
+    current_event: Mutex<Option<EventData>>,
+}
+
+impl std::ops::Deref for ServicePoolHandle {
+    type Target = ServicePool;
+
+    fn deref(&self) -> &ServicePool {
+        &self.pool
+    }
 }
 
-static CURRENT_EVENT_DATA: Mutex<Option<EventData>> = Mutex::new(None);
+/// An event queue with the data of its current event, so queues can be polled
+/// on different threads
+struct EventQueueHandle {
+    queue: EventQueue,
+    event_data: Mutex<Option<EventData>>,
+}
+
+// The objects behind the handles given to the host, so a handle used after its
+// object is destroyed is refused with `InvalidHandle` (or whatever the function
+// returns for a null handle)
+static SERVERS: Registry<ServerHandle> = Registry::new();
+static CLIENTS: Registry<ClientHandle> = Registry::new();
+static SERVICE_POOLS: Registry<ServicePoolHandle> = Registry::new();
+static LOAD_TESTS: Registry<LoadTest> = Registry::new();
+static EVENT_QUEUES: Registry<EventQueueHandle> = Registry::new();
+#[cfg(feature = "zstd")]
+static DICT_TRAINERS: Registry<Mutex<DictionaryTrainer>> = Registry::new();
+static ANNOUNCEMENTS: Registry<Announcement> = Registry::new();
+static BROWSERS: Registry<Browser> = Registry::new();
+
+/// Allocate the strings and buffers the library hands to the host, and the data of
+/// polled events, with `alloc_fn` and `free_fn`, called with `user_data`. Must be
+/// called before any function that returns a string or buffer; returns `AlreadyRunning` if one already did or an
+/// allocator is already set, and `InvalidParam` if either function is null.
+///
+/// # Safety
+///
+/// - `alloc_fn` and `free_fn` must be callable from any thread with `user_data` for
+///   as long as the library is loaded
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_set_allocator(
+    alloc_fn: DwebbleWSAllocFn,
+    free_fn: DwebbleWSFreeFn,
+    user_data: *mut c_void,
+) -> DwebbleWSResult {
+    let (Some(alloc_fn), Some(free_fn)) = (alloc_fn, free_fn) else {
+        return DwebbleWSResult::InvalidParam;
+    };
+
+    if allocator::set(alloc_fn, free_fn, user_data) {
+        DwebbleWSResult::Ok
+    } else {
+        DwebbleWSResult::AlreadyRunning
+    }
+}
 
 /// Initialize tracing (optional, call once)
 #[no_mangle]
 pub extern "C" fn dwebble_rws_init_tracing() {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .try_init();
+    logging::init();
+}
+
+/// A string given as a pointer and a length in bytes, or null-terminated if the
+/// length is 0. Returns `None` for a null pointer.
+unsafe fn config_string<'a>(s: *const c_char, len: usize) -> Option<std::borrow::Cow<'a, str>> {
+    if s.is_null() {
+        None
+    } else if len == 0 {
+        Some(CStr::from_ptr(s).to_string_lossy())
+    } else {
+        Some(String::from_utf8_lossy(std::slice::from_raw_parts(s.cast(), len)))
+    }
 }
 
 /// Create a new WebSocket server with the given configuration.
@@ -50,7 +223,8 @@ pub extern "C" fn dwebble_rws_init_tracing() {
 /// # Safety
 ///
 /// - `config` must be a valid pointer to a `DwebbleWSServerConfig`
-/// - All string fields in `config` must be valid null-terminated UTF-8 or null
+/// - All string fields in `config` must be null, valid for their `_len` bytes, or
+///   null-terminated if their `_len` is 0
 #[no_mangle]
 pub unsafe extern "C" fn dwebble_rws_server_create(
     config: *const DwebbleWSServerConfig,
@@ -61,28 +235,33 @@ pub unsafe extern "C" fn dwebble_rws_server_create(
 
     let config = &*config;
 
-    let bind_address = if config.bind_address.is_null() {
-        "127.0.0.1".to_string()
-    } else {
-        CStr::from_ptr(config.bind_address)
-            .to_string_lossy()
-            .into_owned()
+    let bind_address = config_string(config.bind_address, config.bind_address_len)
+        .map_or_else(|| "127.0.0.1".to_string(), |s| s.into_owned());
+
+    let settings_json = config_string(config.settings_json, config.settings_json_len);
+    let mut settings = match settings_json {
+        None => Settings::default(),
+        Some(json) => match Settings::from_json(&json) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Invalid settings JSON: {}", e);
+                return ptr::null_mut();
+            }
+        },
     };
 
-    let subprotocols = if config.subprotocols.is_null() {
-        vec![]
-    } else {
-        let s = CStr::from_ptr(config.subprotocols).to_string_lossy();
-        s.split(',')
+    if let Some(s) = config_string(config.subprotocols, config.subprotocols_len) {
+        settings.subprotocols = s
+            .split(',')
             .map(|s| s.trim().to_string())
             .filter(|s| !s.is_empty())
-            .collect()
-    };
-
-    let tls = if !config.tls_cert_path.is_null() && !config.tls_key_path.is_null() {
-        let cert_path = CStr::from_ptr(config.tls_cert_path).to_string_lossy();
-        let key_path = CStr::from_ptr(config.tls_key_path).to_string_lossy();
+            .collect();
+    }
 
+    let cert_path = config_string(config.tls_cert_path, config.tls_cert_path_len);
+    let key_path = config_string(config.tls_key_path, config.tls_key_path_len);
+    #[cfg(feature = "tls")]
+    let tls = if let (Some(cert_path), Some(key_path)) = (cert_path, key_path) {
         match TlsConfig::from_pem_files(&cert_path, &key_path) {
             Ok(tls) => Some(tls),
             Err(e) => {
@@ -93,251 +272,3858 @@ pub unsafe extern "C" fn dwebble_rws_server_create(
     } else {
         None
     };
+    #[cfg(not(feature = "tls"))]
+    if cert_path.is_some() && key_path.is_some() {
+        tracing::error!("TLS unavailable: built without the `tls` feature");
+        return ptr::null_mut();
+    }
+
+    let journal = match settings.journal.clone().map(Journal::open) {
+        Some(Ok(journal)) => Some(journal),
+        Some(Err(e)) => {
+            tracing::error!("Failed to open message journal: {}", e);
+            return ptr::null_mut();
+        }
+        None => None,
+    };
+
+    if settings.archive.is_some() && !cfg!(feature = "archive") {
+        tracing::error!("Message archive unavailable: built without the `archive` feature");
+        return ptr::null_mut();
+    }
+    #[cfg(feature = "archive")]
+    let archive = match settings.archive.clone().map(Archive::open) {
+        Some(Ok(archive)) => Some(archive),
+        Some(Err(e)) => {
+            tracing::error!("Failed to open message archive: {}", e);
+            return ptr::null_mut();
+        }
+        None => None,
+    };
+
+    let recorder = match settings.record.as_ref().map(|r| Recorder::create(&r.path)) {
+        Some(Ok(recorder)) => Some(recorder),
+        Some(Err(e)) => {
+            tracing::error!("Failed to create event recording: {}", e);
+            return ptr::null_mut();
+        }
+        None => None,
+    };
+
+    let replay = match settings.replay.as_ref() {
+        Some(r) => match recording::load(&r.path) {
+            Ok(events) => Some(Replay {
+                events,
+                speed: r.speed,
+            }),
+            Err(e) => {
+                tracing::error!("Failed to load recording '{}': {}", r.path, e);
+                return ptr::null_mut();
+            }
+        },
+        None => None,
+    };
+
+    if let Some(bridge) = &settings.bridge {
+        for url in bridge.upstream_urls() {
+            let valid = url
+                .into_client_request()
+                .is_ok_and(|r| matches!(r.uri().scheme_str(), Some("ws" | "wss")));
+            if !valid {
+                tracing::error!("Invalid bridge upstream URL '{}'", url);
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    if settings.http2 && !cfg!(feature = "http2") {
+        tracing::error!("HTTP/2 unavailable: built without the `http2` feature");
+        return ptr::null_mut();
+    }
+
+    if settings.webrtc.is_some() && !cfg!(feature = "webrtc") {
+        tracing::error!("WebRTC unavailable: built without the `webrtc` feature");
+        return ptr::null_mut();
+    }
+
+    if settings.port_mapping.is_some() && !cfg!(feature = "port-mapping") {
+        tracing::error!("Port mapping unavailable: built without the `port-mapping` feature");
+        return ptr::null_mut();
+    }
+
+    if settings.zstd.is_some() && !cfg!(feature = "zstd") {
+        tracing::error!("zstd compression unavailable: built without the `zstd` feature");
+        return ptr::null_mut();
+    }
+
+    if settings.webtransport.is_some() && !cfg!(feature = "webtransport") {
+        tracing::error!("WebTransport unavailable: built without the `webtransport` feature");
+        return ptr::null_mut();
+    }
+    #[cfg(feature = "webtransport")]
+    if settings.webtransport.is_some() && tls.is_none() {
+        tracing::error!("WebTransport requires a TLS certificate and key");
+        return ptr::null_mut();
+    }
+
+    let cluster = match settings.cluster.clone().map(Cluster::new) {
+        Some(Ok(cluster)) => Some(cluster),
+        Some(Err(e)) => {
+            tracing::error!("Cluster backend unavailable: {}", e);
+            return ptr::null_mut();
+        }
+        None => None,
+    };
+    let webhooks = match settings.webhooks.clone().map(Webhooks::new) {
+        Some(Ok(webhooks)) => Some(webhooks),
+        Some(Err(e)) => {
+            tracing::error!("Invalid webhook: {}", e);
+            return ptr::null_mut();
+        }
+        None => None,
+    };
+
+    if settings.watchdog.is_some() && cfg!(feature = "single-thread") {
+        tracing::error!("The watchdog is unavailable in `single-thread` builds");
+        return ptr::null_mut();
+    }
+    if settings
+        .watchdog
+        .as_ref()
+        .is_some_and(|watchdog| watchdog.check_interval_ms == 0)
+    {
+        tracing::error!("Watchdog check interval must be positive");
+        return ptr::null_mut();
+    }
+    if settings.threads.is_some() && cfg!(feature = "single-thread") {
+        tracing::error!("Thread settings are unavailable in `single-thread` builds");
+        return ptr::null_mut();
+    }
+    if settings.statsd.as_ref().is_some_and(|statsd| statsd.interval_ms == 0) {
+        tracing::error!("StatsD interval must be positive");
+        return ptr::null_mut();
+    }
+
+    if !settings.wasm_filters.is_empty() && !cfg!(feature = "wasm") {
+        tracing::error!("WASM filters unavailable: built without the `wasm` feature");
+        return ptr::null_mut();
+    }
+    #[cfg(feature = "wasm")]
+    let wasm_filters = match wasm::load_all(&settings.wasm_filters) {
+        Ok(filters) => filters,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ptr::null_mut();
+        }
+    };
+
+    if !settings.schemas.is_empty() && !cfg!(feature = "schema") {
+        tracing::error!("Schema validation unavailable: built without the `schema` feature");
+        return ptr::null_mut();
+    }
+    #[cfg(feature = "schema")]
+    let schemas = match schema::compile_all(&settings.schemas) {
+        Ok(schemas) => schemas,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ptr::null_mut();
+        }
+    };
 
     let server_config = ServerConfig {
         port: config.port,
         bind_address,
-        subprotocols,
+        #[cfg(feature = "tls")]
         tls,
+        settings,
+        journal,
+        #[cfg(feature = "archive")]
+        archive,
+        recorder,
+        replay,
+        cluster,
+        webhooks,
+        #[cfg(feature = "wasm")]
+        wasm_filters,
+        #[cfg(feature = "schema")]
+        schemas,
     };
 
-    let server = Box::new(Server::new(server_config));
-    Box::into_raw(server) as DwebbleWSServerHandle
+    SERVERS.insert(ServerHandle {
+        server: Server::new(server_config),
+        current_event: Mutex::new(None),
+        batch_data: Mutex::new(Vec::new()),
+    })
 }
 
-/// Destroy a server handle and free resources.
+/// Destroy a server handle and free resources. A server still running is stopped,
+/// once any calls with it on other threads return.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`, or null
-/// - `handle` must not be used after this call
 #[no_mangle]
 pub unsafe extern "C" fn dwebble_rws_server_destroy(handle: DwebbleWSServerHandle) {
-    if !handle.is_null() {
-        let _ = Box::from_raw(handle as *mut Server);
-    }
+    drop(SERVERS.remove(handle));
 }
 
-/// Start the WebSocket server.
+/// Start the WebSocket server. Returns `Busy` if another thread is starting,
+/// rebinding or stopping it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
 #[no_mangle]
 pub unsafe extern "C" fn dwebble_rws_server_start(handle: DwebbleWSServerHandle) -> DwebbleWSResult {
-    if handle.is_null() {
+    let Some(server) = SERVERS.get(handle) else {
         return DwebbleWSResult::InvalidHandle;
-    }
-
-    let server = &mut *(handle as *mut Server);
+    };
     server.start()
 }
 
-/// Stop the WebSocket server.
+/// Stop the WebSocket server, after the calls using its runtime on other threads
+/// return. Returns `Busy` if another thread is starting, rebinding or stopping it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
 #[no_mangle]
 pub unsafe extern "C" fn dwebble_rws_server_stop(handle: DwebbleWSServerHandle) -> DwebbleWSResult {
-    if handle.is_null() {
+    let Some(server) = SERVERS.get(handle) else {
         return DwebbleWSResult::InvalidHandle;
-    }
-
-    let server = &mut *(handle as *mut Server);
+    };
     server.stop()
 }
 
-/// Poll for the next event. Returns the event in the out parameter.
-/// Returns true if an event was available, false otherwise.
+/// Stop the WebSocket server on a background thread and return at once.
+///
+/// The stop closes connections as `dwebble_rws_server_stop` does; the
+/// `ServerStopped` event (and the stopped callback, on that thread) reports when
+/// it is done. Starting the server again or destroying it waits for the stop to
+/// finish. Builds with the `single-thread` feature stop before returning.
+/// Returns `NotRunning` if the server is not running, and `Busy` as
+/// `dwebble_rws_server_stop` does.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
-/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_poll(
+pub unsafe extern "C" fn dwebble_rws_server_stop_async(
     handle: DwebbleWSServerHandle,
-    out_event: *mut DwebbleWSEvent,
-) -> bool {
-    if handle.is_null() || out_event.is_null() {
-        return false;
-    }
-
-    let server = &*(handle as *const Server);
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.stop_async()
+}
 
-    if let Some(event) = server.poll_event() {
-        let mut event_data = CURRENT_EVENT_DATA.lock();
+/// Stop the WebSocket server immediately: no Close frames are sent and
+/// outstanding tasks are aborted rather than awaited, so every open connection
+/// is dropped and counts as force-closed in the `ServerStopped` event. Returns
+/// `NotRunning` if the server is not running, and `Busy` as
+/// `dwebble_rws_server_stop` does.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_force_stop(
+    handle: DwebbleWSServerHandle,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.force_stop()
+}
 
-        let data_ptr: *const u8;
-        let data_len: usize;
-        let error_ptr: *const c_char;
+/// Move the server's WebSocket listener to a new port, address or TLS
+/// certificate without dropping connections.
+///
+/// The new listener is bound before the old one closes, so existing connections
+/// carry on uninterrupted. If the new address can't be bound (`BindFailed`) or
+/// the certificate can't be loaded (`TlsError`), the old listener stays.
+/// WebTransport, raw and port-mapped listeners keep their ports. Returns
+/// `NotRunning` if the server is not running, and `Busy` if another thread is
+/// starting, rebinding or stopping it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `config` must be a valid pointer to a `DwebbleWSBindConfig` whose string
+///   fields are null, valid for their `_len` bytes, or null-terminated if their
+///   `_len` is 0
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_rebind(
+    handle: DwebbleWSServerHandle,
+    config: *const DwebbleWSBindConfig,
+) -> DwebbleWSResult {
+    if handle.is_null() || config.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
 
-        if let Some(data) = event.data {
-            data_ptr = data.as_ptr();
-            data_len = data.len();
-            *event_data = Some(EventData {
-                data,
-                error: CString::default(),
-            });
-        } else {
-            data_ptr = ptr::null();
-            data_len = 0;
-            *event_data = None;
-        }
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let config = &*config;
 
-        if let Some(error) = event.error {
-            let c_error = CString::new(error).unwrap_or_default();
-            error_ptr = c_error.as_ptr();
-            if let Some(ref mut ed) = *event_data {
-                ed.error = c_error;
-            } else {
-                *event_data = Some(EventData {
-                    data: vec![],
-                    error: c_error,
-                });
+    let cert_path = config_string(config.tls_cert_path, config.tls_cert_path_len);
+    let key_path = config_string(config.tls_key_path, config.tls_key_path_len);
+    #[cfg(feature = "tls")]
+    let tls = if let (Some(cert_path), Some(key_path)) = (cert_path, key_path) {
+        match TlsConfig::from_pem_files(&cert_path, &key_path) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                tracing::error!("TLS configuration error: {}", e);
+                return DwebbleWSResult::TlsError;
             }
-        } else {
-            error_ptr = ptr::null();
         }
-
-        (*out_event).event_type = event.event_type;
-        (*out_event).connection_id = event.connection_id;
-        (*out_event).data = data_ptr;
-        (*out_event).data_len = data_len;
-        (*out_event).error_message = error_ptr;
-
-        true
     } else {
-        *out_event = DwebbleWSEvent::default();
-        false
+        None
+    };
+    #[cfg(not(feature = "tls"))]
+    if cert_path.is_some() && key_path.is_some() {
+        tracing::error!("TLS unavailable: built without the `tls` feature");
+        return DwebbleWSResult::TlsError;
     }
+
+    server.rebind(Listen {
+        port: config.port,
+        bind_address: config_string(config.bind_address, config.bind_address_len)
+            .map(|s| s.into_owned()),
+        #[cfg(feature = "tls")]
+        tls,
+    })
 }
 
-/// Send binary data to a specific connection.
+/// Run the server's pending work on the calling thread for up to `budget_ms`
+/// milliseconds.
+///
+/// Builds with the `single-thread` feature create no threads of their own, so
+/// the host calls this regularly (e.g. every frame) from one thread while the
+/// server runs; clients opened on the server make progress here too. Other
+/// builds return at once.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
-/// - `data` must be a valid pointer to `data_len` bytes
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_send(
+pub unsafe extern "C" fn dwebble_rws_server_tick(
     handle: DwebbleWSServerHandle,
-    connection_id: DwebbleWSConnectionId,
-    data: *const u8,
-    data_len: usize,
+    budget_ms: u32,
 ) -> DwebbleWSResult {
-    if handle.is_null() || data.is_null() {
-        return DwebbleWSResult::InvalidParam;
-    }
-
-    let server = &*(handle as *const Server);
-    let data_slice = std::slice::from_raw_parts(data, data_len);
-
-    server.send(connection_id, data_slice)
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.tick(Duration::from_millis(u64::from(budget_ms)))
 }
 
-/// Send text data to a specific connection.
+/// Register a callback run at the end of every stop of a running server, after
+/// the `ServerStopped` event is queued. By then every connection is closed and
+/// the runtime has shut down, so nothing of the server runs any more. It runs on
+/// the thread that stops the server, which is a background thread for
+/// `dwebble_rws_server_stop_async`. Replaces any earlier callback; null removes it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
-/// - `text` must be a valid null-terminated UTF-8 string
+/// - `callback` must be safe to call with `user_data` from any thread
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_send_text(
+pub unsafe extern "C" fn dwebble_rws_server_set_stopped_callback(
     handle: DwebbleWSServerHandle,
-    connection_id: DwebbleWSConnectionId,
-    text: *const c_char,
+    callback: DwebbleWSStoppedCallback,
+    user_data: *mut c_void,
 ) -> DwebbleWSResult {
-    if handle.is_null() || text.is_null() {
-        return DwebbleWSResult::InvalidParam;
-    }
-
-    let server = &*(handle as *const Server);
-    let text_str = CStr::from_ptr(text).to_string_lossy();
-
-    server.send_text(connection_id, &text_str)
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let user_data = UserData(user_data);
+    server.set_stopped_callback(callback.map(|callback| {
+        Arc::new(move |stats: &DwebbleWSStopStats| unsafe { callback(user_data.get(), stats) })
+            as StoppedCallback
+    }));
+    DwebbleWSResult::Ok
 }
 
-/// Disconnect a specific connection.
+/// Register a callback run with each health warning of the `watchdog` setting, on
+/// the watchdog thread, as the `HealthWarning` event is raised. It still reaches the
+/// host when the event queue is stalled. Replaces any earlier callback once a call in
+/// progress returns, so its user data may be freed then; null removes it.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_disconnect(
+pub unsafe extern "C" fn dwebble_rws_server_set_health_callback(
     handle: DwebbleWSServerHandle,
-    connection_id: DwebbleWSConnectionId,
+    callback: DwebbleWSHealthCallback,
+    user_data: *mut c_void,
 ) -> DwebbleWSResult {
-    if handle.is_null() {
+    let Some(server) = SERVERS.get(handle) else {
         return DwebbleWSResult::InvalidHandle;
-    }
-
-    let server = &*(handle as *const Server);
-    server.disconnect(connection_id)
+    };
+    let user_data = UserData(user_data);
+    server.set_health_callback(callback.map(|callback| {
+        Arc::new(move |issue, connection_id, stalled_ms| unsafe {
+            callback(user_data.get(), issue, connection_id, stalled_ms)
+        }) as HealthCallback
+    }));
+    DwebbleWSResult::Ok
 }
 
-/// Get the actual port the server is listening to.
+/// Register a callback run on each thread of the server's runtime as it starts,
+/// before it runs any task, to apply the platform's own core affinity and priority
+/// where the `threads` setting can't. Takes effect from the next start; fails with
+/// `AlreadyRunning` until the server has stopped, so the earlier callback's user
+/// data may be freed once this succeeds, and with `Busy` while another thread is
+/// starting or stopping it. Null removes it. Never called in `single-thread` builds.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_get_port(handle: DwebbleWSServerHandle) -> u16 {
-    if handle.is_null() {
-        return 0;
-    }
-
-    let server = &*(handle as *const Server);
-    server.get_actual_port()
+pub unsafe extern "C" fn dwebble_rws_server_set_thread_callback(
+    handle: DwebbleWSServerHandle,
+    callback: DwebbleWSThreadCallback,
+    user_data: *mut c_void,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let user_data = UserData(user_data);
+    server.set_thread_callback(callback.map(|callback| {
+        Arc::new(move |index| unsafe { callback(user_data.get(), index) }) as ThreadCallback
+    }))
 }
 
-/// Get the number of active connections.
+/// Set the dictionary of zstd compression (the `zstd` setting), or compress without one
+/// if `data` is null. Clients must compress with the same dictionary; connections
+/// already open keep the dictionary they negotiated. Returns `InvalidParam` without the
+/// `zstd` setting.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be null or a valid pointer to `data_len` bytes
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_get_connection_count(
+pub unsafe extern "C" fn dwebble_rws_server_set_zstd_dictionary(
     handle: DwebbleWSServerHandle,
-) -> usize {
-    if handle.is_null() {
-        return 0;
-    }
-
-    let server = &*(handle as *const Server);
-    server.get_connection_count()
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let dictionary = (!data.is_null()).then(|| std::slice::from_raw_parts(data, data_len).to_vec());
+    server.set_zstd_dictionary(dictionary)
 }
 
-/// Get server info string. Caller must free with `dwebble_rws_free_string`.
+/// Get the dictionary of zstd compression negotiated from now on, e.g. to ship to
+/// clients; the buffer is empty without one. Returns `InvalidParam` without the `zstd`
+/// setting. Free the buffer with `dwebble_rws_free_buffer`.
 ///
 /// # Safety
 ///
 /// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
 #[no_mangle]
-pub unsafe extern "C" fn dwebble_rws_server_info(handle: DwebbleWSServerHandle) -> *mut c_char {
+pub unsafe extern "C" fn dwebble_rws_server_get_zstd_dictionary(
+    handle: DwebbleWSServerHandle,
+    out_buffer: *mut DwebbleWSBuffer,
+) -> DwebbleWSResult {
     if handle.is_null() {
-        return ptr::null_mut();
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_buffer.is_null() {
+        return DwebbleWSResult::InvalidParam;
     }
 
-    let server = &*(handle as *const Server);
-    let info = server.info();
-
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    match server.zstd_dictionary() {
+        Ok(data) => {
+            *out_buffer = allocator::buffer(data);
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_buffer = DwebbleWSBuffer::default();
+            result
+        }
+    }
+}
+
+/// Apply hot-changeable settings to a server without dropping connections.
+///
+/// `json` is an object with any of: `subprotocols`, `max_connections`,
+/// `max_message_size`, `handshake_timeout_ms`, `idle_timeout_ms`, `hold_messages`,
+/// `authentication`, `allowed_origins`, `log_level`, `close_code`, `close_reason`,
+/// `close_grace_ms`, `shutdown_timeout_ms`, `slow_client`, `send_queue_limit`,
+/// `rate_limit`, `event_budget`, `utf8_policy`, `sessions`, `network_sim`, `json_rpc`,
+/// `typed_codec`, `wasm_filters`, `schemas`. Handshake-time settings apply to connections accepted
+/// after the update; replaced WASM filters and schemas are reloaded from disk.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_update_config(
+    handle: DwebbleWSServerHandle,
+    json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if json.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let json = CStr::from_ptr(json).to_string_lossy();
+
+    match SettingsUpdate::from_json(&json) {
+        Ok(update) => server.update_settings(update),
+        Err(e) => {
+            tracing::error!("Invalid settings update: {}", e);
+            DwebbleWSResult::InvalidParam
+        }
+    }
+}
+
+/// Override the simulated network conditions of one connection.
+///
+/// `json` uses the same fields as the `network_sim` setting (`latency_ms`,
+/// `jitter_ms`, `drop_rate`, `disconnect_rate`, `bandwidth_bytes_per_sec`);
+/// `{}` disables simulation for the connection. Pass null to follow the
+/// server-wide setting again.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_set_network_sim(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    json: *const c_char,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    if json.is_null() {
+        return server.set_network_sim(connection_id, None);
+    }
+
+    let json = CStr::from_ptr(json).to_string_lossy();
+    match serde_json::from_str::<NetworkSimSettings>(&json) {
+        Ok(sim) => server.set_network_sim(connection_id, Some(sim)),
+        Err(e) => {
+            tracing::error!("Invalid network simulation settings: {}", e);
+            DwebbleWSResult::InvalidParam
+        }
+    }
+}
+
+/// Schedule a scripted chaos scenario on a running server.
+///
+/// `json` is an object with a `steps` array. Each step has an `at_ms` offset
+/// from now and an `action`:
+/// - `disconnect` with `percent` and optional `abrupt` (default true: drop
+///   without a closing handshake)
+/// - `stall_writers` with `percent` and `duration_ms`
+/// - `refuse_handshakes` with `duration_ms`
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `json` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_schedule_chaos(
+    handle: DwebbleWSServerHandle,
+    json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if json.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let json = CStr::from_ptr(json).to_string_lossy();
+
+    match Scenario::from_json(&json) {
+        Ok(scenario) => server.schedule_chaos(scenario),
+        Err(e) => {
+            tracing::error!("Invalid chaos scenario: {}", e);
+            DwebbleWSResult::InvalidParam
+        }
+    }
+}
+
+/// Cancel every scheduled chaos scenario, resuming stalled writers and handshakes.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_cancel_chaos(
+    handle: DwebbleWSServerHandle,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.cancel_chaos();
+    DwebbleWSResult::Ok
+}
+
+/// Schedule a maintenance shutdown of a running server at `at_unix_ts`, in seconds
+/// since the Unix epoch, replacing any scheduled before. `warning_message` (null for
+/// none) is broadcast as text to every connection `warning_intervals[i]` seconds
+/// before the shutdown, with `{seconds}` replaced by the seconds left; intervals
+/// already past are skipped.
+///
+/// At the time, new handshakes are refused with 503, every connection is closed
+/// with the `close_code` and `close_reason` settings and suspended sessions end.
+/// A `ShutdownDue` event follows once the connections are gone, or after
+/// `close_grace_ms`: stop the server then.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `warning_message` must be null or a valid null-terminated UTF-8 string
+/// - `warning_intervals` must point to `warning_count` values, or be null if it is 0
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_schedule_shutdown(
+    handle: DwebbleWSServerHandle,
+    at_unix_ts: u64,
+    warning_message: *const c_char,
+    warning_intervals: *const u32,
+    warning_count: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if warning_intervals.is_null() && warning_count > 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let warning_intervals_s = if warning_count > 0 {
+        std::slice::from_raw_parts(warning_intervals, warning_count).to_vec()
+    } else {
+        Vec::new()
+    };
+    server.schedule_shutdown(ShutdownSchedule {
+        at: std::time::UNIX_EPOCH + Duration::from_secs(at_unix_ts),
+        warning_message: optional_reason(warning_message).into_owned(),
+        warning_intervals_s,
+    })
+}
+
+/// Cancel a scheduled shutdown. If it was already due, new handshakes are taken
+/// again; connections it closed stay closed.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_cancel_shutdown(
+    handle: DwebbleWSServerHandle,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.cancel_shutdown();
+    DwebbleWSResult::Ok
+}
+
+/// Ask a STUN server (`host[:port]`, port 3478 by default) for the server's
+/// public IP and port, e.g. to register it with a matchmaking backend. The
+/// result arrives as a `PublicEndpoint` event, or an `Error` event on failure.
+/// Returns `Busy` while another thread is starting, rebinding or stopping the server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `stun_server` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_discover_public_endpoint(
+    handle: DwebbleWSServerHandle,
+    stun_server: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if stun_server.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let stun_server = CStr::from_ptr(stun_server).to_string_lossy();
+    if stun_server.is_empty() {
+        return DwebbleWSResult::InvalidParam;
+    }
+    server.discover_public_endpoint(&stun_server)
+}
+
+/// Choose which event types are raised: bit N of `mask` enables the type with value
+/// N (`1 << DwebbleWSEventType`), and all are enabled initially. Disabled events are
+/// dropped at the source instead of queueing for `dwebble_rws_server_poll`, though
+/// recordings still capture them. The mask is kept across restarts.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_set_event_mask(
+    handle: DwebbleWSServerHandle,
+    mask: u64,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.set_event_mask(mask);
+    DwebbleWSResult::Ok
+}
+
+/// Poll for the next event. Returns the event in the out parameter.
+/// Returns true if an event was available, false otherwise. Its data stays valid
+/// until the next poll of the same server. The events raised before a stop, ending
+/// with `ServerStopped`, can still be polled after it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_poll(
+    handle: DwebbleWSServerHandle,
+    out_event: *mut DwebbleWSEvent,
+) -> bool {
+    if handle.is_null() || out_event.is_null() {
+        return false;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return false;
+    };
+    write_event(
+        &server.current_event,
+        server.poll_event(),
+        |event| server.event_checksum(event),
+        out_event,
+    )
+}
+
+/// Poll for up to `max_events` events at once, e.g. every event of a frame, for
+/// less overhead per event than `dwebble_rws_server_poll`. Returns the number of
+/// events written to `out_events`. Their data stays valid until the next batch poll of
+/// the same server, independently of `dwebble_rws_server_poll`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_events` must point to an array of at least `max_events` `DwebbleWSEvent`s
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_poll_batch(
+    handle: DwebbleWSServerHandle,
+    out_events: *mut DwebbleWSEvent,
+    max_events: usize,
+) -> usize {
+    if handle.is_null() || out_events.is_null() {
+        return 0;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return 0;
+    };
+    let mut batch_data = server.batch_data.lock();
+    // The host is done with the previous batch once it polls again
+    batch_data.clear();
+    let events = server.poll_events(max_events);
+    let count = events.len();
+    for (index, event) in events.into_iter().enumerate() {
+        let checksum = server.event_checksum(&event);
+        let (event, event_data) = to_ffi_event(event, checksum);
+        out_events.add(index).write(event);
+        batch_data.extend(event_data);
+    }
+    count
+}
+
+/// Open a queue taking the events of a room's members, to poll on a thread of its
+/// own with `dwebble_rws_event_queue_poll`, e.g. one per match. Events of a
+/// connection in the room go to its queue instead of `dwebble_rws_server_poll`
+/// (to the earliest opened queue if it is in several rooms with one), up to and
+/// including its `ClientDisconnected` event. Returns null for an empty room.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_open_event_queue(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+) -> DwebbleWSEventQueueHandle {
+    if handle.is_null() || room.is_null() {
+        return ptr::null_mut();
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    match server.open_event_queue(&room) {
+        Ok(queue) => {
+            let handle = EventQueueHandle {
+                queue,
+                event_data: Mutex::new(None),
+            };
+            EVENT_QUEUES.insert(handle)
+        }
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Poll for the next event of an event queue. Returns true if an event was
+/// available. Its data stays valid until the next poll of the same queue, and
+/// different queues may be polled concurrently.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_open_event_queue`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_event_queue_poll(
+    handle: DwebbleWSEventQueueHandle,
+    out_event: *mut DwebbleWSEvent,
+) -> bool {
+    if handle.is_null() || out_event.is_null() {
+        return false;
+    }
+
+    let Some(handle) = EVENT_QUEUES.get(handle) else {
+        return false;
+    };
+    write_event(
+        &handle.event_data,
+        handle.queue.poll_event(),
+        |event| handle.queue.event_checksum(event),
+        out_event,
+    )
+}
+
+/// Close an event queue. Events still queued are dropped, and later events of its
+/// room's members go to the server's queue again.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_open_event_queue`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_event_queue_close(handle: DwebbleWSEventQueueHandle) {
+    drop(EVENT_QUEUES.remove(handle));
+}
+
+/// Copy an event into `out_event` with the checksum of its data, keeping its payload
+/// alive in `slot` until the next poll. Clears `out_event` and returns false if there
+/// is no event.
+unsafe fn write_event(
+    slot: &Mutex<Option<EventData>>,
+    event: Option<ServerEvent>,
+    checksum: impl FnOnce(&ServerEvent) -> u32,
+    out_event: *mut DwebbleWSEvent,
+) -> bool {
+    if let Some(event) = event {
+        let mut event_data = slot.lock();
+        let checksum = checksum(&event);
+        let (event, data) = to_ffi_event(event, checksum);
+        *out_event = event;
+        *event_data = data;
+        true
+    } else {
+        *out_event = DwebbleWSEvent::default();
+        false
+    }
+}
+
+/// An event as handed to the host, with the data its pointers point into, which must
+/// be kept alive until the host is done with the event
+fn to_ffi_event(event: ServerEvent, checksum: u32) -> (DwebbleWSEvent, Option<EventData>) {
+    let mut event_data = EventData {
+        data: vec![],
+        error: CString::default(),
+        host_data: None,
+        host_error: None,
+    };
+    let mut kept = false;
+
+    let (data_ptr, data_len) = match event.data {
+        Some(data) => {
+            kept = true;
+            let len = data.len();
+            match HostCopy::new(&data) {
+                Some(copy) => {
+                    pool::release(data);
+                    let p = copy.as_ptr();
+                    event_data.host_data = Some(copy);
+                    (p, len)
+                }
+                None => {
+                    let p = data.as_ptr();
+                    event_data.data = data;
+                    (p, len)
+                }
+            }
+        }
+        None => (ptr::null(), 0),
+    };
+
+    let error_ptr: *const c_char = match event.error {
+        Some(error) => {
+            kept = true;
+            let c_error = CString::new(error).unwrap_or_default();
+            match HostCopy::new(c_error.as_bytes_with_nul()) {
+                Some(copy) => {
+                    let p = copy.as_ptr().cast();
+                    event_data.host_error = Some(copy);
+                    p
+                }
+                None => {
+                    let p = c_error.as_ptr();
+                    event_data.error = c_error;
+                    p
+                }
+            }
+        }
+        None => ptr::null(),
+    };
+    let event_data = kept.then_some(event_data);
+
+    let out_event = DwebbleWSEvent {
+        event_type: event.event_type,
+        connection_id: event.connection_id,
+        data: data_ptr,
+        data_len,
+        error_message: error_ptr,
+        request_id: event.request_id,
+        checksum,
+    };
+    (out_event, event_data)
+}
+
+/// Send binary data to a specific connection.
+///
+/// Binary messages starting with `DW` and two uppercase ASCII letters are the
+/// library's control frames, never raised as `MessageReceived`. Every binary send and
+/// broadcast puts data starting like one behind a `DWES` prefix for the client to
+/// strip, and clients must do the same; the library's client does both.
+///
+/// Returns `ConnectionClosed` if the connection has closed or is suspended without a
+/// replay buffer, `QueueFull` if the message would take its send queue over
+/// `send_queue_limit`, `InvalidHandle` if the connection ID was never issued, and
+/// `NotRunning` unless the server is running. See
+/// `dwebble_rws_server_get_last_send_error` for details.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send(connection_id, data_slice)
+}
+
+/// Send binary data to a specific connection under a dedupe key. If a message queued
+/// under the same key earlier hasn't been sent yet, it is dropped and this one goes out
+/// instead, so "latest state wins" updates don't pile up in a slow client's queue.
+///
+/// Returns as `dwebble_rws_server_send`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `key` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_with_key(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    key: *const c_char,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || key.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let key = CStr::from_ptr(key).to_string_lossy();
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_with_key(connection_id, &key, data_slice)
+}
+
+/// Send binary data to a specific connection with a time-to-live. If the message is
+/// still queued `ttl_ms` milliseconds later, e.g. behind a slow client's backlog, it is
+/// dropped instead of sent, and counted in the connection's `expired_messages`.
+///
+/// Returns as `dwebble_rws_server_send`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_with_ttl(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    data: *const u8,
+    data_len: usize,
+    ttl_ms: u32,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_with_ttl(
+        connection_id,
+        data_slice,
+        Duration::from_millis(u64::from(ttl_ms)),
+    )
+}
+
+/// Send binary data wrapped in a typed envelope (`[type_id, payload]`) encoded
+/// with the `typed_codec` setting. Returns `InvalidParam` if no codec is configured.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_typed(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    type_id: u32,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_typed(connection_id, type_id, data_slice)
+}
+
+/// Send binary data on a virtual channel configured in the `channels` setting,
+/// delivered as a `ChannelMessage` event by peers speaking the channel envelope.
+/// Returns `InvalidParam` if the channel is not configured.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_channel(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    channel: u8,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_channel(connection_id, channel, data_slice)
+}
+
+/// Get the statistics of a virtual channel of a connection (all zero if
+/// nothing was sent or received on it).
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSChannelStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_channel_stats(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    channel: u8,
+    out_stats: *mut DwebbleWSChannelStats,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_stats = server.channel_stats(connection_id, channel);
+    DwebbleWSResult::Ok
+}
+
+/// Register a middleware callback run on every text and binary message, with its
+/// ID written to `out_id`. Inbound messages pass through the middleware in
+/// ascending `priority` order (ties in registration order) before the library
+/// handles them, and outbound messages in descending order just before they are
+/// queued, so layers such as compression and encryption nest. A callback can
+/// pass a message on, replace it or drop it. Middleware stays registered until
+/// removed or the server is destroyed.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `callback` must be safe to call with `user_data` from any thread until removed
+/// - `out_id` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_add_middleware(
+    handle: DwebbleWSServerHandle,
+    callback: DwebbleWSMiddlewareCallback,
+    user_data: *mut c_void,
+    priority: i32,
+    out_id: *mut u64,
+) -> DwebbleWSResult {
+    let Some(callback) = callback else {
+        return DwebbleWSResult::InvalidParam;
+    };
+    if handle.is_null() || out_id.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let user_data = UserData(user_data);
+    let middleware = Arc::new(move |message: &mut MiddlewareMessage| {
+        let mut host_message = DwebbleWSMiddlewareMessage {
+            inbound: message.inbound,
+            connection_id: message.connection_id,
+            is_text: message.is_text,
+            data: message.data.as_ptr(),
+            data_len: message.data.len(),
+            replacement: DwebbleWSBuffer::default(),
+        };
+        let action = callback(user_data.get(), &mut host_message);
+        message.is_text = host_message.is_text;
+        message.replacement = allocator::take_buffer(host_message.replacement);
+        action
+    });
+    *out_id = server.add_middleware(middleware, priority);
+    DwebbleWSResult::Ok
+}
+
+/// Unregister a middleware callback. Messages already passing through the chain
+/// may still reach it. Returns `InvalidParam` if the ID is unknown.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_remove_middleware(
+    handle: DwebbleWSServerHandle,
+    middleware_id: u64,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    if server.remove_middleware(middleware_id) {
+        DwebbleWSResult::Ok
+    } else {
+        DwebbleWSResult::InvalidParam
+    }
+}
+
+/// Send the latest version of a keyed state blob to a connection. The library
+/// keeps the versions it sent and sends a delta against the one the client last
+/// acknowledged where that is smaller, with a full state every
+/// `delta.full_snapshot_interval` updates. Loopback clients rebuild the state and
+/// raise `StateUpdated`. Returns `InvalidParam` if the `delta` setting is null.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_state(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    key: u32,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_state(connection_id, key, data_slice)
+}
+
+/// Send binary data numbered with the connection's next sequence, written to
+/// `out_sequence`. The data is wrapped in an envelope (`DWSQ`, u64 sequence,
+/// payload) and the client acknowledges it with `DWSK` and the sequence; loopback
+/// clients do so themselves and raise `MessageReceived` with the sequence as the
+/// request ID. Returns `InvalidParam` if the `receipts` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+/// - `out_sequence` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_sequenced(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    data: *const u8,
+    data_len: usize,
+    out_sequence: *mut u64,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() || out_sequence.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    match server.send_sequenced(connection_id, data_slice) {
+        Ok(sequence) => {
+            *out_sequence = sequence;
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_sequence = 0;
+            result
+        }
+    }
+}
+
+/// Get the highest sequence of sequenced sends a connection acknowledged (0 if none).
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_acked_sequence(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> u64 {
+    let Some(server) = SERVERS.get(handle) else {
+        return 0;
+    };
+    server.acked_sequence(connection_id)
+}
+
+/// Sign a connection's messages with an HMAC-SHA256 key and require the messages
+/// it sends to be signed with the same key, e.g. once it authenticated. Messages
+/// that fail verification are dropped with a `SignatureInvalid` event. Pass a null
+/// or empty key to stop signing.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `key` must be valid for `key_len` bytes (or null)
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_set_signing_key(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    key: *const u8,
+    key_len: usize,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let key = (!key.is_null() && key_len > 0).then(|| std::slice::from_raw_parts(key, key_len));
+    server.set_signing_key(connection_id, key)
+}
+
+/// Send an unreliable datagram to a WebTransport connection. Returns `InvalidHandle`
+/// for other connections and `InvalidParam` if the datagram is too large for the path.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_datagram(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.send_datagram(connection_id, data_slice)
+}
+
+/// Send text data to a specific connection. Text that is not valid UTF-8 is handled
+/// by the `utf8_policy` setting; the strict policy fails with `InvalidUtf8`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `text` must be a valid null-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_text(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    text: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.send_text(connection_id, CStr::from_ptr(text).to_bytes())
+}
+
+/// Send text data given as `text_len` bytes, which may contain null bytes, to a
+/// specific connection. Otherwise like `dwebble_rws_server_send_text`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `text` must be a valid pointer to `text_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_send_text_len(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    text: *const u8,
+    text_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.send_text(connection_id, std::slice::from_raw_parts(text, text_len))
+}
+
+/// Get why the latest failed send to a connection failed, kept until the connection is
+/// removed. Returns null if none failed. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_last_send_error(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match server.last_send_error(connection_id).map(CString::new) {
+        Some(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Disconnect a specific connection. Returns `NotRunning` unless the server is
+/// running, as its connections are closed by a stop.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_disconnect(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.disconnect(connection_id)
+}
+
+/// Stop reading a connection's messages until `dwebble_rws_server_resume_read`, so
+/// the host can work through a backlog. The client is held back by TCP flow
+/// control once the socket buffers fill up; the idle timeout doesn't apply
+/// meanwhile. Messages already read are still delivered.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_pause_read(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.set_read_paused(connection_id, true)
+}
+
+/// Resume reading a connection paused with `dwebble_rws_server_pause_read`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_resume_read(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.set_read_paused(connection_id, false)
+}
+
+/// Acknowledge a connection's `ClientConnected` event, delivering the messages held
+/// back under the `hold_messages` setting, in order, and any after them as they
+/// arrive. Call it once the host is ready for the connection's messages, e.g. after
+/// setting up its per-player state. Does nothing for a connection holding none.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_acknowledge_connection(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.acknowledge_connection(connection_id)
+}
+
+/// Mark a connection authenticated, e.g. once its login message checks out. With the
+/// `authentication` setting, a new connection's messages stop being read after its
+/// login messages until this is called, and it is closed with code 1008 if it isn't
+/// called within the setting's `timeout_ms`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_mark_authenticated(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.mark_authenticated(connection_id)
+}
+
+/// Rotate a connection's application-layer encryption keys with a fresh key exchange
+/// inside the encrypted session. `KeyRotated` is raised once both directions use the
+/// new keys; a client that doesn't answer within `handshake_timeout_ms` is closed.
+/// Keys also rotate on their own with the `encryption` setting's `rekey_interval_ms`.
+///
+/// Returns `InvalidParam` if the connection has not completed a key exchange.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_rekey(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.rekey(connection_id)
+}
+
+/// Hand a live connection over to `target`, another running server in this
+/// process, without the client noticing. The socket, queued messages and
+/// encryption keys move along and the connection keeps its ID. This server raises
+/// `ClientDisconnected` with the error "Migrated to another server", the target
+/// raises `ClientConnected`. Rooms, topics, sessions and protocol state stay
+/// behind, and the target's connection limit and origin checks don't apply.
+///
+/// Returns `InvalidParam` for connections on HTTP/2, in bridge mode or still
+/// exchanging or rotating keys, and `ConnectionClosed` if the connection closed meanwhile.
+///
+/// # Safety
+///
+/// - `handle` and `target` must be valid handles returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_migrate_connection(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    target: DwebbleWSServerHandle,
+) -> DwebbleWSResult {
+    let (Some(server), Some(target)) = (SERVERS.get(handle), SERVERS.get(target)) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.migrate(connection_id, &target)
+}
+
+/// Close every connection with a Close frame of `code` and `reason` (null for
+/// none), e.g. at the end of a match. Suspended sessions are ended as well. Each
+/// connection raises `ClientDisconnected` once closed. Returns `InvalidParam` if
+/// `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_kick_all(
+    handle: DwebbleWSServerHandle,
+    code: u16,
+    reason: *const c_char,
+) -> DwebbleWSResult {
+    if !is_sendable_close_code(code) {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.kick_all(code, &optional_reason(reason))
+}
+
+/// Close every connection from an IP address with a Close frame of `code` and
+/// `reason` (null for none). Returns `InvalidParam` if `ip` is not an IP address or
+/// `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_kick_ip(
+    handle: DwebbleWSServerHandle,
+    ip: *const c_char,
+    code: u16,
+    reason: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || ip.is_null() || !is_sendable_close_code(code) {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let Ok(ip) = CStr::from_ptr(ip).to_string_lossy().trim().parse() else {
+        return DwebbleWSResult::InvalidParam;
+    };
+    server.kick_ip(ip, code, &optional_reason(reason))
+}
+
+/// Close every member of a room with a Close frame of `code` and `reason` (null
+/// for none). Suspended members have their session ended. Returns `InvalidParam`
+/// if `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_kick_room(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    code: u16,
+    reason: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() || !is_sendable_close_code(code) {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    server.kick_room(&room, code, &optional_reason(reason))
+}
+
+/// Refuse connections from an IP address for `duration_ms` (0 until unbanned), and
+/// close those open with a Close frame of code 1008 and `reason` (null for none).
+/// Bans are kept across restarts. Returns `InvalidParam` if `ip` is not an IP address.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_ban_ip(
+    handle: DwebbleWSServerHandle,
+    ip: *const c_char,
+    duration_ms: u64,
+    reason: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || ip.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let Ok(ip) = CStr::from_ptr(ip).to_string_lossy().trim().parse() else {
+        return DwebbleWSResult::InvalidParam;
+    };
+    let duration = (duration_ms > 0).then(|| Duration::from_millis(duration_ms));
+    server.ban_ip(ip, duration, &optional_reason(reason))
+}
+
+/// Lift the ban of an IP address. Returns `InvalidParam` if `ip` is not an IP
+/// address or isn't banned.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `ip` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_unban_ip(
+    handle: DwebbleWSServerHandle,
+    ip: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || ip.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let Ok(ip) = CStr::from_ptr(ip).to_string_lossy().trim().parse() else {
+        return DwebbleWSResult::InvalidParam;
+    };
+    server.unban_ip(ip)
+}
+
+/// Close reason given as a C string, with null meaning none
+unsafe fn optional_reason<'a>(reason: *const c_char) -> std::borrow::Cow<'a, str> {
+    if reason.is_null() {
+        "".into()
+    } else {
+        CStr::from_ptr(reason).to_string_lossy()
+    }
+}
+
+/// Add a connection to a room. With the `history` setting's `replay_on_join`, a
+/// new member is sent the room's history first.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_room_join(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    room: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    server.join_room(connection_id, &room)
+}
+
+/// Remove a connection from a room. Returns `InvalidParam` if it was not a member.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_room_leave(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    room: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    server.leave_room(connection_id, &room)
+}
+
+/// Read up to `max_messages` of the latest messages broadcast to a room, oldest
+/// first, kept with the `history` setting. The messages are returned back to back,
+/// each laid out as (little-endian) a u8 kind (0 = binary, 1 = text), a u32
+/// payload length and the payload. Free the buffer with `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_history(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    max_messages: usize,
+    out_buffer: *mut DwebbleWSBuffer,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if room.is_null() || out_buffer.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    let mut data = Vec::new();
+    for msg in server.history(&room, max_messages) {
+        let (kind, payload): (u8, &[u8]) = match &msg {
+            Message::Text(text) => (1, text.as_bytes()),
+            Message::Binary(data) => (0, data),
+            _ => continue,
+        };
+        data.push(kind);
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(payload);
+    }
+    *out_buffer = allocator::buffer(data);
+    DwebbleWSResult::Ok
+}
+
+/// Forget the history of a room. Returns `InvalidParam` if it has none.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_clear_history(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    server.clear_history(&room)
+}
+
+/// Send binary data to every member of a room.
+/// With clustering enabled, members connected to sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_room_broadcast(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.broadcast_room(&room, Message::Binary(data_slice.to_vec().into()))
+}
+
+/// Send text to every member of a room.
+/// With clustering enabled, members connected to sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` and `text` must be valid null-terminated UTF-8 strings
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_room_broadcast_text(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    text: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    match server.text_message(CStr::from_ptr(text).to_bytes()) {
+        Ok(msg) => server.broadcast_room(&room, msg),
+        Err(result) => result,
+    }
+}
+
+/// Send text given as `text_len` bytes, which may contain null bytes, to every member
+/// of a room. Otherwise like `dwebble_rws_server_room_broadcast_text`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+/// - `text` must be a valid pointer to `text_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_room_broadcast_text_len(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    text: *const u8,
+    text_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || room.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    match server.text_message(std::slice::from_raw_parts(text, text_len)) {
+        Ok(msg) => server.broadcast_room(&room, msg),
+        Err(result) => result,
+    }
+}
+
+/// Tag a connection, e.g. with `team:red`. Tags are a lighter grouping than rooms:
+/// they stay on this instance. Returns `InvalidParam` for an empty tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_set_tag(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    tag: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    server.set_tag(connection_id, &tag)
+}
+
+/// Remove a tag from a connection. Returns `InvalidParam` if it did not have the tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_clear_tag(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    tag: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    server.clear_tag(connection_id, &tag)
+}
+
+/// Get the tags of a connection as a sorted JSON array. Caller must free with
+/// `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_tags(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match serde_json::to_string(&server.tags(connection_id)).map(CString::new) {
+        Ok(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Send binary data to every connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_tag_broadcast(
+    handle: DwebbleWSServerHandle,
+    tag: *const c_char,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.broadcast_tag(&tag, Message::Binary(data_slice.to_vec().into()))
+}
+
+/// Send text to every connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` and `text` must be valid null-terminated UTF-8 strings
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_tag_broadcast_text(
+    handle: DwebbleWSServerHandle,
+    tag: *const c_char,
+    text: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    match server.text_message(CStr::from_ptr(text).to_bytes()) {
+        Ok(msg) => server.broadcast_tag(&tag, msg),
+        Err(result) => result,
+    }
+}
+
+/// Send text given as `text_len` bytes, which may contain null bytes, to every
+/// connection with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `text` must be a valid pointer to `text_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_tag_broadcast_text_len(
+    handle: DwebbleWSServerHandle,
+    tag: *const c_char,
+    text: *const u8,
+    text_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    match server.text_message(std::slice::from_raw_parts(text, text_len)) {
+        Ok(msg) => server.broadcast_tag(&tag, msg),
+        Err(result) => result,
+    }
+}
+
+/// Close every connection with a tag with a Close frame of `code` and `reason`
+/// (null for none). Suspended sessions with the tag are ended. Returns
+/// `InvalidParam` if `code` may not be sent in a Close frame.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `reason` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_kick_tag(
+    handle: DwebbleWSServerHandle,
+    tag: *const c_char,
+    code: u16,
+    reason: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || tag.is_null() || !is_sendable_close_code(code) {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    server.kick_tag(&tag, code, &optional_reason(reason))
+}
+
+/// Get the statistics of the connections with a tag.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `tag` must be a valid null-terminated UTF-8 string
+/// - `out_stats` must be a valid pointer to a `DwebbleWSTagStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_tag_stats(
+    handle: DwebbleWSServerHandle,
+    tag: *const c_char,
+    out_stats: *mut DwebbleWSTagStats,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if tag.is_null() || out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let tag = CStr::from_ptr(tag).to_string_lossy();
+    *out_stats = server.tag_stats(&tag);
+    DwebbleWSResult::Ok
+}
+
+/// Pipe two connections to each other, e.g. peers meeting at a rendezvous relay:
+/// their text and binary messages go to the other connection as they are, without
+/// `MessageReceived` events, until `dwebble_rws_server_unpipe` or either
+/// connection ends. Returns `InvalidParam` if they are the same connection or
+/// either is piped already.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_pipe(
+    handle: DwebbleWSServerHandle,
+    connection_a: DwebbleWSConnectionId,
+    connection_b: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.pipe(connection_a, connection_b)
+}
+
+/// End the pipe of a connection; the messages of both connections raise events
+/// again. Returns `InvalidParam` if the connection is not piped.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_unpipe(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.unpipe(connection_id)
+}
+
+/// Send binary data as a request and wait up to `timeout_ms` for the response.
+/// The request ID is written to `out_request_id`; a `ResponseReceived` or
+/// `RequestTimedOut` event with that ID reports the outcome.
+///
+/// The data is wrapped in an envelope (`DWRQ`, u64 request ID, payload) and the
+/// client must answer with the same layout using the `DWRS` magic.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+/// - `out_request_id` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_request(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    data: *const u8,
+    data_len: usize,
+    timeout_ms: u32,
+    out_request_id: *mut u64,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() || out_request_id.is_null() || timeout_ms == 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+
+    match server.request(connection_id, data_slice, timeout) {
+        Ok(request_id) => {
+            *out_request_id = request_id;
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_request_id = 0;
+            result
+        }
+    }
+}
+
+/// Subscribe a connection to a topic pattern. Use connection ID 0 to subscribe
+/// the host, which receives matching publications as `TopicMessage` events.
+///
+/// Topics are dot-separated segments. In patterns, `*` matches exactly one
+/// segment and a trailing `>` matches one or more remaining segments.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `pattern` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_subscribe(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    pattern: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || pattern.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let pattern = CStr::from_ptr(pattern).to_string_lossy();
+    server.subscribe(connection_id, &pattern)
+}
+
+/// Remove a subscription made with `dwebble_rws_server_subscribe`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `pattern` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_unsubscribe(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    pattern: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || pattern.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let pattern = CStr::from_ptr(pattern).to_string_lossy();
+    server.unsubscribe(connection_id, &pattern)
+}
+
+/// Publish binary data to every subscriber whose pattern matches `topic`.
+/// With clustering enabled, subscribers on sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_publish(
+    handle: DwebbleWSServerHandle,
+    topic: *const c_char,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || topic.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let topic = CStr::from_ptr(topic).to_string_lossy();
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.publish(&topic, Message::Binary(data_slice.to_vec().into()))
+}
+
+/// Publish text to every subscriber whose pattern matches `topic`.
+/// With clustering enabled, subscribers on sibling instances receive it too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `text` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_publish_text(
+    handle: DwebbleWSServerHandle,
+    topic: *const c_char,
+    text: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || topic.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let topic = CStr::from_ptr(topic).to_string_lossy();
+    match server.text_message(CStr::from_ptr(text).to_bytes()) {
+        Ok(msg) => server.publish(&topic, msg),
+        Err(result) => result,
+    }
+}
+
+/// Publish text given as `text_len` bytes, which may contain null bytes, to every
+/// subscriber whose pattern matches `topic`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `text` must be a valid pointer to `text_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_publish_text_len(
+    handle: DwebbleWSServerHandle,
+    topic: *const c_char,
+    text: *const u8,
+    text_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || topic.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let topic = CStr::from_ptr(topic).to_string_lossy();
+    match server.text_message(std::slice::from_raw_parts(text, text_len)) {
+        Ok(msg) => server.publish(&topic, msg),
+        Err(result) => result,
+    }
+}
+
+/// Publish a message to the MQTT clients subscribed to `topic` (QoS 0 or 1).
+/// A retained message is also sent to later subscribers; publishing an empty
+/// retained payload clears it.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `topic` must be a valid null-terminated UTF-8 string without wildcards
+/// - `data` must be valid for `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_mqtt_publish(
+    handle: DwebbleWSServerHandle,
+    topic: *const c_char,
+    data: *const u8,
+    data_len: usize,
+    qos: u8,
+    retain: bool,
+) -> DwebbleWSResult {
+    if handle.is_null() || topic.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let topic = CStr::from_ptr(topic).to_string_lossy();
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+
+    server.mqtt_publish(&topic, data_slice, qos, retain)
+}
+
+/// Answer the JSON-RPC call `call_id` (from an `RpcCall` event) with a result.
+/// Returns `InvalidParam` if the call is unknown or already answered, or if
+/// `result_json` is not valid JSON.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `result_json` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_rpc_reply(
+    handle: DwebbleWSServerHandle,
+    call_id: u64,
+    result_json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || result_json.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let result_json = CStr::from_ptr(result_json).to_string_lossy();
+    server.rpc_reply(call_id, &result_json)
+}
+
+/// Answer the JSON-RPC call `call_id` (from an `RpcCall` event) with an error.
+/// `data_json` is optional (null to omit) and must be valid JSON otherwise.
+///
+/// Standard codes: -32601 method not found, -32602 invalid params, -32603
+/// internal error; -32000 to -32099 are reserved for server errors.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `message` must be a valid null-terminated UTF-8 string
+/// - `data_json` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_rpc_error(
+    handle: DwebbleWSServerHandle,
+    call_id: u64,
+    code: i32,
+    message: *const c_char,
+    data_json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || message.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let message = CStr::from_ptr(message).to_string_lossy();
+    let data_json = (!data_json.is_null()).then(|| CStr::from_ptr(data_json).to_string_lossy());
+    server.rpc_error(call_id, code, &message, data_json.as_deref())
+}
+
+/// Emit a Socket.IO event to a client that joined the main namespace.
+/// `args_json` is a JSON array of the event's arguments (null for none).
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `event` must be a valid null-terminated UTF-8 string
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_socketio_emit(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    event: *const c_char,
+    args_json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || event.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let event = CStr::from_ptr(event).to_string_lossy();
+    let args_json = optional_args(args_json);
+    server.socketio_emit(connection_id, &event, &args_json)
+}
+
+/// Emit a Socket.IO event and wait up to `timeout_ms` for the client's
+/// acknowledgement. The request ID is written to `out_request_id`; a
+/// `ResponseReceived` event (data: the ack arguments as a JSON array) or a
+/// `RequestTimedOut` event with that ID reports the outcome.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `event` must be a valid null-terminated UTF-8 string
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+/// - `out_request_id` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_socketio_emit_with_ack(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    event: *const c_char,
+    args_json: *const c_char,
+    timeout_ms: u32,
+    out_request_id: *mut u64,
+) -> DwebbleWSResult {
+    if handle.is_null() || event.is_null() || out_request_id.is_null() || timeout_ms == 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let event = CStr::from_ptr(event).to_string_lossy();
+    let args_json = optional_args(args_json);
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+
+    match server.socketio_emit_with_ack(connection_id, &event, &args_json, timeout) {
+        Ok(request_id) => {
+            *out_request_id = request_id;
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_request_id = 0;
+            result
+        }
+    }
+}
+
+/// Acknowledge the Socket.IO event with ack ID `ack` (from a `SocketIoEvent`
+/// event). `args_json` is a JSON array of the acknowledgement's arguments
+/// (null for none). Returns `InvalidParam` if the ack is unknown or already sent.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `args_json` must be null or a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_socketio_ack(
+    handle: DwebbleWSServerHandle,
+    ack: u64,
+    args_json: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let args_json = optional_args(args_json);
+    server.socketio_ack(ack, &args_json)
+}
+
+/// Socket.IO arguments given as JSON, with null meaning no arguments
+unsafe fn optional_args<'a>(args_json: *const c_char) -> std::borrow::Cow<'a, str> {
+    if args_json.is_null() {
+        "[]".into()
+    } else {
+        CStr::from_ptr(args_json).to_string_lossy()
+    }
+}
+
+/// Identify a connection as a logical user (typically after authenticating it),
+/// or clear its identity with a null or empty `user_id`.
+///
+/// A user is online while at least one of its connections is open on any
+/// clustered instance; `PresenceJoined`/`PresenceLeft` events report the changes.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `user_id` must be a valid null-terminated UTF-8 string, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_set_user_id(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    user_id: *const c_char,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let user_id = (!user_id.is_null()).then(|| CStr::from_ptr(user_id).to_string_lossy());
+    server.set_user_id(connection_id, user_id.as_deref().filter(|u| !u.is_empty()))
+}
+
+/// Get the user ID of a connection. Caller must free with `dwebble_rws_free_string`.
+/// Returns null if the connection has not been identified.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_user_id(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match server.user_id(connection_id).map(CString::new) {
+        Some(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Check whether a user is online on this instance or any clustered sibling.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `user_id` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_is_user_online(
+    handle: DwebbleWSServerHandle,
+    user_id: *const c_char,
+) -> bool {
+    if handle.is_null() || user_id.is_null() {
+        return false;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return false;
+    };
+    server.is_user_online(&CStr::from_ptr(user_id).to_string_lossy())
+}
+
+/// Get the online users as a JSON array of user IDs. Caller must free with
+/// `dwebble_rws_free_string`.
+///
+/// With a null `room`, lists the users online on any clustered instance.
+/// With a room, lists the identified members of that room on this instance.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_online_users(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    let room = (!room.is_null()).then(|| CStr::from_ptr(room).to_string_lossy());
+    let users = server.online_users(room.as_deref());
+
+    match serde_json::to_string(&users).map(CString::new) {
+        Ok(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Get the session token of a connection. Caller must free with `dwebble_rws_free_string`.
+/// Returns null if sessions are disabled or the connection is unknown.
+///
+/// Clients present the token on reconnect in the `Dwebble-Session` header or
+/// the `dwebble_session` query parameter.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_session_token(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match server.session_token(connection_id).map(CString::new) {
+        Some(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Include or exclude a connection from the message journal.
+/// Only relevant when the journal's `all_connections` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_journal_select(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    enabled: bool,
+) -> DwebbleWSResult {
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    server.journal_select(connection_id, enabled)
+}
+
+/// Include or exclude the members of a room from the message journal, for as long as
+/// they are members. Only relevant when the journal's `all_connections` setting is off.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `room` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_journal_select_room(
+    handle: DwebbleWSServerHandle,
+    room: *const c_char,
+    enabled: bool,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if room.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let room = CStr::from_ptr(room).to_string_lossy();
+    server.journal_select_room(&room, enabled)
+}
+
+/// Read up to `max_records` journal records starting at sequence `from_seq`.
+/// The records are returned back to back in their on-disk layout (see the
+/// `journal` module). Free the buffer with `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_journal_read(
+    handle: DwebbleWSServerHandle,
+    from_seq: u64,
+    max_records: usize,
+    out_buffer: *mut DwebbleWSBuffer,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_buffer.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    match server.journal_read(from_seq, max_records) {
+        Ok(data) => {
+            *out_buffer = allocator::buffer(data);
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_buffer = DwebbleWSBuffer::default();
+            result
+        }
+    }
+}
+
+/// Query the message archive of the `archive` setting. `query_json` holds the
+/// filters, each optional: `room`, `connection_id`, a time range of `from_ms`
+/// (inclusive) and `to_ms` (exclusive) in milliseconds since the Unix epoch,
+/// `after_id` to page on from the last row read, and `limit` (default 1000). The
+/// rows are returned back to back, oldest first, in the layout of the `archive`
+/// module. Free the buffer with `dwebble_rws_free_buffer`. Returns `InvalidParam`
+/// without the `archive` setting or in builds without the `archive` feature.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `query_json` must be a valid null-terminated UTF-8 string, or null for every row
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_archive_query(
+    handle: DwebbleWSServerHandle,
+    query_json: *const c_char,
+    out_buffer: *mut DwebbleWSBuffer,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_buffer.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+    *out_buffer = DwebbleWSBuffer::default();
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    #[cfg(feature = "archive")]
+    {
+        let query = if query_json.is_null() {
+            ArchiveQuery::default()
+        } else {
+            match ArchiveQuery::from_json(&CStr::from_ptr(query_json).to_string_lossy()) {
+                Ok(query) => query,
+                Err(e) => {
+                    tracing::error!("Invalid archive query JSON: {}", e);
+                    return DwebbleWSResult::InvalidParam;
+                }
+            }
+        };
+        match server.archive_query(&query) {
+            Ok(data) => {
+                *out_buffer = allocator::buffer(data);
+                DwebbleWSResult::Ok
+            }
+            Err(result) => result,
+        }
+    }
+    #[cfg(not(feature = "archive"))]
+    {
+        let _ = (server, query_json);
+        tracing::error!("Message archive unavailable: built without the `archive` feature");
+        DwebbleWSResult::InvalidParam
+    }
+}
+
+/// Get the actual port the server is listening to.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_port(handle: DwebbleWSServerHandle) -> u16 {
+    let Some(server) = SERVERS.get(handle) else {
+        return 0;
+    };
+    server.get_actual_port()
+}
+
+/// Get where the server is in its lifecycle. Sending to and disconnecting its
+/// connections fail with `NotRunning` unless it is `Running`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_state` must be a valid pointer to a `DwebbleWSServerState`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_state(
+    handle: DwebbleWSServerHandle,
+    out_state: *mut DwebbleWSServerState,
+) -> DwebbleWSResult {
+    if out_state.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_state = server.state();
+    DwebbleWSResult::Ok
+}
+
+/// Get the number of active connections.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_connection_count(
+    handle: DwebbleWSServerHandle,
+) -> usize {
+    let Some(server) = SERVERS.get(handle) else {
+        return 0;
+    };
+    server.get_connection_count()
+}
+
+/// Get the number of message events dropped over the `event_budget` setting since
+/// the server was created.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_dropped_event_count(
+    handle: DwebbleWSServerHandle,
+) -> u64 {
+    let Some(server) = SERVERS.get(handle) else {
+        return 0;
+    };
+    server.dropped_event_count()
+}
+
+/// Get information about a connection. Returns `InvalidHandle` for unknown
+/// connections, including suspended sessions.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_info` must be a valid pointer to a `DwebbleWSConnectionInfo`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_connection_info(
+    handle: DwebbleWSServerHandle,
+    connection_id: DwebbleWSConnectionId,
+    out_info: *mut DwebbleWSConnectionInfo,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_info.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    match server.connection_info(connection_id) {
+        Some(info) => {
+            *out_info = info;
+            DwebbleWSResult::Ok
+        }
+        None => DwebbleWSResult::InvalidHandle,
+    }
+}
+
+/// Get the resource usage of a server: its threads and tasks, and the bytes held in
+/// its send queues, session buffers and event queue. The runtime's figures are 0
+/// while it is stopped.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `out_usage` must be a valid pointer to a `DwebbleWSResourceUsage`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_resource_usage(
+    handle: DwebbleWSServerHandle,
+    out_usage: *mut DwebbleWSResourceUsage,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_usage.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_usage = server.resource_usage();
+    DwebbleWSResult::Ok
+}
+
+/// Get the addresses the server listens on as JSON, e.g.
+/// `{"listeners": [{"kind": "websocket", "addr": "0.0.0.0:8080"}], "mapped": null,
+/// "public": "203.0.113.7:8080"}`. Listener kinds are `websocket`, `webtransport`,
+/// `raw_tcp` and `raw_udp`; `mapped` is the external address the gateway forwards
+/// (`port_mapping` setting) and `public` the address the last STUN discovery
+/// reported, each null until known. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_listen_addrs(
+    handle: DwebbleWSServerHandle,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match CString::new(server.listen_addrs().to_string()) {
+        Ok(s) => allocator::string(s),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Get summaries of the server's histograms as JSON, e.g. `{"received_size":
+/// {"count": 120, "min": 12, "max": 4100, "mean": 230, "p50": 180, "p90": 410,
+/// "p99": 2050, "p999": 4100}, "sent_size": {...}, "poll_latency_us": {...}}`.
+/// `received_size` and `sent_size` are the sizes of data messages in bytes as read
+/// and written, and `poll_latency_us` the time events waited for the host to poll
+/// them, in microseconds. Percentiles are within about 3% of the values recorded,
+/// since the server was created. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_get_histograms(
+    handle: DwebbleWSServerHandle,
+) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match CString::new(server.histograms().to_string()) {
+        Ok(s) => allocator::string(s),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Get server info string. Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_info(handle: DwebbleWSServerHandle) -> *mut c_char {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    let info = server.info();
+
     match CString::new(info) {
-        Ok(s) => s.into_raw(),
+        Ok(s) => allocator::string(s),
         Err(_) => ptr::null_mut(),
     }
 }
 
+/// Connect an in-memory loopback client to a running server.
+/// Returns a client handle or null on failure.
+///
+/// The connection goes through the normal handshake and raises the usual
+/// server events, but uses no TCP socket and no TLS. The client is disconnected
+/// when the server stops.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_connect_loopback(
+    handle: DwebbleWSServerHandle,
+) -> DwebbleWSClientHandle {
+    let Some(server) = SERVERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match server.connect_loopback() {
+        Ok(client) => CLIENTS.insert(client.into()),
+        Err(e) => {
+            tracing::error!("Loopback connect failed: {:?}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Dial the server at `url` (`ws://`, or `wss://` with the `tls` feature) from a
+/// running server, which then acts as a peer: the outbound connection shares the
+/// server's connection IDs and events, and is sent to, closed and tagged like an
+/// accepted one. The ID is written to `out_connection_id` at once; a
+/// `ClientConnected` event with a request ID of 1 follows when the handshake
+/// completes, or `Error` and `ClientDisconnected` if it fails.
+///
+/// `settings_json` (optional) sets the `headers`, `cookies`, `query` parameters,
+/// `subprotocols`, `handshake_timeout_ms` and `tls` verification of the connection
+/// as in `dwebble_rws_client_connect`. The server's encryption, sessions,
+/// authentication, message holding, bridge, Socket.IO and MQTT settings apply to
+/// accepted connections only.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_server_create`
+/// - `url` must be a valid null-terminated UTF-8 string
+/// - `settings_json` must be a valid null-terminated UTF-8 string, or null
+/// - `out_connection_id` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_server_dial(
+    handle: DwebbleWSServerHandle,
+    url: *const c_char,
+    settings_json: *const c_char,
+    out_connection_id: *mut DwebbleWSConnectionId,
+) -> DwebbleWSResult {
+    if url.is_null() || out_connection_id.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+    let Some(server) = SERVERS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+
+    let settings = if settings_json.is_null() {
+        ClientSettings::default()
+    } else {
+        match ClientSettings::from_json(&CStr::from_ptr(settings_json).to_string_lossy()) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Invalid client settings JSON: {}", e);
+                return DwebbleWSResult::InvalidParam;
+            }
+        }
+    };
+
+    match server.dial(&CStr::from_ptr(url).to_string_lossy(), &settings) {
+        Ok(connection_id) => {
+            *out_connection_id = connection_id;
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_connection_id = 0;
+            result
+        }
+    }
+}
+
+/// Connect a client to the server at `url` (`ws://`, or `wss://` with the `tls`
+/// feature). Returns a client handle or null if the URL or settings are invalid;
+/// connection failures are reported by the client's events.
+///
+/// `settings_json` (optional) is an object with any of `headers` and `cookies` for
+/// the upgrade request, e.g. `{"headers": {"Authorization": "Bearer ..."}}`, `query`
+/// parameters appended to the URL, `subprotocols` offered, `handshake_timeout_ms`
+/// (default 10000, 0 to wait indefinitely), `tls`, `offline_queue` and `server`, the
+/// settings of the server dialed for its virtual channels, state sync, receipts,
+/// encryption and replay protection. The client runs on threads of its own.
+///
+/// With `offline_queue`, e.g. `{"max_messages": 256, "reconnect_delay_ms": 1000,
+/// "max_reconnect_delay_ms": 30000}`, the client reconnects whenever the connection
+/// drops, doubling the delay after each failed attempt. Its `ClientDisconnected`
+/// event has a request ID of 1 then, and 0 once the client is closed for good.
+/// Messages sent while disconnected are queued and go out in order on reconnect;
+/// when the queue is full, the oldest is dropped and raised in a `MessageDropped`
+/// event. Virtual channel sends are not queued, and channels and state sync start
+/// over with each connection.
+///
+/// `tls` chooses how a `wss://` server's certificate is verified:
+/// `{"verification": "mozilla_roots"}` (default) trusts the Mozilla root
+/// certificates built into the library, `"system_roots"` the operating system's
+/// store, `"ca_bundle"` the PEM certificates of `ca_pem` only, and `"pinned"` any
+/// certificate whose DER SubjectPublicKeyInfo hashes to one of `spki_pins` (base64
+/// SHA-256), whoever signed it. `"disabled"` accepts any certificate and is meant for
+/// development only. `dwebble_rws_client_get_info` reports the choice.
+///
+/// # Safety
+///
+/// - `url` must be a valid null-terminated UTF-8 string
+/// - `settings_json` must be a valid null-terminated UTF-8 string, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_connect(
+    url: *const c_char,
+    settings_json: *const c_char,
+) -> DwebbleWSClientHandle {
+    if url.is_null() {
+        return ptr::null_mut();
+    }
+
+    let settings = if settings_json.is_null() {
+        ClientSettings::default()
+    } else {
+        match ClientSettings::from_json(&CStr::from_ptr(settings_json).to_string_lossy()) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("Invalid client settings JSON: {}", e);
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    match Client::connect(&CStr::from_ptr(url).to_string_lossy(), &settings) {
+        Ok(client) => CLIENTS.insert(client.into()),
+        Err(e) => {
+            tracing::error!("Failed to connect client: {:?}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get information about a client's connection
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_info` must be a valid pointer to a `DwebbleWSClientInfo`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_get_info(
+    handle: DwebbleWSClientHandle,
+    out_info: *mut DwebbleWSClientInfo,
+) -> DwebbleWSResult {
+    if out_info.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_info = client.info();
+    DwebbleWSResult::Ok
+}
+
+/// Run a client's connection on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed for clients of `dwebble_rws_client_connect` in builds
+/// with the `single-thread` feature; see `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_tick(
+    handle: DwebbleWSClientHandle,
+    budget_ms: u32,
+) -> DwebbleWSResult {
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    client.tick(Duration::from_millis(u64::from(budget_ms)));
+    DwebbleWSResult::Ok
+}
+
+/// Destroy a client handle, dropping its connection.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_destroy(handle: DwebbleWSClientHandle) {
+    drop(CLIENTS.remove(handle));
+}
+
+/// Poll for the next client event. Returns true if an event was available. Its data
+/// stays valid until the next poll of the same client.
+///
+/// Client events use the server event types with a connection id of 0:
+/// `ClientConnected` once the handshake completes, `MessageReceived`,
+/// `ChannelMessage`, `StateUpdated`, `Error` and `ClientDisconnected`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_poll(
+    handle: DwebbleWSClientHandle,
+    out_event: *mut DwebbleWSEvent,
+) -> bool {
+    if handle.is_null() || out_event.is_null() {
+        return false;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return false;
+    };
+    write_event(&client.current_event, client.poll_event(), |_| 0, out_event)
+}
+
+/// Send binary data from a client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_send(
+    handle: DwebbleWSClientHandle,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    if client.send(std::slice::from_raw_parts(data, data_len)) {
+        DwebbleWSResult::Ok
+    } else {
+        DwebbleWSResult::ConnectionClosed
+    }
+}
+
+/// Send text data from a client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `text` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_send_text(
+    handle: DwebbleWSClientHandle,
+    text: *const c_char,
+) -> DwebbleWSResult {
+    if handle.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    client.send_text(CStr::from_ptr(text).to_bytes())
+}
+
+/// Send text data given as `text_len` bytes, which may contain null bytes, from a
+/// client to its server.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `text` must be a valid pointer to `text_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_send_text_len(
+    handle: DwebbleWSClientHandle,
+    text: *const u8,
+    text_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || text.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    client.send_text(std::slice::from_raw_parts(text, text_len))
+}
+
+/// Send binary data from a client on a virtual channel, configured like the
+/// server's `channels` setting. Returns `InvalidParam` if the channel is not configured.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_send_channel(
+    handle: DwebbleWSClientHandle,
+    channel: u8,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() || data.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    client.send_channel(channel, std::slice::from_raw_parts(data, data_len))
+}
+
+/// Get the statistics of a virtual channel of a client.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSChannelStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_channel_stats(
+    handle: DwebbleWSClientHandle,
+    channel: u8,
+    out_stats: *mut DwebbleWSChannelStats,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_stats = client.channel_stats(channel);
+    DwebbleWSResult::Ok
+}
+
+/// Sign a client's messages with an HMAC-SHA256 key and require the server's to
+/// be signed with it, matching `dwebble_rws_server_set_signing_key` on the server's
+/// side. Pass a null or empty key to stop signing.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+/// - `key` must be valid for `key_len` bytes (or null)
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_set_signing_key(
+    handle: DwebbleWSClientHandle,
+    key: *const u8,
+    key_len: usize,
+) -> DwebbleWSResult {
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let key = (!key.is_null() && key_len > 0).then(|| std::slice::from_raw_parts(key, key_len));
+    client.set_signing_key(key);
+    DwebbleWSResult::Ok
+}
+
+/// Close a client connection gracefully.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_client_connect` or
+///   `dwebble_rws_server_connect_loopback`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_client_close(handle: DwebbleWSClientHandle) -> DwebbleWSResult {
+    let Some(client) = CLIENTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    client.close();
+    DwebbleWSResult::Ok
+}
+
+/// Open a pool of connections to backend services for `dwebble_rws_service_pool_request`.
+/// Returns a service pool handle, or null if the settings are invalid.
+///
+/// `settings_json` is an object with the `urls` of the services (`ws://` or
+/// `wss://`), the `connections_per_url` kept open (default 1), `health_check_ms`,
+/// the interval of ping health checks (default 5000, 0 to disable),
+/// `reconnect_delay_ms` (default 1000) and `client`, the headers, cookies, query
+/// parameters, subprotocols, handshake timeout and `tls` of the connections as in
+/// `dwebble_rws_client_connect`. A connection that hasn't answered a ping by the
+/// next check is closed, and every closed connection is reopened after the delay.
+/// The pool runs on threads of its own.
+///
+/// Events use the server event types with the connection's index in the pool as
+/// connection ID (`connections_per_url` per URL, in the order of `urls`):
+/// `ClientConnected` and `ClientDisconnected` (error message: why) as connections
+/// open and close, `Error` for each failed attempt, and `ResponseReceived` or
+/// `RequestTimedOut` for each request.
+///
+/// # Safety
+///
+/// - `settings_json` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_create(
+    settings_json: *const c_char,
+) -> DwebbleWSServicePoolHandle {
+    if settings_json.is_null() {
+        return ptr::null_mut();
+    }
+
+    let json = CStr::from_ptr(settings_json).to_string_lossy();
+    let settings = match ServicePoolSettings::from_json(&json) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("Invalid service pool settings JSON: {}", e);
+            return ptr::null_mut();
+        }
+    };
+
+    match ServicePool::new(settings) {
+        Ok(pool) => SERVICE_POOLS.insert(ServicePoolHandle {
+            pool,
+            current_event: Mutex::new(None),
+        }),
+        Err(e) => {
+            tracing::error!("Failed to create service pool: {:?}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Destroy a service pool handle, closing its connections. Its requests in flight
+/// get no event.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`,
+///   or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_destroy(handle: DwebbleWSServicePoolHandle) {
+    drop(SERVICE_POOLS.remove(handle));
+}
+
+/// Send binary data as a request on the next healthy connection of a pool, in turn,
+/// and wait up to `timeout_ms` for the response. The request ID is written to
+/// `out_request_id`; a `ResponseReceived` or `RequestTimedOut` event with that ID
+/// reports the outcome. Returns `ConnectionClosed` if no connection is healthy.
+///
+/// The data is wrapped in an envelope (`DWRQ`, u64 request ID, payload) and the
+/// service must answer with the same layout using the `DWRS` magic, as clients
+/// answer `dwebble_rws_server_request`. Requests of a connection that closes time
+/// out at once.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+/// - `out_request_id` must be a valid pointer to a `u64`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_request(
+    handle: DwebbleWSServicePoolHandle,
+    data: *const u8,
+    data_len: usize,
+    timeout_ms: u32,
+    out_request_id: *mut u64,
+) -> DwebbleWSResult {
+    if data.is_null() || out_request_id.is_null() || timeout_ms == 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(pool) = SERVICE_POOLS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    let data_slice = std::slice::from_raw_parts(data, data_len);
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+
+    match pool.request(data_slice, timeout) {
+        Ok(request_id) => {
+            *out_request_id = request_id;
+            DwebbleWSResult::Ok
+        }
+        Err(result) => {
+            *out_request_id = 0;
+            result
+        }
+    }
+}
+
+/// Get the statistics of a service pool
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSServicePoolStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_stats(
+    handle: DwebbleWSServicePoolHandle,
+    out_stats: *mut DwebbleWSServicePoolStats,
+) -> DwebbleWSResult {
+    if out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+    let Some(pool) = SERVICE_POOLS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_stats = pool.stats();
+    DwebbleWSResult::Ok
+}
+
+/// Poll for the next event of a service pool. Returns true if an event was available.
+/// Its data stays valid until the next poll of the same pool.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+/// - `out_event` must be a valid pointer to a `DwebbleWSEvent`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_poll(
+    handle: DwebbleWSServicePoolHandle,
+    out_event: *mut DwebbleWSEvent,
+) -> bool {
+    if handle.is_null() || out_event.is_null() {
+        return false;
+    }
+
+    let Some(pool) = SERVICE_POOLS.get(handle) else {
+        return false;
+    };
+    write_event(&pool.current_event, pool.poll_event(), |_| 0, out_event)
+}
+
+/// Run a service pool's connections on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed in builds with the `single-thread` feature; see
+/// `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_service_pool_create`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_service_pool_tick(
+    handle: DwebbleWSServicePoolHandle,
+    budget_ms: u32,
+) -> DwebbleWSResult {
+    let Some(pool) = SERVICE_POOLS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    pool.tick(Duration::from_millis(u64::from(budget_ms)));
+    DwebbleWSResult::Ok
+}
+
+/// Start a load test of `num_clients` WebSocket clients connecting to `url`.
+/// Returns a load test handle or null on failure.
+///
+/// Each client sends `msgs_per_sec` binary messages per second (0 to only hold
+/// the connection open) of `payload_len` bytes (at least 8). The first 8 bytes
+/// carry a send timestamp, so latency is measured when the server echoes them.
+///
+/// # Safety
+///
+/// - `url` must be a valid null-terminated UTF-8 string (`ws://` or `wss://`)
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_loadtest_start(
+    url: *const c_char,
+    num_clients: u32,
+    msgs_per_sec: u32,
+    payload_len: usize,
+) -> DwebbleWSLoadTestHandle {
+    if url.is_null() {
+        return ptr::null_mut();
+    }
+
+    let config = LoadTestConfig {
+        url: CStr::from_ptr(url).to_string_lossy().into_owned(),
+        num_clients,
+        msgs_per_sec,
+        payload_len,
+    };
+
+    match LoadTest::start(config) {
+        Ok(load_test) => LOAD_TESTS.insert(load_test),
+        Err(e) => {
+            tracing::error!("Failed to start load test: {:?}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get the aggregate statistics of a running load test.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`
+/// - `out_stats` must be a valid pointer to a `DwebbleWSLoadTestStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_loadtest_poll(
+    handle: DwebbleWSLoadTestHandle,
+    out_stats: *mut DwebbleWSLoadTestStats,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    let Some(load_test) = LOAD_TESTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    *out_stats = load_test.stats();
+    DwebbleWSResult::Ok
+}
+
+/// Run a load test's clients on the calling thread for up to `budget_ms`
+/// milliseconds. Only needed in builds with the `single-thread` feature; see
+/// `dwebble_rws_server_tick`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_loadtest_tick(
+    handle: DwebbleWSLoadTestHandle,
+    budget_ms: u32,
+) -> DwebbleWSResult {
+    let Some(load_test) = LOAD_TESTS.get(handle) else {
+        return DwebbleWSResult::InvalidHandle;
+    };
+    load_test.tick(Duration::from_millis(u64::from(budget_ms)));
+    DwebbleWSResult::Ok
+}
+
+/// Stop a load test, closing all of its clients, and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_loadtest_start`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_loadtest_stop(handle: DwebbleWSLoadTestHandle) {
+    drop(LOAD_TESTS.remove(handle));
+}
+
+/// Create a trainer of zstd dictionaries. Returns a trainer handle, or null in builds
+/// without the `zstd` feature.
+#[no_mangle]
+pub extern "C" fn dwebble_rws_dict_trainer_create() -> DwebbleWSDictTrainerHandle {
+    #[cfg(feature = "zstd")]
+    return DICT_TRAINERS.insert(Mutex::default());
+    #[cfg(not(feature = "zstd"))]
+    {
+        tracing::error!("Dictionary training unavailable: built without the `zstd` feature");
+        ptr::null_mut()
+    }
+}
+
+/// Add a sample payload to train a dictionary on, e.g. a message of recorded traffic.
+/// Samples should be typical of the messages to compress; a few thousand work well.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`
+/// - `data` must be a valid pointer to `data_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_dict_trainer_add_sample(
+    handle: DwebbleWSDictTrainerHandle,
+    data: *const u8,
+    data_len: usize,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if data.is_null() && data_len > 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    #[cfg(feature = "zstd")]
+    {
+        let Some(trainer) = DICT_TRAINERS.get(handle) else {
+            return DwebbleWSResult::InvalidHandle;
+        };
+        let sample = if data_len == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(data, data_len)
+        };
+        trainer.lock().add_sample(sample);
+        DwebbleWSResult::Ok
+    }
+    #[cfg(not(feature = "zstd"))]
+    DwebbleWSResult::InvalidHandle
+}
+
+/// Train a dictionary of up to `max_size` bytes from the samples added so far, to set
+/// with `dwebble_rws_server_set_zstd_dictionary` and ship to clients. Returns
+/// `InvalidParam` if there are too few samples to train on. Free the buffer with
+/// `dwebble_rws_free_buffer`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`
+/// - `out_buffer` must be a valid pointer to a `DwebbleWSBuffer`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_dict_trainer_train(
+    handle: DwebbleWSDictTrainerHandle,
+    max_size: usize,
+    out_buffer: *mut DwebbleWSBuffer,
+) -> DwebbleWSResult {
+    if handle.is_null() {
+        return DwebbleWSResult::InvalidHandle;
+    }
+    if out_buffer.is_null() || max_size == 0 {
+        return DwebbleWSResult::InvalidParam;
+    }
+
+    *out_buffer = DwebbleWSBuffer::default();
+    #[cfg(feature = "zstd")]
+    {
+        let Some(trainer) = DICT_TRAINERS.get(handle) else {
+            return DwebbleWSResult::InvalidHandle;
+        };
+        let trained = trainer.lock().train(max_size);
+        match trained {
+            Ok(dictionary) => {
+                *out_buffer = allocator::buffer(dictionary);
+                DwebbleWSResult::Ok
+            }
+            Err(e) => {
+                tracing::error!("Failed to train zstd dictionary: {}", e);
+                DwebbleWSResult::InvalidParam
+            }
+        }
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        let _ = max_size;
+        DwebbleWSResult::InvalidHandle
+    }
+}
+
+/// Free a dictionary trainer and its samples.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_dict_trainer_create`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_dict_trainer_destroy(handle: DwebbleWSDictTrainerHandle) {
+    #[cfg(feature = "zstd")]
+    drop(DICT_TRAINERS.remove(handle));
+    #[cfg(not(feature = "zstd"))]
+    let _ = handle;
+}
+
+/// Announce a service to LAN browsers over mDNS until withdrawn.
+/// Returns an announcement handle or null on failure.
+///
+/// `service_name` (1-15 letters, digits and hyphens, e.g. `mygame`) is what
+/// browsers look for; announce a server's actual port as reported by
+/// `dwebble_rws_server_get_port`. `metadata` is a JSON object of string values
+/// (session name, map, player count, ...) carried in the TXT record.
+///
+/// # Safety
+///
+/// - `service_name` must be a valid null-terminated UTF-8 string
+/// - `metadata` must be a valid null-terminated UTF-8 string, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_discovery_announce(
+    service_name: *const c_char,
+    port: u16,
+    metadata: *const c_char,
+) -> DwebbleWSAnnouncementHandle {
+    if service_name.is_null() {
+        return ptr::null_mut();
+    }
+
+    let service_name = CStr::from_ptr(service_name).to_string_lossy();
+    let metadata = if metadata.is_null() {
+        HashMap::new()
+    } else {
+        match serde_json::from_slice(CStr::from_ptr(metadata).to_bytes()) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                tracing::error!("Invalid discovery metadata: {}", e);
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    match Announcement::start(&service_name, port, metadata) {
+        Ok(announcement) => ANNOUNCEMENTS.insert(announcement),
+        Err(e) => {
+            tracing::error!("Failed to announce {}: {}", service_name, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Withdraw an announced service and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_announce`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_discovery_withdraw(handle: DwebbleWSAnnouncementHandle) {
+    drop(ANNOUNCEMENTS.remove(handle));
+}
+
+/// Start browsing the LAN for announcements of a service.
+/// Returns a browser handle or null on failure.
+///
+/// # Safety
+///
+/// - `service_name` must be a valid null-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_discovery_browse(
+    service_name: *const c_char,
+) -> DwebbleWSBrowserHandle {
+    if service_name.is_null() {
+        return ptr::null_mut();
+    }
+
+    let service_name = CStr::from_ptr(service_name).to_string_lossy();
+    match Browser::start(&service_name) {
+        Ok(browser) => BROWSERS.insert(browser),
+        Err(e) => {
+            tracing::error!("Failed to browse for {}: {}", service_name, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get the services a browser currently sees as a JSON array of
+/// `{"name", "host", "addresses", "port", "metadata"}` objects.
+/// Caller must free with `dwebble_rws_free_string`.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_browse`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_discovery_services(
+    handle: DwebbleWSBrowserHandle,
+) -> *mut c_char {
+    let Some(browser) = BROWSERS.get(handle) else {
+        return ptr::null_mut();
+    };
+    match serde_json::to_string(&browser.services()).map(CString::new) {
+        Ok(Ok(s)) => allocator::string(s),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Stop browsing and free the handle.
+///
+/// # Safety
+///
+/// - `handle` must be a valid handle returned by `dwebble_rws_discovery_browse`, or null
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_discovery_browse_stop(handle: DwebbleWSBrowserHandle) {
+    drop(BROWSERS.remove(handle));
+}
+
 /// Free a string allocated by this library.
 ///
 /// # Safety
 ///
-/// - `s` must be a string returned by `dwebble_rws_server_info`, or null
+/// - `s` must be a string returned by this library, or null
 /// - `s` must not be used after this call
 #[no_mangle]
 pub unsafe extern "C" fn dwebble_rws_free_string(s: *mut c_char) {
-    if !s.is_null() {
-        let _ = CString::from_raw(s);
+    allocator::free_string(s);
+}
+
+/// Allocate a zeroed buffer of `len` bytes, e.g. for a middleware callback's
+/// replacement message. Free with `dwebble_rws_free_buffer` unless handed to the library.
+#[no_mangle]
+pub extern "C" fn dwebble_rws_alloc_buffer(len: usize) -> DwebbleWSBuffer {
+    allocator::buffer(vec![0; len])
+}
+
+/// Free a buffer allocated by this library.
+///
+/// # Safety
+///
+/// - `buffer` must be a buffer returned by this library, or have a null `data` pointer
+/// - `buffer` must not be used after this call
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_free_buffer(buffer: DwebbleWSBuffer) {
+    allocator::free_buffer(buffer);
+}
+
+/// Get the statistics of the pool the payloads of received messages are copied into,
+/// shared by every server and client.
+///
+/// # Safety
+///
+/// - `out_stats` must be a valid pointer to a `DwebbleWSPoolStats`
+#[no_mangle]
+pub unsafe extern "C" fn dwebble_rws_pool_stats(
+    out_stats: *mut DwebbleWSPoolStats,
+) -> DwebbleWSResult {
+    if out_stats.is_null() {
+        return DwebbleWSResult::InvalidParam;
     }
+
+    *out_stats = pool::stats();
+    DwebbleWSResult::Ok
 }
@@ -3,49 +3,54 @@
  */
 
 //! FFI types shared between Rust and C++
+//!
+//! Handles, strings, buffers and callbacks of the C ABI. The plain enums and
+//! structs the core's API uses too are defined there and re-exported here.
 
 use std::ffi::{c_char, c_void};
 
-/// Result codes for WebSocket FFI operations
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DwebbleWSResult {
-    Ok = 0,
-    InvalidHandle = 1,
-    InvalidParam = 2,
-    AlreadyRunning = 3,
-    NotRunning = 4,
-    BindFailed = 5,
-    TlsError = 6,
-    RuntimeError = 7,
-    SendFailed = 8,
-    ConnectionClosed = 9,
-}
-
-/// WebSocket event types for polling
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DwebbleWSEventType {
-    None = 0,
-    ClientConnected = 1,
-    ClientDisconnected = 2,
-    MessageReceived = 3,
-    Error = 4,
-}
+pub use dwebble_rws_core::types::*;
 
 /// WebSocket server configuration passed from C++
+///
+/// Each string is UTF-8 of its `_len` field's length in bytes, or null-terminated if
+/// that is 0.
 #[repr(C)]
 pub struct DwebbleWSServerConfig {
     /// Port to listen on (0 for auto)
     pub port: u16,
-    /// Bind address (null-terminated UTF-8)
+    /// Bind address
     pub bind_address: *const c_char,
-    /// Subprotocols (null-terminated, comma-separated)
+    pub bind_address_len: usize,
+    /// Subprotocols (comma-separated), in order of preference
     pub subprotocols: *const c_char,
+    pub subprotocols_len: usize,
+    /// TLS certificate path (null for no TLS)
+    pub tls_cert_path: *const c_char,
+    pub tls_cert_path_len: usize,
+    /// TLS private key path
+    pub tls_key_path: *const c_char,
+    pub tls_key_path_len: usize,
+    /// Extended settings as a JSON object (null for defaults)
+    pub settings_json: *const c_char,
+    pub settings_json_len: usize,
+}
+
+/// Listener configuration passed to `dwebble_rws_server_rebind`, with strings as
+/// in `DwebbleWSServerConfig`
+#[repr(C)]
+pub struct DwebbleWSBindConfig {
+    /// Port to listen on (0 for auto)
+    pub port: u16,
+    /// Bind address (null to keep the current one)
+    pub bind_address: *const c_char,
+    pub bind_address_len: usize,
     /// TLS certificate path (null for no TLS)
     pub tls_cert_path: *const c_char,
+    pub tls_cert_path_len: usize,
     /// TLS private key path
     pub tls_key_path: *const c_char,
+    pub tls_key_path_len: usize,
 }
 
 /// WebSocket event data returned from polling
@@ -54,12 +59,36 @@ pub struct DwebbleWSEvent {
     pub event_type: DwebbleWSEventType,
     /// Connection ID (valid for Connected/Disconnected/MessageReceived)
     pub connection_id: u64,
-    /// Message data pointer (valid for MessageReceived)
+    /// Message data pointer (valid for MessageReceived/DatagramReceived/ChannelMessage).
+    /// For ClientConnected/SessionResumed, the negotiated subprotocol (null if none was selected).
+    /// For PresenceJoined/PresenceLeft, the user ID. For TopicMessage/MqttPublish, the published payload.
+    /// For RpcCall, the params JSON. For SocketIoEvent, the arguments JSON array.
+    /// For PortMapped/PublicEndpoint, the external address as `ip:port`.
+    /// For StateUpdated, the whole state after the update.
+    /// For MessageRejected/ValidationFailed/SignatureInvalid/ReplayRejected/ChecksumMismatch,
+    /// the message. For ServerStopped, the stop statistics as JSON.
     pub data: *const u8,
     /// Message data length
     pub data_len: usize,
-    /// Error message (valid for Error, null-terminated)
+    /// Error message (valid for Error, null-terminated).
+    /// For ClientDisconnected, the reason if the server closed the connection.
+    /// For TopicMessage/MqttPublish, the topic. For RpcCall, the method.
+    /// For SocketIoEvent, the event name. For MessageRejected, the filter path.
+    /// For ValidationFailed/SignatureInvalid/ReplayRejected/ChecksumMismatch, why the
+    /// message was refused.
     pub error_message: *const c_char,
+    /// Request ID (valid for ResponseReceived/RequestTimedOut).
+    /// For RpcCall, the call ID to answer (0 for notifications).
+    /// For TypedMessage, the message type ID. For SocketIoEvent, the ack ID (0 if none).
+    /// For ChannelMessage, the channel ID. For StateUpdated, the state key.
+    /// For MessageReceived on a loopback client, the sequence of a sequenced send (0 otherwise).
+    /// For MessageRejected, the filter's code. For ValidationFailed, 1 if the message was
+    /// delivered anyway.
+    /// For ClientConnected of a WebRTC data channel, the signaling connection's ID.
+    /// For ClientConnected of a connection the server dialed, 1.
+    pub request_id: u64,
+    /// With the `checksum` setting, the checksum of the data (0 otherwise)
+    pub checksum: u32,
 }
 
 impl Default for DwebbleWSEvent {
@@ -70,12 +99,105 @@ impl Default for DwebbleWSEvent {
             data: std::ptr::null(),
             data_len: 0,
             error_message: std::ptr::null(),
+            request_id: 0,
+            checksum: 0,
         }
     }
 }
 
-/// WebSocket server handle (opaque pointer)
+/// Byte buffer allocated by the library. Free with `dwebble_rws_free_buffer`.
+#[repr(C)]
+pub struct DwebbleWSBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl Default for DwebbleWSBuffer {
+    fn default() -> Self {
+        Self {
+            data: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// A message passed to a middleware callback
+#[repr(C)]
+pub struct DwebbleWSMiddlewareMessage {
+    /// True for messages from a client, false for messages to one
+    pub inbound: bool,
+    pub connection_id: u64,
+    /// Whether this is a text message. A callback returning `Modify` may change it;
+    /// replacements that are not valid UTF-8 become binary messages.
+    pub is_text: bool,
+    /// Message content, valid for the duration of the callback
+    pub data: *const u8,
+    pub data_len: usize,
+    /// New content when returning `Modify`, allocated with `dwebble_rws_alloc_buffer`.
+    /// The library takes ownership of it.
+    pub replacement: DwebbleWSBuffer,
+}
+
+/// Host allocation function: returns `size` bytes (at least 1), or null on failure.
+/// May be called from any thread, including several at once.
+pub type DwebbleWSAllocFn =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, size: usize) -> *mut c_void>;
+
+/// Host function freeing memory from the matching `DwebbleWSAllocFn`
+pub type DwebbleWSFreeFn = Option<unsafe extern "C" fn(user_data: *mut c_void, ptr: *mut c_void)>;
+
+/// Callback run on the watchdog thread with each health warning: the issue, the
+/// connection of a stalled writer (0 otherwise) and how long the stall has lasted
+pub type DwebbleWSHealthCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        issue: DwebbleWSHealthIssue,
+        connection_id: u64,
+        stalled_ms: u64,
+    ),
+>;
+
+/// Callback run once a server has stopped, on the thread that stopped it (a
+/// background thread for an asynchronous stop)
+pub type DwebbleWSStoppedCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, stats: *const DwebbleWSStopStats)>;
+
+/// Callback run on each thread of a server's runtime as it starts, before it runs
+/// any task, with the order it started in
+pub type DwebbleWSThreadCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, index: u32)>;
+
+/// Middleware callback, called with the `user_data` it was registered with.
+/// May be called from any thread, including several at once.
+pub type DwebbleWSMiddlewareCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        message: *mut DwebbleWSMiddlewareMessage,
+    ) -> DwebbleWSMiddlewareAction,
+>;
+
+/// WebSocket server handle (opaque, checked on every call)
 pub type DwebbleWSServerHandle = *mut c_void;
 
+/// WebSocket client handle (opaque, checked on every call)
+pub type DwebbleWSClientHandle = *mut c_void;
+
+/// Service pool handle (opaque, checked on every call)
+pub type DwebbleWSServicePoolHandle = *mut c_void;
+
+/// Load test handle (opaque, checked on every call)
+pub type DwebbleWSLoadTestHandle = *mut c_void;
+
+/// zstd dictionary trainer handle (opaque, checked on every call)
+pub type DwebbleWSDictTrainerHandle = *mut c_void;
+
+/// Per-room event queue handle (opaque, checked on every call)
+pub type DwebbleWSEventQueueHandle = *mut c_void;
+
+/// LAN discovery announcement handle (opaque, checked on every call)
+pub type DwebbleWSAnnouncementHandle = *mut c_void;
+
+/// LAN discovery browser handle (opaque, checked on every call)
+pub type DwebbleWSBrowserHandle = *mut c_void;
+
 /// WebSocket connection handle
 pub type DwebbleWSConnectionId = u64;